@@ -1,4 +1,56 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() {
+    build_info();
     tauri_build::build()
 }
 
+/// Commit, dirty flag, build time and target for `build_info.rs`, as
+/// `ARBOR_*` env vars; a source tarball without git gets `unknown`
+fn build_info() {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let sha = git(&["rev-parse", "HEAD"]);
+    let dirty = match (&sha, git(&["status", "--porcelain"])) {
+        (Some(_), Some(status)) => (!status.is_empty()).to_string(),
+        _ => "unknown".to_string(),
+    };
+    // Reproducible builds pin the time
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0)
+        });
+
+    println!(
+        "cargo:rustc-env=ARBOR_GIT_SHA={}",
+        sha.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rustc-env=ARBOR_GIT_DIRTY={}", dirty);
+    println!("cargo:rustc-env=ARBOR_BUILT_AT={}", built_at);
+    println!(
+        "cargo:rustc-env=ARBOR_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=src");
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/index", git_dir);
+        // Moves on each commit, where HEAD names a branch
+        if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, branch);
+        }
+    }
+}
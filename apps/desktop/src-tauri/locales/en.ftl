@@ -0,0 +1,43 @@
+# English messages for coded errors, rendered by `describe_error`.
+# Placeables are `{ $param }`; every message here needs a French twin.
+
+unknown-error = Something went wrong (error code { $code })
+command-failed = { $command } failed: { $error }
+task-panicked = Background task { $task } stopped unexpectedly: { $message }
+docker-socket-permission-denied = Permission denied on { $socket_path }: add your user to the '{ $required_group }' group and log out and back in
+docker-confined = Docker installed as a { $packaging } package can't mount the project at { $project_root }
+docker-not-installed = Docker isn't installed. Install Docker Desktop or Docker Engine, then try again.
+docker-daemon-unreachable = Docker is installed but isn't running. Please start Docker Desktop (or the docker service) and try again.
+docker-version-unsupported = Docker { $version } found, { $required } or newer required. Update Docker and try again.
+compose-missing = Docker Compose isn't installed. Install the docker compose plugin (v2), then try again.
+compose-legacy-only = Only the old docker-compose { $version } is installed, and Arbor needs the docker compose plugin (v2). Install it, then try again.
+project-files-changed = These project files changed since you trusted them: { $files }. Review them and trust them again to continue.
+version-mismatch = Services { $services } were started by Arbor { $stack_version }, which Arbor { $app_version } can't work with. Update the services, or start anyway.
+protocol-frontend-outdated = This window is running an older interface (protocol { $frontend_version }) than Arbor expects ({ $backend_version }). Reload the window.
+protocol-backend-outdated = This interface (protocol { $frontend_version }) needs a newer Arbor than this one ({ $backend_version }). Update Arbor.
+protocol-handshake-missing = The interface didn't finish connecting to Arbor. Reload the window.
+keyring-timeout = The keychain didn't answer within { $timeout_secs } seconds. It may be waiting to be unlocked.
+secret-file-wrong-passphrase = That passphrase doesn't unlock your secrets. Check it and try again.
+secret-file-empty-passphrase = Enter a passphrase to protect your secrets.
+secret-file-not-in-use = Your secrets are kept in the system keychain, so there's nothing to unlock.
+secret-file-corrupt = Your secrets file is damaged and can't be opened: { $detail }
+secret-file-io = Your secrets file couldn't be read or written: { $detail }
+secret-file-rejected = { $detail }
+keyring-not-found = There's no { $name } yet. Set up encryption to create one.
+keyring-access-denied = The keychain refused access. Unlock it and try again.
+keyring-platform-unavailable = No keychain is available on this system.
+keyring-invalid-key = That isn't a valid encryption key: { $detail }
+keyring-rejected = { $detail }
+keyring-io = The keychain couldn't be used: { $detail }
+stack-already-starting = Services are already starting.
+stack-already-running = Services are already running.
+stack-already-stopping = Services are stopping. Wait for them to stop, then try again.
+ports-in-use = Other programs are using ports Arbor needs: { $ports }. Quit them or change Arbor's ports, then start again.
+disk-space-low = Only { $free } is free at { $path }. Image downloads may run out of space.
+disk-space-insufficient = Only { $free } is free at { $path }, and Arbor needs { $required } to start. Free some space, or start anyway.
+migrations-services-stopped = Migrations need the services running. Start them, then try again.
+stats-failed = Container usage couldn't be read: { $detail }
+pull-offline = The image registry can't be reached. Check your internet connection, then try again.
+pull-auth-required = The image registry needs you to sign in. Run `docker login`, then try again.
+pull-metered = Images weren't downloaded because you're on a metered connection.
+pull-failed = Images couldn't be pulled: { $detail }
@@ -0,0 +1,42 @@
+# Messages en français des erreurs codées, rendus par `describe_error`.
+
+unknown-error = Une erreur s'est produite (code d'erreur { $code })
+command-failed = Échec de { $command } : { $error }
+task-panicked = La tâche de fond { $task } s'est arrêtée de façon inattendue : { $message }
+docker-socket-permission-denied = Accès refusé à { $socket_path } : ajoutez votre utilisateur au groupe « { $required_group } » puis reconnectez-vous
+docker-confined = Docker installé en paquet { $packaging } ne peut pas monter le projet situé dans { $project_root }
+docker-not-installed = Docker n'est pas installé. Installez Docker Desktop ou Docker Engine, puis réessayez.
+docker-daemon-unreachable = Docker est installé mais n'est pas démarré. Lancez Docker Desktop (ou le service docker) puis réessayez.
+docker-version-unsupported = Docker { $version } trouvé, la version { $required } ou plus récente est requise. Mettez Docker à jour puis réessayez.
+compose-missing = Docker Compose n'est pas installé. Installez le plugin docker compose (v2), puis réessayez.
+compose-legacy-only = Seul l'ancien docker-compose { $version } est installé, et Arbor a besoin du plugin docker compose (v2). Installez-le, puis réessayez.
+project-files-changed = Ces fichiers du projet ont changé depuis que vous leur avez fait confiance : { $files }. Relisez-les et faites-leur confiance à nouveau pour continuer.
+version-mismatch = Les services { $services } ont été démarrés par Arbor { $stack_version }, avec lequel Arbor { $app_version } ne peut pas fonctionner. Mettez à jour les services, ou démarrez quand même.
+protocol-frontend-outdated = Cette fenêtre utilise une interface plus ancienne (protocole { $frontend_version }) que celle attendue par Arbor ({ $backend_version }). Rechargez la fenêtre.
+protocol-backend-outdated = Cette interface (protocole { $frontend_version }) nécessite une version d'Arbor plus récente que celle-ci ({ $backend_version }). Mettez Arbor à jour.
+protocol-handshake-missing = L'interface n'a pas fini de se connecter à Arbor. Rechargez la fenêtre.
+keyring-timeout = Le trousseau n'a pas répondu en { $timeout_secs } secondes. Il attend peut-être d'être déverrouillé.
+secret-file-wrong-passphrase = Cette phrase secrète ne déverrouille pas vos secrets. Vérifiez-la et réessayez.
+secret-file-empty-passphrase = Saisissez une phrase secrète pour protéger vos secrets.
+secret-file-not-in-use = Vos secrets sont conservés dans le trousseau du système : il n'y a rien à déverrouiller.
+secret-file-corrupt = Votre fichier de secrets est endommagé et ne peut pas être ouvert : { $detail }
+secret-file-io = Votre fichier de secrets n'a pas pu être lu ou écrit : { $detail }
+secret-file-rejected = { $detail }
+keyring-not-found = Il n'y a pas encore de { $name }. Configurez le chiffrement pour en créer un.
+keyring-access-denied = Le trousseau a refusé l'accès. Déverrouillez-le puis réessayez.
+keyring-platform-unavailable = Aucun trousseau n'est disponible sur ce système.
+keyring-invalid-key = Cette clé de chiffrement n'est pas valide : { $detail }
+keyring-rejected = { $detail }
+keyring-io = Le trousseau n'a pas pu être utilisé : { $detail }
+stack-already-starting = Les services sont déjà en cours de démarrage.
+stack-already-running = Les services sont déjà démarrés.
+stack-already-stopping = Les services sont en cours d'arrêt. Attendez qu'ils soient arrêtés, puis réessayez.
+ports-in-use = D'autres programmes utilisent des ports dont Arbor a besoin : { $ports }. Quittez-les ou changez les ports d'Arbor, puis redémarrez.
+disk-space-low = Seulement { $free } libres sur { $path }. Les téléchargements d'images risquent de manquer de place.
+disk-space-insufficient = Seulement { $free } libres sur { $path }, et Arbor a besoin de { $required } pour démarrer. Libérez de l'espace, ou démarrez quand même.
+migrations-services-stopped = Les migrations ont besoin des services démarrés. Démarrez-les, puis réessayez.
+stats-failed = L'utilisation des conteneurs n'a pas pu être lue : { $detail }
+pull-offline = Le registre d'images est injoignable. Vérifiez votre connexion Internet, puis réessayez.
+pull-auth-required = Le registre d'images demande une connexion. Lancez `docker login`, puis réessayez.
+pull-metered = Les images n'ont pas été téléchargées car votre connexion est limitée.
+pull-failed = Les images n'ont pas pu être récupérées : { $detail }
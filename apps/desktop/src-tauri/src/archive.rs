@@ -0,0 +1,201 @@
+// Zip archives
+// Just enough of the zip format to hand files to support: deflated entries
+// with UTF-8 names, written in one pass, no zip64. Every unzip tool and
+// the OS file managers open these.
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{self, Write};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const VERSION: u16 = 20;
+/// Names are UTF-8
+const UTF8_FLAG: u16 = 0x0800;
+const DEFLATED: u16 = 8;
+
+struct Entry {
+    name: String,
+    crc: u32,
+    compressed: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes a zip archive entry by entry; nothing is readable until `finish`
+pub struct ZipWriter<W: Write> {
+    out: W,
+    written: u64,
+    entries: Vec<Entry>,
+    /// DOS time and date every entry is stamped with
+    modified: (u16, u16),
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            written: 0,
+            entries: Vec::new(),
+            modified: dos_time(chrono::Local::now().naive_local()),
+        }
+    }
+
+    /// Add a file at `name`, a `/`-separated path inside the archive
+    pub fn add(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents)?;
+        let compressed = encoder.finish()?;
+        let entry = Entry {
+            name: name.to_string(),
+            crc: crc32fast::hash(contents),
+            compressed: fits(compressed.len() as u64)?,
+            size: fits(contents.len() as u64)?,
+            offset: fits(self.written)?,
+        };
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        put32(&mut header, LOCAL_HEADER);
+        put16(&mut header, VERSION);
+        self.put_common(&mut header, &entry);
+        put16(&mut header, 0);
+        header.extend_from_slice(name.as_bytes());
+        self.write(&header)?;
+        self.write(&compressed)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Write the central directory, returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let start = fits(self.written)?;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            put32(&mut directory, CENTRAL_HEADER);
+            put16(&mut directory, VERSION);
+            put16(&mut directory, VERSION);
+            self.put_common(&mut directory, entry);
+            // Extra field, comment, disk, internal and external attributes
+            put16(&mut directory, 0);
+            put16(&mut directory, 0);
+            put16(&mut directory, 0);
+            put16(&mut directory, 0);
+            put32(&mut directory, 0);
+            put32(&mut directory, entry.offset);
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let count = u16::try_from(self.entries.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too many zip entries"))?;
+        let size = fits(directory.len() as u64)?;
+        put32(&mut directory, END_OF_CENTRAL_DIRECTORY);
+        put16(&mut directory, 0);
+        put16(&mut directory, 0);
+        put16(&mut directory, count);
+        put16(&mut directory, count);
+        put32(&mut directory, size);
+        put32(&mut directory, start);
+        put16(&mut directory, 0);
+        self.write(&directory)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// The fields local and central headers share, from the flags up to the
+    /// name length
+    fn put_common(&self, header: &mut Vec<u8>, entry: &Entry) {
+        put16(header, UTF8_FLAG);
+        put16(header, DEFLATED);
+        put16(header, self.modified.0);
+        put16(header, self.modified.1);
+        put32(header, entry.crc);
+        put32(header, entry.compressed);
+        put32(header, entry.size);
+        put16(header, entry.name.len() as u16);
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+fn fits(value: u64) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Zip archives are capped at 4GB",
+        )
+    })
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// DOS time and date, at two-second precision, from 1980 on
+fn dos_time(at: chrono::NaiveDateTime) -> (u16, u16) {
+    use chrono::{Datelike, Timelike};
+    let time = (at.hour() << 11) | (at.minute() << 5) | (at.second() / 2);
+    let date = ((at.year().max(1980) - 1980) as u32) << 9 | (at.month() << 5) | at.day();
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    fn read16(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn read32(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_entries_can_be_read_back_through_the_central_directory() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add("logs/arbor.log", b"line one\nline two\n").unwrap();
+        zip.add("empty.txt", b"").unwrap();
+        let bytes = zip.finish().unwrap();
+
+        let end = bytes.len() - 22;
+        assert_eq!(read32(&bytes, end), END_OF_CENTRAL_DIRECTORY);
+        assert_eq!(read16(&bytes, end + 10), 2);
+        let central = read32(&bytes, end + 16) as usize;
+        assert_eq!(read32(&bytes, central), CENTRAL_HEADER);
+        let name_len = read16(&bytes, central + 28) as usize;
+        assert_eq!(&bytes[central + 46..][..name_len], b"logs/arbor.log");
+
+        let local = read32(&bytes, central + 42) as usize;
+        assert_eq!(read32(&bytes, local), LOCAL_HEADER);
+        let compressed = read32(&bytes, local + 18) as usize;
+        let data = local + 30 + read16(&bytes, local + 26) as usize;
+        let mut contents = Vec::new();
+        DeflateDecoder::new(&bytes[data..data + compressed])
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"line one\nline two\n");
+        assert_eq!(read32(&bytes, local + 14), crc32fast::hash(&contents));
+    }
+
+    #[test]
+    fn test_dos_time() {
+        let at = chrono::NaiveDate::from_ymd_opt(2026, 3, 14)
+            .unwrap()
+            .and_hms_opt(15, 9, 27)
+            .unwrap();
+        assert_eq!(
+            dos_time(at),
+            ((15 << 11) | (9 << 5) | 13, (46 << 9) | (3 << 5) | 14)
+        );
+    }
+}
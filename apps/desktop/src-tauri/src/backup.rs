@@ -0,0 +1,742 @@
+// Data backup and restore
+// The services keep their data in the profile's named volumes, which
+// `backup_data` archives into one `.tar.gz` with a directory per volume,
+// named as compose names it (e.g. `postgres_data`), so a backup restores
+// into any profile. The tar runs in a throwaway BACKUP_IMAGE container with
+// the volumes and the destination mounted; the containers using the volumes
+// are paused meanwhile, so nothing writes halfway through, and resumed
+// afterwards. The archive is written under a `.partial` name, listed back to
+// check it's complete, and only then renamed. Its first entry is a small
+// HEADER_NAME file, so a restore can tell an Arbor backup from any other
+// archive before running anything on it.
+//
+// `restore_data` replaces the volumes' contents with a backup's. It's
+// destructive, so a dry run lists the volumes it would replace and hands out
+// the confirmation token the real call needs, and it's refused while any
+// container uses them. Both run as jobs, so a large volume shows progress.
+// Both paths come from the frontend and go through `paths::validate` before
+// any docker command sees them; the jobs only get the resolved path, which
+// is bind-mounted into the helper container with its `--mount` fields quoted.
+
+use crate::confirm::Confirmations;
+use crate::docker::PROJECT_LABEL;
+use crate::jobs::{JobEnv, JobKind, JobManager};
+use crate::paths::{self, PathPolicy};
+use crate::process::{CommandRunner, CommandSpec};
+use crate::protocol::Handshake;
+use crate::services::StackState;
+use crate::uninstall::lines;
+use crate::ServiceManager;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, State};
+use tokio::time::Duration;
+
+/// Small image with tar, gzip and find in it
+pub const BACKUP_IMAGE: &str = "alpine:3.20";
+/// Every helper container is labelled, so a cancelled job can remove them
+pub const CONTAINER_LABEL: &str = "dev.arbor.data-backup=1";
+/// Label compose puts the volume's name, without the project, in
+const VOLUME_LABEL: &str = "com.docker.compose.volume";
+const FILE_PREFIX: &str = "arbor-backup-";
+const PARTIAL_SUFFIX: &str = ".partial";
+/// Where volumes and the backup's directory are mounted in the container
+const VOLUMES_MOUNT: &str = "/volumes";
+const BACKUP_MOUNT: &str = "/backup";
+/// A backup's first entry, next to the volumes' directories
+pub const HEADER_NAME: &str = "arbor-backup.json";
+/// How a backup's unpacked bytes start: the tar entry of HEADER_NAME, whose
+/// name is NUL-padded
+pub const HEADER: &[u8] = b"arbor-backup.json\0";
+const HEADER_FORMAT: u32 = 1;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A named volume as compose created it for the profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataVolume {
+    /// e.g. `arbor_postgres_data`
+    pub name: String,
+    /// Its directory in a backup, e.g. `postgres_data`
+    pub short_name: String,
+}
+
+/// What `backup_data` wrote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataBackup {
+    pub output_file: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+    /// Compose names of the volumes in it
+    pub volumes: Vec<String>,
+}
+
+/// What HEADER_NAME holds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct BackupHeader {
+    format: u32,
+    /// Compose names of the volumes in the backup
+    volumes: Vec<String>,
+}
+
+/// What `restore_data` replaces, or replaced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataRestore {
+    pub archive_path: String,
+    /// Volumes whose contents are replaced, created where they're missing
+    pub volumes: Vec<String>,
+    /// Only on a dry run; the real call passes it back
+    pub confirmation_token: Option<String>,
+}
+
+/// The profile's named volumes
+pub async fn volumes(runner: &dyn CommandRunner, project: &str) -> Result<Vec<DataVolume>, String> {
+    let filter = format!("label={}={}", PROJECT_LABEL, project);
+    let format = format!("{{{{.Name}}}}\t{{{{.Label \"{}\"}}}}", VOLUME_LABEL);
+    let spec =
+        CommandSpec::new("docker").args(["volume", "ls", "--filter", &filter, "--format", &format]);
+    let prefix = format!("{}_", project);
+    Ok(lines(runner, &spec)
+        .await?
+        .iter()
+        .map(|line| {
+            let (name, label) = line.split_once('\t').unwrap_or((line, ""));
+            let short_name = match label.trim() {
+                "" | "<no value>" => name.strip_prefix(&prefix).unwrap_or(name),
+                label => label,
+            };
+            DataVolume {
+                name: name.trim().to_string(),
+                short_name: short_name.to_string(),
+            }
+        })
+        .filter(|volume| valid_short_name(&volume.short_name))
+        .collect())
+}
+
+/// Names that are safe as a directory in the archive and the container
+fn valid_short_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Names of the containers using any of `volumes`, in `status` if given,
+/// otherwise running, paused or restarting
+pub async fn users(
+    runner: &dyn CommandRunner,
+    volumes: &[DataVolume],
+    status: Option<&str>,
+) -> Result<Vec<String>, String> {
+    if volumes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut spec = CommandSpec::new("docker").args(["ps", "--format", "{{.Names}}"]);
+    if let Some(status) = status {
+        spec = spec.args(["--filter".to_string(), format!("status={}", status)]);
+    }
+    for volume in volumes {
+        spec = spec.args(["--filter".to_string(), format!("volume={}", volume.name)]);
+    }
+    lines(runner, &spec).await
+}
+
+/// `docker pause` or `docker unpause` each of `containers`
+pub async fn set_paused(
+    runner: &dyn CommandRunner,
+    containers: &[String],
+    paused: bool,
+) -> Result<(), String> {
+    if containers.is_empty() {
+        return Ok(());
+    }
+    let command = if paused { "pause" } else { "unpause" };
+    let spec = CommandSpec::new("docker")
+        .args([command])
+        .args(containers.iter().cloned());
+    lines(runner, &spec).await.map(|_| ())
+}
+
+/// Where a backup asked for at `dest_path` goes: into it, under a dated
+/// name, when it's a directory
+pub fn target(dest_path: &Path) -> Result<PathBuf, String> {
+    let file = match dest_path.is_dir() {
+        true => dest_path.join(format!(
+            "{}{}.tar.gz",
+            FILE_PREFIX,
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        )),
+        false => dest_path.to_path_buf(),
+    };
+    if file.exists() {
+        return Err(format!("{} already exists", file.display()));
+    }
+    match file.parent() {
+        Some(dir) if dir.is_dir() => Ok(file),
+        _ => Err(format!("No directory to write {} in", file.display())),
+    }
+}
+
+/// The name `file` is written under until it's verified
+pub fn partial(file: &Path) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(PARTIAL_SUFFIX);
+    file.with_file_name(name)
+}
+
+/// The header file written next to `file` while it's archived
+pub fn header_file(file: &Path) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(".header");
+    name.push(PARTIAL_SUFFIX);
+    file.with_file_name(name)
+}
+
+/// `path` as a string, refusing one that isn't UTF-8 rather than passing an
+/// altered path on
+fn utf8(path: PathBuf) -> Result<String, String> {
+    path.into_os_string()
+        .into_string()
+        .map_err(|path| format!("{:?} isn't valid UTF-8", path))
+}
+
+/// The backup job for `dest_path`, resolved to an existing directory or a
+/// file to write in one
+pub fn backup_job(dest_path: &str) -> Result<JobKind, String> {
+    let dest_path = Path::new(dest_path);
+    let policy = match dest_path.is_dir() {
+        true => PathPolicy::Directory,
+        false => PathPolicy::Export,
+    };
+    let dest_path = utf8(paths::validate(dest_path, policy)?)?;
+    Ok(JobKind::BackupData { dest_path })
+}
+
+/// Which mounts a helper container may write to; the others are read-only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Writes {
+    Archive,
+    Volumes,
+    Nothing,
+}
+
+/// A `--mount` value; docker reads it as CSV, so a field with a `,` or `"`
+/// in it is quoted rather than split into options
+fn mount(kind: &str, source: &str, target: &str, readonly: bool) -> String {
+    let field = |key: &str, value: &str| {
+        let field = format!("{}={}", key, value);
+        match field.contains([',', '"']) {
+            true => format!("\"{}\"", field.replace('"', "\"\"")),
+            false => field,
+        }
+    };
+    let mut mount = [
+        field("type", kind),
+        field("source", source),
+        field("target", target),
+    ]
+    .join(",");
+    if readonly {
+        mount.push_str(",readonly");
+    }
+    mount
+}
+
+/// `docker run` of BACKUP_IMAGE with `volumes` under VOLUMES_MOUNT, the
+/// directory of `file` under BACKUP_MOUNT and `header`, if any, as
+/// HEADER_NAME beside the volumes, running `command`
+fn helper(
+    volumes: &[DataVolume],
+    file: &Path,
+    header: Option<&Path>,
+    writes: Writes,
+    command: &[String],
+) -> CommandSpec {
+    let dir = file.parent().unwrap_or(Path::new("."));
+    let mut spec = CommandSpec::new("docker").args(["run", "--rm", "--label", CONTAINER_LABEL]);
+    for volume in volumes {
+        let target = format!("{}/{}", VOLUMES_MOUNT, volume.short_name);
+        let readonly = writes != Writes::Volumes;
+        spec = spec.args([
+            "--mount".to_string(),
+            mount("volume", &volume.name, &target, readonly),
+        ]);
+    }
+    let source = dir.to_string_lossy();
+    let readonly = writes != Writes::Archive;
+    spec = spec.args([
+        "--mount".to_string(),
+        mount("bind", &source, BACKUP_MOUNT, readonly),
+    ]);
+    if let Some(header) = header {
+        let target = format!("{}/{}", VOLUMES_MOUNT, HEADER_NAME);
+        let source = header.to_string_lossy();
+        spec = spec.args(["--mount".to_string(), mount("bind", &source, &target, true)]);
+    }
+    spec.args([BACKUP_IMAGE]).args(command.iter().cloned())
+}
+
+/// `file` as the helper container sees it
+fn in_container(file: &Path) -> String {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    format!("{}/{}", BACKUP_MOUNT, name)
+}
+
+/// Tar and gzip `volumes` into `file`, after a HEADER_NAME entry, reporting
+/// how much is written so far
+pub async fn archive(
+    runner: &dyn CommandRunner,
+    volumes: &[DataVolume],
+    file: &Path,
+    progress: &(dyn Fn(u64) + Sync),
+) -> Result<(), String> {
+    let header = header_file(file);
+    let contents = serde_json::to_vec(&BackupHeader {
+        format: HEADER_FORMAT,
+        volumes: volumes
+            .iter()
+            .map(|volume| volume.short_name.clone())
+            .collect(),
+    })
+    .map_err(|e| e.to_string())?;
+    std::fs::write(&header, contents)
+        .map_err(|e| format!("Failed to write {}: {}", header.display(), e))?;
+    let archived = archive_with(runner, volumes, file, &header, progress).await;
+    let _ = std::fs::remove_file(&header);
+    archived
+}
+
+async fn archive_with(
+    runner: &dyn CommandRunner,
+    volumes: &[DataVolume],
+    file: &Path,
+    header: &Path,
+    progress: &(dyn Fn(u64) + Sync),
+) -> Result<(), String> {
+    let mut command = vec!["tar".to_string(), "-czf".to_string(), in_container(file)];
+    command.extend(["-C".to_string(), VOLUMES_MOUNT.to_string()]);
+    command.push(HEADER_NAME.to_string());
+    command.extend(volumes.iter().map(|volume| volume.short_name.clone()));
+    let spec = helper(volumes, file, Some(header), Writes::Archive, &command);
+    let run = lines(runner, &spec);
+    tokio::pin!(run);
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    loop {
+        tokio::select! {
+            result = &mut run => return result.map(|_| ()),
+            _ = interval.tick() => {
+                if let Ok(metadata) = std::fs::metadata(file) {
+                    progress(metadata.len());
+                }
+            }
+        }
+    }
+}
+
+/// List `file` back, which reads it through, returning the volumes in it
+pub async fn verify(runner: &dyn CommandRunner, file: &Path) -> Result<Vec<String>, String> {
+    let command = ["tar".to_string(), "-tzf".to_string(), in_container(file)];
+    let entries = lines(runner, &helper(&[], file, None, Writes::Nothing, &command))
+        .await
+        .map_err(|e| format!("{} isn't a readable backup: {}", file.display(), e))?;
+    let unexpected = |entry: &str| {
+        format!(
+            "{} isn't an Arbor backup: it has {:?}",
+            file.display(),
+            entry
+        )
+    };
+    // Top-level entries, and those seen as directories
+    let (mut top, mut volumes) = (BTreeSet::new(), BTreeSet::new());
+    for entry in &entries {
+        let entry = entry.trim_start_matches("./");
+        if entry.is_empty() || entry == HEADER_NAME {
+            continue;
+        }
+        let mut parts = entry.split('/');
+        let volume = parts.next().unwrap_or_default();
+        if !valid_short_name(volume) || entry.split('/').any(|part| part == "..") {
+            return Err(unexpected(entry));
+        }
+        top.insert(volume);
+        if parts.next().is_some() {
+            volumes.insert(volume);
+        }
+    }
+    if let Some(loose) = top.difference(&volumes).next() {
+        return Err(unexpected(loose));
+    }
+    if volumes.is_empty() {
+        return Err(format!("{} holds no volumes", file.display()));
+    }
+    Ok(volumes.into_iter().map(str::to_string).collect())
+}
+
+/// The SHA-256 of `file`, read in chunks
+pub async fn checksum(file: &Path) -> Result<String, String> {
+    let path = file.to_path_buf();
+    let digest = tokio::task::spawn_blocking(move || -> std::io::Result<String> {
+        let mut reader = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 1 << 20];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(format!("{:x}", hasher.finalize())),
+                read => hasher.update(&buf[..read]),
+            }
+        }
+    });
+    digest
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to read {}: {}", file.display(), e))
+}
+
+/// The profile's volumes a backup holding `short_names` restores into,
+/// creating the ones it doesn't have yet as compose would
+pub async fn restore_targets(
+    runner: &dyn CommandRunner,
+    project: &str,
+    short_names: &[String],
+) -> Result<Vec<DataVolume>, String> {
+    let existing = volumes(runner, project).await?;
+    let mut targets = Vec::new();
+    for short_name in short_names {
+        if let Some(volume) = existing
+            .iter()
+            .find(|volume| &volume.short_name == short_name)
+        {
+            targets.push(volume.clone());
+            continue;
+        }
+        let name = format!("{}_{}", project, short_name);
+        let spec = CommandSpec::new("docker")
+            .args(["volume", "create", "--label"])
+            .args([
+                format!("{}={}", PROJECT_LABEL, project),
+                "--label".to_string(),
+            ])
+            .args([format!("{}={}", VOLUME_LABEL, short_name), name.clone()]);
+        lines(runner, &spec).await?;
+        targets.push(DataVolume {
+            name,
+            short_name: short_name.clone(),
+        });
+    }
+    Ok(targets)
+}
+
+/// Delete everything in `volumes`, leaving them empty
+pub async fn clear(
+    runner: &dyn CommandRunner,
+    volumes: &[DataVolume],
+    archive: &Path,
+) -> Result<(), String> {
+    let command = ["find", VOLUMES_MOUNT, "-mindepth", "2", "-delete"].map(str::to_string);
+    lines(
+        runner,
+        &helper(volumes, archive, None, Writes::Volumes, &command),
+    )
+    .await
+    .map(|_| ())
+}
+
+/// The command that unpacks `archive` into `volumes`, listing what it writes
+pub fn extract_spec(volumes: &[DataVolume], archive: &Path) -> CommandSpec {
+    let command = ["tar", "-xzvf", &in_container(archive), "-C", VOLUMES_MOUNT].map(str::to_string);
+    helper(volumes, archive, None, Writes::Volumes, &command)
+}
+
+/// e.g. `Archived 1.2 GB of about 3.4 GB`; the archive is compressed, so
+/// it usually ends up smaller than the volumes
+pub fn archived(written: u64, total: u64) -> String {
+    let gb = |bytes: u64| format!("{:.1} GB", bytes as f64 / 1e9);
+    match total {
+        0 => format!("Archived {}", gb(written)),
+        total => format!("Archived {} of about {}", gb(written), gb(total)),
+    }
+}
+
+/// Remove the helper containers a cancelled job left and, for a backup to
+/// `dest_path`, its unfinished archive
+pub async fn clean_up(runner: &dyn CommandRunner, dest_path: Option<&Path>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let label = format!("label={}", CONTAINER_LABEL);
+    let list = CommandSpec::new("docker").args(["ps", "-aq", "--filter", &label]);
+    match lines(runner, &list).await {
+        Ok(ids) if !ids.is_empty() => {
+            let remove = CommandSpec::new("docker").args(["rm", "-f"]).args(ids);
+            if let Err(e) = lines(runner, &remove).await {
+                errors.push(e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => errors.push(e),
+    }
+    let Some(dest_path) = dest_path else {
+        return errors;
+    };
+    let partials: Vec<PathBuf> = match dest_path.is_dir() {
+        true => std::fs::read_dir(dest_path)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        name.starts_with(FILE_PREFIX) && name.ends_with(PARTIAL_SUFFIX)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        false => vec![partial(dest_path), header_file(&partial(dest_path))],
+    };
+    for path in partials.iter().filter(|path| path.exists()) {
+        if let Err(e) = std::fs::remove_file(path) {
+            errors.push(format!("Failed to remove {}: {}", path.display(), e));
+        }
+    }
+    errors
+}
+
+/// Refuse to restore unless the app's stack is stopped; the job also
+/// checks nothing else uses the volumes
+fn ensure_stopped(app_handle: &AppHandle) -> Result<(), String> {
+    let service_manager = app_handle.state::<ServiceManager>();
+    if !matches!(*service_manager.stack.lock(), StackState::Stopped) {
+        return Err("Stop services before restoring data".to_string());
+    }
+    Ok(())
+}
+
+/// Archive the profile's volumes to `dest_path`, a file or a directory to
+/// write a dated one in, pausing the services meanwhile; `start_job` with
+/// `backup_data` does the same without waiting
+#[tauri::command]
+pub async fn backup_data(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    dest_path: String,
+) -> Result<DataBackup, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    let kind = backup_job(&dest_path)?;
+    let (_, done) = jobs.try_start(kind, JobEnv::without_project(&app_handle)?)?;
+    let result = done
+        .await
+        .map_err(|_| "The backup was interrupted".to_string())??;
+    serde_json::from_value(result).map_err(|e| e.to_string())
+}
+
+/// Replace the profile's volumes with the backup at `archive_path`; run with
+/// `dry_run` first for the confirmation token. Refused while services run.
+#[tauri::command]
+pub async fn restore_data(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    confirmations: State<'_, Confirmations>,
+    archive_path: String,
+    dry_run: bool,
+    confirmation_token: Option<String>,
+) -> Result<DataRestore, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    ensure_stopped(&app_handle)?;
+    let archive_path = utf8(paths::validate(
+        Path::new(&archive_path),
+        PathPolicy::Archive(HEADER),
+    )?)?;
+    let action = format!("restore_data:{}", archive_path);
+    let env = JobEnv::without_project(&app_handle)?;
+    if dry_run {
+        let short_names = verify(env.runner.as_ref(), Path::new(&archive_path)).await?;
+        let volumes = short_names
+            .iter()
+            .map(|short_name| format!("{}_{}", env.setup.project, short_name))
+            .collect();
+        return Ok(DataRestore {
+            archive_path,
+            volumes,
+            confirmation_token: Some(confirmations.issue(&action)),
+        });
+    }
+    let token =
+        confirmation_token.ok_or("restore_data needs the confirmation token from a dry run")?;
+    confirmations.redeem(&action, &token)?;
+
+    let kind = JobKind::RestoreData { archive_path };
+    let (_, done) = jobs.try_start(kind, env)?;
+    let result = done
+        .await
+        .map_err(|_| "The restore was interrupted".to_string())??;
+    serde_json::from_value(result).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::test_support::temp_dir;
+    use std::sync::{Arc, Mutex};
+
+    fn volume(short_name: &str) -> DataVolume {
+        DataVolume {
+            name: format!("arbor_{}", short_name),
+            short_name: short_name.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_volumes_are_archived_by_their_compose_name() {
+        let runner = MockRunner::with_stdout(
+            "arbor_postgres_data\tpostgres_data\narbor_minio\t<no value>\narbor_bad\t../etc\n",
+        );
+        let found = volumes(&runner, "arbor").await.unwrap();
+        assert_eq!(found, [volume("postgres_data"), volume("minio")]);
+
+        let tmp = temp_dir();
+        let file = tmp.path().join("arbor-backup-test.tar.gz");
+        archive(&runner, &found, &file, &|_| {}).await.unwrap();
+        let run = runner.calls().pop().unwrap();
+        let args = run.args.join(" ");
+        assert!(args.contains(
+            "--mount type=volume,source=arbor_postgres_data,target=/volumes/postgres_data,readonly"
+        ));
+        assert!(args.ends_with(
+            "alpine:3.20 tar -czf /backup/arbor-backup-test.tar.gz -C /volumes arbor-backup.json \
+             postgres_data minio"
+        ));
+        let header = header_file(&file);
+        assert!(args.contains(&format!(
+            "type=bind,source={},target=/volumes/arbor-backup.json,readonly",
+            header.display()
+        )));
+        assert!(!header.exists());
+        assert_eq!(
+            partial(&file).file_name().unwrap(),
+            "arbor-backup-test.tar.gz.partial"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_header_names_the_volumes() {
+        let tmp = temp_dir();
+        let file = tmp.path();
+        let header = header_file(file);
+        let written = Arc::new(Mutex::new(None));
+        let seen = written.clone();
+        let runner = MockRunner::new(move |_| {
+            *seen.lock().unwrap() = std::fs::read_to_string(&header).ok();
+            Ok(CommandOutput {
+                code: Some(0),
+                ..Default::default()
+            })
+        });
+        archive(&runner, &[volume("postgres_data")], file, &|_| {})
+            .await
+            .unwrap();
+
+        let written = written.lock().unwrap().clone().unwrap();
+        let header: BackupHeader = serde_json::from_str(&written).unwrap();
+        assert_eq!(header.volumes, ["postgres_data"]);
+        assert_eq!(header.format, HEADER_FORMAT);
+    }
+
+    #[test]
+    fn test_mount_paths_with_commas_stay_one_field() {
+        let file = Path::new("/tmp/my,backups/size=1\"/data.tar.gz");
+        let command = ["tar".to_string(), "-tzf".to_string(), in_container(file)];
+        let spec = helper(
+            &[volume("postgres_data")],
+            file,
+            None,
+            Writes::Nothing,
+            &command,
+        );
+
+        let bind = "type=bind,\"source=/tmp/my,backups/size=1\"\"\",target=/backup,readonly";
+        assert!(spec.args.contains(&bind.to_string()), "{:?}", spec.args);
+        let volume =
+            "type=volume,source=arbor_postgres_data,target=/volumes/postgres_data,readonly";
+        assert!(spec.args.contains(&volume.to_string()));
+    }
+
+    #[test]
+    fn test_paths_are_resolved_before_any_command() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("backups")).unwrap();
+        let canonical = dir.canonicalize().unwrap().join("backups");
+
+        let dotted = dir.join("backups/../backups");
+        assert_eq!(
+            backup_job(&dotted.to_string_lossy()).unwrap(),
+            JobKind::BackupData {
+                dest_path: canonical.display().to_string()
+            }
+        );
+        assert_eq!(
+            backup_job(&dotted.join("data.tar.gz").to_string_lossy()).unwrap(),
+            JobKind::BackupData {
+                dest_path: canonical.join("data.tar.gz").display().to_string()
+            }
+        );
+        assert!(backup_job(&dir.join("missing/data.tar.gz").to_string_lossy()).is_err());
+
+        let gzip = |name: &str, first_entry: &str| {
+            let path = dir.join("backups").join(name);
+            let file = std::fs::File::create(&path).unwrap();
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+            let mut tar_header = first_entry.as_bytes().to_vec();
+            tar_header.resize(512, 0);
+            std::io::Write::write_all(&mut encoder, &tar_header).unwrap();
+            encoder.finish().unwrap();
+            dir.join("backups/..").join("backups").join(name)
+        };
+        let backup = gzip("backup.tar.gz", HEADER_NAME);
+        let resolved = paths::validate(&backup, PathPolicy::Archive(HEADER)).unwrap();
+        assert_eq!(resolved, canonical.join("backup.tar.gz"));
+        let other = gzip("other.tar.gz", "postgres_data/");
+        assert!(paths::validate(&other, PathPolicy::Archive(HEADER)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_only_archives_of_volume_directories_verify() {
+        let file = Path::new("/tmp/backups/data.tar.gz");
+        let backup = MockRunner::with_stdout(
+            "arbor-backup.json\npostgres_data/\npostgres_data/PG_VERSION\n./minio/\n\
+             ./minio/.minio.sys/format.json\n",
+        );
+        assert_eq!(
+            verify(&backup, file).await.unwrap(),
+            ["minio", "postgres_data"]
+        );
+        let run = &backup.calls()[0];
+        assert!(run
+            .args
+            .contains(&"type=bind,source=/tmp/backups,target=/backup,readonly".to_string()));
+
+        let loose = MockRunner::with_stdout("notes.txt\n");
+        assert!(verify(&loose, file)
+            .await
+            .unwrap_err()
+            .contains("isn't an Arbor backup"));
+        let escaping = MockRunner::with_stdout("postgres_data/../../etc/passwd\n");
+        assert!(verify(&escaping, file).await.is_err());
+        let truncated = MockRunner::new(|_| {
+            Ok(CommandOutput {
+                code: Some(1),
+                stdout: b"postgres_data/\n".to_vec(),
+                stderr: b"gzip: invalid magic\ntar: short read\n".to_vec(),
+            })
+        });
+        assert!(verify(&truncated, file)
+            .await
+            .unwrap_err()
+            .contains("short read"));
+    }
+}
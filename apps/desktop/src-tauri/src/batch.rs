@@ -0,0 +1,234 @@
+// Event batching for high-volume streams
+// A chatty `docker compose logs -f` can produce thousands of lines a second,
+// and one IPC event per line makes the webview unresponsive. Streams push
+// entries into an EventBatcher, which emits them as one array event per
+// window (or as soon as a batch is full). The frontend acks each batch; while
+// too many are unacked it is not keeping up, so entries wait in the buffer,
+// and past the buffer's cap new ones are dropped and counted. Each source
+// numbers its entries, dropped ones included, so gaps show where lines were
+// lost. State changes and other low-volume events go straight through
+// `events::emit`.
+
+use crate::events::{self, EventSink};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchLimits {
+    /// How long an entry may wait for its batch to fill
+    pub window: Duration,
+    /// Entries per event
+    pub max_entries: usize,
+    /// Entries buffered while the frontend is behind; more are dropped
+    pub cap: usize,
+    /// Batches emitted and not yet acked before the frontend counts as behind
+    pub max_in_flight: u64,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(50),
+            max_entries: 500,
+            cap: 10_000,
+            max_in_flight: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry<T> {
+    /// e.g. the service a log line came from
+    pub source: String,
+    /// Per source, starting at 1
+    pub seq: u64,
+    pub data: T,
+}
+
+#[derive(Serialize)]
+struct Batch<'a, T> {
+    stream: &'a str,
+    /// Pass to the stream's ack command once handled
+    batch: u64,
+    entries: Vec<Entry<T>>,
+    /// Entries dropped since the previous batch
+    dropped: u64,
+}
+
+struct BatchState<T> {
+    buffer: VecDeque<Entry<T>>,
+    seqs: HashMap<String, u64>,
+    emitted: u64,
+    acked: u64,
+    dropped: u64,
+    total_dropped: u64,
+}
+
+/// Buffers one stream's entries and emits them as `event` batches
+pub struct EventBatcher<T> {
+    sink: Arc<dyn EventSink>,
+    event: &'static str,
+    stream: String,
+    limits: BatchLimits,
+    state: Mutex<BatchState<T>>,
+}
+
+impl<T: Serialize> EventBatcher<T> {
+    pub fn new(
+        sink: Arc<dyn EventSink>,
+        event: &'static str,
+        stream: &str,
+        limits: BatchLimits,
+    ) -> Self {
+        Self {
+            sink,
+            event,
+            stream: stream.to_string(),
+            limits,
+            state: Mutex::new(BatchState {
+                buffer: VecDeque::new(),
+                seqs: HashMap::new(),
+                emitted: 0,
+                acked: 0,
+                dropped: 0,
+                total_dropped: 0,
+            }),
+        }
+    }
+
+    pub fn limits(&self) -> BatchLimits {
+        self.limits
+    }
+
+    /// Queue an entry, emitting right away once a batch is full
+    pub fn push(&self, source: &str, data: T) {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.seqs.entry(source.to_string()).or_default();
+        *seq += 1;
+        let seq = *seq;
+        if state.buffer.len() >= self.limits.cap {
+            state.dropped += 1;
+            state.total_dropped += 1;
+            return;
+        }
+        state.buffer.push_back(Entry {
+            source: source.to_string(),
+            seq,
+            data,
+        });
+        self.emit_ready(&mut state, true);
+    }
+
+    /// Emit what's buffered, as far as the frontend is keeping up; called
+    /// every `window` by the stream
+    pub fn flush(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.emit_ready(&mut state, false);
+    }
+
+    /// The frontend handled every batch up to `batch`
+    pub fn ack(&self, batch: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.acked = state.acked.max(batch.min(state.emitted));
+    }
+
+    /// Entries dropped over the stream's life
+    pub fn total_dropped(&self) -> u64 {
+        self.state.lock().unwrap().total_dropped
+    }
+
+    // Under the lock, so batches go out in order
+    fn emit_ready(&self, state: &mut BatchState<T>, full_only: bool) {
+        while state.emitted - state.acked < self.limits.max_in_flight {
+            let ready = match full_only {
+                true => state.buffer.len() >= self.limits.max_entries,
+                false => !state.buffer.is_empty() || state.dropped > 0,
+            };
+            if !ready {
+                break;
+            }
+            let count = state.buffer.len().min(self.limits.max_entries);
+            state.emitted += 1;
+            let batch = Batch {
+                stream: &self.stream,
+                batch: state.emitted,
+                entries: state.buffer.drain(..count).collect(),
+                dropped: std::mem::take(&mut state.dropped),
+            };
+            events::emit(&*self.sink, self.event, &batch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+
+    const EVENT: &str = "test-batch";
+
+    fn batcher(sink: Arc<RecordingSink>) -> EventBatcher<String> {
+        let limits = BatchLimits {
+            window: Duration::from_millis(50),
+            max_entries: 3,
+            cap: 5,
+            max_in_flight: 2,
+        };
+        EventBatcher::new(sink, EVENT, "logs-1", limits)
+    }
+
+    fn lines(batch: &serde_json::Value) -> Vec<String> {
+        batch["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| format!("{}#{}", entry["source"].as_str().unwrap(), entry["seq"]))
+            .collect()
+    }
+
+    #[test]
+    fn test_entries_are_batched_in_order_with_per_source_seqs() {
+        let sink = Arc::new(RecordingSink::default());
+        let batcher = batcher(sink.clone());
+
+        batcher.push("api", "a".to_string());
+        batcher.push("redis", "b".to_string());
+        assert!(sink.events_named(EVENT).is_empty(), "waits for the window");
+        batcher.push("api", "c".to_string());
+        batcher.push("api", "d".to_string());
+        batcher.flush();
+
+        let batches = sink.events_named(EVENT);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(lines(&batches[0]), ["api#1", "redis#1", "api#2"]);
+        assert_eq!(lines(&batches[1]), ["api#3"]);
+        assert_eq!(batches[1]["batch"], 2);
+        assert_eq!(batches[0]["stream"], "logs-1");
+    }
+
+    #[test]
+    fn test_lagging_frontend_gets_drops_counted_instead_of_floods() {
+        let sink = Arc::new(RecordingSink::default());
+        let batcher = batcher(sink.clone());
+
+        // Two full batches go out unacked; the rest waits, then overflows
+        for i in 0..15 {
+            batcher.push("api", i.to_string());
+        }
+        batcher.flush();
+        assert_eq!(sink.events_named(EVENT).len(), 2);
+        assert_eq!(batcher.total_dropped(), 4);
+
+        batcher.ack(2);
+        batcher.flush();
+        let batches = sink.events_named(EVENT);
+        assert_eq!(batches.len(), 4);
+        assert_eq!(lines(&batches[2]), ["api#7", "api#8", "api#9"]);
+        assert_eq!(batches[2]["dropped"], 4);
+        // The gap in seq shows where lines were dropped
+        assert_eq!(lines(&batches[3]), ["api#10", "api#11"]);
+        assert_eq!(batches[3]["dropped"], 0);
+    }
+}
@@ -0,0 +1,91 @@
+// Build info
+// Several builds are cut per version while testing, so a version alone
+// doesn't say which one a report came from. build.rs stamps the commit,
+// whether the tree had uncommitted changes, the build time and the target
+// into the binary; `get_build_info` returns them with the Tauri and webview
+// versions, for the About dialog and support bundles.
+
+use serde::Serialize;
+
+/// What `ARBOR_GIT_SHA` is without git, e.g. built from a source tarball
+pub const UNKNOWN: &str = "unknown";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    pub version: String,
+    /// Full commit SHA, or `unknown`
+    pub git_sha: String,
+    /// Whether the tree had uncommitted changes; `None` without git
+    pub git_dirty: Option<bool>,
+    /// RFC 3339, or `SOURCE_DATE_EPOCH` where the build pinned it
+    pub built_at: String,
+    /// e.g. `aarch64-apple-darwin`
+    pub target: String,
+    pub debug: bool,
+    pub tauri_version: String,
+    /// WebKitGTK, WKWebView or WebView2, if it can be queried
+    pub webview_version: Option<String>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let built_at = env!("ARBOR_BUILT_AT")
+            .parse()
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|at| at.to_rfc3339())
+            .unwrap_or_else(|| UNKNOWN.to_string());
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: env!("ARBOR_GIT_SHA").to_string(),
+            git_dirty: env!("ARBOR_GIT_DIRTY").parse().ok(),
+            built_at,
+            target: env!("ARBOR_TARGET").to_string(),
+            debug: cfg!(debug_assertions),
+            tauri_version: tauri::VERSION.to_string(),
+            webview_version: tauri::webview_version().ok(),
+        }
+    }
+
+    /// One line for logs, e.g. `0.1.0 (3f2a9c1e-dirty, debug)`
+    pub fn describe(&self) -> String {
+        let mut build = self.git_sha.chars().take(8).collect::<String>();
+        if self.git_dirty == Some(true) {
+            build.push_str("-dirty");
+        }
+        if self.debug {
+            build.push_str(", debug");
+        }
+        format!("{} ({})", self.version, build)
+    }
+}
+
+#[tauri::command]
+pub async fn get_build_info() -> Result<BuildInfo, String> {
+    Ok(BuildInfo::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info_is_stamped_in() {
+        let info = BuildInfo::current();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(info.git_sha == UNKNOWN || info.git_sha.len() == 40);
+        assert_ne!(info.built_at, UNKNOWN);
+        assert!(!info.target.is_empty());
+
+        let dirty = BuildInfo {
+            git_sha: "3f2a9c1e5b7d".to_string(),
+            git_dirty: Some(true),
+            debug: false,
+            ..info
+        };
+        assert_eq!(
+            dirty.describe(),
+            format!("{} (3f2a9c1e-dirty)", env!("CARGO_PKG_VERSION"))
+        );
+    }
+}
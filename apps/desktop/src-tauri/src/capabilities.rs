@@ -0,0 +1,230 @@
+// Feature capabilities
+// Some features only work on some platforms, builds or configurations. The
+// frontend asks `get_capabilities` which are available, and why not, so it
+// can hide or explain a feature instead of invoking it to find out. They're
+// computed at startup from the platform and keychain probes and the
+// settings, recomputed when either changes, and each change is broadcast
+// as `capabilities-changed`.
+
+use crate::events::{self, EventSink};
+use crate::keyring::KeyringHealth;
+use crate::settings::AppSettings;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tauri::State;
+use tokio::sync::watch;
+
+/// Emitted with the new Capabilities whenever one changes
+pub const CAPABILITIES_CHANGED_EVENT: &str = "capabilities-changed";
+
+/// Why a feature can't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unavailable {
+    /// Not on this operating system
+    UnsupportedPlatform,
+    /// Supported here but not part of this build
+    NotBuilt,
+    /// The startup probe hasn't finished yet
+    NotChecked,
+    /// No keychain backend is reachable
+    KeychainMissing,
+    KeychainLocked,
+    KeychainError,
+    DevModeDisabled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capability {
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Unavailable>,
+}
+
+impl Capability {
+    fn from_check(check: Result<(), Unavailable>) -> Self {
+        Self {
+            available: check.is_ok(),
+            reason: check.err(),
+        }
+    }
+}
+
+/// Every feature the frontend may need to hide or explain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// The master key can be kept in the OS keychain
+    pub keychain: Capability,
+    /// The OS says whether the connection is metered, rather than the
+    /// `assume_metered` setting deciding
+    pub metered_detection: Capability,
+    /// Docker socket permissions are checked up front, with a fix to offer
+    pub docker_socket_check: Capability,
+    /// Host source directories mounted into service containers
+    pub source_mounts: Capability,
+    /// Unlocking with Touch ID
+    pub biometric_unlock: Capability,
+    pub tray: Capability,
+}
+
+impl Capabilities {
+    /// For `os` as in `std::env::consts::OS`; `keyring` is `None` until probed
+    pub fn compute(os: &str, keyring: Option<&KeyringHealth>, settings: &AppSettings) -> Self {
+        let on = |platforms: &[&str]| match platforms.contains(&os) {
+            true => Ok(()),
+            false => Err(Unavailable::UnsupportedPlatform),
+        };
+        let keychain = match keyring {
+            None => Err(Unavailable::NotChecked),
+            Some(KeyringHealth::Ok) => Ok(()),
+            Some(KeyringHealth::Unavailable { .. }) => Err(Unavailable::KeychainMissing),
+            Some(KeyringHealth::Locked { .. }) => Err(Unavailable::KeychainLocked),
+            Some(KeyringHealth::Error { .. }) => Err(Unavailable::KeychainError),
+        };
+        Self {
+            keychain: Capability::from_check(keychain),
+            metered_detection: Capability::from_check(on(&["linux", "windows"])),
+            docker_socket_check: Capability::from_check(on(&["linux"])),
+            source_mounts: Capability::from_check(match settings.dev_mode {
+                true => Ok(()),
+                false => Err(Unavailable::DevModeDisabled),
+            }),
+            biometric_unlock: Capability::from_check(
+                on(&["macos"]).and(Err(Unavailable::NotBuilt)),
+            ),
+            tray: Capability::from_check(on(&["linux", "macos", "windows"])),
+        }
+    }
+}
+
+struct Inputs {
+    keyring: Option<KeyringHealth>,
+    settings: AppSettings,
+    current: Capabilities,
+}
+
+/// The current capabilities, kept up to date with their inputs
+#[derive(Clone)]
+pub struct CapabilityCache {
+    sink: Arc<dyn EventSink>,
+    inputs: Arc<Mutex<Inputs>>,
+}
+
+impl CapabilityCache {
+    pub fn new(sink: Arc<dyn EventSink>, settings: AppSettings) -> Self {
+        let current = Capabilities::compute(std::env::consts::OS, None, &settings);
+        Self {
+            sink,
+            inputs: Arc::new(Mutex::new(Inputs {
+                keyring: None,
+                settings,
+                current,
+            })),
+        }
+    }
+
+    pub fn get(&self) -> Capabilities {
+        self.inputs.lock().unwrap().current
+    }
+
+    pub fn set_keyring(&self, health: KeyringHealth) {
+        self.update(|inputs| inputs.keyring = Some(health));
+    }
+
+    pub fn set_settings(&self, settings: AppSettings) {
+        self.update(|inputs| inputs.settings = settings);
+    }
+
+    /// Recompute on every settings change until the settings store goes away
+    pub async fn watch_settings(self, mut settings: watch::Receiver<AppSettings>) {
+        while settings.changed().await.is_ok() {
+            let latest = settings.borrow_and_update().clone();
+            self.set_settings(latest);
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Inputs)) {
+        let changed = {
+            let mut inputs = self.inputs.lock().unwrap();
+            change(&mut inputs);
+            let computed = Capabilities::compute(
+                std::env::consts::OS,
+                inputs.keyring.as_ref(),
+                &inputs.settings,
+            );
+            let changed = computed != inputs.current;
+            inputs.current = computed;
+            changed.then_some(computed)
+        };
+        if let Some(capabilities) = changed {
+            events::emit(&*self.sink, CAPABILITIES_CHANGED_EVENT, &capabilities);
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_capabilities(cache: State<'_, CapabilityCache>) -> Result<Capabilities, String> {
+    Ok(cache.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+
+    #[test]
+    fn test_schema_is_pinned() {
+        let capabilities = Capabilities::compute("macos", None, &AppSettings::default());
+        assert_eq!(
+            serde_json::to_value(capabilities).unwrap(),
+            serde_json::json!({
+                "keychain": { "available": false, "reason": "not_checked" },
+                "metered_detection": { "available": false, "reason": "unsupported_platform" },
+                "docker_socket_check": { "available": false, "reason": "unsupported_platform" },
+                "source_mounts": { "available": false, "reason": "dev_mode_disabled" },
+                "biometric_unlock": { "available": false, "reason": "not_built" },
+                "tray": { "available": true },
+            })
+        );
+
+        let linux =
+            Capabilities::compute("linux", Some(&KeyringHealth::Ok), &AppSettings::default());
+        assert_eq!(
+            serde_json::to_value(linux.keychain).unwrap(),
+            serde_json::json!({ "available": true })
+        );
+        assert!(linux.metered_detection.available && linux.docker_socket_check.available);
+        assert_eq!(
+            linux.biometric_unlock.reason,
+            Some(Unavailable::UnsupportedPlatform)
+        );
+    }
+
+    #[test]
+    fn test_changes_are_recomputed_and_broadcast() {
+        let sink = Arc::new(RecordingSink::default());
+        let cache = CapabilityCache::new(sink.clone(), AppSettings::default());
+        assert!(!cache.get().source_mounts.available);
+
+        cache.set_settings(AppSettings {
+            dev_mode: true,
+            ..AppSettings::default()
+        });
+        assert!(cache.get().source_mounts.available);
+        cache.set_keyring(KeyringHealth::Locked {
+            detail: "locked".to_string(),
+        });
+        assert_eq!(
+            cache.get().keychain.reason,
+            Some(Unavailable::KeychainLocked)
+        );
+        // Unchanged inputs don't make noise
+        cache.set_keyring(KeyringHealth::Locked {
+            detail: "still locked".to_string(),
+        });
+
+        let events = sink.events_named(CAPABILITIES_CHANGED_EVENT);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["keychain"]["reason"], "keychain_locked");
+    }
+}
@@ -0,0 +1,329 @@
+// Orphaned resource cleanup
+// Crashes and force-quits leave stopped arbor containers behind, which the
+// status check lists as services and whose names a later `make up` can
+// collide with. `cleanup_orphaned_resources` removes the exited ones and,
+// only when asked, the profile's volumes no container uses any more, since
+// those hold the services' data. Containers are removed without `-f`, so
+// one that started in the meantime is refused by docker rather than killed.
+// At launch, before services start, the containers (never the volumes) are
+// cleaned up the same way.
+
+use crate::docker;
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::profiles;
+use crate::protocol::Handshake;
+use crate::stats;
+use crate::uninstall::lines;
+use crate::ServiceManager;
+use crate::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use tauri::{AppHandle, Manager};
+
+/// Container states left by a crash; running, paused and restarting
+/// containers are never touched
+const ORPHANED_STATES: [&str; 3] = ["exited", "dead", "created"];
+
+/// What a cleanup deleted
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CleanupReport {
+    pub containers: Vec<String>,
+    pub volumes: Vec<String>,
+    /// Disk space the removed containers and volumes were using, as far as
+    /// docker reports it
+    pub reclaimed_bytes: u64,
+    /// What couldn't be removed, and why
+    pub errors: Vec<String>,
+}
+
+/// A stopped container, with the size of its writable layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Orphan {
+    name: String,
+    size: u64,
+}
+
+/// Remove the stopped arbor containers of a crash and, with
+/// `remove_volumes`, the profile's volumes no container uses
+pub async fn cleanup(
+    runner: &dyn CommandRunner,
+    project: &str,
+    remove_volumes: bool,
+) -> Result<CleanupReport, String> {
+    let mut report = CleanupReport::default();
+    let orphans = orphaned_containers(runner).await?;
+    let sizes: HashMap<&str, u64> = orphans.iter().map(|o| (o.name.as_str(), o.size)).collect();
+    let names: Vec<String> = orphans.iter().map(|orphan| orphan.name.clone()).collect();
+    for name in remove(runner, "container", &names, &mut report.errors).await {
+        report.reclaimed_bytes += sizes.get(name.as_str()).copied().unwrap_or(0);
+        report.containers.push(name);
+    }
+
+    if remove_volumes {
+        let filter = format!("label={}={}", docker::PROJECT_LABEL, project);
+        let spec = CommandSpec::new("docker").args([
+            "volume",
+            "ls",
+            "--filter",
+            "dangling=true",
+            "--filter",
+            &filter,
+            "--format",
+            "{{.Name}}",
+        ]);
+        let volumes = lines(runner, &spec).await?;
+        let sizes = volume_sizes(runner).await;
+        for name in remove(runner, "volume", &volumes, &mut report.errors).await {
+            report.reclaimed_bytes += sizes.get(&name).copied().unwrap_or(0);
+            report.volumes.push(name);
+        }
+    }
+    Ok(report)
+}
+
+/// The arbor containers in one of ORPHANED_STATES
+async fn orphaned_containers(runner: &dyn CommandRunner) -> Result<Vec<Orphan>, String> {
+    let spec = CommandSpec::new("docker").args([
+        "ps",
+        "--all",
+        "--size",
+        "--filter",
+        docker::CONTAINER_NAME_FILTER,
+        "--format",
+        "{{.Names}}\t{{.State}}\t{{.Size}}",
+    ]);
+    Ok(lines(runner, &spec)
+        .await?
+        .iter()
+        .filter_map(|line| parse_container(line))
+        .collect())
+}
+
+/// A `docker ps` line, if the container is orphaned; its size reads like
+/// `12.3kB (virtual 245MB)`, of which only the first part is its own
+fn parse_container(line: &str) -> Option<Orphan> {
+    let mut fields = line.split('\t');
+    let (name, state, size) = (fields.next()?, fields.next()?, fields.next().unwrap_or(""));
+    if !ORPHANED_STATES.contains(&state.trim()) {
+        return None;
+    }
+    let size = size.split_whitespace().next().and_then(stats::parse_size);
+    Some(Orphan {
+        name: name.trim().to_string(),
+        size: size.unwrap_or(0),
+    })
+}
+
+/// Sizes of the local volumes, from `docker system df -v`; empty where it
+/// can't be read, so removal goes on without them
+pub async fn volume_sizes(runner: &dyn CommandRunner) -> HashMap<String, u64> {
+    let spec = CommandSpec::new("docker").args(["system", "df", "-v"]);
+    match lines(runner, &spec).await {
+        Ok(lines) => parse_volume_sizes(&lines),
+        Err(e) => {
+            warn!("⚠️  Can't tell how much space volumes use: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// The `VOLUME NAME  LINKS  SIZE` table of `docker system df -v`
+fn parse_volume_sizes(lines: &[String]) -> HashMap<String, u64> {
+    lines
+        .iter()
+        .skip_while(|line| !line.starts_with("VOLUME NAME"))
+        .skip(1)
+        .map_while(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [name, links, size] if links.parse::<u32>().is_ok() => {
+                    Some((name.to_string(), stats::parse_size(size)?))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `docker <kind> rm` each of `names`, returning those removed; docker goes
+/// on past the ones it refuses, which are added to `errors`
+async fn remove(
+    runner: &dyn CommandRunner,
+    kind: &str,
+    names: &[String],
+    errors: &mut Vec<String>,
+) -> Vec<String> {
+    if names.is_empty() {
+        return Vec::new();
+    }
+    let spec = CommandSpec::new("docker")
+        .args([kind, "rm"])
+        .args(names.iter().cloned());
+    match runner.output(&spec).await {
+        Ok(output) => {
+            if !output.success() {
+                errors.extend(
+                    output
+                        .stderr_text()
+                        .lines()
+                        .map(|line| line.trim().to_string()),
+                );
+            }
+            let removed = output.stdout_text();
+            let removed: Vec<&str> = removed.lines().map(str::trim).collect();
+            names
+                .iter()
+                .filter(|name| removed.contains(&name.as_str()))
+                .cloned()
+                .collect()
+        }
+        Err(e) => {
+            errors.push(format!("Failed to remove {}s: {}", kind, e));
+            Vec::new()
+        }
+    }
+}
+
+/// At launch: remove the containers a crash left, so `make up` doesn't trip
+/// over their names; volumes are left alone
+pub async fn cleanup_at_launch(runner: &dyn CommandRunner) {
+    match cleanup(runner, &profiles::compose_project(), false).await {
+        Ok(report) if !report.containers.is_empty() => info!(
+            "🧹 Removed {} stopped containers left from an earlier run",
+            report.containers.len()
+        ),
+        Ok(_) => {}
+        Err(e) => warn!("⚠️  Skipped cleaning up stopped containers: {}", e),
+    }
+}
+
+/// Remove stopped arbor containers and, with `remove_volumes`, the unused
+/// volumes of the active profile, returning what went
+/// Refused while services are starting or stopping, when containers come and
+/// go as part of that.
+#[tauri::command]
+pub async fn cleanup_orphaned_resources(
+    app_handle: AppHandle,
+    remove_volumes: bool,
+) -> Result<CleanupReport, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    app_handle
+        .state::<ServiceManager>()
+        .stack
+        .lock()
+        .settled()
+        .map_err(|e| e.to_string())?;
+    let project = profiles::compose_project();
+    let report = cleanup(&SystemRunner, &project, remove_volumes).await?;
+    info!(
+        "🧹 Removed {} containers and {} volumes, reclaiming {} bytes",
+        report.containers.len(),
+        report.volumes.len(),
+        report.reclaimed_bytes
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    fn runner() -> MockRunner {
+        MockRunner::new(|spec| {
+            let args = spec.args.join(" ");
+            let stdout = if args.starts_with("ps") {
+                "arbor-postgres\texited\t1.5kB (virtual 400MB)\n\
+                 arbor-api\trunning\t2kB (virtual 300MB)\n\
+                 arbor-minio\tcreated\t0B (virtual 200MB)\n"
+            } else if args.starts_with("volume ls") {
+                "arbor_postgres_data\n"
+            } else if args.starts_with("system df") {
+                "Local Volumes space usage:\n\n\
+                 VOLUME NAME           LINKS     SIZE\n\
+                 arbor_postgres_data   0         1MB\n\n\
+                 Build cache usage: 0B\n"
+            } else if args.starts_with("container rm") || args.starts_with("volume rm") {
+                return Ok(CommandOutput {
+                    code: Some(0),
+                    stdout: spec.args[2..].join("\n").into_bytes(),
+                    stderr: Vec::new(),
+                });
+            } else {
+                ""
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        })
+    }
+
+    fn removals(runner: &MockRunner) -> Vec<String> {
+        runner
+            .calls()
+            .into_iter()
+            .map(|call| call.args.join(" "))
+            .filter(|args| args.contains(" rm "))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_only_stopped_containers_are_removed() {
+        let runner = runner();
+        let report = cleanup(&runner, "arbor", false).await.unwrap();
+        assert_eq!(report.containers, ["arbor-postgres", "arbor-minio"]);
+        assert!(report.volumes.is_empty());
+        assert_eq!(report.reclaimed_bytes, 1_500);
+        assert_eq!(
+            removals(&runner),
+            ["container rm arbor-postgres arbor-minio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_volumes_are_removed_only_when_asked() {
+        let runner = runner();
+        let report = cleanup(&runner, "arbor", true).await.unwrap();
+        assert_eq!(report.volumes, ["arbor_postgres_data"]);
+        assert_eq!(report.reclaimed_bytes, 1_500 + 1_000_000);
+        let volume_ls = runner
+            .calls()
+            .into_iter()
+            .find(|call| {
+                call.args
+                    .starts_with(&["volume".to_string(), "ls".to_string()])
+            })
+            .unwrap();
+        assert!(volume_ls.args.contains(&"dangling=true".to_string()));
+        assert!(volume_ls
+            .args
+            .contains(&"label=com.docker.compose.project=arbor".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_refused_removals_are_reported() {
+        let runner = MockRunner::new(|spec| {
+            let (stdout, stderr, code) = match spec.args[0].as_str() {
+                "ps" => ("arbor-postgres\texited\t0B\narbor-api\texited\t0B\n", "", 0),
+                _ => (
+                    "arbor-postgres\n",
+                    "Error response from daemon: cannot remove container \"arbor-api\": \
+                     container is running\n",
+                    1,
+                ),
+            };
+            Ok(CommandOutput {
+                code: Some(code),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+            })
+        });
+        let report = cleanup(&runner, "arbor", false).await.unwrap();
+        assert_eq!(report.containers, ["arbor-postgres"]);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].contains("container is running"));
+    }
+}
@@ -0,0 +1,227 @@
+// Setup command output
+// A verbose make target can print tens of megabytes. The full output goes to
+// a per-invocation file under the logs dir; the UI gets a capped copy (head
+// and tail) plus the file name, and pages through the rest on demand.
+
+use crate::encoding::OutputText;
+use crate::paths::{self, PathPolicy};
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+
+pub const COMMAND_LOGS_DIR_NAME: &str = "commands";
+
+/// Most text returned inline, split between head and tail
+const MAX_INLINE_BYTES: usize = 256 * 1024;
+const MAX_PAGE_LINES: usize = 5000;
+const MAX_LOG_FILES: usize = 50;
+
+/// Result of a `run_setup_command` job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetupCommandOutput {
+    /// Output text, capped to its head and tail when large
+    pub output: OutputText,
+    pub truncated: bool,
+    pub total_lines: usize,
+    /// Full output, readable with `read_command_output`
+    pub output_file: Option<String>,
+}
+
+/// A page of a command's full output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutputPage {
+    pub lines: Vec<String>,
+    pub offset: usize,
+    pub total_lines: usize,
+    pub eof: bool,
+}
+
+/// Keep the head and tail of `text` within `max_bytes`
+/// Returns the capped text and whether anything was left out.
+pub fn cap_text(text: &str, max_bytes: usize) -> (String, bool) {
+    if text.len() <= max_bytes {
+        return (text.to_string(), false);
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let budget = max_bytes / 2;
+    let mut head = 0;
+    let mut used = 0;
+    while head < lines.len() && used + lines[head].len() < budget {
+        used += lines[head].len() + 1;
+        head += 1;
+    }
+    let mut tail = lines.len();
+    used = 0;
+    while tail > head && used + lines[tail - 1].len() < budget {
+        used += lines[tail - 1].len() + 1;
+        tail -= 1;
+    }
+
+    let omitted = tail - head;
+    let mut capped = lines[..head].join("\n");
+    capped.push_str(&format!("\n… {} lines omitted …\n", omitted));
+    capped.push_str(&lines[tail..].join("\n"));
+    (capped, true)
+}
+
+pub struct CommandLogs {
+    dir: PathBuf,
+}
+
+impl CommandLogs {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn for_app(app_handle: &AppHandle) -> Result<Self, String> {
+        let logs = paths::log_dir(app_handle)?;
+        Ok(Self::new(logs.join(COMMAND_LOGS_DIR_NAME)))
+    }
+
+    /// Save `stdout` of `target` and build the capped result
+    pub fn record(&self, target: &str, stdout: &[u8], include_raw: bool) -> SetupCommandOutput {
+        let mut output = OutputText::decode(stdout, include_raw);
+        let total_lines = output.text.lines().count();
+        let (text, truncated) = cap_text(&output.text, MAX_INLINE_BYTES);
+        output.text = text;
+        if truncated {
+            // The full bytes are in the file instead
+            output.raw_base64 = None;
+        }
+
+        let output_file = match self.save(target, stdout) {
+            Ok(name) => Some(name),
+            Err(e) => {
+                warn!("⚠️  {}", e);
+                None
+            }
+        };
+        SetupCommandOutput {
+            output,
+            truncated,
+            total_lines,
+            output_file,
+        }
+    }
+
+    fn save(&self, target: &str, stdout: &[u8]) -> Result<String, String> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create command log directory: {}", e))?;
+        let name = format!(
+            "{}-{}.log",
+            target.replace(['/', ':'], "_"),
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        std::fs::write(self.dir.join(&name), stdout)
+            .map_err(|e| format!("Failed to write command output: {}", e))?;
+        self.prune();
+        Ok(name)
+    }
+
+    fn prune(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<(std::time::SystemTime, PathBuf)> = entries
+            .flatten()
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        files.sort();
+        let excess = files.len().saturating_sub(MAX_LOG_FILES);
+        for (_, path) in files.into_iter().take(excess) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Lines `offset..offset + limit` of a saved output
+    pub fn read(&self, file: &str, offset: usize, limit: usize) -> Result<OutputPage, String> {
+        let path = paths::validate(Path::new(file), PathPolicy::FileWithin(&self.dir))?;
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Failed to read command output: {}", e))?;
+        let text = crate::encoding::decode(&bytes).text;
+
+        let total_lines = text.lines().count();
+        let lines: Vec<String> = text
+            .lines()
+            .skip(offset)
+            .take(limit.min(MAX_PAGE_LINES))
+            .map(str::to_string)
+            .collect();
+        Ok(OutputPage {
+            eof: offset + lines.len() >= total_lines,
+            lines,
+            offset,
+            total_lines,
+        })
+    }
+}
+
+#[tauri::command]
+pub async fn read_command_output(
+    app_handle: AppHandle,
+    file: String,
+    offset: usize,
+    limit: usize,
+) -> Result<OutputPage, String> {
+    CommandLogs::for_app(&app_handle)?.read(&file, offset, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    /// Logs in a scratch directory, removed along with the returned guard
+    fn logs() -> (TempDir, CommandLogs) {
+        let dir = temp_dir();
+        let logs = CommandLogs::new(dir.path().to_path_buf());
+        (dir, logs)
+    }
+
+    fn numbered(lines: usize) -> String {
+        (0..lines).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[test]
+    fn test_small_output_is_untouched() {
+        assert_eq!(cap_text("ok\n", 100), ("ok\n".to_string(), false));
+    }
+
+    #[test]
+    fn test_cap_keeps_head_and_tail() {
+        let (capped, truncated) = cap_text(&numbered(1000), 200);
+        assert!(truncated);
+        assert!(capped.len() <= 200 + 40, "{}", capped.len());
+        assert!(capped.starts_with("line 0\n"));
+        assert!(capped.ends_with("line 999"));
+        assert!(capped.contains("lines omitted"));
+    }
+
+    #[test]
+    fn test_large_output_is_saved_and_paged() {
+        let (_dir, logs) = logs();
+        let stdout = numbered(100_000);
+
+        let result = logs.record("db-migrate", stdout.as_bytes(), true);
+        assert!(result.truncated);
+        assert!(result.output.text.len() <= MAX_INLINE_BYTES + 40);
+        assert_eq!(result.total_lines, 100_000);
+        assert!(result.output.raw_base64.is_none());
+
+        let file = result.output_file.unwrap();
+        let page = logs.read(&file, 50_000, 3).unwrap();
+        assert_eq!(page.lines, vec!["line 50000", "line 50001", "line 50002"]);
+        assert!(!page.eof);
+        assert!(logs.read(&file, 99_999, 10).unwrap().eof);
+    }
+
+    #[test]
+    fn test_read_rejects_paths_outside_log_dir() {
+        let (_dir, logs) = logs();
+        assert!(logs.read("../settings.json", 0, 10).is_err());
+        assert!(logs.read("/etc/passwd", 0, 10).is_err());
+    }
+}
@@ -0,0 +1,388 @@
+// App / stack version compatibility
+// Containers carry the version of the app that started them in a label. An
+// auto-updated app may find containers speaking an API it no longer
+// understands, so before starting over or adopting a running stack the
+// labels are checked against the current version. Incompatible stacks are
+// refused with a `version-mismatch` event until the services are updated or
+// the user overrides the check for the session.
+
+use crate::confirm::Confirmations;
+use crate::docker::PROJECT_LABEL;
+use crate::events::{self, EventSink};
+use crate::messages::{Coded, Message};
+use crate::process::{CommandRunner, CommandSpec};
+use crate::profiles;
+use crate::protocol::Handshake;
+use crate::{info, warn};
+use semver::Version;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use tauri::State;
+
+pub const APP_VERSION_LABEL: &str = "dev.arbor.app-version";
+/// Emitted when a running stack is refused, with a VersionMismatch payload
+pub const VERSION_MISMATCH_EVENT: &str = "version-mismatch";
+
+/// A release whose app can't work with containers started before it, nor
+/// older apps with containers it started; add one whenever the frontend and
+/// services stop understanding each other across a patch or minor bump
+pub struct BreakingRelease {
+    pub version: &'static str,
+    pub reason: &'static str,
+}
+
+/// Breaking releases beyond what semver already implies (a new major, or a
+/// new minor before 1.0)
+pub const BREAKING_RELEASES: &[BreakingRelease] = &[];
+
+pub fn app_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compatibility {
+    Compatible,
+    Incompatible { reason: String },
+}
+
+/// Whether an app at `app` can work with containers started by `stack`
+pub fn compatibility(stack: &str, app: &str, breaking: &[BreakingRelease]) -> Compatibility {
+    let incompatible = |reason: String| Compatibility::Incompatible { reason };
+    let (Ok(stack), Ok(app)) = (Version::parse(stack.trim()), Version::parse(app.trim())) else {
+        return incompatible(format!("unrecognized version {:?}", stack));
+    };
+    if stack == app {
+        return Compatibility::Compatible;
+    }
+    if stack.major != app.major {
+        return incompatible(format!(
+            "major version changed from {} to {}",
+            stack.major, app.major
+        ));
+    }
+    if app.major == 0 && stack.minor != app.minor {
+        return incompatible(format!(
+            "minor version changed from 0.{} to 0.{}",
+            stack.minor, app.minor
+        ));
+    }
+
+    let (low, high) = if stack < app {
+        (&stack, &app)
+    } else {
+        (&app, &stack)
+    };
+    breaking
+        .iter()
+        .filter_map(|release| Some((Version::parse(release.version).ok()?, release.reason)))
+        .find(|(version, _)| low < version && version <= high)
+        .map_or(Compatibility::Compatible, |(version, reason)| {
+            incompatible(format!("{} changed in {}", reason, version))
+        })
+}
+
+/// Running services the current app can't work with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionMismatch {
+    /// App version that started the incompatible containers
+    pub stack_version: String,
+    pub app_version: String,
+    pub services: Vec<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Services {} were started by Arbor {}, which this version ({}) can't work with ({}); \
+             update services or start anyway",
+            self.services.join(", "),
+            self.stack_version,
+            self.app_version,
+            self.reason
+        )
+    }
+}
+
+impl Coded for VersionMismatch {
+    fn message(&self) -> Message {
+        Message::new("version-mismatch")
+            .with_param("stack_version", &self.stack_version)
+            .with_param("app_version", &self.app_version)
+            .with_param("services", self.services.join(", "))
+    }
+}
+
+/// The first incompatible version among `labels` (service → version), with
+/// every service started by it; unlabelled services predate labelling or
+/// weren't started by Arbor and are let through
+pub fn find_mismatch(
+    labels: &BTreeMap<String, String>,
+    app: &str,
+    breaking: &[BreakingRelease],
+) -> Option<VersionMismatch> {
+    let (version, reason) = labels
+        .values()
+        .filter(|version| !version.is_empty())
+        .find_map(|version| match compatibility(version, app, breaking) {
+            Compatibility::Compatible => None,
+            Compatibility::Incompatible { reason } => Some((version.clone(), reason)),
+        })?;
+    Some(VersionMismatch {
+        services: labels
+            .iter()
+            .filter(|(_, label)| **label == version)
+            .map(|(service, _)| service.clone())
+            .collect(),
+        stack_version: version,
+        app_version: app.to_string(),
+        reason,
+    })
+}
+
+/// Most common version among the labels, i.e. the one that started the stack
+pub fn stack_version(labels: &BTreeMap<String, String>) -> Option<String> {
+    let mut counts: BTreeMap<&String, usize> = BTreeMap::new();
+    for version in labels.values().filter(|version| !version.is_empty()) {
+        *counts.entry(version).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(version, _)| version.clone())
+}
+
+/// Service → app version label of the stack's running containers; empty
+/// for containers without the label
+pub async fn running_versions(
+    runner: &dyn CommandRunner,
+) -> Result<BTreeMap<String, String>, String> {
+    let project_filter = format!("label={}={}", PROJECT_LABEL, profiles::compose_project());
+    let format = format!(
+        "{{{{.Label \"com.docker.compose.service\"}}}}\t{{{{.Label \"{}\"}}}}",
+        APP_VERSION_LABEL
+    );
+    let ps =
+        CommandSpec::new("docker").args(["ps", "--filter", &project_filter, "--format", &format]);
+    let output = runner
+        .output(&ps)
+        .await
+        .map_err(|e| format!("Failed to list containers: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to list containers: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(output
+        .stdout_text()
+        .lines()
+        .filter_map(|line| {
+            let (service, version) = line.trim().split_once('\t').unwrap_or((line.trim(), ""));
+            (!service.is_empty()).then(|| (service.to_string(), version.trim().to_string()))
+        })
+        .collect())
+}
+
+/// An override of the check, kept for the session and reported by
+/// `get_full_state` and the diagnostics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionOverride {
+    /// Version that started the stack refused before the override, if any
+    pub stack_version: Option<String>,
+    pub app_version: String,
+    pub overridden_at: String,
+}
+
+/// What `allow_version_mismatch` lets through
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MismatchAllowance {
+    /// The last refusal, which the override is confirmed against
+    pub mismatch: Option<VersionMismatch>,
+    /// Only on a dry run; the real call passes it back
+    pub confirmation_token: Option<String>,
+}
+
+/// Whether incompatible stacks are let through this session
+#[derive(Default)]
+pub struct VersionGate {
+    overridden: Mutex<Option<VersionOverride>>,
+    refused: Mutex<Option<VersionMismatch>>,
+}
+
+impl VersionGate {
+    /// Refuse a running stack this app can't work with
+    /// Returns the version that started the stack, if it's labelled.
+    pub async fn check(
+        &self,
+        sink: &dyn EventSink,
+        runner: &dyn CommandRunner,
+    ) -> Result<Option<String>, String> {
+        let labels = running_versions(runner).await?;
+        match find_mismatch(&labels, app_version(), BREAKING_RELEASES) {
+            Some(mismatch) if self.overridden().is_some() => {
+                warn!("⚠️  {} (check overridden)", mismatch);
+            }
+            Some(mismatch) => {
+                warn!("🚫 {}", mismatch);
+                events::emit_coded(sink, VERSION_MISMATCH_EVENT, &mismatch);
+                let error = mismatch.to_string();
+                *self.refused.lock().unwrap() = Some(mismatch);
+                return Err(error);
+            }
+            None => {}
+        }
+        Ok(stack_version(&labels))
+    }
+
+    /// The override in force, if the check was overridden this session
+    pub fn overridden(&self) -> Option<VersionOverride> {
+        self.overridden.lock().unwrap().clone()
+    }
+
+    fn refused(&self) -> Option<VersionMismatch> {
+        self.refused.lock().unwrap().clone()
+    }
+
+    fn allow(&self) -> VersionOverride {
+        let allowed = VersionOverride {
+            stack_version: self.refused().map(|mismatch| mismatch.stack_version),
+            app_version: app_version().to_string(),
+            overridden_at: chrono::Utc::now().to_rfc3339(),
+        };
+        *self.overridden.lock().unwrap() = Some(allowed.clone());
+        allowed
+    }
+}
+
+/// Let an incompatible stack run for the rest of the session, instead of
+/// running the `update_services` job; run with `dry_run` first for the
+/// confirmation token
+#[tauri::command]
+pub async fn allow_version_mismatch(
+    handshake: State<'_, Handshake>,
+    gate: State<'_, VersionGate>,
+    confirmations: State<'_, Confirmations>,
+    dry_run: bool,
+    confirmation_token: Option<String>,
+) -> Result<MismatchAllowance, String> {
+    handshake.ensure()?;
+    let mismatch = gate.refused();
+    let refused_version = mismatch
+        .as_ref()
+        .map(|mismatch| mismatch.stack_version.as_str());
+    let action = format!(
+        "allow_version_mismatch:{}",
+        refused_version.unwrap_or_default()
+    );
+    if dry_run {
+        return Ok(MismatchAllowance {
+            mismatch,
+            confirmation_token: Some(confirmations.issue(&action)),
+        });
+    }
+    let token = confirmation_token
+        .ok_or("allow_version_mismatch needs the confirmation token from a dry run")?;
+    confirmations.redeem(&action, &token)?;
+
+    gate.allow();
+    info!("⚠️  Version compatibility check overridden for this session");
+    Ok(MismatchAllowance {
+        mismatch,
+        confirmation_token: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    const TABLE: &[BreakingRelease] = &[BreakingRelease {
+        version: "1.4.0",
+        reason: "the events API",
+    }];
+
+    fn is_compatible(stack: &str, app: &str) -> bool {
+        compatibility(stack, app, TABLE) == Compatibility::Compatible
+    }
+
+    #[test]
+    fn test_compatibility_across_patch_minor_and_major() {
+        // Patch and minor bumps are fine from 1.0 on, either way
+        assert!(is_compatible("1.2.3", "1.2.3"));
+        assert!(is_compatible("1.2.3", "1.2.9"));
+        assert!(is_compatible("1.2.9", "1.2.3"));
+        assert!(is_compatible("1.1.0", "1.3.5"));
+        // Unless a breaking release lies between them
+        assert!(!is_compatible("1.3.5", "1.4.0"));
+        assert!(!is_compatible("1.2.0", "1.4.2"));
+        assert!(!is_compatible("1.4.1", "1.3.0"));
+        assert!(is_compatible("1.4.0", "1.5.0"));
+        // Majors never mix
+        assert!(!is_compatible("1.9.9", "2.0.0"));
+        assert!(!is_compatible("2.0.0", "1.0.0"));
+        // Before 1.0 the minor is the major
+        assert!(is_compatible("0.1.0", "0.1.7"));
+        assert!(!is_compatible("0.1.7", "0.2.0"));
+        // Prereleases compare below their release
+        assert!(!is_compatible("1.4.0-beta.1", "1.4.0"));
+        assert!(!is_compatible("garbage", "1.0.0"));
+    }
+
+    #[test]
+    fn test_find_mismatch_lists_services_of_the_old_version() {
+        let labels = BTreeMap::from([
+            ("api".to_string(), "0.1.0".to_string()),
+            ("postgres".to_string(), "0.1.0".to_string()),
+            ("redis".to_string(), "0.2.0".to_string()),
+            ("proxy".to_string(), String::new()),
+        ]);
+
+        let mismatch = find_mismatch(&labels, "0.2.0", TABLE).unwrap();
+        assert_eq!(mismatch.stack_version, "0.1.0");
+        assert_eq!(mismatch.services, ["api", "postgres"]);
+        assert_eq!(mismatch.message().code, "version-mismatch");
+        assert!(find_mismatch(&labels, "0.1.3", TABLE).is_some());
+        assert_eq!(stack_version(&labels).as_deref(), Some("0.1.0"));
+
+        let unlabelled = BTreeMap::from([("api".to_string(), String::new())]);
+        assert!(find_mismatch(&unlabelled, "0.2.0", TABLE).is_none());
+        assert_eq!(stack_version(&unlabelled), None);
+    }
+
+    #[tokio::test]
+    async fn test_gate_blocks_until_overridden() {
+        let stdout = "api\t0.0.1\nredis\t\n";
+        let runner = MockRunner::new(move |_| {
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        });
+        let sink = RecordingSink::default();
+        let gate = VersionGate::default();
+
+        assert!(gate.check(&sink, &runner).await.is_err());
+        let events = sink.envelopes_named(VERSION_MISMATCH_EVENT);
+        assert_eq!(events[0]["payload"]["services"], serde_json::json!(["api"]));
+        assert_eq!(events[0]["message"]["code"], "version-mismatch");
+
+        assert_eq!(gate.refused().unwrap().stack_version, "0.0.1");
+        assert_eq!(gate.overridden(), None);
+        let allowed = gate.allow();
+        assert_eq!(allowed.stack_version.as_deref(), Some("0.0.1"));
+        assert_eq!(gate.overridden(), Some(allowed));
+        assert_eq!(
+            gate.check(&sink, &runner).await.unwrap().as_deref(),
+            Some("0.0.1")
+        );
+        assert_eq!(sink.events_named(VERSION_MISMATCH_EVENT).len(), 1);
+    }
+}
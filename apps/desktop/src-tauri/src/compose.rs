@@ -0,0 +1,334 @@
+// Docker Compose check
+// The docker CLI can be installed without the compose plugin, and then
+// `make up`, which runs `docker compose`, fails with a usage error that
+// doesn't say so. `check` runs `docker compose version`, which goes to the
+// standalone binary where `tool_paths.compose` sets one, and otherwise
+// probes the legacy `docker-compose` so the error can say it's there but
+// not enough. Starting services is refused with ComposeMissing unless the
+// plugin or a configured standalone binary answers.
+// Optional services sit behind compose profiles; `service_profile`, or the
+// profile passed to `start_services`, must be one `config --profiles` lists.
+
+use crate::jobs::JobEnv;
+use crate::messages::{Coded, Message};
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::services::ComposeSetup;
+use crate::tools::{self, LocatedTool, Tool};
+use crate::uninstall;
+use semver::Version;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use tauri::AppHandle;
+
+/// Emitted with a ComposeMissing when starting is refused
+pub const COMPOSE_MISSING_EVENT: &str = "compose-missing";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComposeVariant {
+    /// `docker compose`
+    Plugin,
+    /// The binary `tool_paths.compose` sets, which `docker compose` runs as
+    Standalone,
+    /// `docker-compose` on PATH; the Makefile doesn't use it
+    Legacy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComposeInstall {
+    pub variant: ComposeVariant,
+    /// e.g. `2.24.5`, without the `v` some builds print
+    pub version: String,
+    /// Where the standalone binary is; `None` for the plugin
+    pub path: Option<String>,
+}
+
+impl ComposeInstall {
+    /// Whether services can be started with it
+    pub fn usable(&self) -> bool {
+        self.variant != ComposeVariant::Legacy
+    }
+}
+
+/// Why the stack wasn't started: no `docker compose` to start it with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComposeMissing {
+    /// The legacy `docker-compose`, when that's all there is
+    pub legacy: Option<ComposeInstall>,
+    pub detail: String,
+}
+
+impl fmt::Display for ComposeMissing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.legacy {
+            Some(legacy) => write!(
+                f,
+                "Only the legacy docker-compose {} is installed, and Arbor needs the docker \
+                 compose plugin (v2); install it, or set tool_paths.compose",
+                legacy.version
+            ),
+            None => write!(
+                f,
+                "Docker Compose isn't installed; install the docker compose plugin (v2): {}",
+                self.detail
+            ),
+        }
+    }
+}
+
+impl Coded for ComposeMissing {
+    fn message(&self) -> Message {
+        match &self.legacy {
+            Some(legacy) => {
+                Message::new("compose-legacy-only").with_param("version", &legacy.version)
+            }
+            None => Message::new("compose-missing"),
+        }
+    }
+}
+
+/// The version in `compose version` output, e.g. `Docker Compose version
+/// v2.24.5`, `2.24.5` or `docker-compose version 1.29.2, build 5becea4c`
+pub fn parse_version(output: &str) -> Option<String> {
+    output.split_whitespace().find_map(|word| {
+        let word = word.trim_end_matches(',');
+        let word = word.strip_prefix('v').unwrap_or(word);
+        Version::parse(word).ok().map(|version| version.to_string())
+    })
+}
+
+/// The version `spec` prints, if it runs
+async fn version_of(runner: &dyn CommandRunner, spec: &CommandSpec) -> Result<String, String> {
+    let output = runner.output(spec).await.map_err(|e| e.to_string())?;
+    if !output.success() {
+        return Err(output.stderr_text().trim().to_string());
+    }
+    let stdout = output.stdout_text();
+    parse_version(&stdout).ok_or_else(|| format!("Unrecognized version {:?}", stdout.trim()))
+}
+
+/// Which compose is installed, given the resolved `tools`
+pub async fn check(
+    runner: &dyn CommandRunner,
+    tools: &[LocatedTool],
+) -> Result<ComposeInstall, ComposeMissing> {
+    let spec = CommandSpec::new("docker").args(["compose", "version"]);
+    let detail = match version_of(runner, &spec).await {
+        Ok(version) => {
+            let standalone = tools
+                .iter()
+                .find(|tool| tool.tool == Tool::Compose)
+                .and_then(|tool| tool.path.as_ref());
+            return Ok(ComposeInstall {
+                variant: match standalone {
+                    Some(_) => ComposeVariant::Standalone,
+                    None => ComposeVariant::Plugin,
+                },
+                version,
+                path: standalone.map(|path| path.display().to_string()),
+            });
+        }
+        Err(e) => e,
+    };
+    let legacy = CommandSpec::new("docker-compose").args(["version"]);
+    match version_of(runner, &legacy).await {
+        Ok(version) => Ok(ComposeInstall {
+            variant: ComposeVariant::Legacy,
+            version,
+            path: None,
+        }),
+        Err(_) => Err(ComposeMissing {
+            legacy: None,
+            detail,
+        }),
+    }
+}
+
+/// `check`, failing unless services can be started with what it found
+pub async fn ensure_usable(
+    runner: &dyn CommandRunner,
+    tools: &[LocatedTool],
+) -> Result<ComposeInstall, ComposeMissing> {
+    let install = check(runner, tools).await?;
+    if !install.usable() {
+        return Err(ComposeMissing {
+            detail: format!(
+                "docker compose isn't available; docker-compose {} is",
+                install.version
+            ),
+            legacy: Some(install),
+        });
+    }
+    Ok(install)
+}
+
+/// Profiles the compose files declare
+pub async fn profiles(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<Vec<String>, String> {
+    let spec = setup
+        .compose_command(project_root)
+        .args(["config", "--profiles"]);
+    uninstall::lines(runner, &spec).await
+}
+
+/// Fails unless `setup`'s profile, if it has one, is declared
+pub async fn ensure_profile(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<(), String> {
+    let Some(profile) = &setup.profile else {
+        return Ok(());
+    };
+    let declared = profiles(runner, project_root, setup).await?;
+    if declared.contains(profile) {
+        return Ok(());
+    }
+    match declared.is_empty() {
+        true => Err(format!(
+            "Unknown compose profile {:?}: the compose files declare none",
+            profile
+        )),
+        false => Err(format!(
+            "Unknown compose profile {:?}; the compose files declare {}",
+            profile,
+            declared.join(", ")
+        )),
+    }
+}
+
+/// Services `setup` starts: those without a profile, and those in its own
+pub async fn enabled_services(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<Vec<String>, String> {
+    let spec = setup
+        .compose_command(project_root)
+        .args(["config", "--services"]);
+    uninstall::lines(runner, &spec).await
+}
+
+/// Profiles `service_profile` can be set to
+#[tauri::command]
+pub async fn get_compose_profiles(app_handle: AppHandle) -> Result<Vec<String>, String> {
+    let env = JobEnv::for_app(&app_handle)?;
+    profiles(&SystemRunner, &env.project_root, &env.setup).await
+}
+
+/// Which compose is installed and its version: the plugin, a configured
+/// standalone binary, or the legacy `docker-compose`
+#[tauri::command]
+pub async fn check_compose_installed() -> Result<ComposeInstall, ComposeMissing> {
+    check(&SystemRunner, &tools::located()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::settings::AppSettings;
+    use crate::tools::ToolSource;
+    use std::path::PathBuf;
+
+    fn runner(plugin: Option<&'static str>, legacy: Option<&'static str>) -> MockRunner {
+        MockRunner::new(move |spec| {
+            let answer = match spec.program.as_str() {
+                "docker-compose" => legacy,
+                _ => plugin,
+            };
+            Ok(CommandOutput {
+                code: Some(if answer.is_some() { 0 } else { 1 }),
+                stdout: answer.unwrap_or_default().as_bytes().to_vec(),
+                stderr: b"docker: 'compose' is not a docker command.\n".to_vec(),
+            })
+        })
+    }
+
+    #[test]
+    fn test_versions_parse_with_or_without_a_v() {
+        assert_eq!(
+            parse_version("Docker Compose version v2.24.5\n").as_deref(),
+            Some("2.24.5")
+        );
+        assert_eq!(parse_version("2.24.5\n").as_deref(), Some("2.24.5"));
+        assert_eq!(
+            parse_version("Docker Compose version v2.24.6-desktop.1").as_deref(),
+            Some("2.24.6-desktop.1")
+        );
+        assert_eq!(
+            parse_version("docker-compose version 1.29.2, build 5becea4c").as_deref(),
+            Some("1.29.2")
+        );
+        assert_eq!(parse_version("Docker Compose version dev"), None);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_is_preferred_and_legacy_alone_is_refused() {
+        let plugin = check(&runner(Some("Docker Compose version v2.24.5\n"), None), &[]).await;
+        assert_eq!(plugin.unwrap().variant, ComposeVariant::Plugin);
+
+        let configured = LocatedTool {
+            tool: Tool::Compose,
+            path: Some(PathBuf::from("/opt/compose/docker-compose")),
+            source: Some(ToolSource::Setting),
+            error: None,
+        };
+        let standalone = check(&runner(Some("2.24.5\n"), None), &[configured])
+            .await
+            .unwrap();
+        assert_eq!(standalone.variant, ComposeVariant::Standalone);
+        assert_eq!(
+            standalone.path.as_deref(),
+            Some("/opt/compose/docker-compose")
+        );
+
+        let legacy = runner(
+            None,
+            Some("docker-compose version 1.29.2, build 5becea4c\n"),
+        );
+        assert_eq!(
+            check(&legacy, &[]).await.unwrap().variant,
+            ComposeVariant::Legacy
+        );
+        let refused = ensure_usable(&legacy, &[]).await.unwrap_err();
+        assert_eq!(refused.message().code, "compose-legacy-only");
+
+        let missing = ensure_usable(&runner(None, None), &[]).await.unwrap_err();
+        assert!(missing.legacy.is_none());
+        assert!(missing
+            .to_string()
+            .contains("'compose' is not a docker command"));
+    }
+
+    #[tokio::test]
+    async fn test_profile_must_be_declared() {
+        let root = Path::new("/work");
+        let mut setup =
+            ComposeSetup::from_settings(&AppSettings::default(), root, Path::new("/data/arbor"));
+        let runner = MockRunner::with_stdout("analytics\nworker\n");
+        ensure_profile(&runner, root, &setup).await.unwrap();
+        assert!(
+            runner.calls().is_empty(),
+            "nothing to check without a profile"
+        );
+
+        setup.profile = Some("worker".to_string());
+        ensure_profile(&runner, root, &setup).await.unwrap();
+        let call = &runner.calls()[0];
+        assert!(call
+            .args
+            .ends_with(&["config".to_string(), "--profiles".to_string()]));
+        let flag = call.args.iter().position(|arg| arg == "--profile").unwrap();
+        assert_eq!(call.args[flag + 1], "worker");
+
+        setup.profile = Some("mailhog".to_string());
+        let error = ensure_profile(&runner, root, &setup).await.unwrap_err();
+        assert!(error.ends_with("declare analytics, worker"), "{}", error);
+    }
+}
@@ -0,0 +1,334 @@
+// Config file watcher
+// Watches the compose files, the dev override and arbor.toml. A burst of
+// saves is collapsed into one check that re-validates the config, works out
+// which running services drifted and broadcasts `config-changed`, optionally
+// recreating the affected services.
+
+use crate::events::{self, EventSink};
+use crate::process::CommandRunner;
+use crate::project_config::{ConfigSources, ProjectConfig};
+use crate::services::{self, ComposeSetup};
+use crate::{error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+pub const CONFIG_CHANGED_EVENT: &str = "config-changed";
+
+// Editors often write a file several times per save (truncate, write, rename)
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Payload of the `config-changed` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigChange {
+    pub changed_files: Vec<String>,
+    /// Validation error, in which case nothing else was checked
+    pub error: Option<String>,
+    /// Running services whose config no longer matches the files
+    pub affected_services: Vec<String>,
+    pub restart_required: bool,
+    pub auto_restarted: bool,
+}
+
+/// Owns the file watcher and forwards changed paths to a channel
+pub struct ConfigWatcher {
+    tx: mpsc::Sender<PathBuf>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl ConfigWatcher {
+    pub fn new(tx: mpsc::Sender<PathBuf>) -> Self {
+        Self {
+            tx,
+            watcher: Mutex::new(None),
+        }
+    }
+
+    /// (Re)point the watcher at `files`, replacing the previous set
+    /// The parent directories are watched rather than the files themselves,
+    /// so files replaced by atomic rename (or created later) are still seen.
+    pub fn watch(&self, files: Vec<PathBuf>) -> Result<(), String> {
+        let targets: HashSet<PathBuf> = files.iter().cloned().collect();
+        let tx = self.tx.clone();
+
+        let mut watcher =
+            notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+                let Ok(event) = result else { return };
+                if event.kind.is_access() {
+                    return;
+                }
+                for path in event
+                    .paths
+                    .into_iter()
+                    .filter(|path| targets.contains(path))
+                {
+                    // Debounced downstream, so a full channel just loses duplicates
+                    let _ = tx.try_send(path);
+                }
+            })
+            .map_err(|e| format!("Failed to create config watcher: {}", e))?;
+
+        let dirs: BTreeSet<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+        for dir in dirs.into_iter().filter(|dir| dir.is_dir()) {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| format!("Failed to watch {:?}: {}", dir, e))?;
+        }
+
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(())
+    }
+}
+
+/// Wait for the next change and collect the files touched until things go quiet
+/// Returns `None` once the watcher side of the channel is gone.
+pub async fn next_change(rx: &mut mpsc::Receiver<PathBuf>) -> Option<Vec<PathBuf>> {
+    let mut changed = BTreeSet::new();
+    changed.insert(rx.recv().await?);
+
+    while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+        changed.insert(path);
+    }
+
+    Some(changed.into_iter().collect())
+}
+
+/// Re-check the config after `changed_files` were edited and broadcast the result
+pub async fn handle_change(
+    runner: &dyn CommandRunner,
+    sink: &dyn EventSink,
+    project_root: &Path,
+    config_sources: &ConfigSources,
+    setup: &ComposeSetup,
+    auto_restart: bool,
+    changed_files: Vec<PathBuf>,
+) -> ConfigChange {
+    let mut change = ConfigChange {
+        changed_files: changed_files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        error: None,
+        affected_services: Vec::new(),
+        restart_required: false,
+        auto_restarted: false,
+    };
+
+    let validation = match ProjectConfig::load(project_root, config_sources) {
+        Ok(_) => services::validate_config(runner, project_root, setup).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = validation {
+        warn!("⚠️  {}", e);
+        change.error = Some(e);
+        events::emit(sink, CONFIG_CHANGED_EVENT, &change);
+        return change;
+    }
+
+    match services::detect_drift(runner, project_root, setup).await {
+        Ok(drifted) => change.affected_services = drifted,
+        Err(e) => warn!("⚠️  Drift detection failed: {}", e),
+    }
+    change.restart_required = !change.affected_services.is_empty();
+
+    if auto_restart && change.restart_required {
+        info!(
+            "🔄 Config changed, restarting {:?}",
+            change.affected_services
+        );
+        match services::restart_services(runner, project_root, setup, &change.affected_services)
+            .await
+        {
+            Ok(()) => {
+                change.auto_restarted = true;
+                change.restart_required = false;
+            }
+            Err(e) => error!("❌ {}", e),
+        }
+    }
+
+    events::emit(sink, CONFIG_CHANGED_EVENT, &change);
+    change
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::settings::AppSettings;
+    use crate::test_support::temp_dir;
+
+    fn setup() -> ComposeSetup {
+        ComposeSetup::from_settings(
+            &AppSettings::default(),
+            Path::new("/work/arbor"),
+            Path::new("/data/arbor"),
+        )
+    }
+
+    // `config --quiet` result, then api drifted between hashes and containers
+    fn runner(config_ok: bool) -> MockRunner {
+        MockRunner::new(move |spec| {
+            let ok = |stdout: &str| {
+                Ok(CommandOutput {
+                    code: Some(0),
+                    stdout: stdout.as_bytes().to_vec(),
+                    stderr: Vec::new(),
+                })
+            };
+            if spec.args.contains(&"--quiet".to_string()) {
+                return if config_ok {
+                    ok("")
+                } else {
+                    Ok(CommandOutput {
+                        code: Some(15),
+                        stdout: Vec::new(),
+                        stderr: b"services.api.ports must be a list".to_vec(),
+                    })
+                };
+            }
+            if spec.args.contains(&"--hash".to_string()) {
+                return ok("api new\nredis same\n");
+            }
+            if spec.args.first().map(String::as_str) == Some("ps") {
+                return ok("api\told\nredis\tsame\n");
+            }
+            ok("")
+        })
+    }
+
+    fn changed() -> Vec<PathBuf> {
+        vec![PathBuf::from("/work/arbor/apps/api/docker-compose.yml")]
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rapid_saves_are_debounced() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let compose = PathBuf::from("/work/arbor/apps/api/docker-compose.yml");
+        let config = PathBuf::from("/work/arbor/arbor.toml");
+
+        for path in [&compose, &compose, &config, &compose] {
+            tx.send(path.clone()).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let batch = next_change(&mut rx).await.unwrap();
+        assert_eq!(batch, vec![compose.clone(), config]);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tx.send(compose.clone()).await.unwrap();
+        assert_eq!(next_change(&mut rx).await.unwrap(), vec![compose]);
+
+        drop(tx);
+        assert!(next_change(&mut rx).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_change_reports_affected_services() {
+        let runner = runner(true);
+        let sink = RecordingSink::default();
+
+        let change = handle_change(
+            &runner,
+            &sink,
+            Path::new("/work/arbor"),
+            &ConfigSources::default(),
+            &setup(),
+            false,
+            changed(),
+        )
+        .await;
+
+        assert_eq!(change.affected_services, vec!["api".to_string()]);
+        assert!(change.restart_required);
+        assert!(!change.auto_restarted);
+        assert_eq!(sink.events_named(CONFIG_CHANGED_EVENT).len(), 1);
+        assert!(!runner
+            .calls()
+            .iter()
+            .any(|call| call.args.contains(&"up".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_auto_restart_recreates_only_affected_services() {
+        let runner = runner(true);
+        let sink = RecordingSink::default();
+
+        let change = handle_change(
+            &runner,
+            &sink,
+            Path::new("/work/arbor"),
+            &ConfigSources::default(),
+            &setup(),
+            true,
+            changed(),
+        )
+        .await;
+
+        assert!(change.auto_restarted);
+        assert!(!change.restart_required);
+        let up = runner
+            .calls()
+            .into_iter()
+            .find(|call| call.args.contains(&"up".to_string()))
+            .expect("affected services should be recreated");
+        assert!(up
+            .args
+            .ends_with(&["--no-deps".to_string(), "api".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config_is_reported_without_restart() {
+        let runner = runner(false);
+        let sink = RecordingSink::default();
+
+        let change = handle_change(
+            &runner,
+            &sink,
+            Path::new("/work/arbor"),
+            &ConfigSources::default(),
+            &setup(),
+            true,
+            changed(),
+        )
+        .await;
+
+        assert!(change.error.unwrap().contains("ports must be a list"));
+        assert!(!change.auto_restarted);
+        assert_eq!(
+            runner.calls().len(),
+            1,
+            "Nothing runs after a failed validation"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_atomic_replace_is_detected() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir).unwrap();
+        let file = dir.join("docker-compose.yml");
+        std::fs::write(&file, "services: {}").unwrap();
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let watcher = ConfigWatcher::new(tx);
+        watcher.watch(vec![file.clone()]).unwrap();
+
+        // What editors do on save: write a sibling, then rename it over the file
+        let temp = dir.join(".docker-compose.yml.swp");
+        std::fs::write(&temp, "services: { api: {} }").unwrap();
+        std::fs::rename(&temp, &file).unwrap();
+
+        let batch = tokio::time::timeout(Duration::from_secs(5), next_change(&mut rx))
+            .await
+            .expect("replacing the file should be noticed")
+            .unwrap();
+        assert_eq!(batch, vec![file]);
+    }
+}
@@ -0,0 +1,70 @@
+// Confirmation tokens
+// Destructive commands run in two calls: a dry run that lists what would
+// happen and hands out a token, then the real call carrying it. A token is
+// good once, for a few minutes, and only for the action it was issued for,
+// so a stray or replayed call can't destroy anything on its own.
+
+use base64::{engine::general_purpose, Engine as _};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const TOKEN_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Default)]
+pub struct Confirmations {
+    // Token → the action it confirms and when it was issued
+    pending: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl Confirmations {
+    /// A fresh token confirming `action`
+    pub fn issue(&self, action: &str) -> String {
+        let bytes: [u8; 16] = rand::rng().random();
+        let token = general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, (_, issued)| issued.elapsed() < TOKEN_TTL);
+        pending.insert(token.clone(), (action.to_string(), Instant::now()));
+        token
+    }
+
+    /// Use up `token`, which must have been issued for `action` and not expired
+    pub fn redeem(&self, action: &str, token: &str) -> Result<(), String> {
+        let issued = self.pending.lock().unwrap().remove(token);
+        match issued {
+            Some((issued_for, at)) if issued_for == action && at.elapsed() < TOKEN_TTL => Ok(()),
+            Some((issued_for, _)) if issued_for != action => Err(format!(
+                "Confirmation token was issued for {}, not {}",
+                issued_for, action
+            )),
+            _ => Err(format!(
+                "Confirmation token for {} is unknown or expired; do a dry run for a new one",
+                action
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_single_use_and_bound_to_their_action() {
+        let confirmations = Confirmations::default();
+
+        let token = confirmations.issue("uninstall");
+        assert!(confirmations.redeem("uninstall", &token).is_ok());
+        assert!(confirmations.redeem("uninstall", &token).is_err());
+
+        let token = confirmations.issue("uninstall");
+        assert_ne!(confirmations.issue("uninstall"), token);
+        assert!(confirmations
+            .redeem("uninstall:with-secrets", &token)
+            .is_err());
+        // A misdirected token is spent, not left for a retry
+        assert!(confirmations.redeem("uninstall", &token).is_err());
+        assert!(confirmations.redeem("uninstall", "made-up").is_err());
+    }
+}
@@ -0,0 +1,726 @@
+// Crash monitor
+// Watches container events for unexpected exits, works out why the container
+// died and broadcasts `service-crashed`. Docker's restart policy brings a
+// crashed service back; what happens next is up to the service's
+// `restart_policy` in the config. By default, when a container keeps crashing
+// (or keeps running out of memory, which restarting never fixes) its restart
+// policy is turned off.
+
+use crate::docker::{self, ContainerEvent};
+use crate::events::{self, EventSink};
+use crate::lifecycle::{Lifecycle, StopInitiator, StopReason};
+use crate::notify::{NotificationKind, Notifier};
+use crate::postmortem::PostMortemStore;
+use crate::process::CommandRunner;
+use crate::project_config::{ConfigSources, ProjectConfig};
+use crate::stats::StatsSampler;
+use crate::status::StatusCache;
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, State};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+pub const SERVICE_CRASHED_EVENT: &str = "service-crashed";
+
+/// Crashes considered together when looking for a crash loop
+const CRASH_WINDOW: Duration = Duration::from_secs(300);
+const CRASH_LOOP_THRESHOLD: usize = 5;
+/// OOM kills repeat identically after a restart, so give up much sooner
+const OOM_LOOP_THRESHOLD: usize = 2;
+
+/// SIGKILL; what the kernel OOM killer sends
+const EXIT_CODE_SIGKILL: i32 = 137;
+
+/// What the crash monitor does when a service's container dies
+/// Written like docker's: `never`, `always` or `on-failure:<max retries>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RestartPolicy {
+    /// Stop the service at its first crash
+    Never,
+    /// Restart until it crashes more than `max_retries` times within the
+    /// crash loop window, or keeps running out of memory
+    OnFailure { max_retries: usize },
+    /// Restart however often it crashes
+    Always,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::OnFailure {
+            max_retries: CRASH_LOOP_THRESHOLD - 1,
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text.split_once(':') {
+            None if text == "never" => Ok(RestartPolicy::Never),
+            None if text == "always" => Ok(RestartPolicy::Always),
+            None if text == "on-failure" => Ok(RestartPolicy::default()),
+            Some(("on-failure", retries)) => retries
+                .parse()
+                .map(|max_retries| RestartPolicy::OnFailure { max_retries })
+                .map_err(|_| format!("Invalid restart policy {:?}: bad retry count", text)),
+            _ => Err(format!(
+                "Invalid restart policy {:?}: expected never, always or on-failure:<max retries>",
+                text
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for RestartPolicy {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartPolicy::Never => write!(f, "never"),
+            RestartPolicy::OnFailure { max_retries } => write!(f, "on-failure:{}", max_retries),
+            RestartPolicy::Always => write!(f, "always"),
+        }
+    }
+}
+
+impl From<RestartPolicy> for String {
+    fn from(policy: RestartPolicy) -> Self {
+        policy.to_string()
+    }
+}
+
+/// Restart policies by compose service, shared with the running crash monitor
+/// so a change applies to the next crash
+#[derive(Clone, Default)]
+pub struct RestartPolicies {
+    policies: Arc<Mutex<BTreeMap<String, RestartPolicy>>>,
+    // Safe mode: crashes are still reported, but nothing is acted on
+    suspended: Arc<AtomicBool>,
+    // Containers whose docker restart policy was turned off at a crash loop
+    disabled: Arc<Mutex<HashSet<String>>>,
+}
+
+impl RestartPolicies {
+    pub fn set_suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::SeqCst);
+    }
+
+    pub fn suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst)
+    }
+
+    /// Take the policies from a freshly loaded config
+    pub fn replace(&self, config: &ProjectConfig) {
+        *self.policies.lock().unwrap() = config
+            .services
+            .iter()
+            .filter_map(|(service, service_config)| {
+                service_config
+                    .restart_policy
+                    .map(|policy| (service.clone(), policy))
+            })
+            .collect();
+    }
+
+    pub fn for_service(&self, service: &str) -> RestartPolicy {
+        self.policies
+            .lock()
+            .unwrap()
+            .get(service)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn for_container(&self, container: &str) -> RestartPolicy {
+        self.for_service(docker::service_of(container))
+    }
+
+    /// Note that `container` crash looped and mustn't be brought back
+    pub fn disable(&self, container: &str) {
+        self.disabled.lock().unwrap().insert(container.to_string());
+    }
+
+    /// Whether `container` crash looped since the stack was last launched
+    pub fn is_disabled(&self, container: &str) -> bool {
+        self.disabled.lock().unwrap().contains(container)
+    }
+
+    /// Forget crash loops, for a freshly launched stack
+    pub fn clear_disabled(&self) {
+        self.disabled.lock().unwrap().clear();
+    }
+}
+
+/// Why a container died
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind")]
+pub enum CrashCause {
+    Exited {
+        exit_code: i32,
+    },
+    OutOfMemory {
+        exit_code: i32,
+        memory_limit_bytes: Option<u64>,
+        /// Usage in the last stats sample before the kill
+        last_usage_bytes: Option<u64>,
+        suggestion: String,
+    },
+}
+
+/// Payload of the `service-crashed` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrashReport {
+    pub container: String,
+    pub cause: CrashCause,
+    /// Crashes of this container within the crash loop window, this one included
+    pub recent_crashes: usize,
+    pub restart_policy: RestartPolicy,
+    /// Docker's restart policy was turned off, to stop a crash loop or
+    /// because the service's policy is `never`
+    pub auto_restart_disabled: bool,
+}
+
+struct Crash {
+    at: Instant,
+    oom: bool,
+}
+
+pub struct CrashMonitor {
+    runner: Arc<dyn CommandRunner>,
+    sink: Arc<dyn EventSink>,
+    stats: StatsSampler,
+    postmortems: Option<PostMortemStore>,
+    policies: RestartPolicies,
+    lifecycle: Option<Lifecycle>,
+    notifier: Option<Notifier>,
+    // Containers we saw being stopped or killed on purpose
+    stopping: HashSet<String>,
+    history: HashMap<String, VecDeque<Crash>>,
+}
+
+impl CrashMonitor {
+    pub fn new(
+        runner: Arc<dyn CommandRunner>,
+        sink: Arc<dyn EventSink>,
+        stats: StatsSampler,
+    ) -> Self {
+        Self {
+            runner,
+            sink,
+            stats,
+            postmortems: None,
+            policies: RestartPolicies::default(),
+            lifecycle: None,
+            notifier: None,
+            stopping: HashSet::new(),
+            history: HashMap::new(),
+        }
+    }
+
+    /// Capture a post-mortem bundle for every crash
+    pub fn with_postmortems(mut self, store: PostMortemStore) -> Self {
+        self.postmortems = Some(store);
+        self
+    }
+
+    /// Consult `policies` instead of the default policy for every service
+    pub fn with_restart_policies(mut self, policies: RestartPolicies) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    /// Record every crash as a stop reason in `lifecycle`
+    pub fn with_lifecycle(mut self, lifecycle: Lifecycle) -> Self {
+        self.lifecycle = Some(lifecycle);
+        self
+    }
+
+    /// Show an OS notification for every crash, coalesced per container
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    pub async fn watch_events(mut self, mut rx: mpsc::Receiver<ContainerEvent>) {
+        while let Some(event) = rx.recv().await {
+            self.handle_event(&event).await;
+        }
+    }
+
+    /// Track stop requests and report dies that weren't one
+    pub async fn handle_event(&mut self, event: &ContainerEvent) -> Option<CrashReport> {
+        match event.action.as_str() {
+            // `docker stop`/`compose down` signal the container before it dies
+            "kill" | "stop" => {
+                self.stopping.insert(event.container.clone());
+                None
+            }
+            "start" => {
+                self.stopping.remove(&event.container);
+                None
+            }
+            "die" if self.stopping.remove(&event.container) => None,
+            "die" => self.report_crash(&event.container).await,
+            _ => None,
+        }
+    }
+
+    async fn report_crash(&mut self, container: &str) -> Option<CrashReport> {
+        let exit = match docker::inspect_exit(&*self.runner, container).await {
+            Ok(exit) => exit,
+            Err(e) => {
+                warn!("⚠️  {}", e);
+                return None;
+            }
+        };
+
+        // OOMKilled isn't reliably set under every cgroup setup, but a SIGKILL
+        // nobody asked docker for is the OOM killer in practice
+        let oom = exit.oom_killed || exit.exit_code == EXIT_CODE_SIGKILL;
+        let cause = if oom {
+            let sample = self.stats.latest(container);
+            CrashCause::OutOfMemory {
+                exit_code: exit.exit_code,
+                memory_limit_bytes: exit
+                    .memory_limit_bytes
+                    .or_else(|| sample.as_ref().map(|s| s.memory_limit_bytes)),
+                last_usage_bytes: sample.map(|s| s.memory_usage_bytes),
+                suggestion: format!(
+                    "{} ran out of memory. Raise its limit (mem_limit in the compose \
+                     override) or the memory available to Docker (Docker Desktop → \
+                     Settings → Resources).",
+                    container
+                ),
+            }
+        } else if exit.exit_code != 0 {
+            CrashCause::Exited {
+                exit_code: exit.exit_code,
+            }
+        } else {
+            return None;
+        };
+
+        let now = Instant::now();
+        let history = self.history.entry(container.to_string()).or_default();
+        history.push_back(Crash { at: now, oom });
+        while history
+            .front()
+            .is_some_and(|crash| now.duration_since(crash.at) > CRASH_WINDOW)
+        {
+            history.pop_front();
+        }
+        let recent_crashes = history.len();
+        let recent_ooms = history.iter().filter(|crash| crash.oom).count();
+
+        let restart_policy = self.policies.for_container(container);
+        let give_up = !self.policies.suspended()
+            && match restart_policy {
+                RestartPolicy::Never => true,
+                RestartPolicy::OnFailure { max_retries } => {
+                    recent_crashes > max_retries || recent_ooms >= OOM_LOOP_THRESHOLD
+                }
+                RestartPolicy::Always => false,
+            };
+        let auto_restart_disabled = give_up
+            && match docker::disable_restart(&*self.runner, container).await {
+                Ok(()) => {
+                    self.policies.disable(container);
+                    true
+                }
+                Err(e) => {
+                    warn!("⚠️  {}", e);
+                    false
+                }
+            };
+        // The compose restart policy has most likely brought it back already
+        if auto_restart_disabled && restart_policy == RestartPolicy::Never {
+            if let Err(e) = docker::stop_container(&*self.runner, container).await {
+                warn!("⚠️  {}", e);
+            }
+        }
+
+        let report = CrashReport {
+            container: container.to_string(),
+            cause,
+            recent_crashes,
+            restart_policy,
+            auto_restart_disabled,
+        };
+        match &report.cause {
+            CrashCause::OutOfMemory { .. } => {
+                warn!("💥 {} was killed: out of memory", container)
+            }
+            CrashCause::Exited { exit_code } => {
+                warn!(
+                    "💥 {} exited unexpectedly with code {}",
+                    container, exit_code
+                )
+            }
+        }
+        if auto_restart_disabled && restart_policy == RestartPolicy::Never {
+            warn!("🛑 {} crashed and its restart policy is never, stopped", container);
+        } else if auto_restart_disabled {
+            warn!("🛑 {} is crash looping, auto-restart disabled", container);
+        }
+
+        events::emit(&*self.sink, SERVICE_CRASHED_EVENT, &report);
+        if let Some(notifier) = &self.notifier {
+            let body = match &report.cause {
+                CrashCause::OutOfMemory { .. } => "Killed: out of memory".to_string(),
+                CrashCause::Exited { exit_code } => format!("Exited with code {}", exit_code),
+            };
+            notifier.notify_coalesced(
+                container,
+                format!("{} crashed", docker::service_of(container)),
+                body,
+                NotificationKind::ServiceCrashed,
+            );
+        }
+        if let Some(lifecycle) = &self.lifecycle {
+            let error = match &report.cause {
+                CrashCause::OutOfMemory { .. } => "out of memory".to_string(),
+                CrashCause::Exited { exit_code } => format!("exited with code {}", exit_code),
+            };
+            let reason = StopReason::new(StopInitiator::Crash)
+                .with_services(&[docker::service_of(container).to_string()])
+                .with_error(error);
+            // Docker's restart policy brings it back unless we just turned that off
+            lifecycle.record(match report.auto_restart_disabled {
+                true => reason,
+                false => reason.restarted(),
+            });
+        }
+
+        if let Some(store) = &self.postmortems {
+            let stats = self.stats.recent(container);
+            match store.capture(&*self.runner, &report, &exit, &stats).await {
+                Ok(bundle) => info!("📁 Post-mortem saved to {:?}", bundle),
+                Err(e) => warn!("⚠️  Failed to capture post-mortem: {}", e),
+            }
+        }
+        Some(report)
+    }
+}
+
+/// Change a service's restart policy, persisted to the user config
+/// Applies to the service's next crash. ARBOR_CONFIG and `--config` still
+/// win, so the policy in effect afterwards is returned.
+#[tauri::command]
+pub async fn set_restart_policy(
+    app_handle: AppHandle,
+    sources: State<'_, ConfigSources>,
+    policies: State<'_, RestartPolicies>,
+    status_cache: State<'_, StatusCache>,
+    service: String,
+    policy: RestartPolicy,
+) -> Result<RestartPolicy, String> {
+    if service.is_empty() || service.contains('.') {
+        return Err(format!("Invalid service name: {:?}", service));
+    }
+    sources.set_user_value(
+        &["services", &service, "restart_policy"],
+        toml::Value::String(policy.to_string()),
+    )?;
+
+    let project_root = crate::find_project_root(&app_handle)?;
+    policies.replace(&ProjectConfig::load(&project_root, &sources)?);
+    let effective = policies.for_service(&service);
+    info!("🔁 Restart policy of {} is now {}", service, effective);
+
+    status_cache.refresh_and_broadcast().await;
+    Ok(effective)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::test_support::temp_dir;
+
+    fn event(container: &str, action: &str) -> ContainerEvent {
+        ContainerEvent {
+            container: container.to_string(),
+            action: action.to_string(),
+        }
+    }
+
+    /// Runner whose `docker inspect` reports the given state and memory limit
+    fn runner(state: &'static str, memory: &'static str) -> Arc<MockRunner> {
+        Arc::new(MockRunner::new(move |spec| {
+            let stdout = match spec.args.first().map(String::as_str) {
+                Some("inspect") => format!("{}\t{}\n", state, memory),
+                Some("stats") => {
+                    r#"{"Name":"arbor-postgres","CPUPerc":"3.00%","MemUsage":"511.9MiB / 512MiB"}"#
+                        .to_string()
+                }
+                _ => String::new(),
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.into_bytes(),
+                stderr: Vec::new(),
+            })
+        }))
+    }
+
+    async fn monitor(runner: Arc<MockRunner>) -> (CrashMonitor, Arc<RecordingSink>) {
+        let sink = Arc::new(RecordingSink::default());
+        let stats = StatsSampler::new(runner.clone());
+        stats.sample().await.unwrap();
+        (CrashMonitor::new(runner, sink.clone(), stats), sink)
+    }
+
+    fn disabled_restarts(runner: &MockRunner) -> usize {
+        runner
+            .calls()
+            .iter()
+            .filter(|call| call.args.first().map(String::as_str) == Some("update"))
+            .count()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_oom_kill_is_reported_with_memory_details() {
+        let runner = runner(r#"{"ExitCode":137,"OOMKilled":true}"#, "536870912");
+        let (mut monitor, sink) = monitor(runner.clone()).await;
+
+        let report = monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+
+        let CrashCause::OutOfMemory {
+            memory_limit_bytes,
+            last_usage_bytes,
+            suggestion,
+            ..
+        } = &report.cause
+        else {
+            panic!("expected an OOM cause, got {:?}", report.cause);
+        };
+        assert_eq!(*memory_limit_bytes, Some(536_870_912));
+        assert_eq!(*last_usage_bytes, Some((511.9 * 1024.0 * 1024.0) as u64));
+        assert!(suggestion.contains("mem_limit"));
+
+        let payload = &sink.events_named(SERVICE_CRASHED_EVENT)[0];
+        assert_eq!(payload["cause"]["kind"], "OutOfMemory");
+        assert!(!report.auto_restart_disabled);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sigkill_without_stop_request_counts_as_oom() {
+        let runner = runner(r#"{"ExitCode":137,"OOMKilled":false}"#, "0");
+        let (mut monitor, _) = monitor(runner).await;
+
+        let report = monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+        let CrashCause::OutOfMemory {
+            memory_limit_bytes, ..
+        } = report.cause
+        else {
+            panic!("expected an OOM cause");
+        };
+        assert_eq!(
+            memory_limit_bytes,
+            Some(512 * 1024 * 1024),
+            "Without a configured limit the sampled limit is reported"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crash_captures_postmortem() {
+        let runner = runner(r#"{"ExitCode":1,"OOMKilled":false}"#, "0");
+        let (monitor, _) = monitor(runner).await;
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = PostMortemStore::new(dir.to_path_buf(), Arc::new(|| serde_json::Value::Null));
+        let mut monitor = monitor.with_postmortems(store.clone());
+
+        monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+
+        let reports = store.list().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].container, "arbor-postgres");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crash_is_recorded_as_a_stop_reason() {
+        let runner = runner(r#"{"ExitCode":3,"OOMKilled":false}"#, "0");
+        let (monitor, sink) = monitor(runner).await;
+        let lifecycle = Lifecycle::new(sink.clone());
+        let mut monitor = monitor.with_lifecycle(lifecycle.clone());
+
+        monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+
+        let reason = lifecycle.last_stop().unwrap();
+        assert_eq!(reason.initiator, StopInitiator::Crash);
+        assert_eq!(reason.services, ["postgres"]);
+        assert_eq!(reason.error.as_deref(), Some("exited with code 3"));
+        assert!(reason.restarted);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requested_stop_is_not_a_crash() {
+        let runner = runner(r#"{"ExitCode":137,"OOMKilled":false}"#, "0");
+        let (mut monitor, sink) = monitor(runner).await;
+
+        monitor.handle_event(&event("arbor-redis", "kill")).await;
+        assert!(monitor
+            .handle_event(&event("arbor-redis", "die"))
+            .await
+            .is_none());
+        assert!(sink.events_named(SERVICE_CRASHED_EVENT).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_repeated_oom_disables_restart_immediately() {
+        let runner = runner(r#"{"ExitCode":137,"OOMKilled":true}"#, "536870912");
+        let (mut monitor, _) = monitor(runner.clone()).await;
+
+        let first = monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+        assert!(!first.auto_restart_disabled);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let second = monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+        assert!(second.auto_restart_disabled);
+        assert_eq!(disabled_restarts(&runner), 1);
+    }
+
+    fn policies(service: &str, policy: &str) -> RestartPolicies {
+        let mut config = ProjectConfig::default();
+        config.services.insert(
+            service.to_string(),
+            crate::project_config::ServiceConfig {
+                restart_policy: Some(policy.parse().unwrap()),
+                ..Default::default()
+            },
+        );
+        let policies = RestartPolicies::default();
+        policies.replace(&config);
+        policies
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_never_policy_stops_at_first_crash() {
+        let runner = runner(r#"{"ExitCode":1,"OOMKilled":false}"#, "0");
+        let (monitor, sink) = monitor(runner.clone()).await;
+        let policies = policies("postgres", "never");
+        let mut monitor = monitor.with_restart_policies(policies.clone());
+
+        let report = monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+        assert_eq!(report.restart_policy, RestartPolicy::Never);
+        assert!(report.auto_restart_disabled);
+        assert!(runner
+            .calls()
+            .iter()
+            .any(|call| call.args == ["stop", "arbor-postgres"]));
+        assert_eq!(
+            sink.events_named(SERVICE_CRASHED_EVENT)[0]["restart_policy"],
+            "never"
+        );
+
+        // A changed policy applies to the next crash without a new monitor
+        policies.replace(&ProjectConfig::default());
+        let report = monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+        assert!(!report.auto_restart_disabled);
+
+        // Safe mode suspends every policy
+        let suspended = self::policies("postgres", "never");
+        suspended.set_suspended(true);
+        monitor = monitor.with_restart_policies(suspended);
+        let report = monitor
+            .handle_event(&event("arbor-postgres", "die"))
+            .await
+            .unwrap();
+        assert!(!report.auto_restart_disabled);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_always_and_on_failure_policies() {
+        let runner = runner(r#"{"ExitCode":1,"OOMKilled":false}"#, "0");
+        let (monitor, _) = monitor(runner.clone()).await;
+        let mut monitor = monitor.with_restart_policies(policies("worker", "always"));
+        for _ in 0..CRASH_LOOP_THRESHOLD * 2 {
+            let report = monitor
+                .handle_event(&event("arbor-worker", "die"))
+                .await
+                .unwrap();
+            assert!(!report.auto_restart_disabled);
+        }
+        assert_eq!(disabled_restarts(&runner), 0);
+
+        let mut monitor = monitor.with_restart_policies(policies("minio", "on-failure:1"));
+        let first = monitor.handle_event(&event("arbor-minio", "die")).await.unwrap();
+        assert!(!first.auto_restart_disabled);
+        let second = monitor.handle_event(&event("arbor-minio", "die")).await.unwrap();
+        assert!(second.auto_restart_disabled);
+
+        assert_eq!("on-failure".parse(), Ok(RestartPolicy::default()));
+        assert!("on-failure:many".parse::<RestartPolicy>().is_err());
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ordinary_crashes_loop_only_after_threshold() {
+        let runner = runner(r#"{"ExitCode":1,"OOMKilled":false}"#, "0");
+        let (mut monitor, _) = monitor(runner.clone()).await;
+
+        for _ in 1..CRASH_LOOP_THRESHOLD {
+            let report = monitor
+                .handle_event(&event("arbor-minio", "die"))
+                .await
+                .unwrap();
+            assert_eq!(report.cause, CrashCause::Exited { exit_code: 1 });
+            assert!(!report.auto_restart_disabled);
+            tokio::time::advance(Duration::from_secs(10)).await;
+        }
+        let report = monitor
+            .handle_event(&event("arbor-minio", "die"))
+            .await
+            .unwrap();
+        assert!(report.auto_restart_disabled);
+
+        // Crashes outside the window are forgotten
+        tokio::time::advance(CRASH_WINDOW * 2).await;
+        let report = monitor
+            .handle_event(&event("arbor-minio", "die"))
+            .await
+            .unwrap();
+        assert_eq!(report.recent_crashes, 1);
+    }
+}
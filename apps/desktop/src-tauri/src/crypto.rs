@@ -0,0 +1,181 @@
+// Encryption with the master key
+// The frontend hands its data to `encrypt_data` / `decrypt_data` rather than
+// holding the master key itself. Data is sealed with AES-256-GCM as the
+// random 12-byte nonce followed by the ciphertext and tag, base64-encoded
+// over IPC. Key bytes, and plaintext on its way through, are wiped as soon
+// as a call is done with them.
+// Parts of the app that keep their own encrypted data (the notes database,
+// attachments, sync) get a subkey from `derive_key` instead: HKDF-SHA256
+// over the master key, unsalted, with the purpose as info. Changing that
+// derivation would orphan their data, so tests pin it.
+
+use crate::keyring::{self, Keychain, KEY_LEN};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose, Engine as _};
+use hkdf::Hkdf;
+use rand::Rng;
+use sha2::Sha256;
+use tauri::State;
+use zeroize::Zeroizing;
+
+const NONCE_LEN: usize = 12;
+/// The GCM authentication tag at the end of the ciphertext
+const TAG_LEN: usize = 16;
+
+/// `plaintext` sealed under `key`, nonce first
+pub fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce: [u8; NONCE_LEN] = rand::rng().random();
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|e| format!("Failed to encrypt: {}", e))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+/// The plaintext of `sealed`, as `encrypt` produced it
+pub fn decrypt(key: &[u8; KEY_LEN], sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(format!(
+            "Failed to decrypt: {} bytes is too short to be encrypted data",
+            sealed.len()
+        ));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| {
+            "Failed to decrypt: the data was modified or encrypted with a different key".to_string()
+        })
+}
+
+/// The subkey for `purpose`, the same every time for the same master key
+pub fn derive_subkey(
+    master: &[u8; KEY_LEN],
+    purpose: &str,
+) -> Result<Zeroizing<[u8; KEY_LEN]>, String> {
+    if purpose.trim().is_empty() {
+        return Err("A key purpose is required".to_string());
+    }
+    let mut subkey = Zeroizing::new([0u8; KEY_LEN]);
+    Hkdf::<Sha256>::new(None, master)
+        .expand(purpose.as_bytes(), subkey.as_mut())
+        .map_err(|e| format!("Failed to derive a key: {}", e))?;
+    Ok(subkey)
+}
+
+fn decode(field: &str, value: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    general_purpose::STANDARD
+        .decode(value)
+        .map(Zeroizing::new)
+        .map_err(|e| format!("{} is not valid base64: {}", field, e))
+}
+
+/// Encrypt base64 `plaintext_base64` with the master key
+#[tauri::command]
+pub async fn encrypt_data(
+    keychain: State<'_, Keychain>,
+    plaintext_base64: String,
+) -> Result<String, String> {
+    let plaintext_base64 = Zeroizing::new(plaintext_base64);
+    let plaintext = decode("plaintext_base64", &plaintext_base64)?;
+    let key = keyring::master_key(keychain.store()).await?;
+    let sealed = encrypt(&key, &plaintext)?;
+    Ok(general_purpose::STANDARD.encode(sealed))
+}
+
+/// Decrypt what `encrypt_data` returned, as base64
+#[tauri::command]
+pub async fn decrypt_data(
+    keychain: State<'_, Keychain>,
+    ciphertext_base64: String,
+) -> Result<String, String> {
+    let sealed = decode("ciphertext_base64", &ciphertext_base64)?;
+    let key = keyring::master_key(keychain.store()).await?;
+    let plaintext = decrypt(&key, &sealed)?;
+    Ok(general_purpose::STANDARD.encode(plaintext.as_slice()))
+}
+
+/// A base64 32-byte subkey of the master key for `purpose`, e.g. "notes"
+#[tauri::command]
+pub async fn derive_key(keychain: State<'_, Keychain>, purpose: String) -> Result<String, String> {
+    let master = keyring::master_key(keychain.store()).await?;
+    let subkey = derive_subkey(&master, &purpose)?;
+    Ok(general_purpose::STANDARD.encode(subkey.as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_with_a_fresh_nonce_each_time() {
+        let key = [7u8; KEY_LEN];
+        let first = encrypt(&key, b"my notes").unwrap();
+        let second = encrypt(&key, b"my notes").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.len(), NONCE_LEN + b"my notes".len() + TAG_LEN);
+
+        assert_eq!(decrypt(&key, &first).unwrap().as_slice(), b"my notes");
+        assert_eq!(
+            decrypt(&key, &encrypt(&key, b"").unwrap()).unwrap().len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_tampering_and_wrong_keys_fail_cleanly() {
+        let key = [7u8; KEY_LEN];
+        let mut sealed = encrypt(&key, b"my notes").unwrap();
+
+        let wrong = decrypt(&[8u8; KEY_LEN], &sealed).unwrap_err();
+        assert!(wrong.contains("different key"), "{}", wrong);
+        sealed[NONCE_LEN] ^= 1;
+        assert!(decrypt(&key, &sealed).is_err());
+        assert!(decrypt(&key, &sealed[..NONCE_LEN])
+            .unwrap_err()
+            .contains("too short"));
+    }
+
+    #[test]
+    fn test_subkeys_are_pinned_per_purpose() {
+        let master = [7u8; KEY_LEN];
+        // Independently computed; if these change, existing data is lost
+        let expected = [
+            ("notes", "8LsFCTlhpReqiaGLiwEebwBLmPWGWk8UZjaFc+kqLdw="),
+            (
+                "attachments",
+                "vr6h+Yd56eqmroEm3QyM/Z/I03mzbpsBhO/j37YNcgs=",
+            ),
+            ("sync", "t/HpuKnvM+emrN08eaytpqXeadfszBGTMdOYqNY/lGw="),
+        ];
+        for (purpose, subkey) in expected {
+            let derived = derive_subkey(&master, purpose).unwrap();
+            assert_eq!(
+                general_purpose::STANDARD.encode(derived.as_slice()),
+                subkey,
+                "{}",
+                purpose
+            );
+        }
+        assert_eq!(
+            derive_subkey(&master, "notes").unwrap(),
+            derive_subkey(&master, "notes").unwrap()
+        );
+        assert_ne!(
+            derive_subkey(&master, "notes").unwrap(),
+            derive_subkey(&[8u8; KEY_LEN], "notes").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_empty_purpose_is_rejected() {
+        let master = [7u8; KEY_LEN];
+        assert!(derive_subkey(&master, "").is_err());
+        assert!(derive_subkey(&master, "  ").is_err());
+    }
+}
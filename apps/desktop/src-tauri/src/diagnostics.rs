@@ -0,0 +1,245 @@
+// Diagnostics export
+// Bundles what a bug report needs into a timestamped directory under the app
+// data dir: app and platform details, the current settings, recent job
+// records, the last smoke test report and the most recent crash post-mortems.
+
+use crate::build_info::BuildInfo;
+use crate::docker::ComposeNetwork;
+use crate::jobs::{JobManager, JobRecord};
+use crate::keyring::{KeyringHealth, KeyringStatus};
+use crate::paths;
+use crate::postmortem::PostMortemStore;
+use crate::process::{CommandRunner, SystemRunner};
+use crate::settings::{AppSettings, SettingsStore};
+use crate::smoke::SmokeReport;
+use crate::tools::LocatedTool;
+use crate::{info, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, State};
+
+pub const DIAGNOSTICS_DIR_NAME: &str = "diagnostics";
+const EXPORTED_POSTMORTEMS: usize = 5;
+const EXPORTED_JOBS: usize = 20;
+
+/// App and platform details shown in the UI and included in diagnostics
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemInfo {
+    pub app_version: String,
+    /// The commit and build this is, beyond the version
+    pub build: BuildInfo,
+    pub os: String,
+    pub arch: String,
+    /// Result of the startup keyring check, `None` until it has run
+    pub keyring: Option<KeyringHealth>,
+    /// Which make / docker / compose binaries are used
+    pub tools: Vec<LocatedTool>,
+    /// The stack's compose networks and any subnet conflicts with host routes
+    pub compose_networks: Vec<ComposeNetwork>,
+    /// Started with `start_safe_mode` and not exited since
+    pub safe_mode: bool,
+    /// Whether the Makefile and compose files are checked before running
+    pub project_file_verification: bool,
+    /// Settings that weaken the app's safety checks
+    pub warnings: Vec<String>,
+}
+
+impl SystemInfo {
+    pub fn collect(keyring: &KeyringStatus) -> Self {
+        Self {
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            build: BuildInfo::current(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            keyring: keyring.health(),
+            tools: crate::tools::located(),
+            compose_networks: Vec::new(),
+            safe_mode: false,
+            project_file_verification: true,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Add what the settings turn off
+    pub fn with_settings(mut self, settings: &AppSettings) -> Self {
+        self.project_file_verification = !crate::trust::verification_disabled(settings);
+        self.warnings.extend(crate::trust::verification_warning(settings));
+        self
+    }
+
+    /// Add the compose networks; left empty if docker can't be asked
+    pub async fn with_networks(mut self, runner: &dyn CommandRunner) -> Self {
+        match crate::docker::inspect_compose_networks(runner).await {
+            Ok(networks) => self.compose_networks = networks,
+            Err(e) => warn!("⚠️  Network inspection failed: {}", e),
+        }
+        self
+    }
+}
+
+/// Write a diagnostics bundle into `dir`, returning the bundle's path
+pub fn export_to(
+    dir: &Path,
+    system: &SystemInfo,
+    settings: &SettingsStore,
+    jobs: &[JobRecord],
+    smoke_test: Option<&SmokeReport>,
+    postmortems: &PostMortemStore,
+) -> Result<PathBuf, String> {
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    let bundle = dir.join(format!("arbor-diagnostics-{}", timestamp));
+    std::fs::create_dir_all(&bundle)
+        .map_err(|e| format!("Failed to create diagnostics directory: {}", e))?;
+
+    let system = serde_json::json!({
+        "system": system,
+        "settings": settings.get().redacted(),
+    });
+    let contents = serde_json::to_vec_pretty(&system).map_err(|e| e.to_string())?;
+    std::fs::write(bundle.join("system.json"), contents)
+        .map_err(|e| format!("Failed to write diagnostics: {}", e))?;
+
+    let recent_jobs = &jobs[jobs.len().saturating_sub(EXPORTED_JOBS)..];
+    let contents = serde_json::to_vec_pretty(recent_jobs).map_err(|e| e.to_string())?;
+    std::fs::write(bundle.join("jobs.json"), contents)
+        .map_err(|e| format!("Failed to write diagnostics: {}", e))?;
+
+    if let Some(report) = smoke_test {
+        let contents = serde_json::to_vec_pretty(report).map_err(|e| e.to_string())?;
+        std::fs::write(bundle.join(crate::smoke::REPORT_FILE_NAME), contents)
+            .map_err(|e| format!("Failed to write diagnostics: {}", e))?;
+    }
+
+    let crashes = bundle.join(crate::postmortem::CRASHES_DIR_NAME);
+    for postmortem in postmortems
+        .bundles()?
+        .into_iter()
+        .take(EXPORTED_POSTMORTEMS)
+    {
+        let Some(name) = postmortem.file_name() else {
+            continue;
+        };
+        copy_dir(&postmortem, &crashes.join(name))?;
+    }
+
+    Ok(bundle)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(to).map_err(|e| format!("Failed to create {:?}: {}", to, e))?;
+    let entries =
+        std::fs::read_dir(from).map_err(|e| format!("Failed to read {:?}: {}", from, e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_file() {
+            std::fs::copy(&path, to.join(entry.file_name()))
+                .map_err(|e| format!("Failed to copy {:?}: {}", path, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_system_info(
+    app_handle: AppHandle,
+    keyring: State<'_, KeyringStatus>,
+) -> Result<SystemInfo, String> {
+    let system = SystemInfo::collect(&keyring)
+        .with_settings(&app_handle.state::<SettingsStore>().get())
+        .with_networks(&SystemRunner)
+        .await;
+    Ok(SystemInfo {
+        safe_mode: app_handle.state::<crate::ServiceManager>().in_safe_mode(),
+        ..system
+    })
+}
+
+#[tauri::command]
+pub async fn export_diagnostics(app_handle: AppHandle) -> Result<String, String> {
+    let data_dir = paths::data_dir(&app_handle)?;
+    let dir = data_dir.join(DIAGNOSTICS_DIR_NAME);
+    let system = SystemInfo::collect(&app_handle.state::<KeyringStatus>())
+        .with_settings(&app_handle.state::<SettingsStore>().get())
+        .with_networks(&SystemRunner)
+        .await;
+    let system = SystemInfo {
+        safe_mode: app_handle.state::<crate::ServiceManager>().in_safe_mode(),
+        ..system
+    };
+    let bundle = export_to(
+        &dir,
+        &system,
+        &app_handle.state::<SettingsStore>(),
+        &app_handle.state::<JobManager>().records(),
+        crate::smoke::load_report(&data_dir).as_ref(),
+        &app_handle.state::<PostMortemStore>(),
+    )?;
+
+    info!("📁 Diagnostics exported to {:?}", bundle);
+    Ok(bundle.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_export_includes_recent_postmortems() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        let crashes = root.join("crashes");
+        for i in 0..EXPORTED_POSTMORTEMS + 2 {
+            let bundle = crashes.join(format!("arbor-minio-20260101T0000{:02}Z", i));
+            std::fs::create_dir_all(&bundle).unwrap();
+            std::fs::write(bundle.join("crash.json"), "{}").unwrap();
+        }
+        let postmortems = PostMortemStore::new(crashes, Arc::new(|| serde_json::Value::Null));
+        let settings = SettingsStore::load(root.join("settings.json"));
+
+        let keyring = KeyringStatus::default();
+        keyring.record(KeyringHealth::Ok);
+        let system = SystemInfo::collect(&keyring);
+
+        let smoke_test = SmokeReport {
+            ran_at: "2026-01-01T00:00:00Z".to_string(),
+            passed: true,
+            steps: Vec::new(),
+            cleanup_errors: Vec::new(),
+        };
+
+        let bundle = export_to(
+            &root.join("out"),
+            &system,
+            &settings,
+            &[],
+            Some(&smoke_test),
+            &postmortems,
+        )
+        .unwrap();
+
+        let system = std::fs::read_to_string(bundle.join("system.json")).unwrap();
+        assert!(system.contains("app_version"));
+        assert!(system.contains(r#""status": "ok""#));
+        assert_eq!(std::fs::read_to_string(bundle.join("jobs.json")).unwrap(), "[]");
+        assert!(bundle.join(crate::smoke::REPORT_FILE_NAME).is_file());
+        let exported = std::fs::read_dir(bundle.join("crashes")).unwrap().count();
+        assert_eq!(exported, EXPORTED_POSTMORTEMS);
+        assert!(bundle
+            .join("crashes/arbor-minio-20260101T000006Z/crash.json")
+            .is_file());
+    }
+
+    #[test]
+    fn test_disabled_verification_is_a_warning() {
+        let settings = AppSettings {
+            dev_mode: true,
+            skip_project_file_verification: true,
+            ..AppSettings::default()
+        };
+        let system = SystemInfo::collect(&KeyringStatus::default()).with_settings(&settings);
+        assert!(!system.project_file_verification);
+        assert_eq!(system.warnings.len(), 1);
+    }
+}
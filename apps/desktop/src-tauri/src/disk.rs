@@ -0,0 +1,249 @@
+// Disk space pre-check
+// A first start pulls several GB of images, and a disk that fills up
+// halfway through only shows as a compose failure. Before `make up` the
+// free space where docker keeps its data is measured: below
+// `disk_space.warn_below_gb` a `low-disk-space` event warns, and below
+// `disk_space.refuse_below_gb` starting is refused as InsufficientDiskSpace
+// unless forced. Docker Desktop's data root is inside its VM, whose disk
+// image lives on the OS drive, so that is measured where the root can't be.
+
+use crate::messages::{Coded, Message};
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::settings::DiskSpaceSettings;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Emitted with a LowDiskSpace when the stack starts short on space
+pub const LOW_DISK_SPACE_EVENT: &str = "low-disk-space";
+/// Emitted with an InsufficientDiskSpace when starting is refused
+pub const INSUFFICIENT_DISK_SPACE_EVENT: &str = "insufficient-disk-space";
+
+const BYTES_PER_GB: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiskSpace {
+    /// Where it was measured
+    pub path: String,
+    pub free_bytes: u64,
+    /// Whether `path` is docker's data root, rather than the OS drive
+    pub docker_root: bool,
+}
+
+/// Space below the warning threshold, though enough to start
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LowDiskSpace {
+    pub path: String,
+    pub free_bytes: u64,
+    pub warn_below_bytes: u64,
+}
+
+impl Coded for LowDiskSpace {
+    fn message(&self) -> Message {
+        Message::new("disk-space-low")
+            .with_param("free", gb(self.free_bytes))
+            .with_param("path", &self.path)
+    }
+}
+
+/// Why the stack wasn't started: too little space for docker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InsufficientDiskSpace {
+    pub path: String,
+    pub free_bytes: u64,
+    pub required_bytes: u64,
+}
+
+impl fmt::Display for InsufficientDiskSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Only {} free at {}, and starting needs {}; free some space or start anyway",
+            gb(self.free_bytes),
+            self.path,
+            gb(self.required_bytes)
+        )
+    }
+}
+
+impl Coded for InsufficientDiskSpace {
+    fn message(&self) -> Message {
+        Message::new("disk-space-insufficient")
+            .with_param("free", gb(self.free_bytes))
+            .with_param("required", gb(self.required_bytes))
+            .with_param("path", &self.path)
+    }
+}
+
+/// e.g. `3.2 GB`
+fn gb(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / BYTES_PER_GB as f64)
+}
+
+/// What starting with `space` comes to under `limits`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Enough,
+    Low(LowDiskSpace),
+    Insufficient(InsufficientDiskSpace),
+}
+
+impl DiskSpace {
+    pub fn verdict(&self, limits: &DiskSpaceSettings) -> Verdict {
+        let refuse_below_bytes = limits.refuse_below_gb * BYTES_PER_GB;
+        let warn_below_bytes = limits.warn_below_gb * BYTES_PER_GB;
+        if self.free_bytes < refuse_below_bytes {
+            Verdict::Insufficient(InsufficientDiskSpace {
+                path: self.path.clone(),
+                free_bytes: self.free_bytes,
+                required_bytes: refuse_below_bytes,
+            })
+        } else if self.free_bytes < warn_below_bytes {
+            Verdict::Low(LowDiskSpace {
+                path: self.path.clone(),
+                free_bytes: self.free_bytes,
+                warn_below_bytes,
+            })
+        } else {
+            Verdict::Enough
+        }
+    }
+}
+
+/// Where docker says it keeps its data, e.g. `/var/lib/docker`
+pub async fn docker_root(runner: &dyn CommandRunner) -> Option<PathBuf> {
+    let spec = CommandSpec::new("docker").args(["info", "--format", "{{.DockerRootDir}}"]);
+    let output = runner.output(&spec).await.ok()?;
+    let root = output.stdout_text().lines().next()?.trim().to_string();
+    (output.success() && !root.is_empty()).then(|| PathBuf::from(root))
+}
+
+/// Free space on docker's data root, or else on the OS drive
+pub async fn check(runner: &dyn CommandRunner) -> Result<DiskSpace, String> {
+    if let Some(root) = docker_root(runner).await {
+        if let Some(free_bytes) = free_space(&root) {
+            return Ok(DiskSpace {
+                path: root.display().to_string(),
+                free_bytes,
+                docker_root: true,
+            });
+        }
+    }
+    let drive = system_drive();
+    let free_bytes = free_space(&drive)
+        .ok_or_else(|| format!("Couldn't measure free space at {}", drive.display()))?;
+    Ok(DiskSpace {
+        path: drive.display().to_string(),
+        free_bytes,
+        docker_root: false,
+    })
+}
+
+/// The volume the OS, and Docker Desktop's VM disk, live on
+fn system_drive() -> PathBuf {
+    if cfg!(windows) {
+        let drive = std::env::var("SystemDrive").unwrap_or_else(|_| "C:".to_string());
+        PathBuf::from(format!("{}\\", drive))
+    } else {
+        PathBuf::from("/")
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs only reads the path and writes into the struct it's given
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+/// Bytes available to this user, within any quota, on the volume holding
+/// `path`
+#[cfg(windows)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use windows::core::HSTRING;
+    use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let mut available = 0u64;
+    // SAFETY: the call only reads the path and writes into `available`
+    unsafe {
+        GetDiskFreeSpaceExW(
+            &HSTRING::from(path.as_os_str()),
+            Some(&mut available),
+            None,
+            None,
+        )
+    }
+    .ok()?;
+    Some(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Free space where docker keeps its data, or on the OS drive where that
+/// can't be measured from the host
+#[tauri::command]
+pub async fn check_disk_space() -> Result<DiskSpace, String> {
+    check(&SystemRunner).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+
+    #[tokio::test]
+    async fn test_unmeasurable_docker_root_falls_back_to_the_os_drive() {
+        let root = std::env::temp_dir();
+        let runner = MockRunner::with_stdout(&format!("{}\n", root.display()));
+        let space = check(&runner).await.unwrap();
+        assert!(space.docker_root);
+        assert_eq!(space.path, root.display().to_string());
+
+        // Docker Desktop's root, inside its VM
+        let runner = MockRunner::with_stdout("/nonexistent/docker\n");
+        let space = check(&runner).await.unwrap();
+        assert!(!space.docker_root);
+        assert_eq!(PathBuf::from(&space.path), system_drive());
+    }
+
+    #[test]
+    fn test_thresholds_warn_then_refuse() {
+        let limits = DiskSpaceSettings {
+            warn_below_gb: 10,
+            refuse_below_gb: 2,
+        };
+        let space = |free_bytes| DiskSpace {
+            path: "/var/lib/docker".to_string(),
+            free_bytes,
+            docker_root: true,
+        };
+
+        assert_eq!(space(12 * BYTES_PER_GB).verdict(&limits), Verdict::Enough);
+        assert!(matches!(
+            space(5 * BYTES_PER_GB).verdict(&limits),
+            Verdict::Low(LowDiskSpace {
+                warn_below_bytes: 10_000_000_000,
+                ..
+            })
+        ));
+        let Verdict::Insufficient(error) = space(1_500_000_000).verdict(&limits) else {
+            panic!("1.5 GB is below the refusal threshold");
+        };
+        assert_eq!(
+            error.to_string(),
+            "Only 1.5 GB free at /var/lib/docker, and starting needs 2.0 GB; \
+             free some space or start anyway"
+        );
+    }
+}
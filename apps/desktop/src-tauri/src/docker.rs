@@ -0,0 +1,1270 @@
+// Docker CLI integration
+// Container queries and the `docker events` subscription that keeps the
+// service status cache (and crash monitor) in sync with what the daemon is
+// actually doing. Commands are written for docker; where podman is the
+// runtime the tool table runs them with podman, and the few places their
+// output differs (version, events, podman-compose's names) read both.
+
+use crate::messages::{Coded, Message};
+use crate::process::{self, CommandRunner, CommandSpec, OutputStream};
+use crate::profiles;
+use crate::subnets::{self, Cidr};
+use crate::tools::{self, ContainerRuntime};
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+/// Compose project of the default profile; others are `arbor-<profile>`
+pub const COMPOSE_PROJECT: &str = "arbor";
+/// Label compose puts the project name in
+pub const PROJECT_LABEL: &str = "com.docker.compose.project";
+
+/// Oldest engine whose bundled compose supports `up --wait`
+pub const MIN_DOCKER_VERSION: &str = "20.10.0";
+/// Oldest podman with `podman compose`
+pub const MIN_PODMAN_VERSION: &str = "4.7.0";
+
+/// Matches every arbor container, whichever profile or compose started it
+pub const CONTAINER_NAME_FILTER: &str = "name=arbor";
+const EVENTS_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Emitted when Docker can't be used, with a DockerError payload
+pub const DOCKER_UNAVAILABLE_EVENT: &str = "docker-unavailable";
+
+/// Docker problems the frontend needs to tell apart
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DockerError {
+    /// No working docker command
+    NotInstalled,
+    /// The CLI works but `docker info` can't reach a daemon, e.g. Docker
+    /// Desktop isn't launched
+    DaemonUnreachable {
+        client_version: Option<String>,
+        detail: String,
+    },
+    /// The daemon is older than MIN_DOCKER_VERSION
+    UnsupportedVersion { version: String, required: String },
+    /// The daemon socket exists but the user may not open it, almost always
+    /// because they aren't (yet) in the group that owns it
+    SocketPermissionDenied {
+        socket_path: String,
+        required_group: String,
+        /// A rootless daemon socket the user could use instead
+        rootless_socket: Option<String>,
+    },
+    /// Docker is snap/flatpak-packaged and its confinement would break our
+    /// compose bind mounts for this project root
+    ConfinedDockerDetected {
+        packaging: DockerPackaging,
+        limitation: String,
+        project_root: String,
+    },
+}
+
+/// How the docker CLI/daemon was installed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DockerPackaging {
+    Native,
+    Snap,
+    Flatpak,
+}
+
+impl DockerPackaging {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DockerPackaging::Native => "native",
+            DockerPackaging::Snap => "snap",
+            DockerPackaging::Flatpak => "flatpak",
+        }
+    }
+}
+
+impl fmt::Display for DockerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DockerError::NotInstalled => write!(f, "Docker is not installed"),
+            DockerError::DaemonUnreachable { detail, .. } => write!(
+                f,
+                "Docker is installed but its daemon isn't running; start Docker Desktop \
+                 (or the docker service): {}",
+                detail
+            ),
+            DockerError::UnsupportedVersion { version, required } => {
+                write!(f, "Docker {} found, {} or newer required", version, required)
+            }
+            DockerError::SocketPermissionDenied {
+                socket_path,
+                required_group,
+                rootless_socket,
+            } => {
+                write!(
+                    f,
+                    "Permission denied on {}: add your user to the '{}' group \
+                     (`sudo usermod -aG {} $USER`) and log out and back in",
+                    socket_path, required_group, required_group
+                )?;
+                if let Some(rootless) = rootless_socket {
+                    write!(f, ", or use the rootless daemon at {}", rootless)?;
+                }
+                Ok(())
+            }
+            DockerError::ConfinedDockerDetected { limitation, .. } => {
+                write!(f, "{}", limitation)
+            }
+        }
+    }
+}
+
+impl Coded for DockerError {
+    fn message(&self) -> Message {
+        match self {
+            DockerError::NotInstalled => Message::new("docker-not-installed"),
+            DockerError::DaemonUnreachable { .. } => Message::new("docker-daemon-unreachable"),
+            DockerError::UnsupportedVersion { version, required } => {
+                Message::new("docker-version-unsupported")
+                    .with_param("version", version)
+                    .with_param("required", required)
+            }
+            DockerError::SocketPermissionDenied {
+                socket_path,
+                required_group,
+                ..
+            } => Message::new("docker-socket-permission-denied")
+                .with_param("socket_path", socket_path)
+                .with_param("required_group", required_group),
+            DockerError::ConfinedDockerDetected {
+                packaging,
+                project_root,
+                ..
+            } => Message::new("docker-confined")
+                .with_param("packaging", packaging.name())
+                .with_param("project_root", project_root),
+        }
+    }
+}
+
+/// One arbor container as reported by `docker ps`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContainerStatus {
+    pub name: String,
+    pub image: String,
+    pub state: String,
+    pub status: String,
+    /// Published host ports
+    pub ports: Vec<u16>,
+    /// From `inspect_states`: the healthcheck's verdict, if it has one
+    pub health: Option<String>,
+    /// From `inspect_states`: when a running container last started
+    pub started_at: Option<String>,
+}
+
+impl ContainerStatus {
+    pub fn is_running(&self) -> bool {
+        self.state == "running"
+    }
+
+    /// Running, and healthy where it has a healthcheck
+    pub fn is_ready(&self) -> bool {
+        self.is_running()
+            && !self.status.contains("health: starting")
+            && !self.status.contains("unhealthy")
+    }
+}
+
+/// List every arbor container, running or not
+pub async fn list_containers(runner: &dyn CommandRunner) -> Result<Vec<ContainerStatus>, String> {
+    let spec = CommandSpec::new("docker").args([
+        "ps",
+        "--all",
+        "--filter",
+        CONTAINER_NAME_FILTER,
+        "--format",
+        "{{.Names}}\t{{.State}}\t{{.Status}}\t{{.Ports}}\t{{.Image}}",
+    ]);
+
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to check service status: {}", e))?;
+
+    if !output.success() {
+        return Err(format!(
+            "Failed to check service status: {}",
+            output.stderr_text().trim()
+        ));
+    }
+
+    Ok(parse_container_list(&output.stdout_text()))
+}
+
+fn parse_container_list(text: &str) -> Vec<ContainerStatus> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            let mut field = || fields.next().unwrap_or_default().trim().to_string();
+            let (name, state, status, ports) = (field(), field(), field(), field());
+            ContainerStatus {
+                name,
+                image: field(),
+                state,
+                status,
+                ports: parse_published_ports(&ports),
+                health: None,
+                started_at: None,
+            }
+        })
+        .collect()
+}
+
+/// Health and start time of a container, from `docker inspect`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerState {
+    pub health: Option<String>,
+    pub started_at: Option<String>,
+}
+
+/// Fill in `health` and `started_at` from `docker inspect`
+/// Containers removed since they were listed are left as they are.
+pub async fn inspect_states(
+    runner: &dyn CommandRunner,
+    containers: &mut [ContainerStatus],
+) -> Result<(), String> {
+    if containers.is_empty() {
+        return Ok(());
+    }
+    let spec = CommandSpec::new("docker")
+        .args([
+            "inspect",
+            "--format",
+            "{{.Name}}\t{{if .State.Health}}{{.State.Health.Status}}{{end}}\t{{if .State.Running}}{{.State.StartedAt}}{{end}}",
+        ])
+        .args(containers.iter().map(|c| c.name.clone()));
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to inspect containers: {}", e))?;
+    // Exits non-zero if any is gone, but still reports the others
+    let states = parse_inspect_states(&output.stdout_text());
+    for container in containers {
+        if let Some(state) = states.get(&container.name) {
+            container.health = state.health.clone();
+            container.started_at = state.started_at.clone();
+        }
+    }
+    Ok(())
+}
+
+fn parse_inspect_states(text: &str) -> HashMap<String, ContainerState> {
+    let optional = |field: Option<&str>| {
+        Some(field?.trim())
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.trim().trim_start_matches('/');
+            if name.is_empty() {
+                return None;
+            }
+            let state = ContainerState {
+                health: optional(fields.next()),
+                started_at: optional(fields.next()),
+            };
+            Some((name.to_string(), state))
+        })
+        .collect()
+}
+
+/// Host ports from `docker ps` output like `0.0.0.0:5432->5432/tcp, :::5432->5432/tcp`
+pub fn parse_published_ports(text: &str) -> Vec<u16> {
+    let mut ports: Vec<u16> = text
+        .split(',')
+        .filter_map(|mapping| {
+            let (host, _) = mapping.split_once("->")?;
+            host.rsplit(':').next()?.trim().parse().ok()
+        })
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// Docker found by `check_status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DockerInfo {
+    pub runtime: ContainerRuntime,
+    /// From `docker --version`
+    pub client_version: Option<String>,
+    pub server_version: String,
+}
+
+/// What `get_container_runtime` reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuntimeInfo {
+    pub runtime: ContainerRuntime,
+    /// None when it isn't installed or can't be reached
+    pub version: Option<String>,
+}
+
+/// Check that docker is installed, its daemon reachable and new enough
+/// An unreachable daemon whose socket the user can't open is reported as
+/// SocketPermissionDenied.
+pub async fn check_status(runner: &dyn CommandRunner) -> Result<DockerInfo, DockerError> {
+    check_runtime(runner, tools::runtime()).await
+}
+
+/// `check_status` of `runtime`, which `runner` runs the `docker` commands with
+async fn check_runtime(
+    runner: &dyn CommandRunner,
+    runtime: ContainerRuntime,
+) -> Result<DockerInfo, DockerError> {
+    let client = CommandSpec::new("docker").args(["--version"]);
+    let client_version = match runner.output(&client).await {
+        Ok(output) if output.success() => parse_client_version(&output.stdout_text()),
+        _ => return Err(DockerError::NotInstalled),
+    };
+
+    let (format, required) = match runtime {
+        ContainerRuntime::Docker => ("{{.ServerVersion}}", MIN_DOCKER_VERSION),
+        ContainerRuntime::Podman => ("{{.Version.Version}}", MIN_PODMAN_VERSION),
+    };
+    let info = CommandSpec::new("docker").args(["info", "--format", format]);
+    let unreachable = |detail: String| DockerError::DaemonUnreachable {
+        client_version: client_version.clone(),
+        detail,
+    };
+    let output = runner
+        .output(&info)
+        .await
+        .map_err(|e| unreachable(e.to_string()))?;
+    let server_version = output.stdout_text().trim().to_string();
+    if !output.success() || server_version.is_empty() {
+        // Often just a socket the user may not open yet; podman has no daemon
+        if runtime == ContainerRuntime::Docker {
+            check_socket_access(runner).await?;
+        }
+        return Err(unreachable(output.stderr_text().trim().to_string()));
+    }
+
+    if !version_supported(&server_version, required) {
+        return Err(DockerError::UnsupportedVersion {
+            version: server_version,
+            required: required.to_string(),
+        });
+    }
+    Ok(DockerInfo {
+        runtime,
+        client_version,
+        server_version,
+    })
+}
+
+/// `24.0.7` from `Docker version 24.0.7, build afdd53b`, or `4.9.3` from
+/// `podman version 4.9.3`
+fn parse_client_version(text: &str) -> Option<String> {
+    let text = text.trim();
+    let version = text
+        .strip_prefix("Docker version ")
+        .or_else(|| text.strip_prefix("podman version "))?;
+    let version = version.split(',').next()?.trim();
+    Some(version.to_string())
+}
+
+/// Compares the leading `major.minor.patch`; suffixes such as `+dfsg1` or
+/// `-rc.1` are ignored, and versions that don't start with one pass
+fn version_supported(version: &str, required: &str) -> bool {
+    let numbers = |version: &str| -> Option<Vec<u64>> {
+        let core = version
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()?;
+        core.split('.').map(|part| part.parse().ok()).collect()
+    };
+    match (numbers(version), numbers(required)) {
+        (Some(found), Some(required)) => found >= required,
+        _ => true,
+    }
+}
+
+/// Verify the current user may open the Docker daemon socket
+/// Only meaningful on Linux; Docker Desktop handles this elsewhere.
+pub async fn check_socket_access(runner: &dyn CommandRunner) -> Result<(), DockerError> {
+    #[cfg(target_os = "linux")]
+    {
+        // A custom DOCKER_HOST points at a different daemon entirely
+        if std::env::var_os("DOCKER_HOST").is_some() {
+            return Ok(());
+        }
+
+        let socket_path = context_socket_path(runner)
+            .await
+            .unwrap_or_else(|| socket::DEFAULT_SOCKET_PATH.into());
+        socket::check_socket_access(&socket_path)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = runner;
+        Ok(())
+    }
+}
+
+/// Socket of the active docker context, which differs from the default for
+/// snap, rootless and Docker Desktop for Linux installs
+#[cfg(target_os = "linux")]
+async fn context_socket_path(runner: &dyn CommandRunner) -> Option<PathBuf> {
+    let spec = CommandSpec::new("docker").args([
+        "context",
+        "inspect",
+        "--format",
+        "{{.Endpoints.docker.Host}}",
+    ]);
+    let output = runner.output(&spec).await.ok().filter(|o| o.success())?;
+    let host = output.stdout_text().trim().to_string();
+    host.strip_prefix("unix://").map(Into::into)
+}
+
+/// Work out whether docker comes from a snap or flatpak
+pub async fn detect_packaging(runner: &dyn CommandRunner) -> DockerPackaging {
+    if let Some(path) = find_in_path("docker") {
+        if let Some(packaging) = packaging_from_path(&path) {
+            return packaging;
+        }
+    }
+
+    // The snap may also be reachable through a plain /usr/bin symlink
+    let snap_list = CommandSpec::new("snap").args(["list", "docker"]);
+    if let Ok(output) = runner.output(&snap_list).await {
+        if output.success() {
+            return DockerPackaging::Snap;
+        }
+    }
+
+    // We are ourselves sandboxed, so docker is reached through the sandbox too
+    if Path::new("/.flatpak-info").exists() {
+        return DockerPackaging::Flatpak;
+    }
+
+    DockerPackaging::Native
+}
+
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(binary))
+        .find(|candidate| candidate.is_file())
+}
+
+fn packaging_from_path(path: &Path) -> Option<DockerPackaging> {
+    // /snap/bin/docker is a symlink to /usr/bin/snap, so check both ends
+    let resolved = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if path.starts_with("/snap") || resolved.file_name() == Some("snap".as_ref()) {
+        return Some(DockerPackaging::Snap);
+    }
+    if path.components().any(|c| c.as_os_str() == "flatpak") {
+        return Some(DockerPackaging::Flatpak);
+    }
+    None
+}
+
+/// The confinement rule `project_root` breaks for this packaging, if any
+fn confinement_limitation(
+    packaging: DockerPackaging,
+    project_root: &Path,
+    home: Option<&Path>,
+) -> Option<String> {
+    let inside_home = |root: &Path| {
+        home.and_then(|home| root.strip_prefix(home).ok())
+            .map(|relative| {
+                // The snap `home` interface excludes hidden top-level dirs
+                !relative
+                    .components()
+                    .next()
+                    .is_some_and(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+            })
+            .unwrap_or(false)
+    };
+
+    match packaging {
+        DockerPackaging::Native => None,
+        DockerPackaging::Snap if !inside_home(project_root) => Some(format!(
+            "Docker is installed as a snap, which can only bind-mount non-hidden paths \
+             inside your home directory; {} is outside that area. Move the Arbor checkout \
+             under your home directory or install Docker from your distribution's packages",
+            project_root.display()
+        )),
+        DockerPackaging::Flatpak if !inside_home(project_root) => Some(format!(
+            "Docker is running through a Flatpak sandbox, which only shares your home \
+             directory by default; {} is not visible to it. Grant access with \
+             `flatpak override --filesystem=<path>` or move the checkout under your home directory",
+            project_root.display()
+        )),
+        DockerPackaging::Snap | DockerPackaging::Flatpak => None,
+    }
+}
+
+/// Fail fast when confined Docker couldn't mount this project's volumes
+/// Compose would otherwise fail halfway through with a baffling mount error.
+pub async fn check_confinement(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+) -> Result<(), DockerError> {
+    let packaging = detect_packaging(runner).await;
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let project_root = std::fs::canonicalize(project_root).unwrap_or_else(|_| project_root.into());
+
+    match confinement_limitation(packaging, &project_root, home.as_deref()) {
+        Some(limitation) => Err(DockerError::ConfinedDockerDetected {
+            packaging,
+            limitation,
+            project_root: project_root.display().to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod socket {
+    use super::DockerError;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::{Path, PathBuf};
+
+    pub(super) const DEFAULT_SOCKET_PATH: &str = "/var/run/docker.sock";
+
+    /// Owner and mode bits of the daemon socket
+    pub(super) struct SocketOwnership {
+        pub uid: u32,
+        pub gid: u32,
+        pub mode: u32,
+    }
+
+    /// Effective identity of this process
+    pub(super) struct UserIdentity {
+        pub uid: u32,
+        pub groups: Vec<u32>,
+    }
+
+    impl SocketOwnership {
+        /// Whether `user` gets read+write on the socket
+        pub fn permits(&self, user: &UserIdentity) -> bool {
+            if user.uid == 0 {
+                return true;
+            }
+            if user.uid == self.uid && self.mode & 0o600 == 0o600 {
+                return true;
+            }
+            if user.groups.contains(&self.gid) && self.mode & 0o060 == 0o060 {
+                return true;
+            }
+            self.mode & 0o006 == 0o006
+        }
+    }
+
+    pub(super) fn check_socket_access(socket_path: &Path) -> Result<(), DockerError> {
+        // A missing socket means the daemon isn't running, not a permission problem
+        let Ok(metadata) = std::fs::metadata(socket_path) else {
+            return Ok(());
+        };
+
+        let socket = SocketOwnership {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mode: metadata.mode(),
+        };
+        let user = current_user();
+        if socket.permits(&user) {
+            return Ok(());
+        }
+
+        let group_file = std::fs::read_to_string("/etc/group").unwrap_or_default();
+        Err(DockerError::SocketPermissionDenied {
+            socket_path: socket_path.display().to_string(),
+            required_group: group_name(&group_file, socket.gid)
+                .unwrap_or_else(|| socket.gid.to_string()),
+            rootless_socket: Some(rootless_socket_path(user.uid))
+                .filter(|path| path.exists())
+                .map(|path| path.display().to_string()),
+        })
+    }
+
+    fn current_user() -> UserIdentity {
+        // SAFETY: these calls only read process credentials; getgroups is
+        // first asked for the count and then given a buffer of that size
+        unsafe {
+            let count = libc::getgroups(0, std::ptr::null_mut());
+            let mut groups = vec![0; count.max(0) as usize];
+            let count = libc::getgroups(count, groups.as_mut_ptr());
+            groups.truncate(count.max(0) as usize);
+            groups.push(libc::getegid());
+
+            UserIdentity {
+                uid: libc::geteuid(),
+                groups,
+            }
+        }
+    }
+
+    /// Look up a group name in /etc/group contents
+    pub(super) fn group_name(group_file: &str, gid: u32) -> Option<String> {
+        group_file.lines().find_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let gid_field = fields.nth(1)?;
+            (gid_field.parse() == Ok(gid)).then(|| name.to_string())
+        })
+    }
+
+    /// Where a rootless daemon for this user would put its socket
+    fn rootless_socket_path(uid: u32) -> PathBuf {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", uid)))
+            .join("docker.sock")
+    }
+}
+
+/// A container lifecycle event from `docker events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEvent {
+    pub container: String,
+    pub action: String,
+}
+
+#[derive(Deserialize)]
+struct RawEvent {
+    /// `Status` in podman's events
+    #[serde(rename = "Action", alias = "Status")]
+    action: String,
+    #[serde(rename = "Actor", default)]
+    actor: RawActor,
+    /// Podman's events carry the labels and name at the top level
+    #[serde(rename = "Attributes", default)]
+    attributes: HashMap<String, String>,
+    #[serde(rename = "Name")]
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawActor {
+    #[serde(rename = "Attributes", default)]
+    attributes: HashMap<String, String>,
+}
+
+/// Parse one `--format '{{json .}}'` line, keeping only events of the
+/// compose `project` that can change what the status view shows (exec,
+/// attach, resize etc. are dropped)
+fn parse_event_line(line: &str, project: &str) -> Option<ContainerEvent> {
+    let mut raw: RawEvent = serde_json::from_str(line).ok()?;
+    raw.attributes.extend(raw.actor.attributes);
+    if raw.action == "died" {
+        // Podman's name for a die
+        raw.action = "die".to_string();
+    }
+    if !is_status_relevant(&raw.action)
+        || raw.attributes.get(PROJECT_LABEL).map(String::as_str) != Some(project)
+    {
+        return None;
+    }
+
+    Some(ContainerEvent {
+        container: raw
+            .attributes
+            .remove("name")
+            .or(raw.name)
+            .unwrap_or_default(),
+        action: raw.action,
+    })
+}
+
+fn is_status_relevant(action: &str) -> bool {
+    const ACTIONS: [&str; 10] = [
+        "create", "start", "restart", "die", "stop", "kill", "oom", "pause", "unpause", "destroy",
+    ];
+    ACTIONS.contains(&action) || action.starts_with("health_status")
+}
+
+/// Follow `docker events` for arbor containers and forward relevant ones
+/// to every subscriber. Reconnects after a delay when the stream ends (daemon
+/// restart, Docker not running yet) and returns once all receiving sides have
+/// gone away.
+pub async fn subscribe_events(mut subscribers: Vec<mpsc::Sender<ContainerEvent>>) {
+    loop {
+        if let Err(e) = follow_events(&mut subscribers).await {
+            warn!("⚠️  docker events subscription failed: {}", e);
+        }
+
+        subscribers.retain(|tx| !tx.is_closed());
+        if subscribers.is_empty() {
+            return;
+        }
+
+        tokio::time::sleep(EVENTS_RECONNECT_DELAY).await;
+    }
+}
+
+async fn follow_events(subscribers: &mut Vec<mpsc::Sender<ContainerEvent>>) -> std::io::Result<()> {
+    // Every compose project's events, so switching profiles needs no resubscribe
+    let project_filter = format!("label={}", PROJECT_LABEL);
+    let spec = CommandSpec::new("docker")
+        .args(["events", "--filter", "type=container", "--filter"])
+        .args([project_filter.as_str(), "--format", "{{json .}}"]);
+    let (child, mut lines) = process::spawn_piped(&spec)?;
+
+    while let Some(line) = lines.recv().await {
+        if line.stream != OutputStream::Stdout {
+            continue;
+        }
+        let Some(event) = parse_event_line(&line.line, &profiles::compose_project()) else {
+            continue;
+        };
+
+        let mut delivered = Vec::with_capacity(subscribers.len());
+        for tx in subscribers.drain(..) {
+            if tx.send(event.clone()).await.is_ok() {
+                delivered.push(tx);
+            }
+        }
+        *subscribers = delivered;
+
+        // Dropping the child on return kills `docker events`
+        if subscribers.is_empty() {
+            return Ok(());
+        }
+    }
+
+    child.wait().await?;
+    Ok(())
+}
+
+/// How a container last exited, from `docker inspect`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContainerExit {
+    pub exit_code: i32,
+    pub oom_killed: bool,
+    /// `HostConfig.Memory`, when a limit is set
+    pub memory_limit_bytes: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawState {
+    exit_code: i32,
+    #[serde(rename = "OOMKilled")]
+    oom_killed: bool,
+}
+
+pub async fn inspect_exit(
+    runner: &dyn CommandRunner,
+    container: &str,
+) -> Result<ContainerExit, String> {
+    let spec = CommandSpec::new("docker").args([
+        "inspect",
+        "--format",
+        "{{json .State}}\t{{.HostConfig.Memory}}",
+        container,
+    ]);
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to inspect {}: {}", container, e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to inspect {}: {}",
+            container,
+            output.stderr_text().trim()
+        ));
+    }
+
+    let stdout = output.stdout_text();
+    let (state, memory) = stdout.trim().split_once('\t').unwrap_or((stdout.trim(), ""));
+    let state: RawState = serde_json::from_str(state)
+        .map_err(|e| format!("Failed to parse state of {}: {}", container, e))?;
+
+    Ok(ContainerExit {
+        exit_code: state.exit_code,
+        oom_killed: state.oom_killed,
+        memory_limit_bytes: memory.trim().parse().ok().filter(|limit| *limit > 0),
+    })
+}
+
+/// Compose service of an arbor container; the compose files name every
+/// container `arbor-<service>`, and podman-compose names those they don't
+/// `arbor_<service>_<n>`
+pub fn service_of(container: &str) -> &str {
+    if let Some(rest) = container.strip_prefix("arbor_") {
+        return match rest.rsplit_once('_') {
+            Some((service, n)) if n.parse::<u32>().is_ok() => service,
+            _ => rest,
+        };
+    }
+    container.strip_prefix("arbor-").unwrap_or(container)
+}
+
+/// Stop a container that docker already restarted
+pub async fn stop_container(runner: &dyn CommandRunner, container: &str) -> Result<(), String> {
+    let spec = CommandSpec::new("docker").args(["stop", container]);
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to stop {}: {}", container, e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to stop {}: {}",
+            container,
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Force-remove `containers`, running or not
+pub async fn remove_containers(
+    runner: &dyn CommandRunner,
+    containers: &[String],
+) -> Result<(), String> {
+    if containers.is_empty() {
+        return Ok(());
+    }
+    let spec = CommandSpec::new("docker")
+        .args(["rm", "-f"])
+        .args(containers.iter().cloned());
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("failed to remove containers: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "failed to remove containers: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Start a stopped container again
+pub async fn start_container(runner: &dyn CommandRunner, container: &str) -> Result<(), String> {
+    let spec = CommandSpec::new("docker").args(["start", container]);
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to start {}: {}", container, e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to start {}: {}",
+            container,
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Turn off a container's restart policy so a crash loop stops
+pub async fn disable_restart(runner: &dyn CommandRunner, container: &str) -> Result<(), String> {
+    let spec = CommandSpec::new("docker").args(["update", "--restart=no", container]);
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to update {}: {}", container, e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to update {}: {}",
+            container,
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(())
+}
+
+/// A network compose created for the stack
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComposeNetwork {
+    pub name: String,
+    pub subnets: Vec<String>,
+    #[serde(flatten)]
+    pub condition: NetworkCondition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "condition", rename_all = "snake_case")]
+pub enum NetworkCondition {
+    Ok,
+    /// Host routes (typically a VPN's) overlap the network, so traffic meant
+    /// for the services goes elsewhere. Pin another range with `setting`.
+    NetworkSubnetConflict {
+        subnet: String,
+        overlapping_routes: Vec<String>,
+        setting: String,
+    },
+}
+
+/// The stack's compose networks, checked against the host's routes
+pub async fn inspect_compose_networks(runner: &dyn CommandRunner) -> Result<Vec<ComposeNetwork>, String> {
+    let project_filter = format!("label={}={}", PROJECT_LABEL, profiles::compose_project());
+    let ls = CommandSpec::new("docker").args([
+        "network",
+        "ls",
+        "--filter",
+        &project_filter,
+        "--format",
+        "{{.Name}}",
+    ]);
+    let output = runner
+        .output(&ls)
+        .await
+        .map_err(|e| format!("Failed to list networks: {}", e))?;
+    if !output.success() {
+        return Err(format!("Failed to list networks: {}", output.stderr_text().trim()));
+    }
+    let names: Vec<String> = output
+        .stdout_text()
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let inspect = CommandSpec::new("docker")
+        .args(["network", "inspect", "--format"])
+        .args(["{{.Name}}\t{{range .IPAM.Config}}{{.Subnet}} {{end}}"])
+        .args(&names);
+    let output = runner
+        .output(&inspect)
+        .await
+        .map_err(|e| format!("Failed to inspect networks: {}", e))?;
+    if !output.success() {
+        return Err(format!("Failed to inspect networks: {}", output.stderr_text().trim()));
+    }
+
+    let routes = subnets::host_routes(runner).await;
+    Ok(output
+        .stdout_text()
+        .lines()
+        .filter_map(|line| {
+            let (name, subnets) = line.split_once('\t')?;
+            Some(check_network(name.trim(), subnets.split_whitespace().collect(), &routes))
+        })
+        .collect())
+}
+
+fn check_network(name: &str, subnets: Vec<&str>, routes: &[Cidr]) -> ComposeNetwork {
+    let condition = subnets
+        .iter()
+        .filter_map(|subnet| Some((*subnet, Cidr::parse(subnet).ok()?)))
+        .find_map(|(text, subnet)| {
+            let overlapping = subnets::overlapping_routes(&subnet, routes);
+            (!overlapping.is_empty()).then(|| NetworkCondition::NetworkSubnetConflict {
+                subnet: text.to_string(),
+                overlapping_routes: overlapping.iter().map(Cidr::to_string).collect(),
+                setting: "compose_subnet".to_string(),
+            })
+        })
+        .unwrap_or(NetworkCondition::Ok);
+    ComposeNetwork {
+        name: name.to_string(),
+        subnets: subnets.into_iter().map(str::to_string).collect(),
+        condition,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    #[tokio::test]
+    async fn test_list_containers_parses_ps_output() {
+        let runner = MockRunner::with_stdout(
+            "arbor-postgres\trunning\tUp 2 hours (healthy)\t0.0.0.0:5432->5432/tcp, :::5432->5432/tcp\narbor-redis\texited\tExited (137) 5 minutes ago\t\n",
+        );
+
+        let containers = list_containers(&runner).await.unwrap();
+
+        assert_eq!(containers.len(), 2);
+        assert_eq!(containers[0].name, "arbor-postgres");
+        assert!(containers[0].is_running());
+        assert_eq!(containers[0].ports, vec![5432]);
+        assert_eq!(containers[1].status, "Exited (137) 5 minutes ago");
+        assert!(!containers[1].is_running());
+    }
+
+    #[tokio::test]
+    async fn test_inspect_states_fills_in_health_and_start() {
+        let runner = MockRunner::new(|_| {
+            Ok(CommandOutput {
+                code: Some(1),
+                stdout:
+                    b"/arbor-postgres\thealthy\t2026-10-14T09:00:00.123456789Z\n/arbor-redis\t\t\n"
+                        .to_vec(),
+                stderr: b"Error: No such object: arbor-minio".to_vec(),
+            })
+        });
+        let mut containers = parse_container_list(
+            "arbor-postgres\trunning\tUp 2 hours (healthy)\t\tpostgres:16\narbor-redis\texited\tExited (1)\t\tredis:7\narbor-minio\trunning\tUp 1 second\t\tminio\n",
+        );
+
+        inspect_states(&runner, &mut containers).await.unwrap();
+
+        assert_eq!(containers[0].image, "postgres:16");
+        assert_eq!(containers[0].health.as_deref(), Some("healthy"));
+        assert_eq!(
+            containers[0].started_at.as_deref(),
+            Some("2026-10-14T09:00:00.123456789Z")
+        );
+        assert_eq!(containers[1].health, None);
+        assert_eq!(containers[1].started_at, None);
+        assert_eq!(containers[2].health, None, "removed since listed");
+        let args = &runner.calls()[0].args;
+        assert_eq!(args[0], "inspect");
+        assert_eq!(args[3..], ["arbor-postgres", "arbor-redis", "arbor-minio"]);
+    }
+
+    #[tokio::test]
+    async fn test_check_status_tells_missing_stopped_and_old_docker_apart() {
+        fn runner(
+            version: Option<&'static str>,
+            info: Result<&'static str, &'static str>,
+        ) -> MockRunner {
+            MockRunner::new(move |spec| {
+                let (code, stdout, stderr) = match spec.args[0].as_str() {
+                    "--version" => match version {
+                        Some(version) => (0, version, ""),
+                        None => return Err(std::io::ErrorKind::NotFound.into()),
+                    },
+                    "info" => match info {
+                        Ok(server) => (0, server, ""),
+                        Err(stderr) => (1, "\n", stderr),
+                    },
+                    _ => (1, "", ""),
+                };
+                Ok(CommandOutput {
+                    code: Some(code),
+                    stdout: stdout.as_bytes().to_vec(),
+                    stderr: stderr.as_bytes().to_vec(),
+                })
+            })
+        }
+        let installed = Some("Docker version 24.0.7, build afdd53b\n");
+
+        let missing = check_status(&runner(None, Ok("24.0.7"))).await;
+        assert_eq!(missing, Err(DockerError::NotInstalled));
+
+        let stopped = check_status(&runner(
+            installed,
+            Err("Cannot connect to the Docker daemon. Is the docker daemon running?"),
+        ))
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            &stopped,
+            DockerError::DaemonUnreachable { client_version: Some(v), detail }
+                if v == "24.0.7" && detail.starts_with("Cannot connect")
+        ));
+        assert_eq!(stopped.message().code, "docker-daemon-unreachable");
+
+        let old = check_status(&runner(installed, Ok("19.03.15\n"))).await;
+        assert_eq!(
+            old,
+            Err(DockerError::UnsupportedVersion {
+                version: "19.03.15".to_string(),
+                required: MIN_DOCKER_VERSION.to_string(),
+            })
+        );
+
+        let ok = check_status(&runner(installed, Ok("27.1.0-rc.1\n")))
+            .await
+            .unwrap();
+        assert_eq!(ok.server_version, "27.1.0-rc.1");
+        assert!(version_supported("20.10.24+dfsg1", MIN_DOCKER_VERSION));
+        assert!(!version_supported("20.9", MIN_DOCKER_VERSION));
+
+        // No daemon socket to check, and its own minimum version
+        let podman = Some("podman version 4.9.3\n");
+        let ok = check_runtime(&runner(podman, Ok("4.9.3\n")), ContainerRuntime::Podman)
+            .await
+            .unwrap();
+        assert_eq!(ok.runtime, ContainerRuntime::Podman);
+        assert_eq!(ok.client_version.as_deref(), Some("4.9.3"));
+        let old = check_runtime(&runner(podman, Ok("4.3.1\n")), ContainerRuntime::Podman).await;
+        assert!(matches!(
+            &old,
+            Err(DockerError::UnsupportedVersion { required, .. }) if required == MIN_PODMAN_VERSION
+        ));
+    }
+
+    #[test]
+    fn test_parse_event_line_keeps_lifecycle_events() {
+        let line = r#"{"status":"die","id":"abc","Type":"container","Action":"die","Actor":{"ID":"abc","Attributes":{"name":"arbor-postgres","exitCode":"137","com.docker.compose.project":"arbor"}}}"#;
+
+        assert_eq!(
+            parse_event_line(line, "arbor"),
+            Some(ContainerEvent {
+                container: "arbor-postgres".to_string(),
+                action: "die".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_event_line_drops_noise() {
+        let exec = r#"{"Type":"container","Action":"exec_start: pg_isready","Actor":{"Attributes":{"name":"arbor-postgres","com.docker.compose.project":"arbor"}}}"#;
+        assert_eq!(parse_event_line(exec, "arbor"), None);
+        assert_eq!(parse_event_line("not json", "arbor"), None);
+
+        let health = r#"{"Type":"container","Action":"health_status: unhealthy","Actor":{"Attributes":{"name":"arbor-redis","com.docker.compose.project":"arbor"}}}"#;
+        assert!(parse_event_line(health, "arbor").is_some());
+        // Another profile's stack
+        assert_eq!(parse_event_line(health, "arbor-work"), None);
+    }
+
+    #[test]
+    fn test_podman_events_and_names_are_read() {
+        let line = r#"{"ID":"abc","Image":"redis:7","Name":"arbor_redis_1","Status":"died","Type":"container","Attributes":{"com.docker.compose.project":"arbor"}}"#;
+        assert_eq!(
+            parse_event_line(line, "arbor"),
+            Some(ContainerEvent {
+                container: "arbor_redis_1".to_string(),
+                action: "die".to_string(),
+            })
+        );
+
+        assert_eq!(service_of("arbor_redis_1"), "redis");
+        assert_eq!(service_of("arbor_sync_worker_2"), "sync_worker");
+        assert_eq!(service_of("arbor-postgres"), "postgres");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_socket_permissions() {
+        use socket::{SocketOwnership, UserIdentity};
+
+        // root:docker srw-rw----
+        let socket = SocketOwnership {
+            uid: 0,
+            gid: 998,
+            mode: 0o140660,
+        };
+
+        let outsider = UserIdentity {
+            uid: 1000,
+            groups: vec![1000, 27],
+        };
+        let member = UserIdentity {
+            uid: 1000,
+            groups: vec![1000, 998],
+        };
+        let root = UserIdentity {
+            uid: 0,
+            groups: vec![0],
+        };
+
+        assert!(!socket.permits(&outsider));
+        assert!(socket.permits(&member));
+        assert!(socket.permits(&root));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_group_name_lookup() {
+        let group_file = "root:x:0:\nsudo:x:27:ward\ndocker:x:998:ward,ci\n";
+
+        assert_eq!(
+            socket::group_name(group_file, 998),
+            Some("docker".to_string())
+        );
+        assert_eq!(socket::group_name(group_file, 0), Some("root".to_string()));
+        assert_eq!(socket::group_name(group_file, 1234), None);
+    }
+
+    #[test]
+    fn test_snap_blocks_project_outside_home() {
+        let home = Path::new("/home/ward");
+
+        let outside =
+            confinement_limitation(DockerPackaging::Snap, Path::new("/opt/arbor"), Some(home));
+        assert!(outside.unwrap().contains("/opt/arbor"));
+
+        let hidden = confinement_limitation(
+            DockerPackaging::Snap,
+            Path::new("/home/ward/.local/src/arbor"),
+            Some(home),
+        );
+        assert!(
+            hidden.is_some(),
+            "Hidden dirs are not covered by the snap home interface"
+        );
+
+        let inside = confinement_limitation(
+            DockerPackaging::Snap,
+            Path::new("/home/ward/code/arbor"),
+            Some(home),
+        );
+        assert_eq!(inside, None);
+    }
+
+    #[test]
+    fn test_native_docker_is_never_confined() {
+        let limitation =
+            confinement_limitation(DockerPackaging::Native, Path::new("/opt/arbor"), None);
+        assert_eq!(limitation, None);
+    }
+
+    #[test]
+    fn test_packaging_from_snap_path() {
+        assert_eq!(
+            packaging_from_path(Path::new("/snap/bin/docker")),
+            Some(DockerPackaging::Snap)
+        );
+        assert_eq!(
+            packaging_from_path(Path::new("/var/lib/flatpak/exports/bin/docker")),
+            Some(DockerPackaging::Flatpak)
+        );
+        assert_eq!(
+            packaging_from_path(Path::new("/nonexistent/bin/docker")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_socket_permission_error_serializes_with_kind() {
+        let error = DockerError::SocketPermissionDenied {
+            socket_path: "/var/run/docker.sock".to_string(),
+            required_group: "docker".to_string(),
+            rootless_socket: None,
+        };
+
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["kind"], "socket_permission_denied");
+        assert_eq!(json["required_group"], "docker");
+        assert!(error.to_string().contains("usermod -aG docker"));
+    }
+
+    #[test]
+    fn test_network_subnet_conflict_names_the_routes() {
+        let vpn = [Cidr::parse("0.0.0.0/0").unwrap(), Cidr::parse("172.16.0.0/12").unwrap()];
+
+        let network = check_network("api_default", vec!["172.18.0.0/16"], &vpn);
+        let json = serde_json::to_value(&network).unwrap();
+        assert_eq!(json["condition"], "network_subnet_conflict");
+        assert_eq!(
+            network.condition,
+            NetworkCondition::NetworkSubnetConflict {
+                subnet: "172.18.0.0/16".to_string(),
+                overlapping_routes: vec!["172.16.0.0/12".to_string()],
+                setting: "compose_subnet".to_string(),
+            }
+        );
+
+        let pinned = check_network("api_default", vec!["10.250.0.0/24"], &vpn);
+        assert_eq!(pinned.condition, NetworkCondition::Ok);
+    }
+}
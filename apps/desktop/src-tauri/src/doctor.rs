@@ -0,0 +1,471 @@
+// Diagnostics doctor
+// `run_diagnostics` checks, in one go, what "services didn't start" reports
+// usually come down to: docker, its daemon, compose and make, the project
+// root, the stack's ports, disk space where docker keeps its data, the
+// keychain, the state of Arbor's containers and whether the version check
+// was overridden. Each check gets a status and a detail line, and the report
+// is JSON the user can paste into a bug report.
+// Checks run side by side, each under CHECK_TIMEOUT, so a hung daemon or
+// keychain fails its own check instead of holding up the report.
+
+use crate::compat::{VersionGate, VersionOverride};
+use crate::compose;
+use crate::disk;
+use crate::docker;
+use crate::keyring::{self, KeyringHealth};
+use crate::paths;
+use crate::ports;
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::services::ComposeSetup;
+use crate::settings::SettingsStore;
+use crate::tools::{self, LocatedTool, Tool};
+use crate::ServiceManager;
+use serde::Serialize;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tauri::{AppHandle, Manager};
+use tokio::time::Duration;
+
+/// How long one check may take before it's failed as hung
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+/// Free space under which starting is likely to fail
+const MIN_FREE_BYTES: u64 = 2_000_000_000;
+/// Free space under which image pulls may run out
+const LOW_FREE_BYTES: u64 = 10_000_000_000;
+
+/// Least severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiagnosticsReport {
+    pub ran_at: String,
+    pub app_version: String,
+    pub os: String,
+    pub arch: String,
+    /// The worst status among the checks
+    pub status: CheckStatus,
+    pub checks: Vec<Check>,
+}
+
+/// What the checks run against
+pub struct DoctorEnv<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub tools: &'a [LocatedTool],
+    pub project_root: Result<PathBuf, String>,
+    /// The compose setup the stack runs (or would run) with, given a root
+    pub setup: Option<ComposeSetup>,
+    /// Set once the version check was overridden this session
+    pub version_override: Option<VersionOverride>,
+    pub timeout: Duration,
+}
+
+type Outcome = (CheckStatus, String);
+
+/// Run every check, with `keychain` probing the OS keychain
+pub async fn run(
+    env: &DoctorEnv<'_>,
+    keychain: impl Future<Output = KeyringHealth>,
+) -> DiagnosticsReport {
+    let ran_at = chrono::Utc::now().to_rfc3339();
+    let app_version = env!("CARGO_PKG_VERSION").to_string();
+    let checks = tokio::join!(
+        check(env, "app_version", async {
+            (CheckStatus::Ok, format!("Arbor {}", app_version))
+        }),
+        check(env, "docker_installed", docker_installed(env.runner)),
+        check(env, "docker_daemon", docker_daemon(env.runner)),
+        check(
+            env,
+            "compose_available",
+            compose_available(env.runner, env.tools)
+        ),
+        check(env, "make_available", make_available(env.runner, env.tools)),
+        check(env, "project_root", async {
+            match &env.project_root {
+                Ok(root) => (CheckStatus::Ok, root.display().to_string()),
+                Err(e) => (CheckStatus::Fail, e.clone()),
+            }
+        }),
+        check(env, "ports_free", ports_free(env)),
+        check(env, "disk_space", disk_space(env.runner)),
+        check(env, "keychain", async { keychain_outcome(keychain.await) }),
+        check(env, "containers", containers(env.runner)),
+        check(env, "version_check", async {
+            version_check(env.version_override.as_ref())
+        }),
+    );
+    let checks = vec![
+        checks.0, checks.1, checks.2, checks.3, checks.4, checks.5, checks.6, checks.7, checks.8,
+        checks.9, checks.10,
+    ];
+
+    DiagnosticsReport {
+        ran_at,
+        app_version,
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        status: checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(CheckStatus::Ok),
+        checks,
+    }
+}
+
+/// Run one check under the timeout
+async fn check(
+    env: &DoctorEnv<'_>,
+    name: &'static str,
+    work: impl Future<Output = Outcome>,
+) -> Check {
+    let started = Instant::now();
+    let (status, detail) = match tokio::time::timeout(env.timeout, work).await {
+        Ok(outcome) => outcome,
+        Err(_) => (
+            CheckStatus::Fail,
+            format!("No answer within {}s", env.timeout.as_secs()),
+        ),
+    };
+    Check {
+        name,
+        status,
+        detail,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+/// The first line `spec` prints, if it runs and succeeds
+async fn first_line(runner: &dyn CommandRunner, spec: &CommandSpec) -> Result<String, String> {
+    let output = runner.output(spec).await.map_err(|e| e.to_string())?;
+    if !output.success() {
+        return Err(output.stderr_text().trim().to_string());
+    }
+    let stdout = output.stdout_text();
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+async fn docker_installed(runner: &dyn CommandRunner) -> Outcome {
+    let spec = CommandSpec::new("docker").args(["--version"]);
+    match first_line(runner, &spec).await {
+        Ok(version) => (CheckStatus::Ok, version),
+        Err(e) => (CheckStatus::Fail, format!("docker doesn't run: {}", e)),
+    }
+}
+
+async fn docker_daemon(runner: &dyn CommandRunner) -> Outcome {
+    match docker::check_status(runner).await {
+        Ok(info) => (
+            CheckStatus::Ok,
+            format!("{} {}", info.runtime.name(), info.server_version),
+        ),
+        Err(e) => (CheckStatus::Fail, e.to_string()),
+    }
+}
+
+async fn compose_available(runner: &dyn CommandRunner, tools: &[LocatedTool]) -> Outcome {
+    match compose::ensure_usable(runner, tools).await {
+        Ok(install) => match install.path {
+            Some(path) => (CheckStatus::Ok, format!("{} at {}", install.version, path)),
+            None => (
+                CheckStatus::Ok,
+                format!("docker compose {}", install.version),
+            ),
+        },
+        Err(e) => (CheckStatus::Fail, e.to_string()),
+    }
+}
+
+/// Only a warning without it: services then run with `docker compose`
+async fn make_available(runner: &dyn CommandRunner, tools: &[LocatedTool]) -> Outcome {
+    let make = tools.iter().find(|tool| tool.tool == Tool::Make);
+    if let Some(LocatedTool {
+        path: None, error, ..
+    }) = make
+    {
+        let error = error.as_deref().unwrap_or("make not found");
+        return (
+            CheckStatus::Warn,
+            format!("{}; services run with docker compose", error),
+        );
+    }
+    let spec = CommandSpec::new("make").args(["--version"]);
+    match first_line(runner, &spec).await {
+        Ok(version) => (CheckStatus::Ok, version),
+        Err(e) => (CheckStatus::Warn, format!("make doesn't run: {}", e)),
+    }
+}
+
+async fn ports_free(env: &DoctorEnv<'_>) -> Outcome {
+    let (Ok(root), Some(setup)) = (&env.project_root, &env.setup) else {
+        return (
+            CheckStatus::Warn,
+            "Not checked without a project root".to_string(),
+        );
+    };
+    match ports::check(env.runner, root, setup).await {
+        Ok(conflicts) if conflicts.is_empty() => (
+            CheckStatus::Ok,
+            "The stack's published ports are free".to_string(),
+        ),
+        Ok(conflicts) => (
+            CheckStatus::Fail,
+            ports::PortsInUse { conflicts }.to_string(),
+        ),
+        Err(e) => (
+            CheckStatus::Warn,
+            format!("Couldn't read the stack's ports: {}", e),
+        ),
+    }
+}
+
+async fn disk_space(runner: &dyn CommandRunner) -> Outcome {
+    let Some(root) = disk::docker_root(runner).await else {
+        return (
+            CheckStatus::Warn,
+            "Docker didn't say where it keeps its data".to_string(),
+        );
+    };
+    // Docker Desktop's data root is inside its VM
+    let Some(free) = disk::free_space(&root) else {
+        return (
+            CheckStatus::Warn,
+            format!("{} can't be measured from the host", root.display()),
+        );
+    };
+    let detail = format!("{:.1} GB free at {}", free as f64 / 1e9, root.display());
+    match free {
+        free if free < MIN_FREE_BYTES => (CheckStatus::Fail, detail),
+        free if free < LOW_FREE_BYTES => (CheckStatus::Warn, detail),
+        _ => (CheckStatus::Ok, detail),
+    }
+}
+
+fn keychain_outcome(health: KeyringHealth) -> Outcome {
+    match health {
+        KeyringHealth::Ok => (CheckStatus::Ok, "The keychain answers".to_string()),
+        KeyringHealth::Locked { detail } => (
+            CheckStatus::Warn,
+            format!("The keychain is locked: {}", detail),
+        ),
+        KeyringHealth::Unavailable { detail } | KeyringHealth::Error { detail } => {
+            (CheckStatus::Fail, detail)
+        }
+    }
+}
+
+/// Stopped containers are only a warning: they may have been stopped on
+/// purpose
+async fn containers(runner: &dyn CommandRunner) -> Outcome {
+    let containers = match docker::list_containers(runner).await {
+        Ok(containers) => containers,
+        Err(e) => return (CheckStatus::Fail, e),
+    };
+    if containers.is_empty() {
+        return (CheckStatus::Ok, "No Arbor containers".to_string());
+    }
+    let down: Vec<String> = containers
+        .iter()
+        .filter(|container| !container.is_running())
+        .map(|container| format!("{} {}", container.name, container.state))
+        .collect();
+    let running = containers.len() - down.len();
+    match down.is_empty() {
+        true => (CheckStatus::Ok, format!("{} running", running)),
+        false => (
+            CheckStatus::Warn,
+            format!("{} running; {}", running, down.join(", ")),
+        ),
+    }
+}
+
+/// Only a warning when overridden: the user chose to run the stack anyway
+fn version_check(version_override: Option<&VersionOverride>) -> Outcome {
+    match version_override {
+        None => (
+            CheckStatus::Ok,
+            "Incompatible stacks are refused".to_string(),
+        ),
+        Some(allowed) => (
+            CheckStatus::Warn,
+            format!(
+                "Overridden at {} for the stack started by Arbor {}",
+                allowed.overridden_at,
+                allowed.stack_version.as_deref().unwrap_or("(unknown)")
+            ),
+        ),
+    }
+}
+
+/// The compose setup the stack runs with, or would be started with from
+/// `root`
+pub fn stack_setup(app_handle: &AppHandle, root: &Path) -> Option<ComposeSetup> {
+    let active = app_handle
+        .state::<ServiceManager>()
+        .active_setup
+        .lock()
+        .clone();
+    active.or_else(|| {
+        let data_dir = paths::data_dir(app_handle).ok()?;
+        let settings = app_handle.state::<SettingsStore>().get();
+        Some(ComposeSetup::from_settings(&settings, root, &data_dir))
+    })
+}
+
+/// `run` against the app's own tools, project and keychain
+pub async fn diagnose(app_handle: &AppHandle) -> DiagnosticsReport {
+    let project_root = crate::find_project_root(app_handle);
+    let setup = project_root
+        .as_ref()
+        .ok()
+        .and_then(|root| stack_setup(app_handle, root));
+    let tools = tools::located();
+    let env = DoctorEnv {
+        runner: &SystemRunner,
+        tools: &tools,
+        project_root,
+        setup,
+        version_override: app_handle.state::<VersionGate>().overridden(),
+        timeout: CHECK_TIMEOUT,
+    };
+    run(&env, keyring::probe()).await
+}
+
+/// Check everything Arbor depends on, for the Diagnostics page and bug reports
+#[tauri::command]
+pub async fn run_diagnostics(app_handle: AppHandle) -> Result<DiagnosticsReport, String> {
+    let report = diagnose(&app_handle).await;
+    crate::flight::debug(format!(
+        "🩺 Diagnostics: {}",
+        serde_json::to_string(&report).unwrap_or_default()
+    ));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    fn status(report: &DiagnosticsReport, name: &str) -> (CheckStatus, String) {
+        let check = report
+            .checks
+            .iter()
+            .find(|check| check.name == name)
+            .unwrap();
+        (check.status, check.detail.clone())
+    }
+
+    #[tokio::test]
+    async fn test_report_covers_each_check() {
+        let runner = MockRunner::new(|spec| {
+            let args = spec.args.join(" ");
+            let (code, stdout) = match (spec.program.as_str(), args.as_str()) {
+                (_, "--version") if spec.program == "make" => (0, "GNU Make 4.3\n"),
+                (_, "--version") => (0, "Docker version 24.0.7, build afdd53b\n"),
+                (_, "info --format {{.ServerVersion}}") => (0, "24.0.7\n"),
+                (_, "info --format {{.DockerRootDir}}") => (0, "/nonexistent/docker\n"),
+                (_, "compose version") => (0, "Docker Compose version v2.24.5\n"),
+                (_, args) if args.starts_with("ps") => (
+                    0,
+                    "arbor-api\trunning\tUp\t\tapi\narbor-minio\texited\tExited (1)\t\tminio\n",
+                ),
+                _ => (1, ""),
+            };
+            Ok(CommandOutput {
+                code: Some(code),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        });
+        let env = DoctorEnv {
+            runner: &runner,
+            tools: &[],
+            project_root: Err("No project root found".to_string()),
+            setup: None,
+            version_override: None,
+            timeout: CHECK_TIMEOUT,
+        };
+        let report = run(&env, async { KeyringHealth::Ok }).await;
+
+        assert_eq!(report.checks.len(), 11);
+        let docker = status(&report, "docker_installed");
+        assert_eq!(
+            docker,
+            (
+                CheckStatus::Ok,
+                "Docker version 24.0.7, build afdd53b".into()
+            )
+        );
+        assert_eq!(
+            status(&report, "docker_daemon"),
+            (CheckStatus::Ok, "docker 24.0.7".into())
+        );
+        assert_eq!(
+            status(&report, "make_available"),
+            (CheckStatus::Ok, "GNU Make 4.3".into())
+        );
+        assert_eq!(status(&report, "project_root").0, CheckStatus::Fail);
+        assert_eq!(status(&report, "ports_free").0, CheckStatus::Warn);
+        assert_eq!(status(&report, "disk_space").0, CheckStatus::Warn);
+        assert_eq!(status(&report, "keychain").0, CheckStatus::Ok);
+        assert_eq!(
+            status(&report, "containers"),
+            (CheckStatus::Warn, "1 running; arbor-minio exited".into())
+        );
+        assert_eq!(status(&report, "version_check").0, CheckStatus::Ok);
+        assert_eq!(report.status, CheckStatus::Fail);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hung_checks_fail_without_holding_up_the_report() {
+        let runner = MockRunner::with_stdout("");
+        let env = DoctorEnv {
+            runner: &runner,
+            tools: &[],
+            project_root: Ok(PathBuf::from("/arbor")),
+            setup: None,
+            version_override: None,
+            timeout: Duration::from_secs(1),
+        };
+        let report = run(&env, std::future::pending()).await;
+        assert_eq!(
+            status(&report, "keychain"),
+            (CheckStatus::Fail, "No answer within 1s".into())
+        );
+        assert_eq!(status(&report, "app_version").0, CheckStatus::Ok);
+    }
+
+    #[test]
+    fn test_an_overridden_version_check_is_a_warning() {
+        let allowed = VersionOverride {
+            stack_version: Some("0.1.0".to_string()),
+            app_version: "0.2.0".to_string(),
+            overridden_at: "2026-01-01T00:00:00+00:00".to_string(),
+        };
+        assert_eq!(
+            version_check(Some(&allowed)),
+            (
+                CheckStatus::Warn,
+                "Overridden at 2026-01-01T00:00:00+00:00 for the stack started by Arbor 0.1.0"
+                    .into()
+            )
+        );
+    }
+}
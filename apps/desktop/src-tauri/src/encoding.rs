@@ -0,0 +1,275 @@
+// Output decoding
+// Commands hand back raw bytes. Most tools print UTF-8, but Windows tools
+// use the ANSI code page or UTF-16 and some progress bars emit arbitrary
+// bytes. Decoding tries UTF-8 first, then UTF-16, then the locale's
+// single-byte encoding, and says whether the text is a faithful rendering.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+    Latin1,
+}
+
+/// Text decoded from command output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: TextEncoding,
+    /// False when the bytes didn't fit the encoding, e.g. binary output
+    pub exact: bool,
+}
+
+// Windows-1252 differs from Latin-1 only in 0x80..=0x9F; `None` is unassigned
+#[rustfmt::skip]
+const WINDOWS_1252_HIGH: [Option<char>; 32] = [
+    Some('€'), None, Some('‚'), Some('ƒ'), Some('„'), Some('…'), Some('†'), Some('‡'),
+    Some('ˆ'), Some('‰'), Some('Š'), Some('‹'), Some('Œ'), None, Some('Ž'), None,
+    None, Some('‘'), Some('’'), Some('“'), Some('”'), Some('•'), Some('–'), Some('—'),
+    Some('˜'), Some('™'), Some('š'), Some('›'), Some('œ'), None, Some('ž'), Some('Ÿ'),
+];
+
+/// Output as returned to the UI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputText {
+    pub text: String,
+    pub encoding: TextEncoding,
+    pub exact: bool,
+    /// The original bytes, base64, if asked for and `text` isn't exact
+    pub raw_base64: Option<String>,
+}
+
+impl OutputText {
+    pub fn decode(bytes: &[u8], include_raw: bool) -> Self {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let decoded = decode(bytes);
+        let raw_base64 =
+            (include_raw && !decoded.exact).then(|| general_purpose::STANDARD.encode(bytes));
+        Self {
+            text: decoded.text,
+            encoding: decoded.encoding,
+            exact: decoded.exact,
+            raw_base64,
+        }
+    }
+}
+
+/// Decode a whole output buffer
+pub fn decode(bytes: &[u8]) -> Decoded {
+    // First, because BOM-less UTF-16 of ASCII text is also valid UTF-8
+    if let Some(decoded) = decode_utf16(bytes) {
+        return decoded;
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Decoded {
+            text: text.to_string(),
+            encoding: TextEncoding::Utf8,
+            exact: true,
+        };
+    }
+    decode_single_byte(bytes, locale_encoding())
+}
+
+/// Decode one line of streamed output
+/// Lines are split on `\n` bytes, which cuts UTF-16 apart, so only UTF-8 and
+/// the single-byte fallback apply.
+pub fn decode_line(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => decode_single_byte(bytes, locale_encoding()).text,
+    }
+}
+
+fn decode_utf16(bytes: &[u8]) -> Option<Decoded> {
+    let (encoding, body) = match bytes {
+        [0xFF, 0xFE, rest @ ..] => (TextEncoding::Utf16Le, rest),
+        [0xFE, 0xFF, rest @ ..] => (TextEncoding::Utf16Be, rest),
+        _ => (sniff_utf16(bytes)?, bytes),
+    };
+    if !body.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let units = body.chunks_exact(2).map(|pair| match encoding {
+        TextEncoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+        _ => u16::from_be_bytes([pair[0], pair[1]]),
+    });
+    let mut exact = true;
+    let text = char::decode_utf16(units)
+        .map(|c| {
+            c.unwrap_or_else(|_| {
+                exact = false;
+                char::REPLACEMENT_CHARACTER
+            })
+        })
+        .collect();
+    Some(Decoded {
+        text,
+        encoding,
+        exact,
+    })
+}
+
+/// BOM-less UTF-16 of mostly ASCII text has a NUL in every other byte
+fn sniff_utf16(bytes: &[u8]) -> Option<TextEncoding> {
+    if bytes.len() < 4 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let zeros_at = |offset: usize| {
+        bytes
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if odd * 2 > pairs && even == 0 {
+        Some(TextEncoding::Utf16Le)
+    } else if even * 2 > pairs && odd == 0 {
+        Some(TextEncoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+fn decode_single_byte(bytes: &[u8], encoding: TextEncoding) -> Decoded {
+    let mut exact = true;
+    let text = bytes
+        .iter()
+        .map(|&byte| {
+            let c = match (encoding, byte) {
+                (TextEncoding::Windows1252, 0x80..=0x9F) => {
+                    WINDOWS_1252_HIGH[(byte - 0x80) as usize]
+                }
+                _ => Some(byte as char),
+            };
+            // Control bytes other than whitespace and ANSI escapes mean
+            // this isn't text in the guessed encoding
+            let control =
+                matches!(byte, 0x00..=0x08 | 0x0B | 0x0C | 0x0E..=0x1A | 0x1C..=0x1F | 0x7F);
+            if control || c.is_none() {
+                exact = false;
+            }
+            c.unwrap_or(char::REPLACEMENT_CHARACTER)
+        })
+        .collect();
+    Decoded {
+        text,
+        encoding,
+        exact,
+    }
+}
+
+/// Single-byte encoding of the user's locale
+/// Windows consoles default to the ANSI code page, 1252 for Western locales;
+/// elsewhere a non-UTF-8 locale is taken from LC_ALL / LC_CTYPE / LANG.
+fn locale_encoding() -> TextEncoding {
+    if cfg!(windows) {
+        return TextEncoding::Windows1252;
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_default();
+    encoding_for_locale(&locale)
+}
+
+fn encoding_for_locale(locale: &str) -> TextEncoding {
+    let charset = locale
+        .split_once('.')
+        .map(|(_, charset)| charset.split('@').next().unwrap_or_default())
+        .unwrap_or_default()
+        .to_lowercase()
+        .replace(['-', '_'], "");
+    match charset.as_str() {
+        "cp1252" | "windows1252" => TextEncoding::Windows1252,
+        _ => TextEncoding::Latin1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "Größe: 5 MB" as printed by a Latin-1 tool
+    const LATIN1: &[u8] = b"Gr\xf6\xdfe: 5 MB\n";
+
+    fn utf16le(text: &str, bom: bool) -> Vec<u8> {
+        let mut bytes = if bom { vec![0xFF, 0xFE] } else { Vec::new() };
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        bytes
+    }
+
+    #[test]
+    fn test_utf8_is_passed_through() {
+        let decoded = decode("Größe ✅\n".as_bytes());
+        assert_eq!(decoded.text, "Größe ✅\n");
+        assert_eq!(decoded.encoding, TextEncoding::Utf8);
+        assert!(decoded.exact);
+    }
+
+    #[test]
+    fn test_latin1_fallback() {
+        let decoded = decode_single_byte(LATIN1, TextEncoding::Latin1);
+        assert_eq!(decoded.text, "Größe: 5 MB\n");
+        assert!(decoded.exact);
+        assert_eq!(decode_line(LATIN1).trim_end(), "Größe: 5 MB");
+    }
+
+    #[test]
+    fn test_windows_1252_punctuation() {
+        let decoded = decode_single_byte(b"\x93quoted\x94 \x80 5", TextEncoding::Windows1252);
+        assert_eq!(decoded.text, "“quoted” € 5");
+        assert!(decoded.exact);
+    }
+
+    #[test]
+    fn test_utf16_with_and_without_bom() {
+        let text = "Docker Desktop is starting…\r\n";
+        for bom in [true, false] {
+            let decoded = decode(&utf16le(text, bom));
+            assert_eq!(decoded.text, text, "bom: {}", bom);
+            assert_eq!(decoded.encoding, TextEncoding::Utf16Le);
+            assert!(decoded.exact);
+        }
+
+        let mut be = vec![0xFE, 0xFF];
+        be.extend("ok".encode_utf16().flat_map(u16::to_be_bytes));
+        assert_eq!(decode(&be).text, "ok");
+    }
+
+    #[test]
+    fn test_binary_output_is_not_exact() {
+        let decoded = decode(b"\x1b[2K\r\x00\x01\x02\xff\xfe\x80progress");
+        assert!(!decoded.exact);
+    }
+
+    #[test]
+    fn test_raw_bytes_only_when_requested_and_inexact() {
+        let binary = b"\x00\x01\xff";
+        assert!(OutputText::decode(binary, true).raw_base64.is_some());
+        assert!(OutputText::decode(binary, false).raw_base64.is_none());
+        assert!(OutputText::decode(b"ok", true).raw_base64.is_none());
+    }
+
+    #[test]
+    fn test_encoding_for_locale() {
+        assert_eq!(
+            encoding_for_locale("de_DE.ISO-8859-1"),
+            TextEncoding::Latin1
+        );
+        assert_eq!(
+            encoding_for_locale("en_US.CP1252"),
+            TextEncoding::Windows1252
+        );
+        assert_eq!(encoding_for_locale("C"), TextEncoding::Latin1);
+    }
+}
@@ -0,0 +1,299 @@
+// Service endpoints
+// The ports a service is reachable on depend on the compose file, which
+// people remap, so `get_service_endpoints` asks docker for the host ports the
+// running arbor containers publish rather than the frontend assuming them.
+// Containers are matched by name, like the status cache, so it works whether
+// the app started the stack or adopted it. Bindings on every interface
+// (`0.0.0.0`, `::`) are reported on loopback, which is what the app dials.
+// A `health_url` whose host is a service name, e.g. `http://api:8080/health`,
+// is dialled at the host port that service publishes for 8080.
+
+use crate::docker::CONTAINER_NAME_FILTER;
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::readiness::HealthUrl;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+
+/// Label compose puts the service name in
+const SERVICE_LABEL: &str = "com.docker.compose.service";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointsStatus {
+    Running,
+    /// No arbor container is running, so there are no endpoints
+    Stopped,
+}
+
+/// A container port published on the host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceEndpoint {
+    pub container_port: u16,
+    pub host_port: u16,
+    /// `tcp`, `udp` or `sctp`
+    pub protocol: String,
+    /// `http://<host>:<host_port>` for TCP ports
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceEndpoints {
+    pub status: EndpointsStatus,
+    /// By compose service name
+    pub services: BTreeMap<String, Vec<ServiceEndpoint>>,
+}
+
+impl ServiceEndpoints {
+    /// The host port `service` publishes `container_port` on over TCP
+    pub fn host_port(&self, service: &str, container_port: u16) -> Option<u16> {
+        self.services
+            .get(service)?
+            .iter()
+            .find(|endpoint| {
+                endpoint.container_port == container_port && endpoint.protocol == "tcp"
+            })
+            .map(|endpoint| endpoint.host_port)
+    }
+}
+
+/// One binding from `.NetworkSettings.Ports`
+#[derive(Debug, Deserialize)]
+struct Binding {
+    #[serde(rename = "HostIp", default)]
+    host_ip: String,
+    #[serde(rename = "HostPort")]
+    host_port: String,
+}
+
+/// Endpoints of every running arbor container
+pub async fn inspect(runner: &dyn CommandRunner) -> Result<ServiceEndpoints, String> {
+    let ps = CommandSpec::new("docker").args([
+        "ps",
+        "--filter",
+        CONTAINER_NAME_FILTER,
+        "--filter",
+        "status=running",
+        "--format",
+        "{{.Names}}",
+    ]);
+    let output = runner
+        .output(&ps)
+        .await
+        .map_err(|e| format!("Failed to list containers: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to list containers: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    let names: Vec<String> = output
+        .stdout_text()
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    if names.is_empty() {
+        return Ok(ServiceEndpoints {
+            status: EndpointsStatus::Stopped,
+            services: BTreeMap::new(),
+        });
+    }
+
+    let format = format!(
+        "{{{{.Name}}}}\t{{{{index .Config.Labels \"{}\"}}}}\t{{{{json .NetworkSettings.Ports}}}}",
+        SERVICE_LABEL
+    );
+    let spec = CommandSpec::new("docker")
+        .args(["inspect", "--format", &format])
+        .args(names);
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to inspect containers: {}", e))?;
+    // Exits non-zero if one stopped since it was listed, but still reports
+    // the others
+    Ok(ServiceEndpoints {
+        status: EndpointsStatus::Running,
+        services: parse_endpoints(&output.stdout_text()),
+    })
+}
+
+fn parse_endpoints(text: &str) -> BTreeMap<String, Vec<ServiceEndpoint>> {
+    let mut services: BTreeMap<String, Vec<ServiceEndpoint>> = BTreeMap::new();
+    for line in text.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let name = fields
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .trim_start_matches('/');
+        let service = fields.next().unwrap_or_default().trim();
+        let Ok(ports) = serde_json::from_str::<HashMap<String, Option<Vec<Binding>>>>(
+            fields.next().unwrap_or_default(),
+        ) else {
+            continue;
+        };
+        // Without compose labels, e.g. a container started by hand
+        let service = match service {
+            "" | "<no value>" => name,
+            service => service,
+        };
+        if service.is_empty() {
+            continue;
+        }
+        let endpoints = services.entry(service.to_string()).or_default();
+        for (port, bindings) in ports {
+            let (container_port, protocol) = port.split_once('/').unwrap_or((&port, "tcp"));
+            let Ok(container_port) = container_port.parse() else {
+                continue;
+            };
+            for endpoint in published(container_port, protocol, bindings.unwrap_or_default()) {
+                if !endpoints.contains(&endpoint) {
+                    endpoints.push(endpoint);
+                }
+            }
+        }
+        endpoints.sort_by_key(|endpoint| (endpoint.container_port, endpoint.host_port));
+    }
+    services
+}
+
+/// One endpoint per host port, on loopback where it's bound there or on
+/// every interface
+fn published(container_port: u16, protocol: &str, bindings: Vec<Binding>) -> Vec<ServiceEndpoint> {
+    let mut hosts: BTreeMap<u16, String> = BTreeMap::new();
+    for binding in bindings {
+        let Ok(host_port) = binding.host_port.parse() else {
+            continue;
+        };
+        let host = loopback_or(&binding.host_ip);
+        let current = hosts.entry(host_port).or_insert_with(|| host.clone());
+        if is_loopback(&host) && !is_loopback(current) {
+            *current = host;
+        }
+    }
+    hosts
+        .into_iter()
+        .map(|(host_port, host)| ServiceEndpoint {
+            container_port,
+            host_port,
+            protocol: protocol.to_string(),
+            url: (protocol == "tcp").then(|| format!("http://{}:{}", host, host_port)),
+        })
+        .collect()
+}
+
+/// The host part of a URL for `host_ip`, on loopback for any interface
+fn loopback_or(host_ip: &str) -> String {
+    match host_ip.parse::<IpAddr>() {
+        Ok(ip) if ip.is_unspecified() && ip.is_ipv6() => "[::1]".to_string(),
+        Ok(ip) if ip.is_unspecified() => "127.0.0.1".to_string(),
+        Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+        Ok(ip) => ip.to_string(),
+        Err(_) => "127.0.0.1".to_string(),
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    host == "127.0.0.1" || host == "[::1]"
+}
+
+/// `url` dialled at the host port its service publishes, when its host
+/// names a running service, or else as it is
+pub async fn resolve(runner: &dyn CommandRunner, url: &HealthUrl) -> HealthUrl {
+    let Some((host, port)) = url.address.rsplit_once(':') else {
+        return url.clone();
+    };
+    if host == "localhost" || host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        return url.clone();
+    }
+    let Ok(port) = port.parse() else {
+        return url.clone();
+    };
+    match inspect(runner).await {
+        Ok(endpoints) => match endpoints.host_port(host, port) {
+            Some(host_port) => HealthUrl {
+                address: format!("127.0.0.1:{}", host_port),
+                path: url.path.clone(),
+            },
+            None => url.clone(),
+        },
+        Err(_) => url.clone(),
+    }
+}
+
+/// Where each running service can be reached from this machine; empty and
+/// `stopped` when the stack isn't running
+#[tauri::command]
+pub async fn get_service_endpoints() -> Result<ServiceEndpoints, String> {
+    inspect(&SystemRunner).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    fn runner(ps: &'static str, inspect: &'static str) -> MockRunner {
+        MockRunner::new(move |spec| {
+            let stdout = if spec.args[0] == "ps" { ps } else { inspect };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_endpoints_are_reported_on_loopback() {
+        let runner = runner(
+            "arbor-api-1\narbor-postgres-1\n",
+            concat!(
+                "/arbor-api-1\tapi\t",
+                r#"{"8080/tcp":[{"HostIp":"0.0.0.0","HostPort":"18080"},"#,
+                r#"{"HostIp":"::","HostPort":"18080"}],"9229/tcp":null}"#,
+                "\n/arbor-postgres-1\tpostgres\t",
+                r#"{"5432/tcp":[{"HostIp":"192.168.1.5","HostPort":"5433"}],"#,
+                r#""514/udp":[{"HostIp":"","HostPort":"1514"}]}"#,
+                "\n",
+            ),
+        );
+
+        let endpoints = inspect(&runner).await.unwrap();
+        assert_eq!(endpoints.status, EndpointsStatus::Running);
+        assert_eq!(
+            endpoints.services["api"],
+            [ServiceEndpoint {
+                container_port: 8080,
+                host_port: 18080,
+                protocol: "tcp".to_string(),
+                url: Some("http://127.0.0.1:18080".to_string()),
+            }]
+        );
+        let postgres = &endpoints.services["postgres"];
+        assert_eq!(postgres[0].container_port, 514);
+        assert_eq!(postgres[0].url, None);
+        assert_eq!(postgres[1].url.as_deref(), Some("http://192.168.1.5:5433"));
+
+        let health = HealthUrl::parse("http://api:8080/health").unwrap();
+        assert_eq!(
+            resolve(&runner, &health).await.to_string(),
+            "http://127.0.0.1:18080/health"
+        );
+        let literal = HealthUrl::parse("http://localhost:8080/health").unwrap();
+        assert_eq!(resolve(&runner, &literal).await, literal);
+    }
+
+    #[tokio::test]
+    async fn test_stopped_stack_has_no_endpoints() {
+        let runner = runner("", "");
+        let endpoints = inspect(&runner).await.unwrap();
+        assert_eq!(endpoints.status, EndpointsStatus::Stopped);
+        assert!(endpoints.services.is_empty());
+        assert_eq!(runner.calls().len(), 1, "nothing to inspect");
+    }
+}
@@ -0,0 +1,162 @@
+// Error history
+// A bounded record of recent failures (panicked tasks, failed commands) that
+// would otherwise only reach the console, so the UI and diagnostics can show
+// what went wrong after the fact. Each one also flushes the flight recorder,
+// keeping the debug lines that led up to it.
+
+use crate::flight;
+use crate::messages::Message;
+use crate::{error, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tauri::State;
+
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorRecord {
+    /// RFC 3339 time the error was recorded
+    pub at: String,
+    /// What failed, e.g. the background task's name
+    pub source: String,
+    /// English text; the frontend can render `code` in the user's locale
+    pub message: String,
+    pub code: String,
+    pub params: BTreeMap<String, String>,
+    /// Flight recording written when this was recorded
+    pub flight_recording: Option<String>,
+}
+
+#[derive(Default)]
+struct Entries {
+    records: VecDeque<ErrorRecord>,
+    // How many of the newest records the user hasn't seen
+    unread: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct ErrorHistory {
+    entries: Arc<Mutex<Entries>>,
+    // Set once the app data dir is known
+    flight_dir: Arc<Mutex<Option<PathBuf>>>,
+}
+
+impl ErrorHistory {
+    pub fn set_flight_dir(&self, dir: PathBuf) {
+        *self.flight_dir.lock().unwrap() = Some(dir);
+    }
+
+    pub fn record(&self, source: &str, message: &Message) {
+        self.push(ErrorRecord {
+            at: chrono::Utc::now().to_rfc3339(),
+            source: source.to_string(),
+            message: message.english(),
+            code: message.code.clone(),
+            params: message.params.clone(),
+            flight_recording: None,
+        });
+    }
+
+    /// A command the frontend invoked returned `error`
+    pub fn record_command_error(&self, command: &str, error: &str) {
+        let message = Message::new("command-failed")
+            .with_param("command", command)
+            .with_param("error", error);
+        self.record(command, &message);
+    }
+
+    fn push(&self, mut record: ErrorRecord) {
+        let flight_dir = self.flight_dir.lock().unwrap().clone();
+        if let Some(dir) = flight_dir {
+            match flight::flush(&dir) {
+                Ok(path) => record.flight_recording = Some(path.display().to_string()),
+                Err(e) => warn!("⚠️  {}", e),
+            }
+        }
+        error!("❌ {}: {}", record.source, record.message);
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.records.len() == MAX_ENTRIES {
+            entries.records.pop_front();
+        }
+        entries.records.push_back(record);
+        entries.unread = (entries.unread + 1).min(entries.records.len());
+    }
+
+    /// Recorded errors, oldest first
+    pub fn recent(&self) -> Vec<ErrorRecord> {
+        self.entries.lock().unwrap().records.iter().cloned().collect()
+    }
+
+    pub fn unread_count(&self) -> usize {
+        self.entries.lock().unwrap().unread
+    }
+
+    pub fn mark_read(&self) {
+        self.entries.lock().unwrap().unread = 0;
+    }
+}
+
+#[tauri::command]
+pub async fn get_error_history(
+    history: State<'_, ErrorHistory>,
+) -> Result<Vec<ErrorRecord>, String> {
+    Ok(history.recent())
+}
+
+/// The user has seen the error history
+#[tauri::command]
+pub async fn mark_errors_read(history: State<'_, ErrorHistory>) -> Result<(), String> {
+    history.mark_read();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn failure(i: usize) -> Message {
+        Message::new("task-panicked")
+            .with_param("task", "test")
+            .with_param("message", format!("failure {}", i))
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let history = ErrorHistory::default();
+        for i in 0..MAX_ENTRIES + 10 {
+            history.record("test", &failure(i));
+        }
+
+        let recent = history.recent();
+        assert_eq!(recent.len(), MAX_ENTRIES);
+        assert_eq!(recent[0].params["message"], "failure 10");
+        assert_eq!(history.unread_count(), MAX_ENTRIES);
+
+        history.mark_read();
+        history.record("test", &failure(MAX_ENTRIES + 10));
+        assert_eq!(history.unread_count(), 1);
+    }
+
+    #[test]
+    fn test_errors_reference_a_flight_recording() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let history = ErrorHistory::default();
+        history.set_flight_dir(dir.to_path_buf());
+
+        flight::init();
+        flight::debug("ran make up");
+        history.record_command_error("start_services", "make exited with 2");
+
+        let record = &history.recent()[0];
+        assert_eq!(record.code, "command-failed");
+        let recording = record.flight_recording.as_ref().unwrap();
+        assert!(std::fs::read_to_string(recording)
+            .unwrap()
+            .contains("ran make up"));
+    }
+}
@@ -0,0 +1,141 @@
+// Event emission to the webview
+// Background code emits through the EventSink trait rather than holding an
+// AppHandle directly, so it can be tested with a recording sink.
+// Every event is wrapped as `{ "state_seq": n, "payload": ... }` with n from
+// one app-wide counter, so a frontend that just fetched `get_full_state` can
+// drop the events the snapshot already covers. Error payloads also carry
+// their coded `message`, for the frontend to render in the user's locale.
+// An observer, when one is installed, sees every payload as it goes out.
+
+use crate::messages::{Coded, Message};
+use crate::warn;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tauri::{AppHandle, Emitter, Runtime};
+
+static STATE_SEQ: AtomicU64 = AtomicU64::new(0);
+static OBSERVER: RwLock<Option<Observer>> = RwLock::new(None);
+
+/// Called with the name and unwrapped payload of each emitted event
+pub type Observer = Arc<dyn Fn(&str, &serde_json::Value) + Send + Sync>;
+
+pub trait EventSink: Send + Sync {
+    fn emit_json(&self, event: &str, payload: serde_json::Value);
+}
+
+impl<R: Runtime> EventSink for AppHandle<R> {
+    fn emit_json(&self, event: &str, payload: serde_json::Value) {
+        if let Err(e) = self.emit(event, payload) {
+            warn!("⚠️  Failed to emit {}: {}", event, e);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    state_seq: u64,
+    payload: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<Message>,
+}
+
+/// Sequence number of the latest event emitted
+pub fn state_seq() -> u64 {
+    STATE_SEQ.load(Ordering::SeqCst)
+}
+
+/// Watch every event emitted from now on, on whatever sink
+pub fn set_observer(observer: Observer) {
+    *OBSERVER.write().unwrap() = Some(observer);
+}
+
+/// Serialize a payload and emit it on the sink
+pub fn emit<T: Serialize>(sink: &dyn EventSink, event: &str, payload: &T) {
+    send(sink, event, payload, None);
+}
+
+/// Emit an error payload along with its coded message
+pub fn emit_coded<T: Serialize + Coded>(sink: &dyn EventSink, event: &str, payload: &T) {
+    send(sink, event, payload, Some(payload.message()));
+}
+
+fn send<T: Serialize>(sink: &dyn EventSink, event: &str, payload: &T, message: Option<Message>) {
+    let envelope = Envelope {
+        state_seq: STATE_SEQ.fetch_add(1, Ordering::SeqCst) + 1,
+        payload,
+        message,
+    };
+    match serde_json::to_value(&envelope) {
+        Ok(value) => {
+            crate::flight::trace(format!("📣 {} #{}", event, envelope.state_seq));
+            if let Some(observer) = OBSERVER.read().unwrap().as_ref() {
+                observer(event, &value["payload"]);
+            }
+            sink.emit_json(event, value)
+        }
+        Err(e) => warn!("⚠️  Failed to serialize {} payload: {}", event, e),
+    }
+}
+
+#[cfg(test)]
+pub mod recording {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Sink that keeps every emitted event for later assertions
+    #[derive(Default)]
+    pub struct RecordingSink {
+        events: Mutex<Vec<(String, serde_json::Value)>>,
+    }
+
+    impl RecordingSink {
+        /// Payloads of the events named `event`, unwrapped from their envelope
+        pub fn events_named(&self, event: &str) -> Vec<serde_json::Value> {
+            self.envelopes_named(event)
+                .into_iter()
+                .map(|envelope| envelope["payload"].clone())
+                .collect()
+        }
+
+        pub fn envelopes_named(&self, event: &str) -> Vec<serde_json::Value> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, _)| name == event)
+                .map(|(_, envelope)| envelope.clone())
+                .collect()
+        }
+    }
+
+    impl EventSink for RecordingSink {
+        fn emit_json(&self, event: &str, payload: serde_json::Value) {
+            self.events
+                .lock()
+                .unwrap()
+                .push((event.to_string(), payload));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::recording::RecordingSink;
+    use super::*;
+
+    #[test]
+    fn test_events_carry_increasing_state_seq() {
+        let sink = RecordingSink::default();
+        let before = state_seq();
+        emit(&sink, "first", &"a");
+        emit(&sink, "second", &vec![1, 2]);
+
+        let first = &sink.envelopes_named("first")[0];
+        let second = &sink.envelopes_named("second")[0];
+        assert!(first["state_seq"].as_u64().unwrap() > before);
+        assert!(second["state_seq"].as_u64() > first["state_seq"].as_u64());
+        assert!(state_seq() >= second["state_seq"].as_u64().unwrap());
+        assert_eq!(sink.events_named("second")[0], serde_json::json!([1, 2]));
+    }
+}
@@ -0,0 +1,505 @@
+// Flight recorder
+// Debug output is too noisy to print all the time, but it's what explains a
+// failure after the fact. Logging goes through `tracing`, and a layer keeps
+// every event in a ring buffer of the most recent ones whatever the level;
+// the buffer is written out when a command fails or a service crashes. Only
+// events at or above the current level are printed; `set_log_level` raises
+// it for a bounded time.
+// Printed events also go to a daily `arbor.<date>.log` in the app log dir
+// through a rolling file appender, since a bundled build's stdout goes
+// nowhere; `get_recent_logs` reads them back for the frontend's log viewer.
+// `ARBOR_LOG` is an env filter (e.g. `ARBOR_LOG=debug` or
+// `ARBOR_LOG=arbor::jobs=trace`) bounding what's printed, and a plain level
+// there also sets the level over the `log_level` setting.
+// Log through the `info!`, `warn!` and `error!` macros rather than
+// println!, so the event is kept; they take `tracing`'s fields too.
+
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+use tauri::AppHandle;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{self, EnvFilter, FilterExt, LevelFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+pub const FLIGHT_DIR_NAME: &str = "flight-recordings";
+/// Written into each crash post-mortem bundle
+pub const FLIGHT_FILE_NAME: &str = "flight.log";
+
+const CAPACITY: usize = 2000;
+const MAX_RECORDINGS: usize = 20;
+const MAX_RAISE: Duration = Duration::from_secs(24 * 60 * 60);
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Log files are named `arbor.<date>.log`
+const LOG_FILE_PREFIX: &str = "arbor";
+const LOG_FILE_SUFFIX: &str = "log";
+/// Env filter for what's printed; a plain level sets the level to print at
+pub const LOG_LEVEL_ENV: &str = "ARBOR_LOG";
+/// Daily log files kept, today's included
+const MAX_LOG_FILES: usize = 7;
+const MAX_RECENT_LINES: usize = 5000;
+
+/// Log an event at info
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        ::tracing::info!($($arg)*)
+    };
+}
+
+/// Log an event at warn
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        ::tracing::warn!($($arg)*)
+    };
+}
+
+/// Log an event at error
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        ::tracing::error!($($arg)*)
+    };
+}
+
+/// Log levels, least verbose first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        };
+        f.pad(name)
+    }
+}
+
+impl From<&Level> for LogLevel {
+    fn from(level: &Level) -> Self {
+        match *level {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warn,
+            Level::INFO => LogLevel::Info,
+            Level::DEBUG => LogLevel::Debug,
+            Level::TRACE => LogLevel::Trace,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    at: String,
+    level: LogLevel,
+    message: String,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:<5} {}", self.at, self.level, self.message)
+    }
+}
+
+struct Levels {
+    // Safe mode lowers this to Debug for the session
+    base: LogLevel,
+    // From `set_log_level`, until the deadline passes
+    raised: Option<(LogLevel, Instant)>,
+}
+
+static BUFFER: Mutex<VecDeque<Entry>> = Mutex::new(VecDeque::new());
+static LOG_FILE: Mutex<Option<RollingFileAppender>> = Mutex::new(None);
+static LEVELS: Mutex<Levels> = Mutex::new(Levels {
+    base: LogLevel::Info,
+    raised: None,
+});
+
+/// Keeps every event in the ring buffer, whatever the level
+struct FlightLayer;
+
+impl<S: Subscriber> Layer<S> for FlightLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let entry = Entry {
+            at: chrono::Utc::now().format(TIMESTAMP_FORMAT).to_string(),
+            level: event.metadata().level().into(),
+            message: fields.into_message(),
+        };
+
+        let mut buffer = BUFFER.lock().unwrap();
+        if buffer.len() == CAPACITY {
+            buffer.pop_front();
+        }
+        buffer.push_back(entry);
+    }
+}
+
+/// An event's message, then its other fields as `name=value`
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Fields {
+    fn push(&mut self, field: &Field, value: impl fmt::Display) {
+        use std::fmt::Write as _;
+        let _ = match field.name() {
+            "message" => write!(self.message, "{}", value),
+            name => write!(self.rest, " {}={}", name, value),
+        };
+    }
+
+    fn into_message(self) -> String {
+        self.message + &self.rest
+    }
+}
+
+impl Visit for Fields {
+    // Quoted like the log file's fields, except the message itself
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.push(field, value),
+            _ => self.push(field, format_args!("{:?}", value)),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, format_args!("{:?}", value));
+    }
+}
+
+/// Whether the current level lets an event through to the console and file
+fn printed(metadata: &Metadata<'_>) -> bool {
+    LogLevel::from(metadata.level()) <= current_level()
+}
+
+/// The buffer's timestamps, for the log file's lines
+struct Timestamp;
+
+impl FormatTime for Timestamp {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        write!(w, "{}", chrono::Utc::now().format(TIMESTAMP_FORMAT))
+    }
+}
+
+/// Writes to the appender `open_log_file` set up, or nowhere before that
+struct LogFileWriter;
+
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Send `tracing` events to the buffer, the console and the log file; only
+/// the first call does anything
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let env = EnvFilter::builder()
+            .with_default_directive(LevelFilter::TRACE.into())
+            .with_env_var(LOG_LEVEL_ENV)
+            .from_env_lossy();
+        let console = tracing_subscriber::fmt::layer()
+            .without_time()
+            .with_level(false)
+            .with_target(false)
+            .with_writer(io::stderr.with_max_level(Level::WARN).or_else(io::stdout));
+        let file = tracing_subscriber::fmt::layer()
+            .with_target(false)
+            .with_timer(Timestamp)
+            .with_writer(|| LogFileWriter);
+        let output = console
+            .and_then(file)
+            .with_filter(filter::filter_fn(printed).and(env));
+        let subscriber = tracing_subscriber::registry().with(FlightLayer).with(output);
+        if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+            eprintln!("⚠️  Failed to set up logging: {}", e);
+        }
+    });
+}
+
+pub fn debug(message: impl Into<String>) {
+    tracing::debug!("{}", message.into());
+}
+
+pub fn trace(message: impl Into<String>) {
+    tracing::trace!("{}", message.into());
+}
+
+/// The level lines are printed at, with an expired raise reverted
+pub fn current_level() -> LogLevel {
+    let mut levels = LEVELS.lock().unwrap();
+    match levels.raised {
+        Some((level, until)) if Instant::now() < until => level.max(levels.base),
+        Some(_) => {
+            levels.raised = None;
+            levels.base
+        }
+        None => levels.base,
+    }
+}
+
+/// Level kept until changed again, under any temporary raise
+pub fn set_base_level(level: LogLevel) {
+    LEVELS.lock().unwrap().base = level;
+}
+
+/// The level to print at given the `log_level` setting: `ARBOR_LOG`'s if set
+pub fn configured_level(setting: LogLevel) -> LogLevel {
+    std::env::var(LOG_LEVEL_ENV)
+        .ok()
+        .and_then(|value| parse_level(&value))
+        .unwrap_or(setting)
+}
+
+fn parse_level(value: &str) -> Option<LogLevel> {
+    serde_json::from_value(serde_json::Value::String(value.trim().to_lowercase())).ok()
+}
+
+/// Whether `name` is one of the daily log files
+pub fn is_log_file(name: &str) -> bool {
+    name.strip_prefix(LOG_FILE_PREFIX)
+        .and_then(|rest| rest.strip_suffix(LOG_FILE_SUFFIX))
+        .is_some_and(|date| date.len() > 2 && date.starts_with('.') && date.ends_with('.'))
+}
+
+fn appender(dir: &Path) -> Result<RollingFileAppender, String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create log directory: {}", e))?;
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix(LOG_FILE_SUFFIX)
+        .max_log_files(MAX_LOG_FILES)
+        .build(dir)
+        .map_err(|e| format!("Failed to open log file in {:?}: {}", dir, e))
+}
+
+/// Append printed events to the day's log file in `dir` from now on
+pub fn open_log_file(dir: &Path) -> Result<(), String> {
+    *LOG_FILE.lock().unwrap() = Some(appender(dir)?);
+    Ok(())
+}
+
+/// The log files in `dir`, oldest first
+fn log_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to list {:?}: {}", dir, e)),
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| is_log_file(&entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect();
+    // Dated names sort oldest first
+    files.sort();
+    Ok(files)
+}
+
+/// The last `lines` lines of the logs in `dir`, oldest first, reaching into
+/// earlier days' files when today's is short
+pub fn recent_lines(dir: &Path, lines: usize) -> Result<Vec<String>, String> {
+    let mut recent = VecDeque::with_capacity(lines);
+    for file in log_files(dir)?.iter().rev() {
+        if recent.len() == lines {
+            break;
+        }
+        let contents = std::fs::read(file)
+            .map_err(|e| format!("Failed to read {:?}: {}", file, e))?;
+        for line in String::from_utf8_lossy(&contents).lines().rev() {
+            if recent.len() == lines {
+                break;
+            }
+            recent.push_front(line.to_string());
+        }
+    }
+    Ok(recent.into())
+}
+
+/// Buffered lines, oldest first
+#[cfg(test)]
+pub fn buffered() -> Vec<String> {
+    BUFFER.lock().unwrap().iter().map(ToString::to_string).collect()
+}
+
+fn raise_level(level: LogLevel, duration: Duration) {
+    LEVELS.lock().unwrap().raised = Some((level, Instant::now() + duration));
+}
+
+/// Write the buffered lines, oldest first, to `path`
+pub fn write_to(path: &Path) -> Result<(), String> {
+    let contents: String = BUFFER
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|entry| format!("{}\n", entry))
+        .collect();
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write flight recording: {}", e))
+}
+
+/// Write the buffer to a new timestamped file in `dir`, keeping the newest few
+pub fn flush(dir: &Path) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create flight recording directory: {}", e))?;
+    let path = dir.join(format!(
+        "flight-{}.log",
+        chrono::Utc::now().format(TIMESTAMP_FORMAT)
+    ));
+    write_to(&path)?;
+
+    let mut recordings: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to list flight recordings: {}", e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+    // Timestamped names sort oldest first
+    recordings.sort();
+    let excess = recordings.len().saturating_sub(MAX_RECORDINGS);
+    for old in &recordings[..excess] {
+        let _ = std::fs::remove_file(old);
+    }
+    Ok(path)
+}
+
+/// Print at `level` or more verbose for `duration_secs`, then revert
+#[tauri::command]
+pub async fn set_log_level(level: LogLevel, duration_secs: u64) -> Result<String, String> {
+    let duration = Duration::from_secs(duration_secs);
+    if duration.is_zero() || duration > MAX_RAISE {
+        return Err(format!(
+            "Log level duration must be between 1 and {} seconds",
+            MAX_RAISE.as_secs()
+        ));
+    }
+    raise_level(level, duration);
+    let until = chrono::Utc::now() + chrono::Duration::seconds(duration_secs as i64);
+    crate::info!(
+        "🔊 Log level raised to {} until {}",
+        level,
+        until.to_rfc3339()
+    );
+    Ok(until.to_rfc3339())
+}
+
+/// The last `lines` lines of the log file, oldest first, for a log viewer
+#[tauri::command]
+pub async fn get_recent_logs(app_handle: AppHandle, lines: usize) -> Result<Vec<String>, String> {
+    let dir = paths::log_dir(&app_handle)?;
+    recent_lines(&dir, lines.min(MAX_RECENT_LINES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    // The recorder is process-wide; one test owns the level changes
+    #[test]
+    fn test_raised_level_reverts() {
+        assert_eq!(current_level(), LogLevel::Info);
+
+        raise_level(LogLevel::Trace, Duration::from_secs(60));
+        assert_eq!(current_level(), LogLevel::Trace);
+
+        raise_level(LogLevel::Debug, Duration::ZERO);
+        assert_eq!(current_level(), LogLevel::Info);
+        assert!(LEVELS.lock().unwrap().raised.is_none());
+    }
+
+    #[test]
+    fn test_log_files_roll_daily_and_read_back() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("arbor.2020-01-01.log"), "first\nsecond\n").unwrap();
+        std::fs::write(dir.join("arbor.log.1"), "not a daily file\n").unwrap();
+        let mut file = appender(dir).unwrap();
+        file.write_all(b"third\n").unwrap();
+        file.flush().unwrap();
+
+        let files = log_files(dir).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files[1].ends_with(format!("arbor.{}.log", chrono::Utc::now().format("%Y-%m-%d"))));
+        assert_eq!(recent_lines(dir, 2).unwrap(), ["second", "third"]);
+        assert_eq!(recent_lines(dir, 10).unwrap(), ["first", "second", "third"]);
+        assert!(recent_lines(dir, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_structured_fields_are_kept() {
+        init();
+        crate::info!(job = "job-1", attempt = 2, "flight fields marker");
+
+        assert!(buffered()
+            .iter()
+            .any(|line| line.ends_with("INFO  flight fields marker job=\"job-1\" attempt=2")));
+    }
+
+    #[test]
+    fn test_levels_are_parsed_from_the_environment() {
+        assert_eq!(parse_level("DEBUG"), Some(LogLevel::Debug));
+        assert_eq!(parse_level(" warn "), Some(LogLevel::Warn));
+        assert_eq!(parse_level("loud"), None);
+    }
+
+    #[test]
+    fn test_flush_writes_buffered_lines() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        init();
+        trace("flight test marker");
+
+        let path = flush(dir).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents
+            .lines()
+            .any(|line| line.contains("TRACE") && line.ends_with("flight test marker")));
+        assert!(BUFFER.lock().unwrap().len() <= CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level_bounds_duration() {
+        assert!(set_log_level(LogLevel::Debug, 0).await.is_err());
+        assert!(set_log_level(LogLevel::Debug, MAX_RAISE.as_secs() + 1)
+            .await
+            .is_err());
+    }
+}
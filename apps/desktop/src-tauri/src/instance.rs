@@ -0,0 +1,336 @@
+// Single instance
+// Two copies of the app would both run `make up` and both think they own
+// the stack, so the first launch claims `instance.lock` in the data dir and
+// listens on a loopback port named in it. A later launch finds the lock,
+// hands its arguments to that port and exits; the first focuses its window
+// and emits `second-instance` with them, for deep links to build on.
+//
+// The lock is removed on a clean exit. One left by a killed process is
+// stale when its PID is gone or nothing answers on its port, and the next
+// launch takes it over. The lock also holds a random token a launch must
+// send, so other local users can't poke the app through the port.
+
+use crate::{info, warn};
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+pub const LOCK_FILE_NAME: &str = "instance.lock";
+/// Emitted with a SecondInstance when the app is launched again
+pub const SECOND_INSTANCE_EVENT: &str = "second-instance";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a lock may stay empty while the launch that made it writes it
+const WRITE_GRACE: Duration = Duration::from_secs(2);
+const MAX_MESSAGE_LEN: u64 = 64 * 1024;
+const ACK: &str = "ok";
+
+/// What `instance.lock` holds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct LockInfo {
+    pid: u32,
+    port: u16,
+    token: String,
+    started_at: String,
+}
+
+/// A later launch, as forwarded to the running app
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecondInstance {
+    /// Arguments after the program name
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+}
+
+impl SecondInstance {
+    pub fn current() -> Self {
+        Self {
+            args: std::env::args().skip(1).collect(),
+            cwd: std::env::current_dir()
+                .ok()
+                .map(|cwd| cwd.display().to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+    token: String,
+    launch: SecondInstance,
+}
+
+/// What claiming the lock came to
+pub enum Claim {
+    /// This is the only instance; serve the listener until exit
+    Primary(InstanceLock),
+    /// Another instance is running and has the arguments
+    Forwarded,
+}
+
+/// The claimed lock, and the listener later launches connect to
+pub struct InstanceLock {
+    info: LockInfo,
+    listener: TcpListener,
+}
+
+/// Claim the lock in `dir`, or hand `launch` to the instance holding it
+pub fn claim(dir: &Path, launch: &SecondInstance) -> io::Result<Claim> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(LOCK_FILE_NAME);
+    let deadline = SystemTime::now() + WRITE_GRACE;
+    loop {
+        match read_lock(&path) {
+            Ok(Some(existing)) => {
+                match forward(&existing, launch) {
+                    Ok(()) => return Ok(Claim::Forwarded),
+                    Err(e) => {
+                        warn!(
+                            "⚠️  Taking over the lock of an instance that's gone (PID {}): {}",
+                            existing.pid, e
+                        );
+                        remove_if_unchanged(&path, &existing);
+                    }
+                }
+                continue;
+            }
+            // Mid-write by a launch at the same moment, or left corrupt
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                if SystemTime::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(50));
+                } else {
+                    warn!("⚠️  Replacing an unreadable {}: {}", LOCK_FILE_NAME, e);
+                    let _ = std::fs::remove_file(&path);
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+            Ok(None) => {}
+        }
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let info = LockInfo {
+            pid: std::process::id(),
+            port: listener.local_addr()?.port(),
+            token: format!("{:016x}", rand::rng().random::<u64>()),
+            started_at: Utc::now().to_rfc3339(),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(serde_json::to_string(&info)?.as_bytes())?;
+                file.sync_all()?;
+                return Ok(Claim::Primary(InstanceLock { info, listener }));
+            }
+            // Another launch got there first; forward to it instead
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The lock at `path`, if there is one; `InvalidData` if it can't be read yet
+fn read_lock(path: &Path) -> io::Result<Option<LockInfo>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove a stale lock, unless another launch has replaced it meanwhile
+fn remove_if_unchanged(path: &Path, stale: &LockInfo) {
+    if let Ok(Some(current)) = read_lock(path) {
+        if &current == stale {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Send `launch` to the instance holding `lock`, waiting for it to answer
+fn forward(lock: &LockInfo, launch: &SecondInstance) -> io::Result<()> {
+    if !pid_alive(lock.pid) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the process isn't running",
+        ));
+    }
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, lock.port));
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    stream.set_write_timeout(Some(REPLY_TIMEOUT))?;
+    let message = Message {
+        token: lock.token.clone(),
+        launch: launch.clone(),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&message)?)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.trim() == ACK {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "something else answered on its port",
+        ))
+    }
+}
+
+#[cfg(unix)]
+fn pid_alive(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    // EPERM means it exists but belongs to someone else
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Left to the port check where there's no cheap PID probe
+#[cfg(not(unix))]
+fn pid_alive(_pid: u32) -> bool {
+    true
+}
+
+impl InstanceLock {
+    /// Hand every later launch to `on_launch` until the app exits
+    pub async fn serve(self, mut on_launch: impl FnMut(SecondInstance)) {
+        let InstanceLock { info, listener } = self;
+        let listener = match listener
+            .set_nonblocking(true)
+            .and_then(|_| tokio::net::TcpListener::from_std(listener))
+        {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("⚠️  Later launches won't reach this one: {}", e);
+                return;
+            }
+        };
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("⚠️  Failed to accept on the instance port: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let received = receive(stream, &info.token, &mut on_launch);
+            match tokio::time::timeout(REPLY_TIMEOUT, received).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("⚠️  Ignored a connection to the instance port: {}", e),
+                Err(_) => warn!("⚠️  Ignored a connection to the instance port: timed out"),
+            }
+        }
+    }
+}
+
+/// Remove the lock in `dir` if this process holds it, on a clean exit
+pub fn release(dir: &Path) {
+    let path = dir.join(LOCK_FILE_NAME);
+    if let Ok(Some(lock)) = read_lock(&path) {
+        if lock.pid == std::process::id() {
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
+
+/// Read a launch from `stream`, and answer once it's been handled
+async fn receive(
+    mut stream: tokio::net::TcpStream,
+    token: &str,
+    on_launch: &mut impl FnMut(SecondInstance),
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut line = String::new();
+    tokio::io::BufReader::new(reader.take(MAX_MESSAGE_LEN))
+        .read_line(&mut line)
+        .await?;
+    let message: Message =
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if message.token != token {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "wrong token",
+        ));
+    }
+    info!("🪟 Launched again with {:?}", message.launch.args);
+    on_launch(message.launch);
+    writer.write_all(format!("{}\n", ACK).as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::sync::{Arc, Mutex};
+
+    fn launch(args: &[&str]) -> SecondInstance {
+        SecondInstance {
+            args: args.iter().map(ToString::to_string).collect(),
+            cwd: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_second_launch_is_forwarded_to_the_first() {
+        let tmp = temp_dir();
+        let dir = tmp.path().join("instance");
+        let Claim::Primary(lock) = claim(&dir, &launch(&[])).unwrap() else {
+            panic!("the first launch should claim the lock");
+        };
+        assert!(dir.join(LOCK_FILE_NAME).exists());
+        let launches = Arc::new(Mutex::new(Vec::new()));
+        let received = launches.clone();
+        tokio::spawn(lock.serve(move |launch| received.lock().unwrap().push(launch)));
+
+        let second_dir = dir.clone();
+        let second =
+            tokio::task::spawn_blocking(move || claim(&second_dir, &launch(&["arbor://open/api"])));
+        assert!(matches!(second.await.unwrap().unwrap(), Claim::Forwarded));
+        assert_eq!(*launches.lock().unwrap(), [launch(&["arbor://open/api"])]);
+
+        release(&dir);
+        assert!(!dir.join(LOCK_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_stale_lock_is_taken_over() {
+        let tmp = temp_dir();
+        let dir = tmp.path().join("instance");
+        std::fs::create_dir_all(&dir).unwrap();
+        // A port nothing listens on any more
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let stale = LockInfo {
+            pid: std::process::id(),
+            port,
+            token: "0".to_string(),
+            started_at: "2026-01-01T00:00:00+00:00".to_string(),
+        };
+        std::fs::write(
+            dir.join(LOCK_FILE_NAME),
+            serde_json::to_string(&stale).unwrap(),
+        )
+        .unwrap();
+
+        let Claim::Primary(lock) = claim(&dir, &launch(&[])).unwrap() else {
+            panic!("a stale lock should be taken over");
+        };
+        assert_ne!(lock.info, stale);
+        assert_eq!(
+            read_lock(&dir.join(LOCK_FILE_NAME)).unwrap(),
+            Some(lock.info)
+        );
+    }
+}
@@ -0,0 +1,1939 @@
+// Detached jobs
+// Long-running operations (setup targets, image pulls, updates, support
+// bundles, migrations, data backups) run as jobs:
+// `start_job` returns an id right away, the work runs as a managed
+// background task emitting `job-progress` events, and the outcome is kept so
+// a reloaded webview can pick it up again with `get_job_status`.
+// `cancel_job` stops a job between steps, or mid-step by killing the command
+// it's waiting on, and then undoes whatever the cancelled step left behind.
+// Setup targets stream what they print as `job-output` batches, keyed by
+// the job id and acked with `ack_job_output`, and are killed once they run
+// past `setup_command_timeout_secs`. The last lines are kept on the job, so
+// a target that was cancelled or timed out still shows how far it got.
+// A summary of every job is kept in `jobs.json` so the outcome of a job
+// survives a restart; jobs the previous process never finished show up as
+// interrupted, and pulls can be resumed.
+// `spawn_task`, `cancel_task`, `get_task_status` and `list_tasks` are the
+// same commands under their task names, and each `job-progress` comes with
+// a slimmer `task-progress` `{ id, percent, message }`.
+
+use crate::backup::{self, DataBackup, DataRestore};
+use crate::batch::{BatchLimits, EventBatcher};
+use crate::cleanup;
+use crate::compat::{VersionGate, VersionOverride};
+use crate::command_log::CommandLogs;
+use crate::diagnostics::SystemInfo;
+use crate::doctor::{self, DoctorEnv};
+use crate::events::{self, EventSink};
+use crate::keyring::{self, Keychain, KeyringStatus, SecretStore};
+use crate::lifecycle::{Lifecycle, StopInitiator, StopReason};
+use crate::migrations::{self, MigrationRun};
+use crate::network::{self, DownloadCheck, DownloadDecision};
+use crate::paths;
+use crate::process::{CommandOutput, CommandRunner, CommandSpec, OutputLine, OutputStream};
+use crate::pull;
+use crate::services::{self, ComposeSetup};
+use crate::settings::{MeteredDownloads, SettingsStore};
+use crate::smoke::{self, SmokeEnv};
+use crate::support::{self, BundleEnv};
+use crate::tasks::TaskManager;
+use crate::tools::LocatedTool;
+use crate::trust::TrustStore;
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+/// Emitted with the job on every progress step and when it finishes
+pub const JOB_PROGRESS_EVENT: &str = "job-progress";
+/// Emitted with a `TaskProgress` alongside each `job-progress`
+pub const TASK_PROGRESS_EVENT: &str = "task-progress";
+
+pub const JOBS_FILE_NAME: &str = "jobs.json";
+/// Asks whether to download on a metered connection; answered with `answer_metered_prompt`
+pub const METERED_PROMPT_EVENT: &str = "metered-download-prompt";
+
+/// Emitted with batches of `{ source: stream, seq, data: line }` entries a
+/// setup target printed; the batch's `stream` is the job id
+pub const JOB_OUTPUT_EVENT: &str = "job-output";
+
+/// Job records kept across restarts
+const MAX_JOB_HISTORY: usize = 100;
+/// Output lines kept on a job
+const OUTPUT_TAIL_LINES: usize = 50;
+pub const SETUP_TIMEOUT_RANGE: RangeInclusive<u64> = 10..=86_400;
+
+/// What a job does, with its parameters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "params", rename_all = "snake_case")]
+pub enum JobKind {
+    /// `make <target> NAME=value...` in the project root
+    SetupCommand {
+        target: String,
+        #[serde(default)]
+        vars: BTreeMap<String, String>,
+        #[serde(default)]
+        raw_output: bool,
+    },
+    PullImages,
+    /// Pull, then recreate whatever changed
+    UpdateServices,
+    /// Check the machine can run Arbor at all, with throwaway resources
+    SmokeTest,
+    /// Zip up logs, diagnostics and the compose config into `dest_dir`
+    SupportBundle { dest_dir: String },
+    /// `make db-migrate`, reporting which migrations it applied
+    Migrations,
+    /// Archive the profile's volumes to `dest_path`, a file or a directory
+    BackupData { dest_path: String },
+    /// Replace the profile's volumes with a backup; confirmed through
+    /// `restore_data`, so `start_job` refuses it
+    RestoreData { archive_path: String },
+}
+
+impl JobKind {
+    /// Parse the `kind` / `params` pair sent by the webview
+    pub fn parse(kind: &str, params: Option<Value>) -> Result<Self, String> {
+        let mut request = serde_json::json!({ "kind": kind });
+        if let Some(params) = params {
+            request["params"] = params;
+        }
+        serde_json::from_value(request).map_err(|e| format!("Invalid job {:?}: {}", kind, e))
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            JobKind::SetupCommand { .. } => "setup_command",
+            JobKind::PullImages => "pull_images",
+            JobKind::UpdateServices => "update_services",
+            JobKind::SmokeTest => "smoke_test",
+            JobKind::SupportBundle { .. } => "support_bundle",
+            JobKind::Migrations => "migrations",
+            JobKind::BackupData { .. } => "backup_data",
+            JobKind::RestoreData { .. } => "restore_data",
+        }
+    }
+
+    /// Whether it changes the database schema, or needs it to hold still;
+    /// only one such job runs at a time
+    fn migrates(&self) -> bool {
+        match self {
+            JobKind::Migrations | JobKind::BackupData { .. } | JobKind::RestoreData { .. } => true,
+            JobKind::SetupCommand { target, .. } => {
+                migrations::MIGRATING_TARGETS.contains(&target.as_str())
+            }
+            _ => false,
+        }
+    }
+
+    /// Jobs that can safely be started again after an interruption
+    fn resumable(name: &str) -> bool {
+        // Docker keeps the layers it already pulled
+        name == "pull_images"
+    }
+
+    /// Stable FNV-1a hash of the parameters, so records don't keep them
+    fn params_hash(&self) -> String {
+        let params = serde_json::to_string(self).unwrap_or_default();
+        let hash = params.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        format!("{:016x}", hash)
+    }
+
+    fn total_steps(&self) -> usize {
+        match self {
+            JobKind::UpdateServices | JobKind::SupportBundle { .. } => 2,
+            JobKind::SmokeTest => smoke::TOTAL_STEPS,
+            JobKind::Migrations => 3,
+            JobKind::BackupData { .. } | JobKind::RestoreData { .. } => 4,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Succeeded {
+        result: Value,
+    },
+    Failed {
+        error: String,
+    },
+    /// Killed after running for `timeout_secs`
+    TimedOut {
+        timeout_secs: u64,
+    },
+    Cancelled {
+        /// Step that was running when the job was cancelled
+        at_step: usize,
+        /// What was undone, if the cancelled step left anything behind
+        cleanup: Option<String>,
+        cleanup_error: Option<String>,
+    },
+}
+
+/// A job as kept across restarts and listed by `list_jobs`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub kind: String,
+    pub params_hash: String,
+    /// `running`, `succeeded`, `failed`, `timed_out`, `cancelled` or
+    /// `interrupted`
+    pub state: String,
+    pub summary: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    #[serde(default)]
+    pub resumable: bool,
+    /// Job started by `resume_job` to finish this one
+    #[serde(default)]
+    pub resumed_as: Option<String>,
+    /// The metered connection check of a download
+    #[serde(default)]
+    pub network: Option<DownloadCheck>,
+}
+
+impl JobRecord {
+    fn of(job: &Job) -> Self {
+        let (state, summary) = match &job.state {
+            JobState::Running => ("running", job.progress.message.clone()),
+            JobState::Succeeded { result } => {
+                let summary = match result.get("output_file").and_then(Value::as_str) {
+                    Some(file) => format!("Output saved to {}", file),
+                    None => "Succeeded".to_string(),
+                };
+                ("succeeded", summary)
+            }
+            JobState::Failed { error } => (
+                "failed",
+                error.lines().next().unwrap_or_default().to_string(),
+            ),
+            JobState::TimedOut { timeout_secs } => (
+                "timed_out",
+                format!("Stopped after running for {}s", timeout_secs),
+            ),
+            JobState::Cancelled {
+                at_step, cleanup, ..
+            } => {
+                let mut summary =
+                    format!("Cancelled at step {}/{}", at_step, job.progress.total_steps);
+                if let Some(cleanup) = cleanup {
+                    summary.push_str(&format!("; {}", cleanup));
+                }
+                ("cancelled", summary)
+            }
+        };
+        JobRecord {
+            id: job.id.clone(),
+            kind: job.kind.name().to_string(),
+            params_hash: job.kind.params_hash(),
+            state: state.to_string(),
+            summary,
+            started_at: job.started_at.clone(),
+            finished_at: job.finished_at.clone(),
+            resumable: false,
+            resumed_as: None,
+            network: job.network.clone(),
+        }
+    }
+
+    /// A record left `running` by a process that's gone
+    fn interrupted(mut self) -> Self {
+        if self.state == "running" {
+            self.summary = format!("Interrupted by an app restart ({})", self.summary);
+            self.state = "interrupted".to_string();
+            self.resumable = JobKind::resumable(&self.kind);
+        }
+        self
+    }
+}
+
+/// `jobs.json` in the app data dir
+pub struct JobStore {
+    path: PathBuf,
+}
+
+impl JobStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn load(&self) -> Vec<JobRecord> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("⚠️  Ignoring invalid job history {:?}: {}", self.path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Written to a temporary file and renamed over, like the settings, so
+    /// a crash midway never leaves a truncated history
+    fn save(&self, records: &[JobRecord]) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create job history directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(records).map_err(|e| e.to_string())?;
+        let temporary = self.path.with_extension("json.tmp");
+        std::fs::write(&temporary, contents)
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| format!("Failed to save job history: {}", e))
+    }
+}
+
+/// Why a job stopped early
+enum JobError {
+    Failed(String),
+    TimedOut(Duration),
+    Cancelled,
+}
+
+impl From<String> for JobError {
+    fn from(error: String) -> Self {
+        JobError::Failed(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JobProgress {
+    pub step: usize,
+    pub total_steps: usize,
+    pub message: String,
+}
+
+/// Payload of `task-progress`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskProgress {
+    pub id: String,
+    pub percent: u8,
+    pub message: String,
+}
+
+impl TaskProgress {
+    fn of(job: &Job) -> Self {
+        let JobProgress {
+            step, total_steps, ..
+        } = job.progress;
+        let percent = match job.state {
+            JobState::Succeeded { .. } => 100,
+            _ if total_steps == 0 => 0,
+            _ => (step.min(total_steps) * 100 / total_steps) as u8,
+        };
+        let message = match job.state {
+            JobState::Running => job.progress.message.clone(),
+            _ => JobRecord::of(job).summary,
+        };
+        Self {
+            id: job.id.clone(),
+            percent,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Job {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: JobKind,
+    #[serde(flatten)]
+    pub state: JobState,
+    pub progress: JobProgress,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub network: Option<DownloadCheck>,
+    /// The last lines a setup target printed
+    #[serde(skip_serializing_if = "VecDeque::is_empty")]
+    pub output_tail: VecDeque<OutputLine>,
+}
+
+/// Payload of `metered-download-prompt`
+#[derive(Debug, Clone, Serialize)]
+pub struct MeteredPrompt {
+    pub job_id: String,
+    pub kind: String,
+    pub connection: network::ConnectionInfo,
+}
+
+/// What a job needs from the app to run
+pub struct JobEnv {
+    pub runner: Arc<dyn CommandRunner>,
+    pub project_root: PathBuf,
+    pub setup: ComposeSetup,
+    pub logs: CommandLogs,
+    pub downloads_on_metered: MeteredDownloads,
+    pub assume_metered: bool,
+    pub setup_timeout: Duration,
+    pub data_dir: PathBuf,
+    pub secrets: Arc<dyn SecretStore>,
+    pub tools: Vec<LocatedTool>,
+    pub lifecycle: Lifecycle,
+    pub log_dir: PathBuf,
+    pub system: SystemInfo,
+    pub version_override: Option<VersionOverride>,
+}
+
+impl JobEnv {
+    pub fn for_app(app_handle: &AppHandle) -> Result<Self, String> {
+        let project_root = crate::find_project_root(app_handle)?;
+        let settings = app_handle.state::<SettingsStore>().get();
+        app_handle
+            .state::<TrustStore>()
+            .check(app_handle, &project_root, &settings)?;
+        let env = Self::with_root(app_handle, project_root)?;
+        env.setup.prepare()?;
+        Ok(env)
+    }
+
+    /// For jobs that don't run the project's files, so it needn't be found
+    pub fn without_project(app_handle: &AppHandle) -> Result<Self, String> {
+        let project_root = crate::find_project_root(app_handle).unwrap_or_default();
+        Self::with_root(app_handle, project_root)
+    }
+
+    fn with_root(app_handle: &AppHandle, project_root: PathBuf) -> Result<Self, String> {
+        let data_dir = paths::data_dir(app_handle)?;
+        let settings = app_handle.state::<SettingsStore>().get();
+        let setup = ComposeSetup::from_settings(&settings, &project_root, &data_dir);
+
+        Ok(Self {
+            runner: Arc::new(crate::process::SystemRunner),
+            project_root,
+            setup,
+            logs: CommandLogs::for_app(app_handle)?,
+            downloads_on_metered: settings.downloads_on_metered,
+            assume_metered: settings.assume_metered,
+            setup_timeout: Duration::from_secs(settings.setup_command_timeout_secs),
+            data_dir,
+            secrets: app_handle.state::<Keychain>().store(),
+            tools: crate::tools::located(),
+            lifecycle: app_handle.state::<Lifecycle>().inner().clone(),
+            log_dir: paths::log_dir(app_handle)?,
+            system: SystemInfo::collect(&app_handle.state::<KeyringStatus>())
+                .with_settings(&settings),
+            version_override: app_handle.state::<VersionGate>().overridden(),
+        })
+    }
+
+    fn smoke_env(&self) -> SmokeEnv<'_> {
+        SmokeEnv {
+            runner: self.runner.as_ref(),
+            secrets: &self.secrets,
+            data_dir: &self.data_dir,
+            tools: &self.tools,
+        }
+    }
+}
+
+// A prompt shown to the frontend and where its answer goes
+type PendingPrompt = (MeteredPrompt, oneshot::Sender<bool>);
+
+/// Live and finished jobs of this session
+#[derive(Clone)]
+pub struct JobManager {
+    jobs: Arc<Mutex<BTreeMap<String, Job>>>,
+    sink: Arc<dyn EventSink>,
+    tasks: TaskManager,
+    next_id: Arc<AtomicU64>,
+    // Cancellation tokens of the running jobs
+    running: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    // Records from earlier sessions, and where they're kept
+    history: Arc<Mutex<Vec<JobRecord>>>,
+    store: Option<Arc<JobStore>>,
+    // Jobs waiting for an answer to a metered download prompt
+    prompts: Arc<Mutex<HashMap<String, PendingPrompt>>>,
+    // Output streams of the running setup targets
+    outputs: Arc<Mutex<HashMap<String, Arc<EventBatcher<String>>>>>,
+    // Held by `try_start` from its check until the job is listed
+    starting: Arc<Mutex<()>>,
+}
+
+impl JobManager {
+    pub fn new(sink: Arc<dyn EventSink>, tasks: TaskManager) -> Self {
+        Self {
+            jobs: Arc::new(Mutex::new(BTreeMap::new())),
+            sink,
+            tasks,
+            next_id: Arc::new(AtomicU64::new(1)),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            history: Arc::new(Mutex::new(Vec::new())),
+            store: None,
+            prompts: Arc::new(Mutex::new(HashMap::new())),
+            outputs: Arc::new(Mutex::new(HashMap::new())),
+            starting: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Keep job records in `store`, loading the ones of earlier sessions
+    pub fn with_store(mut self, store: JobStore) -> Self {
+        let history: Vec<JobRecord> = store
+            .load()
+            .into_iter()
+            .map(JobRecord::interrupted)
+            .collect();
+        *self.history.lock().unwrap() = history;
+        self.store = Some(Arc::new(store));
+        self.persist();
+        self
+    }
+
+    fn persist(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        if let Err(e) = store.save(&self.records()) {
+            warn!("⚠️  {}", e);
+        }
+    }
+
+    /// Records of earlier sessions and of this one, oldest first
+    pub fn records(&self) -> Vec<JobRecord> {
+        let mut records = self.history.lock().unwrap().clone();
+        records.extend(self.list().iter().map(JobRecord::of));
+        let excess = records.len().saturating_sub(MAX_JOB_HISTORY);
+        records.drain(..excess);
+        records
+    }
+
+    /// Start an interrupted job's work again, returning the new job's id
+    pub fn resume(&self, id: &str, env: JobEnv) -> Result<String, String> {
+        let kind = {
+            let history = self.history.lock().unwrap();
+            let record = history
+                .iter()
+                .find(|record| record.id == id)
+                .ok_or_else(|| format!("No interrupted job {}", id))?;
+            if !record.resumable || record.resumed_as.is_some() {
+                return Err(format!("Job {} ({}) can't be resumed", id, record.kind));
+            }
+            JobKind::parse(&record.kind, None)?
+        };
+
+        let (new_id, _) = self.start(kind, env);
+        if let Some(record) = self.history.lock().unwrap().iter_mut().find(|r| r.id == id) {
+            record.resumed_as = Some(new_id.clone());
+        }
+        self.persist();
+        Ok(new_id)
+    }
+
+    /// Start `kind` in the background
+    /// Returns the job id and a receiver for the outcome, for callers that
+    /// want to wait for it.
+    pub fn start(
+        &self,
+        kind: JobKind,
+        env: JobEnv,
+    ) -> (String, oneshot::Receiver<Result<Value, String>>) {
+        let id = format!(
+            "job-{}-{}",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+            self.next_id.fetch_add(1, Ordering::Relaxed)
+        );
+        let job = Job {
+            id: id.clone(),
+            kind: kind.clone(),
+            state: JobState::Running,
+            progress: JobProgress {
+                step: 0,
+                total_steps: kind.total_steps(),
+                message: "Starting".to_string(),
+            },
+            started_at: chrono::Utc::now().to_rfc3339(),
+            finished_at: None,
+            network: None,
+            output_tail: VecDeque::new(),
+        };
+        self.jobs.lock().unwrap().insert(id.clone(), job.clone());
+        self.emit_progress(&job);
+        self.persist();
+
+        let token = CancellationToken::new();
+        self.running
+            .lock()
+            .unwrap()
+            .insert(id.clone(), token.clone());
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let manager = self.clone();
+        let job_id = id.clone();
+        self.tasks.spawn(&format!("job {}", id), async move {
+            let steps = Steps {
+                manager: &manager,
+                id: &job_id,
+                token: &token,
+            };
+            let outcome = execute(&kind, &env, &steps).await;
+            manager.close_output(&job_id);
+            let result = match outcome {
+                Ok(result) => Ok(result),
+                Err(JobError::Failed(error)) => Err(error),
+                Err(JobError::TimedOut(timeout)) => {
+                    let timeout_secs = timeout.as_secs();
+                    manager.update(&job_id, |job| {
+                        job.state = JobState::TimedOut { timeout_secs };
+                        job.finished_at = Some(chrono::Utc::now().to_rfc3339());
+                    });
+                    manager.running.lock().unwrap().remove(&job_id);
+                    let error = format!("Job was stopped after running for {}s", timeout_secs);
+                    let _ = done_tx.send(Err(error));
+                    return;
+                }
+                Err(JobError::Cancelled) => {
+                    let at_step = manager.get(&job_id).map_or(0, |job| job.progress.step);
+                    let (cleanup, cleanup_error) = match clean_up(&job_id, &kind, &env, at_step).await {
+                        Some(Ok(done)) => (Some(done), None),
+                        Some(Err(e)) => (None, Some(e)),
+                        None => (None, None),
+                    };
+                    manager.update(&job_id, |job| {
+                        job.state = JobState::Cancelled {
+                            at_step,
+                            cleanup,
+                            cleanup_error,
+                        };
+                        job.finished_at = Some(chrono::Utc::now().to_rfc3339());
+                    });
+                    manager.running.lock().unwrap().remove(&job_id);
+                    let _ = done_tx.send(Err("Job was cancelled".to_string()));
+                    return;
+                }
+            };
+            manager.finish(&job_id, result.clone());
+            let _ = done_tx.send(result);
+        });
+        (id, done_rx)
+    }
+
+    /// `start`, unless `kind` migrates the database while another job that
+    /// does is still running
+    pub fn try_start(
+        &self,
+        kind: JobKind,
+        env: JobEnv,
+    ) -> Result<(String, oneshot::Receiver<Result<Value, String>>), String> {
+        let _starting = self.starting.lock().unwrap();
+        if kind.migrates() {
+            let running = self.jobs.lock().unwrap().values().find_map(|job| {
+                (job.state == JobState::Running && job.kind.migrates()).then(|| job.id.clone())
+            });
+            if let Some(id) = running {
+                return Err(format!("Job {} is already migrating the database", id));
+            }
+        }
+        Ok(self.start(kind, env))
+    }
+
+    /// Answer a job's metered download prompt
+    pub fn answer_prompt(&self, id: &str, proceed: bool) -> Result<(), String> {
+        let answer = self.prompts.lock().unwrap().remove(id);
+        match answer {
+            Some((_, answer)) => {
+                let _ = answer.send(proceed);
+                Ok(())
+            }
+            None => Err(format!("Job {} isn't waiting for an answer", id)),
+        }
+    }
+
+    /// Metered download prompts still waiting for an answer
+    pub fn pending_prompts(&self) -> Vec<MeteredPrompt> {
+        let mut prompts: Vec<MeteredPrompt> = self
+            .prompts
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(prompt, _)| prompt.clone())
+            .collect();
+        prompts.sort_by(|a, b| a.job_id.cmp(&b.job_id));
+        prompts
+    }
+
+    /// Ids of the jobs still running
+    pub fn running_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.running.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Ask a job to stop; finished jobs are left as they are
+    pub fn cancel(&self, id: &str) -> Result<(), String> {
+        if let Some(token) = self.running.lock().unwrap().get(id) {
+            info!("🛑 Cancelling job {}", id);
+            token.cancel();
+            return Ok(());
+        }
+        match self.get(id) {
+            Some(_) => Ok(()),
+            None => Err(format!("Unknown job: {}", id)),
+        }
+    }
+
+    /// The frontend handled a job's output batches up to `batch`
+    pub fn ack_output(&self, id: &str, batch: u64) -> Result<(), String> {
+        let outputs = self.outputs.lock().unwrap();
+        let output = outputs
+            .get(id)
+            .ok_or_else(|| format!("Job {} has no output stream", id))?;
+        output.ack(batch);
+        Ok(())
+    }
+
+    fn open_output(&self, id: &str) -> Arc<EventBatcher<String>> {
+        let output = Arc::new(EventBatcher::new(
+            self.sink.clone(),
+            JOB_OUTPUT_EVENT,
+            id,
+            BatchLimits::default(),
+        ));
+        self.outputs
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), output.clone());
+        output
+    }
+
+    /// Send what's left of a job's output, however the job ended
+    fn close_output(&self, id: &str) {
+        if let Some(output) = self.outputs.lock().unwrap().remove(id) {
+            output.flush();
+        }
+    }
+
+    /// Keep `line` on the job without announcing it; it goes out with the
+    /// output batches and the job's next update
+    fn record_output(&self, id: &str, line: OutputLine) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            if job.output_tail.len() == OUTPUT_TAIL_LINES {
+                job.output_tail.pop_front();
+            }
+            job.output_tail.push_back(line);
+        }
+    }
+
+    fn progress(&self, id: &str, step: usize, message: &str) {
+        self.update(id, |job| {
+            job.progress.step = step;
+            job.progress.message = message.to_string();
+        });
+    }
+
+    fn finish(&self, id: &str, result: Result<Value, String>) {
+        self.update(id, |job| {
+            job.state = match result {
+                Ok(result) => JobState::Succeeded { result },
+                Err(error) => JobState::Failed { error },
+            };
+            job.finished_at = Some(chrono::Utc::now().to_rfc3339());
+        });
+        self.running.lock().unwrap().remove(id);
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut Job)) {
+        let job = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let Some(job) = jobs.get_mut(id) else {
+                return;
+            };
+            change(job);
+            job.clone()
+        };
+        self.emit_progress(&job);
+        if job.state != JobState::Running {
+            self.persist();
+        }
+    }
+
+    fn emit_progress(&self, job: &Job) {
+        events::emit(&*self.sink, JOB_PROGRESS_EVENT, job);
+        events::emit(&*self.sink, TASK_PROGRESS_EVENT, &TaskProgress::of(job));
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// All jobs, oldest first
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.lock().unwrap().values().cloned().collect();
+        jobs.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+        jobs
+    }
+}
+
+/// Progress reporting and cancellation points of one running job
+struct Steps<'a> {
+    manager: &'a JobManager,
+    id: &'a str,
+    token: &'a CancellationToken,
+}
+
+impl Steps<'_> {
+    /// Run one step, unless the job was cancelled before it
+    /// Cancelling mid-step drops `work`, which kills the command it runs.
+    async fn run<T>(
+        &self,
+        step: usize,
+        message: &str,
+        work: impl std::future::Future<Output = Result<T, String>>,
+    ) -> Result<T, JobError> {
+        if self.token.is_cancelled() {
+            return Err(JobError::Cancelled);
+        }
+        self.manager.progress(self.id, step, message);
+        tokio::select! {
+            result = work => result.map_err(JobError::Failed),
+            _ = self.token.cancelled() => Err(JobError::Cancelled),
+        }
+    }
+}
+
+impl Steps<'_> {
+    /// Run `spec` to completion, streaming its output as `job-output`
+    async fn output_streamed(
+        &self,
+        runner: &dyn CommandRunner,
+        spec: &CommandSpec,
+    ) -> Result<CommandOutput, String> {
+        let output = self.manager.open_output(self.id);
+        let (lines_tx, mut lines) = mpsc::unbounded_channel::<OutputLine>();
+        let forward = async {
+            let mut window = tokio::time::interval(output.limits().window);
+            loop {
+                tokio::select! {
+                    line = lines.recv() => match line {
+                        Some(line) => {
+                            let source = match line.stream {
+                                OutputStream::Stdout => "stdout",
+                                OutputStream::Stderr => "stderr",
+                            };
+                            output.push(source, line.line.clone());
+                            self.manager.record_output(self.id, line);
+                        }
+                        None => break,
+                    },
+                    _ = window.tick() => output.flush(),
+                }
+            }
+        };
+        let (result, ()) = tokio::join!(runner.output_lines(spec, lines_tx), forward);
+        result.map_err(|e| format!("Failed to run command: {}", e))
+    }
+
+    /// Ask the frontend whether to go ahead on a metered connection
+    async fn confirm_download(
+        &self,
+        kind: &JobKind,
+        connection: &network::ConnectionInfo,
+    ) -> Result<bool, JobError> {
+        let (answer_tx, answer_rx) = oneshot::channel();
+        let prompt = MeteredPrompt {
+            job_id: self.id.to_string(),
+            kind: kind.name().to_string(),
+            connection: connection.clone(),
+        };
+        self.manager
+            .prompts
+            .lock()
+            .unwrap()
+            .insert(self.id.to_string(), (prompt.clone(), answer_tx));
+        self.manager
+            .progress(self.id, 0, "Waiting to confirm a download on a metered connection");
+        events::emit(&*self.manager.sink, METERED_PROMPT_EVENT, &prompt);
+
+        let answer = tokio::select! {
+            answer = answer_rx => Ok(answer.unwrap_or(false)),
+            _ = self.token.cancelled() => Err(JobError::Cancelled),
+        };
+        self.manager.prompts.lock().unwrap().remove(self.id);
+        answer
+    }
+}
+
+/// Check the connection before a download, recording the decision on the job
+async fn check_download(kind: &JobKind, env: &JobEnv, steps: &Steps<'_>) -> Result<(), JobError> {
+    let connection = network::detect(env.runner.as_ref(), env.assume_metered).await;
+    let policy = env.downloads_on_metered;
+    let decision = match (connection.metered, policy) {
+        (false, _) | (true, MeteredDownloads::Proceed) => DownloadDecision::Proceeded,
+        (true, MeteredDownloads::Skip) => DownloadDecision::Skipped,
+        (true, MeteredDownloads::Prompt) => match steps.confirm_download(kind, &connection).await? {
+            true => DownloadDecision::Proceeded,
+            false => DownloadDecision::Declined,
+        },
+    };
+    steps.manager.update(steps.id, |job| {
+        job.network = Some(DownloadCheck {
+            connection,
+            policy,
+            decision,
+        })
+    });
+
+    match decision {
+        DownloadDecision::Proceeded => Ok(()),
+        DownloadDecision::Skipped => Err(JobError::Failed(
+            "Download skipped on a metered connection (downloads_on_metered is skip)".to_string(),
+        )),
+        DownloadDecision::Declined => Err(JobError::Failed(
+            "Download declined on a metered connection".to_string(),
+        )),
+    }
+}
+
+async fn execute(kind: &JobKind, env: &JobEnv, steps: &Steps<'_>) -> Result<Value, JobError> {
+    let runner = env.runner.as_ref();
+    match kind {
+        JobKind::SetupCommand {
+            target,
+            vars,
+            raw_output,
+        } => {
+            services::validate_setup_target(target)?;
+            let spec = CommandSpec::new("make")
+                .args([target])
+                .args(services::setup_make_vars(vars)?)
+                .envs(env.setup.make_env())
+                .current_dir(&env.project_root);
+            info!("🔧 Running setup command: {}", spec.command_line());
+
+            let message = format!("Running make {}", target);
+            let run = steps.run(1, &message, steps.output_streamed(runner, &spec));
+            // Dropping the step kills the command, as for a cancel
+            let output = tokio::time::timeout(env.setup_timeout, run)
+                .await
+                .map_err(|_| JobError::TimedOut(env.setup_timeout))??;
+            if !output.success() {
+                return Err(format!("Command failed: {}", output.stderr_text()).into());
+            }
+            let result = env.logs.record(target, &output.stdout, *raw_output);
+            Ok(serde_json::to_value(result).map_err(|e| e.to_string())?)
+        }
+        JobKind::PullImages => {
+            check_download(kind, env, steps).await?;
+            let sink = &*steps.manager.sink;
+            let pull = pull::pull_images(runner, sink, &env.project_root, &env.setup);
+            let pull = async { pull.await.map_err(|e| e.to_string()) };
+            let summary = steps.run(1, "Pulling images", pull).await?;
+            Ok(serde_json::to_value(summary).map_err(|e| e.to_string())?)
+        }
+        JobKind::UpdateServices => {
+            check_download(kind, env, steps).await?;
+            let sink = &*steps.manager.sink;
+            let pull = pull::pull_images(runner, sink, &env.project_root, &env.setup);
+            let pull = async { pull.await.map_err(|e| e.to_string()) };
+            steps.run(1, "Pulling images", pull).await?;
+            let update = services::update_services(runner, &env.project_root, &env.setup);
+            steps.run(2, "Recreating changed services", update).await?;
+            env.lifecycle.record(
+                StopReason::new(StopInitiator::Update)
+                    .with_operation(steps.id)
+                    .restarted(),
+            );
+            Ok(Value::Null)
+        }
+        JobKind::SmokeTest => {
+            let progress =
+                |step: usize, message: &str| steps.manager.progress(steps.id, step, message);
+            let smoke = async { Ok(smoke::run(&env.smoke_env(), &progress).await) };
+            let report = steps.run(1, "Running the smoke test", smoke).await?;
+            if let Err(e) = smoke::save_report(&env.data_dir, &report) {
+                warn!("⚠️  {}", e);
+            }
+            Ok(serde_json::to_value(report).map_err(|e| e.to_string())?)
+        }
+        JobKind::SupportBundle { dest_dir } => {
+            // Left out of the bundle when no checkout was found
+            let root = Some(&env.project_root).filter(|root| !root.as_os_str().is_empty());
+            let doctor_env = DoctorEnv {
+                runner,
+                tools: &env.tools,
+                project_root: root.cloned().ok_or_else(|| "No Arbor checkout found".to_string()),
+                setup: root.map(|_| env.setup.clone()),
+                version_override: env.version_override.clone(),
+                timeout: doctor::CHECK_TIMEOUT,
+            };
+            let diagnose = async { Ok(doctor::run(&doctor_env, keyring::probe()).await) };
+            let diagnostics = steps.run(1, "Running diagnostics", diagnose).await?;
+            let bundle = BundleEnv {
+                runner,
+                log_dir: &env.log_dir,
+                system: &env.system,
+                diagnostics: &diagnostics,
+                stack: root.map(|root| (root.as_path(), &env.setup)),
+            };
+            let write = support::write_bundle(&bundle, Path::new(dest_dir));
+            let path = steps.run(2, "Writing the support bundle", write).await?;
+            info!("📦 Support bundle written to {:?}", path);
+            Ok(serde_json::json!({ "output_file": path.display().to_string() }))
+        }
+        JobKind::Migrations => {
+            let root = &env.project_root;
+            let check = migrations::status(runner, root, &env.setup);
+            let before = steps.run(1, "Checking migration status", check).await?;
+            let spec = CommandSpec::new("make")
+                .args([migrations::MIGRATE_TARGET])
+                .envs(env.setup.make_env())
+                .current_dir(root);
+            info!("🗃️  Applying {} pending migrations", before.pending.len());
+            let message = format!("Running make {}", migrations::MIGRATE_TARGET);
+            let run = steps.run(2, &message, steps.output_streamed(runner, &spec));
+            let output = tokio::time::timeout(env.setup_timeout, run)
+                .await
+                .map_err(|_| JobError::TimedOut(env.setup_timeout))??;
+            if !output.success() {
+                let after = migrations::status(runner, root, &env.setup).await.ok();
+                return Err(migrations::failure(after.as_ref(), &output.stderr_text()).into());
+            }
+            let check = migrations::status(runner, root, &env.setup);
+            let after = steps.run(3, "Checking which migrations were applied", check).await?;
+            let run = MigrationRun::between(&before, &after);
+            Ok(serde_json::to_value(run).map_err(|e| e.to_string())?)
+        }
+        JobKind::BackupData { dest_path } => {
+            let file = backup::target(Path::new(dest_path))?;
+            let partial = backup::partial(&file);
+            let list = backup::volumes(runner, &env.setup.project);
+            let volumes = steps.run(1, "Finding the data volumes", list).await?;
+            if volumes.is_empty() {
+                return Err("There are no data volumes to back up".to_string().into());
+            }
+            let users = backup::users(runner, &volumes, Some("running"));
+            let paused = steps.run(2, "Pausing services", users).await?;
+            let pause = backup::set_paused(runner, &paused, true);
+            steps.run(2, "Pausing services", pause).await?;
+
+            let total: u64 = {
+                let sizes = cleanup::volume_sizes(runner).await;
+                volumes.iter().filter_map(|volume| sizes.get(&volume.name)).sum()
+            };
+            let progress =
+                |written| steps.manager.progress(steps.id, 3, &backup::archived(written, total));
+            let archive = backup::archive(runner, &volumes, &partial, &progress);
+            let archived = steps.run(3, "Archiving volumes", archive).await;
+            // Resumed whether or not the archive was written; a cancel
+            // resumes them in `clean_up`
+            if !matches!(archived, Err(JobError::Cancelled)) {
+                let resume = backup::set_paused(runner, &paused, false).await;
+                if let Err(e) = resume {
+                    warn!("⚠️  Failed to resume services after a backup: {}", e);
+                }
+            }
+            if let Err(e) = archived {
+                let _ = std::fs::remove_file(&partial);
+                return Err(e);
+            }
+
+            let check = async {
+                let found = backup::verify(runner, &partial).await?;
+                let missing: Vec<&str> = volumes
+                    .iter()
+                    .map(|volume| volume.short_name.as_str())
+                    .filter(|name| !found.iter().any(|found| found == name))
+                    .collect();
+                if !missing.is_empty() {
+                    return Err(format!("The backup is missing {}", missing.join(", ")));
+                }
+                let sha256 = backup::checksum(&partial).await?;
+                std::fs::rename(&partial, &file)
+                    .map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+                Ok((found, sha256))
+            };
+            let (found, sha256) = match steps.run(4, "Verifying the backup", check).await {
+                Ok(checked) => checked,
+                Err(e) => {
+                    let _ = std::fs::remove_file(&partial);
+                    return Err(e);
+                }
+            };
+            let size_bytes = std::fs::metadata(&file).map(|m| m.len()).unwrap_or(0);
+            info!("💾 Backed up {} volumes to {:?}", found.len(), file);
+            let backup = DataBackup {
+                output_file: file.display().to_string(),
+                size_bytes,
+                sha256,
+                volumes: found,
+            };
+            Ok(serde_json::to_value(backup).map_err(|e| e.to_string())?)
+        }
+        JobKind::RestoreData { archive_path } => {
+            let archive = Path::new(archive_path);
+            let check = backup::verify(runner, archive);
+            let short_names = steps.run(1, "Verifying the backup", check).await?;
+            let project = &env.setup.project;
+            let targets = backup::restore_targets(runner, project, &short_names);
+            let volumes = steps.run(2, "Checking the volumes aren't in use", targets).await?;
+            let users = backup::users(runner, &volumes, None);
+            let users = steps.run(2, "Checking the volumes aren't in use", users).await?;
+            if !users.is_empty() {
+                return Err(format!(
+                    "Stop {} before restoring data; they use its volumes",
+                    users.join(", ")
+                )
+                .into());
+            }
+            let clear = backup::clear(runner, &volumes, archive);
+            steps.run(3, "Clearing the volumes", clear).await?;
+            let spec = backup::extract_spec(&volumes, archive);
+            let extract = steps.run(4, "Restoring volumes", steps.output_streamed(runner, &spec));
+            let output = extract.await?;
+            if !output.success() {
+                return Err(format!(
+                    "Restoring {} failed: {}; restore it again before starting services",
+                    archive.display(),
+                    output.stderr_text().trim()
+                )
+                .into());
+            }
+            info!("💾 Restored {} volumes from {:?}", volumes.len(), archive);
+            let restore = DataRestore {
+                archive_path: archive_path.clone(),
+                volumes: volumes.into_iter().map(|volume| volume.name).collect(),
+                confirmation_token: None,
+            };
+            Ok(serde_json::to_value(restore).map_err(|e| e.to_string())?)
+        }
+    }
+}
+
+/// Undo what cancelling `kind` during `at_step` left behind
+/// Pulls and setup targets need nothing: docker drops incomplete layers and
+/// command output is only saved on success. A stack cancelled while being
+/// recreated is brought down rather than left half-started, and a smoke
+/// test's container, secret and file are removed.
+async fn clean_up(
+    id: &str,
+    kind: &JobKind,
+    env: &JobEnv,
+    at_step: usize,
+) -> Option<Result<String, String>> {
+    match (kind, at_step) {
+        (JobKind::SmokeTest, _) => {
+            let errors = smoke::clean_up(&env.smoke_env()).await;
+            Some(match errors.is_empty() {
+                true => Ok("Removed the smoke test's resources".to_string()),
+                false => Err(errors.join("; ")),
+            })
+        }
+        (JobKind::BackupData { dest_path }, 2..) => {
+            let runner = env.runner.as_ref();
+            let mut errors = backup::clean_up(runner, Some(Path::new(dest_path))).await;
+            let resumed = match backup::volumes(runner, &env.setup.project).await {
+                Ok(volumes) => backup::users(runner, &volumes, Some("paused")).await,
+                Err(e) => Err(e),
+            };
+            match resumed {
+                Ok(users) => {
+                    if let Err(e) = backup::set_paused(runner, &users, false).await {
+                        errors.push(e);
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+            Some(match errors.is_empty() {
+                true => Ok("Resumed services and removed the unfinished backup".to_string()),
+                false => Err(errors.join("; ")),
+            })
+        }
+        (JobKind::RestoreData { .. }, 3..) => {
+            let errors = backup::clean_up(env.runner.as_ref(), None).await;
+            let left = "The volumes were left partly restored; restore the backup again";
+            Some(match errors.is_empty() {
+                true => Err(left.to_string()),
+                false => Err(format!("{}; {}", left, errors.join("; "))),
+            })
+        }
+        (JobKind::UpdateServices, 2) => {
+            let down =
+                services::compose_down(env.runner.as_ref(), &env.project_root, &env.setup).await;
+            let reason = StopReason::new(StopInitiator::Update).with_operation(id);
+            env.lifecycle.record(match &down {
+                Ok(()) => reason,
+                Err(e) => reason.with_error(e),
+            });
+            Some(down.map(|()| "Stopped partially recreated services".to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// `timeout_secs` within SETUP_TIMEOUT_RANGE
+pub fn validate_setup_timeout(timeout_secs: u64) -> Result<(), String> {
+    if !SETUP_TIMEOUT_RANGE.contains(&timeout_secs) {
+        return Err(format!(
+            "setup_command_timeout_secs must be between {} and {}",
+            SETUP_TIMEOUT_RANGE.start(),
+            SETUP_TIMEOUT_RANGE.end()
+        ));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn start_job(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    kind: String,
+    params: Option<Value>,
+) -> Result<String, String> {
+    app_handle.state::<crate::protocol::Handshake>().ensure()?;
+    let kind = match JobKind::parse(&kind, params)? {
+        JobKind::SupportBundle { dest_dir } => support::bundle_job(&dest_dir)?,
+        JobKind::BackupData { dest_path } => backup::backup_job(&dest_path)?,
+        kind => kind,
+    };
+    if matches!(kind, JobKind::RestoreData { .. }) {
+        return Err("Restores need a confirmation token; use restore_data".to_string());
+    }
+    if kind == JobKind::UpdateServices {
+        app_handle
+            .state::<crate::ServiceManager>()
+            .ensure_owned("update services")?;
+    }
+    let env = match kind {
+        JobKind::SmokeTest => JobEnv::without_project(&app_handle)?,
+        JobKind::SupportBundle { .. } => support::job_env(&app_handle)?,
+        JobKind::Migrations => migrations::job_env(&app_handle)?,
+        _ => JobEnv::for_app(&app_handle)?,
+    };
+    Ok(jobs.try_start(kind, env)?.0)
+}
+
+/// Start the smoke test job, returning its id
+/// Its report is the job's result and goes into the next diagnostics bundle.
+#[tauri::command]
+pub async fn run_smoke_test(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+) -> Result<String, String> {
+    app_handle.state::<crate::protocol::Handshake>().ensure()?;
+    let env = JobEnv::without_project(&app_handle)?;
+    Ok(jobs.try_start(JobKind::SmokeTest, env)?.0)
+}
+
+#[tauri::command]
+pub async fn cancel_job(jobs: State<'_, JobManager>, id: String) -> Result<(), String> {
+    jobs.cancel(&id)
+}
+
+/// The frontend handled a job's `job-output` batches up to `batch`
+#[tauri::command]
+pub async fn ack_job_output(
+    jobs: State<'_, JobManager>,
+    id: String,
+    batch: u64,
+) -> Result<(), String> {
+    jobs.ack_output(&id, batch)
+}
+
+#[tauri::command]
+pub async fn get_job_status(jobs: State<'_, JobManager>, id: String) -> Result<Job, String> {
+    jobs.get(&id).ok_or_else(|| format!("Unknown job: {}", id))
+}
+
+/// Jobs of this session and earlier ones, oldest first
+#[tauri::command]
+pub async fn list_jobs(jobs: State<'_, JobManager>) -> Result<Vec<JobRecord>, String> {
+    Ok(jobs.records())
+}
+
+/// `start_job` under its task name
+#[tauri::command]
+pub async fn spawn_task(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    kind: String,
+    params: Option<Value>,
+) -> Result<String, String> {
+    start_job(app_handle, jobs, kind, params).await
+}
+
+/// `cancel_job` under its task name
+#[tauri::command]
+pub async fn cancel_task(jobs: State<'_, JobManager>, id: String) -> Result<(), String> {
+    cancel_job(jobs, id).await
+}
+
+/// `get_job_status` under its task name
+#[tauri::command]
+pub async fn get_task_status(jobs: State<'_, JobManager>, id: String) -> Result<Job, String> {
+    get_job_status(jobs, id).await
+}
+
+/// `list_jobs` under its task name
+#[tauri::command]
+pub async fn list_tasks(jobs: State<'_, JobManager>) -> Result<Vec<JobRecord>, String> {
+    list_jobs(jobs).await
+}
+
+/// Answer a `metered-download-prompt`
+#[tauri::command]
+pub async fn answer_metered_prompt(
+    jobs: State<'_, JobManager>,
+    id: String,
+    proceed: bool,
+) -> Result<(), String> {
+    jobs.answer_prompt(&id, proceed)
+}
+
+#[tauri::command]
+pub async fn resume_job(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    id: String,
+) -> Result<String, String> {
+    jobs.resume(&id, JobEnv::for_app(&app_handle)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorHistory;
+    use crate::events::recording::RecordingSink;
+    use crate::keyring::mock::MemorySecrets;
+    use crate::process::mock::MockRunner;
+    use crate::process::BoxFuture;
+    use crate::settings::AppSettings;
+    use crate::test_support::temp_dir;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    const ROOT: &str = "/work/arbor";
+
+    fn env(dir: &TempDir, name: &str, runner: Arc<dyn CommandRunner>) -> JobEnv {
+        let logs = dir.path().join(name);
+        JobEnv {
+            runner,
+            project_root: PathBuf::from(ROOT),
+            setup: ComposeSetup::from_settings(
+                &AppSettings::default(),
+                Path::new(ROOT),
+                Path::new("/data/arbor"),
+            ),
+            logs: CommandLogs::new(logs.clone()),
+            downloads_on_metered: MeteredDownloads::default(),
+            assume_metered: false,
+            setup_timeout: Duration::from_secs(600),
+            data_dir: logs.clone(),
+            secrets: Arc::new(MemorySecrets::default()),
+            tools: Vec::new(),
+            lifecycle: Lifecycle::new(Arc::new(RecordingSink::default())),
+            log_dir: logs.join("logs"),
+            system: SystemInfo::collect(&KeyringStatus::default()),
+            version_override: None,
+        }
+    }
+
+    /// Calls other than the metered connection check
+    fn docker_calls(calls: Vec<CommandSpec>) -> Vec<CommandSpec> {
+        calls.into_iter().filter(|call| call.program == "docker").collect()
+    }
+
+    fn manager() -> (JobManager, Arc<RecordingSink>) {
+        let sink = Arc::new(RecordingSink::default());
+        let manager = JobManager::new(sink.clone(), TaskManager::new(ErrorHistory::default()));
+        (manager, sink)
+    }
+
+    #[test]
+    fn test_parse_kind_and_params() {
+        assert_eq!(
+            JobKind::parse(
+                "setup_command",
+                Some(serde_json::json!({ "target": "db-migrate" }))
+            )
+            .unwrap(),
+            JobKind::SetupCommand {
+                target: "db-migrate".to_string(),
+                vars: BTreeMap::new(),
+                raw_output: false
+            }
+        );
+        assert_eq!(
+            JobKind::parse("pull_images", None).unwrap(),
+            JobKind::PullImages
+        );
+        assert!(JobKind::parse("format_disk", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_setup_command_job_reports_progress_and_result() {
+        let tmp = temp_dir();
+        let (manager, sink) = manager();
+        let runner = Arc::new(MockRunner::with_stdout("migrated 3 tables\n"));
+        let kind = JobKind::SetupCommand {
+            target: "db-migrate".to_string(),
+            vars: BTreeMap::new(),
+            raw_output: false,
+        };
+
+        let (id, done) = manager.start(kind, env(&tmp, "setup", runner.clone()));
+        let result = done.await.unwrap().unwrap();
+
+        assert_eq!(result["output"]["text"], "migrated 3 tables\n");
+        let call = &runner.calls()[0];
+        assert_eq!(call.args, vec!["db-migrate"]);
+        assert_eq!(call.cwd.as_deref(), Some(Path::new(ROOT)));
+
+        let job = manager.get(&id).unwrap();
+        assert!(matches!(job.state, JobState::Succeeded { .. }));
+        assert!(job.finished_at.is_some());
+        let events = sink.events_named(JOB_PROGRESS_EVENT);
+        assert_eq!(events.len(), 3, "started, step 1, finished");
+        assert_eq!(events[2]["state"], "succeeded");
+        let progress = sink.events_named(TASK_PROGRESS_EVENT);
+        let percents: Vec<_> = progress.iter().map(|event| event["percent"].clone()).collect();
+        assert_eq!(percents, [0, 100, 100]);
+        assert_eq!(progress[2]["id"], id.as_str());
+        assert_eq!(progress[0]["message"], "Starting");
+        let output = &sink.events_named(JOB_OUTPUT_EVENT)[0];
+        assert_eq!(output["stream"], id.as_str());
+        assert_eq!(output["entries"][0]["data"], "migrated 3 tables");
+    }
+
+    #[tokio::test]
+    async fn test_support_bundle_job_writes_the_archive() {
+        let tmp = temp_dir();
+        let (manager, sink) = manager();
+        let runner = Arc::new(MockRunner::with_stdout(""));
+        let env = env(&tmp, "bundle", runner);
+        let dest_dir = env.data_dir.join("exports");
+        std::fs::create_dir_all(&dest_dir).unwrap();
+        let kind = JobKind::SupportBundle {
+            dest_dir: dest_dir.display().to_string(),
+        };
+
+        let (id, done) = manager.start(kind, env);
+        let result = done.await.unwrap().unwrap();
+
+        let path = PathBuf::from(result["output_file"].as_str().unwrap());
+        assert_eq!(path.parent(), Some(dest_dir.as_path()));
+        assert!(path.exists());
+        let record = JobRecord::of(&manager.get(&id).unwrap());
+        assert_eq!(record.summary, format!("Output saved to {}", path.display()));
+        let events = sink.events_named(JOB_PROGRESS_EVENT);
+        assert_eq!(events.len(), 4, "started, two steps, finished");
+    }
+
+    #[tokio::test]
+    async fn test_backup_pauses_services_and_keeps_only_verified_archives() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = Arc::new(MockRunner::new(|spec| {
+            let args = spec.args.join(" ");
+            let stdout = if args.starts_with("volume ls") {
+                "arbor_postgres_data\tpostgres_data\n"
+            } else if args.starts_with("ps") {
+                "arbor-postgres-1\n"
+            } else if let Some(file) = args.split("tar -czf /backup/").nth(1) {
+                let source = args.split("type=bind,source=").nth(1).unwrap();
+                let dir = source.split(",target=").next().unwrap();
+                let file = file.split_whitespace().next().unwrap();
+                std::fs::write(Path::new(dir).join(file), b"archive").unwrap();
+                ""
+            } else if args.contains("tar -tzf") {
+                "postgres_data/\npostgres_data/PG_VERSION\n"
+            } else {
+                ""
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        }));
+        let env = env(&tmp, "backup", runner.clone());
+        let dest_dir = env.data_dir.join("backups");
+        std::fs::create_dir_all(&dest_dir).unwrap();
+        let kind = JobKind::BackupData {
+            dest_path: dest_dir.display().to_string(),
+        };
+
+        let (_, done) = manager.start(kind, env);
+        let backup: DataBackup = serde_json::from_value(done.await.unwrap().unwrap()).unwrap();
+        assert_eq!(backup.volumes, ["postgres_data"]);
+        assert_eq!(backup.size_bytes, 7);
+        assert!(Path::new(&backup.output_file).exists());
+        assert!(!backup::partial(Path::new(&backup.output_file)).exists());
+        let commands: Vec<String> = runner
+            .calls()
+            .iter()
+            .map(|call| call.args[..2].join(" "))
+            .collect();
+        let pause = commands.iter().position(|c| c == "pause arbor-postgres-1");
+        let resume = commands.iter().position(|c| c == "unpause arbor-postgres-1");
+        assert!(pause < resume && pause.is_some(), "{:?}", commands);
+    }
+
+    #[tokio::test]
+    async fn test_setup_vars_are_passed_and_checked_before_running() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = Arc::new(MockRunner::with_stdout(""));
+        let seed = |name: &str, value: &str| JobKind::SetupCommand {
+            target: "seed".to_string(),
+            vars: BTreeMap::from([(name.to_string(), value.to_string())]),
+            raw_output: false,
+        };
+
+        let (_, done) = manager.start(seed("SEED_USERS", "25"), env(&tmp, "vars", runner.clone()));
+        done.await.unwrap().unwrap();
+        let (_, done) = manager.start(
+            seed("SEED_USERS", "$(id)"),
+            env(&tmp, "vars", runner.clone()),
+        );
+        assert!(done.await.unwrap().is_err());
+
+        let calls = runner.calls();
+        assert_eq!(calls.len(), 1, "The invalid value never reached make");
+        assert_eq!(calls[0].args, ["seed", "SEED_USERS=25"]);
+    }
+
+    /// Runner that prints a line and then never finishes, like a hung migration
+    struct StalledRunner;
+
+    impl CommandRunner for StalledRunner {
+        fn output<'a>(
+            &'a self,
+            _spec: &'a CommandSpec,
+        ) -> BoxFuture<'a, std::io::Result<CommandOutput>> {
+            Box::pin(std::future::pending())
+        }
+
+        fn output_lines<'a>(
+            &'a self,
+            _spec: &'a CommandSpec,
+            lines: mpsc::UnboundedSender<OutputLine>,
+        ) -> BoxFuture<'a, std::io::Result<CommandOutput>> {
+            let _ = lines.send(OutputLine {
+                stream: OutputStream::Stdout,
+                line: "applying 0042_add_index".to_string(),
+            });
+            Box::pin(async move {
+                let _lines = lines;
+                std::future::pending().await
+            })
+        }
+    }
+
+    fn migrate() -> JobKind {
+        JobKind::SetupCommand {
+            target: "db-migrate".to_string(),
+            vars: BTreeMap::new(),
+            raw_output: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hung_setup_command_times_out_with_its_output() {
+        let tmp = temp_dir();
+        let (manager, sink) = manager();
+        let mut env = env(&tmp, "timeout", Arc::new(StalledRunner));
+        env.setup_timeout = Duration::from_millis(100);
+
+        let (id, done) = manager.start(migrate(), env);
+        let error = done.await.unwrap().unwrap_err();
+        assert!(error.contains("stopped after running for 0s"), "{}", error);
+
+        let job = manager.get(&id).unwrap();
+        assert_eq!(job.state, JobState::TimedOut { timeout_secs: 0 });
+        assert_eq!(job.output_tail[0].line, "applying 0042_add_index");
+        assert_eq!(JobRecord::of(&job).state, "timed_out");
+        let output = sink.events_named(JOB_OUTPUT_EVENT);
+        assert_eq!(output[0]["entries"][0]["data"], "applying 0042_add_index");
+        assert!(manager.ack_output(&id, 1).is_err(), "The stream is closed");
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_setup_command_keeps_its_output() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+
+        let (id, done) = manager.start(
+            migrate(),
+            env(&tmp, "cancel-setup", Arc::new(StalledRunner)),
+        );
+        wait_for_step(&manager, &id, 1).await;
+        for _ in 0..100 {
+            if !manager.get(&id).unwrap().output_tail.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        manager.ack_output(&id, 0).unwrap();
+        manager.cancel(&id).unwrap();
+        assert!(done.await.unwrap().is_err());
+
+        let job = manager.get(&id).unwrap();
+        assert!(matches!(job.state, JobState::Cancelled { at_step: 1, .. }));
+        assert_eq!(job.output_tail.len(), 1);
+        assert!(validate_setup_timeout(600).is_ok());
+        assert!(validate_setup_timeout(0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_one_job_migrates_the_database_at_a_time() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let stalled = || env(&tmp, "exclusive", Arc::new(StalledRunner));
+
+        let (id, done) = manager.try_start(migrate(), stalled()).unwrap();
+        let error = manager.try_start(JobKind::Migrations, stalled()).unwrap_err();
+        assert!(error.contains(&id), "{}", error);
+        let seed = JobKind::SetupCommand {
+            target: "seed".to_string(),
+            vars: BTreeMap::new(),
+            raw_output: false,
+        };
+        assert!(manager.try_start(seed, stalled()).is_ok());
+
+        manager.cancel(&id).unwrap();
+        assert!(done.await.unwrap().is_err());
+        assert!(manager.try_start(JobKind::Migrations, stalled()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failed_job_keeps_error() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = Arc::new(MockRunner::new(|_| {
+            Ok(CommandOutput {
+                code: Some(2),
+                stdout: Vec::new(),
+                stderr: b"make: *** No rule to make target 'seed'.".to_vec(),
+            })
+        }));
+        let kind = JobKind::SetupCommand {
+            target: "seed".to_string(),
+            vars: BTreeMap::new(),
+            raw_output: false,
+        };
+
+        let (id, done) = manager.start(kind, env(&tmp, "failed", runner));
+        assert!(done.await.unwrap().is_err());
+
+        match manager.get(&id).unwrap().state {
+            JobState::Failed { error } => assert!(error.contains("No rule to make target")),
+            state => panic!("unexpected state {:?}", state),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_pulls_then_recreates() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = Arc::new(MockRunner::with_stdout(""));
+
+        let (_, done) = manager.start(JobKind::UpdateServices, env(&tmp, "update", runner.clone()));
+        done.await.unwrap().unwrap();
+
+        // After reading the compose config for the images
+        let calls = docker_calls(runner.calls());
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[1].args.last().unwrap(), "pull");
+        assert!(calls[2].args.ends_with(&[
+            "up".to_string(),
+            "-d".to_string(),
+            "--wait".to_string()
+        ]));
+        assert_eq!(manager.list().len(), 1);
+    }
+
+    /// Runner reporting a metered NetworkManager connection
+    #[cfg(target_os = "linux")]
+    fn metered_runner() -> Arc<MockRunner> {
+        Arc::new(MockRunner::new(|spec| {
+            let stdout = if spec.program == "busctl" { "u 1\n" } else { "" };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        }))
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_metered_pull_is_skipped_and_recorded() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = metered_runner();
+        let mut env = env(&tmp, "metered-skip", runner.clone());
+        env.downloads_on_metered = MeteredDownloads::Skip;
+
+        let (id, done) = manager.start(JobKind::PullImages, env);
+        assert!(done.await.unwrap().unwrap_err().contains("metered"));
+        assert!(docker_calls(runner.calls()).is_empty(), "Nothing was pulled");
+
+        let record = JobRecord::of(&manager.get(&id).unwrap());
+        let check = record.network.unwrap();
+        assert!(check.connection.metered);
+        assert_eq!(check.connection.source, network::CostSource::NetworkManager);
+        assert_eq!(check.decision, DownloadDecision::Skipped);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_metered_pull_waits_for_prompt_answer() {
+        let tmp = temp_dir();
+        let (manager, sink) = manager();
+        let runner = metered_runner();
+
+        let (id, done) = manager.start(
+            JobKind::PullImages,
+            env(&tmp, "metered-prompt", runner.clone()),
+        );
+        for _ in 0..100 {
+            if !sink.events_named(METERED_PROMPT_EVENT).is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(sink.events_named(METERED_PROMPT_EVENT)[0]["job_id"], id.as_str());
+        assert!(docker_calls(runner.calls()).is_empty(), "Waits for the answer");
+
+        manager.answer_prompt(&id, true).unwrap();
+        done.await.unwrap().unwrap();
+        let pulls = docker_calls(runner.calls())
+            .into_iter()
+            .filter(|call| call.args.last().unwrap() == "pull")
+            .count();
+        assert_eq!(pulls, 1);
+        let check = manager.get(&id).unwrap().network.unwrap();
+        assert_eq!(check.policy, MeteredDownloads::Prompt);
+        assert_eq!(check.decision, DownloadDecision::Proceeded);
+        assert!(manager.answer_prompt(&id, true).is_err(), "Only answered once");
+    }
+
+    /// Runner whose commands containing `hang_on` never finish, like a slow pull
+    struct HangingRunner {
+        hang_on: &'static str,
+        calls: Mutex<Vec<CommandSpec>>,
+    }
+
+    impl HangingRunner {
+        fn new(hang_on: &'static str) -> Arc<Self> {
+            Arc::new(Self {
+                hang_on,
+                calls: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn calls(&self) -> Vec<CommandSpec> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl CommandRunner for HangingRunner {
+        fn output<'a>(
+            &'a self,
+            spec: &'a CommandSpec,
+        ) -> BoxFuture<'a, std::io::Result<CommandOutput>> {
+            self.calls.lock().unwrap().push(spec.clone());
+            if spec.args.iter().any(|arg| arg == self.hang_on) {
+                return Box::pin(std::future::pending());
+            }
+            Box::pin(async {
+                Ok(CommandOutput {
+                    code: Some(0),
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    async fn wait_for_step(manager: &JobManager, id: &str, step: usize) {
+        for _ in 0..100 {
+            if manager.get(id).unwrap().progress.step == step {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("job never reached step {}", step);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_during_pull_needs_no_cleanup() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = HangingRunner::new("pull");
+
+        let (id, done) = manager.start(
+            JobKind::UpdateServices,
+            env(&tmp, "cancel-pull", runner.clone()),
+        );
+        wait_for_step(&manager, &id, 1).await;
+        manager.cancel(&id).unwrap();
+        manager.cancel(&id).unwrap();
+        assert!(done.await.unwrap().is_err());
+
+        assert_eq!(
+            manager.get(&id).unwrap().state,
+            JobState::Cancelled {
+                at_step: 1,
+                cleanup: None,
+                cleanup_error: None
+            }
+        );
+        let calls = docker_calls(runner.calls());
+        assert_eq!(calls.last().unwrap().args.last().unwrap(), "pull");
+        assert!(
+            !calls.iter().any(|call| call.args.contains(&"--wait".to_string())),
+            "Never got to recreating services"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_during_recreate_brings_stack_down() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = HangingRunner::new("--wait");
+
+        let (id, done) = manager.start(
+            JobKind::UpdateServices,
+            env(&tmp, "cancel-up", runner.clone()),
+        );
+        wait_for_step(&manager, &id, 2).await;
+        manager.cancel(&id).unwrap();
+        let _ = done.await;
+
+        match manager.get(&id).unwrap().state {
+            JobState::Cancelled {
+                at_step, cleanup, ..
+            } => {
+                assert_eq!(at_step, 2);
+                assert!(cleanup.is_some());
+            }
+            state => panic!("unexpected state {:?}", state),
+        }
+        let down = runner.calls().pop().unwrap();
+        assert!(down
+            .args
+            .ends_with(&["down".to_string(), "--remove-orphans".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_finished_or_unknown_job() {
+        let tmp = temp_dir();
+        let (manager, _) = manager();
+        let runner = Arc::new(MockRunner::with_stdout(""));
+
+        let (id, done) = manager.start(JobKind::PullImages, env(&tmp, "cancel-done", runner));
+        done.await.unwrap().unwrap();
+
+        assert!(manager.cancel(&id).is_ok());
+        assert!(matches!(
+            manager.get(&id).unwrap().state,
+            JobState::Succeeded { .. }
+        ));
+        assert!(manager.cancel("job-missing").is_err());
+    }
+
+    fn store(dir: &TempDir) -> JobStore {
+        JobStore::new(dir.path().join(JOBS_FILE_NAME))
+    }
+
+    #[tokio::test]
+    async fn test_history_survives_restart_and_running_jobs_are_interrupted() {
+        let tmp = temp_dir();
+        let store = store(&tmp);
+        let path = store.path.clone();
+        let (first, _) = manager();
+        let first = first.with_store(store);
+
+        let (done_id, done) = first.start(
+            JobKind::PullImages,
+            env(&tmp, "restart-done", Arc::new(MockRunner::with_stdout(""))),
+        );
+        done.await.unwrap().unwrap();
+        let (hung_id, _) = first.start(
+            JobKind::PullImages,
+            env(&tmp, "restart-hung", HangingRunner::new("pull")),
+        );
+        wait_for_step(&first, &hung_id, 1).await;
+
+        // A new process over the same file, written whole
+        assert!(!path.with_extension("json.tmp").exists());
+        let (second, _) = manager();
+        let second = second.with_store(JobStore::new(path));
+        let records = second.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].id, done_id);
+        assert_eq!(records[0].state, "succeeded");
+        assert!(!records[0].resumable);
+        assert_eq!(records[1].id, hung_id);
+        assert_eq!(records[1].state, "interrupted");
+        assert!(records[1].resumable);
+        assert_eq!(records[1].params_hash, JobKind::PullImages.params_hash());
+
+        let resumed = second
+            .resume(
+                &hung_id,
+                env(&tmp, "restart-resume", Arc::new(MockRunner::with_stdout(""))),
+            )
+            .unwrap();
+        assert_ne!(resumed, hung_id);
+        assert!(second
+            .resume(
+                &hung_id,
+                env(&tmp, "restart-again", Arc::new(MockRunner::with_stdout("")))
+            )
+            .is_err());
+        assert!(second
+            .resume(
+                &done_id,
+                env(&tmp, "restart-finished", Arc::new(MockRunner::with_stdout("")))
+            )
+            .is_err());
+        assert_eq!(
+            second.records()[1].resumed_as.as_deref(),
+            Some(resumed.as_str())
+        );
+        // The resumed job saves the history once it ends, which must happen
+        // before the directory is removed
+        while second.running.lock().unwrap().contains_key(&resumed) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let tmp = temp_dir();
+        let store = store(&tmp);
+        let records: Vec<JobRecord> = (0..MAX_JOB_HISTORY + 5)
+            .map(|i| JobRecord {
+                id: format!("job-{}", i),
+                kind: "pull_images".to_string(),
+                params_hash: String::new(),
+                state: "succeeded".to_string(),
+                summary: "Succeeded".to_string(),
+                started_at: String::new(),
+                finished_at: None,
+                resumable: false,
+                resumed_as: None,
+                network: None,
+            })
+            .collect();
+        store.save(&records).unwrap();
+
+        let (manager, _) = manager();
+        let manager = manager.with_store(store);
+        let records = manager.records();
+        assert_eq!(records.len(), MAX_JOB_HISTORY);
+        assert_eq!(records[0].id, "job-5");
+    }
+}
@@ -1,76 +1,1134 @@
 // Master key management using OS keychain
 // This module provides secure storage for the app's master encryption key
+// Backends are synchronous and can block for seconds (a DBus secret service
+// waiting on an unlock prompt), so every call runs on the blocking pool, one
+// at a time, and callers give up after a timeout.
+// Without any keychain backend, secrets go to a passphrase-sealed file in the
+// app data dir instead (see `secret_file`); commands use whichever is active.
 
-use tauri::command;
+use crate::capabilities::CapabilityCache;
+use crate::confirm::Confirmations;
+use crate::flight;
+use crate::info;
+use crate::messages::{Coded, Message};
+use crate::protocol::Handshake;
+use crate::secret_file::{FileSecrets, UnlockError};
+use tauri::{command, State};
 use ::keyring::Entry;
+use bip39::{Language, Mnemonic};
 use rand::Rng;
 use base64::{Engine as _, engine::general_purpose};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::time::{Duration, Instant};
+use zeroize::Zeroizing;
 
 const SERVICE_NAME: &str = "dev.arbor.app";
 const KEY_NAME: &str = "master_encryption_key";
+/// AES-256
+pub const KEY_LEN: usize = 32;
+/// Every secret the app keeps, for uninstalling
+pub const SECRET_NAMES: [&str; 1] = [KEY_NAME];
+// Never written; reading it tells us whether the backend answers at all
+const HEALTH_CHECK_NAME: &str = "health_check";
+// Secrets stored with `set_secret` live under this prefix, so a name can't
+// collide with the app's own entries
+const NAMED_PREFIX: &str = "secret:";
+// Lists the named secrets, since backends can't enumerate their entries
+const INDEX_NAME: &str = "secret_index";
+const MAX_SECRET_NAME_LEN: usize = 128;
+
+/// Emitted at startup when the keychain can't be used, with a KeyringHealth payload
+pub const KEYRING_DEGRADED_EVENT: &str = "keyring-degraded";
+/// How long one keychain call may take before its caller gives up on it
+pub const OPERATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the OS keychain can currently be used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum KeyringHealth {
+    Ok,
+    /// No secret service / keychain backend is reachable
+    Unavailable { detail: String },
+    /// The backend is there but refuses access, typically a locked keychain
+    Locked { detail: String },
+    Error { detail: String },
+}
+
+impl KeyringHealth {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, KeyringHealth::Ok)
+    }
+}
+
+fn classify(result: Result<String, ::keyring::Error>) -> KeyringHealth {
+    match result {
+        Ok(_) | Err(::keyring::Error::NoEntry) => KeyringHealth::Ok,
+        Err(::keyring::Error::NoStorageAccess(e)) => KeyringHealth::Locked {
+            detail: e.to_string(),
+        },
+        Err(::keyring::Error::PlatformFailure(e)) => KeyringHealth::Unavailable {
+            detail: e.to_string(),
+        },
+        Err(e) => KeyringHealth::Error {
+            detail: e.to_string(),
+        },
+    }
+}
+
+/// Probe the keychain backend without touching the master key
+/// Blocking: the backends talk to DBus / the Security framework synchronously.
+pub fn probe_keyring_health() -> KeyringHealth {
+    match Entry::new(SERVICE_NAME, HEALTH_CHECK_NAME) {
+        Ok(entry) => classify(entry.get_password()),
+        Err(e) => classify(Err(e)),
+    }
+}
+
+/// `probe_keyring_health` off the runtime; a backend that doesn't answer
+/// counts as unavailable
+pub async fn probe() -> KeyringHealth {
+    match run_blocking("check the keychain", OPERATION_TIMEOUT, || Ok(probe_keyring_health())).await {
+        Ok(Ok(health)) => health,
+        Ok(Err(detail)) => KeyringHealth::Error { detail },
+        Err(timeout) => KeyringHealth::Unavailable {
+            detail: timeout.to_string(),
+        },
+    }
+}
+
+/// A keychain call that didn't return in time; it may still finish later
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyringTimeout {
+    pub operation: String,
+    pub timeout_secs: u64,
+}
+
+impl fmt::Display for KeyringTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The keychain didn't answer within {}s while trying to {}; it may be waiting to be unlocked",
+            self.timeout_secs, self.operation
+        )
+    }
+}
+
+impl Coded for KeyringTimeout {
+    fn message(&self) -> Message {
+        Message::new("keyring-timeout")
+            .with_param("operation", &self.operation)
+            .with_param("timeout_secs", self.timeout_secs)
+    }
+}
+
+/// Why a keychain operation failed, so the frontend can tell onboarding
+/// from unlocking from a platform without a keychain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum KeyringError {
+    /// No such secret, e.g. no master key before onboarding
+    NotFound { name: String },
+    /// The keychain refuses access, typically until it's unlocked
+    AccessDenied { detail: String },
+    /// No keychain backend in this session
+    PlatformUnavailable { detail: String },
+    /// A key or recovery phrase that can't be a master key
+    InvalidKey { detail: String },
+    /// The keychain didn't answer; it may be waiting to be unlocked
+    Timeout { operation: String, timeout_secs: u64 },
+    /// The request itself was refused, e.g. an unconfirmed reset
+    Rejected { detail: String },
+    Io { detail: String },
+}
+
+impl KeyringError {
+    fn backend(name: &str, e: ::keyring::Error) -> Self {
+        match e {
+            ::keyring::Error::NoEntry => KeyringError::NotFound {
+                name: name.to_string(),
+            },
+            ::keyring::Error::NoStorageAccess(e) => KeyringError::AccessDenied {
+                detail: e.to_string(),
+            },
+            ::keyring::Error::PlatformFailure(e) => KeyringError::PlatformUnavailable {
+                detail: e.to_string(),
+            },
+            ::keyring::Error::BadEncoding(_) => KeyringError::InvalidKey {
+                detail: format!("{} is not valid UTF-8", name),
+            },
+            e => KeyringError::Io {
+                detail: format!("{}: {}", name, e),
+            },
+        }
+    }
+
+    fn rejected(detail: impl ToString) -> Self {
+        KeyringError::Rejected {
+            detail: detail.to_string(),
+        }
+    }
+
+    /// Commands that change what's stored wait for the frontend's handshake
+    fn unless_handshaken(handshake: &Handshake) -> Result<(), Self> {
+        handshake.ensure().map_err(KeyringError::rejected)
+    }
+
+    fn invalid_key(detail: impl ToString) -> Self {
+        KeyringError::InvalidKey {
+            detail: detail.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyringError::NotFound { name } => write!(f, "No {} in the keychain", name),
+            KeyringError::AccessDenied { detail } => {
+                write!(f, "Keychain access denied: {}", detail)
+            }
+            KeyringError::PlatformUnavailable { detail } => {
+                write!(f, "No keychain is available: {}", detail)
+            }
+            KeyringError::Timeout {
+                operation,
+                timeout_secs,
+            } => KeyringTimeout {
+                operation: operation.clone(),
+                timeout_secs: *timeout_secs,
+            }
+            .fmt(f),
+            KeyringError::InvalidKey { detail } | KeyringError::Rejected { detail } => {
+                write!(f, "{}", detail)
+            }
+            KeyringError::Io { detail } => write!(f, "Keychain call failed: {}", detail),
+        }
+    }
+}
+
+impl Coded for KeyringError {
+    fn message(&self) -> Message {
+        match self {
+            KeyringError::NotFound { name } => {
+                Message::new("keyring-not-found").with_param("name", name)
+            }
+            KeyringError::AccessDenied { .. } => Message::new("keyring-access-denied"),
+            KeyringError::PlatformUnavailable { .. } => {
+                Message::new("keyring-platform-unavailable")
+            }
+            KeyringError::Timeout {
+                operation,
+                timeout_secs,
+            } => Message::new("keyring-timeout")
+                .with_param("operation", operation)
+                .with_param("timeout_secs", timeout_secs),
+            KeyringError::InvalidKey { detail } => {
+                Message::new("keyring-invalid-key").with_param("detail", detail)
+            }
+            KeyringError::Rejected { detail } => {
+                Message::new("keyring-rejected").with_param("detail", detail)
+            }
+            KeyringError::Io { detail } => Message::new("keyring-io").with_param("detail", detail),
+        }
+    }
+}
+
+impl From<KeyringTimeout> for KeyringError {
+    fn from(timeout: KeyringTimeout) -> Self {
+        KeyringError::Timeout {
+            operation: timeout.operation,
+            timeout_secs: timeout.timeout_secs,
+        }
+    }
+}
+
+// For callers that report errors as text
+impl From<KeyringError> for String {
+    fn from(e: KeyringError) -> Self {
+        e.to_string()
+    }
+}
+
+// Held by the blocking call itself, not its caller, so a hung backend keeps
+// it after the caller times out and later calls queue here instead of
+// parking more threads in the backend
+fn backend_lock() -> Arc<tokio::sync::Mutex<()>> {
+    static LOCK: OnceLock<Arc<tokio::sync::Mutex<()>>> = OnceLock::new();
+    LOCK.get_or_init(Default::default).clone()
+}
+
+/// Run a blocking keychain call on the blocking pool, after any call still
+/// in progress; waiting for the turn counts towards `timeout`
+pub async fn run_blocking<T, F>(
+    operation: &str,
+    timeout: Duration,
+    call: F,
+) -> Result<Result<T, String>, KeyringTimeout>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    let run = async move {
+        let turn = backend_lock().lock_owned().await;
+        tokio::task::spawn_blocking(move || {
+            let _turn = turn;
+            call()
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("Keychain call failed: {}", e)))
+    };
+    tokio::time::timeout(timeout, run).await.map_err(|_| KeyringTimeout {
+        operation: operation.to_string(),
+        timeout_secs: timeout.as_secs(),
+    })
+}
+
+/// `call` against `store` off the runtime, giving up after OPERATION_TIMEOUT
+pub async fn call<T, F>(
+    store: Arc<dyn SecretStore>,
+    operation: &str,
+    call: F,
+) -> Result<T, KeyringError>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn SecretStore) -> Result<T, KeyringError> + Send + 'static,
+{
+    let started = Instant::now();
+    let result = match run_blocking(operation, OPERATION_TIMEOUT, move || Ok(call(store.as_ref())))
+        .await
+    {
+        Ok(Ok(result)) => result,
+        Ok(Err(detail)) => Err(KeyringError::Io { detail }),
+        Err(timeout) => Err(timeout.into()),
+    };
+    // The operation and outcome only; values never reach the log
+    let outcome = if result.is_ok() { "done" } else { "failed" };
+    flight::debug(format!(
+        "🔑 Keychain: {} {} in {:?}",
+        operation,
+        outcome,
+        started.elapsed()
+    ));
+    result
+}
+
+/// Last keyring health result, gating master key flows while degraded
+#[derive(Default)]
+pub struct KeyringStatus {
+    health: Mutex<Option<KeyringHealth>>,
+}
+
+impl KeyringStatus {
+    pub fn record(&self, health: KeyringHealth) {
+        *self.health.lock().unwrap() = Some(health);
+    }
+
+    /// `None` until the startup check has run
+    pub fn health(&self) -> Option<KeyringHealth> {
+        self.health.lock().unwrap().clone()
+    }
+
+    fn ensure_usable(&self) -> Result<(), KeyringError> {
+        let detail =
+            |detail: &str| format!("{}; resolve it and re-run check_keyring_health", detail);
+        match self.health() {
+            Some(KeyringHealth::Locked { detail: d }) => {
+                Err(KeyringError::AccessDenied { detail: detail(&d) })
+            }
+            Some(KeyringHealth::Unavailable { detail: d }) => {
+                Err(KeyringError::PlatformUnavailable { detail: detail(&d) })
+            }
+            Some(KeyringHealth::Error { detail: d }) => {
+                Err(KeyringError::Io { detail: detail(&d) })
+            }
+            Some(KeyringHealth::Ok) | None => Ok(()),
+        }
+    }
+}
+
+/// Re-check the keychain, e.g. after the user unlocked it
+#[command]
+pub async fn check_keyring_health(
+    status: State<'_, KeyringStatus>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<KeyringHealth, String> {
+    let health = probe().await;
+    status.record(health.clone());
+    capabilities.set_keyring(health.clone());
+    Ok(health)
+}
+
+/// Named secrets in a keychain backend, so callers can be tested without one
+pub trait SecretStore: Send + Sync {
+    fn set(&self, name: &str, value: &str) -> Result<(), KeyringError>;
+    /// `None` when the backend says there is no such secret; errors are for
+    /// a backend that can't be asked, e.g. one denying access
+    fn find(&self, name: &str) -> Result<Option<String>, KeyringError>;
+    fn get(&self, name: &str) -> Result<String, KeyringError> {
+        self.find(name)?.ok_or_else(|| KeyringError::NotFound {
+            name: name.to_string(),
+        })
+    }
+    /// Removing a secret that isn't there succeeds
+    fn delete(&self, name: &str) -> Result<(), KeyringError>;
+}
+
+/// The OS keychain, under one service name
+pub struct OsKeyring {
+    service: String,
+}
+
+impl OsKeyring {
+    /// The app's own entries
+    pub fn app() -> Self {
+        Self::with_service(SERVICE_NAME)
+    }
+
+    pub fn with_service(service: &str) -> Self {
+        Self {
+            service: service.to_string(),
+        }
+    }
+}
+
+impl SecretStore for OsKeyring {
+    fn set(&self, name: &str, value: &str) -> Result<(), KeyringError> {
+        Entry::new(&self.service, name)
+            .and_then(|entry| entry.set_password(value))
+            .map_err(|e| KeyringError::backend(name, e))
+    }
+
+    fn find(&self, name: &str) -> Result<Option<String>, KeyringError> {
+        match Entry::new(&self.service, name).and_then(|entry| entry.get_password()) {
+            Ok(value) => Ok(Some(value)),
+            Err(::keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(KeyringError::backend(name, e)),
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<(), KeyringError> {
+        match Entry::new(&self.service, name).and_then(|entry| entry.delete_credential()) {
+            Ok(()) | Err(::keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(KeyringError::backend(name, e)),
+        }
+    }
+}
+
+/// Which store holds the app's secrets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeystoreBackend {
+    OsKeychain,
+    /// No OS keychain is reachable; see `secret_file`
+    EncryptedFile,
+}
+
+/// Result of `get_keystore_backend`, for the frontend's onboarding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeystoreInfo {
+    pub backend: KeystoreBackend,
+    /// The file fallback needs `unlock_fallback_store` first; always true
+    /// for the OS keychain
+    pub unlocked: bool,
+    /// The file fallback has a passphrase already, rather than taking one
+    /// on first unlock
+    pub initialized: bool,
+}
+
+struct Backends {
+    os: Arc<dyn SecretStore>,
+    fallback: Option<Arc<FileSecrets>>,
+}
+
+/// The secret store every command uses, managed so tests can swap it
+/// Starts on the OS keychain; switched to the encrypted file at startup
+/// when no keychain is reachable.
+#[derive(Clone)]
+pub struct Keychain {
+    backends: Arc<Mutex<Backends>>,
+}
+
+impl Keychain {
+    pub fn new(store: Arc<dyn SecretStore>) -> Self {
+        Self {
+            backends: Arc::new(Mutex::new(Backends {
+                os: store,
+                fallback: None,
+            })),
+        }
+    }
+
+    /// The OS keychain, under the app's service name
+    pub fn os() -> Self {
+        Self::new(Arc::new(OsKeyring::app()))
+    }
+
+    /// Keep secrets in `file` from now on
+    pub fn use_fallback(&self, file: Arc<FileSecrets>) {
+        self.backends.lock().unwrap().fallback = Some(file);
+    }
+
+    pub fn fallback(&self) -> Option<Arc<FileSecrets>> {
+        self.backends.lock().unwrap().fallback.clone()
+    }
+
+    pub fn store(&self) -> Arc<dyn SecretStore> {
+        let backends = self.backends.lock().unwrap();
+        match &backends.fallback {
+            Some(file) => file.clone(),
+            None => backends.os.clone(),
+        }
+    }
+
+    pub fn info(&self) -> KeystoreInfo {
+        match self.fallback() {
+            Some(file) => KeystoreInfo {
+                backend: KeystoreBackend::EncryptedFile,
+                unlocked: file.is_unlocked(),
+                initialized: file.exists(),
+            },
+            None => KeystoreInfo {
+                backend: KeystoreBackend::OsKeychain,
+                unlocked: true,
+                initialized: true,
+            },
+        }
+    }
+
+    /// Refuse key flows while the active store can't tell a missing key
+    /// from one it can't reach
+    fn ensure_usable(&self, status: &KeyringStatus) -> Result<(), KeyringError> {
+        match self.fallback() {
+            Some(file) => file.ensure_unlocked(),
+            None => status.ensure_usable(),
+        }
+    }
+}
+
+/// Whether secrets are kept in the OS keychain or the encrypted file
+#[command]
+pub async fn get_keystore_backend(
+    keychain: State<'_, Keychain>,
+) -> Result<KeystoreInfo, KeyringError> {
+    Ok(keychain.info())
+}
+
+/// Unlock the encrypted file fallback, setting its passphrase on first use
+#[command]
+pub async fn unlock_fallback_store(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+    passphrase: String,
+) -> Result<(), UnlockError> {
+    handshake.ensure().map_err(|detail| UnlockError::Rejected { detail })?;
+    let file = keychain.fallback().ok_or(UnlockError::NotInUse)?;
+    tokio::task::spawn_blocking(move || file.unlock(&passphrase))
+        .await
+        .map_err(|e| UnlockError::Io {
+            detail: e.to_string(),
+        })??;
+    info!("🔓 Secrets file unlocked");
+    Ok(())
+}
 
 /// Get the master encryption key from OS keychain
 /// Returns the key as a base64-encoded string
 #[command]
-pub async fn get_master_key() -> Result<String, String> {
-    let entry = Entry::new(SERVICE_NAME, KEY_NAME)
-        .map_err(|e| format!("Failed to access keychain: {}", e))?;
+pub async fn get_master_key(keychain: State<'_, Keychain>) -> Result<String, KeyringError> {
+    read_key(keychain.store()).await
+}
 
-    let password = entry.get_password()
-        .map_err(|e| format!("Failed to get master key: {}", e))?;
+/// Whether a master key is stored, without handing it out
+#[command]
+pub async fn has_master_key(keychain: State<'_, Keychain>) -> Result<bool, KeyringError> {
+    Ok(find_key(keychain.store()).await?.is_some())
+}
 
-    Ok(password)
+/// A short fingerprint of the master key, to compare keys across devices
+/// without showing them
+#[command]
+pub async fn get_master_key_fingerprint(
+    keychain: State<'_, Keychain>,
+) -> Result<String, KeyringError> {
+    let key = find_key(keychain.store())
+        .await?
+        .ok_or_else(|| KeyringError::NotFound {
+            name: KEY_NAME.to_string(),
+        })?;
+    fingerprint(&key)
 }
 
 /// Set the master encryption key in OS keychain
-/// Accepts a base64-encoded key string
+/// Accepts a base64-encoded key string, standard or URL-safe, which is
+/// stored as standard base64
 #[command]
-pub async fn set_master_key(key: String) -> Result<(), String> {
-    let entry = Entry::new(SERVICE_NAME, KEY_NAME)
-        .map_err(|e| format!("Failed to access keychain: {}", e))?;
-
-    entry.set_password(&key)
-        .map_err(|e| format!("Failed to set master key: {}", e))?;
+pub async fn set_master_key(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+    key: String,
+) -> Result<(), KeyringError> {
+    KeyringError::unless_handshaken(&handshake)?;
+    store_key(keychain.store(), &key).await
+}
 
-    Ok(())
+/// Remove the master key from OS keychain
+/// Succeeds when there is no key, so it can be called to get a clean state.
+#[command]
+pub async fn delete_master_key(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+) -> Result<(), KeyringError> {
+    KeyringError::unless_handshaken(&handshake)?;
+    delete_key(keychain.store()).await
 }
 
 /// Generate a new 32-byte master encryption key and store it in OS keychain
 /// Returns the generated key as a base64-encoded string
 #[command]
-pub async fn generate_master_key() -> Result<String, String> {
-    // Generate 32 random bytes
-    let key_bytes: [u8; 32] = {
+pub async fn generate_master_key(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+) -> Result<String, KeyringError> {
+    KeyringError::unless_handshaken(&handshake)?;
+    let _turn = KEY_LOCK.lock().await;
+    generate_key(keychain.store()).await
+}
+
+async fn read_key(store: Arc<dyn SecretStore>) -> Result<String, KeyringError> {
+    call(store, "read the master key", |s| s.get(KEY_NAME)).await
+}
+
+/// `None` only when the keychain says there is no key
+async fn find_key(store: Arc<dyn SecretStore>) -> Result<Option<Zeroizing<String>>, KeyringError> {
+    let key = call(store, "look up the master key", |s| s.find(KEY_NAME)).await?;
+    Ok(key.map(Zeroizing::new))
+}
+
+/// The first 8 hex digits of the SHA-256 of the key's bytes
+fn fingerprint(key: &str) -> Result<String, KeyringError> {
+    let bytes = Zeroizing::new(general_purpose::STANDARD.decode(key).map_err(|e| {
+        KeyringError::invalid_key(format!("Stored master key is not valid base64: {}", e))
+    })?);
+    let digest = format!("{:x}", Sha256::digest(bytes.as_slice()));
+    Ok(digest[..8].to_string())
+}
+
+/// The master key's bytes, for encrypting in Rust; wiped when dropped
+pub async fn master_key(
+    store: Arc<dyn SecretStore>,
+) -> Result<Zeroizing<[u8; KEY_LEN]>, KeyringError> {
+    let encoded = Zeroizing::new(read_key(store).await?);
+    let decoded = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(encoded.as_bytes())
+            .map_err(|e| {
+                KeyringError::invalid_key(format!("Stored master key is not valid base64: {}", e))
+            })?,
+    );
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    if decoded.len() != KEY_LEN {
+        return Err(KeyringError::invalid_key(format!(
+            "Stored master key is {} bytes, not {}",
+            decoded.len(),
+            KEY_LEN
+        )));
+    }
+    key.copy_from_slice(&decoded);
+    Ok(key)
+}
+
+async fn store_key(store: Arc<dyn SecretStore>, key: &str) -> Result<(), KeyringError> {
+    let key = Zeroizing::new(normalize_key(key)?);
+    let print = fingerprint(&key)?;
+    call(store, "store the master key", move |s| s.set(KEY_NAME, &key)).await?;
+    info!("🔑 Master key {} stored", print);
+    Ok(())
+}
+
+async fn delete_key(store: Arc<dyn SecretStore>) -> Result<(), KeyringError> {
+    call(store, "delete the master key", |s| s.delete(KEY_NAME)).await
+}
+
+async fn generate_key(store: Arc<dyn SecretStore>) -> Result<String, KeyringError> {
+    let key_base64 = random_key();
+    store_key(store, &key_base64).await?;
+    Ok(key_base64)
+}
+
+/// A 32-byte AES-256 key in standard base64, checked before it's stored
+fn normalize_key(key: &str) -> Result<String, KeyringError> {
+    let engines = [
+        general_purpose::STANDARD,
+        general_purpose::URL_SAFE,
+        general_purpose::STANDARD_NO_PAD,
+        general_purpose::URL_SAFE_NO_PAD,
+    ];
+    let bytes = engines
+        .iter()
+        .find_map(|engine| engine.decode(key).ok())
+        .ok_or_else(|| KeyringError::invalid_key("key is not valid base64"))?;
+    if bytes.len() != KEY_LEN {
+        return Err(KeyringError::invalid_key(format!(
+            "key must be {} bytes, got {}",
+            KEY_LEN,
+            bytes.len()
+        )));
+    }
+    Ok(general_purpose::STANDARD.encode(bytes))
+}
+
+/// 32 random bytes, base64-encoded
+fn random_key() -> String {
+    let key_bytes: [u8; KEY_LEN] = {
         let mut rng = rand::rng();
         rng.random()
     };
+    general_purpose::STANDARD.encode(key_bytes)
+}
 
-    // Encode as base64
-    let key_base64 = general_purpose::STANDARD.encode(&key_bytes);
+/// The master key as its 24-word recovery phrase, to write down
+#[command]
+pub async fn export_master_key_phrase(
+    keychain: State<'_, Keychain>,
+) -> Result<String, KeyringError> {
+    export_phrase(keychain.store()).await
+}
+
+/// Restore the master key from its recovery phrase, replacing any stored key
+/// Case and spacing don't matter.
+#[command]
+pub async fn import_master_key_phrase(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+    words: String,
+) -> Result<(), KeyringError> {
+    let words = Zeroizing::new(words);
+    KeyringError::unless_handshaken(&handshake)?;
+    import_phrase(keychain.store(), words).await?;
+    info!("🔑 Master key restored from its recovery phrase");
+    Ok(())
+}
 
-    // Store in keychain
-    set_master_key(key_base64.clone()).await?;
+const PHRASE_WORDS: usize = 24;
 
-    Ok(key_base64)
+async fn export_phrase(store: Arc<dyn SecretStore>) -> Result<String, KeyringError> {
+    let key = master_key(store).await?;
+    let phrase = Mnemonic::from_entropy_in(Language::English, key.as_slice()).map_err(|e| {
+        KeyringError::invalid_key(format!("Failed to encode the recovery phrase: {}", e))
+    })?;
+    Ok(phrase.to_string())
+}
+
+async fn import_phrase(
+    store: Arc<dyn SecretStore>,
+    words: Zeroizing<String>,
+) -> Result<(), KeyringError> {
+    let key = Zeroizing::new(key_from_phrase(&words)?);
+    store_key(store, &key).await
+}
+
+/// The base64 key a recovery phrase encodes, checksum checked
+fn key_from_phrase(words: &str) -> Result<String, KeyringError> {
+    let words: Vec<_> = words.split_whitespace().collect();
+    if words.len() != PHRASE_WORDS {
+        return Err(KeyringError::invalid_key(format!(
+            "A recovery phrase has {} words, got {}",
+            PHRASE_WORDS,
+            words.len()
+        )));
+    }
+    let normalized = Zeroizing::new(words.join(" ").to_lowercase());
+    let phrase = Mnemonic::parse_in_normalized(Language::English, &normalized).map_err(|e| {
+        KeyringError::invalid_key(match e {
+            bip39::Error::UnknownWord(i) => format!(
+                "Word {} (\"{}\") isn't a recovery phrase word",
+                i + 1,
+                words[i]
+            ),
+            bip39::Error::InvalidChecksum => "The recovery phrase's checksum doesn't match; \
+                 check the words and their order"
+                .to_string(),
+            e => format!("Invalid recovery phrase: {}", e),
+        })
+    })?;
+    let (entropy, len) = phrase.to_entropy_array();
+    let entropy = Zeroizing::new(entropy);
+    Ok(general_purpose::STANDARD.encode(&entropy[..len]))
+}
+
+/// Store an API token or other secret under `name`
+#[command]
+pub async fn set_secret(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+    name: String,
+    value: String,
+) -> Result<(), KeyringError> {
+    let value = Zeroizing::new(value);
+    KeyringError::unless_handshaken(&handshake)?;
+    call(keychain.store(), "store a secret", move |s| set_named(s, &name, &value)).await
+}
+
+#[command]
+pub async fn get_secret(
+    keychain: State<'_, Keychain>,
+    name: String,
+) -> Result<String, KeyringError> {
+    call(keychain.store(), "read a secret", move |s| {
+        get_named(s, &name)
+    })
+    .await
+}
+
+/// Removing a secret that isn't there succeeds
+#[command]
+pub async fn delete_secret(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+    name: String,
+) -> Result<(), KeyringError> {
+    KeyringError::unless_handshaken(&handshake)?;
+    call(keychain.store(), "delete a secret", move |s| {
+        delete_named(s, &name)
+    })
+    .await
+}
+
+/// Names stored with `set_secret`, sorted
+#[command]
+pub async fn list_secret_names(keychain: State<'_, Keychain>) -> Result<Vec<String>, KeyringError> {
+    let names = call(keychain.store(), "list secrets", read_index).await?;
+    Ok(names.into_iter().collect())
+}
+
+fn validate_secret_name(name: &str) -> Result<(), KeyringError> {
+    if name.trim().is_empty() {
+        return Err(KeyringError::rejected("Secret name can't be empty"));
+    }
+    if name.chars().count() > MAX_SECRET_NAME_LEN {
+        return Err(KeyringError::rejected(format!(
+            "Secret name is longer than {} characters",
+            MAX_SECRET_NAME_LEN
+        )));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(KeyringError::rejected(
+            "Secret name can't contain control characters",
+        ));
+    }
+    // Out of reach behind the prefix anyway, but a caller asking for
+    // these has the wrong idea
+    let reserved = [KEY_NAME, HEALTH_CHECK_NAME, INDEX_NAME, crate::smoke::SECRET_NAME];
+    if reserved.contains(&name) {
+        return Err(KeyringError::rejected(format!(
+            "{} is reserved for Arbor",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn read_index(store: &dyn SecretStore) -> Result<BTreeSet<String>, KeyringError> {
+    match store.find(INDEX_NAME)? {
+        Some(index) => serde_json::from_str(&index).map_err(|e| KeyringError::Io {
+            detail: format!("Failed to read the secret index: {}", e),
+        }),
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+fn write_index(store: &dyn SecretStore, names: &BTreeSet<String>) -> Result<(), KeyringError> {
+    let index = serde_json::to_string(names).map_err(|e| KeyringError::Io {
+        detail: e.to_string(),
+    })?;
+    store.set(INDEX_NAME, &index)
+}
+
+// Each runs as one keychain call, so the index can't be updated
+// concurrently
+fn set_named(store: &dyn SecretStore, name: &str, value: &str) -> Result<(), KeyringError> {
+    validate_secret_name(name)?;
+    store.set(&format!("{}{}", NAMED_PREFIX, name), value)?;
+    let mut names = read_index(store)?;
+    if names.insert(name.to_string()) {
+        write_index(store, &names)?;
+    }
+    info!("🔑 Secret {} stored ({} bytes)", name, value.len());
+    Ok(())
+}
+
+fn get_named(store: &dyn SecretStore, name: &str) -> Result<String, KeyringError> {
+    validate_secret_name(name)?;
+    let value = store
+        .find(&format!("{}{}", NAMED_PREFIX, name))?
+        .ok_or_else(|| KeyringError::NotFound {
+            name: name.to_string(),
+        })?;
+    flight::debug(format!("🔑 Secret {} read ({} bytes)", name, value.len()));
+    Ok(value)
+}
+
+fn delete_named(store: &dyn SecretStore, name: &str) -> Result<(), KeyringError> {
+    validate_secret_name(name)?;
+    store.delete(&format!("{}{}", NAMED_PREFIX, name))?;
+    let mut names = read_index(store)?;
+    if names.remove(name) {
+        write_index(store, &names)?;
+    }
+    info!("🔑 Secret {} deleted", name);
+    Ok(())
+}
+
+/// Keychain entries holding named secrets, index included, for uninstalling
+pub fn named_secret_entries(store: &dyn SecretStore) -> Result<Vec<String>, KeyringError> {
+    let names = read_index(store)?;
+    Ok(names
+        .iter()
+        .map(|name| format!("{}{}", NAMED_PREFIX, name))
+        .chain([INDEX_NAME.to_string()])
+        .collect())
+}
+
+/// Result of `reset_encryption`
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptionReset {
+    /// From the dry run, for the real call
+    pub confirmation_token: Option<String>,
+    /// The new master key, once reset
+    pub key: Option<String>,
+}
+
+/// Replace the master key with a new one, so data encrypted with the old
+/// key is gone for good
+/// A dry run hands out the confirmation token the real call needs. Refused
+/// while the keychain is degraded, like `get_or_generate_master_key`.
+#[command]
+pub async fn reset_encryption(
+    status: State<'_, KeyringStatus>,
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+    confirmations: State<'_, Confirmations>,
+    dry_run: bool,
+    confirmation_token: Option<String>,
+) -> Result<EncryptionReset, KeyringError> {
+    KeyringError::unless_handshaken(&handshake)?;
+    keychain.ensure_usable(&status)?;
+    const ACTION: &str = "reset_encryption";
+    if dry_run {
+        return Ok(EncryptionReset {
+            confirmation_token: Some(confirmations.issue(ACTION)),
+            key: None,
+        });
+    }
+    let token = confirmation_token.ok_or_else(|| {
+        KeyringError::rejected("reset_encryption needs the confirmation token from a dry run")
+    })?;
+    confirmations
+        .redeem(ACTION, &token)
+        .map_err(KeyringError::rejected)?;
+
+    let key = reset(keychain.store()).await?;
+    info!("🔑 Master key reset");
+    Ok(EncryptionReset {
+        confirmation_token: None,
+        key: Some(key),
+    })
+}
+
+async fn reset(store: Arc<dyn SecretStore>) -> Result<String, KeyringError> {
+    let _turn = KEY_LOCK.lock().await;
+    delete_key(store.clone()).await?;
+    generate_key(store).await
 }
 
 /// Get or generate master key - convenience function
 /// If key exists, returns it. If not, generates and stores a new one.
+/// Refused while the keychain is degraded, so an unreachable key is never
+/// mistaken for a missing one and replaced.
+#[command]
+pub async fn get_or_generate_master_key(
+    status: State<'_, KeyringStatus>,
+    keychain: State<'_, Keychain>,
+) -> Result<String, KeyringError> {
+    keychain.ensure_usable(&status)?;
+    get_or_generate(keychain.store()).await
+}
+
+// Held across check-then-generate, reset and rotation, so concurrent
+// callers can't each store a key and walk away with one that was overwritten
+static KEY_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+async fn get_or_generate(store: Arc<dyn SecretStore>) -> Result<String, KeyringError> {
+    let _turn = KEY_LOCK.lock().await;
+    // Only a key the keychain says is missing is replaced; one it won't
+    // hand out (locked, denied) is an error
+    let existing = call(store.clone(), "read the master key", |s| s.find(KEY_NAME)).await?;
+    if let Some(key) = existing {
+        return Ok(key);
+    }
+    generate_key(store.clone()).await?;
+    // Hand out what the keychain actually holds
+    read_key(store).await
+}
+
+/// Result of `rotate_master_key`: re-encrypt with `new_key`, then commit
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyRotation {
+    pub old_key: String,
+    pub new_key: String,
+}
+
+/// A rotation handed out and not yet committed or aborted
+/// Only kept in memory, so a crash before the commit leaves the old key
+/// where it was.
+#[derive(Default)]
+pub struct PendingRotation {
+    rotation: Mutex<Option<KeyRotation>>,
+}
+
+/// Generate a replacement master key without storing it
+/// The keychain keeps the old key until `commit_key_rotation`; a later
+/// rotation replaces one still pending.
 #[command]
-pub async fn get_or_generate_master_key() -> Result<String, String> {
-    match get_master_key().await {
-        Ok(key) => Ok(key),
-        Err(_) => generate_master_key().await,
+pub async fn rotate_master_key(
+    status: State<'_, KeyringStatus>,
+    keychain: State<'_, Keychain>,
+    pending: State<'_, PendingRotation>,
+) -> Result<KeyRotation, KeyringError> {
+    keychain.ensure_usable(&status)?;
+    rotate(keychain.store(), &pending).await
+}
+
+/// Store the pending rotation's new key, once the vault is re-encrypted
+#[command]
+pub async fn commit_key_rotation(
+    keychain: State<'_, Keychain>,
+    handshake: State<'_, Handshake>,
+    pending: State<'_, PendingRotation>,
+) -> Result<(), KeyringError> {
+    KeyringError::unless_handshaken(&handshake)?;
+    commit_rotation(keychain.store(), &pending).await?;
+    info!("🔑 Master key rotated");
+    Ok(())
+}
+
+/// Drop the pending rotation; the old key stays
+#[command]
+pub async fn abort_key_rotation(pending: State<'_, PendingRotation>) -> Result<(), KeyringError> {
+    pending.rotation.lock().unwrap().take();
+    Ok(())
+}
+
+async fn rotate(
+    store: Arc<dyn SecretStore>,
+    pending: &PendingRotation,
+) -> Result<KeyRotation, KeyringError> {
+    let _turn = KEY_LOCK.lock().await;
+    let old_key = read_key(store).await?;
+    let rotation = KeyRotation {
+        old_key,
+        new_key: random_key(),
+    };
+    *pending.rotation.lock().unwrap() = Some(rotation.clone());
+    Ok(rotation)
+}
+
+async fn commit_rotation(
+    store: Arc<dyn SecretStore>,
+    pending: &PendingRotation,
+) -> Result<(), KeyringError> {
+    let _turn = KEY_LOCK.lock().await;
+    let rotation = pending
+        .rotation
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| KeyringError::rejected("No master key rotation in progress"))?;
+    // The vault was re-encrypted from old_key; anything else means the key
+    // changed under the rotation, and storing new_key would orphan it
+    if read_key(store.clone()).await? != rotation.old_key {
+        pending.rotation.lock().unwrap().take();
+        return Err(KeyringError::rejected(
+            "The master key changed during the rotation; rotate again",
+        ));
+    }
+    store_key(store, &rotation.new_key).await?;
+    pending.rotation.lock().unwrap().take();
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// In-memory keychain
+    #[derive(Default)]
+    pub struct MemorySecrets {
+        secrets: Mutex<HashMap<String, String>>,
+    }
+
+    impl SecretStore for MemorySecrets {
+        fn set(&self, name: &str, value: &str) -> Result<(), KeyringError> {
+            self.secrets
+                .lock()
+                .unwrap()
+                .insert(name.to_string(), value.to_string());
+            Ok(())
+        }
+
+        fn find(&self, name: &str) -> Result<Option<String>, KeyringError> {
+            Ok(self.secrets.lock().unwrap().get(name).cloned())
+        }
+
+        fn delete(&self, name: &str) -> Result<(), KeyringError> {
+            self.secrets.lock().unwrap().remove(name);
+            Ok(())
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::temp_dir;
+
+    const TEST_KEY: &str = "dGVzdGtleTMyYnl0ZXN0ZXN0a2V5MzJieXRlc3Rlc3Q="; // base64 of "testkey32bytestestkey32bytestest"
+
+    fn memory() -> Arc<dyn SecretStore> {
+        Arc::new(mock::MemorySecrets::default())
+    }
+
+    #[tokio::test]
+    async fn test_keychain_logs_never_hold_secret_values() {
+        flight::init();
+        let store = memory();
+        store_key(store.clone(), TEST_KEY).await.unwrap();
+        assert_eq!(read_key(store.clone()).await.unwrap(), TEST_KEY);
+        let token = "ghp_logcheck0123456789";
+        call(store.clone(), "store a secret", move |s| set_named(s, "log-check", token))
+            .await
+            .unwrap();
+        call(store, "read a secret", |s| get_named(s, "log-check"))
+            .await
+            .unwrap();
+
+        let lines = flight::buffered();
+        assert!(lines.iter().all(|line| !line.contains(TEST_KEY) && !line.contains(token)));
+        let print = fingerprint(TEST_KEY).unwrap();
+        assert!(lines.iter().any(|line| line.contains(&format!("Master key {} stored", print))));
+        assert!(lines.iter().any(|line| line.contains("Secret log-check stored (22 bytes)")));
+        assert!(lines.iter().any(|line| line.contains("Keychain: read the master key done in")));
+    }
 
     #[tokio::test]
     async fn test_generate_master_key() {
-        let result = generate_master_key().await;
+        let store = memory();
+        let result = generate_key(store.clone()).await;
         assert!(result.is_ok(), "Should generate a master key");
         
         let key = result.unwrap();
@@ -78,39 +1136,43 @@ mod tests {
         
         // Base64 encoded 32 bytes should be 44 characters (with padding)
         assert_eq!(key.len(), 44, "Base64 encoded 32-byte key should be 44 characters");
+        assert_eq!(read_key(store).await.unwrap(), key, "Generated key should be stored");
     }
 
     #[tokio::test]
     async fn test_set_and_get_master_key() {
-        // Generate a test key
-        let test_key = "dGVzdGtleTE2Ynl0ZXN0ZXN0a2V5MTZieXRlcw=="; // base64 of "testkey16bytestestkey16bytes"
+        let store = memory();
         
         // Set the key
-        let set_result = set_master_key(test_key.to_string()).await;
+        let set_result = store_key(store.clone(), TEST_KEY).await;
         assert!(set_result.is_ok(), "Should set master key successfully");
         
         // Get the key back
-        let get_result = get_master_key().await;
+        let get_result = read_key(store).await;
         assert!(get_result.is_ok(), "Should get master key successfully");
         
         let retrieved_key = get_result.unwrap();
-        assert_eq!(retrieved_key, test_key, "Retrieved key should match set key");
+        assert_eq!(retrieved_key, TEST_KEY, "Retrieved key should match set key");
     }
 
     #[tokio::test]
     async fn test_get_nonexistent_key() {
-        // First, try to delete any existing key (ignore errors)
-        // This test assumes a clean state
-        
-        let result = get_master_key().await;
-        // Should either return a key (if one exists) or error
-        // We can't guarantee clean state in tests, so we just verify it doesn't panic
-        assert!(result.is_ok() || result.is_err());
+        let result = read_key(memory()).await;
+        assert!(result.is_err(), "A missing key should not be found");
+    }
+
+    #[tokio::test]
+    async fn test_delete_master_key_is_idempotent() {
+        let store = memory();
+        store_key(store.clone(), TEST_KEY).await.unwrap();
+        assert!(delete_key(store.clone()).await.is_ok());
+        assert!(read_key(store.clone()).await.is_err());
+        assert!(delete_key(store).await.is_ok(), "Deleting a missing key succeeds");
     }
 
     #[tokio::test]
     async fn test_get_or_generate_creates_key_if_missing() {
-        let result = get_or_generate_master_key().await;
+        let result = get_or_generate(memory()).await;
         assert!(result.is_ok(), "Should get or generate a key");
         
         let key = result.unwrap();
@@ -120,25 +1182,426 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_or_generate_returns_existing_key() {
+        let store = memory();
         // First, set a known key
-        let test_key = "dGVzdGtleTE2Ynl0ZXN0ZXN0a2V5MTZieXRlcw==";
-        let _ = set_master_key(test_key.to_string()).await;
+        store_key(store.clone(), TEST_KEY).await.unwrap();
         
         // Now get_or_generate should return the existing key
-        let result = get_or_generate_master_key().await;
+        let result = get_or_generate(store).await;
         assert!(result.is_ok(), "Should get existing key");
         
         let key = result.unwrap();
-        assert_eq!(key, test_key, "Should return the existing key, not generate a new one");
+        assert_eq!(key, TEST_KEY, "Should return the existing key, not generate a new one");
     }
 
     #[tokio::test]
     async fn test_generated_keys_are_unique() {
+        let store = memory();
         // Generate two keys and verify they're different
-        let key1 = generate_master_key().await.unwrap();
-        let key2 = generate_master_key().await.unwrap();
+        let key1 = generate_key(store.clone()).await.unwrap();
+        let key2 = generate_key(store).await.unwrap();
         
         assert_ne!(key1, key2, "Each generated key should be unique");
     }
-}
 
+    /// Round-trips a key through the real OS keychain, under a throwaway
+    /// service name; run with `cargo test -- --ignored` on a desktop session
+    #[tokio::test]
+    #[ignore = "needs a real OS keychain"]
+    async fn test_os_keychain_round_trip() {
+        let store: Arc<dyn SecretStore> = Arc::new(OsKeyring::with_service("dev.arbor.app.test"));
+        delete_key(store.clone()).await.unwrap();
+
+        store_key(store.clone(), TEST_KEY).await.unwrap();
+        assert_eq!(read_key(store.clone()).await.unwrap(), TEST_KEY);
+        delete_key(store.clone()).await.unwrap();
+        assert!(read_key(store).await.is_err());
+    }
+
+    #[test]
+    fn test_classify_keyring_errors() {
+        assert_eq!(classify(Err(::keyring::Error::NoEntry)), KeyringHealth::Ok);
+        assert!(matches!(
+            classify(Err(::keyring::Error::NoStorageAccess(
+                std::io::Error::other("keychain is locked").into()
+            ))),
+            KeyringHealth::Locked { .. }
+        ));
+        assert!(matches!(
+            classify(Err(::keyring::Error::PlatformFailure(
+                std::io::Error::other("org.freedesktop.secrets was not provided").into()
+            ))),
+            KeyringHealth::Unavailable { .. }
+        ));
+    }
+
+    /// Backend that takes `delay` to answer, counting calls in progress
+    #[derive(Default)]
+    struct SlowSecrets {
+        delay: std::time::Duration,
+        in_flight: std::sync::atomic::AtomicUsize,
+        most_in_flight: std::sync::atomic::AtomicUsize,
+        started: std::sync::atomic::AtomicUsize,
+    }
+
+    impl SecretStore for SlowSecrets {
+        fn set(&self, _name: &str, _value: &str) -> Result<(), KeyringError> {
+            Ok(())
+        }
+
+        fn find(&self, name: &str) -> Result<Option<String>, KeyringError> {
+            use std::sync::atomic::Ordering;
+            self.started.fetch_add(1, Ordering::SeqCst);
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_in_flight.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(self.delay);
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(Some(name.to_string()))
+        }
+
+        fn delete(&self, _name: &str) -> Result<(), KeyringError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hung_backend_times_out_without_stalling_the_runtime() {
+        use std::sync::atomic::Ordering;
+        let slow = Arc::new(SlowSecrets {
+            delay: Duration::from_millis(400),
+            ..SlowSecrets::default()
+        });
+        let read = |store: Arc<SlowSecrets>| {
+            run_blocking("read a secret", Duration::from_millis(100), move || {
+                store.get("key").map_err(String::from)
+            })
+        };
+
+        // A single-threaded runtime would stall for the whole delay if the
+        // call ran on it
+        let started = std::time::Instant::now();
+        let (first, second, third, other_command) = tokio::join!(
+            read(slow.clone()),
+            read(slow.clone()),
+            read(slow.clone()),
+            async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                started.elapsed()
+            }
+        );
+        assert!(other_command < Duration::from_millis(100), "{:?}", other_command);
+        for result in [first, second, third] {
+            let timeout = result.unwrap_err();
+            assert_eq!(timeout.operation, "read a secret");
+            assert_eq!(timeout.message().code, "keyring-timeout");
+        }
+
+        // Waiting callers gave up in the queue rather than joining the hung call
+        assert_eq!(slow.started.load(Ordering::SeqCst), 1);
+        assert_eq!(slow.most_in_flight.load(Ordering::SeqCst), 1);
+
+        // Once the backend answers, calls go through again
+        let store: Arc<dyn SecretStore> = slow.clone();
+        assert_eq!(call(store, "read a secret", |s| s.get("key")).await.unwrap(), "key");
+    }
+
+    #[test]
+    fn test_keys_are_validated_before_storing() {
+        assert_eq!(
+            normalize_key(""),
+            Err(KeyringError::invalid_key("key must be 32 bytes, got 0"))
+        );
+        let short = general_purpose::STANDARD.encode([7u8; 16]);
+        assert_eq!(
+            normalize_key(&short),
+            Err(KeyringError::invalid_key("key must be 32 bytes, got 16"))
+        );
+        assert_eq!(
+            normalize_key("not base64!"),
+            Err(KeyringError::invalid_key("key is not valid base64"))
+        );
+
+        let bytes = [0xfbu8; KEY_LEN];
+        let standard = general_purpose::STANDARD.encode(bytes);
+        assert_eq!(normalize_key(&standard).unwrap(), standard);
+        // URL-safe, with or without padding, is stored as standard
+        assert_eq!(normalize_key(&general_purpose::URL_SAFE.encode(bytes)).unwrap(), standard);
+        let unpadded = general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        assert_eq!(normalize_key(&unpadded).unwrap(), standard);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_get_or_generate_agree_on_one_key() {
+        let store = memory();
+
+        let callers: Vec<_> = (0..16)
+            .map(|_| tokio::spawn(get_or_generate(store.clone())))
+            .collect();
+        let mut keys = Vec::new();
+        for caller in callers {
+            keys.push(caller.await.unwrap().unwrap());
+        }
+
+        let stored = store.get(KEY_NAME).unwrap();
+        assert!(keys.iter().all(|key| *key == stored), "{:?}", keys);
+    }
+
+    #[tokio::test]
+    async fn test_reset_replaces_the_master_key() {
+        let store = memory();
+        store.set(KEY_NAME, "old").unwrap();
+
+        let key = reset(store.clone()).await.unwrap();
+        assert_eq!(key.len(), 44);
+        assert_eq!(store.get(KEY_NAME).unwrap(), key);
+    }
+
+    #[test]
+    fn test_degraded_keyring_blocks_master_key_flow() {
+        let status = KeyringStatus::default();
+        assert!(status.ensure_usable().is_ok(), "Unchecked keyring is not blocked");
+
+        status.record(KeyringHealth::Locked {
+            detail: "locked".to_string(),
+        });
+        assert!(status.ensure_usable().is_err());
+
+        status.record(KeyringHealth::Ok);
+        assert!(status.ensure_usable().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_file_takes_over_once_unlocked() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let keychain = Keychain::new(memory());
+        assert_eq!(keychain.info().backend, KeystoreBackend::OsKeychain);
+
+        let cheap = crate::secret_file::KdfParams {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let file =
+            FileSecrets::new(dir.join(crate::secret_file::SECRET_FILE_NAME)).with_kdf(cheap);
+        keychain.use_fallback(Arc::new(file));
+        let info = keychain.info();
+        assert_eq!(info.backend, KeystoreBackend::EncryptedFile);
+        assert!(!info.unlocked && !info.initialized);
+        // The OS keychain being unavailable doesn't matter any more, the
+        // file being locked does
+        let status = KeyringStatus::default();
+        status.record(KeyringHealth::Unavailable {
+            detail: "no secret service".to_string(),
+        });
+        assert!(keychain.ensure_usable(&status).is_err());
+
+        keychain.fallback().unwrap().unlock("passphrase").unwrap();
+        assert!(keychain.ensure_usable(&status).is_ok());
+        let key = get_or_generate(keychain.store()).await.unwrap();
+        assert_eq!(read_key(keychain.store()).await.unwrap(), key);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_then_commit_stores_the_new_key() {
+        let store = memory();
+        store_key(store.clone(), TEST_KEY).await.unwrap();
+        let pending = PendingRotation::default();
+
+        let rotation = rotate(store.clone(), &pending).await.unwrap();
+        assert_eq!(rotation.old_key, TEST_KEY);
+        assert_ne!(rotation.new_key, TEST_KEY);
+        assert_eq!(normalize_key(&rotation.new_key).unwrap(), rotation.new_key);
+        // Nothing is written until the commit, so a crash keeps the old key
+        assert_eq!(read_key(store.clone()).await.unwrap(), TEST_KEY);
+
+        commit_rotation(store.clone(), &pending).await.unwrap();
+        assert_eq!(read_key(store.clone()).await.unwrap(), rotation.new_key);
+        assert!(commit_rotation(store, &pending).await.is_err(), "Committed once");
+    }
+
+    #[tokio::test]
+    async fn test_rotate_then_abort_keeps_the_old_key() {
+        let store = memory();
+        store_key(store.clone(), TEST_KEY).await.unwrap();
+        let pending = PendingRotation::default();
+
+        rotate(store.clone(), &pending).await.unwrap();
+        pending.rotation.lock().unwrap().take();
+        assert!(commit_rotation(store.clone(), &pending).await.is_err());
+        assert_eq!(read_key(store).await.unwrap(), TEST_KEY);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_without_a_key_fails() {
+        let store = memory();
+        let pending = PendingRotation::default();
+
+        assert!(rotate(store.clone(), &pending).await.is_err());
+        assert!(pending.rotation.lock().unwrap().is_none());
+        assert!(read_key(store).await.is_err(), "No key is made up");
+    }
+
+    #[tokio::test]
+    async fn test_commit_refuses_a_key_changed_mid_rotation() {
+        let store = memory();
+        store_key(store.clone(), TEST_KEY).await.unwrap();
+        let pending = PendingRotation::default();
+
+        rotate(store.clone(), &pending).await.unwrap();
+        let replaced = reset(store.clone()).await.unwrap();
+        assert!(commit_rotation(store.clone(), &pending).await.is_err());
+        assert_eq!(read_key(store).await.unwrap(), replaced);
+    }
+
+    #[tokio::test]
+    async fn test_key_presence_and_fingerprint_without_the_key() {
+        let store = memory();
+        assert_eq!(find_key(store.clone()).await.unwrap(), None);
+
+        store_key(store.clone(), TEST_KEY).await.unwrap();
+        assert!(find_key(store.clone()).await.unwrap().is_some());
+        // Pinned, so users comparing devices see the same thing everywhere
+        assert_eq!(fingerprint(TEST_KEY).unwrap(), "4eb5c242");
+        let zeros = general_purpose::STANDARD.encode([0u8; KEY_LEN]);
+        assert_eq!(fingerprint(&zeros).unwrap(), "66687aad");
+    }
+
+    #[tokio::test]
+    async fn test_denied_access_is_not_a_missing_key() {
+        /// Won't hand out the key, but would take a new one
+        #[derive(Default)]
+        struct Denied {
+            writes: Mutex<Vec<String>>,
+        }
+        impl SecretStore for Denied {
+            fn set(&self, name: &str, _value: &str) -> Result<(), KeyringError> {
+                self.writes.lock().unwrap().push(name.to_string());
+                Ok(())
+            }
+            fn find(&self, _name: &str) -> Result<Option<String>, KeyringError> {
+                Err(KeyringError::AccessDenied {
+                    detail: "locked".to_string(),
+                })
+            }
+            fn delete(&self, _name: &str) -> Result<(), KeyringError> {
+                Ok(())
+            }
+        }
+        let denied = Arc::new(Denied::default());
+
+        assert!(matches!(
+            find_key(denied.clone()).await,
+            Err(KeyringError::AccessDenied { .. })
+        ));
+        assert!(matches!(
+            get_or_generate(denied.clone()).await,
+            Err(KeyringError::AccessDenied { .. })
+        ));
+        assert!(denied.writes.lock().unwrap().is_empty(), "No key was replaced");
+
+        assert_eq!(
+            read_key(memory()).await,
+            Err(KeyringError::NotFound {
+                name: KEY_NAME.to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_keyring_errors_serialize_by_kind() {
+        let denied = KeyringError::backend(
+            KEY_NAME,
+            ::keyring::Error::NoStorageAccess("locked".into()),
+        );
+        assert_eq!(
+            serde_json::to_value(&denied).unwrap(),
+            serde_json::json!({ "kind": "access_denied", "detail": "locked" })
+        );
+        assert_eq!(
+            serde_json::to_value(KeyringError::backend(KEY_NAME, ::keyring::Error::NoEntry))
+                .unwrap()["kind"],
+            "not_found"
+        );
+        let platform = KeyringError::backend(
+            KEY_NAME,
+            ::keyring::Error::PlatformFailure("no secret service".into()),
+        );
+        assert_eq!(platform.message().code, "keyring-platform-unavailable");
+    }
+
+    #[test]
+    fn test_key_changes_wait_for_the_handshake() {
+        let handshake = Handshake::default();
+        let refused = KeyringError::unless_handshaken(&handshake).unwrap_err();
+        assert_eq!(refused.message().code, "keyring-rejected");
+        handshake.accept(crate::protocol::PROTOCOL_VERSION).unwrap();
+        assert!(KeyringError::unless_handshaken(&handshake).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_phrase_round_trip() {
+        let store = memory();
+        let key = generate_key(store.clone()).await.unwrap();
+        let phrase = export_phrase(store.clone()).await.unwrap();
+        assert_eq!(phrase.split(' ').count(), PHRASE_WORDS);
+
+        delete_key(store.clone()).await.unwrap();
+        // Written down by hand, so case and spacing vary
+        let typed = format!("  {}\n", phrase.to_uppercase().replace(' ', "   "));
+        import_phrase(store.clone(), Zeroizing::new(typed)).await.unwrap();
+        assert_eq!(read_key(store).await.unwrap(), key);
+    }
+
+    #[test]
+    fn test_bad_recovery_phrases_say_what_is_wrong() {
+        let zeros = ["abandon"; 23].join(" ");
+        assert_eq!(
+            key_from_phrase(&format!("{} art", zeros)).unwrap(),
+            general_purpose::STANDARD.encode([0u8; KEY_LEN])
+        );
+
+        let short = key_from_phrase(&["abandon"; 12].join(" ")).unwrap_err().to_string();
+        assert!(short.contains("24 words, got 12"), "{}", short);
+        let unknown = key_from_phrase(&format!("{} arbor", zeros)).unwrap_err().to_string();
+        assert!(unknown.contains("Word 24 (\"arbor\")"), "{}", unknown);
+        let checksum = key_from_phrase(&format!("{} abandon", zeros)).unwrap_err().to_string();
+        assert!(checksum.contains("checksum"), "{}", checksum);
+    }
+
+    #[test]
+    fn test_named_secrets_are_indexed_and_kept_apart() {
+        let store = mock::MemorySecrets::default();
+        set_named(&store, "github_token", "ghp_1").unwrap();
+        set_named(&store, "openai key", "sk-1").unwrap();
+        set_named(&store, "github_token", "ghp_2").unwrap();
+        assert_eq!(get_named(&store, "github_token").unwrap(), "ghp_2");
+        assert_eq!(
+            read_index(&store).unwrap().into_iter().collect::<Vec<_>>(),
+            ["github_token", "openai key"]
+        );
+
+        delete_named(&store, "github_token").unwrap();
+        delete_named(&store, "github_token").unwrap();
+        assert!(get_named(&store, "github_token").is_err());
+        assert_eq!(
+            named_secret_entries(&store).unwrap(),
+            ["secret:openai key", "secret_index"]
+        );
+        assert!(store.find(KEY_NAME).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_secret_names_are_validated() {
+        let store = mock::MemorySecrets::default();
+        store.set(KEY_NAME, TEST_KEY).unwrap();
+        for name in ["", "  ", "line\nbreak", &"x".repeat(MAX_SECRET_NAME_LEN + 1)] {
+            assert!(set_named(&store, name, "v").is_err(), "{:?}", name);
+        }
+        let reserved = set_named(&store, KEY_NAME, "clobbered").unwrap_err().to_string();
+        assert!(reserved.contains("reserved"), "{}", reserved);
+        assert!(delete_named(&store, KEY_NAME).is_err());
+        assert_eq!(store.get(KEY_NAME).unwrap(), TEST_KEY);
+        assert!(set_named(&store, &"x".repeat(MAX_SECRET_NAME_LEN), "v").is_ok());
+    }
+}
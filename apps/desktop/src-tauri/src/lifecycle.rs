@@ -0,0 +1,209 @@
+// Why services stopped
+// Every path that stops or restarts services records who did it: the user,
+// closing the window, an update, safe mode, a config change, a crash or
+// uninstalling. Reasons are kept in the app data dir, so the last one
+// survives the app quitting along with the services, and each is broadcast
+// as `services-stopped` for notifications.
+
+use crate::events::{self, EventSink};
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::State;
+
+pub const LIFECYCLE_FILE_NAME: &str = "lifecycle.json";
+/// Emitted with a StopReason whenever services stop or restart
+pub const SERVICES_STOPPED_EVENT: &str = "services-stopped";
+
+const MAX_HISTORY: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopInitiator {
+    User,
+    WindowClose,
+    Update,
+    SafeMode,
+    ConfigChange,
+    Crash,
+    Uninstall,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StopReason {
+    pub initiator: StopInitiator,
+    pub at: String,
+    /// Job that stopped them, e.g. an update
+    pub operation_id: Option<String>,
+    /// The services affected; empty for the whole stack
+    pub services: Vec<String>,
+    /// Brought straight back up, as by a restart
+    pub restarted: bool,
+    /// `make down` hung, so the containers were force-removed
+    #[serde(default)]
+    pub forced: bool,
+    /// Why the stop failed, or how a service crashed
+    pub error: Option<String>,
+}
+
+impl StopReason {
+    pub fn new(initiator: StopInitiator) -> Self {
+        Self {
+            initiator,
+            at: chrono::Utc::now().to_rfc3339(),
+            operation_id: None,
+            services: Vec::new(),
+            restarted: false,
+            forced: false,
+            error: None,
+        }
+    }
+
+    pub fn with_operation(mut self, id: &str) -> Self {
+        self.operation_id = Some(id.to_string());
+        self
+    }
+
+    pub fn with_services(mut self, services: &[String]) -> Self {
+        self.services = services.to_vec();
+        self
+    }
+
+    pub fn with_error(mut self, error: impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+
+    pub fn restarted(mut self) -> Self {
+        self.restarted = true;
+        self
+    }
+
+    pub fn forced(mut self) -> Self {
+        self.forced = true;
+        self
+    }
+}
+
+/// Recent stop reasons, newest last
+#[derive(Clone)]
+pub struct Lifecycle {
+    sink: Arc<dyn EventSink>,
+    history: Arc<Mutex<VecDeque<StopReason>>>,
+    path: Option<Arc<PathBuf>>,
+}
+
+impl Lifecycle {
+    pub fn new(sink: Arc<dyn EventSink>) -> Self {
+        Self {
+            sink,
+            history: Arc::new(Mutex::new(VecDeque::new())),
+            path: None,
+        }
+    }
+
+    /// Keep the history at `path`, loading what earlier sessions recorded
+    pub fn with_store(mut self, path: PathBuf) -> Self {
+        let history = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("⚠️  Ignoring invalid lifecycle history {:?}: {}", path, e);
+                VecDeque::new()
+            }),
+            Err(_) => VecDeque::new(),
+        };
+        self.history = Arc::new(Mutex::new(history));
+        self.path = Some(Arc::new(path));
+        self
+    }
+
+    pub fn record(&self, reason: StopReason) {
+        info!(
+            "📝 Services {} by {:?}",
+            if reason.restarted {
+                "restarted"
+            } else {
+                "stopped"
+            },
+            reason.initiator
+        );
+        let history = {
+            let mut history = self.history.lock().unwrap();
+            history.push_back(reason.clone());
+            while history.len() > MAX_HISTORY {
+                history.pop_front();
+            }
+            history.clone()
+        };
+        if let Some(path) = &self.path {
+            if let Err(e) = save(path, &history) {
+                warn!("⚠️  {}", e);
+            }
+        }
+        events::emit(&*self.sink, SERVICES_STOPPED_EVENT, &reason);
+    }
+
+    pub fn last_stop(&self) -> Option<StopReason> {
+        self.history.lock().unwrap().back().cloned()
+    }
+
+    pub fn history(&self) -> Vec<StopReason> {
+        self.history.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+fn save(path: &Path, history: &VecDeque<StopReason>) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create lifecycle history directory: {}", e))?;
+    }
+    let contents = serde_json::to_string_pretty(history).map_err(|e| e.to_string())?;
+    std::fs::write(path, contents).map_err(|e| format!("Failed to save lifecycle history: {}", e))
+}
+
+/// Stop and restart reasons, oldest first
+#[tauri::command]
+pub async fn get_lifecycle_history(
+    lifecycle: State<'_, Lifecycle>,
+) -> Result<Vec<StopReason>, String> {
+    Ok(lifecycle.history())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_reasons_are_broadcast_kept_and_bounded() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let path = dir.join(LIFECYCLE_FILE_NAME);
+        let sink = Arc::new(RecordingSink::default());
+        let lifecycle = Lifecycle::new(sink.clone()).with_store(path.clone());
+        assert_eq!(lifecycle.last_stop(), None);
+
+        for _ in 0..MAX_HISTORY {
+            lifecycle.record(StopReason::new(StopInitiator::User));
+        }
+        lifecycle.record(
+            StopReason::new(StopInitiator::Update)
+                .with_operation("job-3")
+                .restarted(),
+        );
+
+        let events = sink.events_named(SERVICES_STOPPED_EVENT);
+        assert_eq!(events.len(), MAX_HISTORY + 1);
+        assert_eq!(events[MAX_HISTORY]["initiator"], "update");
+        assert_eq!(events[MAX_HISTORY]["operation_id"], "job-3");
+
+        // The app quitting with the services doesn't lose why they stopped
+        let reloaded = Lifecycle::new(sink).with_store(path);
+        assert_eq!(reloaded.history().len(), MAX_HISTORY);
+        let last = reloaded.last_stop().unwrap();
+        assert_eq!(last.initiator, StopInitiator::Update);
+        assert!(last.restarted);
+    }
+}
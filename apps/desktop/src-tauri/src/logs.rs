@@ -0,0 +1,274 @@
+// Service log streaming
+// `follow_logs` runs `docker compose logs -f` for the stack and streams its
+// lines to the frontend as `service-logs` batches through an EventBatcher,
+// each entry carrying the service it came from. The frontend acks each
+// batch with `ack_log_batch` and ends the stream with `stop_logs`.
+// While the stack starts, `make up`'s own output is forwarded line by line
+// as `service-log`, so a failing pull or port conflict shows in the UI.
+// When it exits, `services-exited` says with what code, and a failure is
+// logged with the last lines it printed to stderr.
+
+use crate::batch::{BatchLimits, EventBatcher};
+use crate::docker;
+use crate::events::{self, EventSink};
+use crate::paths;
+use crate::process::{self, OutputLine, OutputStream, PipedChild};
+use crate::services::ComposeSetup;
+use crate::settings::SettingsStore;
+use crate::tasks::TaskManager;
+use crate::ServiceManager;
+use crate::{error, info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Emitted with a batch of `{ source: service, seq, data: line }` entries
+pub const SERVICE_LOGS_EVENT: &str = "service-logs";
+/// Emitted with each ServiceLogLine `make up` prints
+pub const SERVICE_LOG_EVENT: &str = "service-log";
+/// Emitted with a ServicesExited once `make up` has exited
+pub const SERVICES_EXITED_EVENT: &str = "services-exited";
+const DEFAULT_TAIL: u32 = 200;
+/// stderr lines of `make up` kept for its exit report
+const EXIT_STDERR_LINES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceLogLine {
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServicesExited {
+    /// None if it was killed by a signal or couldn't be waited for
+    pub code: Option<i32>,
+    /// The last lines it printed to stderr
+    pub stderr: Vec<String>,
+}
+
+struct LogStream {
+    batcher: Arc<EventBatcher<String>>,
+    cancel: CancellationToken,
+}
+
+/// Log streams being followed, by stream id
+#[derive(Default)]
+pub struct LogStreams {
+    streams: Mutex<HashMap<String, LogStream>>,
+    next_id: AtomicU64,
+}
+
+/// The service and text of a `docker compose logs` line, which compose
+/// prefixes with the container name padded to a column
+pub fn parse_line(line: &str) -> (&str, &str) {
+    match line.split_once(" | ") {
+        Some((container, text)) => (docker::service_of(container.trim()), text),
+        None => ("", line),
+    }
+}
+
+/// Emit each line `make up` prints until its pipes close, then reap it and
+/// report how it exited
+pub async fn monitor_make_up(
+    sink: &dyn EventSink,
+    child: PipedChild,
+    mut lines: mpsc::Receiver<OutputLine>,
+) -> ServicesExited {
+    let mut stderr = VecDeque::new();
+    while let Some(OutputLine { stream, line }) = lines.recv().await {
+        if stream == OutputStream::Stderr {
+            if stderr.len() == EXIT_STDERR_LINES {
+                stderr.pop_front();
+            }
+            stderr.push_back(line.clone());
+        }
+        events::emit(sink, SERVICE_LOG_EVENT, &ServiceLogLine { stream, line });
+    }
+
+    let code = match child.wait().await {
+        Ok(exit) => exit.code,
+        Err(e) => {
+            warn!("⚠️  Failed to wait for make up: {}", e);
+            None
+        }
+    };
+    let exited = ServicesExited {
+        code,
+        stderr: stderr.into(),
+    };
+    if code != Some(0) {
+        error!("❌ make up exited with {:?}", code);
+        for line in &exited.stderr {
+            warn!("   {}", line);
+        }
+    }
+    events::emit(sink, SERVICES_EXITED_EVENT, &exited);
+    exited
+}
+
+/// Follow the logs of `services`, or of the whole stack when empty; returns
+/// the id the stream's batches carry
+#[tauri::command]
+pub async fn follow_logs(
+    app_handle: AppHandle,
+    streams: State<'_, LogStreams>,
+    services: Vec<String>,
+    tail: Option<u32>,
+) -> Result<String, String> {
+    let project_root = crate::find_project_root(&app_handle)?;
+    let data_dir = paths::data_dir(&app_handle)?;
+    let setup = app_handle
+        .state::<ServiceManager>()
+        .active_setup
+        .lock()
+        .clone()
+        .unwrap_or_else(|| {
+            ComposeSetup::from_settings(
+                &app_handle.state::<SettingsStore>().get(),
+                &project_root,
+                &data_dir,
+            )
+        });
+    let tail = tail.unwrap_or(DEFAULT_TAIL).to_string();
+    let spec = setup
+        .compose_command(&project_root)
+        .args(["logs", "-f", "--no-color", "--tail", &tail])
+        .args(services.iter().cloned());
+    let (child, mut lines) =
+        process::spawn_piped(&spec).map_err(|e| format!("Failed to follow logs: {}", e))?;
+
+    let id = format!(
+        "logs-{}",
+        streams.next_id.fetch_add(1, Ordering::SeqCst) + 1
+    );
+    let batcher = Arc::new(EventBatcher::new(
+        Arc::new(app_handle.clone()),
+        SERVICE_LOGS_EVENT,
+        &id,
+        BatchLimits::default(),
+    ));
+    let stream = batcher.clone();
+    let task = format!("follow_logs:{}", id);
+    let cancel = app_handle.state::<TaskManager>().spawn(&task, async move {
+        // Dropping the child when the task ends kills `docker compose logs`
+        let _child = child;
+        let mut window = tokio::time::interval(stream.limits().window);
+        loop {
+            tokio::select! {
+                line = lines.recv() => match line {
+                    Some(line) => {
+                        let (service, text) = parse_line(&line.line);
+                        stream.push(service, text.to_string());
+                    }
+                    None => break,
+                },
+                _ = window.tick() => stream.flush(),
+            }
+        }
+        stream.flush();
+    });
+    streams
+        .streams
+        .lock()
+        .unwrap()
+        .insert(id.clone(), LogStream { batcher, cancel });
+    info!("📜 Following logs as {}", id);
+    Ok(id)
+}
+
+/// The frontend handled the stream's batches up to `batch`
+#[tauri::command]
+pub async fn ack_log_batch(
+    streams: State<'_, LogStreams>,
+    stream_id: String,
+    batch: u64,
+) -> Result<(), String> {
+    let streams = streams.streams.lock().unwrap();
+    let stream = streams
+        .get(&stream_id)
+        .ok_or_else(|| format!("Unknown log stream {}", stream_id))?;
+    stream.batcher.ack(batch);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn stop_logs(streams: State<'_, LogStreams>, stream_id: String) -> Result<(), String> {
+    let stream = streams
+        .streams
+        .lock()
+        .unwrap()
+        .remove(&stream_id)
+        .ok_or_else(|| format!("Unknown log stream {}", stream_id))?;
+    stream.cancel.cancel();
+    let dropped = stream.batcher.total_dropped();
+    if dropped > 0 {
+        info!(
+            "📜 Stopped {}; {} lines dropped while the view lagged",
+            stream_id, dropped
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_lines_are_attributed_to_their_service() {
+        assert_eq!(
+            parse_line("arbor-postgres  | LOG:  database system is ready"),
+            ("postgres", "LOG:  database system is ready")
+        );
+        assert_eq!(parse_line("arbor-api | a | b"), ("api", "a | b"));
+        assert_eq!(parse_line("no prefix"), ("", "no prefix"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_startup_output_is_forwarded_line_by_line() {
+        use crate::process::CommandSpec;
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir).unwrap();
+        let script = dir.join("make-up.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             echo pulling\n\
+             echo 'port is already allocated' >&2\n\
+             printf 'caf\\351\\n'\n\
+             exit 2\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let spec = CommandSpec::new(script.display().to_string());
+        let (child, lines) = process::spawn_piped(&spec).unwrap();
+        let sink = RecordingSink::default();
+
+        let exited = monitor_make_up(&sink, child, lines).await;
+        assert_eq!(exited.code, Some(2));
+        assert_eq!(exited.stderr, ["port is already allocated"]);
+        assert_eq!(
+            sink.events_named(SERVICES_EXITED_EVENT),
+            [serde_json::json!({ "code": 2, "stderr": ["port is already allocated"] })]
+        );
+
+        let mut events = sink.events_named(SERVICE_LOG_EVENT);
+        events.sort_by_key(|event| event["stream"].to_string());
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["stream"], "stderr");
+        assert_eq!(events[0]["line"], "port is already allocated");
+        // Not valid UTF-8, but still shown
+        assert!(events[1..]
+            .iter()
+            .any(|event| event["line"].as_str().unwrap().starts_with("caf")));
+    }
+}
@@ -1,165 +1,1174 @@
 // Prevents additional console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod archive;
+mod backup;
+mod batch;
+mod build_info;
+mod capabilities;
+mod cleanup;
+mod command_log;
+mod compat;
+mod compose;
+mod config_watch;
+mod confirm;
+mod crashes;
+mod crypto;
+mod diagnostics;
+mod disk;
+mod docker;
+mod doctor;
+mod endpoints;
+mod encoding;
+mod errors;
+mod events;
+mod flight;
+mod instance;
+mod jobs;
 mod keyring;
+mod lifecycle;
+mod logs;
+mod makefile;
+mod messages;
+mod metrics;
+mod migrations;
+mod network;
+mod notify;
+mod paths;
+mod ports;
+mod postmortem;
+mod process;
+mod profiles;
+mod project_config;
+mod project_root;
+mod protocol;
+mod pull;
+mod quit;
+mod readiness;
+mod relaunch;
+mod schedule;
+mod secret_file;
+mod services;
+mod settings;
+mod settings_profile;
+mod smoke;
+mod snapshot;
+mod splash;
+mod stats;
+mod subnets;
+mod status;
+mod support;
+mod sync;
+mod tasks;
+#[cfg(test)]
+mod test_support;
+mod tools;
+mod tray;
+mod trust;
+mod uninstall;
+mod updates;
+mod watchdog;
 
-use std::process::{Child, Command};
-use std::sync::Mutex;
-use tauri::{Manager, State};
+use build_info::BuildInfo;
+use compat::VersionGate;
+use config_watch::ConfigWatcher;
+use crashes::{CrashMonitor, RestartPolicies};
+use errors::ErrorHistory;
+use events::EventSink;
+use flight::LogLevel;
+use lifecycle::{Lifecycle, StopInitiator, StopReason};
+use notify::{NotificationKind, Notifier};
+use postmortem::PostMortemStore;
+use project_config::{ConfigSources, ProjectConfig};
+use project_root::{ProjectRoot, RootSources};
+use protocol::Handshake;
+use readiness::ReadinessLimits;
+use schedule::{BackgroundTasks, TaskKind};
+use services::{
+    AdoptionReport, ComposeSetup, RestartFailed, RestartPhase, Restarted, Restarting,
+    ServiceAction, ServiceBackend, ServiceState, ServiceStatus, StackConflict, StackState,
+    StopOutcome,
+};
+use settings::SettingsStore;
+use splash::StartupStage;
+use stats::StatsSampler;
+use status::{ProfileScope, StatusCache};
+use sync::Lock;
+use tasks::TaskManager;
+use trust::TrustStore;
+use watchdog::Watchdog;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager, RunEvent, State};
+use tokio_util::sync::CancellationToken;
 
+/// How long background tasks get to stop when the app exits
+const SHUTDOWN_DEADLINE: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+
+// Its locks recover from poisoning, so a panic in one command doesn't
+// leave every later start or stop panicking too
 struct ServiceManager {
-    docker_process: Mutex<Option<Child>>,
+    // Forwards `make up`'s output while it runs; cancelling kills it
+    make_up: Lock<Option<CancellationToken>>,
+    // Compose configuration the running stack was started with
+    active_setup: Lock<Option<ComposeSetup>>,
+    stack: Lock<StackState>,
+    // On from `start_safe_mode` until `exit_safe_mode`, across stops
+    safe_mode: Lock<bool>,
+    // App version that started the running stack, if known
+    stack_version: Lock<Option<String>>,
 }
 
 impl ServiceManager {
     fn new() -> Self {
         Self {
-            docker_process: Mutex::new(None),
+            make_up: Lock::new("make_up", None),
+            active_setup: Lock::new("active_setup", None),
+            stack: Lock::new("stack", StackState::Stopped),
+            safe_mode: Lock::new("safe_mode", false),
+            stack_version: Lock::new("stack_version", None),
+        }
+    }
+
+    fn in_safe_mode(&self) -> bool {
+        *self.safe_mode.lock()
+    }
+
+    /// Refuse `action` on a stack that was adopted rather than started by Arbor
+    fn ensure_owned(&self, action: &str) -> Result<(), String> {
+        self.stack.lock().ensure_owned(action)
+    }
+
+    /// Move the stack to the state `next` allows from the current one, in one
+    /// step under the lock, so a second start or stop can't slip in between;
+    /// returns the state it left, to go back to if the action fails
+    fn begin(
+        &self,
+        sink: &dyn EventSink,
+        next: fn(&StackState) -> Result<StackState, StackConflict>,
+    ) -> Result<StackState, String> {
+        let previous = {
+            let mut stack = self.stack.lock();
+            let previous = *stack;
+            *stack = next(&previous).map_err(|e| e.to_string())?;
+            previous
+        };
+        events::emit(sink, services::STACK_STATE_EVENT, &*self.stack.lock());
+        Ok(previous)
+    }
+
+    fn set_stack(&self, sink: &dyn EventSink, state: StackState) {
+        *self.stack.lock() = state;
+        events::emit(sink, services::STACK_STATE_EVENT, &state);
+    }
+}
+
+/// Keep a failed command in the error history, with a flight recording
+fn recorded<T>(app_handle: &AppHandle, command: &str, result: Result<T, String>) -> Result<T, String> {
+    if let Err(e) = &result {
+        app_handle.state::<ErrorHistory>().record_command_error(command, e);
+    }
+    result
+}
+
+/// Note in the launch state whether this launch owns a running stack, so the
+/// next one can tell containers a crash left behind from ones started elsewhere
+fn record_session(app_handle: &AppHandle, owned: bool) {
+    let recorded = relaunch::LaunchState::for_app(app_handle).and_then(|state| match owned {
+        true => state.record_session(),
+        false => state.clear_session(),
+    });
+    if let Err(e) = recorded {
+        warn!("⚠️  {}", e);
+    }
+}
+
+/// The Arbor checkout the stack is run from
+fn find_project_root(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let settings = app_handle.state::<SettingsStore>().get();
+    app_handle
+        .state::<ProjectRoot>()
+        .get(settings.project_root.as_deref())
+}
+
+/// Bring the stack up
+/// Refused, rather than a no-op, unless the stack is stopped: a second click
+/// or the startup hook racing a manual start gets an "already starting" or
+/// "already running" error instead of spawning another `make up`. `force`
+/// starts even with less disk space than `disk_space.refuse_below_gb`.
+/// `profile` is the compose profile to start with, kept as `service_profile`
+/// once it's found in the compose files.
+#[tauri::command]
+async fn start_services(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+    force: Option<bool>,
+    profile: Option<String>,
+) -> Result<String, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    start_stack(app_handle, service_manager, settings, force, profile).await
+}
+
+/// `start_services` for the app's own callers, the startup hook and the
+/// tray, which can run before the webview has shaken hands
+pub(crate) async fn start_stack(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+    force: Option<bool>,
+    profile: Option<String>,
+) -> Result<String, String> {
+    info!("🚀 Starting Arbor services...");
+    service_manager.ensure_owned("start services")?;
+    if service_manager.in_safe_mode() {
+        return Err("Safe mode is on; exit safe mode to start every service".to_string());
+    }
+    if let Some(profile) = &profile {
+        settings::validate_service_profile(profile)?;
+        // What the running profile started has to come down first
+        let active = service_manager
+            .active_setup
+            .lock()
+            .as_ref()
+            .map(|setup| setup.profile.clone());
+        if let Some(active) = active.filter(|active| active.as_ref() != Some(profile)) {
+            let running = match active {
+                Some(active) => format!("profile {}", active),
+                None => "no profile".to_string(),
+            };
+            return Err(format!(
+                "Services are running with {}; restart them to switch to profile {}",
+                running, profile
+            ));
         }
     }
+    // Started outside Arbor (e.g. `make up` in a terminal): watch it instead of running make up
+    service_manager.stack.lock().start().map_err(|e| e.to_string())?;
+    if let Some(report) = find_unowned_stack(&app_handle).await {
+        let report = adopt_stack(&app_handle, &service_manager, &settings, report).await?;
+        return Ok(format!("Adopted {} running services", report.services.len()));
+    }
+    let force = force.unwrap_or(false);
+    let result = launch_stack(&app_handle, &service_manager, &settings, force, profile).await;
+    if let Err(e) = &result {
+        app_handle
+            .state::<Notifier>()
+            .notify("Arbor services failed to start", e.as_str(), NotificationKind::StartFailed);
+    }
+    recorded(&app_handle, "start_services", result)
 }
 
+/// Start only the services marked essential in the config, for troubleshooting
+/// The crash monitor stops acting on crashes, stats sampling pauses and debug
+/// lines are printed. Safe mode holds, across stops, until `exit_safe_mode`.
 #[tauri::command]
-async fn start_services(service_manager: State<'_, ServiceManager>) -> Result<String, String> {
-    println!("🚀 Starting Arbor services...");
+async fn start_safe_mode(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<String, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    info!("🩺 Starting Arbor in safe mode...");
+    service_manager.ensure_owned("start safe mode")?;
+    service_manager.stack.lock().start().map_err(|e| e.to_string())?;
+
+    *service_manager.safe_mode.lock() = true;
+    app_handle.state::<RestartPolicies>().set_suspended(true);
+    app_handle
+        .state::<BackgroundTasks>()
+        .set_paused(TaskKind::StatsSample, true);
+    flight::set_base_level(LogLevel::Debug);
 
-    // Get the project root by finding the directory containing the Makefile
-    // Start from current dir and walk up until we find it
-    let mut project_root = std::env::current_dir()
-        .map_err(|e| format!("Failed to get current directory: {}", e))?;
+    let result = launch_stack(&app_handle, &service_manager, &settings, false, None).await;
+    recorded(&app_handle, "start_safe_mode", result)
+}
+
+/// Leave safe mode, starting the services it skipped if the stack is up
+#[tauri::command]
+async fn exit_safe_mode(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+) -> Result<String, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    service_manager.ensure_owned("exit safe mode")?;
+    if !service_manager.in_safe_mode() {
+        return Err("Safe mode is not on".to_string());
+    }
 
-    loop {
-        let makefile_path = project_root.join("Makefile");
-        if makefile_path.exists() {
-            break;
+    let active = service_manager.active_setup.lock().clone();
+    if let Some(mut setup) = active.filter(|setup| !setup.only_services.is_empty()) {
+        info!("🚀 Starting the services safe mode skipped...");
+        let project_root = find_project_root(&app_handle)?;
+        app_handle.state::<TrustStore>().check(
+            &app_handle,
+            &project_root,
+            &app_handle.state::<SettingsStore>().get(),
+        )?;
+        setup.only_services.clear();
+        services::restart_services(&process::SystemRunner, &project_root, &setup, &[]).await?;
+        app_handle
+            .state::<Lifecycle>()
+            .record(StopReason::new(StopInitiator::SafeMode).restarted());
+        *service_manager.active_setup.lock() = Some(setup);
+
+        let config = ProjectConfig::load(&project_root, &app_handle.state::<ConfigSources>())?;
+        let limits = ReadinessLimits::resolve(&app_handle.state::<SettingsStore>().get(), &config)?;
+        app_handle
+            .state::<TaskManager>()
+            .spawn("wait_for_services", wait_for_services(app_handle.clone(), limits));
+    }
+
+    *service_manager.safe_mode.lock() = false;
+    app_handle.state::<RestartPolicies>().set_suspended(false);
+    app_handle
+        .state::<BackgroundTasks>()
+        .set_paused(TaskKind::StatsSample, false);
+    let level = app_handle.state::<SettingsStore>().get().log_level;
+    flight::set_base_level(flight::configured_level(level));
+
+    info!("✅ Safe mode exited");
+    Ok("Safe mode exited".to_string())
+}
+
+/// Bring the stack up with `make up`; only the essential services in safe mode
+async fn launch_stack(
+    app_handle: &AppHandle,
+    service_manager: &ServiceManager,
+    settings: &SettingsStore,
+    force: bool,
+    profile: Option<String>,
+) -> Result<String, String> {
+    let previous = service_manager.begin(app_handle, StackState::start)?;
+    let result = run_make_up(app_handle, service_manager, settings, force, profile).await;
+    let state = match result {
+        Ok(_) => {
+            app_handle.state::<Watchdog>().arm();
+            record_session(app_handle, true);
+            StackState::Running { adopted: false }
         }
+        Err(_) => previous,
+    };
+    service_manager.set_stack(app_handle, state);
+    result
+}
+
+async fn run_make_up(
+    app_handle: &AppHandle,
+    service_manager: &ServiceManager,
+    settings: &SettingsStore,
+    force: bool,
+    profile: Option<String>,
+) -> Result<String, String> {
+    let app_handle = app_handle.clone();
 
-        project_root = project_root
-            .parent()
-            .ok_or("Failed to find project root (no Makefile found)")?
-            .to_path_buf();
+    let project_root = find_project_root(&app_handle)?;
+    info!("📁 Project root: {:?}", project_root);
+
+    // Snap/flatpak Docker can't mount paths outside its sandbox; fail before compose does
+    if let Err(e) = docker::check_confinement(&process::SystemRunner, &project_root).await {
+        events::emit_coded(&app_handle, docker::DOCKER_UNAVAILABLE_EVENT, &e);
+        return Err(e.to_string());
+    }
+    // make up runs `docker compose`, whose absence it reports as a usage error
+    match compose::ensure_usable(&process::SystemRunner, &tools::located()).await {
+        Ok(install) => info!("🐙 Docker Compose {} ({:?})", install.version, install.variant),
+        Err(e) => {
+            events::emit_coded(&app_handle, compose::COMPOSE_MISSING_EVENT, &e);
+            return Err(e.to_string());
+        }
     }
 
-    println!("📁 Project root: {:?}", project_root);
+    // Read on every start, so changed timeouts apply without an app restart
+    let current_settings = settings.get();
+    app_handle
+        .state::<TrustStore>()
+        .check(&app_handle, &project_root, &current_settings)?;
+    // make up only recreates changed containers; refuse to start next to ones this version can't talk to
+    app_handle
+        .state::<VersionGate>()
+        .check(&app_handle, &process::SystemRunner)
+        .await?;
+    let config = ProjectConfig::load(&project_root, &app_handle.state::<ConfigSources>())?;
+    let policies = app_handle.state::<RestartPolicies>();
+    policies.replace(&config);
+    policies.clear_disabled();
+    let limits = ReadinessLimits::resolve(&current_settings, &config)?;
+
+    let data_dir = paths::data_dir(&app_handle)?;
+    let mut setup = ComposeSetup::from_settings(&current_settings, &project_root, &data_dir);
+    if profile.is_some() {
+        setup.profile = profile.clone();
+    }
+    if service_manager.in_safe_mode() {
+        setup.only_services = config.essential_services();
+        if setup.only_services.is_empty() {
+            return Err("No services are marked essential in the config".to_string());
+        }
+        info!("🩺 Safe mode: starting only {:?}", setup.only_services);
+    }
+    setup.prepare()?;
+    compose::ensure_profile(&process::SystemRunner, &project_root, &setup).await?;
+    if let Some(active) = &setup.profile {
+        info!("🧩 Starting with compose profile {}", active);
+    }
+    // Restarts and later starts use it too
+    if profile.is_some() && profile != current_settings.service_profile {
+        settings.set("service_profile", serde_json::json!(profile))?;
+    }
+    // Services the profile leaves out don't count against the stack's status
+    match compose::enabled_services(&process::SystemRunner, &project_root, &setup).await {
+        Ok(services) => app_handle.state::<StatusCache>().set_scope(Some(ProfileScope {
+            profile: setup.profile.clone(),
+            services,
+        })),
+        Err(e) => warn!("⚠️  Couldn't list the profile's services: {}", e),
+    }
+    if let Some(override_file) = &setup.override_file {
+        info!("🛠️  Dev mode: using compose override {:?}", override_file);
+    }
+    for mount in &setup.source_mounts {
+        info!(
+            "🛠️  Dev mode: mounting {:?} into {} at {:?}",
+            mount.host_path, mount.service, mount.container_path
+        );
+    }
+
+    // Fail before make up does, with the ports and who holds them
+    match ports::check(&process::SystemRunner, &project_root, &setup).await {
+        Ok(conflicts) if !conflicts.is_empty() => {
+            let e = ports::PortsInUse { conflicts };
+            events::emit_coded(&app_handle, ports::PORTS_IN_USE_EVENT, &e);
+            return Err(e.to_string());
+        }
+        Ok(_) => {}
+        Err(e) => warn!("⚠️  Skipped the port check: {}", e),
+    }
+
+    // Image pulls need room; fail before one runs out of it halfway
+    let disk_space = disk::check(&process::SystemRunner).await;
+    match disk_space.map(|space| space.verdict(&current_settings.disk_space)) {
+        Ok(disk::Verdict::Insufficient(e)) if force => {
+            warn!("⚠️  Starting anyway: {}", e);
+        }
+        Ok(disk::Verdict::Insufficient(e)) => {
+            events::emit_coded(&app_handle, disk::INSUFFICIENT_DISK_SPACE_EVENT, &e);
+            return Err(e.to_string());
+        }
+        Ok(disk::Verdict::Low(low)) => {
+            let free_gb = low.free_bytes as f64 / 1e9;
+            warn!("⚠️  Low on disk space: {:.1} GB free at {}", free_gb, low.path);
+            events::emit_coded(&app_handle, disk::LOW_DISK_SPACE_EVENT, &low);
+        }
+        Ok(disk::Verdict::Enough) => {}
+        Err(e) => warn!("⚠️  Skipped the disk space check: {}", e),
+    }
+
+    // Follow the files this stack is started with
+    if let Err(e) = app_handle
+        .state::<ConfigWatcher>()
+        .watch(setup.watched_files(&project_root))
+    {
+        warn!("⚠️  {}", e);
+    }
 
     // Start Docker services using make
-    let child = Command::new("make")
-        .arg("up")
-        .current_dir(&project_root)
-        .spawn()
-        .map_err(|e| format!("Failed to start services: {}", e))?;
+    splash::advance(&app_handle, StartupStage::ServicesStarting);
+    match setup.backend {
+        ServiceBackend::Make => info!("🧰 Starting services with make"),
+        ServiceBackend::Compose => {
+            info!("🧰 make isn't installed; starting services with docker compose")
+        }
+    }
+    let spec = setup.up_command(&project_root);
+    let (child, lines) =
+        process::spawn_piped(&spec).map_err(|e| format!("Failed to start services: {}", e))?;
+    let sink = app_handle.clone();
+    let make_up = app_handle.state::<TaskManager>().spawn("make_up", async move {
+        logs::monitor_make_up(&sink, child, lines).await;
+        // Reaped; nothing is left for a stop to kill
+        sink.state::<ServiceManager>().make_up.lock().take();
+    });
 
-    // Store the process handle
-    let mut process = service_manager.docker_process.lock().unwrap();
-    *process = Some(child);
+    // Keep the output forwarded while make up runs
+    *service_manager.make_up.lock() = Some(make_up);
+    *service_manager.active_setup.lock() = Some(setup);
+    *service_manager.stack_version.lock() = Some(compat::app_version().to_string());
 
-    println!("✅ Services started successfully");
+    app_handle
+        .state::<TaskManager>()
+        .spawn("wait_for_services", wait_for_services(app_handle.clone(), limits));
+
+    info!("✅ Services started successfully");
     Ok("Services started successfully".to_string())
 }
 
-#[tauri::command]
-async fn stop_services(service_manager: State<'_, ServiceManager>) -> Result<String, String> {
-    println!("🛑 Stopping Arbor services...");
+/// Take over a stack left running by a relaunch, without restarting it
+async fn adopt_services(
+    app_handle: AppHandle,
+    setup: Option<ComposeSetup>,
+    stack_version: Option<String>,
+) -> Result<(), String> {
+    let containers = docker::list_containers(&process::SystemRunner).await?;
+    let running = containers.iter().filter(|c| c.is_running()).count();
+    if running == 0 {
+        return Err("no Arbor containers are running".to_string());
+    }
+
+    let project_root = find_project_root(&app_handle)?;
+    let settings = app_handle.state::<SettingsStore>().get();
+    let config = ProjectConfig::load(&project_root, &app_handle.state::<ConfigSources>())?;
+    app_handle.state::<RestartPolicies>().replace(&config);
+    let limits = ReadinessLimits::resolve(&settings, &config)?;
+    let setup = match setup {
+        Some(setup) => setup,
+        None => {
+            let data_dir = paths::data_dir(&app_handle)?;
+            ComposeSetup::from_settings(&settings, &project_root, &data_dir)
+        }
+    };
+
+    if let Err(e) = app_handle
+        .state::<ConfigWatcher>()
+        .watch(setup.watched_files(&project_root))
+    {
+        warn!("⚠️  {}", e);
+    }
+    let service_manager = app_handle.state::<ServiceManager>();
+    service_manager.stack.lock().settled().map_err(|e| e.to_string())?;
+    *service_manager.active_setup.lock() = Some(setup);
+    // Started by the previous launch, so still ours
+    service_manager.set_stack(&app_handle, StackState::Running { adopted: false });
+    app_handle.state::<Watchdog>().arm();
+    record_session(&app_handle, true);
+    *service_manager.stack_version.lock() = stack_version;
 
-    // Get the project root by finding the directory containing the Makefile
-    let mut project_root = std::env::current_dir()
-        .map_err(|e| format!("Failed to get current directory: {}", e))?;
+    info!("♻️  Adopted {} running containers", running);
+    app_handle
+        .state::<TaskManager>()
+        .spawn("wait_for_services", wait_for_services(app_handle.clone(), limits));
+    Ok(())
+}
 
-    loop {
-        let makefile_path = project_root.join("Makefile");
-        if makefile_path.exists() {
-            break;
+/// A running stack this launch didn't start, if there is one
+async fn find_unowned_stack(app_handle: &AppHandle) -> Option<AdoptionReport> {
+    let project_root = find_project_root(app_handle).ok()?;
+    let data_dir = paths::data_dir(app_handle).ok()?;
+    let setup = ComposeSetup::from_settings(&app_handle.state::<SettingsStore>().get(), &project_root, &data_dir);
+    match services::inspect_running_stack(&process::SystemRunner, &project_root, &setup).await {
+        Ok(report) if !report.services.is_empty() => Some(report),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("⚠️  Failed to check for a running stack: {}", e);
+            None
         }
+    }
+}
 
-        project_root = project_root
-            .parent()
-            .ok_or("Failed to find project root (no Makefile found)")?
-            .to_path_buf();
+/// Wait for the started stack to become ready and report the outcome
+async fn wait_for_services(app_handle: AppHandle, limits: ReadinessLimits) {
+    info!(
+        "⏳ Waiting for services to be ready (up to {}s)...",
+        limits.startup_timeout.as_secs()
+    );
+    let on_ready = |service: &str| {
+        splash::advance(&app_handle, StartupStage::ServiceReady { service: service.to_string() })
+    };
+    match readiness::wait_until_ready(&process::SystemRunner, limits, &on_ready).await {
+        Ok(services) => {
+            info!("✅ Arbor is ready!");
+            events::emit(&app_handle, readiness::SERVICES_READY_EVENT, &services);
+            splash::advance(&app_handle, StartupStage::ServicesReady);
+        }
+        Err(failure) => {
+            error!("❌ {}", failure);
+            events::emit(&app_handle, readiness::SERVICES_FAILED_EVENT, &failure);
+            app_handle.state::<Notifier>().notify(
+                "Arbor services failed to start",
+                failure.to_string(),
+                NotificationKind::StartFailed,
+            );
+            splash::advance(&app_handle, StartupStage::Failed {
+                message: failure.to_string(),
+                setup_required: false,
+            });
+        }
     }
+}
+
+/// Bring the stack down; the result says whether its containers had to be
+/// force-removed, and a stop that failed either way is an error
+#[tauri::command]
+async fn stop_services(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<StopOutcome, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    stop_for_user(app_handle, service_manager, settings).await
+}
+
+/// `stop_services` for the tray and `relaunch_app`, which needn't wait for
+/// the webview
+pub(crate) async fn stop_for_user(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<StopOutcome, String> {
+    let reason = StopReason::new(StopInitiator::User);
+    let result = stop_stack(app_handle.clone(), service_manager, settings, reason).await;
+    recorded(&app_handle, "stop_services", result)
+}
+
+/// Stop the stack and start it again, e.g. to apply changed settings
+/// A stack that isn't running is just started, once any containers a crashed
+/// session left are down; a failed stop isn't followed by a start, and the
+/// error says which phase failed.
+#[tauri::command]
+async fn restart_services(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<Restarted, RestartFailed> {
+    app_handle
+        .state::<Handshake>()
+        .ensure()
+        .map_err(|error| RestartFailed {
+            phase: RestartPhase::Start,
+            error,
+        })?;
+    restart_for_user(app_handle, service_manager, settings).await
+}
+
+/// `restart_services` for the tray, which needn't wait for the webview
+pub(crate) async fn restart_for_user(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<Restarted, RestartFailed> {
+    let result = restart_stack(&app_handle, service_manager, settings).await;
+    if let Err(failed) = &result {
+        app_handle
+            .state::<ErrorHistory>()
+            .record_command_error("restart_services", &failed.to_string());
+    }
+    result
+}
+
+async fn restart_stack(
+    app_handle: &AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<Restarted, RestartFailed> {
+    let failed = |phase| move |error| RestartFailed { phase, error };
+    service_manager
+        .ensure_owned("restart services")
+        .map_err(failed(RestartPhase::Start))?;
+
+    let stack = *service_manager.stack.lock();
+    stack
+        .settled()
+        .map_err(|e| failed(RestartPhase::Start)(e.to_string()))?;
+    // Containers a crashed session left running are taken down too, for a fresh start
+    let was_running = stack != StackState::Stopped || find_unowned_stack(app_handle).await.is_some();
+    info!("🔄 Restarting Arbor services...");
+    events::emit(app_handle, services::SERVICES_RESTARTING_EVENT, &Restarting { was_running });
+
+    let stopped = match was_running {
+        true => {
+            let reason = StopReason::new(StopInitiator::User).restarted();
+            let stopped = stop_stack(
+                app_handle.clone(),
+                service_manager.clone(),
+                settings.clone(),
+                reason,
+            )
+            .await
+            .map_err(failed(RestartPhase::Stop))?;
+            Some(stopped)
+        }
+        false => None,
+    };
+    launch_stack(app_handle, &service_manager, &settings, false, None)
+        .await
+        .map_err(failed(RestartPhase::Start))?;
+
+    let restarted = Restarted { stopped };
+    events::emit(app_handle, services::SERVICES_RESTARTED_EVENT, &restarted);
+    Ok(restarted)
+}
+
+/// Bring the stack down, recording `reason` once `make down` has run
+async fn stop_stack(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+    reason: StopReason,
+) -> Result<StopOutcome, String> {
+    info!("🛑 Stopping Arbor services...");
+    service_manager.ensure_owned("stop services")?;
+    let previous = service_manager.begin(&app_handle, StackState::stop)?;
+    // Containers going down now aren't crashes to recover from
+    app_handle.state::<Watchdog>().disarm();
+    let result = run_make_down(&app_handle, &service_manager, &settings, reason).await;
+    let state = match result {
+        Ok(_) => {
+            record_session(&app_handle, false);
+            StackState::Stopped
+        }
+        Err(_) => {
+            if previous == (StackState::Running { adopted: false }) {
+                app_handle.state::<Watchdog>().arm();
+            }
+            previous
+        }
+    };
+    service_manager.set_stack(&app_handle, state);
+    result
+}
+
+async fn run_make_down(
+    app_handle: &AppHandle,
+    service_manager: &ServiceManager,
+    settings: &SettingsStore,
+    reason: StopReason,
+) -> Result<StopOutcome, String> {
+    let project_root = find_project_root(app_handle)?;
+    let data_dir = paths::data_dir(app_handle)?;
+    app_handle
+        .state::<TrustStore>()
+        .check(app_handle, &project_root, &settings.get())?;
+
+    // Tear down with the files the stack was started with, so services that
+    // only exist in the dev override are removed too
+    let setup = service_manager
+        .active_setup
+        .lock()
+        .clone()
+        .unwrap_or_else(|| ComposeSetup::from_settings(&settings.get(), &project_root, &data_dir));
 
     // Stop Docker services using make
-    let output = Command::new("make")
-        .arg("down")
-        .current_dir(&project_root)
-        .output()
-        .map_err(|e| format!("Failed to stop services: {}", e))?;
+    let timeout = tokio::time::Duration::from_secs(settings.get().stop_timeout_secs);
+    let outcome =
+        match services::bring_down(&process::SystemRunner, &project_root, &setup, timeout).await {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                app_handle.state::<Lifecycle>().record(reason.with_error(&error));
+                return Err(error);
+            }
+        };
 
-    if !output.status.success() {
-        return Err(format!("Failed to stop services: {:?}", String::from_utf8_lossy(&output.stderr)));
+    // Kills make up if it's still running
+    if let Some(make_up) = service_manager.make_up.lock().take() {
+        make_up.cancel();
+    }
+    *service_manager.active_setup.lock() = None;
+    *service_manager.stack_version.lock() = None;
+    app_handle.state::<StatusCache>().set_scope(None);
+    match &outcome {
+        StopOutcome::Graceful => {
+            app_handle.state::<Lifecycle>().record(reason);
+            info!("✅ Services stopped successfully");
+        }
+        StopOutcome::Forced { removed } => {
+            app_handle.state::<Lifecycle>().record(reason.forced());
+            info!("⚠️  Services force-stopped; removed {}", removed.join(", "));
+        }
     }
+    Ok(outcome)
+}
+
+/// Start, stop or restart one service without touching the rest of the stack
+async fn control_service(
+    app_handle: &AppHandle,
+    service_manager: &ServiceManager,
+    action: ServiceAction,
+    service: &str,
+) -> Result<ServiceStatus, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    service_manager.ensure_owned(&format!("{} {}", action.command(), service))?;
+    service_manager.stack.lock().settled().map_err(|e| e.to_string())?;
 
-    // Clear the stored process
-    let mut process = service_manager.docker_process.lock().unwrap();
-    *process = None;
+    let project_root = find_project_root(app_handle)?;
+    let settings = app_handle.state::<SettingsStore>().get();
+    app_handle
+        .state::<TrustStore>()
+        .check(app_handle, &project_root, &settings)?;
+    let data_dir = paths::data_dir(app_handle)?;
+    let setup = service_manager
+        .active_setup
+        .lock()
+        .clone()
+        .unwrap_or_else(|| ComposeSetup::from_settings(&settings, &project_root, &data_dir));
 
-    println!("✅ Services stopped successfully");
-    Ok("Services stopped successfully".to_string())
+    info!("🔁 Running {} on {}...", action.command(), service);
+    // Held while it goes down, so the watchdog doesn't bring it back
+    let watchdog = app_handle.state::<Watchdog>();
+    if action != ServiceAction::Start {
+        watchdog.hold(service);
+    }
+    let result =
+        services::control_service(&process::SystemRunner, &project_root, &setup, action, service)
+            .await;
+    if action != ServiceAction::Stop || result.is_err() {
+        watchdog.release(service);
+    }
+    let status = result?;
+
+    let reason = StopReason::new(StopInitiator::User).with_services(&[service.to_string()]);
+    match action {
+        ServiceAction::Start => {}
+        ServiceAction::Stop => app_handle.state::<Lifecycle>().record(reason),
+        ServiceAction::Restart => app_handle.state::<Lifecycle>().record(reason.restarted()),
+    }
+    events::emit(app_handle, services::SERVICE_STATUS_EVENT, &status);
+    Ok(status)
 }
 
 #[tauri::command]
-async fn check_services_status() -> Result<String, String> {
-    // Check if Docker containers are running
-    let output = Command::new("docker")
-        .args(&["ps", "--filter", "name=arbor", "--format", "{{.Names}}"])
-        .output()
-        .map_err(|e| format!("Failed to check service status: {}", e))?;
+async fn start_service(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    name: String,
+) -> Result<ServiceStatus, String> {
+    let result = control_service(&app_handle, &service_manager, ServiceAction::Start, &name).await;
+    recorded(&app_handle, "start_service", result)
+}
 
-    let containers = String::from_utf8_lossy(&output.stdout);
-    let container_count = containers.lines().count();
+#[tauri::command]
+async fn stop_service(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    name: String,
+) -> Result<ServiceStatus, String> {
+    let result = control_service(&app_handle, &service_manager, ServiceAction::Stop, &name).await;
+    recorded(&app_handle, "stop_service", result)
+}
 
-    if container_count > 0 {
-        Ok(format!("Running ({} containers)", container_count))
-    } else {
-        Ok("Stopped".to_string())
+#[tauri::command]
+async fn restart_service(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    name: String,
+) -> Result<ServiceStatus, String> {
+    let result =
+        control_service(&app_handle, &service_manager, ServiceAction::Restart, &name).await;
+    recorded(&app_handle, "restart_service", result)
+}
+
+#[tauri::command]
+async fn check_services_status(status_cache: State<'_, StatusCache>) -> Result<String, String> {
+    // Served from the status cache, which docker events keep up to date
+    let report = status_cache.report().await;
+    match report.error {
+        Some(error) => Err(error),
+        None => Ok(report.summary()),
+    }
+}
+
+/// What starts and stops the services: that of the running stack, or what
+/// the next start would use
+#[tauri::command]
+fn get_service_backend(service_manager: State<'_, ServiceManager>) -> ServiceBackend {
+    match service_manager.active_setup.lock().as_ref() {
+        Some(setup) => setup.backend,
+        None => ServiceBackend::detect(&tools::located(), tools::runtime()),
     }
 }
 
 #[tauri::command]
-async fn check_docker_installed() -> Result<bool, String> {
-    // Check if Docker is installed by running `docker --version`
-    match Command::new("docker")
-        .arg("--version")
-        .output()
+async fn get_service_state(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<ServiceState, String> {
+    let project_root = find_project_root(&app_handle)?;
+    let data_dir = paths::data_dir(&app_handle)?;
+    let current = ComposeSetup::from_settings(&settings.get(), &project_root, &data_dir);
+    let active = service_manager.active_setup.lock().clone();
+    let stack = *service_manager.stack.lock();
+
+    // Drift is judged against the files the running stack actually uses
+    let effective = active.clone().unwrap_or_else(|| current.clone());
+    let drifted_services =
+        match services::detect_drift(&process::SystemRunner, &project_root, &effective).await {
+            Ok(drifted) => drifted,
+            Err(e) => {
+                warn!("⚠️  Drift detection failed: {}", e);
+                Vec::new()
+            }
+        };
+    let networks = match docker::inspect_compose_networks(&process::SystemRunner).await {
+        Ok(networks) => networks,
+        Err(e) => {
+            warn!("⚠️  Network inspection failed: {}", e);
+            Vec::new()
+        }
+    };
+
+    Ok(ServiceState {
+        dev_mode: effective.dev_mode,
+        compose_override: effective
+            .override_file
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        // Safe mode's service subset isn't a settings change
+        restart_required: active.is_some_and(|mut active| {
+            active.only_services.clear();
+            active != current
+        }),
+        drifted_services,
+        source_mounts: effective.source_mounts,
+        stack,
+        stops_on_exit: stack.stops_on_exit(settings.get().stop_adopted_on_exit),
+        safe_mode: service_manager.in_safe_mode(),
+        networks,
+        last_stop_reason: app_handle.state::<Lifecycle>().last_stop(),
+    })
+}
+
+/// Watch a stack started outside Arbor (e.g. `make up` in a terminal) or
+/// left running by a session that crashed
+/// Status, logs and stats work as usual; lifecycle actions are refused until
+/// `take_ownership_of_services`.
+#[tauri::command]
+async fn adopt_running_services(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+    settings: State<'_, SettingsStore>,
+) -> Result<AdoptionReport, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    service_manager.stack.lock().settled().map_err(|e| e.to_string())?;
+    let project_root = find_project_root(&app_handle)?;
+    let data_dir = paths::data_dir(&app_handle)?;
+    let setup = ComposeSetup::from_settings(&settings.get(), &project_root, &data_dir);
+
+    let report = services::inspect_running_stack(&process::SystemRunner, &project_root, &setup).await?;
+    if report.services.is_empty() {
+        return Err("No running Arbor services to adopt".to_string());
+    }
+    adopt_stack(&app_handle, &service_manager, &settings, report).await
+}
+
+/// Mark the stack `report` found as adopted, watched but left to whoever started it
+async fn adopt_stack(
+    app_handle: &AppHandle,
+    service_manager: &ServiceManager,
+    settings: &SettingsStore,
+    report: AdoptionReport,
+) -> Result<AdoptionReport, String> {
+    let project_root = find_project_root(app_handle)?;
+    let data_dir = paths::data_dir(app_handle)?;
+    let setup = ComposeSetup::from_settings(&settings.get(), &project_root, &data_dir);
+    let stack_version = app_handle
+        .state::<VersionGate>()
+        .check(app_handle, &process::SystemRunner)
+        .await?;
+    for warning in &report.warnings {
+        warn!("⚠️  Adopted stack differs from the config: {:?}", warning);
+    }
+
+    if let Err(e) = app_handle
+        .state::<ConfigWatcher>()
+        .watch(setup.watched_files(&project_root))
     {
-        Ok(output) => Ok(output.status.success()),
-        Err(_) => Ok(false),
+        warn!("⚠️  {}", e);
     }
+    *service_manager.active_setup.lock() = Some(setup);
+    service_manager.set_stack(app_handle, StackState::Running { adopted: true });
+    // Whoever started it stops it, so no session is left to recover
+    record_session(app_handle, false);
+    *service_manager.stack_version.lock() = stack_version;
+    info!("👀 Adopted {} running services", report.services.len());
+    splash::advance(app_handle, StartupStage::ServicesReady);
+    Ok(report)
 }
 
+/// Let Arbor stop, restart and update an adopted stack
 #[tauri::command]
-async fn run_setup_command(command: String) -> Result<String, String> {
-    println!("🔧 Running setup command: {}", command);
+async fn take_ownership_of_services(
+    app_handle: AppHandle,
+    service_manager: State<'_, ServiceManager>,
+) -> Result<(), String> {
+    app_handle.state::<Handshake>().ensure()?;
+    let stack = *service_manager.stack.lock();
+    match stack {
+        StackState::Running { adopted: true } => {
+            service_manager.set_stack(&app_handle, StackState::Running { adopted: false });
+            app_handle.state::<Watchdog>().arm();
+            record_session(&app_handle, true);
+            info!("✅ Took ownership of the running stack");
+            Ok(())
+        }
+        StackState::Running { adopted: false } => Ok(()),
+        StackState::Stopped | StackState::Starting | StackState::Stopping => {
+            Err("No running stack to take ownership of".to_string())
+        }
+    }
+}
+
+/// Re-check the config after the watcher saw `changed_files` edited
+async fn on_config_changed(app_handle: &AppHandle, changed_files: Vec<PathBuf>) {
+    let project_root = match find_project_root(app_handle) {
+        Ok(root) => root,
+        Err(e) => return warn!("⚠️  {}", e),
+    };
+    let data_dir = match paths::data_dir(app_handle) {
+        Ok(dir) => dir,
+        Err(e) => return warn!("⚠️  {}", e),
+    };
+    info!("📝 Config changed: {:?}", changed_files);
+
+    let settings = app_handle.state::<SettingsStore>().get();
+    let active = app_handle
+        .state::<ServiceManager>()
+        .active_setup
+        .lock()
+        .clone();
+    let setup = active
+        .unwrap_or_else(|| ComposeSetup::from_settings(&settings, &project_root, &data_dir));
+    // Edited compose files aren't run until they're trusted again
+    let auto_restart = settings.auto_restart_on_config_change
+        && app_handle
+            .state::<TrustStore>()
+            .check(app_handle, &project_root, &settings)
+            .is_ok();
+
+    let change = config_watch::handle_change(
+        &process::SystemRunner,
+        app_handle,
+        &project_root,
+        &app_handle.state::<ConfigSources>(),
+        &setup,
+        auto_restart,
+        changed_files,
+    )
+    .await;
+    if change.auto_restarted {
+        app_handle.state::<Lifecycle>().record(
+            StopReason::new(StopInitiator::ConfigChange)
+                .with_services(&change.affected_services)
+                .restarted(),
+        );
+    }
+}
+
+/// Watch the config files of the settings' setup until the stack is started
+fn start_config_watcher(app_handle: &AppHandle, config_dir: &Path) -> Result<(), String> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+    let watcher = ConfigWatcher::new(tx);
 
-    // Get the project root by finding the directory containing the Makefile
-    let mut project_root = std::env::current_dir()
-        .map_err(|e| format!("Failed to get current directory: {}", e))?;
+    let project_root = find_project_root(app_handle)?;
+    let data_dir = paths::data_dir(app_handle)?;
+    let settings = SettingsStore::load(config_dir.join(settings::SETTINGS_FILE_NAME)).get();
+    let setup = ComposeSetup::from_settings(&settings, &project_root, &data_dir);
+    watcher.watch(setup.watched_files(&project_root))?;
+    app_handle.manage(watcher);
 
-    loop {
-        let makefile_path = project_root.join("Makefile");
-        if makefile_path.exists() {
-            break;
+    let handle = app_handle.clone();
+    app_handle.state::<TaskManager>().spawn("config_watcher", async move {
+        while let Some(changed_files) = config_watch::next_change(&mut rx).await {
+            on_config_changed(&handle, changed_files).await;
         }
+    });
+    Ok(())
+}
+
+/// The app's own state, as captured into crash post-mortems
+fn app_state_snapshot(app_handle: &AppHandle) -> serde_json::Value {
+    let settings = app_handle.state::<SettingsStore>().get();
+    let active_setup = app_handle
+        .state::<ServiceManager>()
+        .active_setup
+        .lock()
+        .clone();
+
+    serde_json::json!({
+        "app_version": env!("CARGO_PKG_VERSION"),
+        "settings": settings,
+        "active_setup": active_setup,
+        "container_stats": app_handle.state::<StatsSampler>().snapshot(),
+        "last_stop_reason": app_handle.state::<Lifecycle>().last_stop(),
+    })
+}
 
-        project_root = project_root
-            .parent()
-            .ok_or("Failed to find project root (no Makefile found)")?
-            .to_path_buf();
+#[tauri::command]
+async fn check_docker_installed() -> Result<bool, docker::DockerError> {
+    match docker::check_status(&process::SystemRunner).await {
+        Ok(_) => Ok(true),
+        Err(docker::DockerError::NotInstalled) => Ok(false),
+        // Installed but unusable is reported as an error so the UI can explain why
+        Err(e @ docker::DockerError::SocketPermissionDenied { .. }) => Err(e),
+        Err(_) => Ok(true),
     }
+}
 
-    // Run the make command
-    let output = Command::new("make")
-        .arg(&command)
-        .current_dir(&project_root)
-        .output()
-        .map_err(|e| format!("Failed to run command: {}", e))?;
+/// Whether docker is installed, running and new enough, with its versions
+#[tauri::command]
+async fn check_docker_status() -> Result<docker::DockerInfo, docker::DockerError> {
+    docker::check_status(&process::SystemRunner).await
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Command failed: {}", stderr));
+/// Which container runtime is in use, with its version when it's reachable
+#[tauri::command]
+async fn get_container_runtime() -> docker::RuntimeInfo {
+    docker::RuntimeInfo {
+        runtime: tools::runtime(),
+        version: docker::check_status(&process::SystemRunner)
+            .await
+            .ok()
+            .map(|info| info.server_version),
     }
+}
+
+/// Start a setup target, returning its job id
+/// Its output arrives as `job-output` and its `SetupCommandOutput` is the
+/// job's result; `cancel_setup_command` stops it.
+#[tauri::command]
+async fn run_setup_command(
+    app_handle: AppHandle,
+    jobs: State<'_, jobs::JobManager>,
+    target: String,
+    vars: Option<BTreeMap<String, String>>,
+    raw_output: Option<bool>,
+) -> Result<String, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    services::validate_setup_target(&target)?;
+    let vars = vars.unwrap_or_default();
+    services::setup_make_vars(&vars)?;
+    let kind = jobs::JobKind::SetupCommand {
+        target,
+        vars,
+        raw_output: raw_output.unwrap_or(false),
+    };
+    let (id, done) = jobs.try_start(kind, jobs::JobEnv::for_app(&app_handle)?)?;
+
+    tauri::async_runtime::spawn(async move {
+        let result = done
+            .await
+            .map_err(|_| "Setup command was interrupted".to_string())
+            .and_then(|result| result);
+        let _ = recorded(&app_handle, "run_setup_command", result);
+    });
+    Ok(id)
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    Ok(stdout.to_string())
+/// Stop a setup target `run_setup_command` started
+#[tauri::command]
+async fn cancel_setup_command(
+    jobs: State<'_, jobs::JobManager>,
+    handshake: State<'_, Handshake>,
+    task_id: String,
+) -> Result<(), String> {
+    handshake.ensure()?;
+    match jobs.get(&task_id) {
+        Some(job) if matches!(job.kind, jobs::JobKind::SetupCommand { .. }) => {
+            jobs.cancel(&task_id)
+        }
+        Some(_) => Err(format!("Job {} isn't a setup command", task_id)),
+        None => Err(format!("Unknown job: {}", task_id)),
+    }
 }
 
 #[tauri::command]
@@ -169,62 +1178,628 @@ async fn get_app_version() -> Result<String, String> {
 }
 
 fn main() {
-    tauri::Builder::default()
+    flight::init();
+    let error_history = ErrorHistory::default();
+    let task_manager = TaskManager::new(error_history.clone());
+
+    let app = tauri::Builder::default()
         .plugin(tauri_plugin_keyring::init())
         .manage(ServiceManager::new())
+        .manage(keyring::KeyringStatus::default())
+        .manage(keyring::Keychain::os())
+        .manage(keyring::PendingRotation::default())
+        .manage(error_history)
+        .manage(task_manager.clone())
+        .manage(splash::Splash::default())
+        .manage(VersionGate::default())
+        .manage(confirm::Confirmations::default())
+        .manage(Handshake::default())
+        .manage(logs::LogStreams::default())
+        .manage(quit::Quitting::default())
+        .manage(Watchdog::default())
+        .manage(stats::UsageWatch::default())
+        .manage(updates::UpdateChecker::default())
+        .register_uri_scheme_protocol("arbor-splash", |ctx, request| {
+            let body = splash::handle_request(ctx.app_handle(), request.uri().path(), request.uri().query());
+            tauri::http::Response::builder()
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(body)
+                .unwrap()
+        })
         .invoke_handler(tauri::generate_handler![
             start_services,
+            start_safe_mode,
+            exit_safe_mode,
             stop_services,
+            restart_services,
+            start_service,
+            stop_service,
+            restart_service,
             check_services_status,
+            get_service_state,
+            get_service_backend,
+            adopt_running_services,
+            take_ownership_of_services,
+            compat::allow_version_mismatch,
             check_docker_installed,
+            compose::check_compose_installed,
+            compose::get_compose_profiles,
+            check_docker_status,
+            get_container_runtime,
             run_setup_command,
+            cancel_setup_command,
+            makefile::list_setup_commands,
+            ports::check_port_conflicts,
+            cleanup::cleanup_orphaned_resources,
+            pull::update_service_images,
+            project_root::get_project_root,
+            project_root::set_project_root,
+            command_log::read_command_output,
+            jobs::start_job,
+            jobs::cancel_job,
+            jobs::ack_job_output,
+            jobs::get_job_status,
+            jobs::list_jobs,
+            jobs::spawn_task,
+            jobs::cancel_task,
+            jobs::get_task_status,
+            jobs::list_tasks,
+            jobs::resume_job,
+            jobs::answer_metered_prompt,
+            jobs::run_smoke_test,
             get_app_version,
+            build_info::get_build_info,
+            protocol::get_protocol_version,
+            protocol::handshake,
+            splash::frontend_ready,
+            relaunch::relaunch_app,
+            quit::quit_without_stopping,
+            settings::get_settings,
+            settings::update_settings,
+            settings::set_setting,
+            settings_profile::export_settings,
+            settings_profile::import_settings,
+            tasks::get_background_tasks,
+            errors::get_error_history,
+            errors::mark_errors_read,
+            flight::set_log_level,
+            flight::get_recent_logs,
+            messages::describe_error,
+            snapshot::get_full_state,
+            lifecycle::get_lifecycle_history,
+            logs::follow_logs,
+            logs::ack_log_batch,
+            logs::stop_logs,
+            project_config::get_effective_config,
+            crashes::set_restart_policy,
+            trust::trust_project_files,
+            stats::get_container_stats,
+            stats::get_services_resource_usage,
+            stats::watch_resource_usage,
+            stats::unwatch_resource_usage,
+            tools::locate_tools,
+            postmortem::list_crash_reports,
+            diagnostics::export_diagnostics,
+            diagnostics::get_system_info,
+            doctor::run_diagnostics,
+            support::export_support_bundle,
+            uninstall::uninstall_cleanup,
+            updates::check_for_updates,
+            disk::check_disk_space,
+            migrations::get_migration_status,
+            migrations::run_migrations,
+            backup::backup_data,
+            backup::restore_data,
+            endpoints::get_service_endpoints,
+            paths::get_app_dirs,
+            profiles::list_profiles,
+            profiles::create_profile,
+            profiles::set_active_profile,
+            profiles::delete_profile,
             keyring::get_master_key,
+            keyring::has_master_key,
+            keyring::get_master_key_fingerprint,
+            keyring::export_master_key_phrase,
+            keyring::import_master_key_phrase,
+            keyring::set_secret,
+            keyring::get_secret,
+            keyring::delete_secret,
+            keyring::list_secret_names,
             keyring::set_master_key,
             keyring::generate_master_key,
-            keyring::get_or_generate_master_key
+            keyring::delete_master_key,
+            keyring::reset_encryption,
+            keyring::rotate_master_key,
+            keyring::commit_key_rotation,
+            keyring::abort_key_rotation,
+            keyring::get_or_generate_master_key,
+            keyring::check_keyring_health,
+            crypto::encrypt_data,
+            crypto::decrypt_data,
+            crypto::derive_key,
+            keyring::get_keystore_backend,
+            keyring::unlock_fallback_store,
+            capabilities::get_capabilities,
+            status::get_services_status
         ])
         .setup(|app| {
             let app_handle = app.handle().clone();
-            
+            let tasks = app.state::<TaskManager>().inner().clone();
+
+            // Another launch gets this one's window and arguments; it exits before opening any
+            match instance::claim(&paths::data_dir(&app_handle)?, &instance::SecondInstance::current()) {
+                Ok(instance::Claim::Forwarded) => {
+                    info!("🪟 Arbor is already running; handed this launch to it");
+                    std::process::exit(0);
+                }
+                Ok(instance::Claim::Primary(lock)) => {
+                    let launch_handle = app_handle.clone();
+                    tasks.spawn("second_instance", lock.serve(move |launch| {
+                        tray::show_window(&launch_handle);
+                        events::emit(&launch_handle, instance::SECOND_INSTANCE_EVENT, &launch);
+                    }));
+                }
+                Err(e) => warn!("⚠️  Couldn't check for another running instance: {}", e),
+            }
+
+            // Up before anything slow, so there's no blank window while the frontend loads
+            if let Err(e) = splash::open(app) {
+                warn!("⚠️  Failed to open splash window: {}", e);
+                splash::show_main(&app_handle, None);
+            }
+            splash::advance(&app_handle, StartupStage::Starting);
+            tasks.spawn("splash_watchdog", splash::watchdog(app_handle.clone()));
+
+            // Before anything reads or writes its files
+            let dirs = paths::prepare(&app_handle)?;
+            if let Err(e) = flight::open_log_file(&dirs.logs) {
+                warn!("⚠️  Logging to the console only: {}", e);
+            }
+            let config_dir = dirs.config.clone();
+            app.state::<ErrorHistory>()
+                .set_flight_dir(dirs.logs.join(flight::FLIGHT_DIR_NAME));
+            let settings_store = SettingsStore::load(config_dir.join(settings::SETTINGS_FILE_NAME));
+            let settings_rx = settings_store.subscribe();
+            let tool_settings = settings_store.get();
+            tools::configure(&tool_settings.tool_paths, tool_settings.container_runtime);
+            let capabilities =
+                capabilities::CapabilityCache::new(Arc::new(app_handle.clone()), settings_store.get());
+            tasks.spawn(
+                "capabilities",
+                capabilities.clone().watch_settings(settings_store.subscribe()),
+            );
+            app.manage(capabilities);
+            let notifier = Notifier::new(
+                Arc::new(notify::OsNotifications::new(app_handle.clone())),
+                settings_store.subscribe(),
+            );
+            app.state::<Watchdog>().set_notifier(notifier.clone());
+            app.manage(notifier.clone());
+            app.manage(settings_store);
+            app.manage(ConfigSources::from_process(&config_dir));
+            app.manage(TrustStore::new(config_dir.join(trust::TRUST_FILE_NAME)));
+            app.manage(ProjectRoot::new(RootSources {
+                bundled: paths::bundled_resources(&app_handle),
+                ..RootSources::from_process()
+            }));
+            let profile_store = profiles::ProfileStore::load(
+                config_dir.join(profiles::PROFILES_FILE_NAME),
+                dirs.data.clone(),
+            );
+            profiles::activate(&profile_store);
+            app.manage(profile_store);
+
+            // Reloaded with the config on every start; shared with the crash monitor
+            let restart_policies = RestartPolicies::default();
+            match find_project_root(&app_handle)
+                .and_then(|root| ProjectConfig::load(&root, &app.state::<ConfigSources>()))
+            {
+                Ok(config) => restart_policies.replace(&config),
+                Err(e) => warn!("⚠️  Using default restart policies: {}", e),
+            }
+            app.manage(restart_policies.clone());
+
+            // Status cache and crash monitor, fed by the docker events subscription
+            let status_cache = StatusCache::new(
+                Arc::new(process::SystemRunner),
+                Arc::new(app_handle.clone()),
+                restart_policies.clone(),
+            );
+            let stats_sampler = StatsSampler::new(Arc::new(process::SystemRunner));
+            let snapshot_handle = app_handle.clone();
+            let postmortems = PostMortemStore::new(
+                dirs.data.join(postmortem::CRASHES_DIR_NAME),
+                Arc::new(move || app_state_snapshot(&snapshot_handle)),
+            );
+            let lifecycle = Lifecycle::new(Arc::new(app_handle.clone()))
+                .with_store(dirs.data.join(lifecycle::LIFECYCLE_FILE_NAME));
+            app.manage(lifecycle.clone());
+            let crash_monitor = CrashMonitor::new(
+                Arc::new(process::SystemRunner),
+                Arc::new(app_handle.clone()),
+                stats_sampler.clone(),
+            )
+            .with_postmortems(postmortems.clone())
+            .with_restart_policies(restart_policies.clone())
+            .with_lifecycle(lifecycle.clone())
+            .with_notifier(notifier.clone());
+            app.manage(postmortems);
+            let job_store = jobs::JobStore::new(dirs.data.join(jobs::JOBS_FILE_NAME));
+            app.manage(
+                jobs::JobManager::new(Arc::new(app_handle.clone()), tasks.clone())
+                    .with_store(job_store),
+            );
+            let (status_tx, status_rx) = tokio::sync::mpsc::channel(256);
+            let (crash_tx, crash_rx) = tokio::sync::mpsc::channel(256);
+            tasks.spawn("docker_events", docker::subscribe_events(vec![status_tx, crash_tx]));
+            tasks.spawn("status_watcher", status_cache.clone().watch_events(status_rx));
+            tasks.spawn("crash_monitor", crash_monitor.watch_events(crash_rx));
+            app.manage(status_cache.clone());
+
+            // Periodic tasks, on intervals from the settings
+            let background_tasks = BackgroundTasks::default();
+            tasks.spawn(TaskKind::StatusPoll.name(), background_tasks.clone().run_periodic(
+                TaskKind::StatusPoll,
+                settings_rx.clone(),
+                move || {
+                    let status_cache = status_cache.clone();
+                    async move { status_cache.poll().await }
+                },
+            ));
+            let sampler = stats_sampler.clone();
+            tasks.spawn(TaskKind::StatsSample.name(), background_tasks.clone().run_periodic(
+                TaskKind::StatsSample,
+                settings_rx.clone(),
+                move || {
+                    let sampler = sampler.clone();
+                    async move {
+                        if let Err(e) = sampler.sample().await {
+                            warn!("⚠️  {}", e);
+                        }
+                    }
+                },
+            ));
+            let watchdog_handle = app_handle.clone();
+            tasks.spawn(TaskKind::Watchdog.name(), background_tasks.clone().run_periodic(
+                TaskKind::Watchdog,
+                settings_rx,
+                move || {
+                    let app_handle = watchdog_handle.clone();
+                    let policies = restart_policies.clone();
+                    async move {
+                        let settings = app_handle.state::<SettingsStore>().get().auto_restart;
+                        app_handle
+                            .state::<Watchdog>()
+                            .check(&process::SystemRunner, &app_handle, &settings, &policies)
+                            .await;
+                    }
+                },
+            ));
+            app.manage(stats_sampler);
+            app.manage(background_tasks);
+
+            // Pick up edited tool paths and runtime without a restart
+            let mut tool_settings = app.state::<SettingsStore>().subscribe();
+            tasks.spawn("tool_paths", async move {
+                while tool_settings.changed().await.is_ok() {
+                    let (paths, runtime) = {
+                        let settings = tool_settings.borrow_and_update();
+                        (settings.tool_paths.clone(), settings.container_runtime)
+                    };
+                    tools::configure(&paths, runtime);
+                }
+            });
+
+            // Announces a newer release once a session, unless checks are off
+            tasks.spawn("update_check", updates::watch(app_handle.clone()));
+
+            // The log level setting applies at once, except over safe mode's
+            let level = app.state::<SettingsStore>().get().log_level;
+            flight::set_base_level(flight::configured_level(level));
+            let mut level_settings = app.state::<SettingsStore>().subscribe();
+            let level_handle = app_handle.clone();
+            tasks.spawn("log_level", async move {
+                while level_settings.changed().await.is_ok() {
+                    let level = level_settings.borrow_and_update().log_level;
+                    if !level_handle.state::<ServiceManager>().in_safe_mode() {
+                        flight::set_base_level(flight::configured_level(level));
+                    }
+                }
+            });
+
+            // Opt-in scrape endpoint; without it no observer is installed and events cost nothing extra
+            let metrics_settings = app.state::<SettingsStore>().get().metrics;
+            if let Err(e) = metrics_settings.validate() {
+                warn!("⚠️  Metrics endpoint disabled: {}", e);
+            } else if metrics_settings.enabled {
+                let metrics = metrics::Metrics::default();
+                let observer = metrics.clone();
+                events::set_observer(Arc::new(move |event, payload| observer.observe(event, payload)));
+                let metrics_handle = app_handle.clone();
+                tasks.spawn("metrics", async move {
+                    let listener = match tokio::net::TcpListener::bind(&metrics_settings.listen).await {
+                        Ok(listener) => listener,
+                        Err(e) => {
+                            warn!("⚠️  Metrics endpoint disabled: failed to listen on {}: {}", metrics_settings.listen, e);
+                            return;
+                        }
+                    };
+                    info!("📈 Serving metrics on http://{}/metrics", metrics_settings.listen);
+                    let render = move || {
+                        metrics.render(metrics_handle.state::<keyring::KeyringStatus>().health().as_ref())
+                    };
+                    metrics::serve(listener, metrics_settings.token.unwrap_or_default(), Arc::new(render)).await;
+                });
+            }
+
+            if let Err(e) = tray::install(&app_handle) {
+                warn!("⚠️  Tray disabled: {}", e);
+            }
+
+            if let Err(e) = start_config_watcher(&app_handle, &config_dir) {
+                warn!("⚠️  Config watcher disabled: {}", e);
+            }
+
             // Start services on app launch
-            tauri::async_runtime::spawn(async move {
-                println!("🌳 Arbor starting up...");
+            tasks.spawn("startup", async move {
+                info!("🌳 Arbor {} starting up...", BuildInfo::current().describe());
                 
                 // Wait a moment for the window to be ready
                 tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-                
+
+                // Surface keychain problems now rather than on the first key operation
+                let health = keyring::probe().await;
+                if !health.is_ok() {
+                    warn!("⚠️  Keychain degraded: {:?}", health);
+                    events::emit(&app_handle, keyring::KEYRING_DEGRADED_EVENT, &health);
+                }
+                if matches!(health, keyring::KeyringHealth::Unavailable { .. }) {
+                    match paths::data_dir(&app_handle) {
+                        Ok(dir) => {
+                            let file = secret_file::FileSecrets::new(dir.join(secret_file::SECRET_FILE_NAME));
+                            app_handle.state::<keyring::Keychain>().use_fallback(Arc::new(file));
+                            info!("🔐 No OS keychain; keeping secrets in an encrypted file");
+                        }
+                        Err(e) => warn!("⚠️  No secrets file fallback: {}", e),
+                    }
+                }
+                app_handle.state::<keyring::KeyringStatus>().record(health.clone());
+                app_handle.state::<capabilities::CapabilityCache>().set_keyring(health);
+
+                // Docker not running, or on first Linux runs not accessible; say
+                // which instead of letting make fail
+                splash::advance(&app_handle, StartupStage::DockerChecking);
+                match docker::check_status(&process::SystemRunner).await {
+                    Ok(info) => info!("🐳 Docker {}", info.server_version),
+                    Err(e) => {
+                        error!("❌ {}", e);
+                        events::emit_coded(&app_handle, docker::DOCKER_UNAVAILABLE_EVENT, &e);
+                        // Starting Docker Desktop is all it takes otherwise
+                        let setup_required = !matches!(e, docker::DockerError::DaemonUnreachable { .. });
+                        splash::advance(&app_handle, StartupStage::Failed {
+                            message: e.to_string(),
+                            setup_required,
+                        });
+                        return;
+                    }
+                }
+
+                // An app update may have left containers this version can't talk to
+                let stack_version = match app_handle
+                    .state::<VersionGate>()
+                    .check(&app_handle, &process::SystemRunner)
+                    .await
+                {
+                    Ok(version) => version,
+                    Err(e) => {
+                        splash::advance(&app_handle, StartupStage::Failed {
+                            message: e,
+                            setup_required: false,
+                        });
+                        return;
+                    }
+                };
+
+                // Relaunched with the stack kept running: pick it up as it is
+                let marker = relaunch::LaunchState::for_app(&app_handle)
+                    .ok()
+                    .and_then(|state| state.take_adopt());
+                if let Some(marker) = marker {
+                    match adopt_services(app_handle.clone(), marker.setup, stack_version).await {
+                        Ok(()) => return,
+                        Err(e) => warn!("⚠️  Can't adopt running stack ({}); starting services", e),
+                    }
+                }
+
+                // Left by a crash, or started outside Arbor (e.g. `make up`): offer to adopt it
+                // or restart fresh rather than run make up over it
+                let unfinished = relaunch::LaunchState::for_app(&app_handle)
+                    .ok()
+                    .and_then(|state| state.unfinished_session());
+                if let Some(report) = find_unowned_stack(&app_handle).await {
+                    let services = report.services.len();
+                    match unfinished {
+                        Some(session) => {
+                            warn!(
+                                "⚠️  Found {} Arbor services left running by a session that didn't stop them (PID {}, started {})",
+                                services, session.pid, session.started_at
+                            );
+                            let previous = relaunch::PreviousSession { session, stack: report };
+                            events::emit(&app_handle, relaunch::PREVIOUS_SESSION_EVENT, &previous);
+                        }
+                        None => {
+                            info!("👀 Found {} running Arbor services", services);
+                            events::emit(&app_handle, services::ADOPTABLE_STACK_EVENT, &report);
+                        }
+                    }
+                    splash::advance(&app_handle, StartupStage::StackFound { services });
+                    return;
+                }
+                if let Some(session) = unfinished {
+                    warn!("⚠️  The previous session (PID {}) ended without stopping services", session.pid);
+                    record_session(&app_handle, false);
+                }
+
+                if !app_handle.state::<SettingsStore>().get().auto_start_services {
+                    info!("⏸️  Not starting services (auto_start_services is off)");
+                    splash::show_main(&app_handle, None);
+                    return;
+                }
+
+                // A crash may have left stopped containers whose names make up would collide with
+                cleanup::cleanup_at_launch(&process::SystemRunner).await;
+
                 // Start services
                 let service_manager = app_handle.state::<ServiceManager>();
-                match start_services(service_manager).await {
-                    Ok(msg) => println!("{}", msg),
-                    Err(e) => eprintln!("❌ Failed to start services: {}", e),
+                let settings = app_handle.state::<SettingsStore>();
+                // Readiness is awaited (within the configured timeouts) by start_stack; the
+                // webview may not have shaken hands yet, so this doesn't wait on it
+                let started =
+                    start_stack(app_handle.clone(), service_manager, settings, None, None).await;
+                match started {
+                    Ok(msg) => info!("{}", msg),
+                    Err(e) => {
+                        error!("❌ Failed to start services: {}", e);
+                        splash::advance(&app_handle, StartupStage::Failed {
+                            message: format!("Failed to start services: {}", e),
+                            setup_required: false,
+                        });
+                    }
                 }
-                
-                // Wait for services to be ready
-                println!("⏳ Waiting for services to be ready...");
-                tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-                
-                println!("✅ Arbor is ready!");
             });
             
             Ok(())
         })
-        .on_window_event(|window, event| {
-            if let tauri::WindowEvent::CloseRequested { .. } = event {
+        .on_window_event(|window, event| match (window.label(), event) {
+            // Closing the splash early just skips ahead to the main window
+            (splash::SPLASH_WINDOW, tauri::WindowEvent::CloseRequested { api, .. }) => {
+                api.prevent_close();
+                splash::show_main(window.app_handle(), None);
+            }
+            // Never leave the splash behind without a main window
+            (splash::MAIN_WINDOW, tauri::WindowEvent::Destroyed) => {
+                splash::close(window.app_handle());
+            }
+            (splash::MAIN_WINDOW, tauri::WindowEvent::CloseRequested { api, .. }) => {
                 let app_handle = window.app_handle().clone();
-                
-                // Stop services on app quit
-                tauri::async_runtime::spawn(async move {
-                    let service_manager = app_handle.state::<ServiceManager>();
-                    match stop_services(service_manager).await {
-                        Ok(msg) => println!("{}", msg),
-                        Err(e) => eprintln!("❌ Failed to stop services: {}", e),
+                // Only with a tray to bring it back from
+                let to_tray = app_handle.state::<SettingsStore>().get().minimize_to_tray
+                    && app_handle.try_state::<tray::Tray>().is_some();
+                if to_tray {
+                    api.prevent_close();
+                    if let Err(e) = window.hide() {
+                        warn!("⚠️  Failed to hide the window: {}", e);
                     }
-                });
+                    return;
+                }
+                if !quit::stops_stack(&app_handle) {
+                    info!("👋 Leaving the adopted stack running");
+                    return;
+                }
+
+                // Stop services on app quit; the app exits once they have
+                api.prevent_close();
+                quit::quit(app_handle);
             }
+            _ => {}
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application");
+
+    app.run(move |app_handle, event| {
+        if let RunEvent::Exit = event {
+            // Cancel background tasks and give them a moment to finish cleanly
+            let report = tauri::async_runtime::block_on(task_manager.shutdown(SHUTDOWN_DEADLINE));
+            info!("🛑 Stopped {} background tasks", report.stopped);
+            if !report.timed_out.is_empty() {
+                warn!("⚠️  Background tasks still running at exit: {}", report.timed_out.join(", "));
+            }
+            if let Ok(dir) = paths::data_dir(app_handle) {
+                instance::release(&dir);
+            }
+        }
+    });
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use events::recording::RecordingSink;
+
+    #[test]
+    fn test_service_controls_survive_a_panic_while_locked() {
+        let service_manager = Arc::new(ServiceManager::new());
+        let holder = service_manager.clone();
+        let panicked = std::thread::spawn(move || {
+            let _stack = holder.stack.lock();
+            let _setup = holder.active_setup.lock();
+            panic!("while locked");
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        let sink = RecordingSink::default();
+        assert!(service_manager.ensure_owned("stop services").is_ok());
+        assert!(!service_manager.in_safe_mode());
+        assert_eq!(
+            service_manager.begin(&sink, StackState::stop),
+            Ok(StackState::Stopped)
+        );
+        assert!(service_manager.active_setup.lock().is_none());
+        service_manager.set_stack(&sink, StackState::Stopped);
+        assert_eq!(
+            sink.events_named(services::STACK_STATE_EVENT),
+            [
+                serde_json::json!({ "state": "stopping" }),
+                serde_json::json!({ "state": "stopped" }),
+            ]
+        );
+    }
+
+    /// The body of `fn name` in `source`, up to the next item
+    fn body<'a>(source: &'a str, name: &str) -> &'a str {
+        let start = source.find(&format!("fn {}(", name)).unwrap();
+        let rest = &source[start..];
+        &rest[..rest.find("\n}\n").unwrap()]
+    }
+
+    // Auto-start runs from the setup hook, racing the webview's handshake,
+    // and the tray works before the webview has loaded; none of them may go
+    // through a command that refuses to run until the handshake is done
+    #[test]
+    fn test_auto_start_and_the_tray_skip_the_handshake() {
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let read = |name: &str| std::fs::read_to_string(src.join(name)).unwrap();
+        let main = read("main.rs");
+        let internal = [
+            "start_stack",
+            "stop_for_user",
+            "restart_for_user",
+            "restart_stack",
+            "stop_stack",
+        ];
+        for name in internal {
+            assert!(!body(&main, name).contains("Handshake"), "{} checks the handshake", name);
+        }
+        for name in ["start_services", "stop_services", "restart_services"] {
+            assert!(body(&main, name).contains("Handshake"), "{} skips the handshake", name);
+        }
+
+        let setup = &main[main.find("auto_start_services is off").unwrap()..];
+        let setup = &setup[..setup.find("Ok(())").unwrap()];
+        assert!(setup.contains("start_stack("));
+        let callers = [
+            ("the setup hook", setup.to_string()),
+            ("tray.rs", read("tray.rs")),
+            ("relaunch.rs", read("relaunch.rs")),
+        ];
+        for (caller, source) in callers {
+            for command in ["start_services(", "stop_services(", "restart_services("] {
+                let called = source.contains(&format!("crate::{}", command))
+                    || source.contains(&format!(" {}", command));
+                assert!(!called, "{} calls {}", caller, command);
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+// Makefile targets
+// The setup screen lists the targets it offers from the checkout's Makefile
+// rather than a copy in the frontend, so the two can't drift apart. Targets
+// are read from rule lines, with the text of a `## comment` (trailing the
+// rule or on the line above it) as their description. Special, internal
+// and pattern targets (`.PHONY`, `_private`, `%.o`) are left out, and
+// `include`d makefiles are read too, as far as their paths are literal.
+
+use crate::services::SETUP_TARGETS;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+
+/// Nesting of `include`s followed before giving up
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MakeTarget {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// The targets of `makefile` and what it includes, in the order they appear
+pub fn targets(makefile: &Path) -> Result<Vec<MakeTarget>, String> {
+    if !makefile.is_file() {
+        return Err(format!("No Makefile at {}", makefile.display()));
+    }
+    let base = makefile.parent().unwrap_or(Path::new("."));
+    let mut found = Vec::new();
+    let mut read = Vec::new();
+    collect(makefile, base, 0, &mut read, &mut found)?;
+    Ok(found)
+}
+
+fn collect(
+    makefile: &Path,
+    base: &Path,
+    depth: usize,
+    read: &mut Vec<PathBuf>,
+    found: &mut Vec<MakeTarget>,
+) -> Result<(), String> {
+    if depth > MAX_INCLUDE_DEPTH || read.iter().any(|path| path == makefile) {
+        return Ok(());
+    }
+    read.push(makefile.to_path_buf());
+    let contents = std::fs::read_to_string(makefile)
+        .map_err(|e| format!("Failed to read {}: {}", makefile.display(), e))?;
+
+    let mut comment: Option<String> = None;
+    for line in contents.lines() {
+        if line.starts_with('\t') {
+            continue;
+        }
+        let line = line.trim_end();
+        if let Some(text) = line.trim_start().strip_prefix("##") {
+            comment = Some(text.trim().to_string());
+            continue;
+        }
+        let above = comment.take();
+
+        if let Some((optional, paths)) = include(line) {
+            for path in paths.split_whitespace().filter(|path| !path.contains('$')) {
+                let included = base.join(path);
+                if included.is_file() {
+                    collect(&included, base, depth + 1, read, found)?;
+                } else if !optional {
+                    return Err(format!(
+                        "{} includes {}, which doesn't exist",
+                        makefile.display(),
+                        path
+                    ));
+                }
+            }
+            continue;
+        }
+
+        let Some((names, rest)) = rule(line) else {
+            continue;
+        };
+        let description = rest
+            .split_once("##")
+            .map(|(_, text)| text.trim().to_string())
+            .or(above)
+            .filter(|text| !text.is_empty());
+        for name in names.split_whitespace().filter(|name| is_public(name)) {
+            match found.iter_mut().find(|target| target.name == name) {
+                Some(target) => {
+                    if target.description.is_none() {
+                        target.description = description.clone();
+                    }
+                }
+                None => found.push(MakeTarget {
+                    name: name.to_string(),
+                    description: description.clone(),
+                }),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `include` paths on `line` may be missing, and the paths
+fn include(line: &str) -> Option<(bool, &str)> {
+    let (directive, paths) = line.split_once(char::is_whitespace)?;
+    match directive {
+        "include" => Some((false, paths)),
+        "-include" | "sinclude" => Some((true, paths)),
+        _ => None,
+    }
+}
+
+/// The targets and the rest of a rule line, which assignments aren't
+fn rule(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with([' ', '#']) {
+        return None;
+    }
+    let (names, rest) = line.split_once(':')?;
+    // `A := b`, `A ::= b`, and `A = b:c` are variables
+    if names.contains('=') || rest.starts_with('=') || rest.starts_with(":=") {
+        return None;
+    }
+    Some((names, rest.trim_start_matches(':')))
+}
+
+fn is_public(name: &str) -> bool {
+    !name.starts_with(['.', '_']) && !name.contains(['%', '$'])
+}
+
+/// The setup targets the checkout's Makefile has, with their descriptions
+/// Only targets `run_setup_command` accepts are listed.
+#[tauri::command]
+pub async fn list_setup_commands(app_handle: AppHandle) -> Result<Vec<MakeTarget>, String> {
+    let project_root = crate::find_project_root(&app_handle)?;
+    let mut targets = targets(&project_root.join("Makefile"))?;
+    targets.retain(|target| SETUP_TARGETS.contains(&target.name.as_str()));
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    fn project(files: &[(&str, &str)]) -> TempDir {
+        let dir = temp_dir();
+        std::fs::create_dir_all(dir.path().join("mk")).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.path().join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    fn target(name: &str, description: Option<&str>) -> MakeTarget {
+        MakeTarget {
+            name: name.to_string(),
+            description: description.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_targets_and_descriptions() {
+        let tmp = project(&[(
+            "Makefile",
+            ".PHONY: setup seed\n\
+             COMPOSE ?= docker compose\n\
+             FLAGS := -f a.yml\n\
+             URL = http://localhost:3000\n\
+             \n\
+             setup: ## Initial setup (run once)\n\
+             \t@./scripts/setup.sh\n\
+             \n\
+             ## Load sample data\n\
+             seed db-seed: setup\n\
+             \t@echo \"seeding: done\"\n\
+             \n\
+             # Not a description\n\
+             clean:\n\
+             _internal:\n\
+             %.o: %.c\n\
+             $(BUILD)/out:\n",
+        )]);
+        let dir = tmp.path();
+
+        assert_eq!(
+            targets(&dir.join("Makefile")).unwrap(),
+            [
+                target("setup", Some("Initial setup (run once)")),
+                target("seed", Some("Load sample data")),
+                target("db-seed", Some("Load sample data")),
+                target("clean", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_included_makefiles_are_read() {
+        let tmp = project(&[
+            (
+                "Makefile",
+                "include mk/db.mk\n-include mk/local.mk\nsinclude $(HOME)/x.mk\nup:\n",
+            ),
+            (
+                "mk/db.mk",
+                "db-migrate: ## Run migrations\ninclude Makefile\n",
+            ),
+        ]);
+        let dir = tmp.path();
+
+        let names: Vec<String> = targets(&dir.join("Makefile"))
+            .unwrap()
+            .into_iter()
+            .map(|target| target.name)
+            .collect();
+        assert_eq!(names, ["db-migrate", "up"]);
+
+        std::fs::write(dir.join("Makefile"), "include mk/missing.mk\nup:\n").unwrap();
+        let error = targets(&dir.join("Makefile")).unwrap_err();
+        assert!(error.contains("mk/missing.mk"), "{}", error);
+    }
+
+    #[test]
+    fn test_missing_makefile_is_an_error() {
+        let tmp = project(&[]);
+        let dir = tmp.path();
+        let error = targets(&dir.join("Makefile")).unwrap_err();
+        assert!(error.starts_with("No Makefile at"), "{}", error);
+    }
+
+    #[test]
+    fn test_every_setup_target_is_in_the_checkout() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../..");
+        let found = targets(&root.join("Makefile")).unwrap();
+        for name in SETUP_TARGETS {
+            let target = found.iter().find(|target| target.name == name);
+            assert!(
+                target.is_some_and(|target| target.description.is_some()),
+                "{} is missing or undescribed",
+                name
+            );
+        }
+    }
+}
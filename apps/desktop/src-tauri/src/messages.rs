@@ -0,0 +1,226 @@
+// Coded user-facing messages
+// Errors the frontend shows carry a stable code and string parameters, so
+// it can render them itself or ask `describe_error` for the text in a
+// locale. Catalogs are embedded Fluent files; only the subset we use is
+// understood: `code = text` entries with `{ $param }` placeables.
+
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+pub const DEFAULT_LOCALE: &str = "en";
+/// Rendered for codes no catalog knows, with the code as `$code`
+pub const UNKNOWN_CODE: &str = "unknown-error";
+
+const CATALOGS: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("fr", include_str!("../locales/fr.ftl")),
+];
+
+/// A message by code, with the values its placeables refer to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub code: String,
+    pub params: BTreeMap<String, String>,
+}
+
+impl Message {
+    pub fn new(code: &str) -> Self {
+        Self {
+            code: code.to_string(),
+            params: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: impl ToString) -> Self {
+        self.params.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// English text, e.g. for logs and the error history
+    pub fn english(&self) -> String {
+        render(&self.code, &self.params, DEFAULT_LOCALE)
+    }
+}
+
+/// Errors that can describe themselves as a coded message
+pub trait Coded {
+    fn message(&self) -> Message;
+}
+
+type Catalog = HashMap<String, String>;
+
+fn catalogs() -> &'static HashMap<&'static str, Catalog> {
+    static PARSED: OnceLock<HashMap<&'static str, Catalog>> = OnceLock::new();
+    PARSED.get_or_init(|| {
+        CATALOGS
+            .iter()
+            .map(|(locale, source)| (*locale, parse_catalog(source)))
+            .collect()
+    })
+}
+
+/// `code = text` entries; indented lines continue the previous entry
+fn parse_catalog(source: &str) -> Catalog {
+    let mut catalog = Catalog::new();
+    let mut current: Option<String> = None;
+    for line in source.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            current = None;
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            if let Some(text) = current.as_ref().and_then(|code| catalog.get_mut(code)) {
+                text.push('\n');
+                text.push_str(line.trim());
+            }
+            continue;
+        }
+        match line.split_once('=') {
+            Some((code, text)) => {
+                let code = code.trim().to_string();
+                catalog.insert(code.clone(), text.trim().to_string());
+                current = Some(code);
+            }
+            None => warn!("⚠️  Ignoring malformed catalog line: {:?}", line),
+        }
+    }
+    catalog
+}
+
+/// The catalog for `locale`, by language when the region isn't known
+/// (`fr-CA` → `fr`), else English
+fn catalog_for(locale: &str) -> &'static Catalog {
+    let catalogs = catalogs();
+    let locale = locale.trim().to_lowercase().replace('_', "-");
+    let language = locale.split('-').next().unwrap_or_default();
+    catalogs
+        .get(locale.as_str())
+        .or_else(|| catalogs.get(language))
+        .unwrap_or(&catalogs[DEFAULT_LOCALE])
+}
+
+/// Text of `code` in `locale`, never failing
+/// Codes missing from the locale fall back to English, unknown codes to the
+/// generic message, and placeables without a parameter are left as written.
+pub fn render(code: &str, params: &BTreeMap<String, String>, locale: &str) -> String {
+    let catalog = catalog_for(locale);
+    let english = &catalogs()[DEFAULT_LOCALE];
+    if let Some(template) = catalog.get(code).or_else(|| english.get(code)) {
+        return fill(template, params);
+    }
+
+    let params = BTreeMap::from([("code".to_string(), code.to_string())]);
+    match catalog
+        .get(UNKNOWN_CODE)
+        .or_else(|| english.get(UNKNOWN_CODE))
+    {
+        Some(template) => fill(template, &params),
+        None => format!("Something went wrong ({})", code),
+    }
+}
+
+fn fill(template: &str, params: &BTreeMap<String, String>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        text.push_str(&rest[..start]);
+        let placeable = &rest[start..=end];
+        let inner = placeable[1..placeable.len() - 1].trim();
+        match inner.strip_prefix('$').and_then(|name| params.get(name)) {
+            Some(value) => text.push_str(value),
+            None => text.push_str(placeable),
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Render a coded message, for a frontend that doesn't keep its own catalogs
+/// Parameters may be any JSON value; strings are used as they are.
+#[tauri::command]
+pub async fn describe_error(
+    code: String,
+    params: Option<BTreeMap<String, serde_json::Value>>,
+    locale: Option<String>,
+) -> Result<String, String> {
+    let params = params
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(text) => (name, text),
+            other => (name, other.to_string()),
+        })
+        .collect();
+    Ok(render(
+        &code,
+        &params,
+        locale.as_deref().unwrap_or(DEFAULT_LOCALE),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_locale_has_every_code() {
+        let english = &catalogs()[DEFAULT_LOCALE];
+        for (locale, catalog) in catalogs() {
+            let mut missing: Vec<&String> = english
+                .keys()
+                .filter(|code| !catalog.contains_key(*code))
+                .collect();
+            missing.sort();
+            assert!(missing.is_empty(), "{} lacks {:?}", locale, missing);
+            assert_eq!(catalog.len(), english.len(), "{} has extra codes", locale);
+        }
+    }
+
+    #[test]
+    fn test_render_fills_params_per_locale() {
+        let message = Message::new("task-panicked")
+            .with_param("task", "stats_sample")
+            .with_param("message", "boom");
+
+        assert_eq!(
+            message.english(),
+            "Background task stats_sample stopped unexpectedly: boom"
+        );
+        let french = render(&message.code, &message.params, "fr-CA");
+        assert!(
+            french.starts_with("La tâche de fond stats_sample"),
+            "{}",
+            french
+        );
+        // Unknown locales get English
+        assert_eq!(
+            render(&message.code, &message.params, "xx"),
+            message.english()
+        );
+    }
+
+    #[test]
+    fn test_unknown_codes_and_missing_params_fall_back() {
+        assert_eq!(
+            render("no-such-code", &BTreeMap::new(), "en"),
+            "Something went wrong (error code no-such-code)"
+        );
+        assert!(render("no-such-code", &BTreeMap::new(), "fr").contains("no-such-code"));
+        assert!(Message::new("task-panicked")
+            .english()
+            .contains("{ $task }"));
+    }
+
+    #[test]
+    fn test_parse_catalog_continuations_and_comments() {
+        let catalog = parse_catalog("# comment\nfirst = one\n    two\n\nsecond=three {\n");
+        assert_eq!(catalog["first"], "one\ntwo");
+        assert_eq!(fill(&catalog["second"], &BTreeMap::new()), "three {");
+    }
+}
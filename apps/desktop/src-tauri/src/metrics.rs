@@ -0,0 +1,533 @@
+// Supervisor metrics
+// For headless and CI setups that want to alert on the supervisor rather
+// than poll the UI. Counters are fed by the events the UI consumes (status
+// broadcasts, crash reports, startup outcomes, job progress) through the
+// events observer, which is only installed when the endpoint is enabled.
+// The endpoint answers `GET /metrics` in the Prometheus text format, behind
+// a bearer token, to at most MAX_CONNECTIONS clients at a time.
+
+use crate::crashes::SERVICE_CRASHED_EVENT;
+use crate::jobs::JOB_PROGRESS_EVENT;
+use crate::keyring::KeyringHealth;
+use crate::readiness::{SERVICES_FAILED_EVENT, SERVICES_READY_EVENT};
+use crate::splash::SERVICES_STARTING_EVENT;
+use crate::status::STATUS_CHANGED_EVENT;
+use crate::warn;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Connections answered at once; more wait in the listen backlog
+const MAX_CONNECTIONS: usize = 8;
+
+#[derive(Default)]
+struct Inner {
+    /// Container name → docker state, from the latest status broadcast
+    states: BTreeMap<String, String>,
+    crashes: BTreeMap<String, u64>,
+    restarts: BTreeMap<String, u64>,
+    startup_began: Option<Instant>,
+    last_startup_secs: Option<f64>,
+    startups: BTreeMap<&'static str, u64>,
+    /// (job kind, outcome) → jobs that ended that way
+    jobs: BTreeMap<(String, String), u64>,
+}
+
+/// Counters and gauges about the supervisor, cheap to clone
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Metrics {
+    /// Update from an emitted event; events that don't matter are ignored
+    pub fn observe(&self, event: &str, payload: &Value) {
+        let mut inner = self.inner.lock().unwrap();
+        match event {
+            STATUS_CHANGED_EVENT => {
+                inner.states = payload["containers"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|container| {
+                        Some((
+                            container["name"].as_str()?.to_string(),
+                            container["state"].as_str()?.to_string(),
+                        ))
+                    })
+                    .collect();
+            }
+            SERVICE_CRASHED_EVENT => {
+                let Some(container) = payload["container"].as_str() else {
+                    return;
+                };
+                *inner.crashes.entry(container.to_string()).or_default() += 1;
+                // Docker restarts the container unless the policy was turned off
+                if payload["auto_restart_disabled"] == Value::Bool(false) {
+                    *inner.restarts.entry(container.to_string()).or_default() += 1;
+                }
+            }
+            SERVICES_STARTING_EVENT => inner.startup_began = Some(Instant::now()),
+            SERVICES_READY_EVENT | SERVICES_FAILED_EVENT => {
+                let outcome = if event == SERVICES_READY_EVENT {
+                    "ready"
+                } else {
+                    "failed"
+                };
+                *inner.startups.entry(outcome).or_default() += 1;
+                if let Some(began) = inner.startup_began.take() {
+                    inner.last_startup_secs = Some(began.elapsed().as_secs_f64());
+                }
+            }
+            JOB_PROGRESS_EVENT => {
+                let (Some(kind), Some(state)) =
+                    (payload["kind"].as_str(), payload["state"].as_str())
+                else {
+                    return;
+                };
+                if state != "running" {
+                    *inner
+                        .jobs
+                        .entry((kind.to_string(), state.to_string()))
+                        .or_default() += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The exposition text; keyring health is read at scrape time since
+    /// recovering from a degraded keychain doesn't emit anything
+    pub fn render(&self, keyring: Option<&KeyringHealth>) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        family(
+            &mut out,
+            "arbor_service_up",
+            "gauge",
+            "Whether the service's container is running",
+        );
+        for (service, state) in &inner.states {
+            sample(
+                &mut out,
+                "arbor_service_up",
+                &[("service", service)],
+                (state == "running") as u8,
+            );
+        }
+        family(
+            &mut out,
+            "arbor_service_state",
+            "gauge",
+            "Docker state of each service's container",
+        );
+        for (service, state) in &inner.states {
+            sample(
+                &mut out,
+                "arbor_service_state",
+                &[("service", service), ("state", state)],
+                1,
+            );
+        }
+        family(
+            &mut out,
+            "arbor_service_crashes_total",
+            "counter",
+            "Crashes seen per service",
+        );
+        for (service, count) in &inner.crashes {
+            sample(
+                &mut out,
+                "arbor_service_crashes_total",
+                &[("service", service)],
+                count,
+            );
+        }
+        family(
+            &mut out,
+            "arbor_service_restarts_total",
+            "counter",
+            "Crashes docker restarted the service after",
+        );
+        for (service, count) in &inner.restarts {
+            sample(
+                &mut out,
+                "arbor_service_restarts_total",
+                &[("service", service)],
+                count,
+            );
+        }
+
+        family(
+            &mut out,
+            "arbor_startups_total",
+            "counter",
+            "Stack startups by outcome",
+        );
+        for outcome in ["ready", "failed"] {
+            let count = inner.startups.get(outcome).copied().unwrap_or(0);
+            sample(
+                &mut out,
+                "arbor_startups_total",
+                &[("outcome", outcome)],
+                count,
+            );
+        }
+        if let Some(secs) = inner.last_startup_secs {
+            family(
+                &mut out,
+                "arbor_last_startup_duration_seconds",
+                "gauge",
+                "Time the latest startup took to finish",
+            );
+            sample(
+                &mut out,
+                "arbor_last_startup_duration_seconds",
+                &[],
+                format!("{:.3}", secs),
+            );
+        }
+
+        family(
+            &mut out,
+            "arbor_jobs_total",
+            "counter",
+            "Finished jobs by kind and outcome",
+        );
+        for ((kind, outcome), count) in &inner.jobs {
+            sample(
+                &mut out,
+                "arbor_jobs_total",
+                &[("kind", kind), ("outcome", outcome)],
+                count,
+            );
+        }
+
+        family(
+            &mut out,
+            "arbor_keyring_healthy",
+            "gauge",
+            "Whether the OS keychain is usable; absent before the first check",
+        );
+        if let Some(health) = keyring {
+            sample(&mut out, "arbor_keyring_healthy", &[], health.is_ok() as u8);
+        }
+        out
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answer scrapes on `listener` until the task is cancelled
+pub async fn serve(
+    listener: TcpListener,
+    token: String,
+    render: Arc<dyn Fn() -> String + Send + Sync>,
+) {
+    serve_limited(listener, token, render, MAX_CONNECTIONS).await
+}
+
+async fn serve_limited(
+    listener: TcpListener,
+    token: String,
+    render: Arc<dyn Fn() -> String + Send + Sync>,
+    max_connections: usize,
+) {
+    let connections = Arc::new(Semaphore::new(max_connections));
+    loop {
+        // Taken before accepting, so a flood of idle clients can't pile up tasks
+        let Ok(permit) = connections.clone().acquire_owned().await else {
+            return;
+        };
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("⚠️  Failed to accept metrics connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let token = token.clone();
+        let render = render.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            // Bounds the write too, so a client that stops reading frees its slot
+            let answered =
+                tokio::time::timeout(REQUEST_TIMEOUT * 2, answer(stream, &token, &*render))
+                    .await
+                    .unwrap_or_else(|_| Err("Timed out answering metrics request".to_string()));
+            if let Err(e) = answered {
+                crate::flight::debug(format!("Metrics request failed: {}", e));
+            }
+        });
+    }
+}
+
+async fn answer(
+    mut stream: TcpStream,
+    token: &str,
+    render: &(dyn Fn() -> String + Send + Sync),
+) -> Result<(), String> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| "Timed out reading metrics request".to_string())??;
+
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let authorized = lines
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .any(|(_, value)| {
+            value
+                .trim()
+                .strip_prefix("Bearer ")
+                .is_some_and(|presented| constant_time_eq(presented.trim(), token))
+        });
+
+    let (status, body) = match path.split('?').next().unwrap_or_default() {
+        "/metrics" if method != "GET" => ("405 Method Not Allowed", String::new()),
+        "/metrics" if !authorized => ("401 Unauthorized", String::new()),
+        "/metrics" => ("200 OK", render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        CONTENT_TYPE,
+        body.len()
+    );
+    if status.starts_with("401") {
+        response.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    response.push_str("\r\n");
+    response.push_str(&body);
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to write metrics response: {}", e))?;
+    let _ = stream.shutdown().await;
+    Ok(())
+}
+
+/// Request line and headers, up to the blank line
+async fn read_head(stream: &mut TcpStream) -> Result<String, String> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_BYTES {
+            return Err("Metrics request headers too large".to_string());
+        }
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| format!("Failed to read metrics request: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Compare without returning early, so timing doesn't give the token away
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut difference = a.len() ^ b.len();
+    for (index, byte) in a.iter().enumerate() {
+        difference |= (byte ^ b.get(index).copied().unwrap_or(0)) as usize;
+    }
+    difference == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TOKEN: &str = "metrics-test-token-0123";
+
+    /// `name{labels} value` lines, keyed by everything before the value
+    fn parse(text: &str) -> BTreeMap<String, f64> {
+        text.lines()
+            .filter(|line| !line.starts_with('#') && !line.is_empty())
+            .map(|line| {
+                let (series, value) = line.rsplit_once(' ').unwrap();
+                (series.to_string(), value.parse::<f64>().unwrap())
+            })
+            .collect()
+    }
+
+    fn observed() -> Metrics {
+        let metrics = Metrics::default();
+        metrics.observe(
+            SERVICES_STARTING_EVENT,
+            &json!({ "stage": "services_starting" }),
+        );
+        metrics.observe(
+            STATUS_CHANGED_EVENT,
+            &json!({ "containers": [
+                { "name": "arbor-api", "state": "running", "status": "Up", "ports": [] },
+                { "name": "arbor-db", "state": "exited", "status": "Exited (1)", "ports": [] },
+            ], "restart_policies": {} }),
+        );
+        metrics.observe(SERVICES_READY_EVENT, &json!(["arbor-api"]));
+        for disabled in [false, true] {
+            metrics.observe(
+                SERVICE_CRASHED_EVENT,
+                &json!({ "container": "arbor-db", "auto_restart_disabled": disabled }),
+            );
+        }
+        metrics.observe(
+            JOB_PROGRESS_EVENT,
+            &json!({ "kind": "pull_images", "state": "running" }),
+        );
+        metrics.observe(
+            JOB_PROGRESS_EVENT,
+            &json!({ "kind": "pull_images", "state": "succeeded" }),
+        );
+        metrics
+    }
+
+    #[test]
+    fn test_events_update_metrics() {
+        let samples = parse(&observed().render(Some(&KeyringHealth::Ok)));
+
+        assert_eq!(samples["arbor_service_up{service=\"arbor-api\"}"], 1.0);
+        assert_eq!(samples["arbor_service_up{service=\"arbor-db\"}"], 0.0);
+        assert_eq!(
+            samples["arbor_service_state{service=\"arbor-db\",state=\"exited\"}"],
+            1.0
+        );
+        assert_eq!(
+            samples["arbor_service_crashes_total{service=\"arbor-db\"}"],
+            2.0
+        );
+        assert_eq!(
+            samples["arbor_service_restarts_total{service=\"arbor-db\"}"],
+            1.0
+        );
+        assert_eq!(samples["arbor_startups_total{outcome=\"ready\"}"], 1.0);
+        assert_eq!(samples["arbor_startups_total{outcome=\"failed\"}"], 0.0);
+        assert!(samples["arbor_last_startup_duration_seconds"] >= 0.0);
+        assert_eq!(
+            samples["arbor_jobs_total{kind=\"pull_images\",outcome=\"succeeded\"}"],
+            1.0
+        );
+        assert_eq!(samples["arbor_keyring_healthy"], 1.0);
+        assert!(!samples.contains_key("arbor_jobs_total{kind=\"pull_images\",outcome=\"running\"}"));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    async fn request(addr: std::net::SocketAddr, head: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(head.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_scrape_requires_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = observed();
+        let server = tokio::spawn(serve(
+            listener,
+            TOKEN.to_string(),
+            Arc::new(move || metrics.render(None)),
+        ));
+
+        let response = request(
+            addr,
+            &format!(
+                "GET /metrics HTTP/1.1\r\nHost: x\r\nAuthorization: Bearer {}\r\n\r\n",
+                TOKEN
+            ),
+        )
+        .await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert!(head.contains(CONTENT_TYPE));
+        let samples = parse(body);
+        assert_eq!(samples["arbor_service_up{service=\"arbor-api\"}"], 1.0);
+        assert!(!samples.contains_key("arbor_keyring_healthy"));
+
+        let unauthorized = request(
+            addr,
+            "GET /metrics HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n",
+        )
+        .await;
+        assert!(unauthorized.starts_with("HTTP/1.1 401"), "{}", unauthorized);
+        assert!(!unauthorized.contains("arbor_service_up"));
+        let missing = request(addr, "GET /other HTTP/1.1\r\n\r\n").await;
+        assert!(missing.starts_with("HTTP/1.1 404"));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_connections_beyond_the_limit_wait() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_limited(
+            listener,
+            TOKEN.to_string(),
+            Arc::new(String::new),
+            1,
+        ));
+
+        let idle = TcpStream::connect(addr).await.unwrap();
+        tokio::task::yield_now().await;
+        let waiting = tokio::spawn(request(addr, "GET /other HTTP/1.1\r\n\r\n"));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!waiting.is_finished(), "answered beyond the limit");
+
+        drop(idle);
+        let response = tokio::time::timeout(Duration::from_secs(2), waiting)
+            .await
+            .expect("the waiting connection was never answered")
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        server.abort();
+    }
+}
@@ -0,0 +1,293 @@
+// Database migrations
+// The schema is migrated with drizzle: `make db-migrate` applies the
+// migrations listed in the journal (MIGRATIONS_JOURNAL) that are newer than
+// the last one applied, and drizzle records each in
+// `drizzle.__drizzle_migrations` under its journal timestamp.
+// `get_migration_status` reads the journal from the checkout and asks the
+// database, inside its container, how far it got. `run_migrations` runs the
+// target as a job, so its output streams as `job-output`, and reports the
+// migrations it applied, or the one it stopped at. Both need the stack
+// running, and only one job that migrates the database runs at a time.
+
+use crate::events;
+use crate::jobs::{JobEnv, JobKind, JobManager};
+use crate::messages::{Coded, Message};
+use crate::process::{CommandRunner, SystemRunner};
+use crate::protocol::Handshake;
+use crate::services::{ComposeSetup, StackState};
+use crate::ServiceManager;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use tauri::{AppHandle, Manager, State};
+
+/// Emitted with a ServicesNotRunning when the stack is down
+pub const MIGRATIONS_UNAVAILABLE_EVENT: &str = "migrations-unavailable";
+/// The setup target that applies pending migrations
+pub const MIGRATE_TARGET: &str = "db-migrate";
+/// Setup targets that change the database schema, and so can't run side by side
+pub const MIGRATING_TARGETS: [&str; 3] = [MIGRATE_TARGET, "db-push", "db-reset"];
+pub const MIGRATIONS_JOURNAL: &str = "apps/api/src/db/migrations/meta/_journal.json";
+
+/// The compose service the database runs in, and how to reach it there
+const DATABASE_SERVICE: &str = "postgres";
+const DATABASE_USER: &str = "arbor";
+const DATABASE_NAME: &str = "arbor_dev";
+const APPLIED_QUERY: &str =
+    "SELECT created_at FROM drizzle.__drizzle_migrations ORDER BY created_at";
+
+/// Why migrations weren't looked at or run: there's no database to ask
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ServicesNotRunning;
+
+impl fmt::Display for ServicesNotRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Migrations need the services running; start them first")
+    }
+}
+
+impl Coded for ServicesNotRunning {
+    fn message(&self) -> Message {
+        Message::new("migrations-services-stopped")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationStatus {
+    /// Journal tags, oldest first
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+}
+
+/// What `run_migrations` did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationRun {
+    /// The migrations this run applied, oldest first
+    pub applied: Vec<String>,
+    /// Still pending afterwards
+    pub pending: Vec<String>,
+}
+
+impl MigrationRun {
+    pub fn between(before: &MigrationStatus, after: &MigrationStatus) -> Self {
+        Self {
+            applied: before
+                .pending
+                .iter()
+                .filter(|tag| after.applied.contains(tag))
+                .cloned()
+                .collect(),
+            pending: after.pending.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JournalEntry {
+    tag: String,
+    /// Milliseconds since the epoch; drizzle records it as `created_at`
+    when: i64,
+}
+
+/// How far the database behind `setup` is through the checkout's migrations
+pub async fn status(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<MigrationStatus, String> {
+    let journal = project_root.join(MIGRATIONS_JOURNAL);
+    let contents = std::fs::read_to_string(&journal)
+        .map_err(|e| format!("Failed to read {}: {}", journal.display(), e))?;
+    let journal: Journal = serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid migrations journal: {}", e))?;
+    let applied = applied_timestamps(runner, project_root, setup).await?;
+    Ok(classify(journal.entries, &applied))
+}
+
+/// The `created_at` of every migration drizzle recorded, oldest first
+async fn applied_timestamps(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<Vec<i64>, String> {
+    let spec = setup
+        .compose_command(project_root)
+        .args(["exec", "-T", DATABASE_SERVICE, "psql", "-U", DATABASE_USER])
+        .args(["-d", DATABASE_NAME, "-At", "-c", APPLIED_QUERY]);
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to query the database: {}", e))?;
+    if !output.success() {
+        let stderr = output.stderr_text();
+        // Drizzle creates its table with the first migration
+        if stderr.contains("does not exist") {
+            return Ok(Vec::new());
+        }
+        return Err(format!("Failed to query the database: {}", stderr.trim()));
+    }
+    output
+        .stdout_text()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse()
+                .map_err(|_| format!("Unexpected migration timestamp {:?}", line))
+        })
+        .collect()
+}
+
+/// Drizzle applies the journal entries newer than the last one it recorded
+fn classify(mut entries: Vec<JournalEntry>, applied: &[i64]) -> MigrationStatus {
+    entries.sort_by_key(|entry| entry.when);
+    let last = applied.iter().max().copied().unwrap_or(i64::MIN);
+    let (applied, pending): (Vec<JournalEntry>, Vec<JournalEntry>) =
+        entries.into_iter().partition(|entry| entry.when <= last);
+    let tags = |entries: Vec<JournalEntry>| entries.into_iter().map(|entry| entry.tag).collect();
+    MigrationStatus {
+        applied: tags(applied),
+        pending: tags(pending),
+    }
+}
+
+/// Why a run failed, naming the migration it stopped at where that's known
+pub fn failure(after: Option<&MigrationStatus>, stderr: &str) -> String {
+    let detail = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("make db-migrate failed")
+        .trim();
+    match after.and_then(|status| status.pending.first()) {
+        Some(tag) => format!("Migration {} failed: {}", tag, detail),
+        None => format!("Migrations failed: {}", detail),
+    }
+}
+
+/// The compose setup of the running stack, or ServicesNotRunning
+fn running_setup(app_handle: &AppHandle, env: &JobEnv) -> Result<ComposeSetup, String> {
+    let service_manager = app_handle.state::<ServiceManager>();
+    if !matches!(*service_manager.stack.lock(), StackState::Running { .. }) {
+        events::emit_coded(
+            app_handle,
+            MIGRATIONS_UNAVAILABLE_EVENT,
+            &ServicesNotRunning,
+        );
+        return Err(ServicesNotRunning.to_string());
+    }
+    let active = service_manager.active_setup.lock().clone();
+    Ok(active.unwrap_or_else(|| env.setup.clone()))
+}
+
+/// What the `migrations` job runs with: the running stack's compose setup
+pub fn job_env(app_handle: &AppHandle) -> Result<JobEnv, String> {
+    let mut env = JobEnv::for_app(app_handle)?;
+    env.setup = running_setup(app_handle, &env)?;
+    Ok(env)
+}
+
+/// Applied and pending migrations
+#[tauri::command]
+pub async fn get_migration_status(app_handle: AppHandle) -> Result<MigrationStatus, String> {
+    let env = job_env(&app_handle)?;
+    status(&SystemRunner, &env.project_root, &env.setup).await
+}
+
+/// Apply the pending migrations, refused while another job migrates the
+/// database; `start_job` with `migrations` does the same without waiting
+#[tauri::command]
+pub async fn run_migrations(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    handshake: State<'_, Handshake>,
+) -> Result<MigrationRun, String> {
+    handshake.ensure()?;
+    let (_, done) = jobs.try_start(JobKind::Migrations, job_env(&app_handle)?)?;
+    let result = done
+        .await
+        .map_err(|_| "Migrations were interrupted".to_string())??;
+    serde_json::from_value(result).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::settings::AppSettings;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    fn checkout() -> TempDir {
+        let root = temp_dir();
+        let journal = root.path().join(MIGRATIONS_JOURNAL);
+        std::fs::create_dir_all(journal.parent().unwrap()).unwrap();
+        let entries = [
+            (0, "0000_init", 100),
+            (1, "0001_nodes", 200),
+            (2, "0002_index", 300),
+        ]
+        .map(|(idx, tag, when)| serde_json::json!({ "idx": idx, "tag": tag, "when": when }));
+        let contents = serde_json::json!({ "version": "7", "entries": entries });
+        std::fs::write(journal, contents.to_string()).unwrap();
+        root
+    }
+
+    fn setup(root: &Path) -> ComposeSetup {
+        ComposeSetup::from_settings(&AppSettings::default(), root, Path::new("/data/arbor"))
+    }
+
+    #[tokio::test]
+    async fn test_status_splits_the_journal_at_the_last_applied() {
+        let tmp = checkout();
+        let root = tmp.path();
+        let runner = MockRunner::with_stdout("100\n200\n");
+
+        let status = status(&runner, root, &setup(root)).await.unwrap();
+        assert_eq!(status.applied, ["0000_init", "0001_nodes"]);
+        assert_eq!(status.pending, ["0002_index"]);
+        let call = &runner.calls()[0];
+        let exec = call.args.iter().position(|arg| arg == "exec").unwrap();
+        assert_eq!(
+            call.args[exec..exec + 4],
+            ["exec", "-T", "postgres", "psql"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fresh_database_has_everything_pending() {
+        let tmp = checkout();
+        let root = tmp.path();
+        let runner = MockRunner::new(|_| {
+            Ok(CommandOutput {
+                code: Some(1),
+                stdout: Vec::new(),
+                stderr: b"ERROR:  relation \"drizzle.__drizzle_migrations\" does not exist\n"
+                    .to_vec(),
+            })
+        });
+
+        let before = status(&runner, root, &setup(root)).await.unwrap();
+        assert!(before.applied.is_empty());
+        assert_eq!(before.pending.len(), 3);
+
+        let after = MigrationStatus {
+            applied: vec!["0000_init".to_string()],
+            pending: vec!["0001_nodes".to_string(), "0002_index".to_string()],
+        };
+        assert_eq!(
+            MigrationRun::between(&before, &after).applied,
+            ["0000_init"]
+        );
+        assert_eq!(
+            failure(Some(&after), "error: column \"parent_id\" already exists\n"),
+            "Migration 0001_nodes failed: error: column \"parent_id\" already exists"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+// Metered connection detection
+// Big downloads (image pulls) check whether the connection is metered first.
+// Linux asks NetworkManager over D-Bus, Windows the connection cost API;
+// where the OS doesn't say (macOS, or no NetworkManager) the
+// `assume_metered` setting decides.
+
+use crate::process::{CommandRunner, CommandSpec};
+use crate::settings::MeteredDownloads;
+use serde::{Deserialize, Serialize};
+
+/// What the OS reports about the cost of the current connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionCost {
+    Unmetered,
+    Metered,
+    Unknown,
+}
+
+/// Where a `ConnectionInfo` came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostSource {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    NetworkManager,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    WindowsConnectionCost,
+    /// Nothing detected; the `assume_metered` setting applied
+    Setting,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub cost: ConnectionCost,
+    pub source: CostSource,
+    /// The verdict downloads go by
+    pub metered: bool,
+}
+
+/// What a download did about the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadDecision {
+    Proceeded,
+    Skipped,
+    /// The user was asked and said no
+    Declined,
+}
+
+/// Recorded on the job of every download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadCheck {
+    pub connection: ConnectionInfo,
+    pub policy: MeteredDownloads,
+    pub decision: DownloadDecision,
+}
+
+/// Detect the current connection's cost, falling back to `assume_metered`
+pub async fn detect(runner: &dyn CommandRunner, assume_metered: bool) -> ConnectionInfo {
+    match os_connection_cost(runner).await {
+        Some((cost, source)) if cost != ConnectionCost::Unknown => ConnectionInfo {
+            cost,
+            source,
+            metered: cost == ConnectionCost::Metered,
+        },
+        _ => ConnectionInfo {
+            cost: ConnectionCost::Unknown,
+            source: CostSource::Setting,
+            metered: assume_metered,
+        },
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn os_connection_cost(runner: &dyn CommandRunner) -> Option<(ConnectionCost, CostSource)> {
+    let spec = CommandSpec::new("busctl").args([
+        "--system",
+        "get-property",
+        "org.freedesktop.NetworkManager",
+        "/org/freedesktop/NetworkManager",
+        "org.freedesktop.NetworkManager",
+        "Metered",
+    ]);
+    match runner.output(&spec).await {
+        Ok(output) if output.success() => Some((
+            parse_network_manager_metered(&output.stdout_text()),
+            CostSource::NetworkManager,
+        )),
+        Ok(_) | Err(_) => None,
+    }
+}
+
+#[cfg(windows)]
+async fn os_connection_cost(_runner: &dyn CommandRunner) -> Option<(ConnectionCost, CostSource)> {
+    use windows::Networking::Connectivity::{NetworkCostType, NetworkInformation};
+
+    let cost = tokio::task::spawn_blocking(|| {
+        NetworkInformation::GetInternetConnectionProfile()
+            .and_then(|profile| profile.GetConnectionCost())
+            .and_then(|cost| cost.NetworkCostType())
+    })
+    .await
+    .ok()?;
+    let cost = match cost {
+        Ok(NetworkCostType::Unrestricted) => ConnectionCost::Unmetered,
+        Ok(NetworkCostType::Fixed) | Ok(NetworkCostType::Variable) => ConnectionCost::Metered,
+        // No internet profile at all, or an unknown cost
+        _ => ConnectionCost::Unknown,
+    };
+    Some((cost, CostSource::WindowsConnectionCost))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+async fn os_connection_cost(_runner: &dyn CommandRunner) -> Option<(ConnectionCost, CostSource)> {
+    None
+}
+
+/// `busctl` prints e.g. `u 4`; NMMetered is unknown, yes, no, guess-yes, guess-no
+#[cfg(target_os = "linux")]
+fn parse_network_manager_metered(stdout: &str) -> ConnectionCost {
+    match stdout.trim().strip_prefix("u ").map(str::trim) {
+        Some("1") | Some("3") => ConnectionCost::Metered,
+        Some("2") | Some("4") => ConnectionCost::Unmetered,
+        _ => ConnectionCost::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_network_manager_metered() {
+        assert_eq!(
+            parse_network_manager_metered("u 1\n"),
+            ConnectionCost::Metered
+        );
+        assert_eq!(
+            parse_network_manager_metered("u 3\n"),
+            ConnectionCost::Metered
+        );
+        assert_eq!(
+            parse_network_manager_metered("u 4\n"),
+            ConnectionCost::Unmetered
+        );
+        assert_eq!(
+            parse_network_manager_metered("u 0\n"),
+            ConnectionCost::Unknown
+        );
+        assert_eq!(parse_network_manager_metered(""), ConnectionCost::Unknown);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_detect_falls_back_to_setting() {
+        let metered = MockRunner::with_stdout("u 1\n");
+        let info = detect(&metered, false).await;
+        assert_eq!(info.source, CostSource::NetworkManager);
+        assert!(info.metered);
+
+        let no_network_manager =
+            MockRunner::new(|_| Err(std::io::Error::new(std::io::ErrorKind::NotFound, "busctl")));
+        let info = detect(&no_network_manager, true).await;
+        assert_eq!(info.cost, ConnectionCost::Unknown);
+        assert_eq!(info.source, CostSource::Setting);
+        assert!(info.metered);
+    }
+}
@@ -0,0 +1,353 @@
+// OS notifications
+// A crash or failed start while the window is hidden would otherwise only
+// reach the log, so they (and a new release) are also shown as OS
+// notifications, unless `notifications_enabled` is off or the window has
+// focus. A crash-looping container gets one notification per
+// COALESCE_WINDOW, the next saying how many were held back.
+//
+// On Linux they go through `notify-send` and on macOS through `osascript`;
+// on Windows they're toasts. Clicking one brings the window back, except on
+// macOS, where `osascript` can't tell.
+
+#[cfg(not(windows))]
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::settings::AppSettings;
+use crate::{splash, tray, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+/// At most one notification per container this often
+const COALESCE_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    ServiceCrashed,
+    ServiceFailed,
+    StartFailed,
+    UpdateAvailable,
+}
+
+impl NotificationKind {
+    /// Whether the stack is down until someone acts on it
+    #[cfg(not(windows))]
+    fn urgent(self) -> bool {
+        matches!(
+            self,
+            NotificationKind::ServiceFailed | NotificationKind::StartFailed
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    pub kind: NotificationKind,
+}
+
+/// Where notifications are shown: the OS, or a recording in tests
+pub trait NotificationSink: Send + Sync {
+    fn show(&self, notification: Notification);
+}
+
+struct Recent {
+    shown_at: Instant,
+    held_back: u32,
+}
+
+/// Shows notifications as the settings allow
+#[derive(Clone)]
+pub struct Notifier {
+    sink: Arc<dyn NotificationSink>,
+    settings: watch::Receiver<AppSettings>,
+    recent: Arc<Mutex<HashMap<String, Recent>>>,
+}
+
+impl Notifier {
+    pub fn new(sink: Arc<dyn NotificationSink>, settings: watch::Receiver<AppSettings>) -> Self {
+        Self {
+            sink,
+            settings,
+            recent: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn notify(
+        &self,
+        title: impl Into<String>,
+        body: impl Into<String>,
+        kind: NotificationKind,
+    ) {
+        if !self.settings.borrow().notifications_enabled {
+            return;
+        }
+        self.sink.show(Notification {
+            title: title.into(),
+            body: body.into(),
+            kind,
+        });
+    }
+
+    /// Like `notify`, but at most once per COALESCE_WINDOW for `key`, e.g. a
+    /// container; the next one after says how many were held back
+    pub fn notify_coalesced(
+        &self,
+        key: &str,
+        title: impl Into<String>,
+        body: impl Into<String>,
+        kind: NotificationKind,
+    ) {
+        let held_back = {
+            let mut recent = self.recent.lock().unwrap();
+            match recent.get_mut(key) {
+                Some(last) if last.shown_at.elapsed() < COALESCE_WINDOW => {
+                    last.held_back += 1;
+                    return;
+                }
+                Some(last) => {
+                    last.shown_at = Instant::now();
+                    std::mem::take(&mut last.held_back)
+                }
+                None => {
+                    let shown_at = Instant::now();
+                    recent.insert(
+                        key.to_string(),
+                        Recent {
+                            shown_at,
+                            held_back: 0,
+                        },
+                    );
+                    0
+                }
+            }
+        };
+        let mut body = body.into();
+        if held_back > 0 {
+            body.push_str(&format!(
+                " ({} more since the last notification)",
+                held_back
+            ));
+        }
+        self.notify(title, body, kind);
+    }
+}
+
+/// Notifications from the OS's own tool, skipped while the window has focus
+pub struct OsNotifications {
+    app_handle: AppHandle,
+}
+
+impl OsNotifications {
+    pub fn new(app_handle: AppHandle) -> Self {
+        Self { app_handle }
+    }
+}
+
+impl NotificationSink for OsNotifications {
+    fn show(&self, notification: Notification) {
+        let focused = self
+            .app_handle
+            .get_webview_window(splash::MAIN_WINDOW)
+            .and_then(|window| window.is_focused().ok())
+            .unwrap_or(false);
+        if focused {
+            return;
+        }
+        let app_handle = self.app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = show_native(&app_handle, &notification).await {
+                warn!("⚠️  Failed to show a notification: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(not(windows))]
+async fn show_native(app_handle: &AppHandle, notification: &Notification) -> Result<(), String> {
+    if run_notifier(&SystemRunner, notification).await? {
+        tray::show_window(app_handle);
+    }
+    Ok(())
+}
+
+/// As a toast, which brings the window back when clicked
+#[cfg(windows)]
+async fn show_native(app_handle: &AppHandle, notification: &Notification) -> Result<(), String> {
+    use windows::core::{IInspectable, HSTRING};
+    use windows::Foundation::TypedEventHandler;
+    use windows::UI::Notifications::{
+        ToastNotification, ToastNotificationManager, ToastTemplateType,
+    };
+
+    let app_id = HSTRING::from(app_handle.config().identifier.as_str());
+    let clicked = app_handle.clone();
+    let shown = (move || {
+        let content = ToastNotificationManager::GetTemplateContent(ToastTemplateType::ToastText02)?;
+        let lines = content.GetElementsByTagName(&HSTRING::from("text"))?;
+        for (index, text) in [&notification.title, &notification.body]
+            .into_iter()
+            .enumerate()
+        {
+            let text = content.CreateTextNode(&HSTRING::from(text.as_str()))?;
+            lines.Item(index as u32)?.AppendChild(&text)?;
+        }
+        let toast = ToastNotification::CreateToastNotification(&content)?;
+        let on_click = TypedEventHandler::<ToastNotification, IInspectable>::new(move |_, _| {
+            tray::show_window(&clicked);
+            Ok(())
+        });
+        toast.Activated(&on_click)?;
+        ToastNotificationManager::CreateToastNotifierWithId(&app_id)?.Show(&toast)
+    })();
+    shown.map_err(|e| e.to_string())
+}
+
+/// Run `notify-send` or `osascript`, returning whether the notification was
+/// clicked where that can be told
+#[cfg(not(windows))]
+async fn run_notifier(
+    runner: &dyn CommandRunner,
+    notification: &Notification,
+) -> Result<bool, String> {
+    let spec = notifier_command(notification, true);
+    let output = runner.output(&spec).await.map_err(|e| e.to_string())?;
+    if output.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim() == "default");
+    }
+    // notify-send before 0.7.10 has no actions
+    let plain = notifier_command(notification, false);
+    if plain == spec {
+        return Err(output.stderr_text().trim().to_string());
+    }
+    let output = runner.output(&plain).await.map_err(|e| e.to_string())?;
+    match output.success() {
+        true => Ok(false),
+        false => Err(output.stderr_text().trim().to_string()),
+    }
+}
+
+#[cfg(not(windows))]
+fn notifier_command(notification: &Notification, clickable: bool) -> CommandSpec {
+    let Notification { title, body, kind } = notification;
+    if cfg!(target_os = "macos") {
+        let quoted =
+            |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!(
+            "display notification {} with title {}",
+            quoted(body),
+            quoted(title)
+        );
+        return CommandSpec::new("osascript").args(["-e", &script]);
+    }
+    let urgency = if kind.urgent() { "critical" } else { "normal" };
+    let mut spec = CommandSpec::new("notify-send")
+        .args(["--app-name=Arbor", &format!("--urgency={}", urgency)]);
+    if clickable {
+        spec = spec.args(["--action=default=Open Arbor", "--wait"]);
+    }
+    spec.args(["--", title.as_str(), body.as_str()])
+}
+
+#[cfg(test)]
+pub mod recording {
+    use super::*;
+
+    /// Keeps every notification shown, for tests
+    #[derive(Default)]
+    pub struct RecordingNotifications {
+        shown: Mutex<Vec<Notification>>,
+    }
+
+    impl RecordingNotifications {
+        pub fn shown(&self) -> Vec<Notification> {
+            self.shown.lock().unwrap().clone()
+        }
+    }
+
+    impl NotificationSink for RecordingNotifications {
+        fn show(&self, notification: Notification) {
+            self.shown.lock().unwrap().push(notification);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::recording::RecordingNotifications;
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crash_loops_are_coalesced_and_the_setting_is_respected() {
+        let sink = Arc::new(RecordingNotifications::default());
+        let settings = watch::Sender::new(AppSettings::default());
+        let notifier = Notifier::new(sink.clone(), settings.subscribe());
+        let crashed = |body: &str| {
+            notifier.notify_coalesced(
+                "arbor-api-1",
+                "api crashed",
+                body,
+                NotificationKind::ServiceCrashed,
+            )
+        };
+
+        crashed("Exited with code 1");
+        crashed("Exited with code 1");
+        crashed("Exited with code 1");
+        notifier.notify_coalesced(
+            "arbor-db-1",
+            "db crashed",
+            "Out of memory",
+            NotificationKind::ServiceCrashed,
+        );
+        tokio::time::advance(COALESCE_WINDOW).await;
+        crashed("Exited with code 137");
+        let bodies: Vec<String> = sink.shown().into_iter().map(|shown| shown.body).collect();
+        assert_eq!(
+            bodies,
+            [
+                "Exited with code 1",
+                "Out of memory",
+                "Exited with code 137 (2 more since the last notification)",
+            ]
+        );
+
+        settings.send_modify(|settings| settings.notifications_enabled = false);
+        notifier.notify(
+            "Arbor failed to start",
+            "make up failed",
+            NotificationKind::StartFailed,
+        );
+        assert_eq!(sink.shown().len(), 3);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_old_notify_send_falls_back_to_a_plain_notification() {
+        use crate::process::mock::MockRunner;
+        use crate::process::CommandOutput;
+
+        let runner = MockRunner::new(|spec| {
+            let clickable = spec.args.iter().any(|arg| arg == "--wait");
+            Ok(CommandOutput {
+                code: Some(if clickable { 1 } else { 0 }),
+                stdout: Vec::new(),
+                stderr: b"Unknown option --action=default=Open Arbor\n".to_vec(),
+            })
+        });
+        let notification = Notification {
+            title: "api crashed".to_string(),
+            body: "-1 exit code".to_string(),
+            kind: NotificationKind::ServiceCrashed,
+        };
+        let clicked = run_notifier(&runner, &notification).await;
+        assert_eq!(clicked, Ok(false));
+        let calls = runner.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].args[2..], ["--", "api crashed", "-1 exit code"]);
+    }
+}
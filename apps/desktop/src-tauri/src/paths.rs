@@ -0,0 +1,686 @@
+// Filesystem paths
+// Where the app keeps its state, and sandboxed validation for paths coming
+// from the frontend.
+//
+// Every directory the app uses comes from AppDirs, which follows platform
+// conventions: XDG dirs on Linux (logs under XDG_STATE_HOME), Application
+// Support and ~/Library on macOS, AppData on Windows. Nothing else resolves
+// directories itself, which a test checks. On startup the dirs are created,
+// private where secrets-adjacent state lives, and files earlier versions
+// left in the wrong place are moved once, with a record kept of what moved.
+//
+// Every command or setting that accepts a filesystem path runs it through
+// `validate` with a policy, so `..`, symlinks and absolute paths can't be used
+// to reach files outside what that command is meant to touch.
+
+use crate::command_log::COMMAND_LOGS_DIR_NAME;
+use crate::flight::FLIGHT_DIR_NAME;
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+/// Record of the one-time migration, in the data dir
+pub const MIGRATIONS_FILE_NAME: &str = "path-migrations.json";
+const MIGRATION_VERSION: u32 = 1;
+
+/// Rejected path, tagged so the frontend can tell the cases apart
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PathNotPermitted {
+    /// The path can't be resolved, e.g. a parent directory isn't readable
+    Unresolvable { path: String, detail: String },
+    /// After resolving `..` and symlinks the path is outside its allowed base
+    OutsideBase { path: String, base: String },
+    /// The path doesn't exist or resolves to the wrong kind of file
+    WrongKind { path: String, expected: String },
+}
+
+impl fmt::Display for PathNotPermitted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathNotPermitted::Unresolvable { path, detail } => {
+                write!(
+                    f,
+                    "Path not permitted: {} can't be resolved ({})",
+                    path, detail
+                )
+            }
+            PathNotPermitted::OutsideBase { path, base } => {
+                write!(f, "Path not permitted: {} is outside {}", path, base)
+            }
+            PathNotPermitted::WrongKind { path, expected } => {
+                write!(f, "Path not permitted: {} is not {}", path, expected)
+            }
+        }
+    }
+}
+
+impl From<PathNotPermitted> for String {
+    fn from(error: PathNotPermitted) -> Self {
+        error.to_string()
+    }
+}
+
+/// What a command may do with the path it was given
+#[derive(Debug, Clone, Copy)]
+pub enum PathPolicy<'a> {
+    /// An existing file inside `base`; relative paths are joined onto it
+    FileWithin(&'a Path),
+    /// An existing directory anywhere, e.g. a project checkout
+    Directory,
+    /// An existing regular file anywhere, e.g. a profile to import; callers
+    /// check its contents are what they expect
+    File,
+    /// A new file to write in an existing directory, e.g. an export; nothing
+    /// may exist at the path yet
+    Export,
+    /// Like `Export`, but an existing regular file may be replaced; callers
+    /// check its contents are theirs to replace
+    Overwrite,
+    /// An existing gzip file whose unpacked contents start with the given
+    /// header, e.g. a backup to restore
+    Archive(&'a [u8]),
+    /// An existing executable file named one of `names` (an `.exe` suffix is allowed)
+    Executable(&'a [&'a str]),
+}
+
+/// Resolve `input` (following `..` and symlinks) and check it against `policy`
+/// Returns the canonical path, which callers should use instead of the input.
+pub fn validate(input: &Path, policy: PathPolicy) -> Result<PathBuf, PathNotPermitted> {
+    let display = input.display().to_string();
+    let wrong_kind = |path: String| PathNotPermitted::WrongKind {
+        path,
+        expected: match policy {
+            PathPolicy::FileWithin(_) => "an existing file".to_string(),
+            PathPolicy::Directory => "an existing directory".to_string(),
+            PathPolicy::File => "an existing file".to_string(),
+            PathPolicy::Export => "a new file in an existing directory".to_string(),
+            PathPolicy::Overwrite => "a file in an existing directory".to_string(),
+            PathPolicy::Archive(_) => "an archive of the expected kind".to_string(),
+            PathPolicy::Executable(names) => {
+                format!("an existing executable named {}", names.join(" or "))
+            }
+        },
+    };
+    match policy {
+        PathPolicy::Export => {
+            return validate_export(input, false).map_err(|_| wrong_kind(display.clone()))
+        }
+        PathPolicy::Overwrite => {
+            return validate_export(input, true).map_err(|_| wrong_kind(display.clone()))
+        }
+        _ => {}
+    }
+    let joined = match policy {
+        PathPolicy::FileWithin(base) => base.join(input),
+        _ => input.to_path_buf(),
+    };
+    let resolved = match joined.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(wrong_kind(display)),
+        Err(e) => {
+            return Err(PathNotPermitted::Unresolvable {
+                path: display,
+                detail: e.to_string(),
+            })
+        }
+    };
+
+    let permitted = match policy {
+        PathPolicy::FileWithin(base) => {
+            let base = base
+                .canonicalize()
+                .map_err(|e| PathNotPermitted::Unresolvable {
+                    path: base.display().to_string(),
+                    detail: e.to_string(),
+                })?;
+            if !resolved.starts_with(&base) {
+                return Err(PathNotPermitted::OutsideBase {
+                    path: display,
+                    base: base.display().to_string(),
+                });
+            }
+            resolved.is_file()
+        }
+        PathPolicy::Directory => resolved.is_dir(),
+        PathPolicy::File | PathPolicy::Export | PathPolicy::Overwrite => resolved.is_file(),
+        PathPolicy::Archive(header) => resolved.is_file() && starts_with(&resolved, header),
+        PathPolicy::Executable(names) => {
+            // Either the given name or the symlink target's, e.g. Homebrew's make -> gmake
+            let named = [input, resolved.as_path()].iter().any(|path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.strip_suffix(".exe").unwrap_or(n))
+                    .is_some_and(|stem| names.contains(&stem))
+            });
+            named && is_executable(&resolved)
+        }
+    };
+    if permitted {
+        Ok(resolved)
+    } else {
+        Err(wrong_kind(display))
+    }
+}
+
+/// Whether `path` is gzip data that unpacks to something starting with `header`
+fn starts_with(path: &Path, header: &[u8]) -> bool {
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let mut start = vec![0; header.len()];
+    flate2::read::GzDecoder::new(file)
+        .read_exact(&mut start)
+        .is_ok()
+        && start == header
+}
+
+/// The directory resolved, the file name kept as given, so a symlink at the
+/// destination isn't followed to wherever it points
+fn validate_export(input: &Path, overwrite: bool) -> Result<PathBuf, ()> {
+    let name = input.file_name().ok_or(())?;
+    let dir = match input.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let resolved = dir.canonicalize().map_err(|_| ())?.join(name);
+    match std::fs::symlink_metadata(&resolved) {
+        Ok(metadata) if !overwrite || !metadata.is_file() => Err(()),
+        _ => Ok(resolved),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Every directory the app keeps state in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppDirs {
+    /// What the user chose: settings, trusted files, profiles
+    pub config: PathBuf,
+    /// What the app keeps: jobs, stop reasons, crash reports, profile data
+    pub data: PathBuf,
+    /// Anything that can be regenerated
+    pub cache: PathBuf,
+    /// Command logs and flight recordings
+    pub logs: PathBuf,
+    /// Gone after a reboot, e.g. locks; XDG_RUNTIME_DIR on Linux
+    pub runtime: PathBuf,
+}
+
+impl AppDirs {
+    pub fn resolve(app_handle: &AppHandle) -> Result<Self, String> {
+        let resolver = app_handle.path();
+        let failed = |e: tauri::Error| format!("Failed to resolve app directories: {}", e);
+        let identifier = &app_handle.config().identifier;
+        let cache = resolver.app_cache_dir().map_err(failed)?;
+        Ok(Self {
+            config: resolver.app_config_dir().map_err(failed)?,
+            data: resolver.app_data_dir().map_err(failed)?,
+            logs: match cfg!(target_os = "linux") {
+                true => xdg_state_home(app_handle)?.join(identifier).join("logs"),
+                false => resolver.app_log_dir().map_err(failed)?,
+            },
+            runtime: match resolver.runtime_dir() {
+                Ok(dir) if cfg!(target_os = "linux") => dir.join(identifier),
+                _ => cache.join("runtime"),
+            },
+            cache,
+        })
+    }
+
+    /// Create the dirs; those holding settings, job history and profile env
+    /// files are private to the user
+    pub fn ensure(&self) -> Result<(), String> {
+        for (dir, private) in [
+            (&self.config, true),
+            (&self.data, true),
+            (&self.runtime, true),
+            (&self.cache, false),
+            (&self.logs, false),
+        ] {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            if private {
+                restrict(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// $XDG_STATE_HOME, or its default ~/.local/state
+fn xdg_state_home(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    match std::env::var_os("XDG_STATE_HOME").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => Ok(dir),
+        _ => app_handle
+            .path()
+            .home_dir()
+            .map(|home| home.join(".local").join("state"))
+            .map_err(|e| format!("Failed to resolve the home directory: {}", e)),
+    }
+}
+
+#[cfg(unix)]
+fn restrict(dir: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .map_err(|e| format!("Failed to restrict {}: {}", dir.display(), e))
+}
+
+#[cfg(not(unix))]
+fn restrict(_dir: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+pub fn data_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    AppDirs::resolve(app_handle).map(|dirs| dirs.data)
+}
+
+pub fn log_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    AppDirs::resolve(app_handle).map(|dirs| dirs.logs)
+}
+
+/// Files bundled with the app, where the platform has such a dir
+pub fn bundled_resources(app_handle: &AppHandle) -> Option<PathBuf> {
+    app_handle.path().resource_dir().ok()
+}
+
+/// A file or directory moved out of a legacy location
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MovedPath {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Why it was left in place, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    pub version: u32,
+    pub migrated_at: String,
+    pub moved: Vec<MovedPath>,
+}
+
+/// Where earlier versions put things, and where they belong now: command
+/// logs went to Tauri's log dir, which on Linux is in XDG_DATA_HOME rather
+/// than XDG_STATE_HOME, and flight recordings went to the data dir
+pub fn legacy_moves(dirs: &AppDirs, legacy_log_dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+    [
+        (
+            legacy_log_dir.join(COMMAND_LOGS_DIR_NAME),
+            dirs.logs.join(COMMAND_LOGS_DIR_NAME),
+        ),
+        (
+            dirs.data.join(FLIGHT_DIR_NAME),
+            dirs.logs.join(FLIGHT_DIR_NAME),
+        ),
+    ]
+    .into_iter()
+    .filter(|(from, to)| from != to)
+    .collect()
+}
+
+/// Move whatever of `moves` exists, once: a record at `record_path` from
+/// this migration version means it already ran. Nothing is overwritten.
+pub fn migrate(
+    moves: &[(PathBuf, PathBuf)],
+    record_path: &Path,
+) -> Result<MigrationRecord, String> {
+    if let Some(record) = std::fs::read_to_string(record_path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<MigrationRecord>(&contents).ok())
+        .filter(|record| record.version >= MIGRATION_VERSION)
+    {
+        return Ok(record);
+    }
+
+    let mut record = MigrationRecord {
+        version: MIGRATION_VERSION,
+        migrated_at: chrono::Utc::now().to_rfc3339(),
+        moved: Vec::new(),
+    };
+    for (from, to) in moves.iter().filter(|(from, _)| from.exists()) {
+        let skipped = if to.exists() {
+            Some("already exists at the new location".to_string())
+        } else {
+            to.parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::rename(from, to))
+                .err()
+                .map(|e| e.to_string())
+        };
+        match &skipped {
+            Some(reason) => warn!("⚠️  Left {} in place: {}", from.display(), reason),
+            None => info!("📦 Moved {} to {}", from.display(), to.display()),
+        }
+        record.moved.push(MovedPath {
+            from: from.clone(),
+            to: to.clone(),
+            skipped,
+        });
+    }
+
+    let contents = serde_json::to_string_pretty(&record).map_err(|e| e.to_string())?;
+    std::fs::write(record_path, contents)
+        .map_err(|e| format!("Failed to save the path migration record: {}", e))?;
+    Ok(record)
+}
+
+/// Create the app dirs and move legacy files into them
+pub fn prepare(app_handle: &AppHandle) -> Result<AppDirs, String> {
+    let dirs = AppDirs::resolve(app_handle)?;
+    dirs.ensure()?;
+    let legacy_log_dir = app_handle
+        .path()
+        .app_log_dir()
+        .map_err(|e| format!("Failed to resolve app directories: {}", e))?;
+    migrate(
+        &legacy_moves(&dirs, &legacy_log_dir),
+        &dirs.data.join(MIGRATIONS_FILE_NAME),
+    )?;
+    Ok(dirs)
+}
+
+#[tauri::command]
+pub async fn get_app_dirs(app_handle: AppHandle) -> Result<AppDirs, String> {
+    AppDirs::resolve(&app_handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    /// A scratch directory holding `base/inside.log` and, outside of `base`,
+    /// `secret.txt`
+    fn fixture(tmp: &TempDir) -> PathBuf {
+        let dir = tmp.path().to_path_buf();
+        std::fs::create_dir_all(dir.join("base")).unwrap();
+        std::fs::write(dir.join("base/inside.log"), "ok").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_file_within_base() {
+        let tmp = temp_dir();
+        let dir = fixture(&tmp);
+        let base = dir.join("base");
+
+        let resolved = validate(Path::new("inside.log"), PathPolicy::FileWithin(&base)).unwrap();
+        assert_eq!(resolved, base.join("inside.log").canonicalize().unwrap());
+
+        assert!(matches!(
+            validate(Path::new("missing.log"), PathPolicy::FileWithin(&base)),
+            Err(PathNotPermitted::WrongKind { .. })
+        ));
+        assert!(matches!(
+            validate(Path::new("."), PathPolicy::FileWithin(&base)),
+            Err(PathNotPermitted::WrongKind { .. })
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_traversal_is_rejected() {
+        let tmp = temp_dir();
+        let dir = fixture(&tmp);
+        let base = dir.join("base");
+
+        for input in [
+            PathBuf::from("../secret.txt"),
+            PathBuf::from("./../base/../secret.txt"),
+            dir.join("secret.txt"),
+        ] {
+            assert!(
+                matches!(
+                    validate(&input, PathPolicy::FileWithin(&base)),
+                    Err(PathNotPermitted::OutsideBase { .. })
+                ),
+                "{:?} should be rejected",
+                input
+            );
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escape_is_rejected() {
+        let tmp = temp_dir();
+        let dir = fixture(&tmp);
+        let base = dir.join("base");
+        std::os::unix::fs::symlink(dir.join("secret.txt"), base.join("link.log")).unwrap();
+        std::os::unix::fs::symlink(&dir, base.join("up")).unwrap();
+
+        for input in ["link.log", "up/secret.txt"] {
+            assert!(
+                matches!(
+                    validate(Path::new(input), PathPolicy::FileWithin(&base)),
+                    Err(PathNotPermitted::OutsideBase { .. })
+                ),
+                "{} should be rejected",
+                input
+            );
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_executable_policy() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = temp_dir();
+        let dir = fixture(&tmp);
+        let make = dir.join("make");
+        std::fs::write(&make, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&make, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let alias = dir.join("totally-make");
+        std::os::unix::fs::symlink(&make, &alias).unwrap();
+
+        assert!(validate(&make, PathPolicy::Executable(&["make", "gmake"])).is_ok());
+        // Symlinks are judged by what they resolve to
+        assert!(validate(&alias, PathPolicy::Executable(&["make"])).is_ok());
+        assert!(validate(&make, PathPolicy::Executable(&["docker"])).is_err());
+        assert!(validate(
+            &dir.join("secret.txt"),
+            PathPolicy::Executable(&["secret.txt"])
+        )
+        .is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_and_export_policies() {
+        let tmp = temp_dir();
+        let dir = fixture(&tmp);
+        let secret = dir.join("secret.txt");
+        std::os::unix::fs::symlink(&secret, dir.join("base/link.json")).unwrap();
+
+        let resolved = validate(&dir.join("base/../secret.txt"), PathPolicy::File).unwrap();
+        assert_eq!(resolved, secret.canonicalize().unwrap());
+        assert!(validate(&dir.join("base"), PathPolicy::File).is_err());
+
+        let export = validate(&dir.join("base/../new.json"), PathPolicy::Export).unwrap();
+        assert_eq!(export, dir.canonicalize().unwrap().join("new.json"));
+        assert!(validate(&secret, PathPolicy::Export).is_err());
+        assert_eq!(
+            validate(&dir.join("base/../secret.txt"), PathPolicy::Overwrite).unwrap(),
+            secret.canonicalize().unwrap()
+        );
+        for input in [
+            dir.join("base/link.json"),
+            dir.join("base"),
+            dir.join("missing/new.json"),
+            dir.join("base/.."),
+        ] {
+            for policy in [PathPolicy::Export, PathPolicy::Overwrite] {
+                assert!(
+                    matches!(
+                        validate(&input, policy),
+                        Err(PathNotPermitted::WrongKind { .. })
+                    ),
+                    "{:?} should be rejected",
+                    input
+                );
+            }
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_archive_policy_checks_the_header() {
+        let tmp = temp_dir();
+        let dir = fixture(&tmp);
+        let gzip = |name: &str, contents: &[u8]| {
+            let path = dir.join(name);
+            let file = std::fs::File::create(&path).unwrap();
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+            std::io::Write::write_all(&mut encoder, contents).unwrap();
+            encoder.finish().unwrap();
+            path
+        };
+        let backup = gzip("backup.tar.gz", b"arbor\0 and the rest");
+
+        let resolved = validate(
+            &dir.join("base/../backup.tar.gz"),
+            PathPolicy::Archive(b"arbor\0"),
+        );
+        assert_eq!(resolved.unwrap(), backup.canonicalize().unwrap());
+        for input in [
+            gzip("other.tar.gz", b"other\0 and the rest"),
+            gzip("short.tar.gz", b"arb"),
+            dir.join("secret.txt"),
+            dir.join("base"),
+            dir.join("missing.tar.gz"),
+        ] {
+            assert!(
+                matches!(
+                    validate(&input, PathPolicy::Archive(b"arbor\0")),
+                    Err(PathNotPermitted::WrongKind { .. })
+                ),
+                "{:?} should be rejected",
+                input
+            );
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn app_dirs(root: &Path) -> AppDirs {
+        AppDirs {
+            config: root.join("config"),
+            data: root.join("data"),
+            cache: root.join("cache"),
+            logs: root.join("state/logs"),
+            runtime: root.join("runtime"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secrets_adjacent_dirs_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = temp_dir();
+        let root = fixture(&tmp);
+        let dirs = app_dirs(&root);
+        dirs.ensure().unwrap();
+
+        let mode = |dir: &Path| std::fs::metadata(dir).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dirs.config), 0o700);
+        assert_eq!(mode(&dirs.data), 0o700);
+        assert_eq!(mode(&dirs.runtime), 0o700);
+        assert!(dirs.logs.is_dir() && dirs.cache.is_dir());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_legacy_files_move_once_without_overwriting() {
+        let tmp = temp_dir();
+        let root = fixture(&tmp);
+        let dirs = app_dirs(&root);
+        let legacy_logs = dirs.data.join("logs");
+        std::fs::create_dir_all(legacy_logs.join(COMMAND_LOGS_DIR_NAME)).unwrap();
+        std::fs::write(legacy_logs.join(COMMAND_LOGS_DIR_NAME).join("up.log"), "ok").unwrap();
+        std::fs::create_dir_all(dirs.data.join(FLIGHT_DIR_NAME)).unwrap();
+        std::fs::create_dir_all(dirs.logs.join(FLIGHT_DIR_NAME)).unwrap();
+        dirs.ensure().unwrap();
+        let record_path = dirs.data.join(MIGRATIONS_FILE_NAME);
+
+        let moves = legacy_moves(&dirs, &legacy_logs);
+        let record = migrate(&moves, &record_path).unwrap();
+        assert_eq!(record.moved.len(), 2);
+        assert!(record.moved[0].skipped.is_none());
+        assert!(dirs
+            .logs
+            .join(COMMAND_LOGS_DIR_NAME)
+            .join("up.log")
+            .is_file());
+        assert!(!legacy_logs.join(COMMAND_LOGS_DIR_NAME).exists());
+        // Something already at the new location is never overwritten
+        assert!(record.moved[1].skipped.is_some());
+        assert!(dirs.data.join(FLIGHT_DIR_NAME).exists());
+
+        // Later launches find the record and leave things alone
+        std::fs::create_dir_all(legacy_logs.join(COMMAND_LOGS_DIR_NAME)).unwrap();
+        assert_eq!(migrate(&moves, &record_path).unwrap(), record);
+        assert!(legacy_logs.join(COMMAND_LOGS_DIR_NAME).exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_only_this_module_resolves_app_directories() {
+        const RESOLVERS: [&str; 12] = [
+            "app_config_dir(",
+            "app_data_dir(",
+            "app_local_data_dir(",
+            "app_cache_dir(",
+            "app_log_dir(",
+            "runtime_dir(",
+            "resource_dir(",
+            "home_dir(",
+            "dirs::",
+            "\"~/",
+            "Application Support",
+            "AppData",
+        ];
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut offenders = Vec::new();
+        for entry in std::fs::read_dir(&src).unwrap().flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "rs") || path.ends_with("paths.rs") {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path).unwrap();
+            for (number, line) in contents.lines().enumerate() {
+                if RESOLVERS.iter().any(|resolver| line.contains(resolver)) {
+                    offenders.push(format!(
+                        "{}:{}: {}",
+                        path.display(),
+                        number + 1,
+                        line.trim()
+                    ));
+                }
+            }
+        }
+        assert!(
+            offenders.is_empty(),
+            "Get directories from paths::AppDirs instead:\n{}",
+            offenders.join("\n")
+        );
+    }
+}
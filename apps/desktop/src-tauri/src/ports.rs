@@ -0,0 +1,206 @@
+// Port conflict pre-check
+// `make up` fails with a wall of compose output when something else holds a
+// port the stack publishes, and that read as "the app won't start". Before
+// `make up`, each host port in the compose config is bound for a moment; the
+// ones that can't be are reported as `ports-in-use`, with the process holding
+// them where `lsof` can tell (not on Windows). Ports published by Arbor's own
+// running containers are skipped, since `make up` keeps those containers.
+
+use crate::docker;
+use crate::messages::{Coded, Message};
+use crate::paths;
+use crate::process::{self, CommandRunner};
+use crate::services::{self, ComposeSetup};
+use crate::settings::SettingsStore;
+use crate::ServiceManager;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::TcpListener;
+use std::path::Path;
+use tauri::{AppHandle, Manager};
+
+/// Emitted with a PortsInUse when starting is refused over held ports
+pub const PORTS_IN_USE_EVENT: &str = "ports-in-use";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortConflict {
+    pub port: u16,
+    /// The compose service that publishes it
+    pub service: String,
+    /// The name of the process holding it, if that could be found
+    pub process: Option<String>,
+}
+
+/// Why the stack wasn't started: ports something else holds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortsInUse {
+    pub conflicts: Vec<PortConflict>,
+}
+
+impl PortsInUse {
+    /// The ports, each with its holder where known, e.g. `5432 (postgres.app), 8080`
+    fn list(&self) -> String {
+        self.conflicts
+            .iter()
+            .map(|conflict| match &conflict.process {
+                Some(process) => format!("{} ({})", conflict.port, process),
+                None => conflict.port.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for PortsInUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let described: Vec<String> = self
+            .conflicts
+            .iter()
+            .map(|conflict| match &conflict.process {
+                Some(process) => format!("Port {} is used by {}", conflict.port, process),
+                None => format!("Port {} is already in use", conflict.port),
+            })
+            .collect();
+        let them = if described.len() == 1 { "it" } else { "them" };
+        write!(f, "{}; free {} and start again", described.join("; "), them)
+    }
+}
+
+impl Coded for PortsInUse {
+    fn message(&self) -> Message {
+        Message::new("ports-in-use").with_param("ports", self.list())
+    }
+}
+
+/// The stack's published ports something other than Arbor holds
+pub async fn check(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<Vec<PortConflict>, String> {
+    let published = services::published_ports(runner, project_root, setup).await?;
+    let ours: Vec<u16> = docker::list_containers(runner)
+        .await?
+        .into_iter()
+        .filter(|container| container.is_running())
+        .flat_map(|container| container.ports)
+        .collect();
+    Ok(find_conflicts(runner, &published, &ours).await)
+}
+
+/// The ports of `published` that can't be bound, leaving out `ours`
+async fn find_conflicts(
+    runner: &dyn CommandRunner,
+    published: &BTreeMap<String, Vec<u16>>,
+    ours: &[u16],
+) -> Vec<PortConflict> {
+    let mut conflicts = Vec::new();
+    for (service, ports) in published {
+        for &port in ports {
+            if ours.contains(&port) || is_free(port) {
+                continue;
+            }
+            conflicts.push(PortConflict {
+                port,
+                service: service.clone(),
+                process: holder(runner, port).await,
+            });
+        }
+    }
+    conflicts.sort_by_key(|conflict| conflict.port);
+    conflicts
+}
+
+/// Whether a listener could be opened on `port`, as compose would publish it
+fn is_free(port: u16) -> bool {
+    TcpListener::bind(("0.0.0.0", port)).is_ok()
+}
+
+/// The name of the process listening on `port`
+#[cfg(unix)]
+async fn holder(runner: &dyn CommandRunner, port: u16) -> Option<String> {
+    let spec = process::CommandSpec::new("lsof").args([
+        "-nP".to_string(),
+        format!("-iTCP:{}", port),
+        "-sTCP:LISTEN".to_string(),
+        "-Fc".to_string(),
+    ]);
+    let output = runner.output(&spec).await.ok()?;
+    // One `p<pid>` then `c<command>` line per process
+    output
+        .stdout_text()
+        .lines()
+        .find_map(|line| line.strip_prefix('c'))
+        .map(str::to_string)
+}
+
+#[cfg(not(unix))]
+async fn holder(_runner: &dyn CommandRunner, _port: u16) -> Option<String> {
+    None
+}
+
+/// Published ports of the stack that are held by something else
+#[tauri::command]
+pub async fn check_port_conflicts(app_handle: AppHandle) -> Result<Vec<PortConflict>, String> {
+    let project_root = crate::find_project_root(&app_handle)?;
+    let data_dir = paths::data_dir(&app_handle)?;
+    let setup = app_handle
+        .state::<ServiceManager>()
+        .active_setup
+        .lock()
+        .clone()
+        .unwrap_or_else(|| {
+            ComposeSetup::from_settings(
+                &app_handle.state::<SettingsStore>().get(),
+                &project_root,
+                &data_dir,
+            )
+        });
+    check(&process::SystemRunner, &project_root, &setup).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+
+    #[tokio::test]
+    async fn test_held_ports_are_reported_with_their_holder() {
+        let held = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let held_port = held.local_addr().unwrap().port();
+        let free_port = TcpListener::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let ours = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let our_port = ours.local_addr().unwrap().port();
+        let runner = MockRunner::with_stdout("p4242\ncpostgres\n");
+        let published = BTreeMap::from([
+            ("api".to_string(), vec![free_port, our_port]),
+            ("postgres".to_string(), vec![held_port]),
+        ]);
+
+        let conflicts = find_conflicts(&runner, &published, &[our_port]).await;
+        let process = cfg!(unix).then(|| "postgres".to_string());
+        assert_eq!(
+            conflicts,
+            [PortConflict {
+                port: held_port,
+                service: "postgres".to_string(),
+                process: process.clone(),
+            }]
+        );
+
+        let error = PortsInUse { conflicts };
+        let expected = match &process {
+            Some(process) => format!("Port {} is used by {}", held_port, process),
+            None => format!("Port {} is already in use", held_port),
+        };
+        assert_eq!(
+            error.to_string(),
+            format!("{}; free it and start again", expected)
+        );
+    }
+}
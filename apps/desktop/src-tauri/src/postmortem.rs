@@ -0,0 +1,324 @@
+// Crash post-mortems
+// When the crash monitor sees an unexpected exit, the evidence is collected
+// right away into `crashes/<container>-<timestamp>/` in the app data dir:
+// recent logs, redacted `docker inspect`, exit details, recent stats, a
+// snapshot of the app's own state and the flight recorder's buffer. Only the
+// newest bundles are kept.
+
+use crate::crashes::CrashReport;
+use crate::docker::ContainerExit;
+use crate::process::{CommandRunner, CommandSpec};
+use crate::stats::ContainerStats;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::State;
+
+pub const CRASHES_DIR_NAME: &str = "crashes";
+
+const LOG_LINES: &str = "500";
+const MAX_POSTMORTEMS: usize = 20;
+const REPORT_FILE: &str = "crash.json";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// Env vars whose values never leave the machine in a post-mortem
+const SECRET_MARKERS: [&str; 6] = ["PASSWORD", "SECRET", "TOKEN", "KEY", "CREDENTIAL", "ACCESS"];
+
+/// Whether an env var or config key named `name` may hold a secret
+pub fn is_secret_name(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    SECRET_MARKERS.iter().any(|marker| upper.contains(marker))
+}
+
+/// Snapshot of the app's own state at the time of a crash
+pub type StateSnapshot = Arc<dyn Fn() -> Value + Send + Sync>;
+
+/// Contents of `crash.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PostMortemReport {
+    container: String,
+    captured_at: String,
+    report: Value,
+    exit: Value,
+}
+
+/// A captured post-mortem, as listed by `list_crash_reports`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CrashReportSummary {
+    pub id: String,
+    pub container: String,
+    pub captured_at: String,
+    pub path: String,
+    pub report: Value,
+}
+
+/// Where post-mortems are written, and how app state is captured into them
+#[derive(Clone)]
+pub struct PostMortemStore {
+    dir: PathBuf,
+    state_snapshot: StateSnapshot,
+}
+
+impl PostMortemStore {
+    pub fn new(dir: PathBuf, state_snapshot: StateSnapshot) -> Self {
+        Self {
+            dir,
+            state_snapshot,
+        }
+    }
+
+    /// Write a post-mortem bundle for `report`, then enforce the retention cap
+    pub async fn capture(
+        &self,
+        runner: &dyn CommandRunner,
+        report: &CrashReport,
+        exit: &ContainerExit,
+        stats: &[ContainerStats],
+    ) -> Result<PathBuf, String> {
+        let now = chrono::Utc::now();
+        let bundle = self.dir.join(format!(
+            "{}-{}",
+            report.container,
+            now.format(TIMESTAMP_FORMAT)
+        ));
+        std::fs::create_dir_all(&bundle)
+            .map_err(|e| format!("Failed to create post-mortem directory: {}", e))?;
+
+        let logs =
+            CommandSpec::new("docker").args(["logs", "--tail", LOG_LINES, &report.container]);
+        let logs = match runner.output(&logs).await {
+            Ok(output) => format!(
+                "{}\n--- stderr ---\n{}",
+                output.stdout_text(),
+                output.stderr_text()
+            ),
+            Err(e) => format!("Failed to read logs: {}", e),
+        };
+        write(&bundle.join("logs.txt"), logs.as_bytes())?;
+
+        let inspect = CommandSpec::new("docker").args(["inspect", &report.container]);
+        let inspect = match runner.output(&inspect).await {
+            Ok(output) if output.success() => serde_json::from_slice(&output.stdout)
+                .map(redact_inspect)
+                .unwrap_or_else(|e| Value::String(format!("Unparseable inspect output: {}", e))),
+            Ok(output) => Value::String(output.stderr_text().trim().to_string()),
+            Err(e) => Value::String(format!("Failed to inspect: {}", e)),
+        };
+        write_json(&bundle.join("inspect.json"), &inspect)?;
+
+        write_json(
+            &bundle.join(REPORT_FILE),
+            &PostMortemReport {
+                container: report.container.clone(),
+                captured_at: now.to_rfc3339(),
+                report: serde_json::to_value(report).map_err(|e| e.to_string())?,
+                exit: serde_json::to_value(exit).map_err(|e| e.to_string())?,
+            },
+        )?;
+        write_json(&bundle.join("stats.json"), &stats)?;
+        write_json(&bundle.join("app-state.json"), &(self.state_snapshot)())?;
+        // What the app was doing in the moments before
+        crate::flight::write_to(&bundle.join(crate::flight::FLIGHT_FILE_NAME))?;
+
+        self.prune()?;
+        Ok(bundle)
+    }
+
+    /// Captured post-mortems, newest first
+    pub fn list(&self) -> Result<Vec<CrashReportSummary>, String> {
+        let mut summaries = Vec::new();
+        for bundle in self.bundles()? {
+            let Ok(contents) = std::fs::read_to_string(bundle.join(REPORT_FILE)) else {
+                continue;
+            };
+            let Ok(report) = serde_json::from_str::<PostMortemReport>(&contents) else {
+                continue;
+            };
+            summaries.push(CrashReportSummary {
+                id: bundle_id(&bundle),
+                container: report.container,
+                captured_at: report.captured_at,
+                path: bundle.display().to_string(),
+                report: report.report,
+            });
+        }
+        Ok(summaries)
+    }
+
+    /// Bundle directories, newest first
+    pub fn bundles(&self) -> Result<Vec<PathBuf>, String> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read crash reports: {}", e)),
+        };
+
+        let mut bundles: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect();
+        // Container names contain dashes, the timestamp suffix doesn't
+        bundles.sort_by_key(|path| {
+            let id = bundle_id(path);
+            id.rsplit_once('-')
+                .map(|(_, ts)| ts.to_string())
+                .unwrap_or_default()
+        });
+        bundles.reverse();
+        Ok(bundles)
+    }
+
+    fn prune(&self) -> Result<(), String> {
+        for stale in self.bundles()?.into_iter().skip(MAX_POSTMORTEMS) {
+            std::fs::remove_dir_all(&stale)
+                .map_err(|e| format!("Failed to remove old post-mortem {:?}: {}", stale, e))?;
+        }
+        Ok(())
+    }
+}
+
+fn bundle_id(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<(), String> {
+    let contents = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+    write(path, &contents)
+}
+
+/// Blank out the values of secret-looking env vars in `docker inspect` output
+fn redact_inspect(mut inspect: Value) -> Value {
+    let containers = inspect.as_array_mut().into_iter().flatten();
+    for env in containers.filter_map(|c| c.pointer_mut("/Config/Env")?.as_array_mut()) {
+        for var in env.iter_mut() {
+            let Some((name, _)) = var.as_str().and_then(|v| v.split_once('=')) else {
+                continue;
+            };
+            if is_secret_name(name) {
+                *var = Value::String(format!("{}=<redacted>", name));
+            }
+        }
+    }
+    inspect
+}
+
+#[tauri::command]
+pub async fn list_crash_reports(
+    store: State<'_, PostMortemStore>,
+) -> Result<Vec<CrashReportSummary>, String> {
+    store.list()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crashes::CrashCause;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    const INSPECT: &str = r#"[{"Name":"/arbor-postgres","Config":{"Env":["POSTGRES_USER=arbor","POSTGRES_PASSWORD=hunter2","PATH=/usr/bin"]}}]"#;
+
+    fn store(dir: &TempDir) -> PostMortemStore {
+        PostMortemStore::new(
+            dir.path().to_path_buf(),
+            Arc::new(|| serde_json::json!({ "dev_mode": false })),
+        )
+    }
+
+    fn runner() -> MockRunner {
+        MockRunner::new(|spec| {
+            let stdout = match spec.args.first().map(String::as_str) {
+                Some("logs") => "LOG:  database system is ready\n".to_string(),
+                Some("inspect") => INSPECT.to_string(),
+                _ => String::new(),
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.into_bytes(),
+                stderr: b"FATAL:  out of shared memory\n".to_vec(),
+            })
+        })
+    }
+
+    fn report(container: &str) -> CrashReport {
+        CrashReport {
+            container: container.to_string(),
+            cause: CrashCause::Exited { exit_code: 1 },
+            recent_crashes: 1,
+            restart_policy: Default::default(),
+            auto_restart_disabled: false,
+        }
+    }
+
+    fn exit() -> ContainerExit {
+        ContainerExit {
+            exit_code: 1,
+            oom_killed: false,
+            memory_limit_bytes: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_writes_redacted_bundle() {
+        let tmp = temp_dir();
+        let store = store(&tmp);
+        let runner = runner();
+
+        let bundle = store
+            .capture(&runner, &report("arbor-postgres"), &exit(), &[])
+            .await
+            .unwrap();
+
+        let logs = std::fs::read_to_string(bundle.join("logs.txt")).unwrap();
+        assert!(logs.contains("database system is ready"));
+        assert!(logs.contains("out of shared memory"));
+        assert!(runner.calls()[0]
+            .args
+            .ends_with(&["500".to_string(), "arbor-postgres".to_string()]));
+
+        let inspect = std::fs::read_to_string(bundle.join("inspect.json")).unwrap();
+        assert!(!inspect.contains("hunter2"));
+        assert!(inspect.contains("POSTGRES_PASSWORD=<redacted>"));
+        assert!(inspect.contains("POSTGRES_USER=arbor"));
+
+        for file in ["crash.json", "stats.json", "app-state.json", crate::flight::FLIGHT_FILE_NAME] {
+            assert!(bundle.join(file).is_file(), "{} missing", file);
+        }
+
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].container, "arbor-postgres");
+        assert_eq!(listed[0].report["cause"]["kind"], "Exited");
+    }
+
+    #[test]
+    fn test_retention_keeps_newest() {
+        let tmp = temp_dir();
+        let store = store(&tmp);
+        for i in 0..MAX_POSTMORTEMS + 3 {
+            let bundle = store
+                .dir
+                .join(format!("arbor-redis-20260101T0000{:02}Z", i));
+            std::fs::create_dir_all(bundle).unwrap();
+        }
+
+        store.prune().unwrap();
+
+        let bundles = store.bundles().unwrap();
+        assert_eq!(bundles.len(), MAX_POSTMORTEMS);
+        assert_eq!(
+            bundle_id(&bundles[0]),
+            format!("arbor-redis-20260101T0000{:02}Z", MAX_POSTMORTEMS + 2)
+        );
+        assert!(!store.dir.join("arbor-redis-20260101T000000Z").exists());
+    }
+}
@@ -0,0 +1,849 @@
+// External process execution
+// Every invocation of docker/make goes through the CommandRunner trait so the
+// callers can be exercised against a mock runner in tests. Long-running
+// children whose output is streamed are spawned with `spawn_piped`, which
+// guarantees their pipes are drained.
+
+use crate::flight;
+use crate::warn;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::Duration;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A program invocation expressed as an argv array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Working directory, the app's own when unset
+    pub cwd: Option<PathBuf>,
+    /// Extra environment on top of the app's
+    pub env: Vec<(String, OsString)>,
+}
+
+impl CommandSpec {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            cwd: None,
+            env: Vec::new(),
+        }
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    fn to_command(&self) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(&self.program);
+        command.args(&self.args).envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(dir) = &self.cwd {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<OsString>,
+    {
+        self.env
+            .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// The invocation as a copy-pasteable command line, for logs and errors
+    /// Commands are always run from the argv array, never from this string.
+    pub fn command_line(&self) -> String {
+        let quote = |arg: &String| {
+            if cfg!(windows) {
+                quote::windows_cmd(arg).unwrap_or_else(|| "<argument with newline>".to_string())
+            } else {
+                quote::posix(arg)
+            }
+        };
+        std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(quote)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Shell quoting of single arguments
+/// Nothing in the app runs through a shell; this exists so rendered command
+/// lines are safe to paste, and for any future place a shell can't be avoided.
+pub mod quote {
+    /// Quote for POSIX `sh`: single quotes, with embedded `'` spliced in as `'\''`
+    pub fn posix(arg: &str) -> String {
+        let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+        if !arg.is_empty() && arg.chars().all(safe) {
+            return arg.to_string();
+        }
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+
+    /// Quote for `cmd.exe` running a program with MSVC argv parsing
+    /// First quoted for CommandLineToArgvW, then every cmd metacharacter
+    /// (including the quotes and `%`) is escaped with `^`. cmd can't carry a
+    /// newline inside an argument at all, so those are refused.
+    pub fn windows_cmd(arg: &str) -> Option<String> {
+        if arg.contains(['\n', '\r']) {
+            return None;
+        }
+
+        let mut argv = String::new();
+        if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '"']) {
+            argv.push_str(arg);
+        } else {
+            argv.push('"');
+            let mut backslashes = 0;
+            for c in arg.chars() {
+                match c {
+                    '\\' => backslashes += 1,
+                    '"' => {
+                        // Backslashes before a quote are doubled, plus one for the quote
+                        argv.push_str(&"\\".repeat(backslashes * 2 + 1));
+                        argv.push('"');
+                        backslashes = 0;
+                    }
+                    _ => {
+                        argv.push_str(&"\\".repeat(backslashes));
+                        argv.push(c);
+                        backslashes = 0;
+                    }
+                }
+            }
+            // ...and so are trailing ones, before the closing quote
+            argv.push_str(&"\\".repeat(backslashes * 2));
+            argv.push('"');
+        }
+
+        let mut escaped = String::with_capacity(argv.len());
+        for c in argv.chars() {
+            if "()%!^\"<>&|".contains(c) {
+                escaped.push('^');
+            }
+            escaped.push(c);
+        }
+        Some(escaped)
+    }
+}
+
+/// Captured result of a finished command
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    pub fn stdout_text(&self) -> String {
+        crate::encoding::decode(&self.stdout).text
+    }
+
+    pub fn stderr_text(&self) -> String {
+        crate::encoding::decode(&self.stderr).text
+    }
+}
+
+/// Runs commands to completion and captures their output
+pub trait CommandRunner: Send + Sync {
+    fn output<'a>(&'a self, spec: &'a CommandSpec) -> BoxFuture<'a, io::Result<CommandOutput>>;
+
+    /// Like `output`, also sending each line to `lines` as it's printed
+    /// Runners that can't stream send the lines once the command is done.
+    fn output_lines<'a>(
+        &'a self,
+        spec: &'a CommandSpec,
+        lines: mpsc::UnboundedSender<OutputLine>,
+    ) -> BoxFuture<'a, io::Result<CommandOutput>> {
+        Box::pin(async move {
+            let output = self.output(spec).await?;
+            for (stream, bytes) in [
+                (OutputStream::Stdout, &output.stdout),
+                (OutputStream::Stderr, &output.stderr),
+            ] {
+                for line in bytes.split_inclusive(|byte| *byte == b'\n') {
+                    let _ = lines.send(OutputLine::decode(stream, line));
+                }
+            }
+            Ok(output)
+        })
+    }
+}
+
+/// Runner backed by real processes on the host
+pub struct SystemRunner;
+
+impl SystemRunner {
+    /// Run `spec` to completion, passing each line to `lines` if given
+    async fn run(
+        spec: &CommandSpec,
+        lines: Option<mpsc::UnboundedSender<OutputLine>>,
+    ) -> io::Result<CommandOutput> {
+        let spec = crate::tools::resolve(spec);
+        flight::debug(format!("🐛 Running {}", spec.command_line()));
+        let mut command = spec.to_command();
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        command.process_group(0);
+
+        // Dropping this future (a cancelled job) takes the whole tree down,
+        // not just the direct child: make leaves docker running otherwise
+        let started = std::time::Instant::now();
+        let mut child = command.spawn()?;
+        let mut tree = ProcessTree(child.id());
+        let stdout = collect(child.stdout.take(), OutputStream::Stdout, lines.clone());
+        let stderr = collect(child.stderr.take(), OutputStream::Stderr, lines);
+        let (status, stdout, stderr) = tokio::try_join!(child.wait(), stdout, stderr)?;
+        tree.0 = None;
+        flight::debug(format!(
+            "🐛 {} exited with {:?} after {:?}",
+            spec.program,
+            status.code(),
+            started.elapsed()
+        ));
+
+        Ok(CommandOutput {
+            code: status.code(),
+            stdout,
+            stderr,
+        })
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn output<'a>(&'a self, spec: &'a CommandSpec) -> BoxFuture<'a, io::Result<CommandOutput>> {
+        Box::pin(Self::run(spec, None))
+    }
+
+    fn output_lines<'a>(
+        &'a self,
+        spec: &'a CommandSpec,
+        lines: mpsc::UnboundedSender<OutputLine>,
+    ) -> BoxFuture<'a, io::Result<CommandOutput>> {
+        Box::pin(Self::run(spec, Some(lines)))
+    }
+}
+
+/// Read `pipe` to EOF, passing each line on to `lines` as it arrives
+async fn collect(
+    pipe: Option<impl AsyncRead + Unpin>,
+    stream: OutputStream,
+    lines: Option<mpsc::UnboundedSender<OutputLine>>,
+) -> io::Result<Vec<u8>> {
+    let mut collected = Vec::new();
+    let Some(pipe) = pipe else {
+        return Ok(collected);
+    };
+    let mut reader = BufReader::new(pipe);
+    loop {
+        let start = collected.len();
+        if reader.read_until(b'\n', &mut collected).await? == 0 {
+            return Ok(collected);
+        }
+        if let Some(lines) = &lines {
+            let _ = lines.send(OutputLine::decode(stream, &collected[start..]));
+        }
+    }
+}
+
+/// Kills a child and everything it started when dropped, unless cleared
+struct ProcessTree(Option<u32>);
+
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        let Some(pid) = self.0 else {
+            return;
+        };
+        // The child leads its own process group, so this reaches grandchildren
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
+        #[cfg(windows)]
+        {
+            let _ = std::process::Command::new("taskkill")
+                .args(["/T", "/F", "/PID", &pid.to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+    }
+}
+
+// Lines buffered per child before the readers wait on the consumer
+const LINE_BUFFER: usize = 1024;
+/// How long the readers may keep going once the child exited; a grandchild
+/// left holding the pipes would otherwise keep `wait` from returning
+const READER_GRACE: Duration = Duration::from_secs(2);
+/// Read errors in a row after which a pipe is given up on
+const MAX_READ_ERRORS: u32 = 16;
+
+/// Which pipe an output line was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutputLine {
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+impl OutputLine {
+    /// A line as read from a pipe, without its line ending
+    fn decode(stream: OutputStream, bytes: &[u8]) -> Self {
+        OutputLine {
+            stream,
+            line: crate::encoding::decode_line(bytes)
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        }
+    }
+}
+
+/// How a piped child finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipedExit {
+    pub code: Option<i32>,
+    /// Lines read after the consumer went away
+    pub dropped_lines: u64,
+}
+
+/// A child whose stdout and stderr are always being read
+/// Each pipe has a reader task forwarding lines to a bounded channel. Once
+/// the receiver is dropped the readers keep draining and count what they
+/// discard, so a chatty child can never block on a full pipe.
+pub struct PipedChild {
+    child: tokio::process::Child,
+    readers: Vec<JoinHandle<()>>,
+    dropped: Arc<AtomicU64>,
+    tree: ProcessTree,
+}
+
+impl PipedChild {
+    /// Wait for the child to exit and both pipes to be drained to EOF, or
+    /// for READER_GRACE after it exited
+    pub async fn wait(mut self) -> io::Result<PipedExit> {
+        let status = self.child.wait().await?;
+        self.tree.0 = None;
+        let readers = std::mem::take(&mut self.readers);
+        let aborts: Vec<_> = readers.iter().map(JoinHandle::abort_handle).collect();
+        let drained = async {
+            for reader in readers {
+                let _ = reader.await;
+            }
+        };
+        if tokio::time::timeout(READER_GRACE, drained).await.is_err() {
+            warn!("⚠️  A child exited but something it started still holds its output open");
+            aborts.iter().for_each(AbortHandle::abort);
+        }
+
+        Ok(PipedExit {
+            code: status.code(),
+            dropped_lines: self.dropped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Spawn `spec` with piped output, returning the child and its output lines
+/// The child and everything it started are killed if the handle is dropped
+/// before it exits, like a cancelled `SystemRunner` command.
+pub fn spawn_piped(spec: &CommandSpec) -> io::Result<(PipedChild, mpsc::Receiver<OutputLine>)> {
+    let spec = crate::tools::resolve(spec);
+    let mut command = spec.to_command();
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command.spawn()?;
+    let tree = ProcessTree(child.id());
+
+    let (tx, rx) = mpsc::channel(LINE_BUFFER);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(tokio::spawn(drain(
+            stdout,
+            OutputStream::Stdout,
+            tx.clone(),
+            dropped.clone(),
+        )));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(tokio::spawn(drain(
+            stderr,
+            OutputStream::Stderr,
+            tx,
+            dropped.clone(),
+        )));
+    }
+
+    Ok((
+        PipedChild {
+            child,
+            readers,
+            dropped,
+            tree,
+        },
+        rx,
+    ))
+}
+
+async fn drain(
+    pipe: impl AsyncRead + Unpin,
+    stream: OutputStream,
+    tx: mpsc::Sender<OutputLine>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut reader = BufReader::new(pipe);
+    let mut buf = Vec::new();
+    let mut consumer = Some(tx);
+    let mut errors = 0;
+
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => return,
+            Ok(_) => errors = 0,
+            // Stopping would leave the child blocked on a full pipe
+            Err(e) if errors < MAX_READ_ERRORS => {
+                errors += 1;
+                dropped.fetch_add(1, Ordering::Relaxed);
+                flight::debug(format!("🐛 Skipped a failed read of {:?}: {}", stream, e));
+                continue;
+            }
+            Err(e) => {
+                warn!("⚠️  Gave up reading {:?} after {} errors: {}", stream, errors, e);
+                return;
+            }
+        }
+
+        let Some(tx) = &consumer else {
+            dropped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        };
+        if tx.send(OutputLine::decode(stream, &buf)).await.is_err() {
+            dropped.fetch_add(1, Ordering::Relaxed);
+            consumer = None;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::sync::Mutex;
+
+    type Handler = dyn Fn(&CommandSpec) -> io::Result<CommandOutput> + Send + Sync;
+
+    /// Runner that answers every command from a closure and records the calls
+    pub struct MockRunner {
+        handler: Box<Handler>,
+        calls: Mutex<Vec<CommandSpec>>,
+    }
+
+    impl MockRunner {
+        pub fn new(
+            handler: impl Fn(&CommandSpec) -> io::Result<CommandOutput> + Send + Sync + 'static,
+        ) -> Self {
+            Self {
+                handler: Box::new(handler),
+                calls: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// Runner whose commands all succeed with the given stdout
+        pub fn with_stdout(stdout: &str) -> Self {
+            let stdout = stdout.as_bytes().to_vec();
+            Self::new(move |_| {
+                Ok(CommandOutput {
+                    code: Some(0),
+                    stdout: stdout.clone(),
+                    stderr: Vec::new(),
+                })
+            })
+        }
+
+        pub fn calls(&self) -> Vec<CommandSpec> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn output<'a>(&'a self, spec: &'a CommandSpec) -> BoxFuture<'a, io::Result<CommandOutput>> {
+            self.calls.lock().unwrap().push(spec.clone());
+            let result = (self.handler)(spec);
+            Box::pin(async move { result })
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::os::unix::fs::PermissionsExt;
+    use std::task::{Context, Poll};
+    use tempfile::TempDir;
+    use tokio::io::ReadBuf;
+
+    /// A stand-in for a very chatty migration: ~32MB on stdout, ~8MB on stderr
+    fn chatty_binary(dir: &TempDir) -> String {
+        let path = dir.path().join("chatty.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/sh\n\
+             yes 'applying migration 0042_add_index_to_documents ... ok' | head -c 33554432\n\
+             yes 'warning: deprecated setting' | head -c 8388608 >&2\n\
+             exit 3\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.display().to_string()
+    }
+
+    // Strings that would run something if pasted into a shell unquoted
+    const INJECTIONS: [&str; 8] = [
+        "up; rm -rf ~",
+        "`id`",
+        "$(id)",
+        "up\nid",
+        "it's",
+        "%PATH%",
+        "a \"b\" \\",
+        "",
+    ];
+
+    #[test]
+    fn test_posix_quoting_round_trips_through_sh() {
+        for payload in INJECTIONS {
+            let script = format!("printf '%s' {}", quote::posix(payload));
+            let output = std::process::Command::new("sh")
+                .args(["-c", &script])
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), payload, "{:?}", script);
+        }
+    }
+
+    #[test]
+    fn test_windows_cmd_quoting() {
+        assert_eq!(quote::windows_cmd("up").unwrap(), "up");
+        assert_eq!(quote::windows_cmd("%PATH%").unwrap(), "^%PATH^%");
+        assert_eq!(quote::windows_cmd("a & b").unwrap(), "^\"a ^& b^\"");
+        assert_eq!(quote::windows_cmd(r#"say "hi"\"#).unwrap(), r#"^"say \^"hi\^"\\^""#);
+        assert_eq!(quote::windows_cmd("").unwrap(), "^\"^\"");
+        assert_eq!(quote::windows_cmd("up\nid"), None);
+    }
+
+    #[test]
+    fn test_command_line_quotes_arguments() {
+        let spec = CommandSpec::new("make").args(["up", "$(id)"]);
+        assert_eq!(spec.command_line(), "make up '$(id)'");
+    }
+
+    /// Nothing may invoke a shell; commands are argv arrays only
+    /// The quoting helpers above are the one allowed place for shell syntax.
+    #[test]
+    fn test_no_shell_invocations_in_crate() {
+        let shells = ["sh", "bash", "zsh", "cmd", "cmd.exe", "powershell", "pwsh"];
+        let patterns: Vec<String> = shells
+            .iter()
+            .flat_map(|shell| {
+                [
+                    format!("Command::new(\"{}\")", shell),
+                    format!("CommandSpec::new(\"{}\")", shell),
+                ]
+            })
+            .chain(["\"/C\"".to_string(), "\"/c\"".to_string()])
+            .collect();
+
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut offenders = Vec::new();
+        for entry in std::fs::read_dir(&src).unwrap().flatten() {
+            let path = entry.path();
+            let contents = std::fs::read_to_string(&path).unwrap();
+            // The tests of this module exercise a real shell on purpose
+            let contents = match path.file_name().and_then(|n| n.to_str()) {
+                Some("process.rs") => contents.split("#[cfg(all(test, unix))]").next().unwrap(),
+                _ => contents.as_str(),
+            };
+            for (number, line) in contents.lines().enumerate() {
+                if patterns.iter().any(|pattern| line.contains(pattern.as_str())) {
+                    offenders.push(format!("{}:{}: {}", path.display(), number + 1, line.trim()));
+                }
+            }
+        }
+        assert!(offenders.is_empty(), "Shell invocations found:\n{}", offenders.join("\n"));
+    }
+
+    /// A script that starts a long sleep and waits on it, and the file it
+    /// writes the sleep's pid to
+    fn spawner(dir: &TempDir) -> (CommandSpec, PathBuf) {
+        let pid_file = dir.path().join("grandchild.pid");
+        let script = dir.path().join("spawner.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\nsleep 30 &\necho $! > {}\nwait\n",
+                pid_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        (CommandSpec::new(script.display().to_string()), pid_file)
+    }
+
+    async fn assert_killed(pid_file: &std::path::Path) {
+        let mut grandchild = None;
+        for _ in 0..50 {
+            grandchild = std::fs::read_to_string(pid_file)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<i32>().ok());
+            if grandchild.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let grandchild = grandchild.expect("the spawner never wrote its pid");
+        // Killed children linger as zombies until their new parent reaps them,
+        // which not every init (e.g. in containers) does
+        let running = |pid: i32| {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+            (unsafe { libc::kill(pid, 0) } == 0) && !stat.contains(") Z ")
+        };
+        let mut alive = true;
+        for _ in 0..50 {
+            alive = running(grandchild);
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!alive, "grandchild {} still running", grandchild);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_command_kills_process_tree() {
+        let tmp = temp_dir();
+        let (spec, pid_file) = spawner(&tmp);
+        let run = SystemRunner.output(&spec);
+        assert!(tokio::time::timeout(Duration::from_millis(500), run)
+            .await
+            .is_err());
+        assert_killed(&pid_file).await;
+    }
+
+    #[tokio::test]
+    async fn test_dropped_piped_child_kills_process_tree() {
+        let tmp = temp_dir();
+        let (spec, pid_file) = spawner(&tmp);
+        let (child, _lines) = spawn_piped(&spec).unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(500), child.wait())
+            .await
+            .is_err());
+        assert_killed(&pid_file).await;
+    }
+
+    /// A slow `make down` or pull must leave the runtime free for other IPC
+    /// calls; on this single-threaded runtime a blocking wait would stall both
+    #[tokio::test]
+    async fn test_slow_command_does_not_block_other_invocations() {
+        let slow = tokio::spawn(async {
+            SystemRunner
+                .output(&CommandSpec::new("sleep").args(["2"]))
+                .await
+        });
+        tokio::task::yield_now().await;
+
+        let version = tokio::time::timeout(Duration::from_millis(500), crate::get_app_version())
+            .await
+            .expect("get_app_version waited on the slow command");
+        assert_eq!(version.unwrap(), env!("CARGO_PKG_VERSION"));
+        assert!(!slow.is_finished());
+        assert!(slow.await.unwrap().unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn test_output_lines_arrive_before_the_command_finishes() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let script = dir.join("migrate.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\necho 'applying 0042'\nsleep 1\necho 'done' >&2\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let spec = CommandSpec::new(script.display().to_string());
+        let (tx, mut lines) = mpsc::unbounded_channel();
+
+        let run = tokio::spawn(async move { SystemRunner.output_lines(&spec, tx).await });
+        let first = tokio::time::timeout(Duration::from_millis(500), lines.recv())
+            .await
+            .expect("no line while the command ran")
+            .unwrap();
+        assert_eq!(first.line, "applying 0042");
+        assert!(!run.is_finished());
+
+        let output = run.await.unwrap().unwrap();
+        assert_eq!(output.stdout, b"applying 0042\n");
+        assert_eq!(output.stderr_text(), "done\n");
+        assert_eq!(lines.recv().await.unwrap().stream, OutputStream::Stderr);
+    }
+
+    #[tokio::test]
+    async fn test_chatty_child_without_consumer_does_not_hang() {
+        let tmp = temp_dir();
+        let (child, lines) = spawn_piped(&CommandSpec::new(chatty_binary(&tmp))).unwrap();
+        drop(lines);
+
+        let exit = tokio::time::timeout(Duration::from_secs(60), child.wait())
+            .await
+            .expect("child blocked on a full pipe")
+            .unwrap();
+
+        assert_eq!(exit.code, Some(3));
+        assert!(exit.dropped_lines > 500_000, "Discarded lines are counted");
+    }
+
+    #[tokio::test]
+    async fn test_consumer_receives_every_line() {
+        let tmp = temp_dir();
+        let (child, mut lines) = spawn_piped(&CommandSpec::new(chatty_binary(&tmp))).unwrap();
+
+        let counter = tokio::spawn(async move {
+            let (mut stdout, mut stderr) = (0u64, 0u64);
+            while let Some(line) = lines.recv().await {
+                match line.stream {
+                    OutputStream::Stdout => stdout += 1,
+                    OutputStream::Stderr => stderr += 1,
+                }
+            }
+            (stdout, stderr)
+        });
+
+        let exit = tokio::time::timeout(Duration::from_secs(60), child.wait())
+            .await
+            .expect("child blocked on a full pipe")
+            .unwrap();
+        let (stdout, stderr) = counter.await.unwrap();
+
+        assert_eq!(exit.dropped_lines, 0);
+        // 54 bytes per stdout line, 28 per stderr line (the last partial)
+        assert_eq!(stdout, 33554432_u64.div_ceil(54));
+        assert_eq!(stderr, 8388608_u64.div_ceil(28));
+    }
+
+    #[tokio::test]
+    async fn test_wait_returns_when_a_grandchild_holds_the_pipes() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let pid_file = dir.join("grandchild.pid");
+        let script = dir.join("daemonize.sh");
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\nsleep 30 &\necho $! > {}\n", pid_file.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (child, _lines) = spawn_piped(&CommandSpec::new(script.display().to_string())).unwrap();
+        let exit = tokio::time::timeout(READER_GRACE * 3, child.wait())
+            .await
+            .expect("wait hung on the grandchild's pipe")
+            .unwrap();
+        assert_eq!(exit.code, Some(0));
+
+        let grandchild: i32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+        unsafe { libc::kill(grandchild, libc::SIGKILL) };
+    }
+
+    /// A pipe whose first reads fail, then yields one line
+    struct Flaky {
+        failures: u32,
+        line: Option<&'static [u8]>,
+    }
+
+    impl AsyncRead for Flaky {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Poll::Ready(Err(io::Error::other("transient")));
+            }
+            if let Some(line) = self.line.take() {
+                buf.put_slice(line);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain_reads_past_errors_until_eof() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (tx, mut rx) = mpsc::channel(LINE_BUFFER);
+        let flaky = Flaky {
+            failures: 3,
+            line: Some(b"still here\n"),
+        };
+        drain(flaky, OutputStream::Stdout, tx, dropped.clone()).await;
+        assert_eq!(rx.recv().await.unwrap().line, "still here");
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+
+        // A pipe that never recovers is given up on
+        let (tx, _rx) = mpsc::channel(LINE_BUFFER);
+        let broken = Flaky {
+            failures: u32::MAX,
+            line: None,
+        };
+        drain(broken, OutputStream::Stderr, tx, dropped.clone()).await;
+        assert_eq!(dropped.load(Ordering::Relaxed), 3 + MAX_READ_ERRORS as u64);
+    }
+}
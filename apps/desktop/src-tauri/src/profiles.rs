@@ -0,0 +1,409 @@
+// Data profiles
+// Each profile runs the stack as its own compose project, so its containers,
+// networks and volumes (named `<project>_…`) never mix with another
+// profile's and two profiles never share one database. The `default`
+// profile is the `arbor` project Arbor has always used. A profile's env file
+// in the app data dir is passed to compose, and the active profile only
+// changes while services are stopped. Unrelated to the settings profiles in
+// `arbor.toml`.
+
+use crate::confirm::Confirmations;
+use crate::docker::COMPOSE_PROJECT;
+use crate::keyring::Keychain;
+use crate::paths::AppDirs;
+use crate::process::SystemRunner;
+use crate::protocol::Handshake;
+use crate::services::StackState;
+use crate::uninstall::{self, UninstallEnv, UninstallManifest};
+use crate::ServiceManager;
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use tauri::{AppHandle, Manager, State};
+
+pub const PROFILES_FILE_NAME: &str = "profiles.json";
+pub const DEFAULT_PROFILE: &str = "default";
+/// Per-profile directories in the app data dir
+pub const PROFILES_DIR_NAME: &str = "profiles";
+const ENV_FILE_NAME: &str = "compose.env";
+const MAX_NAME_LEN: usize = 32;
+
+static ACTIVE: RwLock<Option<String>> = RwLock::new(None);
+
+/// The profile services run as, `default` until one is activated
+pub fn active_name() -> String {
+    ACTIVE
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+/// Compose project of the active profile
+pub fn compose_project() -> String {
+    compose_project_for(&active_name())
+}
+
+pub fn compose_project_for(name: &str) -> String {
+    match name {
+        DEFAULT_PROFILE => COMPOSE_PROJECT.to_string(),
+        name => format!("{}-{}", COMPOSE_PROJECT, name),
+    }
+}
+
+pub fn profile_dir(data_dir: &Path, name: &str) -> PathBuf {
+    data_dir.join(PROFILES_DIR_NAME).join(name)
+}
+
+/// Passed to compose with `--env-file` when it exists
+pub fn env_file(data_dir: &Path, name: &str) -> PathBuf {
+    profile_dir(data_dir, name).join(ENV_FILE_NAME)
+}
+
+/// Lowercase letters, digits and dashes: it ends up in compose project,
+/// container and volume names
+fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    match valid {
+        true => Ok(()),
+        false => Err(format!(
+            "Invalid profile name {:?}: use up to {} lowercase letters, digits and dashes",
+            name, MAX_NAME_LEN
+        )),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DataProfile {
+    pub name: String,
+    pub active: bool,
+    pub compose_project: String,
+    /// Compose prefixes the profile's volumes with it
+    pub volume_prefix: String,
+    pub env_file: Option<PathBuf>,
+    /// Unset for the default profile
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ProfileRecord {
+    name: String,
+    created_at: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ProfilesFile {
+    #[serde(default)]
+    active: Option<String>,
+    /// Every profile but the default one
+    #[serde(default)]
+    profiles: Vec<ProfileRecord>,
+}
+
+/// The profiles and which one is active, kept in the app config dir
+pub struct ProfileStore {
+    path: PathBuf,
+    data_dir: PathBuf,
+    state: Mutex<ProfilesFile>,
+}
+
+impl ProfileStore {
+    pub fn load(path: PathBuf, data_dir: PathBuf) -> Self {
+        let state = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("⚠️  Ignoring invalid profiles file {:?}: {}", path, e);
+                ProfilesFile::default()
+            }),
+            Err(_) => ProfilesFile::default(),
+        };
+        Self {
+            path,
+            data_dir,
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn active(&self) -> String {
+        let state = self.state.lock().unwrap();
+        state
+            .active
+            .clone()
+            .filter(|name| state.profiles.iter().any(|record| record.name == *name))
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    pub fn list(&self) -> Vec<DataProfile> {
+        let active = self.active();
+        let state = self.state.lock().unwrap();
+        [(DEFAULT_PROFILE, None)]
+            .into_iter()
+            .chain(
+                state
+                    .profiles
+                    .iter()
+                    .map(|record| (record.name.as_str(), Some(record.created_at.clone()))),
+            )
+            .map(|(name, created_at)| self.describe(name, name == active, created_at))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<DataProfile> {
+        self.list().into_iter().find(|profile| profile.name == name)
+    }
+
+    /// Add a profile and write its env file
+    pub fn create(&self, name: &str) -> Result<DataProfile, String> {
+        validate_name(name)?;
+        let mut state = self.state.lock().unwrap();
+        if name == DEFAULT_PROFILE || state.profiles.iter().any(|record| record.name == name) {
+            return Err(format!("Profile {} already exists", name));
+        }
+
+        let env_file = env_file(&self.data_dir, name);
+        let contents = format!(
+            "# Written by Arbor for the {} profile\nCOMPOSE_PROJECT_NAME={}\nARBOR_PROFILE={}\n",
+            name,
+            compose_project_for(name),
+            name
+        );
+        std::fs::create_dir_all(profile_dir(&self.data_dir, name))
+            .and_then(|_| std::fs::write(&env_file, contents))
+            .map_err(|e| format!("Failed to write the {} profile's env file: {}", name, e))?;
+
+        let record = ProfileRecord {
+            name: name.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let mut updated = state.clone();
+        updated.profiles.push(record.clone());
+        self.save(&updated)?;
+        *state = updated;
+        Ok(self.describe(name, false, Some(record.created_at)))
+    }
+
+    /// Make `name` the profile services run as; the caller makes sure
+    /// they're stopped
+    pub fn set_active(&self, name: &str) -> Result<DataProfile, String> {
+        let profile = self
+            .get(name)
+            .ok_or_else(|| format!("Profile {} does not exist", name))?;
+        let mut state = self.state.lock().unwrap();
+        let mut updated = state.clone();
+        updated.active = (name != DEFAULT_PROFILE).then(|| name.to_string());
+        self.save(&updated)?;
+        *state = updated;
+        Ok(DataProfile {
+            active: true,
+            ..profile
+        })
+    }
+
+    /// Forget `name`; its containers, volumes and directory are removed by
+    /// `delete_profile`
+    pub fn remove(&self, name: &str) -> Result<(), String> {
+        self.ensure_removable(name)?;
+        let mut state = self.state.lock().unwrap();
+        let mut updated = state.clone();
+        updated.profiles.retain(|record| record.name != name);
+        self.save(&updated)?;
+        *state = updated;
+        Ok(())
+    }
+
+    /// Compose projects of every profile
+    pub fn projects(&self) -> Vec<String> {
+        self.list()
+            .into_iter()
+            .map(|profile| profile.compose_project)
+            .collect()
+    }
+
+    fn ensure_removable(&self, name: &str) -> Result<(), String> {
+        match self.get(name) {
+            _ if name == DEFAULT_PROFILE => Err("The default profile can't be deleted".to_string()),
+            None => Err(format!("Profile {} does not exist", name)),
+            Some(profile) if profile.active => Err(format!(
+                "Profile {} is active; switch to another profile first",
+                name
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn describe(&self, name: &str, active: bool, created_at: Option<String>) -> DataProfile {
+        let compose_project = compose_project_for(name);
+        let env_file = env_file(&self.data_dir, name);
+        DataProfile {
+            name: name.to_string(),
+            active,
+            volume_prefix: format!("{}_", compose_project),
+            compose_project,
+            env_file: env_file.is_file().then_some(env_file),
+            created_at,
+        }
+    }
+
+    /// Written to a temporary file and renamed over, so a switch is never
+    /// left half-saved
+    fn save(&self, state: &ProfilesFile) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create profiles directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+        let temporary = self.path.with_extension("json.tmp");
+        std::fs::write(&temporary, contents)
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| format!("Failed to save profiles: {}", e))
+    }
+}
+
+/// Make the store's active profile the one services run as
+pub fn activate(store: &ProfileStore) {
+    *ACTIVE.write().unwrap() = Some(store.active());
+}
+
+#[tauri::command]
+pub async fn list_profiles(store: State<'_, ProfileStore>) -> Result<Vec<DataProfile>, String> {
+    Ok(store.list())
+}
+
+#[tauri::command]
+pub async fn create_profile(
+    handshake: State<'_, Handshake>,
+    store: State<'_, ProfileStore>,
+    name: String,
+) -> Result<DataProfile, String> {
+    handshake.ensure()?;
+    let profile = store.create(&name)?;
+    info!("🗂️  Created profile {}", name);
+    Ok(profile)
+}
+
+/// Switch the compose project, env file and volumes services run with;
+/// only while they're stopped
+#[tauri::command]
+pub async fn set_active_profile(
+    app_handle: AppHandle,
+    store: State<'_, ProfileStore>,
+    name: String,
+) -> Result<DataProfile, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    let service_manager = app_handle.state::<ServiceManager>();
+    let stack = service_manager.stack.lock();
+    if !matches!(*stack, StackState::Stopped) {
+        return Err(format!(
+            "Stop services before switching to profile {}",
+            name
+        ));
+    }
+    // Held until the switch is done, so services can't start halfway through
+    let profile = store.set_active(&name)?;
+    activate(&store);
+    drop(stack);
+    info!("🗂️  Switched to profile {}", name);
+    Ok(profile)
+}
+
+/// Delete a profile; run with `dry_run` first for the confirmation token.
+/// Its containers, networks and volumes are only removed with `delete_data`.
+#[tauri::command]
+pub async fn delete_profile(
+    app_handle: AppHandle,
+    store: State<'_, ProfileStore>,
+    confirmations: State<'_, Confirmations>,
+    name: String,
+    delete_data: bool,
+    dry_run: bool,
+    confirmation_token: Option<String>,
+) -> Result<UninstallManifest, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    store.ensure_removable(&name)?;
+    let action = match delete_data {
+        true => format!("delete_profile:{}:with-data", name),
+        false => format!("delete_profile:{}", name),
+    };
+    let dirs = AppDirs::resolve(&app_handle)?;
+    let env = UninstallEnv {
+        runner: &SystemRunner,
+        secrets: app_handle.state::<Keychain>().store(),
+        projects: vec![compose_project_for(&name)],
+        dirs: dirs.clone(),
+    };
+    let plan = uninstall::plan_profile(&env, &profile_dir(&dirs.data, &name), delete_data).await;
+
+    if dry_run {
+        return Ok(UninstallManifest {
+            confirmation_token: Some(confirmations.issue(&action)),
+            ..plan
+        });
+    }
+    let token =
+        confirmation_token.ok_or("delete_profile needs the confirmation token from a dry run")?;
+    confirmations.redeem(&action, &token)?;
+
+    let manifest = uninstall::remove_listed(&env, plan).await;
+    store.remove(&name)?;
+    info!(
+        "🗂️  Deleted profile {}: removed {} items, {} failed",
+        name,
+        manifest.removed.len(),
+        manifest.failed.len()
+    );
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_profiles_get_their_own_project_and_env_file() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let path = dir.join("config").join(PROFILES_FILE_NAME);
+        let data_dir = dir.join("data");
+        let store = ProfileStore::load(path.clone(), data_dir.clone());
+        assert_eq!(store.active(), DEFAULT_PROFILE);
+        assert_eq!(store.projects(), [COMPOSE_PROJECT]);
+
+        let work = store.create("work").unwrap();
+        assert_eq!(work.compose_project, "arbor-work");
+        assert_eq!(work.volume_prefix, "arbor-work_");
+        let env = std::fs::read_to_string(work.env_file.unwrap()).unwrap();
+        assert!(env.contains("COMPOSE_PROJECT_NAME=arbor-work"));
+        assert!(store.create("work").is_err());
+        assert!(store.create("Work").is_err());
+        assert!(store.create("a/b").is_err());
+        assert!(store.create(DEFAULT_PROFILE).is_err());
+
+        assert!(store.set_active("missing").is_err());
+        assert!(store.set_active("work").unwrap().active);
+        let reloaded = ProfileStore::load(path, data_dir);
+        assert_eq!(reloaded.active(), "work");
+        assert_eq!(reloaded.projects(), ["arbor", "arbor-work"]);
+    }
+
+    #[test]
+    fn test_default_and_active_profiles_cannot_be_removed() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = ProfileStore::load(dir.join(PROFILES_FILE_NAME), dir.join("data"));
+        store.create("scratch").unwrap();
+        store.set_active("scratch").unwrap();
+
+        assert!(store.remove(DEFAULT_PROFILE).is_err());
+        assert!(store.remove("scratch").is_err());
+        store.set_active(DEFAULT_PROFILE).unwrap();
+        store.remove("scratch").unwrap();
+        assert!(store.get("scratch").is_none());
+        assert!(store.remove("scratch").is_err());
+    }
+}
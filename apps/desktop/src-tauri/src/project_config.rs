@@ -0,0 +1,596 @@
+// Project configuration
+// `arbor.toml` holds profiles that override app settings for a particular
+// environment, e.g. a short startup timeout for CI, and services hold
+// per-service behavior:
+//
+//     [profiles.ci]
+//     startup_timeout_secs = 20
+//
+//     [services.postgres]
+//     restart_policy = "never"
+//
+// The config is merged from layers, later ones winning key by key: the
+// defaults embedded at build time (arbor.default.toml), the project's
+// `arbor.toml`, the user's `arbor.toml` in the app config dir, the
+// ARBOR_CONFIG env var, then `--config` flags. A key that two layers set to
+// different types is a validation error rather than one of them winning.
+
+use crate::crashes::RestartPolicy;
+use crate::services::PROJECT_CONFIG_FILE;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, State};
+use toml::{Table, Value};
+
+pub const DEFAULT_CONFIG: &str = include_str!("../arbor.default.toml");
+/// `--config profiles.ci.startup_timeout_secs=20`, repeatable
+pub const CLI_FLAG: &str = "--config";
+/// `key=value` assignments like the flag's, separated by `;`
+pub const ENV_VAR: &str = "ARBOR_CONFIG";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub profiles: BTreeMap<String, Profile>,
+    /// By compose service name
+    pub services: BTreeMap<String, ServiceConfig>,
+}
+
+/// Settings a profile may override; unset fields keep the app setting
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub startup_timeout_secs: Option<u64>,
+    pub per_service_ready_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// What the crash monitor does when the service dies; see RestartPolicy
+    pub restart_policy: Option<RestartPolicy>,
+    /// Started in safe mode
+    pub essential: bool,
+}
+
+/// Which layer a config value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    Embedded,
+    Project,
+    User,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Embedded => write!(f, "embedded default config"),
+            ConfigSource::Project => write!(f, "{}", PROJECT_CONFIG_FILE),
+            ConfigSource::User => write!(f, "user {}", PROJECT_CONFIG_FILE),
+            ConfigSource::Env => write!(f, "{}", ENV_VAR),
+            ConfigSource::Cli => write!(f, "{}", CLI_FLAG),
+        }
+    }
+}
+
+/// The layers above the project's, captured once so they can be tested
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    pub user_file: Option<PathBuf>,
+    pub env: Vec<String>,
+    pub cli: Vec<String>,
+}
+
+impl ConfigSources {
+    /// Sources of the running process, with the user file in the app config dir
+    pub fn from_process(config_dir: &Path) -> Self {
+        Self {
+            user_file: Some(config_dir.join(PROJECT_CONFIG_FILE)),
+            env: std::env::var(ENV_VAR)
+                .map(|value| {
+                    value
+                        .split(';')
+                        .map(str::trim)
+                        .filter(|assignment| !assignment.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            cli: cli_flags(std::env::args()),
+        }
+    }
+
+    /// Set one value in the user's arbor.toml, keeping its other values
+    /// The file is rewritten, so comments in it are lost.
+    pub fn set_user_value(&self, key: &[&str], value: Value) -> Result<(), String> {
+        let path = self
+            .user_file
+            .as_ref()
+            .ok_or_else(|| "No user config file to write to".to_string())?;
+        let (last, parents) = key.split_last().expect("config key is not empty");
+        let mut table = read_layer(path, ConfigSource::User)?.unwrap_or_default();
+
+        let mut current = &mut table;
+        for part in parents {
+            current = match current
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(table) => table,
+                other => {
+                    return Err(format!(
+                        "Failed to set {}: {} is a {} in {}",
+                        key.join("."),
+                        part,
+                        other.type_str(),
+                        ConfigSource::User
+                    ))
+                }
+            };
+        }
+        current.insert(last.to_string(), value);
+        ProjectConfig::from_table(table.clone())
+            .map_err(|e| format!("Invalid {}: {}", ConfigSource::User, e))?;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        }
+        let contents = toml::to_string(&table)
+            .map_err(|e| format!("Failed to serialize {}: {}", ConfigSource::User, e))?;
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Failed to write {}: {}", ConfigSource::User, e))
+    }
+}
+
+/// Values of every `--config <key=value>` or `--config=<key=value>`
+fn cli_flags(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == CLI_FLAG {
+            values.extend(args.next());
+        } else if let Some(value) = arg.strip_prefix(CLI_FLAG).and_then(|v| v.strip_prefix('=')) {
+            values.push(value.to_string());
+        }
+    }
+    values
+}
+
+/// A config value and the layer it came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigValue {
+    /// Dotted path, e.g. `profiles.ci.startup_timeout_secs`
+    pub key: String,
+    pub value: Value,
+    pub source: ConfigSource,
+}
+
+/// A layer that contributed to the effective config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigLayer {
+    pub source: ConfigSource,
+    pub path: Option<PathBuf>,
+}
+
+/// The merged config with the source of each value, for support
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectiveConfig {
+    pub layers: Vec<ConfigLayer>,
+    pub values: Vec<ConfigValue>,
+}
+
+struct Merged {
+    table: Table,
+    // Layer that last set each dotted path, tables included
+    sources: BTreeMap<String, ConfigSource>,
+    layers: Vec<ConfigLayer>,
+}
+
+impl ProjectConfig {
+    /// Merge every layer for the project root
+    /// Missing files are skipped, so a checkout without `arbor.toml` gets the
+    /// embedded defaults.
+    pub fn load(project_root: &Path, sources: &ConfigSources) -> Result<Self, String> {
+        let merged = merge_layers(project_root, sources)?;
+        Self::from_table(merged.table).map_err(|e| format!("Invalid config: {}", e))
+    }
+
+    pub fn effective(
+        project_root: &Path,
+        sources: &ConfigSources,
+    ) -> Result<EffectiveConfig, String> {
+        let merged = merge_layers(project_root, sources)?;
+        let mut values = Vec::new();
+        collect_values(&merged.table, "", &merged.sources, &mut values);
+        Ok(EffectiveConfig {
+            layers: merged.layers,
+            values,
+        })
+    }
+
+    fn from_table(table: Table) -> Result<Self, toml::de::Error> {
+        Value::Table(table).try_into()
+    }
+
+    /// Services safe mode starts
+    pub fn essential_services(&self) -> Vec<String> {
+        self.services
+            .iter()
+            .filter(|(_, service)| service.essential)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profiles.get(name).ok_or_else(|| {
+            format!(
+                "Profile {:?} is not defined in {}",
+                name, PROJECT_CONFIG_FILE
+            )
+        })
+    }
+}
+
+/// Parse one layer's TOML; its keys and types are checked once merged
+fn parse_layer(contents: &str, source: ConfigSource) -> Result<Table, String> {
+    toml::from_str(contents).map_err(|e| format!("Invalid {}: {}", source, e))
+}
+
+fn read_layer(path: &Path, source: ConfigSource) -> Result<Option<Table>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => parse_layer(&contents, source).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", source, e)),
+    }
+}
+
+/// `key=value` assignments as one layer; a later assignment wins
+fn assignments_layer(
+    assignments: &[String],
+    source: ConfigSource,
+) -> Result<Option<Table>, String> {
+    if assignments.is_empty() {
+        return Ok(None);
+    }
+    // TOML rejects a key assigned twice, so fold the assignments one at a time
+    let mut table = Table::new();
+    for assignment in assignments {
+        let (key, value) = assignment.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid {} value {:?}: expected key=value",
+                source, assignment
+            )
+        })?;
+        let assignment = parse_layer(&format!("{} = {}", key.trim(), value.trim()), source)?;
+        let mut sources = BTreeMap::new();
+        let mut conflicts = Vec::new();
+        merge_table(
+            &mut table,
+            assignment,
+            source,
+            "",
+            &mut sources,
+            &mut conflicts,
+        );
+        if let Some(conflict) = conflicts.into_iter().next() {
+            return Err(format!("Invalid {} value: {}", source, conflict));
+        }
+    }
+    Ok(Some(table))
+}
+
+fn merge_layers(project_root: &Path, sources: &ConfigSources) -> Result<Merged, String> {
+    let embedded = parse_layer(DEFAULT_CONFIG, ConfigSource::Embedded)?;
+    let project_file = project_root.join(PROJECT_CONFIG_FILE);
+    let mut layers = vec![
+        (ConfigSource::Embedded, None, Some(embedded)),
+        (
+            ConfigSource::Project,
+            Some(project_file.clone()),
+            read_layer(&project_file, ConfigSource::Project)?,
+        ),
+    ];
+    if let Some(user_file) = &sources.user_file {
+        layers.push((
+            ConfigSource::User,
+            Some(user_file.clone()),
+            read_layer(user_file, ConfigSource::User)?,
+        ));
+    }
+    layers.push((
+        ConfigSource::Env,
+        None,
+        assignments_layer(&sources.env, ConfigSource::Env)?,
+    ));
+    layers.push((
+        ConfigSource::Cli,
+        None,
+        assignments_layer(&sources.cli, ConfigSource::Cli)?,
+    ));
+
+    let mut merged = Merged {
+        table: Table::new(),
+        sources: BTreeMap::new(),
+        layers: Vec::new(),
+    };
+    let mut conflicts = Vec::new();
+    for (source, path, table) in layers {
+        let Some(table) = table else {
+            continue;
+        };
+        merge_table(
+            &mut merged.table,
+            table,
+            source,
+            "",
+            &mut merged.sources,
+            &mut conflicts,
+        );
+        merged.layers.push(ConfigLayer { source, path });
+    }
+    if !conflicts.is_empty() {
+        return Err(format!(
+            "Conflicting config values: {}",
+            conflicts.join("; ")
+        ));
+    }
+    Ok(merged)
+}
+
+/// Merge `layer` into `into`, tables recursively and other values replaced
+fn merge_table(
+    into: &mut Table,
+    layer: Table,
+    source: ConfigSource,
+    prefix: &str,
+    sources: &mut BTreeMap<String, ConfigSource>,
+    conflicts: &mut Vec<String>,
+) {
+    for (key, incoming) in layer {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (into.get_mut(&key), incoming) {
+            (Some(Value::Table(existing)), Value::Table(incoming)) => {
+                merge_table(existing, incoming, source, &path, sources, conflicts);
+            }
+            (Some(existing), incoming) if !existing.same_type(&incoming) => {
+                let earlier = sources
+                    .get(&path)
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| "an earlier value".to_string());
+                conflicts.push(format!(
+                    "{}: {} in {}, {} in {}",
+                    path,
+                    existing.type_str(),
+                    earlier,
+                    incoming.type_str(),
+                    source
+                ));
+            }
+            (_, Value::Table(incoming)) => {
+                sources.insert(path.clone(), source);
+                let Value::Table(existing) = into.entry(key).or_insert(Value::Table(Table::new()))
+                else {
+                    unreachable!("only a missing key reaches here with a table");
+                };
+                merge_table(existing, incoming, source, &path, sources, conflicts);
+            }
+            (_, incoming) => {
+                sources.insert(path, source);
+                into.insert(key, incoming);
+            }
+        }
+    }
+}
+
+fn collect_values(
+    table: &Table,
+    prefix: &str,
+    sources: &BTreeMap<String, ConfigSource>,
+    values: &mut Vec<ConfigValue>,
+) {
+    for (key, value) in table {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            Value::Table(table) => collect_values(table, &path, sources, values),
+            value => values.push(ConfigValue {
+                source: sources[&path],
+                key: path,
+                value: value.clone(),
+            }),
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_effective_config(
+    app_handle: AppHandle,
+    sources: State<'_, ConfigSources>,
+) -> Result<EffectiveConfig, String> {
+    let project_root = crate::find_project_root(&app_handle)?;
+    ProjectConfig::effective(&project_root, &sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn parse(contents: &str) -> Result<ProjectConfig, String> {
+        let table = parse_layer(contents, ConfigSource::Project)?;
+        ProjectConfig::from_table(table).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let config = parse(
+            "[profiles.ci]\nstartup_timeout_secs = 20\n\n[profiles.slow]\nper_service_ready_timeout_secs = 90\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.profile("ci").unwrap().startup_timeout_secs, Some(20));
+        assert_eq!(
+            config.profile("ci").unwrap().per_service_ready_timeout_secs,
+            None
+        );
+        assert_eq!(
+            config
+                .profile("slow")
+                .unwrap()
+                .per_service_ready_timeout_secs,
+            Some(90)
+        );
+        assert!(config.profile("missing").is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = parse("[profiles.ci]\nstartup_timeout = 20\n").unwrap_err();
+        assert!(err.contains("startup_timeout"));
+    }
+
+    #[test]
+    fn test_missing_file_uses_embedded_defaults() {
+        let config =
+            ProjectConfig::load(Path::new("/nonexistent/arbor"), &ConfigSources::default())
+                .unwrap();
+        let embedded =
+            ProjectConfig::from_table(parse_layer(DEFAULT_CONFIG, ConfigSource::Embedded).unwrap())
+                .unwrap();
+        assert_eq!(config, embedded);
+        assert!(config.profile("ci").is_ok());
+        assert_eq!(config.essential_services(), ["postgres", "redis"]);
+    }
+
+    #[test]
+    fn test_layers_merge_in_order_with_sources() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        std::fs::write(
+            root.join(PROJECT_CONFIG_FILE),
+            "[profiles.ci]\nstartup_timeout_secs = 60\n[profiles.slow]\nstartup_timeout_secs = 600\n",
+        )
+        .unwrap();
+        let user_file = root.join("user.toml");
+        std::fs::write(&user_file, "[profiles.slow]\nstartup_timeout_secs = 900\n").unwrap();
+        let sources = ConfigSources {
+            user_file: Some(user_file),
+            env: vec!["profiles.ci.startup_timeout_secs=30".to_string()],
+            cli: cli_flags(
+                ["arbor", "--config=profiles.ci.startup_timeout_secs=10"].map(String::from),
+            ),
+        };
+
+        let config = ProjectConfig::load(root, &sources).unwrap();
+        assert_eq!(config.profile("ci").unwrap().startup_timeout_secs, Some(10));
+        assert_eq!(
+            config.profile("slow").unwrap().startup_timeout_secs,
+            Some(900)
+        );
+
+        let effective = ProjectConfig::effective(root, &sources).unwrap();
+        let source_of = |key: &str| {
+            effective
+                .values
+                .iter()
+                .find(|value| value.key == key)
+                .map(|value| value.source)
+        };
+        assert_eq!(
+            source_of("profiles.ci.startup_timeout_secs"),
+            Some(ConfigSource::Cli)
+        );
+        assert_eq!(
+            source_of("profiles.ci.per_service_ready_timeout_secs"),
+            Some(ConfigSource::Embedded)
+        );
+        assert_eq!(
+            source_of("profiles.slow.startup_timeout_secs"),
+            Some(ConfigSource::User)
+        );
+        assert_eq!(effective.layers.len(), 5);
+    }
+
+    #[test]
+    fn test_set_user_value_keeps_other_values() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        let user_file = root.join("config").join(PROJECT_CONFIG_FILE);
+        let sources = ConfigSources {
+            user_file: Some(user_file.clone()),
+            ..ConfigSources::default()
+        };
+        sources
+            .set_user_value(
+                &["profiles", "ci", "startup_timeout_secs"],
+                Value::Integer(45),
+            )
+            .unwrap();
+        sources
+            .set_user_value(
+                &["services", "postgres", "restart_policy"],
+                Value::String("never".to_string()),
+            )
+            .unwrap();
+
+        let config = ProjectConfig::load(root, &sources).unwrap();
+        assert_eq!(config.profile("ci").unwrap().startup_timeout_secs, Some(45));
+        assert_eq!(
+            config.services["postgres"].restart_policy,
+            Some(RestartPolicy::Never)
+        );
+
+        let err = sources
+            .set_user_value(
+                &["services", "postgres", "restart_policy"],
+                Value::String("sometimes".to_string()),
+            )
+            .unwrap_err();
+        assert!(err.contains("sometimes"), "{}", err);
+        let config = ProjectConfig::load(root, &sources).unwrap();
+        assert_eq!(
+            config.services["postgres"].restart_policy,
+            Some(RestartPolicy::Never)
+        );
+    }
+
+    #[test]
+    fn test_type_conflicts_are_reported() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        std::fs::write(
+            root.join(PROJECT_CONFIG_FILE),
+            "[profiles.ci]\nstartup_timeout_secs = \"20\"\n",
+        )
+        .unwrap();
+
+        let err = ProjectConfig::load(root, &ConfigSources::default()).unwrap_err();
+        assert!(err.contains("profiles.ci.startup_timeout_secs"), "{}", err);
+        assert!(err.contains("embedded default config"), "{}", err);
+
+        let sources = ConfigSources {
+            cli: vec!["profiles.ci=3".to_string()],
+            ..ConfigSources::default()
+        };
+        std::fs::remove_file(root.join(PROJECT_CONFIG_FILE)).unwrap();
+        let err = ProjectConfig::load(root, &sources).unwrap_err();
+        assert!(
+            err.contains("profiles.ci: table in embedded default config, integer in --config"),
+            "{}",
+            err
+        );
+    }
+}
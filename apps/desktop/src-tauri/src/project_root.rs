@@ -0,0 +1,411 @@
+// Project root resolution
+// The stack is driven from the Arbor checkout (its Makefile and compose
+// files). The checkout is taken from, in order: the `--project-root` flag,
+// the ARBOR_PROJECT_ROOT env var, the `project_root` setting, and only then
+// an upward walk from the launch directory and the executable. The walk
+// never leaves the user's home or the enclosing git repository. A checkout
+// bundled with the app's resources is the last resort. `get_project_root`
+// says which of these the root came from, and `set_project_root` sets the
+// setting after checking the directory.
+
+use crate::protocol::Handshake;
+use crate::services::COMPOSE_FILES;
+use crate::settings::SettingsStore;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::State;
+
+pub const CLI_FLAG: &str = "--project-root";
+pub const ENV_VAR: &str = "ARBOR_PROJECT_ROOT";
+
+/// Everything the resolver looks at, captured once so it can be tested
+#[derive(Debug, Clone, Default)]
+pub struct RootSources {
+    pub cli: Option<PathBuf>,
+    pub env: Option<PathBuf>,
+    /// Directories the upward walk starts from
+    pub search_from: Vec<PathBuf>,
+    pub home: Option<PathBuf>,
+    /// The app's resource dir, used when the walk finds nothing
+    pub bundled: Option<PathBuf>,
+}
+
+impl RootSources {
+    /// Sources of the running process
+    pub fn from_process() -> Self {
+        let mut search_from = Vec::new();
+        if let Ok(dir) = std::env::current_dir() {
+            search_from.push(dir);
+        }
+        // Launched from Finder or a desktop entry the current dir is `/`;
+        // dev builds still live inside the checkout
+        if let Some(dir) = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        {
+            search_from.push(dir);
+        }
+
+        Self {
+            cli: cli_flag(std::env::args()),
+            env: std::env::var_os(ENV_VAR)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from),
+            search_from,
+            home: std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(PathBuf::from),
+            bundled: None,
+        }
+    }
+}
+
+/// Value of `--project-root <dir>` or `--project-root=<dir>`
+fn cli_flag(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == CLI_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(value) = arg.strip_prefix(CLI_FLAG).and_then(|v| v.strip_prefix('=')) {
+            return Some(PathBuf::from(value));
+        }
+    }
+    None
+}
+
+/// A directory with the Makefile and at least one of the stack's compose files
+fn is_checkout(dir: &Path) -> bool {
+    dir.join("Makefile").is_file() && COMPOSE_FILES.iter().any(|file| dir.join(file).is_file())
+}
+
+/// Where a resolved project root came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RootOrigin {
+    CliFlag,
+    EnvVar,
+    Setting,
+    /// Found walking up from the launch directory or the executable
+    Search,
+    Bundled,
+}
+
+impl RootOrigin {
+    fn describe(self) -> &'static str {
+        match self {
+            RootOrigin::CliFlag => CLI_FLAG,
+            RootOrigin::EnvVar => ENV_VAR,
+            RootOrigin::Setting => "project_root setting",
+            RootOrigin::Search => "search",
+            RootOrigin::Bundled => "bundled resources",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResolvedRoot {
+    pub path: PathBuf,
+    pub origin: RootOrigin,
+}
+
+/// Find the checkout, explicit sources first
+/// An explicitly configured root that isn't a checkout is an error rather
+/// than silently falling through to the walk.
+pub fn resolve(sources: &RootSources, setting: Option<&Path>) -> Result<PathBuf, String> {
+    resolve_with_origin(sources, setting).map(|root| root.path)
+}
+
+pub fn resolve_with_origin(
+    sources: &RootSources,
+    setting: Option<&Path>,
+) -> Result<ResolvedRoot, String> {
+    let explicit = [
+        (RootOrigin::CliFlag, sources.cli.as_deref()),
+        (RootOrigin::EnvVar, sources.env.as_deref()),
+        (RootOrigin::Setting, setting),
+    ];
+    if let Some((origin, dir)) = explicit
+        .into_iter()
+        .find_map(|(origin, dir)| dir.map(|dir| (origin, dir)))
+    {
+        let path = dir
+            .canonicalize()
+            .map_err(|e| format!("Project root {:?} from {}: {}", dir, origin.describe(), e))?;
+        if !is_checkout(&path) {
+            return Err(format!(
+                "Project root {:?} from {} has no Makefile and compose files",
+                dir,
+                origin.describe()
+            ));
+        }
+        return Ok(ResolvedRoot { path, origin });
+    }
+
+    let home = sources
+        .home
+        .as_ref()
+        .and_then(|home| home.canonicalize().ok());
+    let found = sources
+        .search_from
+        .iter()
+        .find_map(|start| walk_up(start, home.as_deref()))
+        .map(|path| ResolvedRoot {
+            path,
+            origin: RootOrigin::Search,
+        });
+    let bundled = || {
+        let path = sources.bundled.as_ref()?.canonicalize().ok()?;
+        is_checkout(&path).then_some(ResolvedRoot {
+            path,
+            origin: RootOrigin::Bundled,
+        })
+    };
+    found.or_else(bundled).ok_or_else(|| {
+            format!(
+                "Failed to find project root (no Arbor checkout above {:?}); set {} or the project_root setting",
+                sources.search_from, ENV_VAR
+            )
+        })
+}
+
+fn walk_up(start: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    // Resolving symlinks first keeps `..` in the real checkout
+    let start = start.canonicalize().ok()?;
+    for dir in start.ancestors() {
+        if is_checkout(dir) {
+            return Some(dir.to_path_buf());
+        }
+        if Some(dir) == home || dir.join(".git").exists() {
+            return None;
+        }
+    }
+    None
+}
+
+/// Resolved project root, cached per `project_root` setting
+pub struct ProjectRoot {
+    sources: RootSources,
+    cached: Mutex<Option<(Option<PathBuf>, ResolvedRoot)>>,
+}
+
+impl ProjectRoot {
+    pub fn new(sources: RootSources) -> Self {
+        Self {
+            sources,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub fn get(&self, setting: Option<&Path>) -> Result<PathBuf, String> {
+        self.resolved(setting).map(|root| root.path)
+    }
+
+    /// The root and where it came from
+    pub fn resolved(&self, setting: Option<&Path>) -> Result<ResolvedRoot, String> {
+        let mut cached = self.cached.lock().unwrap();
+        if let Some((cached_setting, root)) = cached.as_ref() {
+            if cached_setting.as_deref() == setting {
+                return Ok(root.clone());
+            }
+        }
+
+        let root = resolve_with_origin(&self.sources, setting)?;
+        *cached = Some((setting.map(Path::to_path_buf), root.clone()));
+        Ok(root)
+    }
+}
+
+/// The project root in use and where it came from, or why none was found
+#[tauri::command]
+pub async fn get_project_root(
+    project_root: State<'_, ProjectRoot>,
+    settings: State<'_, SettingsStore>,
+) -> Result<ResolvedRoot, String> {
+    project_root.resolved(settings.get().project_root.as_deref())
+}
+
+/// Set the `project_root` setting, or clear it with `None`, once the
+/// directory is known to be a checkout
+/// The flag and env var still win; the returned root shows which applies.
+#[tauri::command]
+pub async fn set_project_root(
+    handshake: State<'_, Handshake>,
+    project_root: State<'_, ProjectRoot>,
+    settings: State<'_, SettingsStore>,
+    path: Option<PathBuf>,
+) -> Result<ResolvedRoot, String> {
+    handshake.ensure()?;
+    if let Some(path) = &path {
+        resolve(&RootSources::default(), Some(path))?;
+    }
+    let updated = settings.update(serde_json::json!({ "project_root": path }))?;
+    project_root.resolved(updated.project_root.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    /// home/
+    ///   arbor/            checkout, a git repo
+    ///     apps/desktop/
+    ///     vendor/lib/     nested git repo
+    ///   scratch/          has a Makefile only
+    fn tree(dir: &TempDir) -> PathBuf {
+        let base = dir.path().to_path_buf();
+        let checkout = base.join("home/arbor");
+        std::fs::create_dir_all(checkout.join("apps/api")).unwrap();
+        std::fs::create_dir_all(checkout.join("apps/desktop")).unwrap();
+        std::fs::create_dir_all(checkout.join(".git")).unwrap();
+        std::fs::create_dir_all(checkout.join("vendor/lib/.git")).unwrap();
+        std::fs::write(checkout.join("Makefile"), "up:\n").unwrap();
+        std::fs::write(checkout.join(COMPOSE_FILES[0]), "services: {}\n").unwrap();
+        std::fs::create_dir_all(base.join("home/scratch")).unwrap();
+        std::fs::write(base.join("home/scratch/Makefile"), "all:\n").unwrap();
+        base.canonicalize().unwrap()
+    }
+
+    fn sources(base: &Path, start: &str) -> RootSources {
+        RootSources {
+            search_from: vec![base.join(start)],
+            home: Some(base.join("home")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_walks_up_to_checkout() {
+        let tmp = temp_dir();
+        let base = tree(&tmp);
+        let root = resolve(&sources(&base, "home/arbor/apps/desktop"), None).unwrap();
+        assert_eq!(root, base.join("home/arbor"));
+    }
+
+    #[test]
+    fn test_walk_stops_at_home_and_git_boundaries() {
+        let tmp = temp_dir();
+        let base = tree(&tmp);
+        // A checkout above home must not be picked up
+        std::fs::write(base.join("Makefile"), "up:\n").unwrap();
+        std::fs::create_dir_all(base.join("apps/api")).unwrap();
+        std::fs::write(base.join(COMPOSE_FILES[0]), "services: {}\n").unwrap();
+
+        assert!(resolve(&sources(&base, "home/scratch"), None).is_err());
+        assert!(
+            resolve(&sources(&base, "home/arbor/vendor/lib"), None).is_err(),
+            "Nested repositories are their own boundary"
+        );
+    }
+
+    #[test]
+    fn test_bundled_checkout_is_the_last_resort() {
+        let tmp = temp_dir();
+        let base = tree(&tmp);
+        let bundle = base.join("bundle");
+        std::fs::create_dir_all(bundle.join("apps/api")).unwrap();
+        std::fs::write(bundle.join("Makefile"), "up:\n").unwrap();
+        std::fs::write(bundle.join(COMPOSE_FILES[0]), "services: {}\n").unwrap();
+
+        let mut outside = sources(&base, "home/scratch");
+        outside.bundled = Some(bundle.clone());
+        assert_eq!(
+            resolve_with_origin(&outside, None).unwrap(),
+            ResolvedRoot {
+                path: bundle.clone(),
+                origin: RootOrigin::Bundled
+            }
+        );
+
+        let mut inside = sources(&base, "home/arbor/apps/desktop");
+        inside.bundled = Some(bundle);
+        let found = resolve_with_origin(&inside, None).unwrap();
+        assert_eq!(found.origin, RootOrigin::Search);
+        assert_eq!(found.path, base.join("home/arbor"));
+
+        outside.bundled = Some(base.join("home/scratch"));
+        let err = resolve(&outside, None).unwrap_err();
+        assert!(err.starts_with("Failed to find project root"), "{}", err);
+    }
+
+    #[test]
+    fn test_makefile_alone_is_not_a_checkout() {
+        let tmp = temp_dir();
+        let base = tree(&tmp);
+        let mut sources = sources(&base, "home/scratch");
+        sources.env = Some(base.join("home/scratch"));
+        let err = resolve(&sources, None).unwrap_err();
+        assert!(err.contains(ENV_VAR), "{}", err);
+    }
+
+    #[test]
+    fn test_explicit_sources_take_precedence() {
+        let tmp = temp_dir();
+        let base = tree(&tmp);
+        let other = base.join("other");
+        std::fs::create_dir_all(other.join("apps/api")).unwrap();
+        std::fs::write(other.join("Makefile"), "up:\n").unwrap();
+        std::fs::write(other.join(COMPOSE_FILES[0]), "services: {}\n").unwrap();
+        let checkout = base.join("home/arbor");
+
+        let mut sources = sources(&base, "home/arbor/apps/desktop");
+        assert_eq!(resolve(&sources, Some(&other)).unwrap(), other);
+
+        sources.env = Some(checkout.clone());
+        assert_eq!(resolve(&sources, Some(&other)).unwrap(), checkout);
+        assert_eq!(
+            resolve_with_origin(&sources, Some(&other)).unwrap().origin,
+            RootOrigin::EnvVar
+        );
+
+        sources.cli = Some(other.clone());
+        assert_eq!(resolve(&sources, None).unwrap(), other);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_checkout_resolves_to_real_path() {
+        let tmp = temp_dir();
+        let base = tree(&tmp);
+        std::os::unix::fs::symlink(base.join("home/arbor"), base.join("home/link")).unwrap();
+        let root = resolve(&sources(&base, "home/link/apps/desktop"), None).unwrap();
+        assert_eq!(root, base.join("home/arbor"));
+    }
+
+    #[test]
+    fn test_cli_flag_forms() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            cli_flag(args(&["arbor", "--project-root", "/src/arbor"])),
+            Some(PathBuf::from("/src/arbor"))
+        );
+        assert_eq!(
+            cli_flag(args(&["arbor", "--project-root=/src/arbor"])),
+            Some(PathBuf::from("/src/arbor"))
+        );
+        assert_eq!(cli_flag(args(&["arbor"])), None);
+    }
+
+    #[test]
+    fn test_root_is_cached_per_setting() {
+        let tmp = temp_dir();
+        let base = tree(&tmp);
+        let project_root = ProjectRoot::new(sources(&base, "home/arbor/apps/desktop"));
+        let checkout = base.join("home/arbor");
+        assert_eq!(project_root.get(None).unwrap(), checkout);
+
+        std::fs::remove_file(checkout.join("Makefile")).unwrap();
+        assert_eq!(
+            project_root.get(None).unwrap(),
+            checkout,
+            "Served from cache"
+        );
+        assert!(
+            project_root.get(Some(&checkout)).is_err(),
+            "A changed setting resolves again"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+// Webview ↔ backend protocol version
+// The frontend bundle and the backend can drift apart (a cached bundle after
+// an update, a dev server against an older build), and a changed command
+// signature then fails as an unhelpful serde error. The frontend announces
+// the protocol it speaks with `handshake` before anything else, and
+// commands that change state refuse to run until one has succeeded.
+
+use crate::messages::{Coded, Message};
+use crate::warn;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Mutex;
+use tauri::State;
+
+/// Bumped whenever a command's name, arguments or result change in a way
+/// an older frontend would get wrong
+pub const PROTOCOL_VERSION: u32 = 5;
+/// Oldest frontend protocol this backend still serves
+pub const MIN_FRONTEND_PROTOCOL_VERSION: u32 = 5;
+
+/// Why the frontend and backend can't talk, and who has to move
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProtocolMismatch {
+    /// The loaded bundle predates the backend; a hard reload picks up the
+    /// bundle it shipped with
+    FrontendOutdated {
+        frontend_version: u32,
+        backend_version: u32,
+    },
+    /// The frontend is newer than the backend; the app needs updating
+    BackendOutdated {
+        frontend_version: u32,
+        backend_version: u32,
+    },
+    /// A command ran before any successful handshake
+    HandshakeMissing { backend_version: u32 },
+}
+
+impl fmt::Display for ProtocolMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolMismatch::FrontendOutdated {
+                frontend_version,
+                backend_version,
+            } => write!(
+                f,
+                "Frontend protocol {} is older than the backend's {}; reload the window",
+                frontend_version, backend_version
+            ),
+            ProtocolMismatch::BackendOutdated {
+                frontend_version,
+                backend_version,
+            } => write!(
+                f,
+                "Frontend protocol {} is newer than the backend's {}; update Arbor",
+                frontend_version, backend_version
+            ),
+            ProtocolMismatch::HandshakeMissing { backend_version } => write!(
+                f,
+                "The frontend hasn't completed the protocol handshake (backend protocol {}); reload the window",
+                backend_version
+            ),
+        }
+    }
+}
+
+impl Coded for ProtocolMismatch {
+    fn message(&self) -> Message {
+        match self {
+            ProtocolMismatch::FrontendOutdated {
+                frontend_version,
+                backend_version,
+            } => Message::new("protocol-frontend-outdated")
+                .with_param("frontend_version", frontend_version)
+                .with_param("backend_version", backend_version),
+            ProtocolMismatch::BackendOutdated {
+                frontend_version,
+                backend_version,
+            } => Message::new("protocol-backend-outdated")
+                .with_param("frontend_version", frontend_version)
+                .with_param("backend_version", backend_version),
+            ProtocolMismatch::HandshakeMissing { .. } => Message::new("protocol-handshake-missing"),
+        }
+    }
+}
+
+/// Whether a frontend speaking `frontend_version` can use this backend
+pub fn check(frontend_version: u32) -> Result<(), ProtocolMismatch> {
+    if frontend_version < MIN_FRONTEND_PROTOCOL_VERSION {
+        return Err(ProtocolMismatch::FrontendOutdated {
+            frontend_version,
+            backend_version: PROTOCOL_VERSION,
+        });
+    }
+    if frontend_version > PROTOCOL_VERSION {
+        return Err(ProtocolMismatch::BackendOutdated {
+            frontend_version,
+            backend_version: PROTOCOL_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// The protocol the current frontend agreed to, once it has
+#[derive(Default)]
+pub struct Handshake {
+    accepted: Mutex<Option<u32>>,
+}
+
+impl Handshake {
+    /// Record the frontend's protocol; a mismatch clears any earlier
+    /// handshake, since it means a different bundle is now loaded
+    pub fn accept(&self, frontend_version: u32) -> Result<u32, ProtocolMismatch> {
+        let checked = check(frontend_version);
+        *self.accepted.lock().unwrap() = checked.as_ref().ok().map(|_| frontend_version);
+        checked.map(|_| PROTOCOL_VERSION)
+    }
+
+    /// Guard for commands that change state
+    pub fn ensure(&self) -> Result<(), String> {
+        match *self.accepted.lock().unwrap() {
+            Some(_) => Ok(()),
+            None => Err(ProtocolMismatch::HandshakeMissing {
+                backend_version: PROTOCOL_VERSION,
+            }
+            .to_string()),
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_protocol_version() -> Result<u32, String> {
+    Ok(PROTOCOL_VERSION)
+}
+
+/// Must be called by the frontend before any other command; returns the
+/// backend's protocol version
+#[tauri::command]
+pub async fn handshake(
+    handshake: State<'_, Handshake>,
+    frontend_protocol_version: u32,
+) -> Result<u32, ProtocolMismatch> {
+    handshake
+        .accept(frontend_protocol_version)
+        .map_err(|mismatch| {
+            warn!("🚫 {}", mismatch);
+            mismatch
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_older_frontend_is_told_to_reload() {
+        let handshake = Handshake::default();
+        assert!(handshake.ensure().is_err());
+
+        let mismatch = handshake
+            .accept(MIN_FRONTEND_PROTOCOL_VERSION - 1)
+            .unwrap_err();
+        assert!(matches!(
+            mismatch,
+            ProtocolMismatch::FrontendOutdated { .. }
+        ));
+        assert_eq!(mismatch.message().code, "protocol-frontend-outdated");
+        assert_eq!(
+            serde_json::to_value(&mismatch).unwrap()["kind"],
+            "frontend_outdated"
+        );
+        assert!(handshake.ensure().is_err());
+
+        assert_eq!(handshake.accept(PROTOCOL_VERSION), Ok(PROTOCOL_VERSION));
+        assert!(handshake.ensure().is_ok());
+    }
+
+    #[test]
+    fn test_newer_frontend_is_told_to_update_the_app() {
+        let handshake = Handshake::default();
+        assert!(handshake.accept(PROTOCOL_VERSION).is_ok());
+
+        // A newer bundle loaded later revokes the earlier handshake
+        let mismatch = handshake.accept(PROTOCOL_VERSION + 1).unwrap_err();
+        assert_eq!(
+            mismatch,
+            ProtocolMismatch::BackendOutdated {
+                frontend_version: PROTOCOL_VERSION + 1,
+                backend_version: PROTOCOL_VERSION,
+            }
+        );
+        assert_eq!(mismatch.message().code, "protocol-backend-outdated");
+        assert!(handshake.ensure().is_err());
+    }
+}
@@ -0,0 +1,409 @@
+// Image pulls
+// `docker compose pull` for the stack, with its layer progress emitted as
+// `image-pull-progress` so the frontend can show a bar per image. Compose
+// doesn't say which image a layer line belongs to, so images are pulled one
+// at a time (COMPOSE_PARALLEL_LIMIT=1) and each layer goes to the service
+// whose pull began last. Image ids are compared before and after to tell
+// which images changed, and whether running containers are now behind.
+// No network and a registry wanting credentials are told apart, so the
+// frontend can say what to do about them.
+
+use crate::docker;
+use crate::events::{self, EventSink};
+use crate::jobs::JobEnv;
+use crate::messages::{Coded, Message};
+use crate::network;
+use crate::process::{CommandRunner, CommandSpec, OutputLine};
+use crate::services::{self, ComposeSetup};
+use crate::settings::MeteredDownloads;
+use crate::stats;
+use crate::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use tauri::AppHandle;
+use tokio::sync::mpsc;
+
+/// Emitted with a PullProgress whenever a layer's percentage changes
+pub const IMAGE_PULL_PROGRESS_EVENT: &str = "image-pull-progress";
+
+/// What registries print when they can't be reached
+const OFFLINE_ERRORS: [&str; 6] = [
+    "no such host",
+    "network is unreachable",
+    "Temporary failure in name resolution",
+    "i/o timeout",
+    "TLS handshake timeout",
+    "connection refused",
+];
+/// What registries print when they want credentials
+const AUTH_ERRORS: [&str; 4] = [
+    "unauthorized",
+    "authentication required",
+    "requested access to the resource is denied",
+    "no basic auth credentials",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PullProgress {
+    /// The service's image, or the service where it's unknown
+    pub image: String,
+    pub layer: String,
+    /// Downloaded, 100 once the layer is complete or already present
+    pub percent: u8,
+}
+
+/// What a pull changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PullSummary {
+    /// Images that have a newer version now
+    pub changed: Vec<String>,
+    /// Images that were already up to date
+    pub unchanged: Vec<String>,
+    /// Running containers use a changed image until they're recreated
+    pub restart_needed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PullError {
+    /// The registry couldn't be reached
+    Offline {
+        detail: String,
+    },
+    /// The registry wants credentials (`docker login`)
+    AuthRequired {
+        detail: String,
+    },
+    /// The connection is metered and downloads aren't set to proceed; the
+    /// `pull_images` job asks first
+    Metered,
+    Failed {
+        detail: String,
+    },
+}
+
+impl PullError {
+    /// Tell apart why `docker compose pull` failed from what it printed
+    fn classify(stderr: &str) -> Self {
+        let detail = stderr.trim().to_string();
+        if AUTH_ERRORS.iter().any(|error| stderr.contains(error)) {
+            PullError::AuthRequired { detail }
+        } else if OFFLINE_ERRORS.iter().any(|error| stderr.contains(error)) {
+            PullError::Offline { detail }
+        } else {
+            PullError::Failed { detail }
+        }
+    }
+}
+
+impl fmt::Display for PullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PullError::Offline { detail } => {
+                write!(
+                    f,
+                    "Can't reach the image registry; check your connection: {}",
+                    detail
+                )
+            }
+            PullError::AuthRequired { detail } => write!(
+                f,
+                "The image registry wants credentials; run `docker login`: {}",
+                detail
+            ),
+            PullError::Metered => write!(
+                f,
+                "Not downloading images on a metered connection; start a pull_images job to confirm"
+            ),
+            PullError::Failed { detail } => write!(f, "Failed to pull images: {}", detail),
+        }
+    }
+}
+
+impl Coded for PullError {
+    fn message(&self) -> Message {
+        match self {
+            PullError::Offline { .. } => Message::new("pull-offline"),
+            PullError::AuthRequired { .. } => Message::new("pull-auth-required"),
+            PullError::Metered => Message::new("pull-metered"),
+            PullError::Failed { detail } => {
+                Message::new("pull-failed").with_param("detail", detail)
+            }
+        }
+    }
+}
+
+/// A line of `docker compose pull` output that says something
+#[derive(Debug, PartialEq, Eq)]
+enum PullLine<'a> {
+    /// `<service> Pulling`
+    Started(&'a str),
+    Layer {
+        layer: &'a str,
+        percent: u8,
+    },
+}
+
+fn parse_line(line: &str) -> Option<PullLine<'_>> {
+    let (id, status) = line.trim().split_once(' ')?;
+    let status = status.trim();
+    if status == "Pulling" {
+        return Some(PullLine::Started(id));
+    }
+    if id.len() != 12 || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let percent = match status {
+        "Download complete" | "Pull complete" | "Already exists" => 100,
+        // `Downloading [==>    ]  1.1MB/27.1MB`
+        _ if status.starts_with("Downloading") => {
+            let (done, total) = status.rsplit(' ').next()?.split_once('/')?;
+            let (done, total) = (stats::parse_size(done)?, stats::parse_size(total)?);
+            if total == 0 {
+                return None;
+            }
+            (done.min(total) * 100 / total) as u8
+        }
+        _ => return None,
+    };
+    Some(PullLine::Layer { layer: id, percent })
+}
+
+/// Emit the progress in `lines` until they end
+async fn forward_progress(
+    sink: &dyn EventSink,
+    images: &BTreeMap<String, String>,
+    mut lines: mpsc::UnboundedReceiver<OutputLine>,
+) {
+    let mut image = String::new();
+    let mut reported: HashMap<String, u8> = HashMap::new();
+    while let Some(line) = lines.recv().await {
+        match parse_line(&line.line) {
+            Some(PullLine::Started(service)) => {
+                image = images
+                    .get(service)
+                    .cloned()
+                    .unwrap_or_else(|| service.to_string());
+            }
+            Some(PullLine::Layer { layer, percent }) => {
+                if reported.insert(layer.to_string(), percent) == Some(percent) {
+                    continue;
+                }
+                let progress = PullProgress {
+                    image: image.clone(),
+                    layer: layer.to_string(),
+                    percent,
+                };
+                events::emit(sink, IMAGE_PULL_PROGRESS_EVENT, &progress);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Id of the local copy of `image`, if there is one
+async fn image_id(runner: &dyn CommandRunner, image: &str) -> Option<String> {
+    let spec = CommandSpec::new("docker").args(["image", "inspect", "--format", "{{.Id}}", image]);
+    let output = runner
+        .output(&spec)
+        .await
+        .ok()
+        .filter(|output| output.success())?;
+    Some(output.stdout_text().trim().to_string())
+}
+
+/// Pull the stack's images, emitting their progress on `sink`
+pub async fn pull_images(
+    runner: &dyn CommandRunner,
+    sink: &dyn EventSink,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<PullSummary, PullError> {
+    let images = services::service_images(runner, project_root, setup)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "⚠️  Pulling without knowing the images, so changes aren't reported: {}",
+                e
+            );
+            BTreeMap::new()
+        });
+    let mut before = BTreeMap::new();
+    for image in images.values() {
+        before.insert(image.clone(), image_id(runner, image).await);
+    }
+
+    info!("⬇️  Pulling images...");
+    let mut spec = setup.compose_command(project_root).args(["pull"]);
+    spec.env
+        .push(("COMPOSE_PARALLEL_LIMIT".to_string(), "1".into()));
+    let (lines_tx, lines) = mpsc::unbounded_channel();
+    let (output, ()) = tokio::join!(
+        runner.output_lines(&spec, lines_tx),
+        forward_progress(sink, &images, lines)
+    );
+    let output = output.map_err(|e| PullError::Failed {
+        detail: e.to_string(),
+    })?;
+    if !output.success() {
+        return Err(PullError::classify(&output.stderr_text()));
+    }
+
+    let (mut changed, mut unchanged) = (Vec::new(), Vec::new());
+    for (image, id) in before {
+        match image_id(runner, &image).await == id {
+            true => unchanged.push(image),
+            false => changed.push(image),
+        }
+    }
+    let restart_needed = !changed.is_empty()
+        && match docker::list_containers(runner).await {
+            Ok(containers) => containers
+                .iter()
+                .any(|container| container.is_running() && changed.contains(&container.image)),
+            // Can't tell; say so rather than leave old images running
+            Err(_) => true,
+        };
+    info!("✅ Pulled images; {} changed", changed.len());
+    Ok(PullSummary {
+        changed,
+        unchanged,
+        restart_needed,
+    })
+}
+
+/// Pull the stack's images now, for a connection that needn't be confirmed
+/// On a metered connection this is refused unless downloads proceed anyway;
+/// the `pull_images` job asks the user instead.
+#[tauri::command]
+pub async fn update_service_images(app_handle: AppHandle) -> Result<PullSummary, PullError> {
+    let env = JobEnv::for_app(&app_handle).map_err(|detail| PullError::Failed { detail })?;
+    let connection = network::detect(env.runner.as_ref(), env.assume_metered).await;
+    if connection.metered && env.downloads_on_metered != MeteredDownloads::Proceed {
+        return Err(PullError::Metered);
+    }
+    pull_images(
+        env.runner.as_ref(),
+        &app_handle,
+        &env.project_root,
+        &env.setup,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::settings::AppSettings;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_progress_lines_are_parsed() {
+        assert_eq!(
+            parse_line(" redis Pulling "),
+            Some(PullLine::Started("redis"))
+        );
+        assert_eq!(
+            parse_line(" 1f7ce2fa46ab Downloading [=====>       ]  5MB/20MB"),
+            Some(PullLine::Layer {
+                layer: "1f7ce2fa46ab",
+                percent: 25
+            })
+        );
+        assert_eq!(
+            parse_line(" 1f7ce2fa46ab Pull complete "),
+            Some(PullLine::Layer {
+                layer: "1f7ce2fa46ab",
+                percent: 100
+            })
+        );
+        assert_eq!(parse_line(" 1f7ce2fa46ab Waiting "), None);
+        assert_eq!(parse_line(" redis Pulled "), None);
+    }
+
+    #[test]
+    fn test_registry_errors_are_told_apart() {
+        let offline = "Error response from daemon: Get \"https://registry-1.docker.io/v2/\": \
+                       dial tcp: lookup registry-1.docker.io: no such host";
+        assert!(matches!(
+            PullError::classify(offline),
+            PullError::Offline { .. }
+        ));
+        let auth = "Error response from daemon: pull access denied for arbor/api, repository \
+                    does not exist or may require 'docker login': denied: requested access to \
+                    the resource is denied";
+        assert!(matches!(
+            PullError::classify(auth),
+            PullError::AuthRequired { .. }
+        ));
+        assert!(matches!(
+            PullError::classify("manifest unknown"),
+            PullError::Failed { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pull_reports_progress_and_what_changed() {
+        let pulled = Arc::new(AtomicBool::new(false));
+        let runner = MockRunner::new(move |spec| {
+            let args: Vec<&str> = spec.args.iter().map(String::as_str).collect();
+            let (stdout, stderr) = match args[..] {
+                [.., "config", "--format", "json"] => (
+                    r#"{"services": {"postgres": {"image": "postgres:16"}, "redis": {"image": "redis:7"}}}"#
+                        .to_string(),
+                    String::new(),
+                ),
+                [.., "pull"] => {
+                    pulled.store(true, Ordering::SeqCst);
+                    (
+                        String::new(),
+                        " redis Pulling \n 1f7ce2fa46ab Downloading [=>  ]  1MB/4MB\n \
+                         1f7ce2fa46ab Downloading [=>  ]  1MB/4MB\n 1f7ce2fa46ab Pull complete \n \
+                         redis Pulled \n postgres Pulling \n postgres Pulled \n"
+                            .to_string(),
+                    )
+                }
+                ["image", "inspect", "--format", "{{.Id}}", "redis:7"]
+                    if pulled.load(Ordering::SeqCst) =>
+                {
+                    ("sha256:new\n".to_string(), String::new())
+                }
+                ["image", "inspect", ..] => ("sha256:old\n".to_string(), String::new()),
+                ["ps", ..] => ("arbor-redis\trunning\tUp\t\tredis:7\n".to_string(), String::new()),
+                _ => (String::new(), String::new()),
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.into_bytes(),
+                stderr: stderr.into_bytes(),
+            })
+        });
+        let sink = RecordingSink::default();
+        let root = Path::new("/work/arbor");
+        let setup =
+            ComposeSetup::from_settings(&AppSettings::default(), root, Path::new("/data/arbor"));
+
+        let summary = pull_images(&runner, &sink, root, &setup).await.unwrap();
+        assert_eq!(
+            summary,
+            PullSummary {
+                changed: vec!["redis:7".to_string()],
+                unchanged: vec!["postgres:16".to_string()],
+                restart_needed: true,
+            }
+        );
+        let progress = sink.events_named(IMAGE_PULL_PROGRESS_EVENT);
+        assert_eq!(progress.len(), 2, "repeats aren't emitted");
+        assert_eq!(
+            progress[0],
+            serde_json::json!({ "image": "redis:7", "layer": "1f7ce2fa46ab", "percent": 25 })
+        );
+        assert_eq!(progress[1]["percent"], 100);
+    }
+}
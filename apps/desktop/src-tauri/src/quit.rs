@@ -0,0 +1,138 @@
+// Quitting
+// Closing the main window (or Quit in the tray) holds the close, emits `shutting-down` for the
+// frontend to show that it's stopping services, brings the stack down and
+// only then exits. Otherwise the process went away under the stop and left
+// the containers running. The stop force-removes the containers once
+// `make down` has had `stop_timeout_secs`; should that hang too, quitting
+// gives up a little later, and `quit_without_stopping` exits right away.
+
+use crate::events;
+use crate::lifecycle::{StopInitiator, StopReason};
+use crate::services::StackState;
+use crate::settings::SettingsStore;
+use crate::ServiceManager;
+use crate::{error, info};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+/// Emitted with a ShuttingDown once quitting has begun
+pub const SHUTTING_DOWN_EVENT: &str = "shutting-down";
+/// How long quitting waits past `stop_timeout_secs`, for the force-removal
+const FORCED_STOP_ALLOWANCE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShuttingDown {
+    /// After this, the app exits whether or not services stopped
+    pub timeout_secs: u64,
+}
+
+/// Set once quitting has begun, so closing again doesn't stop twice
+#[derive(Default)]
+pub struct Quitting(AtomicBool);
+
+impl Quitting {
+    /// Whether this call began quitting
+    pub fn begin(&self) -> bool {
+        !self.0.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// The outcome of `stop`, or an error once `timeout` has passed without one
+pub async fn stop_within<T, F>(timeout: Duration, stop: F) -> Result<T, String>
+where
+    F: Future<Output = Result<T, String>>,
+{
+    tokio::time::timeout(timeout, stop)
+        .await
+        .unwrap_or_else(|_| {
+            Err(format!(
+                "Services didn't stop within {}s",
+                timeout.as_secs()
+            ))
+        })
+}
+
+/// Whether quitting should stop the stack first: an adopted stack is left
+/// running unless `stop_adopted_on_exit` is set, and then it's stopped as
+/// if Arbor had started it
+pub fn stops_stack(app_handle: &AppHandle) -> bool {
+    let service_manager = app_handle.state::<ServiceManager>();
+    if service_manager.ensure_owned("stop services").is_ok() {
+        return true;
+    }
+    if !app_handle
+        .state::<SettingsStore>()
+        .get()
+        .stop_adopted_on_exit
+    {
+        return false;
+    }
+    info!("🛑 Stopping the adopted stack (stop_adopted_on_exit)");
+    service_manager.set_stack(app_handle, StackState::Running { adopted: false });
+    true
+}
+
+/// Stop the stack and exit, once the main window's close has been held
+pub fn quit(app_handle: AppHandle) {
+    if !app_handle.state::<Quitting>().begin() {
+        return;
+    }
+    info!("👋 Quitting; stopping services first...");
+    let stop_timeout = app_handle.state::<SettingsStore>().get().stop_timeout_secs;
+    let timeout = Duration::from_secs(stop_timeout) + FORCED_STOP_ALLOWANCE;
+    events::emit(
+        &app_handle,
+        SHUTTING_DOWN_EVENT,
+        &ShuttingDown {
+            timeout_secs: timeout.as_secs(),
+        },
+    );
+    tauri::async_runtime::spawn(async move {
+        let stop = crate::stop_stack(
+            app_handle.clone(),
+            app_handle.state(),
+            app_handle.state(),
+            StopReason::new(StopInitiator::WindowClose),
+        );
+        let result = stop_within(timeout, stop).await;
+        if let Err(e) = crate::recorded(&app_handle, "stop_services", result) {
+            error!("❌ Failed to stop services: {}; quitting anyway", e);
+        }
+        app_handle.exit(0);
+    });
+}
+
+/// Exit now, leaving services as they are
+#[tauri::command]
+pub fn quit_without_stopping(app_handle: AppHandle) {
+    info!("👋 Quitting without stopping services");
+    app_handle.exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_hung_stop_is_given_up_on() {
+        let timeout = Duration::from_secs(15);
+        let stopped = stop_within(timeout, async { Ok("stopped".to_string()) });
+        assert_eq!(stopped.await, Ok("stopped".to_string()));
+
+        let hung = stop_within::<String, _>(timeout, std::future::pending());
+        assert_eq!(
+            hung.await,
+            Err("Services didn't stop within 15s".to_string())
+        );
+    }
+
+    #[test]
+    fn test_quitting_begins_once() {
+        let quitting = Quitting::default();
+        assert!(quitting.begin());
+        assert!(!quitting.begin());
+    }
+}
@@ -0,0 +1,442 @@
+// Startup readiness
+// After `make up`, poll the arbor containers until every one is running (and
+// healthy, where it has a healthcheck), then the health endpoint if one is
+// configured until it answers 2xx, at the port its service publishes where
+// it names one. Two limits apply: the whole startup, and each service from
+// the moment its container appears.
+
+use crate::docker;
+use crate::endpoints;
+use crate::process::CommandRunner;
+use crate::project_config::ProjectConfig;
+use crate::settings::AppSettings;
+use crate::{info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::RangeInclusive;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{Duration, Instant};
+
+pub const SERVICES_READY_EVENT: &str = "services-ready";
+pub const SERVICES_FAILED_EVENT: &str = "services-failed";
+
+pub const STARTUP_TIMEOUT_RANGE: RangeInclusive<u64> = 5..=900;
+pub const PER_SERVICE_TIMEOUT_RANGE: RangeInclusive<u64> = 1..=900;
+pub const POLL_INTERVAL_RANGE_MS: RangeInclusive<u64> = 100..=10_000;
+
+/// How long one health endpoint request may take
+const HEALTH_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Effective limits for one start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadinessLimits {
+    pub startup_timeout: Duration,
+    pub per_service_timeout: Duration,
+    pub poll_interval: Duration,
+    /// Must answer 2xx once the containers are ready
+    pub health_url: Option<HealthUrl>,
+}
+
+impl ReadinessLimits {
+    /// Settings, overridden by the selected profile in `arbor.toml`
+    pub fn resolve(settings: &AppSettings, config: &ProjectConfig) -> Result<Self, String> {
+        let mut startup = settings.startup_timeout_secs;
+        let mut per_service = settings.per_service_ready_timeout_secs;
+
+        if let Some(name) = &settings.profile {
+            let profile = config.profile(name)?;
+            startup = profile.startup_timeout_secs.unwrap_or(startup);
+            per_service = profile
+                .per_service_ready_timeout_secs
+                .unwrap_or(per_service);
+        }
+
+        validate_timeouts(startup, per_service)?;
+        validate_poll_interval(settings.ready_poll_interval_ms)?;
+        Ok(Self {
+            startup_timeout: Duration::from_secs(startup),
+            per_service_timeout: Duration::from_secs(per_service),
+            poll_interval: Duration::from_millis(settings.ready_poll_interval_ms),
+            health_url: settings
+                .health_url
+                .as_deref()
+                .map(HealthUrl::parse)
+                .transpose()?,
+        })
+    }
+}
+
+pub fn validate_poll_interval(interval_ms: u64) -> Result<(), String> {
+    if !POLL_INTERVAL_RANGE_MS.contains(&interval_ms) {
+        return Err(format!(
+            "ready_poll_interval_ms must be between {} and {}",
+            POLL_INTERVAL_RANGE_MS.start(),
+            POLL_INTERVAL_RANGE_MS.end()
+        ));
+    }
+    Ok(())
+}
+
+/// A plain `http://host[:port][/path]` URL, e.g. the API's health route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthUrl {
+    /// `host:port`, the port defaulting to 80
+    pub address: String,
+    pub path: String,
+}
+
+impl HealthUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("health_url must start with http:// (got {:?})", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() || authority.contains(['@', ' ']) {
+            return Err(format!("health_url has no valid host (got {:?})", url));
+        }
+        let address = match authority.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => authority.to_string(),
+            Some(_) => return Err(format!("health_url has an invalid port (got {:?})", url)),
+            None => format!("{}:80", authority),
+        };
+        Ok(Self {
+            address,
+            path: path.to_string(),
+        })
+    }
+
+    /// The response's status code to a GET
+    pub async fn status(&self) -> Result<u16, String> {
+        tokio::time::timeout(HEALTH_REQUEST_TIMEOUT, self.get())
+            .await
+            .map_err(|_| {
+                format!(
+                    "{} did not answer within {}s",
+                    self,
+                    HEALTH_REQUEST_TIMEOUT.as_secs()
+                )
+            })?
+    }
+
+    async fn get(&self) -> Result<u16, String> {
+        let mut stream = tokio::net::TcpStream::connect(&self.address)
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", self.address, e))?;
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            self.path, self.address
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+        let mut response = Vec::new();
+        stream
+            .read_to_end(&mut response)
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?;
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or_default();
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("Unexpected response from {}: {:?}", self, status_line))
+    }
+}
+
+impl fmt::Display for HealthUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.address, self.path)
+    }
+}
+
+/// Both timeouts within range, and a service not allowed longer than the startup
+pub fn validate_timeouts(startup_secs: u64, per_service_secs: u64) -> Result<(), String> {
+    if !STARTUP_TIMEOUT_RANGE.contains(&startup_secs) {
+        return Err(format!(
+            "startup_timeout_secs must be between {} and {}",
+            STARTUP_TIMEOUT_RANGE.start(),
+            STARTUP_TIMEOUT_RANGE.end()
+        ));
+    }
+    if !PER_SERVICE_TIMEOUT_RANGE.contains(&per_service_secs) || per_service_secs > startup_secs {
+        return Err(format!(
+            "per_service_ready_timeout_secs must be between {} and startup_timeout_secs ({})",
+            PER_SERVICE_TIMEOUT_RANGE.start(),
+            startup_secs
+        ));
+    }
+    Ok(())
+}
+
+/// Payload of the `services-failed` event, naming the limit that was exceeded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "limit", rename_all = "snake_case")]
+pub enum ReadinessFailure {
+    StartupTimeout {
+        timeout_secs: u64,
+        pending_services: Vec<String>,
+    },
+    PerServiceReadyTimeout {
+        timeout_secs: u64,
+        service: String,
+        status: String,
+    },
+}
+
+impl std::fmt::Display for ReadinessFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadinessFailure::StartupTimeout {
+                timeout_secs,
+                pending_services,
+            } => write!(
+                f,
+                "Services not ready within the {}s startup timeout (still waiting for: {})",
+                timeout_secs,
+                pending_services.join(", ")
+            ),
+            ReadinessFailure::PerServiceReadyTimeout {
+                timeout_secs,
+                service,
+                status,
+            } => write!(
+                f,
+                "{} not ready within the {}s per-service timeout ({})",
+                service, timeout_secs, status
+            ),
+        }
+    }
+}
+
+/// Poll until all arbor containers and the health endpoint are ready,
+/// returning the containers' names
+/// `on_ready` is called once per service as it becomes ready.
+pub async fn wait_until_ready(
+    runner: &dyn CommandRunner,
+    limits: ReadinessLimits,
+    on_ready: &(dyn Fn(&str) + Sync),
+) -> Result<Vec<String>, ReadinessFailure> {
+    let started = Instant::now();
+    let mut first_seen: HashMap<String, Instant> = HashMap::new();
+    let mut reported: HashSet<String> = HashSet::new();
+    let mut pending = Vec::new();
+
+    loop {
+        match docker::list_containers(runner).await {
+            Ok(containers) => {
+                let now = Instant::now();
+                pending.clear();
+                for container in &containers {
+                    let seen = *first_seen.entry(container.name.clone()).or_insert(now);
+                    if container.is_ready() {
+                        if reported.insert(container.name.clone()) {
+                            on_ready(&container.name);
+                        }
+                        continue;
+                    }
+                    if now.duration_since(seen) >= limits.per_service_timeout {
+                        return Err(ReadinessFailure::PerServiceReadyTimeout {
+                            timeout_secs: limits.per_service_timeout.as_secs(),
+                            service: container.name.clone(),
+                            status: container.status.clone(),
+                        });
+                    }
+                    pending.push(container.name.clone());
+                }
+
+                if !containers.is_empty() && pending.is_empty() {
+                    let Some(url) = &limits.health_url else {
+                        return Ok(containers.into_iter().map(|c| c.name).collect());
+                    };
+                    let url = endpoints::resolve(runner, url).await;
+                    match url.status().await {
+                        Ok(code) if (200..300).contains(&code) => {
+                            return Ok(containers.into_iter().map(|c| c.name).collect());
+                        }
+                        Ok(code) => info!("⏳ {} answered {}", url, code),
+                        Err(e) => info!("⏳ {}", e),
+                    }
+                    pending.push(url.to_string());
+                }
+            }
+            Err(e) => warn!("⚠️  Readiness check failed: {}", e),
+        }
+
+        if started.elapsed() >= limits.startup_timeout {
+            return Err(ReadinessFailure::StartupTimeout {
+                timeout_secs: limits.startup_timeout.as_secs(),
+                pending_services: pending,
+            });
+        }
+        tokio::time::sleep(limits.poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::project_config::Profile;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn limits(startup: u64, per_service: u64) -> ReadinessLimits {
+        ReadinessLimits {
+            startup_timeout: Duration::from_secs(startup),
+            per_service_timeout: Duration::from_secs(per_service),
+            poll_interval: Duration::from_secs(1),
+            health_url: None,
+        }
+    }
+
+    /// Runner answering `docker ps` with successive outputs, repeating the last
+    fn ps_sequence(outputs: &'static [&'static str]) -> MockRunner {
+        let call = AtomicUsize::new(0);
+        MockRunner::new(move |_| {
+            let n = call.fetch_add(1, Ordering::SeqCst).min(outputs.len() - 1);
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: outputs[n].as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waits_for_healthchecks() {
+        let runner = ps_sequence(&[
+            "",
+            "arbor-postgres\trunning\tUp 1 second (health: starting)\narbor-redis\trunning\tUp 1 second\n",
+            "arbor-postgres\trunning\tUp 3 seconds (healthy)\narbor-redis\trunning\tUp 3 seconds\n",
+        ]);
+
+        let order = std::sync::Mutex::new(Vec::new());
+        let on_ready = |service: &str| order.lock().unwrap().push(service.to_string());
+        let ready = wait_until_ready(&runner, limits(60, 30), &on_ready).await.unwrap();
+        assert_eq!(ready, vec!["arbor-postgres", "arbor-redis"]);
+        assert_eq!(runner.calls().len(), 3);
+        // Each service reported once, as it became ready
+        assert_eq!(*order.lock().unwrap(), vec!["arbor-redis", "arbor-postgres"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_service_timeout_names_the_service() {
+        let runner = ps_sequence(&[
+            "arbor-redis\trunning\tUp 1 second\narbor-minio\trunning\tUp 1 second (unhealthy)\n",
+        ]);
+
+        let failure = wait_until_ready(&runner, limits(60, 5), &|_| {}).await.unwrap_err();
+        assert_eq!(
+            failure,
+            ReadinessFailure::PerServiceReadyTimeout {
+                timeout_secs: 5,
+                service: "arbor-minio".to_string(),
+                status: "Up 1 second (unhealthy)".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_startup_timeout_when_nothing_appears() {
+        let runner = ps_sequence(&[""]);
+
+        let failure = wait_until_ready(&runner, limits(20, 10), &|_| {}).await.unwrap_err();
+        assert!(matches!(
+            failure,
+            ReadinessFailure::StartupTimeout {
+                timeout_secs: 20,
+                ..
+            }
+        ));
+        let payload = serde_json::to_value(&failure).unwrap();
+        assert_eq!(payload["limit"], "startup_timeout");
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_the_health_endpoint_after_the_containers() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request).await.unwrap();
+                assert!(String::from_utf8_lossy(&request[..read]).starts_with("GET /health "));
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let runner = ps_sequence(&["arbor-api\trunning\tUp 2 seconds\n"]);
+        let limits = ReadinessLimits {
+            poll_interval: Duration::from_millis(100),
+            health_url: Some(HealthUrl::parse(&format!("http://{}/health", address)).unwrap()),
+            ..limits(60, 30)
+        };
+
+        let ready = wait_until_ready(&runner, limits, &|_| {}).await.unwrap();
+        assert_eq!(ready, vec!["arbor-api"]);
+        assert_eq!(runner.calls().len(), 2, "polled again after the 503");
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_health_urls_are_plain_http() {
+        let url = HealthUrl::parse("http://api.arbor.local/health").unwrap();
+        assert_eq!(url.address, "api.arbor.local:80");
+        assert_eq!(url.path, "/health");
+        let url = HealthUrl::parse("http://localhost:3001").unwrap();
+        assert_eq!(url.to_string(), "http://localhost:3001/");
+        assert!(HealthUrl::parse("https://api.arbor.local/health").is_err());
+        assert!(HealthUrl::parse("http://localhost:api/health").is_err());
+        assert!(HealthUrl::parse("http:///health").is_err());
+    }
+
+    #[test]
+    fn test_profile_overrides_settings() {
+        let mut config = ProjectConfig::default();
+        config.profiles.insert(
+            "ci".to_string(),
+            Profile {
+                startup_timeout_secs: Some(20),
+                per_service_ready_timeout_secs: None,
+            },
+        );
+        let mut settings = AppSettings {
+            per_service_ready_timeout_secs: 15,
+            ..AppSettings::default()
+        };
+
+        let limits = ReadinessLimits::resolve(&settings, &config).unwrap();
+        assert_eq!(
+            limits.startup_timeout,
+            Duration::from_secs(settings.startup_timeout_secs)
+        );
+
+        settings.profile = Some("ci".to_string());
+        let limits = ReadinessLimits::resolve(&settings, &config).unwrap();
+        assert_eq!(limits.startup_timeout, Duration::from_secs(20));
+        assert_eq!(limits.per_service_timeout, Duration::from_secs(15));
+
+        settings.profile = Some("nightly".to_string());
+        assert!(ReadinessLimits::resolve(&settings, &config).is_err());
+    }
+
+    #[test]
+    fn test_timeouts_are_range_checked() {
+        assert!(validate_timeouts(120, 60).is_ok());
+        assert!(validate_timeouts(1, 1).is_err());
+        assert!(validate_timeouts(10_000, 60).is_err());
+        assert!(validate_poll_interval(50).is_err());
+        assert!(validate_timeouts(60, 0).is_err());
+        assert!(
+            validate_timeouts(20, 30).is_err(),
+            "A service can't outlast the startup"
+        );
+    }
+}
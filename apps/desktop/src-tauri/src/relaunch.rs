@@ -0,0 +1,250 @@
+// App relaunch
+// Some settings only take effect after a restart. Quitting stops the stack
+// and the next launch starts it again, so a relaunch can instead keep the
+// containers running: it leaves an "adopt" marker in the launch state file,
+// and the next launch picks the stack up as it is and clears the marker.
+//
+// The file also records the session running a stack Arbor started, until
+// it's stopped. Containers running at launch while that record is still
+// there were left by a crash or force-quit, and the frontend asks whether
+// to adopt them or restart fresh; without it, they were started outside
+// Arbor.
+
+use crate::jobs::JobManager;
+use crate::paths;
+use crate::services::{AdoptionReport, ComposeSetup};
+use crate::settings::SettingsStore;
+use crate::ServiceManager;
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager, State};
+
+pub const LAUNCH_STATE_FILE_NAME: &str = "launch-state.json";
+/// Emitted with a PreviousSession when a crashed one left containers running
+pub const PREVIOUS_SESSION_EVENT: &str = "previous-session-detected";
+
+/// Markers older than this are from a relaunch that never came back up
+const MAX_MARKER_AGE_SECS: i64 = 10 * 60;
+
+/// Left by a relaunch that kept services running
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdoptMarker {
+    /// The compose configuration the running stack was started with
+    pub setup: Option<ComposeSetup>,
+    pub recorded_at: String,
+}
+
+/// The launch running a stack it started
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionMarker {
+    pub pid: u32,
+    pub started_at: String,
+}
+
+/// Containers a session that never stopped them left running
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PreviousSession {
+    pub session: SessionMarker,
+    pub stack: AdoptionReport,
+}
+
+/// State handed from one launch to the next
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct LaunchStateFile {
+    adopt_running_stack: Option<AdoptMarker>,
+    running_session: Option<SessionMarker>,
+}
+
+pub struct LaunchState {
+    path: PathBuf,
+}
+
+impl LaunchState {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn for_app(app_handle: &AppHandle) -> Result<Self, String> {
+        let data_dir = paths::data_dir(app_handle)?;
+        Ok(Self::new(data_dir.join(LAUNCH_STATE_FILE_NAME)))
+    }
+
+    fn load(&self) -> LaunchStateFile {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("⚠️  Ignoring invalid launch state {:?}: {}", self.path, e);
+                LaunchStateFile::default()
+            }),
+            Err(_) => LaunchStateFile::default(),
+        }
+    }
+
+    fn save(&self, state: &LaunchStateFile) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create launch state directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, contents)
+            .map_err(|e| format!("Failed to save launch state: {}", e))
+    }
+
+    /// Ask the next launch to adopt the running stack
+    pub fn record_adopt(&self, setup: Option<ComposeSetup>) -> Result<(), String> {
+        let mut state = self.load();
+        state.adopt_running_stack = Some(AdoptMarker {
+            setup,
+            recorded_at: chrono::Utc::now().to_rfc3339(),
+        });
+        self.save(&state)
+    }
+
+    /// Read and clear the adopt marker; stale markers are dropped
+    pub fn take_adopt(&self) -> Option<AdoptMarker> {
+        let mut state = self.load();
+        let marker = state.adopt_running_stack.take()?;
+        if let Err(e) = self.save(&state) {
+            warn!("⚠️  {}", e);
+        }
+
+        let age = chrono::DateTime::parse_from_rfc3339(&marker.recorded_at)
+            .map(|at| chrono::Utc::now().signed_duration_since(at).num_seconds())
+            .unwrap_or(i64::MAX);
+        if !(0..=MAX_MARKER_AGE_SECS).contains(&age) {
+            warn!(
+                "⚠️  Ignoring stale adopt marker from {}",
+                marker.recorded_at
+            );
+            return None;
+        }
+        Some(marker)
+    }
+
+    /// Note that this launch is running a stack it started
+    pub fn record_session(&self) -> Result<(), String> {
+        let mut state = self.load();
+        state.running_session = Some(SessionMarker {
+            pid: std::process::id(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+        });
+        self.save(&state)
+    }
+
+    /// Clear the session record, once its stack is stopped or given up
+    pub fn clear_session(&self) -> Result<(), String> {
+        let mut state = self.load();
+        if state.running_session.take().is_none() {
+            return Ok(());
+        }
+        self.save(&state)
+    }
+
+    /// A session that started a stack and never stopped it; read at launch,
+    /// before this one starts anything
+    pub fn unfinished_session(&self) -> Option<SessionMarker> {
+        self.load().running_session
+    }
+}
+
+/// Restart the app, optionally leaving the stack running for the next launch
+/// to adopt. Refused while jobs are running unless `force` is set.
+#[tauri::command]
+pub async fn relaunch_app(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    keep_services: bool,
+    force: Option<bool>,
+) -> Result<(), String> {
+    let running = jobs.running_ids();
+    if !running.is_empty() && !force.unwrap_or(false) {
+        return Err(format!(
+            "Jobs still running ({}); cancel them or relaunch with force",
+            running.join(", ")
+        ));
+    }
+
+    if keep_services {
+        let setup = app_handle
+            .state::<ServiceManager>()
+            .active_setup
+            .lock()
+            .clone();
+        LaunchState::for_app(&app_handle)?.record_adopt(setup)?;
+    } else {
+        let service_manager = app_handle.state::<ServiceManager>();
+        let settings = app_handle.state::<SettingsStore>();
+        crate::stop_for_user(app_handle.clone(), service_manager, settings).await?;
+    }
+
+    info!(
+        "🔄 Relaunching Arbor{}",
+        if keep_services {
+            " (keeping services running)"
+        } else {
+            ""
+        }
+    );
+    // Goes through RunEvent::Exit, so background tasks still shut down cleanly
+    app_handle.request_restart();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    fn state(dir: &TempDir) -> LaunchState {
+        LaunchState::new(dir.path().join(LAUNCH_STATE_FILE_NAME))
+    }
+
+    #[test]
+    fn test_adopt_marker_is_taken_once() {
+        let tmp = temp_dir();
+        let state = state(&tmp);
+        assert_eq!(state.take_adopt(), None);
+
+        state.record_adopt(None).unwrap();
+        assert!(state.take_adopt().is_some());
+        assert_eq!(state.take_adopt(), None, "Marker is cleared once read");
+    }
+
+    #[test]
+    fn test_stale_adopt_marker_is_ignored() {
+        let tmp = temp_dir();
+        let state = state(&tmp);
+        let old = chrono::Utc::now() - chrono::Duration::hours(1);
+        state
+            .save(&LaunchStateFile {
+                adopt_running_stack: Some(AdoptMarker {
+                    setup: None,
+                    recorded_at: old.to_rfc3339(),
+                }),
+                ..LaunchStateFile::default()
+            })
+            .unwrap();
+
+        assert_eq!(state.take_adopt(), None);
+        assert_eq!(state.load(), LaunchStateFile::default());
+    }
+
+    #[test]
+    fn test_session_is_recorded_until_cleared() {
+        let tmp = temp_dir();
+        let state = state(&tmp);
+        assert_eq!(state.unfinished_session(), None);
+
+        state.record_adopt(None).unwrap();
+        state.record_session().unwrap();
+        let session = state.unfinished_session().unwrap();
+        assert_eq!(session.pid, std::process::id());
+        assert!(state.take_adopt().is_some());
+        assert_eq!(state.unfinished_session(), Some(session));
+
+        state.clear_session().unwrap();
+        assert_eq!(state.unfinished_session(), None);
+    }
+}
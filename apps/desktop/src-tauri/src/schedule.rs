@@ -0,0 +1,273 @@
+// Background task scheduling
+// Periodic tasks take their interval from the settings, re-read whenever the
+// settings change, and add random jitter so they don't all fire in lockstep
+// after launch. The effective schedules are reported with the task list.
+
+use crate::settings::AppSettings;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+/// Jitter added to each run, as a fraction of the interval
+const JITTER_FRACTION: f64 = 0.1;
+
+/// The periodic tasks and where their interval comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskKind {
+    /// Status refresh backing up the docker events subscription
+    StatusPoll,
+    /// `docker stats` sampling
+    StatsSample,
+    /// Restarting containers that stayed down after a crash
+    Watchdog,
+}
+
+impl TaskKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskKind::StatusPoll => "status_poll",
+            TaskKind::StatsSample => "stats_sample",
+            TaskKind::Watchdog => "watchdog",
+        }
+    }
+
+    fn interval(self, settings: &AppSettings) -> Duration {
+        let secs = match self {
+            TaskKind::StatusPoll => settings.background_intervals.status_poll_secs,
+            TaskKind::StatsSample => settings.background_intervals.stats_sample_secs,
+            TaskKind::Watchdog => settings.background_intervals.watchdog_poll_secs,
+        };
+        Duration::from_secs(secs)
+    }
+}
+
+/// Effective schedule of one task, as reported by `get_background_tasks`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskSchedule {
+    pub name: &'static str,
+    pub interval_secs: u64,
+    pub max_jitter_ms: u64,
+    /// Time until the next run, including this run's jitter
+    pub next_run_in_ms: u64,
+    pub runs: u64,
+    /// Runs are skipped while paused (safe mode)
+    pub paused: bool,
+}
+
+struct TaskState {
+    interval: Duration,
+    max_jitter: Duration,
+    next_run: Instant,
+    runs: u64,
+}
+
+/// Registry of the running periodic tasks
+#[derive(Clone, Default)]
+pub struct BackgroundTasks {
+    tasks: Arc<Mutex<BTreeMap<TaskKind, TaskState>>>,
+    paused: Arc<Mutex<BTreeSet<TaskKind>>>,
+}
+
+impl BackgroundTasks {
+    /// Skip a task's runs until unpaused; it stays scheduled meanwhile
+    pub fn set_paused(&self, kind: TaskKind, paused: bool) {
+        let mut paused_tasks = self.paused.lock().unwrap();
+        if paused {
+            paused_tasks.insert(kind);
+        } else {
+            paused_tasks.remove(&kind);
+        }
+    }
+
+    fn is_paused(&self, kind: TaskKind) -> bool {
+        self.paused.lock().unwrap().contains(&kind)
+    }
+
+    pub fn schedules(&self) -> Vec<TaskSchedule> {
+        let now = Instant::now();
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(kind, state)| TaskSchedule {
+                name: kind.name(),
+                interval_secs: state.interval.as_secs(),
+                max_jitter_ms: state.max_jitter.as_millis() as u64,
+                next_run_in_ms: state.next_run.saturating_duration_since(now).as_millis() as u64,
+                runs: state.runs,
+                paused: self.is_paused(*kind),
+            })
+            .collect()
+    }
+
+    /// Run `tick` every interval (plus jitter) until the settings go away
+    /// The first run is spread over a whole interval; a settings change
+    /// reschedules from that moment with the new interval.
+    pub async fn run_periodic<F, Fut>(
+        self,
+        kind: TaskKind,
+        mut settings: watch::Receiver<AppSettings>,
+        mut tick: F,
+    ) where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut first = true;
+        loop {
+            let interval = kind.interval(&settings.borrow_and_update());
+            let max_jitter = interval.mul_f64(JITTER_FRACTION);
+            let delay = if first {
+                random_up_to(interval)
+            } else {
+                interval + random_up_to(max_jitter)
+            };
+            self.plan(kind, interval, max_jitter, delay);
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {
+                    first = false;
+                    if !self.is_paused(kind) {
+                        tick().await;
+                        self.tasks.lock().unwrap().entry(kind).and_modify(|state| state.runs += 1);
+                    }
+                }
+                changed = settings.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn plan(&self, kind: TaskKind, interval: Duration, max_jitter: Duration, delay: Duration) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let state = tasks.entry(kind).or_insert(TaskState {
+            interval,
+            max_jitter,
+            next_run: Instant::now(),
+            runs: 0,
+        });
+        state.interval = interval;
+        state.max_jitter = max_jitter;
+        state.next_run = Instant::now() + delay;
+    }
+}
+
+fn random_up_to(max: Duration) -> Duration {
+    let millis = max.as_millis() as u64;
+    Duration::from_millis(rand::rng().random_range(0..=millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsStore;
+    use crate::test_support::temp_dir;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tempfile::TempDir;
+
+    fn temp_store(dir: &TempDir) -> SettingsStore {
+        SettingsStore::load(dir.path().join("settings.json"))
+    }
+
+    fn spawn_counter(
+        tasks: &BackgroundTasks,
+        store: &SettingsStore,
+    ) -> (Arc<AtomicU64>, tokio::task::JoinHandle<()>) {
+        let count = Arc::new(AtomicU64::new(0));
+        let counter = count.clone();
+        let handle = tokio::spawn(tasks.clone().run_periodic(
+            TaskKind::StatusPoll,
+            store.subscribe(),
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async {}
+            },
+        ));
+        (count, handle)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runs_within_interval_plus_jitter() {
+        let tmp = temp_dir();
+        let store = temp_store(&tmp);
+        store
+            .set(
+                "background_intervals",
+                serde_json::json!({ "status_poll_secs": 10 }),
+            )
+            .unwrap();
+        let tasks = BackgroundTasks::default();
+        let (count, handle) = spawn_counter(&tasks, &store);
+
+        // First run lands somewhere within one interval
+        tokio::time::sleep(Duration::from_secs(10) + Duration::from_millis(1)).await;
+        assert!(count.load(Ordering::SeqCst) >= 1);
+
+        let schedule = &tasks.schedules()[0];
+        assert_eq!(schedule.name, "status_poll");
+        assert_eq!(schedule.interval_secs, 10);
+        assert_eq!(schedule.max_jitter_ms, 1000);
+        assert!(schedule.next_run_in_ms <= 11_000);
+
+        // Later runs at most interval + jitter apart
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert!(count.load(Ordering::SeqCst) >= 2);
+
+        handle.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paused_task_skips_runs() {
+        let tmp = temp_dir();
+        let store = temp_store(&tmp);
+        let tasks = BackgroundTasks::default();
+        tasks.set_paused(TaskKind::StatusPoll, true);
+        let (count, handle) = spawn_counter(&tasks, &store);
+
+        let interval = TaskKind::StatusPoll.interval(&store.get());
+        tokio::time::sleep(interval * 3).await;
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        assert!(tasks.schedules()[0].paused);
+
+        tasks.set_paused(TaskKind::StatusPoll, false);
+        tokio::time::sleep(interval * 2).await;
+        assert!(count.load(Ordering::SeqCst) >= 1);
+
+        handle.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tightened_interval_is_picked_up_at_runtime() {
+        let tmp = temp_dir();
+        let store = temp_store(&tmp);
+        store
+            .set(
+                "background_intervals",
+                serde_json::json!({ "status_poll_secs": 600 }),
+            )
+            .unwrap();
+        let tasks = BackgroundTasks::default();
+        let (count, handle) = spawn_counter(&tasks, &store);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        store
+            .set(
+                "background_intervals",
+                serde_json::json!({ "status_poll_secs": 5 }),
+            )
+            .unwrap();
+
+        // Without a reschedule this would wait for the 600s interval
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        assert!(count.load(Ordering::SeqCst) >= 1);
+        assert_eq!(tasks.schedules()[0].interval_secs, 5);
+
+        handle.abort();
+    }
+}
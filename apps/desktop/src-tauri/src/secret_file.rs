@@ -0,0 +1,451 @@
+// Encrypted secrets file
+// Where no OS keychain is reachable (a headless Linux box without a secret
+// service), secrets are kept in a file in the app data dir instead. The file
+// holds them as one AES-256-GCM sealed JSON map, under a key derived from the
+// user's passphrase with Argon2id; the salt and KDF costs are stored beside
+// it. The store starts locked: `unlock_fallback_store` derives the key and
+// opens the file, or creates it on first use. Every change re-seals the
+// whole map with a fresh nonce. The key and decrypted secrets are wiped from
+// memory once dropped.
+
+use crate::keyring::{KeyringError, SecretStore};
+use crate::messages::{Coded, Message};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose, Engine as _};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zeroize::{Zeroize, Zeroizing};
+
+pub const SECRET_FILE_NAME: &str = "secrets.enc.json";
+
+const FORMAT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Highest Argon2id costs a file may ask for, far above what `KdfParams`
+/// defaults to, so a tampered file can't make unlocking exhaust memory or hang
+const MAX_KDF: KdfParams = KdfParams {
+    m_cost: 1024 * 1024,
+    t_cost: 16,
+    p_cost: 16,
+};
+
+/// Argon2id costs; stored in the file so it can be opened with the costs it
+/// was sealed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// KiB
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedFile {
+    version: u32,
+    kdf: KdfParams,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Why the file couldn't be unlocked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UnlockError {
+    /// The file opened but the passphrase doesn't decrypt it
+    WrongPassphrase,
+    EmptyPassphrase,
+    /// The OS keychain is in use, so there is no file to unlock
+    NotInUse,
+    /// The file isn't one we wrote, or was damaged
+    Corrupt {
+        detail: String,
+    },
+    Io {
+        detail: String,
+    },
+    /// Refused before trying, e.g. without the frontend's handshake
+    Rejected {
+        detail: String,
+    },
+}
+
+impl fmt::Display for UnlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnlockError::WrongPassphrase => write!(f, "Wrong passphrase for the secrets file"),
+            UnlockError::EmptyPassphrase => write!(f, "The passphrase can't be empty"),
+            UnlockError::NotInUse => {
+                write!(
+                    f,
+                    "The OS keychain is in use; there is no secrets file to unlock"
+                )
+            }
+            UnlockError::Corrupt { detail } => write!(f, "The secrets file is damaged: {}", detail),
+            UnlockError::Io { detail } => {
+                write!(f, "Failed to access the secrets file: {}", detail)
+            }
+            UnlockError::Rejected { detail } => write!(f, "{}", detail),
+        }
+    }
+}
+
+impl Coded for UnlockError {
+    fn message(&self) -> Message {
+        match self {
+            UnlockError::WrongPassphrase => Message::new("secret-file-wrong-passphrase"),
+            UnlockError::EmptyPassphrase => Message::new("secret-file-empty-passphrase"),
+            UnlockError::NotInUse => Message::new("secret-file-not-in-use"),
+            UnlockError::Corrupt { detail } => {
+                Message::new("secret-file-corrupt").with_param("detail", detail)
+            }
+            UnlockError::Io { detail } => {
+                Message::new("secret-file-io").with_param("detail", detail)
+            }
+            UnlockError::Rejected { detail } => {
+                Message::new("secret-file-rejected").with_param("detail", detail)
+            }
+        }
+    }
+}
+
+/// Wiped from memory when dropped, like the plaintext it was read from
+struct Unlocked {
+    key: Zeroizing<[u8; 32]>,
+    salt: [u8; SALT_LEN],
+    kdf: KdfParams,
+    secrets: BTreeMap<String, String>,
+}
+
+impl Drop for Unlocked {
+    fn drop(&mut self) {
+        wipe(&mut self.secrets);
+    }
+}
+
+fn wipe(secrets: &mut BTreeMap<String, String>) {
+    for value in secrets.values_mut() {
+        value.zeroize();
+    }
+}
+
+/// Secrets sealed in a file, readable once unlocked with the passphrase
+pub struct FileSecrets {
+    path: PathBuf,
+    /// For a file created on first unlock
+    kdf: KdfParams,
+    unlocked: Mutex<Option<Unlocked>>,
+}
+
+impl FileSecrets {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            kdf: KdfParams::default(),
+            unlocked: Mutex::new(None),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_kdf(mut self, kdf: KdfParams) -> Self {
+        self.kdf = kdf;
+        self
+    }
+
+    /// A passphrase was set, so unlocking checks it rather than setting it
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.unlocked.lock().unwrap().is_some()
+    }
+
+    /// Open the file with `passphrase`, or create it sealed with it
+    /// Blocking: the key derivation takes a good part of a second.
+    pub fn unlock(&self, passphrase: &str) -> Result<(), UnlockError> {
+        if passphrase.is_empty() {
+            return Err(UnlockError::EmptyPassphrase);
+        }
+        let unlocked = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => open(&contents, passphrase)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let salt: [u8; SALT_LEN] = rand::rng().random();
+                let unlocked = Unlocked {
+                    key: derive_key(passphrase, &salt, self.kdf)?,
+                    salt,
+                    kdf: self.kdf,
+                    secrets: BTreeMap::new(),
+                };
+                // Written now, so the passphrase is fixed by the first unlock
+                seal(&self.path, &unlocked).map_err(|detail| UnlockError::Io { detail })?;
+                unlocked
+            }
+            Err(e) => {
+                return Err(UnlockError::Io {
+                    detail: e.to_string(),
+                })
+            }
+        };
+        *self.unlocked.lock().unwrap() = Some(unlocked);
+        Ok(())
+    }
+
+    /// An error to show instead of treating a locked file's secrets as missing
+    pub fn ensure_unlocked(&self) -> Result<(), KeyringError> {
+        match self.is_unlocked() {
+            true => Ok(()),
+            false => Err(locked()),
+        }
+    }
+
+    fn update(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, String>),
+    ) -> Result<(), KeyringError> {
+        let mut unlocked = self.unlocked.lock().unwrap();
+        let unlocked = unlocked.as_mut().ok_or_else(locked)?;
+        let mut secrets = unlocked.secrets.clone();
+        change(&mut secrets);
+        let mut previous = std::mem::replace(&mut unlocked.secrets, secrets);
+        let sealed = seal(&self.path, unlocked);
+        // Keep memory and disk in step when the write fails
+        if sealed.is_err() {
+            std::mem::swap(&mut unlocked.secrets, &mut previous);
+        }
+        wipe(&mut previous);
+        sealed.map_err(|detail| KeyringError::Io { detail })
+    }
+}
+
+fn locked() -> KeyringError {
+    KeyringError::AccessDenied {
+        detail: "The secrets file is locked; unlock it with your passphrase first".to_string(),
+    }
+}
+
+impl SecretStore for FileSecrets {
+    fn set(&self, name: &str, value: &str) -> Result<(), KeyringError> {
+        self.update(|secrets| {
+            secrets.insert(name.to_string(), value.to_string());
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<Option<String>, KeyringError> {
+        let unlocked = self.unlocked.lock().unwrap();
+        let unlocked = unlocked.as_ref().ok_or_else(locked)?;
+        Ok(unlocked.secrets.get(name).cloned())
+    }
+
+    fn delete(&self, name: &str) -> Result<(), KeyringError> {
+        self.update(|secrets| {
+            secrets.remove(name);
+        })
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    kdf: KdfParams,
+) -> Result<Zeroizing<[u8; 32]>, UnlockError> {
+    if kdf.m_cost > MAX_KDF.m_cost || kdf.t_cost > MAX_KDF.t_cost || kdf.p_cost > MAX_KDF.p_cost {
+        return Err(UnlockError::Corrupt {
+            detail: format!("key derivation costs {:?} exceed {:?}", kdf, MAX_KDF),
+        });
+    }
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32)).map_err(|e| {
+        UnlockError::Corrupt {
+            detail: format!("invalid key derivation costs: {}", e),
+        }
+    })?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| UnlockError::Corrupt {
+            detail: format!("key derivation failed: {}", e),
+        })?;
+    Ok(key)
+}
+
+fn open(contents: &str, passphrase: &str) -> Result<Unlocked, UnlockError> {
+    let corrupt = |detail: String| UnlockError::Corrupt { detail };
+    let file: SealedFile = serde_json::from_str(contents).map_err(|e| corrupt(e.to_string()))?;
+    if file.version != FORMAT_VERSION {
+        return Err(corrupt(format!("unknown format version {}", file.version)));
+    }
+    let decode = |field: &str, value: &str| {
+        general_purpose::STANDARD
+            .decode(value)
+            .map_err(|e| corrupt(format!("{} is not valid base64: {}", field, e)))
+    };
+    let salt: [u8; SALT_LEN] = decode("salt", &file.salt)?
+        .try_into()
+        .map_err(|_| corrupt("salt has the wrong length".to_string()))?;
+    let nonce = decode("nonce", &file.nonce)?;
+    if nonce.len() != NONCE_LEN {
+        return Err(corrupt("nonce has the wrong length".to_string()));
+    }
+    let ciphertext = decode("ciphertext", &file.ciphertext)?;
+
+    let key = derive_key(passphrase, &salt, file.kdf)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_slice()));
+    // The tag covers the whole map, so a failure here is the passphrase
+    // (or tampering, which we can't tell apart)
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map(Zeroizing::new)
+        .map_err(|_| UnlockError::WrongPassphrase)?;
+    let secrets =
+        serde_json::from_slice(&plaintext).map_err(|e| corrupt(format!("secrets: {}", e)))?;
+    Ok(Unlocked {
+        key,
+        salt,
+        kdf: file.kdf,
+        secrets,
+    })
+}
+
+fn seal(path: &Path, unlocked: &Unlocked) -> Result<(), String> {
+    let plaintext =
+        Zeroizing::new(serde_json::to_vec(&unlocked.secrets).map_err(|e| e.to_string())?);
+    let nonce: [u8; NONCE_LEN] = rand::rng().random();
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(unlocked.key.as_slice()));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+        .map_err(|e| format!("Failed to encrypt the secrets file: {}", e))?;
+    let file = SealedFile {
+        version: FORMAT_VERSION,
+        kdf: unlocked.kdf,
+        salt: general_purpose::STANDARD.encode(unlocked.salt),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(ciphertext),
+    };
+    let contents = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create the secrets file directory: {}", e))?;
+    }
+    // Written aside and renamed, so a crash never leaves half a file
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, contents)
+        .and_then(|()| restrict(&partial))
+        .and_then(|()| std::fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to save the secrets file: {}", e))
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    // Cheap, so the tests don't spend seconds deriving keys
+    const TEST_KDF: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn temp_file(dir: &TempDir) -> PathBuf {
+        dir.path().join(SECRET_FILE_NAME)
+    }
+
+    #[test]
+    fn test_secrets_survive_reopening_with_the_passphrase() {
+        let tmp = temp_dir();
+        let path = temp_file(&tmp);
+        let store = FileSecrets::new(path.clone()).with_kdf(TEST_KDF);
+        assert!(!store.exists());
+        assert!(store.get("master_encryption_key").is_err(), "locked");
+        assert!(store.set("master_encryption_key", "key").is_err(), "locked");
+
+        store.unlock("correct horse").unwrap();
+        assert!(store.exists());
+        store.set("master_encryption_key", "key").unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("master_encryption_key"));
+
+        let reopened = FileSecrets::new(path.clone());
+        assert!(!reopened.is_unlocked());
+        reopened.unlock("correct horse").unwrap();
+        assert_eq!(reopened.get("master_encryption_key").unwrap(), "key");
+        reopened.delete("master_encryption_key").unwrap();
+        assert!(reopened.get("master_encryption_key").is_err());
+    }
+
+    #[test]
+    fn test_wrong_passphrase_is_told_apart_from_damage() {
+        let tmp = temp_dir();
+        let path = temp_file(&tmp);
+        let store = FileSecrets::new(path.clone()).with_kdf(TEST_KDF);
+        store.unlock("correct horse").unwrap();
+        store.set("master_encryption_key", "key").unwrap();
+
+        let reopened = FileSecrets::new(path.clone());
+        assert_eq!(
+            reopened.unlock("battery staple"),
+            Err(UnlockError::WrongPassphrase)
+        );
+        assert!(!reopened.is_unlocked());
+        assert_eq!(reopened.unlock(""), Err(UnlockError::EmptyPassphrase));
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(
+            reopened.unlock("correct horse"),
+            Err(UnlockError::Corrupt { .. })
+        ));
+    }
+
+    #[test]
+    fn test_excessive_kdf_costs_are_refused() {
+        let tmp = temp_dir();
+        let path = temp_file(&tmp);
+        FileSecrets::new(path.clone())
+            .with_kdf(TEST_KDF)
+            .unlock("correct horse")
+            .unwrap();
+        let mut file: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        file["kdf"]["m_cost"] = serde_json::json!(u32::MAX);
+        std::fs::write(&path, file.to_string()).unwrap();
+
+        let error = FileSecrets::new(path.clone())
+            .unlock("correct horse")
+            .unwrap_err();
+        assert!(
+            matches!(&error, UnlockError::Corrupt { detail } if detail.contains("exceed")),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,1784 @@
+// Compose stack description
+// Which compose files make up the arbor stack, how dev mode adds the override
+// file and generated source mounts to them, and drift detection between
+// running containers and the files on disk.
+
+use crate::compat;
+use crate::docker::{self, ComposeNetwork, ContainerStatus, COMPOSE_PROJECT, PROJECT_LABEL};
+use crate::lifecycle::StopReason;
+use crate::messages::{Coded, Message};
+use crate::process::{CommandRunner, CommandSpec};
+use crate::profiles;
+use crate::settings::{self, AppSettings};
+use crate::tools::{self, ContainerRuntime, LocatedTool, Tool};
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Compose files the Makefile's `up` target runs with, relative to the project root
+pub const COMPOSE_FILES: [&str; 2] = [
+    "apps/api/docker-compose.yml",
+    "apps/key-value-store/docker-compose.yml",
+];
+/// Generated by the traefik setup script; only part of the stack when present
+pub const TRAEFIK_COMPOSE_FILE: &str = "tmp/traefik/local/arbor-docker-compose.traefik.yml";
+const DEFAULT_OVERRIDE_FILE: &str = "docker-compose.override.yml";
+/// Project-level arbor configuration, relative to the project root
+pub const PROJECT_CONFIG_FILE: &str = "arbor.toml";
+/// Emitted at startup when a stack Arbor didn't start is already running,
+/// with an AdoptionReport payload
+pub const ADOPTABLE_STACK_EVENT: &str = "adoptable-stack-found";
+/// Emitted with the StackState whenever it changes
+pub const STACK_STATE_EVENT: &str = "stack-state";
+/// Emitted with a ServiceStatus once a single service was started, stopped
+/// or restarted
+pub const SERVICE_STATUS_EVENT: &str = "service-status";
+/// Emitted with a Restarting when `restart_services` begins
+pub const SERVICES_RESTARTING_EVENT: &str = "services-restarting";
+/// Emitted with a Restarted once `restart_services` has started the stack
+pub const SERVICES_RESTARTED_EVENT: &str = "services-restarted";
+/// Generated in the app data dir from the dev mode source mounts
+const MOUNTS_OVERRIDE_FILE: &str = "source-mounts.override.yml";
+// Generated into the app data dir when a compose subnet is pinned
+const NETWORK_OVERRIDE_FILE: &str = "network.override.yml";
+// Generated into the app data dir, labelling containers with the app version
+const VERSION_OVERRIDE_FILE: &str = "app-version.override.yml";
+
+// Make file watchers inside the container notice changes on bind mounts
+// (inotify events don't cross the Docker Desktop VM boundary)
+const RELOAD_ENV: [(&str, &str); 2] = [("CHOKIDAR_USEPOLLING", "true"), ("WATCHPACK_POLLING", "true")];
+
+/// A host source directory bind-mounted into a service container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMount {
+    pub service: String,
+    pub host_path: PathBuf,
+    pub container_path: PathBuf,
+}
+
+/// What brings the stack up and down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceBackend {
+    /// The Makefile's `up` and `down`, which also run the migrations
+    #[default]
+    Make,
+    /// `docker compose` on the compose files, where make isn't installed
+    Compose,
+}
+
+impl ServiceBackend {
+    /// make, unless the tools have been resolved and it wasn't found, or the
+    /// runtime is podman (the Makefile calls docker itself)
+    pub fn detect(located: &[LocatedTool], runtime: ContainerRuntime) -> Self {
+        match located.iter().find(|tool| tool.tool == Tool::Make) {
+            _ if runtime == ContainerRuntime::Podman => ServiceBackend::Compose,
+            Some(make) if make.path.is_none() => ServiceBackend::Compose,
+            _ => ServiceBackend::Make,
+        }
+    }
+
+    /// The command as logged, e.g. `make down`
+    pub fn describe(self, action: &str) -> String {
+        match self {
+            ServiceBackend::Make => format!("make {}", action),
+            ServiceBackend::Compose => format!("docker compose {}", action),
+        }
+    }
+}
+
+/// The compose configuration a stack is (or would be) started with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComposeSetup {
+    pub dev_mode: bool,
+    /// Absolute path of the override file, only ever set in dev mode
+    pub override_file: Option<PathBuf>,
+    /// Source mounts, only ever set in dev mode
+    pub source_mounts: Vec<SourceMount>,
+    mounts_override_file: PathBuf,
+    /// Subnet pinned for the default compose network
+    #[serde(default)]
+    pub subnet: Option<String>,
+    #[serde(default)]
+    network_override_file: PathBuf,
+    /// Services to start, every service when empty (safe mode starts only
+    /// the essential ones)
+    #[serde(default)]
+    pub only_services: Vec<String>,
+    /// Services the project's compose files declare, labelled with the app
+    /// version that starts them
+    #[serde(default)]
+    pub labelled_services: Vec<String>,
+    #[serde(default)]
+    version_override_file: PathBuf,
+    /// Compose project of the profile the stack runs as
+    #[serde(default = "default_project")]
+    pub project: String,
+    /// The profile's env file, passed to compose once it exists
+    #[serde(default)]
+    env_file: Option<PathBuf>,
+    #[serde(default)]
+    pub backend: ServiceBackend,
+    /// `service_env` from settings, set on every make and compose run
+    #[serde(default)]
+    pub service_env: BTreeMap<String, String>,
+    /// Compose profile the stack runs with, besides the services without one
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+fn default_project() -> String {
+    COMPOSE_PROJECT.to_string()
+}
+
+impl ComposeSetup {
+    pub fn from_settings(settings: &AppSettings, project_root: &Path, data_dir: &Path) -> Self {
+        // Compose only picks up docker-compose.override.yml implicitly when no
+        // -f is given; we always pass -f, so end users never get it by accident
+        let override_file = settings.dev_mode.then(|| {
+            let path = settings
+                .compose_override_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_OVERRIDE_FILE));
+            project_root.join(path)
+        });
+
+        let source_mounts = if settings.dev_mode {
+            settings
+                .source_mounts
+                .iter()
+                .flat_map(|(service, paths)| {
+                    paths.iter().map(|(host_path, container_path)| SourceMount {
+                        service: service.clone(),
+                        host_path: host_path.clone(),
+                        container_path: container_path.clone(),
+                    })
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            dev_mode: settings.dev_mode,
+            override_file,
+            source_mounts,
+            mounts_override_file: data_dir.join(MOUNTS_OVERRIDE_FILE),
+            subnet: settings.compose_subnet.clone(),
+            network_override_file: data_dir.join(NETWORK_OVERRIDE_FILE),
+            only_services: Vec::new(),
+            labelled_services: declared_services(project_root),
+            version_override_file: data_dir.join(VERSION_OVERRIDE_FILE),
+            project: profiles::compose_project(),
+            env_file: Some(profiles::env_file(data_dir, &profiles::active_name())),
+            backend: ServiceBackend::detect(&tools::located(), tools::runtime()),
+            service_env: settings.service_env.clone(),
+            profile: settings.service_profile.clone(),
+        }
+    }
+
+    /// Validate the dev mode files and (re)generate the generated overrides
+    /// A stale generated override is removed when its setting is off, so
+    /// disabling it takes effect on the next start.
+    pub fn prepare(&self) -> Result<(), String> {
+        if let Some(path) = &self.override_file {
+            if !path.is_file() {
+                return Err(format!(
+                    "Dev mode is enabled but the compose override file {:?} does not exist",
+                    path
+                ));
+            }
+        }
+        self.prepare_network_override()?;
+        self.prepare_version_override()?;
+
+        if self.source_mounts.is_empty() {
+            return match std::fs::remove_file(&self.mounts_override_file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!(
+                    "Failed to remove generated source mount override: {}",
+                    e
+                )),
+                _ => Ok(()),
+            };
+        }
+
+        let mut by_service: HashMap<String, BTreeMap<PathBuf, PathBuf>> = HashMap::new();
+        for mount in &self.source_mounts {
+            by_service
+                .entry(mount.service.clone())
+                .or_default()
+                .insert(mount.host_path.clone(), mount.container_path.clone());
+        }
+        settings::validate_source_mounts(&by_service.into_iter().collect())?;
+
+        if let Some(dir) = self.mounts_override_file.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        }
+        std::fs::write(&self.mounts_override_file, self.render_mounts_override())
+            .map_err(|e| format!("Failed to write generated source mount override: {}", e))
+    }
+
+    fn prepare_network_override(&self) -> Result<(), String> {
+        let Some(subnet) = &self.subnet else {
+            return match std::fs::remove_file(&self.network_override_file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!(
+                    "Failed to remove generated network override: {}",
+                    e
+                )),
+                _ => Ok(()),
+            };
+        };
+        let document = serde_json::json!({
+            "networks": { "default": { "ipam": { "config": [{ "subnet": subnet }] } } }
+        });
+        if let Some(dir) = self.network_override_file.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        }
+        let contents =
+            serde_json::to_string_pretty(&document).expect("override document is serializable");
+        std::fs::write(&self.network_override_file, contents)
+            .map_err(|e| format!("Failed to write generated network override: {}", e))
+    }
+
+    fn prepare_version_override(&self) -> Result<(), String> {
+        if self.labelled_services.is_empty() {
+            return match std::fs::remove_file(&self.version_override_file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!(
+                    "Failed to remove generated version override: {}",
+                    e
+                )),
+                _ => Ok(()),
+            };
+        }
+        let labels = serde_json::json!({ "labels": { compat::APP_VERSION_LABEL: compat::app_version() } });
+        let services: serde_json::Map<String, serde_json::Value> = self
+            .labelled_services
+            .iter()
+            .map(|service| (service.clone(), labels.clone()))
+            .collect();
+        if let Some(dir) = self.version_override_file.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        }
+        let contents = serde_json::to_string_pretty(&serde_json::json!({ "services": services }))
+            .expect("override document is serializable");
+        std::fs::write(&self.version_override_file, contents)
+            .map_err(|e| format!("Failed to write generated version override: {}", e))
+    }
+
+    /// Compose override (JSON is valid YAML) adding bind mounts and reload env
+    fn render_mounts_override(&self) -> String {
+        let mut services = serde_json::Map::new();
+        for mount in &self.source_mounts {
+            let service = services
+                .entry(mount.service.clone())
+                .or_insert_with(|| {
+                    serde_json::json!({
+                        "volumes": [],
+                        "environment": RELOAD_ENV.iter().cloned().collect::<BTreeMap<_, _>>(),
+                    })
+                });
+            service["volumes"]
+                .as_array_mut()
+                .expect("volumes is always an array")
+                .push(serde_json::json!({
+                    "type": "bind",
+                    "source": mount.host_path,
+                    "target": mount.container_path,
+                }));
+        }
+
+        let document = serde_json::json!({ "services": services });
+        serde_json::to_string_pretty(&document).expect("override document is serializable")
+    }
+
+    /// `docker compose` arguments selecting the profile's project, env file,
+    /// compose profile and the compose files
+    pub fn compose_args(&self, project_root: &Path) -> Vec<String> {
+        let env_file = self
+            .env_file()
+            .into_iter()
+            .flat_map(|file| ["--env-file".to_string(), file.display().to_string()]);
+        let profile = self
+            .profile
+            .iter()
+            .flat_map(|profile| ["--profile".to_string(), profile.clone()]);
+        ["compose", "-p", &self.project]
+            .into_iter()
+            .map(str::to_string)
+            .chain(env_file)
+            .chain(profile)
+            .chain(self.file_args(project_root))
+            .collect()
+    }
+
+    /// `docker compose` with `compose_args` and `service_env`, for the
+    /// subcommand to be added
+    pub fn compose_command(&self, project_root: &Path) -> CommandSpec {
+        CommandSpec::new("docker")
+            .args(self.compose_args(project_root))
+            .envs(&self.service_env)
+    }
+
+    /// `-f` arguments for compose invocations made directly by the app
+    pub fn file_args(&self, project_root: &Path) -> Vec<String> {
+        let traefik = project_root.join(TRAEFIK_COMPOSE_FILE);
+        COMPOSE_FILES
+            .iter()
+            .map(|file| project_root.join(file))
+            .chain(traefik.is_file().then_some(traefik))
+            .chain(self.override_file.clone())
+            .chain(self.mounts_override())
+            .chain(self.network_override())
+            .chain(self.version_override())
+            .flat_map(|file| ["-f".to_string(), file.display().to_string()])
+            .collect()
+    }
+
+    /// Variables passed to make so the Makefile's compose calls use the same files
+    pub fn make_vars(&self) -> Vec<String> {
+        let override_var = self
+            .override_file
+            .iter()
+            .map(|file| format!("COMPOSE_OVERRIDE={}", file.display()));
+        let mounts_var = self
+            .mounts_override()
+            .map(|file| format!("COMPOSE_MOUNTS_OVERRIDE={}", file.display()));
+        let network_var = self
+            .network_override()
+            .map(|file| format!("COMPOSE_NETWORK_OVERRIDE={}", file.display()));
+        let version_var = self
+            .version_override()
+            .map(|file| format!("COMPOSE_VERSION_OVERRIDE={}", file.display()));
+        let services_var = (!self.only_services.is_empty())
+            .then(|| format!("COMPOSE_SERVICES={}", self.only_services.join(" ")));
+        let project_var = (self.project != COMPOSE_PROJECT)
+            .then(|| format!("COMPOSE_PROJECT_NAME={}", self.project));
+        let env_file_var = self
+            .env_file()
+            .map(|file| format!("COMPOSE_ENV_FILE={}", file.display()));
+        override_var
+            .chain(mounts_var)
+            .chain(network_var)
+            .chain(version_var)
+            .chain(services_var)
+            .chain(project_var)
+            .chain(env_file_var)
+            .collect()
+    }
+
+    /// Environment for make: `service_env`, and `COMPOSE_PROFILES` for the
+    /// Makefile's compose calls
+    pub fn make_env(&self) -> BTreeMap<String, String> {
+        let mut env = self.service_env.clone();
+        if let Some(profile) = &self.profile {
+            env.insert("COMPOSE_PROFILES".to_string(), profile.clone());
+        }
+        env
+    }
+
+    /// The command that brings the stack up, with `backend`
+    pub fn up_command(&self, project_root: &Path) -> CommandSpec {
+        let spec = match self.backend {
+            ServiceBackend::Make => CommandSpec::new("make")
+                .args(["up"])
+                .args(self.make_vars())
+                .envs(self.make_env()),
+            ServiceBackend::Compose => self
+                .compose_command(project_root)
+                .args(["up", "-d", "--wait", "--remove-orphans"])
+                .args(self.only_services.iter().cloned()),
+        };
+        spec.current_dir(project_root)
+    }
+
+    /// The command that brings the stack down, with `backend`
+    pub fn down_command(&self, project_root: &Path) -> CommandSpec {
+        let spec = match self.backend {
+            ServiceBackend::Make => CommandSpec::new("make")
+                .args(["down"])
+                .args(self.make_vars())
+                .envs(self.make_env()),
+            ServiceBackend::Compose => self
+                .compose_command(project_root)
+                .args(["down", "--remove-orphans"]),
+        };
+        spec.current_dir(project_root)
+    }
+
+    /// Files whose edits can change what the stack should be running
+    /// Includes files that may not exist yet, so creating them is noticed.
+    pub fn watched_files(&self, project_root: &Path) -> Vec<PathBuf> {
+        COMPOSE_FILES
+            .iter()
+            .chain([&TRAEFIK_COMPOSE_FILE, &PROJECT_CONFIG_FILE])
+            .map(|file| project_root.join(file))
+            .chain(self.override_file.clone())
+            .collect()
+    }
+
+    fn mounts_override(&self) -> Option<PathBuf> {
+        (!self.source_mounts.is_empty()).then(|| self.mounts_override_file.clone())
+    }
+
+    fn network_override(&self) -> Option<PathBuf> {
+        self.subnet.as_ref().map(|_| self.network_override_file.clone())
+    }
+
+    fn env_file(&self) -> Option<PathBuf> {
+        self.env_file.clone().filter(|file| file.is_file())
+    }
+
+    // Only once `prepare` has written it, so compose is never handed a missing file
+    fn version_override(&self) -> Option<PathBuf> {
+        (!self.labelled_services.is_empty() && self.version_override_file.is_file())
+            .then(|| self.version_override_file.clone())
+    }
+}
+
+/// Services declared by the project's compose files, read without compose
+/// The files are the project's own and plain; the keys one level under the
+/// top-level `services:` are the service names. The traefik file's services
+/// are left out: the Makefile's `down` doesn't pass that file, and labels
+/// for services compose can't find would fail it.
+fn declared_services(project_root: &Path) -> Vec<String> {
+    let mut services: Vec<String> = COMPOSE_FILES
+        .iter()
+        .filter_map(|file| std::fs::read_to_string(project_root.join(file)).ok())
+        .flat_map(|contents| parse_service_names(&contents))
+        .collect();
+    services.sort();
+    services.dedup();
+    services
+}
+
+fn parse_service_names(yaml: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut in_services = false;
+    let mut indent = None;
+    for line in yaml.lines() {
+        let content = line.split(" #").next().unwrap_or_default().trim_end();
+        if content.trim().is_empty() || content.trim_start().starts_with('#') {
+            continue;
+        }
+        let depth = content.len() - content.trim_start().len();
+        if depth == 0 {
+            in_services = content == "services:";
+            indent = None;
+            continue;
+        }
+        if !in_services || *indent.get_or_insert(depth) != depth {
+            continue;
+        }
+        if let Some(name) = content.trim().strip_suffix(':') {
+            names.push(name.trim_matches(|c| c == '"' || c == '\'').to_string());
+        }
+    }
+    names
+}
+
+/// What `get_service_state` reports about the stack's configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceState {
+    pub dev_mode: bool,
+    pub compose_override: Option<String>,
+    /// The settings changed since the running stack was started
+    pub restart_required: bool,
+    /// Services whose running container no longer matches the compose files
+    pub drifted_services: Vec<String>,
+    /// Host source directories mounted into the running containers
+    pub source_mounts: Vec<SourceMount>,
+    pub stack: StackState,
+    /// Quitting stops the stack; not for an adopted one, unless
+    /// `stop_adopted_on_exit` is set
+    pub stops_on_exit: bool,
+    /// Only the essential services were started, for troubleshooting
+    pub safe_mode: bool,
+    /// The stack's compose networks and any subnet conflicts with host routes
+    pub networks: Vec<ComposeNetwork>,
+    /// Who last stopped or restarted services, and when
+    pub last_stop_reason: Option<StopReason>,
+}
+
+/// Whether the stack is running, and whether Arbor owns it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum StackState {
+    #[default]
+    Stopped,
+    /// `make up` is being prepared or spawned
+    Starting,
+    /// `adopted`: started outside Arbor and only watched, so lifecycle
+    /// actions are refused until the user takes ownership
+    Running { adopted: bool },
+    /// `make down` is running
+    Stopping,
+}
+
+/// Why a start or stop can't begin: the stack is already in this state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StackConflict {
+    Starting,
+    Running,
+    Stopping,
+}
+
+impl fmt::Display for StackConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackConflict::Starting => write!(f, "Services are already starting"),
+            StackConflict::Running => write!(f, "Services are already running"),
+            StackConflict::Stopping => write!(f, "Services are stopping; wait for them to stop"),
+        }
+    }
+}
+
+impl Coded for StackConflict {
+    fn message(&self) -> Message {
+        match self {
+            StackConflict::Starting => Message::new("stack-already-starting"),
+            StackConflict::Running => Message::new("stack-already-running"),
+            StackConflict::Stopping => Message::new("stack-already-stopping"),
+        }
+    }
+}
+
+impl StackState {
+    /// Refuse to act while a start or stop is underway
+    pub fn settled(&self) -> Result<(), StackConflict> {
+        match self {
+            StackState::Starting => Err(StackConflict::Starting),
+            StackState::Stopping => Err(StackConflict::Stopping),
+            StackState::Stopped | StackState::Running { .. } => Ok(()),
+        }
+    }
+
+    /// The state while a start runs; only a stopped stack can be started
+    pub fn start(&self) -> Result<StackState, StackConflict> {
+        self.settled()?;
+        match self {
+            StackState::Running { .. } => Err(StackConflict::Running),
+            _ => Ok(StackState::Starting),
+        }
+    }
+
+    /// The state while a stop runs; a stopped stack can still be brought
+    /// down, to clean up containers left behind
+    pub fn stop(&self) -> Result<StackState, StackConflict> {
+        self.settled()?;
+        Ok(StackState::Stopping)
+    }
+
+    pub fn ensure_owned(&self, action: &str) -> Result<(), String> {
+        match self {
+            StackState::Running { adopted: true } => Err(format!(
+                "Can't {}: the running stack was adopted, not started by Arbor; take ownership first",
+                action
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether quitting stops the stack: an adopted one only with
+    /// `stop_adopted_on_exit`
+    pub fn stops_on_exit(&self, stop_adopted_on_exit: bool) -> bool {
+        match self {
+            StackState::Running { adopted: true } => stop_adopted_on_exit,
+            _ => true,
+        }
+    }
+}
+
+/// A container of a stack Arbor didn't start
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunningService {
+    pub service: String,
+    pub container: String,
+    pub image: String,
+    /// Published host ports
+    pub ports: Vec<u16>,
+}
+
+/// Ways a running stack differs from the compose config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AdoptionWarning {
+    MissingService { service: String },
+    UnexpectedService { service: String },
+    ImageMismatch { service: String, expected: String, running: String },
+    PortMismatch { service: String, expected: Vec<u16>, running: Vec<u16> },
+}
+
+/// What `adopt_running_services` found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AdoptionReport {
+    pub services: Vec<RunningService>,
+    pub warnings: Vec<AdoptionWarning>,
+}
+
+/// Image and published ports a service is configured with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ExpectedService {
+    image: Option<String>,
+    ports: Vec<u16>,
+}
+
+/// Inspect the running arbor-labelled containers against the compose config
+pub async fn inspect_running_stack(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<AdoptionReport, String> {
+    let expected = expected_services(runner, project_root, setup).await?;
+
+    let project_filter = format!("label={}={}", PROJECT_LABEL, setup.project);
+    let ps = CommandSpec::new("docker").args([
+        "ps",
+        "--filter",
+        &project_filter,
+        "--format",
+        "{{.Label \"com.docker.compose.service\"}}\t{{.Names}}\t{{.Image}}\t{{.Ports}}",
+    ]);
+    let output = runner
+        .output(&ps)
+        .await
+        .map_err(|e| format!("Failed to list containers: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to list containers: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    let services = parse_running_services(&output.stdout_text());
+
+    Ok(AdoptionReport {
+        warnings: compare_stack(&expected, &services),
+        services,
+    })
+}
+
+/// Host ports each service of the stack publishes, by service
+/// Only the services `setup` starts are included.
+pub async fn published_ports(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<BTreeMap<String, Vec<u16>>, String> {
+    let mut expected = expected_services(runner, project_root, setup).await?;
+    if !setup.only_services.is_empty() {
+        expected.retain(|service, _| setup.only_services.contains(service));
+    }
+    Ok(expected
+        .into_iter()
+        .map(|(service, config)| (service, config.ports))
+        .collect())
+}
+
+/// The image each service of the stack runs, by service; services that are
+/// only built have none
+pub async fn service_images(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<BTreeMap<String, String>, String> {
+    Ok(expected_services(runner, project_root, setup)
+        .await?
+        .into_iter()
+        .filter_map(|(service, config)| Some((service, config.image?)))
+        .collect())
+}
+
+async fn expected_services(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<BTreeMap<String, ExpectedService>, String> {
+    let config = setup
+        .compose_command(project_root)
+        .args(["config", "--format", "json"]);
+    let output = runner
+        .output(&config)
+        .await
+        .map_err(|e| format!("Failed to read compose config: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to read compose config: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    parse_expected_services(&output.stdout_text())
+}
+
+fn parse_expected_services(json: &str) -> Result<BTreeMap<String, ExpectedService>, String> {
+    let config: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| format!("Invalid compose config: {}", e))?;
+    let Some(services) = config.get("services").and_then(|s| s.as_object()) else {
+        return Ok(BTreeMap::new());
+    };
+    Ok(services
+        .iter()
+        .map(|(name, service)| {
+            let image = service.get("image").and_then(|i| i.as_str()).map(str::to_string);
+            let mut ports: Vec<u16> = service
+                .get("ports")
+                .and_then(|p| p.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|port| match port.get("published")? {
+                    serde_json::Value::String(published) => published.parse().ok(),
+                    published => published.as_u64().and_then(|p| u16::try_from(p).ok()),
+                })
+                .collect();
+            ports.sort_unstable();
+            ports.dedup();
+            (name.clone(), ExpectedService { image, ports })
+        })
+        .collect())
+}
+
+fn parse_running_services(text: &str) -> Vec<RunningService> {
+    let mut services: Vec<RunningService> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(4, '\t').map(str::trim);
+            let service = fields.next().unwrap_or_default().to_string();
+            let container = fields.next().unwrap_or_default().to_string();
+            let image = fields.next().unwrap_or_default().to_string();
+            RunningService {
+                service,
+                container,
+                image,
+                ports: docker::parse_published_ports(fields.next().unwrap_or_default()),
+            }
+        })
+        .collect();
+    services.sort_by(|a, b| a.service.cmp(&b.service));
+    services
+}
+
+fn compare_stack(
+    expected: &BTreeMap<String, ExpectedService>,
+    running: &[RunningService],
+) -> Vec<AdoptionWarning> {
+    let mut warnings = Vec::new();
+    for (service, config) in expected {
+        let Some(container) = running.iter().find(|r| &r.service == service) else {
+            warnings.push(AdoptionWarning::MissingService {
+                service: service.clone(),
+            });
+            continue;
+        };
+        if let Some(image) = &config.image {
+            if image != &container.image {
+                warnings.push(AdoptionWarning::ImageMismatch {
+                    service: service.clone(),
+                    expected: image.clone(),
+                    running: container.image.clone(),
+                });
+            }
+        }
+        if config.ports != container.ports {
+            warnings.push(AdoptionWarning::PortMismatch {
+                service: service.clone(),
+                expected: config.ports.clone(),
+                running: container.ports.clone(),
+            });
+        }
+    }
+    for container in running {
+        if !expected.contains_key(&container.service) {
+            warnings.push(AdoptionWarning::UnexpectedService {
+                service: container.service.clone(),
+            });
+        }
+    }
+    warnings
+}
+
+/// Check the compose files parse and merge cleanly
+pub async fn validate_config(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<(), String> {
+    let config = setup
+        .compose_command(project_root)
+        .args(["config", "--quiet"]);
+    let output = runner
+        .output(&config)
+        .await
+        .map_err(|e| format!("Failed to validate compose config: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Invalid compose config: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Recreate just `services` from the current compose files
+pub async fn restart_services(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+    services: &[String],
+) -> Result<(), String> {
+    let up = setup
+        .compose_command(project_root)
+        .args(["up", "-d", "--no-deps"])
+        .args(services.iter().cloned());
+    let output = runner
+        .output(&up)
+        .await
+        .map_err(|e| format!("Failed to restart services: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to restart services: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(())
+}
+
+/// What to do to a single service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl ServiceAction {
+    /// The `docker compose` subcommand, which is also how it's described
+    pub fn command(&self) -> &'static str {
+        match self {
+            ServiceAction::Start => "start",
+            ServiceAction::Stop => "stop",
+            ServiceAction::Restart => "restart",
+        }
+    }
+}
+
+/// A service's container once a ServiceAction is done
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceStatus {
+    pub service: String,
+    pub action: ServiceAction,
+    /// None if compose has no container for it
+    pub container: Option<ContainerStatus>,
+}
+
+/// Refuse a service the project's compose files don't declare
+pub fn validate_service(project_root: &Path, service: &str) -> Result<(), String> {
+    let declared = declared_services(project_root);
+    if !declared.iter().any(|name| name == service) {
+        return Err(format!(
+            "Unknown service {:?}; the stack has {}",
+            service,
+            declared.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Start, stop or restart just `service`'s container, leaving the rest of
+/// the stack as it is
+pub async fn control_service(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+    action: ServiceAction,
+    service: &str,
+) -> Result<ServiceStatus, String> {
+    validate_service(project_root, service)?;
+    let spec = setup
+        .compose_command(project_root)
+        .args([action.command(), service]);
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to {} {}: {}", action.command(), service, e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to {} {}: {}",
+            action.command(),
+            service,
+            output.stderr_text().trim()
+        ));
+    }
+
+    let container = docker::list_containers(runner)
+        .await?
+        .into_iter()
+        .find(|container| docker::service_of(&container.name) == service);
+    Ok(ServiceStatus {
+        service: service.to_string(),
+        action,
+        container,
+    })
+}
+
+/// Range `stop_timeout_secs` may be set in
+pub const STOP_TIMEOUT_RANGE: RangeInclusive<u64> = 5..=600;
+
+/// How the stack was brought down; a stop that failed is an error instead
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum StopOutcome {
+    /// `make down` finished in time
+    Graceful,
+    /// `make down` timed out and these containers were force-removed, so
+    /// anything they hadn't written out yet may be lost
+    Forced { removed: Vec<String> },
+}
+
+/// Bring the stack down with `make down` (or compose's), force-removing its
+/// containers instead if that takes longer than `timeout` (a wedged daemon)
+pub async fn bring_down(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+    timeout: Duration,
+) -> Result<StopOutcome, String> {
+    let down = setup.down_command(project_root);
+    let command = setup.backend.describe("down");
+    // Dropping the timed-out run kills make and what it started
+    let output = match tokio::time::timeout(timeout, runner.output(&down)).await {
+        Ok(output) => output.map_err(|e| format!("Failed to stop services: {}", e))?,
+        Err(_) => {
+            warn!(
+                "⚠️  {} didn't finish within {}s; removing the containers",
+                command,
+                timeout.as_secs()
+            );
+            let removed: Vec<String> = docker::list_containers(runner)
+                .await?
+                .into_iter()
+                .map(|container| container.name)
+                .collect();
+            docker::remove_containers(runner, &removed).await.map_err(|e| {
+                format!("{} timed out after {}s, and {}", command, timeout.as_secs(), e)
+            })?;
+            return Ok(StopOutcome::Forced { removed });
+        }
+    };
+    if !output.success() {
+        return Err(format!(
+            "Failed to stop services: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(StopOutcome::Graceful)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Restarting {
+    /// Whether there's a stack to stop first
+    pub was_running: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Restarted {
+    /// How the stack was stopped; none if it wasn't running
+    pub stopped: Option<StopOutcome>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPhase {
+    Stop,
+    Start,
+}
+
+/// Why `restart_services` failed; a failed stop isn't followed by a start
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RestartFailed {
+    pub phase: RestartPhase,
+    pub error: String,
+}
+
+impl fmt::Display for RestartFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self.phase {
+            RestartPhase::Stop => "stopping",
+            RestartPhase::Start => "starting",
+        };
+        write!(f, "Restart failed while {}: {}", phase, self.error)
+    }
+}
+
+/// `timeout_secs` within STOP_TIMEOUT_RANGE
+pub fn validate_stop_timeout(timeout_secs: u64) -> Result<(), String> {
+    if !STOP_TIMEOUT_RANGE.contains(&timeout_secs) {
+        return Err(format!(
+            "stop_timeout_secs must be between {} and {}",
+            STOP_TIMEOUT_RANGE.start(),
+            STOP_TIMEOUT_RANGE.end()
+        ));
+    }
+    Ok(())
+}
+
+/// Services whose running containers were created from a different config
+/// Compares compose's per-service config hash (which includes the override
+/// file when dev mode adds it) against the label on each container.
+pub async fn detect_drift(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<Vec<String>, String> {
+    let config = setup
+        .compose_command(project_root)
+        .args(["config", "--hash", "*"]);
+    let output = runner
+        .output(&config)
+        .await
+        .map_err(|e| format!("Failed to hash compose config: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to hash compose config: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    let expected = parse_service_hashes(&output.stdout_text(), ' ');
+
+    let project_filter = format!("label={}={}", PROJECT_LABEL, setup.project);
+    let ps = CommandSpec::new("docker").args([
+        "ps",
+        "--filter",
+        &project_filter,
+        "--format",
+        "{{.Label \"com.docker.compose.service\"}}\t{{.Label \"com.docker.compose.config-hash\"}}",
+    ]);
+    let output = runner
+        .output(&ps)
+        .await
+        .map_err(|e| format!("Failed to list containers: {}", e))?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to list containers: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    let running = parse_service_hashes(&output.stdout_text(), '\t');
+
+    let mut drifted: Vec<String> = running
+        .iter()
+        .filter(|(service, hash)| expected.get(*service) != Some(*hash))
+        .map(|(service, _)| service.clone())
+        .collect();
+    drifted.sort();
+    Ok(drifted)
+}
+
+fn parse_service_hashes(text: &str, separator: char) -> HashMap<String, String> {
+    text.lines()
+        .filter_map(|line| {
+            let (service, hash) = line.trim().split_once(separator)?;
+            Some((service.trim().to_string(), hash.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Recreate services whose image or config changed, waiting until healthy
+pub async fn update_services(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<(), String> {
+    compose(runner, project_root, setup, &["up", "-d", "--wait"], "update services").await
+}
+
+/// Stop and remove the stack's containers
+pub async fn compose_down(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+) -> Result<(), String> {
+    compose(runner, project_root, setup, &["down", "--remove-orphans"], "stop services").await
+}
+
+async fn compose(
+    runner: &dyn CommandRunner,
+    project_root: &Path,
+    setup: &ComposeSetup,
+    args: &[&str],
+    action: &str,
+) -> Result<(), String> {
+    let spec = setup
+        .compose_command(project_root)
+        .args(args.iter().copied());
+    let output = runner
+        .output(&spec)
+        .await
+        .map_err(|e| format!("Failed to {}: {}", action, e))?;
+    if !output.success() {
+        return Err(format!("Failed to {}: {}", action, output.stderr_text().trim()));
+    }
+    Ok(())
+}
+
+/// Makefile targets the frontend may run as setup commands
+pub const SETUP_TARGETS: [&str; 7] = [
+    "setup",
+    "db-migrate",
+    "db-generate",
+    "db-push",
+    "seed",
+    "db-reset",
+    "embeddings-backfill",
+];
+
+/// Variables make itself reads, which would change how it runs
+const MAKE_SPECIAL_VARS: [&str; 5] = ["MAKEFLAGS", "MFLAGS", "MAKEFILES", "MAKE", "SHELL"];
+
+/// Check that `target` is one of SETUP_TARGETS
+pub fn validate_setup_target(target: &str) -> Result<(), String> {
+    if !SETUP_TARGETS.contains(&target) {
+        return Err(format!(
+            "Unknown setup command {:?}; expected one of {}",
+            target,
+            SETUP_TARGETS.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// `vars` as `NAME=value` arguments for make
+/// Recipes expand variables into shell commands unquoted, so values are
+/// limited to characters no shell treats specially. Names make or the
+/// compose setup reads (`COMPOSE_*`) are refused.
+pub fn setup_make_vars(vars: &BTreeMap<String, String>) -> Result<Vec<String>, String> {
+    let name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let value_char = |c: char| c.is_ascii_alphanumeric() || "_-.,:/=@+%".contains(c);
+    vars.iter()
+        .map(|(name, value)| {
+            if name.is_empty()
+                || name.starts_with(|c: char| c.is_ascii_digit())
+                || !name.chars().all(name_char)
+            {
+                return Err(format!("Invalid make variable name: {:?}", name));
+            }
+            if MAKE_SPECIAL_VARS.contains(&name.as_str()) || name.starts_with("COMPOSE_") {
+                return Err(format!("{} is set by Arbor and can't be overridden", name));
+            }
+            if !value.chars().all(value_char) {
+                return Err(format!(
+                    "Value of {} can only contain letters, digits and _-.,:/=@+%",
+                    name
+                ));
+            }
+            Ok(format!("{}={}", name, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::test_support::temp_dir;
+    use std::ffi::OsString;
+
+    const DATA_DIR: &str = "/data/arbor";
+
+    fn dev_settings() -> AppSettings {
+        AppSettings {
+            dev_mode: true,
+            ..AppSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_override_only_used_in_dev_mode() {
+        let root = Path::new("/work/arbor");
+
+        let user = ComposeSetup::from_settings(&AppSettings::default(), root, Path::new(DATA_DIR));
+        assert_eq!(user.override_file, None);
+        assert!(user.make_vars().is_empty());
+        assert!(!user.file_args(root).iter().any(|a| a.contains("override")));
+
+        let dev = ComposeSetup::from_settings(&dev_settings(), root, Path::new(DATA_DIR));
+        assert_eq!(
+            dev.override_file,
+            Some(root.join("docker-compose.override.yml"))
+        );
+        assert_eq!(
+            dev.make_vars(),
+            vec!["COMPOSE_OVERRIDE=/work/arbor/docker-compose.override.yml"]
+        );
+        let args = dev.file_args(root);
+        assert_eq!(
+            args.last().unwrap(),
+            "/work/arbor/docker-compose.override.yml"
+        );
+        assert_eq!(args.iter().filter(|a| *a == "-f").count(), 3);
+    }
+
+    #[test]
+    fn test_custom_override_path() {
+        let settings = AppSettings {
+            dev_mode: true,
+            compose_override_path: Some(PathBuf::from("/elsewhere/debug.yml")),
+            ..AppSettings::default()
+        };
+
+        let setup = ComposeSetup::from_settings(&settings, Path::new("/work/arbor"), Path::new(DATA_DIR));
+        assert_eq!(
+            setup.override_file,
+            Some(PathBuf::from("/elsewhere/debug.yml"))
+        );
+        assert!(
+            setup.prepare().is_err(),
+            "Missing override file should be rejected"
+        );
+    }
+
+    fn mount_settings(dev_mode: bool, source: &Path) -> AppSettings {
+        let mut paths = BTreeMap::new();
+        paths.insert(source.to_path_buf(), PathBuf::from("/app/src"));
+        let mut source_mounts = BTreeMap::new();
+        source_mounts.insert("api".to_string(), paths);
+
+        AppSettings {
+            dev_mode,
+            // Tests create this file so prepare() gets past the override check
+            compose_override_path: Some(source.join("override.yml")),
+            source_mounts,
+            ..AppSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_source_mounts_generate_override() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        std::fs::write(root.join("override.yml"), "services: {}").unwrap();
+        let data_dir = root.join("data");
+
+        let setup = ComposeSetup::from_settings(&mount_settings(true, root), root, &data_dir);
+        setup.prepare().unwrap();
+
+        let generated = data_dir.join(MOUNTS_OVERRIDE_FILE);
+        let document: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&generated).unwrap()).unwrap();
+        let api = &document["services"]["api"];
+        assert_eq!(api["volumes"][0]["source"], root.display().to_string());
+        assert_eq!(api["volumes"][0]["target"], "/app/src");
+        assert_eq!(api["environment"]["CHOKIDAR_USEPOLLING"], "true");
+
+        assert!(setup
+            .make_vars()
+            .contains(&format!("COMPOSE_MOUNTS_OVERRIDE={}", generated.display())));
+        assert!(setup.file_args(root).contains(&generated.display().to_string()));
+    }
+
+    #[test]
+    fn test_disabling_dev_mode_removes_generated_override() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        std::fs::write(root.join("override.yml"), "services: {}").unwrap();
+        let data_dir = root.join("data");
+
+        ComposeSetup::from_settings(&mount_settings(true, root), root, &data_dir)
+            .prepare()
+            .unwrap();
+        assert!(data_dir.join(MOUNTS_OVERRIDE_FILE).exists());
+
+        let disabled = ComposeSetup::from_settings(&mount_settings(false, root), root, &data_dir);
+        assert!(disabled.source_mounts.is_empty());
+        disabled.prepare().unwrap();
+        assert!(!data_dir.join(MOUNTS_OVERRIDE_FILE).exists());
+        assert!(disabled.make_vars().is_empty());
+    }
+
+    #[test]
+    fn test_pinned_subnet_generates_network_override() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        let data_dir = root.join("data");
+        let settings = AppSettings {
+            compose_subnet: Some("10.231.0.0/24".to_string()),
+            ..AppSettings::default()
+        };
+
+        let setup = ComposeSetup::from_settings(&settings, root, &data_dir);
+        setup.prepare().unwrap();
+
+        let generated = data_dir.join(NETWORK_OVERRIDE_FILE);
+        let document: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&generated).unwrap()).unwrap();
+        assert_eq!(
+            document["networks"]["default"]["ipam"]["config"][0]["subnet"],
+            "10.231.0.0/24"
+        );
+        assert_eq!(
+            setup.make_vars(),
+            vec![format!("COMPOSE_NETWORK_OVERRIDE={}", generated.display())]
+        );
+
+        let unpinned = ComposeSetup::from_settings(&AppSettings::default(), root, &data_dir);
+        unpinned.prepare().unwrap();
+        assert!(!generated.exists());
+    }
+
+    #[test]
+    fn test_declared_services_are_labelled_with_the_app_version() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        let data_dir = root.join("data");
+        std::fs::create_dir_all(root.join("apps/api")).unwrap();
+        std::fs::write(
+            root.join(COMPOSE_FILES[0]),
+            "name: arbor\n\nservices:\n  postgres:\n    image: pg # db\n    environment:\n      A: b\n\n  \"minio\":\n    image: minio\nvolumes:\n  data:\n",
+        )
+        .unwrap();
+
+        let setup = ComposeSetup::from_settings(&AppSettings::default(), root, &data_dir);
+        assert_eq!(setup.labelled_services, ["minio", "postgres"]);
+        assert!(setup.make_vars().is_empty(), "not offered before it's written");
+        setup.prepare().unwrap();
+
+        let generated = data_dir.join(VERSION_OVERRIDE_FILE);
+        let document: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&generated).unwrap()).unwrap();
+        assert_eq!(
+            document["services"]["postgres"]["labels"][compat::APP_VERSION_LABEL],
+            compat::app_version()
+        );
+        assert_eq!(
+            setup.make_vars(),
+            vec![format!("COMPOSE_VERSION_OVERRIDE={}", generated.display())]
+        );
+        assert!(setup.file_args(root).contains(&generated.display().to_string()));
+    }
+
+    #[test]
+    fn test_only_services_limits_make_up() {
+        let mut setup =
+            ComposeSetup::from_settings(&AppSettings::default(), Path::new("/work"), Path::new(DATA_DIR));
+        assert!(setup.make_vars().is_empty());
+
+        setup.only_services = vec!["postgres".to_string(), "redis".to_string()];
+        assert_eq!(setup.make_vars(), ["COMPOSE_SERVICES=postgres redis"]);
+    }
+
+    #[test]
+    fn test_compose_backend_is_used_without_make() {
+        let missing = LocatedTool {
+            tool: Tool::Make,
+            path: None,
+            source: None,
+            error: Some("make not found".to_string()),
+        };
+        let docker = ContainerRuntime::Docker;
+        assert_eq!(ServiceBackend::detect(&[], docker), ServiceBackend::Make);
+        assert_eq!(ServiceBackend::detect(&[missing], docker), ServiceBackend::Compose);
+        assert_eq!(
+            ServiceBackend::detect(&[], ContainerRuntime::Podman),
+            ServiceBackend::Compose
+        );
+
+        let root = Path::new("/work");
+        let mut setup =
+            ComposeSetup::from_settings(&AppSettings::default(), root, Path::new(DATA_DIR));
+        assert_eq!(setup.up_command(root).program, "make");
+        setup.backend = ServiceBackend::Compose;
+        setup.only_services = vec!["postgres".to_string()];
+
+        let up = setup.up_command(root);
+        assert_eq!(up.program, "docker");
+        assert_eq!(up.args[..3], ["compose", "-p", "arbor"]);
+        assert!(up.args.contains(&"/work/apps/api/docker-compose.yml".to_string()));
+        assert!(up.args.ends_with(&[
+            "up".to_string(),
+            "-d".to_string(),
+            "--wait".to_string(),
+            "--remove-orphans".to_string(),
+            "postgres".to_string(),
+        ]));
+        let down = setup.down_command(root);
+        assert!(down.args.ends_with(&["down".to_string(), "--remove-orphans".to_string()]));
+        assert_eq!(setup.backend.describe("down"), "docker compose down");
+    }
+
+    #[test]
+    fn test_service_env_reaches_make_and_compose() {
+        let root = Path::new("/work");
+        let settings = AppSettings {
+            service_env: BTreeMap::from([("LOG_LEVEL".to_string(), "debug".to_string())]),
+            ..AppSettings::default()
+        };
+        let mut setup = ComposeSetup::from_settings(&settings, root, Path::new(DATA_DIR));
+        let expected = [("LOG_LEVEL".to_string(), OsString::from("debug"))];
+
+        assert_eq!(setup.up_command(root).env, expected);
+        setup.backend = ServiceBackend::Compose;
+        assert_eq!(setup.up_command(root).env, expected);
+        assert_eq!(setup.down_command(root).env, expected);
+        assert_eq!(setup.compose_command(root).env, expected);
+    }
+
+    #[test]
+    fn test_profile_is_an_env_var_for_make_and_a_flag_for_compose() {
+        let root = Path::new("/work");
+        let settings = AppSettings {
+            service_profile: Some("full".to_string()),
+            ..AppSettings::default()
+        };
+        let mut setup = ComposeSetup::from_settings(&settings, root, Path::new(DATA_DIR));
+
+        let up = setup.up_command(root);
+        assert_eq!(up.env, [("COMPOSE_PROFILES".to_string(), OsString::from("full"))]);
+        assert!(!up.args.contains(&"--profile".to_string()));
+        setup.backend = ServiceBackend::Compose;
+        let up = setup.up_command(root);
+        assert!(up.env.is_empty());
+        assert_eq!(up.args[3..5], ["--profile", "full"]);
+    }
+
+    #[test]
+    fn test_missing_source_directory_is_rejected_at_start() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        std::fs::write(root.join("override.yml"), "services: {}").unwrap();
+        let mut settings = mount_settings(true, root);
+        settings.source_mounts.get_mut("api").unwrap().insert(
+            root.join("does-not-exist"),
+            PathBuf::from("/app/lib"),
+        );
+
+        let setup = ComposeSetup::from_settings(&settings, root, &root.join("data"));
+        assert!(setup.prepare().unwrap_err().contains("not an existing directory"));
+    }
+
+    fn drift_runner(config_hashes: &'static str) -> MockRunner {
+        MockRunner::new(move |spec| {
+            let stdout = if spec.args.contains(&"config".to_string()) {
+                config_hashes
+            } else {
+                "postgres\taaa\nredis\tbbb\n"
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_detect_drift_reports_changed_services() {
+        let runner = drift_runner("postgres aaa\nredis ccc\nminio ddd\n");
+        let setup = ComposeSetup::from_settings(&AppSettings::default(), Path::new("/work/arbor"), Path::new(DATA_DIR));
+
+        let drifted = detect_drift(&runner, Path::new("/work/arbor"), &setup)
+            .await
+            .unwrap();
+
+        assert_eq!(drifted, vec!["redis"]);
+    }
+
+    #[tokio::test]
+    async fn test_detect_drift_hashes_with_override_file() {
+        let runner = drift_runner("postgres aaa\nredis bbb\n");
+        let root = Path::new("/work/arbor");
+        let setup = ComposeSetup::from_settings(&dev_settings(), root, Path::new(DATA_DIR));
+
+        let drifted = detect_drift(&runner, root, &setup).await.unwrap();
+        assert!(drifted.is_empty());
+
+        let config_call = &runner.calls()[0];
+        assert!(config_call
+            .args
+            .contains(&"/work/arbor/docker-compose.override.yml".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_running_stack_warns_on_mismatches() {
+        let config = r#"{"services": {
+            "postgres": {"image": "postgres:16", "ports": [{"target": 5432, "published": "5432"}]},
+            "redis": {"image": "redis:7", "ports": [{"target": 6379, "published": "6379"}]},
+            "minio": {"image": "minio/minio"}
+        }}"#;
+        let runner = MockRunner::new(move |spec| {
+            let stdout = if spec.args.contains(&"config".to_string()) {
+                config.to_string()
+            } else {
+                "postgres\tarbor-postgres\tpostgres:15\t0.0.0.0:5432->5432/tcp, :::5432->5432/tcp\n\
+                 redis\tarbor-redis\tredis:7\t0.0.0.0:6380->6379/tcp\n\
+                 mailhog\tarbor-mailhog\tmailhog\t\n"
+                    .to_string()
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.into_bytes(),
+                stderr: Vec::new(),
+            })
+        });
+        let root = Path::new("/work/arbor");
+        let setup = ComposeSetup::from_settings(&AppSettings::default(), root, Path::new(DATA_DIR));
+
+        let report = inspect_running_stack(&runner, root, &setup).await.unwrap();
+        assert_eq!(report.services.len(), 3);
+        assert_eq!(report.services[1].ports, vec![5432]);
+        assert_eq!(
+            report.warnings,
+            vec![
+                AdoptionWarning::MissingService {
+                    service: "minio".to_string()
+                },
+                AdoptionWarning::ImageMismatch {
+                    service: "postgres".to_string(),
+                    expected: "postgres:16".to_string(),
+                    running: "postgres:15".to_string(),
+                },
+                AdoptionWarning::PortMismatch {
+                    service: "redis".to_string(),
+                    expected: vec![6379],
+                    running: vec![6380],
+                },
+                AdoptionWarning::UnexpectedService {
+                    service: "mailhog".to_string()
+                },
+            ]
+        );
+    }
+
+    fn exited(code: i32, stdout: &str, stderr: &str) -> std::io::Result<CommandOutput> {
+        Ok(CommandOutput {
+            code: Some(code),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+
+    /// Hangs in `make`, as with a wedged docker daemon; docker commands go to
+    /// `docker`
+    struct HungMake {
+        docker: MockRunner,
+    }
+
+    impl CommandRunner for HungMake {
+        fn output<'a>(
+            &'a self,
+            spec: &'a CommandSpec,
+        ) -> crate::process::BoxFuture<'a, std::io::Result<CommandOutput>> {
+            match spec.program.as_str() {
+                "make" => Box::pin(std::future::pending()),
+                _ => self.docker.output(spec),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_is_graceful_when_make_down_finishes() {
+        let root = Path::new("/work/arbor");
+        let setup = ComposeSetup::from_settings(&AppSettings::default(), root, Path::new(DATA_DIR));
+        let timeout = Duration::from_secs(30);
+
+        let runner = MockRunner::new(|_| exited(0, "", ""));
+        assert_eq!(
+            bring_down(&runner, root, &setup, timeout).await,
+            Ok(StopOutcome::Graceful)
+        );
+        assert_eq!(runner.calls().len(), 1);
+        assert_eq!(runner.calls()[0].args, ["down"]);
+
+        let runner = MockRunner::new(|_| exited(2, "", "Cannot connect to the Docker daemon\n"));
+        assert_eq!(
+            bring_down(&runner, root, &setup, timeout).await,
+            Err("Failed to stop services: Cannot connect to the Docker daemon".to_string())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hung_make_down_falls_back_to_removing_containers() {
+        let root = Path::new("/work/arbor");
+        let setup = ComposeSetup::from_settings(&AppSettings::default(), root, Path::new(DATA_DIR));
+        let runner = HungMake {
+            docker: MockRunner::new(|spec| match spec.args[0].as_str() {
+                "ps" => exited(
+                    0,
+                    "arbor-postgres\trunning\tUp\t\tpostgres:16\narbor-api\texited\t\t\tapi\n",
+                    "",
+                ),
+                _ => exited(0, "", ""),
+            }),
+        };
+
+        let outcome = bring_down(&runner, root, &setup, Duration::from_secs(30)).await;
+        assert_eq!(
+            outcome,
+            Ok(StopOutcome::Forced {
+                removed: vec!["arbor-postgres".to_string(), "arbor-api".to_string()]
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(outcome.unwrap()).unwrap()["outcome"],
+            "forced"
+        );
+        let calls = runner.docker.calls();
+        assert_eq!(calls[1].args, ["rm", "-f", "arbor-postgres", "arbor-api"]);
+
+        let runner = HungMake {
+            docker: MockRunner::new(|spec| match spec.args[0].as_str() {
+                "ps" => exited(0, "arbor-postgres\trunning\tUp\t\tpostgres:16\n", ""),
+                _ => exited(1, "", "daemon not responding"),
+            }),
+        };
+        let error = bring_down(&runner, root, &setup, Duration::from_secs(30))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "make down timed out after 30s, and failed to remove containers: daemon not responding"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_a_single_service_is_controlled() {
+        let tmp = temp_dir();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("apps/api")).unwrap();
+        std::fs::write(
+            root.join(COMPOSE_FILES[0]),
+            "services:\n  postgres:\n    image: pg\n  sync-worker:\n    image: worker\n",
+        )
+        .unwrap();
+        let setup =
+            ComposeSetup::from_settings(&AppSettings::default(), root, Path::new(DATA_DIR));
+        let runner = MockRunner::new(|spec| match spec.args[0].as_str() {
+            "ps" => exited(
+                0,
+                "arbor-postgres\trunning\tUp\t\tpg\narbor-sync-worker\trunning\tUp 1s\t\tworker\n",
+                "",
+            ),
+            _ => exited(0, "", ""),
+        });
+
+        let status = control_service(&runner, root, &setup, ServiceAction::Restart, "sync-worker")
+            .await
+            .unwrap();
+        assert_eq!(status.action, ServiceAction::Restart);
+        assert_eq!(status.container.unwrap().name, "arbor-sync-worker");
+        let restart = &runner.calls()[0];
+        assert_eq!(restart.args[0], "compose");
+        assert_eq!(restart.args[restart.args.len() - 2..], ["restart", "sync-worker"]);
+
+        let error = control_service(&runner, root, &setup, ServiceAction::Restart, "sync")
+            .await
+            .unwrap_err();
+        assert_eq!(error, "Unknown service \"sync\"; the stack has postgres, sync-worker");
+        assert_eq!(runner.calls().len(), 2, "nothing runs for an unknown service");
+
+        let runner =
+            MockRunner::new(|_| exited(1, "", "no container found for service postgres\n"));
+        let error = control_service(&runner, root, &setup, ServiceAction::Start, "postgres")
+            .await
+            .unwrap_err();
+        assert_eq!(error, "Failed to start postgres: no container found for service postgres");
+    }
+
+    #[test]
+    fn test_restart_failures_say_which_phase_failed() {
+        let failed = RestartFailed {
+            phase: RestartPhase::Stop,
+            error: "Failed to stop services: daemon not responding".to_string(),
+        };
+        assert_eq!(
+            failed.to_string(),
+            "Restart failed while stopping: Failed to stop services: daemon not responding"
+        );
+        assert_eq!(serde_json::to_value(&failed).unwrap()["phase"], "stop");
+    }
+
+    #[test]
+    fn test_stop_timeout_is_bounded() {
+        assert!(validate_stop_timeout(30).is_ok());
+        assert!(validate_stop_timeout(0).is_err());
+        assert!(validate_stop_timeout(3600).is_err());
+    }
+
+    #[test]
+    fn test_adopted_stack_refuses_lifecycle_actions() {
+        assert!(StackState::Running { adopted: false }.ensure_owned("stop services").is_ok());
+        assert!(StackState::Stopped.ensure_owned("start services").is_ok());
+        let error = StackState::Running { adopted: true }
+            .ensure_owned("stop services")
+            .unwrap_err();
+        assert!(error.contains("take ownership"));
+
+        assert!(!StackState::Running { adopted: true }.stops_on_exit(false));
+        assert!(StackState::Running { adopted: true }.stops_on_exit(true));
+        assert!(StackState::Running { adopted: false }.stops_on_exit(false));
+    }
+
+    #[test]
+    fn test_starts_and_stops_dont_overlap() {
+        assert_eq!(StackState::Stopped.start(), Ok(StackState::Starting));
+        assert_eq!(StackState::Starting.start(), Err(StackConflict::Starting));
+        assert_eq!(
+            StackState::Running { adopted: false }.start(),
+            Err(StackConflict::Running)
+        );
+        assert_eq!(StackState::Stopping.start(), Err(StackConflict::Stopping));
+
+        assert_eq!(StackState::Running { adopted: false }.stop(), Ok(StackState::Stopping));
+        assert_eq!(StackState::Stopped.stop(), Ok(StackState::Stopping));
+        assert_eq!(StackState::Starting.stop(), Err(StackConflict::Starting));
+        assert_eq!(StackState::Stopping.stop(), Err(StackConflict::Stopping));
+
+        assert_eq!(
+            serde_json::to_value(StackState::Starting).unwrap(),
+            serde_json::json!({ "state": "starting" })
+        );
+    }
+
+    #[test]
+    fn test_only_known_setup_targets_run() {
+        for target in ["setup", "db-migrate", "seed", "db-reset"] {
+            assert!(validate_setup_target(target).is_ok(), "{}", target);
+        }
+        for target in ["", "up", "nuke", "-f/tmp/evil.mk", "SHELL=/bin/sh", "seed; id", "$(id)"] {
+            let error = validate_setup_target(target).unwrap_err();
+            assert!(error.starts_with("Unknown setup command"), "{:?}", target);
+        }
+    }
+
+    #[test]
+    fn test_setup_vars_are_validated() {
+        let vars = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert_eq!(
+            setup_make_vars(&vars(&[("SEED_USERS", "25"), ("ENV", "")])).unwrap(),
+            ["ENV=", "SEED_USERS=25"]
+        );
+        assert!(setup_make_vars(&vars(&[("DATABASE_URL", "postgres://db:5432/arbor")])).is_ok());
+        for value in ["a b", "x; id", "$(id)", "`id`", "a|b", "a&b", "it's", "a\nb", "a~b", "*"] {
+            assert!(
+                setup_make_vars(&vars(&[("NAME", value)])).is_err(),
+                "{:?}",
+                value
+            );
+        }
+        for name in ["", "1X", "A-B", "A B", "SHELL", "MAKEFLAGS", "COMPOSE_OVERRIDE"] {
+            assert!(setup_make_vars(&vars(&[(name, "1")])).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,880 @@
+// Persisted app settings
+// Stored as JSON in the Tauri app config dir. Missing fields fall back to
+// their defaults so settings files from older versions keep loading, and
+// keys this version doesn't know are kept so a newer version's survive.
+// A file that can't be parsed is moved aside to `settings.json.corrupt` and
+// replaced with the defaults, rather than keeping the app from starting.
+
+use crate::flight::LogLevel;
+use crate::jobs;
+use crate::paths::{self, PathPolicy};
+use crate::protocol::Handshake;
+use crate::readiness;
+use crate::services::{self, StackState};
+use crate::subnets::Cidr;
+use crate::tools::ContainerRuntime;
+use crate::updates;
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::watch;
+
+pub const SETTINGS_FILE_NAME: &str = "settings.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    /// Start the stack when the app launches
+    pub auto_start_services: bool,
+    /// Lowest level of log lines printed; safe mode and `set_log_level`
+    /// raise it for a while
+    pub log_level: LogLevel,
+    /// Developer mode: run the stack with the compose override file
+    pub dev_mode: bool,
+    /// Override file used in dev mode; relative paths are resolved against
+    /// the project root. Defaults to `docker-compose.override.yml`.
+    pub compose_override_path: Option<PathBuf>,
+    /// Dev mode source mounts: service → host path → container path
+    pub source_mounts: BTreeMap<String, BTreeMap<PathBuf, PathBuf>>,
+    /// Recreate services whose compose config changed on disk, instead of
+    /// only prompting for a restart
+    pub auto_restart_on_config_change: bool,
+    /// How long the whole stack may take to become ready after `make up`
+    pub startup_timeout_secs: u64,
+    /// How long a single service may take once its container exists
+    pub per_service_ready_timeout_secs: u64,
+    /// How often readiness is checked while the stack starts
+    pub ready_poll_interval_ms: u64,
+    /// How long a setup target may run before it's killed
+    pub setup_command_timeout_secs: u64,
+    /// How long `make down` may take before the stack's containers are
+    /// force-removed instead
+    pub stop_timeout_secs: u64,
+    /// Stop a stack started outside Arbor (and adopted) when the app quits,
+    /// as one Arbor started is; otherwise it's left running
+    pub stop_adopted_on_exit: bool,
+    /// Closing the window hides it to the tray instead of quitting
+    pub minimize_to_tray: bool,
+    /// Show OS notifications for crashes, failed starts and updates
+    pub notifications_enabled: bool,
+    /// Look for a newer release at launch and every few hours
+    pub check_updates: bool,
+    /// Releases API endpoint for the latest release, for forks; by default
+    /// Arbor's on GitHub
+    pub update_url: Option<String>,
+    /// Endpoint that must answer 2xx before the stack counts as ready, e.g.
+    /// `http://api.arbor.local/health`; a service name as the host, as in
+    /// `http://api:8080/health`, means the host port it publishes 8080 on
+    pub health_url: Option<String>,
+    /// `arbor.toml` profile whose overrides apply on top of these settings
+    pub profile: Option<String>,
+    pub background_intervals: BackgroundIntervals,
+    /// Arbor checkout to run the stack from, instead of searching for it
+    pub project_root: Option<PathBuf>,
+    pub tool_paths: ToolPaths,
+    /// Container runtime to use where both are installed; by default docker,
+    /// or podman when docker isn't found
+    pub container_runtime: Option<ContainerRuntime>,
+    /// What big downloads do on a metered connection
+    pub downloads_on_metered: MeteredDownloads,
+    /// Treat the connection as metered where the OS doesn't say (e.g. macOS)
+    pub assume_metered: bool,
+    /// IPv4 subnet pinned for the compose network, e.g. to stay clear of a
+    /// VPN's routes; applies when the network is next recreated
+    pub compose_subnet: Option<String>,
+    /// Developer setting: run the Makefile and compose files without
+    /// checking them against their trusted hashes. Only honored in dev mode.
+    pub skip_project_file_verification: bool,
+    pub metrics: MetricsSettings,
+    pub auto_restart: AutoRestartSettings,
+    pub disk_space: DiskSpaceSettings,
+    /// Environment for the stack's `make` and `docker compose` runs, e.g.
+    /// `LOG_LEVEL=debug` for the compose file to read; applies at the next
+    /// start
+    pub service_env: BTreeMap<String, String>,
+    /// Names in RESERVED_SERVICE_ENV that `service_env` may set anyway, e.g.
+    /// `DOCKER_HOST` for a remote daemon
+    pub service_env_allow_reserved: Vec<String>,
+    /// Compose profile to start the stack with, e.g. `full` for the optional
+    /// services; only the services without a profile when unset
+    pub service_profile: Option<String>,
+    /// Top-level keys from a newer version, written back untouched
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// Policy for image pulls and other big downloads on a metered connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeteredDownloads {
+    Proceed,
+    /// Ask the frontend first, via a `metered-download-prompt` event
+    #[default]
+    Prompt,
+    Skip,
+}
+
+/// Explicit locations of the external tools, for when PATH doesn't have them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolPaths {
+    pub make: Option<PathBuf>,
+    pub docker: Option<PathBuf>,
+    pub podman: Option<PathBuf>,
+    /// Standalone `docker-compose` (or `podman-compose`), used instead of
+    /// `docker compose`
+    pub compose: Option<PathBuf>,
+}
+
+impl ToolPaths {
+    fn validate(&self) -> Result<(), String> {
+        let paths: [(&str, &Option<PathBuf>, &[&str]); 4] = [
+            ("make", &self.make, &["make", "gmake"]),
+            ("docker", &self.docker, &["docker"]),
+            ("podman", &self.podman, &["podman"]),
+            ("compose", &self.compose, &["docker-compose", "podman-compose"]),
+        ];
+        for (tool, path, names) in paths {
+            if let Some(path) = path {
+                paths::validate(path, PathPolicy::Executable(names))
+                    .map_err(|e| format!("{} path: {}", tool, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prometheus endpoint for headless setups; changes apply at the next launch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    pub listen: String,
+    /// Bearer token scrapers must send
+    pub token: Option<String>,
+    /// Listen on a non-loopback address
+    pub allow_remote: bool,
+}
+
+impl MetricsSettings {
+    const MIN_TOKEN_LEN: usize = 16;
+
+    pub fn validate(&self) -> Result<(), String> {
+        let addr: SocketAddr = self
+            .listen
+            .parse()
+            .map_err(|_| format!("Metrics listen address {:?} is not ip:port", self.listen))?;
+        if !addr.ip().is_loopback() && !self.allow_remote {
+            return Err(format!(
+                "Metrics listen address {} isn't loopback; set allow_remote to expose it",
+                addr
+            ));
+        }
+        let token_len = self.token.as_deref().map_or(0, |token| token.trim().len());
+        if self.enabled && token_len < Self::MIN_TOKEN_LEN {
+            return Err(format!(
+                "The metrics endpoint needs a token of at least {} characters",
+                Self::MIN_TOKEN_LEN
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:9464".to_string(),
+            token: None,
+            allow_remote: false,
+        }
+    }
+}
+
+/// The watchdog's recovery of containers that stay down after a crash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoRestartSettings {
+    pub enabled: bool,
+    /// Restarts of a container before it's given up on
+    pub max_attempts: u32,
+    /// Wait after the first restart, doubled after each one
+    pub backoff_secs: u64,
+}
+
+impl AutoRestartSettings {
+    const MAX_ATTEMPTS: u32 = 10;
+    const MAX_BACKOFF_SECS: u64 = 60 * 60;
+
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=Self::MAX_ATTEMPTS).contains(&self.max_attempts) {
+            return Err(format!(
+                "auto_restart.max_attempts must be between 1 and {}",
+                Self::MAX_ATTEMPTS
+            ));
+        }
+        if !(1..=Self::MAX_BACKOFF_SECS).contains(&self.backoff_secs) {
+            return Err(format!(
+                "auto_restart.backoff_secs must be between 1 and {}",
+                Self::MAX_BACKOFF_SECS
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for AutoRestartSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_attempts: 3,
+            backoff_secs: 10,
+        }
+    }
+}
+
+/// Free space wanted where docker keeps its data before the stack starts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiskSpaceSettings {
+    /// Starting with less emits `low-disk-space`
+    pub warn_below_gb: u64,
+    /// Starting with less is refused, unless forced
+    pub refuse_below_gb: u64,
+}
+
+impl DiskSpaceSettings {
+    fn validate(&self) -> Result<(), String> {
+        if self.refuse_below_gb > self.warn_below_gb {
+            return Err(
+                "disk_space.refuse_below_gb must not be above disk_space.warn_below_gb".to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for DiskSpaceSettings {
+    fn default() -> Self {
+        Self {
+            warn_below_gb: 10,
+            refuse_below_gb: 2,
+        }
+    }
+}
+
+/// How often the periodic background tasks run (before jitter)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackgroundIntervals {
+    pub status_poll_secs: u64,
+    pub stats_sample_secs: u64,
+    /// How often the watchdog looks for containers that stayed down
+    pub watchdog_poll_secs: u64,
+}
+
+impl BackgroundIntervals {
+    const MIN_STATUS_POLL_SECS: u64 = 5;
+    const MIN_STATS_SAMPLE_SECS: u64 = 2;
+    const MIN_WATCHDOG_POLL_SECS: u64 = 2;
+
+    fn validate(&self) -> Result<(), String> {
+        if self.status_poll_secs < Self::MIN_STATUS_POLL_SECS {
+            return Err(format!(
+                "status_poll_secs must be at least {}",
+                Self::MIN_STATUS_POLL_SECS
+            ));
+        }
+        if self.stats_sample_secs < Self::MIN_STATS_SAMPLE_SECS {
+            return Err(format!(
+                "stats_sample_secs must be at least {}",
+                Self::MIN_STATS_SAMPLE_SECS
+            ));
+        }
+        if self.watchdog_poll_secs < Self::MIN_WATCHDOG_POLL_SECS {
+            return Err(format!(
+                "watchdog_poll_secs must be at least {}",
+                Self::MIN_WATCHDOG_POLL_SECS
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for BackgroundIntervals {
+    fn default() -> Self {
+        Self {
+            status_poll_secs: 30,
+            stats_sample_secs: 15,
+            watchdog_poll_secs: 10,
+        }
+    }
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            auto_start_services: true,
+            log_level: LogLevel::Info,
+            dev_mode: false,
+            compose_override_path: None,
+            source_mounts: BTreeMap::new(),
+            auto_restart_on_config_change: false,
+            startup_timeout_secs: 120,
+            per_service_ready_timeout_secs: 60,
+            ready_poll_interval_ms: 1000,
+            setup_command_timeout_secs: 600,
+            stop_timeout_secs: 30,
+            stop_adopted_on_exit: false,
+            minimize_to_tray: false,
+            notifications_enabled: true,
+            check_updates: true,
+            update_url: None,
+            health_url: None,
+            profile: None,
+            background_intervals: BackgroundIntervals::default(),
+            project_root: None,
+            tool_paths: ToolPaths::default(),
+            container_runtime: None,
+            downloads_on_metered: MeteredDownloads::default(),
+            assume_metered: false,
+            compose_subnet: None,
+            skip_project_file_verification: false,
+            metrics: MetricsSettings::default(),
+            auto_restart: AutoRestartSettings::default(),
+            disk_space: DiskSpaceSettings::default(),
+            service_env: BTreeMap::new(),
+            service_env_allow_reserved: Vec::new(),
+            service_profile: None,
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+impl AppSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_source_mounts(&self.source_mounts)?;
+        readiness::validate_timeouts(self.startup_timeout_secs, self.per_service_ready_timeout_secs)?;
+        readiness::validate_poll_interval(self.ready_poll_interval_ms)?;
+        jobs::validate_setup_timeout(self.setup_command_timeout_secs)?;
+        services::validate_stop_timeout(self.stop_timeout_secs)?;
+        if let Some(url) = &self.health_url {
+            readiness::HealthUrl::parse(url)?;
+        }
+        self.background_intervals.validate()?;
+        self.metrics.validate()?;
+        self.auto_restart.validate()?;
+        self.disk_space.validate()?;
+        validate_service_env(&self.service_env, &self.service_env_allow_reserved)?;
+        if let Some(profile) = &self.service_profile {
+            validate_service_profile(profile)?;
+        }
+        if let Some(url) = &self.update_url {
+            updates::validate_url(url)?;
+        }
+        if let Some(subnet) = &self.compose_subnet {
+            Cidr::parse(subnet)?;
+        }
+        if let Some(root) = &self.project_root {
+            paths::validate(root, PathPolicy::Directory)
+                .map_err(|e| format!("Project root: {}", e))?;
+        }
+        self.tool_paths.validate()
+    }
+
+    /// A copy fit for sharing, e.g. in a diagnostics bundle, with secrets masked
+    pub fn redacted(&self) -> Self {
+        let mut settings = self.clone();
+        if settings.metrics.token.is_some() {
+            settings.metrics.token = Some("<redacted>".to_string());
+        }
+        for value in settings.service_env.values_mut() {
+            *value = "<redacted>".to_string();
+        }
+        settings
+    }
+}
+
+/// Names the processes the stack runs with depend on, which `service_env`
+/// only sets when `service_env_allow_reserved` lists them
+pub const RESERVED_SERVICE_ENV: [&str; 17] = [
+    "PATH",
+    "HOME",
+    "USER",
+    "SHELL",
+    "DOCKER_HOST",
+    "DOCKER_CONTEXT",
+    "DOCKER_CONFIG",
+    "DOCKER_CERT_PATH",
+    "DOCKER_TLS_VERIFY",
+    "COMPOSE_FILE",
+    "COMPOSE_PROJECT_NAME",
+    "COMPOSE_PROFILES",
+    "MAKEFLAGS",
+    "LD_PRELOAD",
+    "LD_LIBRARY_PATH",
+    "DYLD_INSERT_LIBRARIES",
+    "DYLD_LIBRARY_PATH",
+];
+
+/// Names are uppercase letters, digits and underscores, not starting with a
+/// digit; reserved ones must be allowed explicitly
+pub fn validate_service_env(
+    env: &BTreeMap<String, String>,
+    allow_reserved: &[String],
+) -> Result<(), String> {
+    let valid_name = |name: &str| {
+        !name.starts_with(|c: char| c.is_ascii_digit())
+            && !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+    };
+    for name in allow_reserved {
+        if !valid_name(name) {
+            return Err(format!("service_env_allow_reserved: {:?} isn't a variable name", name));
+        }
+    }
+    for (name, value) in env {
+        if !valid_name(name) {
+            return Err(format!(
+                "service_env: {:?} must be uppercase letters, digits and underscores",
+                name
+            ));
+        }
+        if RESERVED_SERVICE_ENV.contains(&name.as_str()) && !allow_reserved.contains(name) {
+            return Err(format!(
+                "service_env: {} is reserved; add it to service_env_allow_reserved to set it",
+                name
+            ));
+        }
+        if value.contains('\0') {
+            return Err(format!("service_env: {} must not contain a NUL character", name));
+        }
+    }
+    Ok(())
+}
+
+/// A name compose accepts for a profile; whether the compose files declare
+/// it is checked when the stack starts
+pub fn validate_service_profile(profile: &str) -> Result<(), String> {
+    let valid = profile.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+    match valid {
+        true => Ok(()),
+        false => Err(format!("service_profile: {:?} isn't a compose profile name", profile)),
+    }
+}
+
+/// Host paths must be existing directories and container paths absolute
+pub fn validate_source_mounts(
+    mounts: &BTreeMap<String, BTreeMap<PathBuf, PathBuf>>,
+) -> Result<(), String> {
+    for (service, paths) in mounts {
+        if service.trim().is_empty() {
+            return Err("Source mount service name must not be empty".to_string());
+        }
+        for (host_path, container_path) in paths {
+            paths::validate(host_path, PathPolicy::Directory)
+                .map_err(|e| format!("Source mount for {}: {}", service, e))?;
+            if !container_path.to_string_lossy().starts_with('/') {
+                return Err(format!(
+                    "Source mount for {}: container path {:?} must be absolute",
+                    service, container_path
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: Mutex<AppSettings>,
+    // Lets long-running tasks pick up changes without being restarted
+    changes: watch::Sender<AppSettings>,
+}
+
+impl SettingsStore {
+    /// Load settings from `path`, using defaults if the file is missing or unreadable
+    /// A corrupt file is kept as `settings.json.corrupt` and replaced with the defaults.
+    pub fn load(path: PathBuf) -> Self {
+        let (settings, corrupt) = match std::fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(settings) => (settings, false),
+                Err(e) => {
+                    warn!("⚠️  Settings file {:?} is corrupt: {}", path, e);
+                    (AppSettings::default(), true)
+                }
+            },
+            Err(_) => (AppSettings::default(), false),
+        };
+
+        let store = Self {
+            path,
+            changes: watch::Sender::new(settings.clone()),
+            settings: Mutex::new(settings),
+        };
+        if corrupt {
+            let backup = store.path.with_extension("json.corrupt");
+            let regenerated = std::fs::rename(&store.path, &backup)
+                .map_err(|e| format!("Failed to back up settings: {}", e))
+                .and_then(|_| store.save(&AppSettings::default()));
+            match regenerated {
+                Ok(()) => warn!("⚠️  Moved it to {:?}; using the defaults", backup),
+                Err(e) => warn!("⚠️  Using the default settings: {}", e),
+            }
+        }
+        store
+    }
+
+    pub fn get(&self) -> AppSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Receiver that sees every successful update
+    pub fn subscribe(&self) -> watch::Receiver<AppSettings> {
+        self.changes.subscribe()
+    }
+
+    /// Update a single setting
+    pub fn set(&self, key: &str, value: serde_json::Value) -> Result<AppSettings, String> {
+        let mut partial = serde_json::Map::new();
+        partial.insert(key.to_string(), value);
+        self.update(serde_json::Value::Object(partial))
+    }
+
+    /// Merge a partial JSON object into the settings and persist the result
+    pub fn update(&self, partial: serde_json::Value) -> Result<AppSettings, String> {
+        let mut settings = self.settings.lock().unwrap();
+
+        let mut merged = serde_json::to_value(&*settings).map_err(|e| e.to_string())?;
+        let (Some(target), serde_json::Value::Object(patch)) = (merged.as_object_mut(), partial)
+        else {
+            return Err("Settings update must be a JSON object".to_string());
+        };
+        for (key, value) in patch {
+            if !target.contains_key(&key) {
+                return Err(format!("Unknown setting: {}", key));
+            }
+            target.insert(key, value);
+        }
+
+        let updated: AppSettings =
+            serde_json::from_value(merged).map_err(|e| format!("Invalid settings: {}", e))?;
+        updated.validate()?;
+        self.save(&updated)?;
+        *settings = updated.clone();
+        self.changes.send_replace(updated.clone());
+
+        Ok(updated)
+    }
+
+    /// Swap in a whole new set of settings, first copying the current file to
+    /// `settings.json.bak`; returns the backup's path if there was a file
+    pub fn replace(&self, updated: AppSettings) -> Result<Option<PathBuf>, String> {
+        let mut settings = self.settings.lock().unwrap();
+        updated.validate()?;
+
+        let backup = self.path.with_extension("json.bak");
+        let backed_up = if self.path.exists() {
+            std::fs::copy(&self.path, &backup)
+                .map_err(|e| format!("Failed to back up settings: {}", e))?;
+            Some(backup)
+        } else {
+            None
+        };
+        self.save(&updated)?;
+        *settings = updated.clone();
+        self.changes.send_replace(updated);
+
+        Ok(backed_up)
+    }
+
+    /// Written to a temporary file and renamed over, so a crash midway never
+    /// leaves a truncated settings file
+    fn save(&self, settings: &AppSettings) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create settings directory: {}", e))?;
+        }
+
+        let contents = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+        let temporary = self.path.with_extension("json.tmp");
+        std::fs::write(&temporary, contents)
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| format!("Failed to save settings: {}", e))
+    }
+}
+
+#[tauri::command]
+pub async fn get_settings(store: State<'_, SettingsStore>) -> Result<AppSettings, String> {
+    Ok(store.get())
+}
+
+/// The settings after a change, and whether the running stack needs a
+/// restart to pick it up
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsUpdate {
+    #[serde(flatten)]
+    pub settings: AppSettings,
+    pub restart_required: bool,
+}
+
+impl SettingsUpdate {
+    /// `service_env` and `service_profile` only apply when the services start
+    fn new(app_handle: &AppHandle, settings: AppSettings) -> Self {
+        let service_manager = app_handle.state::<crate::ServiceManager>();
+        let up = matches!(
+            *service_manager.stack.lock(),
+            StackState::Running { .. } | StackState::Starting
+        );
+        let restart_required = up
+            && service_manager
+                .active_setup
+                .lock()
+                .as_ref()
+                .is_some_and(|active| {
+                    active.service_env != settings.service_env
+                        || active.profile != settings.service_profile
+                });
+        Self {
+            settings,
+            restart_required,
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn set_setting(
+    app_handle: AppHandle,
+    store: State<'_, SettingsStore>,
+    handshake: State<'_, Handshake>,
+    key: String,
+    value: serde_json::Value,
+) -> Result<SettingsUpdate, String> {
+    handshake.ensure()?;
+    let settings = store.set(&key, value)?;
+    Ok(SettingsUpdate::new(&app_handle, settings))
+}
+
+/// Apply a partial update, e.g. `{ "dev_mode": true }`
+#[tauri::command]
+pub async fn update_settings(
+    app_handle: AppHandle,
+    store: State<'_, SettingsStore>,
+    handshake: State<'_, Handshake>,
+    partial: serde_json::Value,
+) -> Result<SettingsUpdate, String> {
+    handshake.ensure()?;
+    let settings = store.update(partial)?;
+    Ok(SettingsUpdate::new(&app_handle, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    fn temp_settings_path(dir: &TempDir) -> PathBuf {
+        dir.path().join(SETTINGS_FILE_NAME)
+    }
+
+    #[test]
+    fn test_missing_file_uses_defaults() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+        assert_eq!(store.get(), AppSettings::default());
+    }
+
+    #[test]
+    fn test_corrupt_file_is_backed_up_and_regenerated() {
+        let tmp = temp_dir();
+        let path = temp_settings_path(&tmp);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "{ \"dev_mode\": tru").unwrap();
+
+        let store = SettingsStore::load(path.clone());
+        assert_eq!(store.get(), AppSettings::default());
+        let backup = path.with_extension("json.corrupt");
+        assert_eq!(
+            std::fs::read_to_string(backup).unwrap(),
+            "{ \"dev_mode\": tru"
+        );
+        assert_eq!(SettingsStore::load(path).get(), AppSettings::default());
+    }
+
+    #[test]
+    fn test_update_persists_and_reloads() {
+        let tmp = temp_dir();
+        let path = temp_settings_path(&tmp);
+        let store = SettingsStore::load(path.clone());
+
+        let updated = store
+            .update(serde_json::json!({ "dev_mode": true }))
+            .unwrap();
+        assert!(updated.dev_mode);
+
+        let reloaded = SettingsStore::load(path);
+        assert!(reloaded.get().dev_mode);
+    }
+
+    #[test]
+    fn test_update_rejects_unknown_and_mistyped_fields() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+
+        assert!(store.update(serde_json::json!({ "no_such": 1 })).is_err());
+        assert!(store
+            .update(serde_json::json!({ "dev_mode": "yes" }))
+            .is_err());
+        assert!(store.update(serde_json::json!([1, 2])).is_err());
+        assert_eq!(store.get(), AppSettings::default());
+    }
+
+    #[test]
+    fn test_timeouts_are_validated() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+
+        let updated = store
+            .update(serde_json::json!({ "startup_timeout_secs": 20, "per_service_ready_timeout_secs": 10 }))
+            .unwrap();
+        assert_eq!(updated.startup_timeout_secs, 20);
+
+        assert!(store
+            .update(serde_json::json!({ "startup_timeout_secs": 0 }))
+            .is_err());
+        assert!(store
+            .update(serde_json::json!({ "per_service_ready_timeout_secs": 30 }))
+            .is_err());
+        assert_eq!(store.get().per_service_ready_timeout_secs, 10);
+
+        for backoff_secs in [0, 60 * 60 + 1] {
+            assert!(store
+                .set("auto_restart", serde_json::json!({ "backoff_secs": backoff_secs }))
+                .is_err());
+        }
+        assert!(store
+            .set("auto_restart", serde_json::json!({ "backoff_secs": 60 * 60 }))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_metrics_endpoint_needs_token_and_loopback() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+
+        assert!(store
+            .set("metrics", serde_json::json!({ "enabled": true }))
+            .is_err());
+        assert!(store
+            .set("metrics", serde_json::json!({ "enabled": true, "token": "short" }))
+            .is_err());
+        let token = "0123456789abcdef";
+        assert!(store
+            .set("metrics", serde_json::json!({ "enabled": true, "token": token, "listen": "0.0.0.0:9464" }))
+            .is_err());
+        let updated = store
+            .set("metrics", serde_json::json!({ "enabled": true, "token": token }))
+            .unwrap();
+        assert_eq!(updated.metrics.listen, "127.0.0.1:9464");
+        assert_eq!(updated.redacted().metrics.token.as_deref(), Some("<redacted>"));
+    }
+
+    #[test]
+    fn test_background_interval_minimums() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+        let mut changes = store.subscribe();
+
+        let updated = store
+            .set("background_intervals", serde_json::json!({ "stats_sample_secs": 5 }))
+            .unwrap();
+        assert_eq!(updated.background_intervals.stats_sample_secs, 5);
+        assert_eq!(updated.background_intervals.status_poll_secs, 30);
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(changes.borrow_and_update().background_intervals.stats_sample_secs, 5);
+
+        assert!(store
+            .set("background_intervals", serde_json::json!({ "status_poll_secs": 1 }))
+            .is_err());
+        assert!(!changes.has_changed().unwrap());
+    }
+
+    #[test]
+    fn test_source_mounts_must_be_directories() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+        let source_dir = std::env::temp_dir().display().to_string();
+        let file = tmp.path().join("mounts-file");
+        let file_key = file.display().to_string();
+        std::fs::write(&file, "{}").unwrap();
+
+        let valid = serde_json::json!({
+            "source_mounts": { "api": { (source_dir.clone()): "/app/src" } }
+        });
+        assert!(store.update(valid).is_ok());
+
+        let not_a_dir = serde_json::json!({
+            "source_mounts": { "api": { (file_key): "/app/src" } }
+        });
+        assert!(store.update(not_a_dir).unwrap_err().contains("not an existing directory"));
+
+        let relative_target = serde_json::json!({
+            "source_mounts": { "api": { (source_dir): "app/src" } }
+        });
+        assert!(store.update(relative_target).unwrap_err().contains("must be absolute"));
+    }
+
+    #[test]
+    fn test_service_env_names_and_reserved_variables() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+
+        let updated = store
+            .set("service_env", serde_json::json!({ "API_PORT": "8081", "LOG_LEVEL": "debug" }))
+            .unwrap();
+        assert_eq!(updated.service_env["API_PORT"], "8081");
+        assert_eq!(updated.redacted().service_env["LOG_LEVEL"], "<redacted>");
+
+        for name in ["log_level", "2FA", "API-PORT", ""] {
+            let env = serde_json::json!({ (name): "1" });
+            assert!(store.set("service_env", env).is_err(), "{}", name);
+        }
+        let remote = serde_json::json!({ "service_env": { "DOCKER_HOST": "tcp://build:2375" } });
+        assert!(store.update(remote.clone()).unwrap_err().contains("reserved"));
+        assert_eq!(store.get().service_env.len(), 2);
+
+        let allowed = serde_json::json!({
+            "service_env": { "DOCKER_HOST": "tcp://build:2375" },
+            "service_env_allow_reserved": ["DOCKER_HOST"]
+        });
+        assert!(store.update(allowed).is_ok());
+        assert!(store.set("service_env_allow_reserved", serde_json::json!([])).is_err());
+        let profiles = serde_json::json!({ "service_env": { "COMPOSE_PROFILES": "full" } });
+        assert!(store.update(profiles).is_err(), "set with service_profile");
+    }
+
+    #[test]
+    fn test_service_profile_names() {
+        let tmp = temp_dir();
+        let store = SettingsStore::load(temp_settings_path(&tmp));
+        assert!(store.set("service_profile", serde_json::json!("full")).is_ok());
+        assert!(store.set("service_profile", serde_json::json!("mail.hog-2")).is_ok());
+        for profile in ["", "-full", "full stack", "a,b"] {
+            let value = serde_json::json!(profile);
+            assert!(store.set("service_profile", value).is_err(), "{}", profile);
+        }
+        assert_eq!(store.get().service_profile.as_deref(), Some("mail.hog-2"));
+        assert!(store.set("service_profile", serde_json::Value::Null).is_ok());
+    }
+}
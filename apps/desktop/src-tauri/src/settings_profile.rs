@@ -0,0 +1,505 @@
+// Portable settings profiles
+// A versioned JSON copy of the settings for setting up a second machine the
+// same way. Secrets stay behind: the metrics token and the `service_env`
+// values are left out on export and the local ones are kept on import. So
+// are the paths in MACHINE_KEYS, which only mean something on the machine
+// they were set on; an older profile carrying them reports them as skipped
+// on import. Keys this version doesn't know travel through untouched, so a
+// profile from a newer Arbor survives a round trip.
+// Both paths come from the frontend and go through `paths::validate`; an
+// export only overwrites a file that is already a profile.
+
+use crate::info;
+use crate::paths::{self, PathPolicy};
+use crate::protocol::Handshake;
+use crate::settings::{AppSettings, SettingsStore};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+/// Marks the file as a settings profile rather than any JSON
+pub const PROFILE_FORMAT: &str = "arbor-settings-profile";
+/// Bumped when the layout changes in a way older versions can't read
+pub const PROFILE_VERSION: u32 = 1;
+
+const MAX_PROFILE_BYTES: u64 = 1024 * 1024;
+/// Settings naming paths on this machine, which don't travel
+const MACHINE_KEYS: [&str; 4] = [
+    "project_root",
+    "tool_paths",
+    "source_mounts",
+    "compose_override_path",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingsProfile {
+    pub format: String,
+    pub version: u32,
+    pub app_version: String,
+    pub exported_at: String,
+    /// Settings as stored, minus secrets
+    pub settings: serde_json::Map<String, Value>,
+}
+
+/// One setting an import changes, by dotted path (`metrics.enabled`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SettingChange {
+    pub key: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImportResult {
+    pub changes: Vec<SettingChange>,
+    pub applied: bool,
+    /// Copy of the previous settings file, when one was written
+    pub backup: Option<String>,
+    /// MACHINE_KEYS the profile set, which the local values replaced
+    pub skipped: Vec<String>,
+}
+
+impl SettingsProfile {
+    pub fn from_settings(settings: &AppSettings) -> Result<Self, String> {
+        let mut settings = settings.clone();
+        settings.metrics.token = None;
+        // Values may be credentials, as `AppSettings::redacted` assumes
+        settings.service_env.clear();
+        let Value::Object(mut settings) =
+            serde_json::to_value(&settings).map_err(|e| e.to_string())?
+        else {
+            return Err("Settings did not serialize to an object".to_string());
+        };
+        for key in MACHINE_KEYS {
+            settings.remove(key);
+        }
+        Ok(Self {
+            format: PROFILE_FORMAT.to_string(),
+            version: PROFILE_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            exported_at: chrono::Utc::now().to_rfc3339(),
+            settings,
+        })
+    }
+
+    /// Parse a profile, refusing other files and newer layouts
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let profile: SettingsProfile =
+            serde_json::from_str(contents).map_err(|e| format!("Not a settings profile: {}", e))?;
+        if profile.format != PROFILE_FORMAT {
+            return Err(format!(
+                "Not a settings profile (format {:?})",
+                profile.format
+            ));
+        }
+        if profile.version > PROFILE_VERSION {
+            return Err(format!(
+                "Settings profile version {} is from a newer Arbor ({}); this one reads up to version {}",
+                profile.version, profile.app_version, PROFILE_VERSION
+            ));
+        }
+        Ok(profile)
+    }
+
+    /// What the settings would be after importing this profile over `current`
+    /// Merging keeps settings the profile doesn't mention; otherwise they
+    /// go back to their defaults.
+    pub fn apply_to(&self, current: &AppSettings, merge: bool) -> Result<AppSettings, String> {
+        let base = if merge {
+            current.clone()
+        } else {
+            AppSettings::default()
+        };
+        let mut merged = serde_json::to_value(&base).map_err(|e| e.to_string())?;
+        let local = serde_json::to_value(current).map_err(|e| e.to_string())?;
+        if let Some(target) = merged.as_object_mut() {
+            target.extend(self.settings.clone());
+            for key in MACHINE_KEYS {
+                target.insert(key.to_string(), local[key].clone());
+            }
+        }
+        let mut imported: AppSettings = serde_json::from_value(merged)
+            .map_err(|e| format!("Invalid settings in profile: {}", e))?;
+        imported.metrics.token = current.metrics.token.clone();
+        imported.service_env = current.service_env.clone();
+        imported.validate()?;
+        Ok(imported)
+    }
+
+    /// MACHINE_KEYS this profile sets, which `apply_to` leaves alone
+    pub fn machine_keys(&self) -> Vec<String> {
+        MACHINE_KEYS
+            .iter()
+            .filter(|key| self.settings.contains_key(**key))
+            .map(|key| key.to_string())
+            .collect()
+    }
+}
+
+/// Settings that differ between `before` and `after`, nested objects by field
+pub fn diff(before: &AppSettings, after: &AppSettings) -> Result<Vec<SettingChange>, String> {
+    let before = serde_json::to_value(before).map_err(|e| e.to_string())?;
+    let after = serde_json::to_value(after).map_err(|e| e.to_string())?;
+    let mut changes = Vec::new();
+    diff_values("", &before, &after, &mut changes);
+    Ok(changes)
+}
+
+fn diff_values(prefix: &str, before: &Value, after: &Value, changes: &mut Vec<SettingChange>) {
+    if let (Value::Object(before), Value::Object(after)) = (before, after) {
+        let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            let missing = Value::Null;
+            diff_values(
+                &path,
+                before.get(key).unwrap_or(&missing),
+                after.get(key).unwrap_or(&missing),
+                changes,
+            );
+        }
+    } else if before != after {
+        changes.push(SettingChange {
+            key: prefix.to_string(),
+            before: before.clone(),
+            after: after.clone(),
+        });
+    }
+}
+
+/// Write the settings, minus secrets, as a profile at `path`, returning
+/// where it went
+pub fn export_to(store: &SettingsStore, path: &Path) -> Result<PathBuf, String> {
+    let path = paths::validate(path, PathPolicy::Overwrite)?;
+    let profile_at = |path: &Path| read_profile(path).and_then(|c| SettingsProfile::parse(&c));
+    if path.exists() && profile_at(&path).is_err() {
+        return Err(format!(
+            "{} exists and isn't a settings profile",
+            path.display()
+        ));
+    }
+    let profile = SettingsProfile::from_settings(&store.get())?;
+    let contents = serde_json::to_string_pretty(&profile).map_err(|e| e.to_string())?;
+    std::fs::write(&path, contents)
+        .map_err(|e| format!("Failed to write settings profile: {}", e))?;
+    Ok(path)
+}
+
+/// The contents of the profile at the resolved `path`, if it's small enough
+/// to be one
+fn read_profile(path: &Path) -> Result<String, String> {
+    let metadata =
+        std::fs::metadata(path).map_err(|e| format!("Failed to read settings profile: {}", e))?;
+    if metadata.len() > MAX_PROFILE_BYTES {
+        return Err(format!("{} is not a settings profile", path.display()));
+    }
+    std::fs::read_to_string(path).map_err(|e| format!("Failed to read settings profile: {}", e))
+}
+
+/// Compare a profile at `path` with the current settings, applying it unless
+/// `dry_run`; nothing is written if validation fails or nothing changes
+pub fn import_from(
+    store: &SettingsStore,
+    path: &Path,
+    merge: bool,
+    dry_run: bool,
+) -> Result<ImportResult, String> {
+    let path = paths::validate(path, PathPolicy::File)?;
+    let profile = SettingsProfile::parse(&read_profile(&path)?)?;
+
+    let current = store.get();
+    let imported = profile.apply_to(&current, merge)?;
+    let changes = diff(&current, &imported)?;
+    let skipped = profile.machine_keys();
+    if dry_run || changes.is_empty() {
+        return Ok(ImportResult {
+            changes,
+            applied: false,
+            backup: None,
+            skipped,
+        });
+    }
+
+    let backup = store.replace(imported)?;
+    info!(
+        "📥 Imported {} setting changes from {:?}",
+        changes.len(),
+        path
+    );
+    Ok(ImportResult {
+        changes,
+        applied: true,
+        backup: backup.map(|path| path.display().to_string()),
+        skipped,
+    })
+}
+
+#[tauri::command]
+pub async fn export_settings(
+    store: State<'_, SettingsStore>,
+    handshake: State<'_, Handshake>,
+    path: String,
+) -> Result<(), String> {
+    handshake.ensure()?;
+    let path = export_to(&store, Path::new(&path))?;
+    info!("📤 Settings exported to {}", path.display());
+    Ok(())
+}
+
+/// Import a profile; `dry_run` only returns what would change
+#[tauri::command]
+pub async fn import_settings(
+    store: State<'_, SettingsStore>,
+    handshake: State<'_, Handshake>,
+    path: String,
+    merge: bool,
+    dry_run: Option<bool>,
+) -> Result<ImportResult, String> {
+    handshake.ensure()?;
+    import_from(&store, Path::new(&path), merge, dry_run.unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SETTINGS_FILE_NAME;
+    use crate::test_support::temp_dir;
+    use serde_json::json;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    #[test]
+    fn test_export_leaves_out_secrets() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        store
+            .update(json!({ "dev_mode": true, "metrics": { "token": TOKEN } }))
+            .unwrap();
+
+        let path = dir.join("profile.json");
+        export_to(&store, &path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains(TOKEN));
+        let profile = SettingsProfile::parse(&contents).unwrap();
+        assert_eq!(profile.version, PROFILE_VERSION);
+        assert_eq!(profile.settings["dev_mode"], json!(true));
+    }
+
+    #[test]
+    fn test_service_env_values_stay_on_this_machine() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        store
+            .update(json!({ "service_env": { "API_KEY": TOKEN } }))
+            .unwrap();
+
+        let path = dir.join("profile.json");
+        export_to(&store, &path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains(TOKEN));
+        assert!(!contents.contains("API_KEY"));
+
+        // Including from a profile written before they were left out
+        let mut profile = SettingsProfile::parse(&contents).unwrap();
+        profile
+            .settings
+            .insert("service_env".into(), json!({ "API_KEY": "theirs" }));
+        let imported = profile.apply_to(&store.get(), false).unwrap();
+        assert_eq!(imported.service_env["API_KEY"], TOKEN);
+    }
+
+    #[test]
+    fn test_machine_paths_are_not_carried_over() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        store
+            .update(json!({ "compose_override_path": "dev.override.yml" }))
+            .unwrap();
+
+        let path = dir.join("profile.json");
+        export_to(&store, &path).unwrap();
+        let exported = SettingsProfile::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        for key in MACHINE_KEYS {
+            assert!(!exported.settings.contains_key(key), "{}", key);
+        }
+
+        // Paths from another machine would fail validation here
+        let profile = json!({
+            "format": PROFILE_FORMAT,
+            "version": 1,
+            "app_version": "1.0.0",
+            "exported_at": "2026-01-01T00:00:00Z",
+            "settings": {
+                "dev_mode": true,
+                "project_root": "/home/someone-else/arbor",
+                "tool_paths": { "make": "/opt/elsewhere/make" },
+            },
+        });
+        std::fs::write(&path, profile.to_string()).unwrap();
+        let result = import_from(&store, &path, false, false).unwrap();
+        assert_eq!(result.skipped, ["project_root", "tool_paths"]);
+        let settings = store.get();
+        assert!(settings.dev_mode);
+        assert_eq!(settings.project_root, None);
+        assert_eq!(
+            settings.compose_override_path,
+            Some(PathBuf::from("dev.override.yml"))
+        );
+    }
+
+    #[test]
+    fn test_import_diffs_backs_up_and_keeps_local_secrets() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        store
+            .update(json!({ "assume_metered": true, "metrics": { "token": TOKEN } }))
+            .unwrap();
+        let profile = json!({
+            "format": PROFILE_FORMAT,
+            "version": 1,
+            "app_version": "9.0.0",
+            "exported_at": "2026-01-01T00:00:00Z",
+            "settings": {
+                "dev_mode": true,
+                "metrics": { "listen": "127.0.0.1:9500" },
+                "from_the_future": { "x": 1 },
+            },
+        });
+        let path = dir.join("profile.json");
+        std::fs::write(&path, profile.to_string()).unwrap();
+
+        let preview = import_from(&store, &path, true, true).unwrap();
+        assert!(!preview.applied);
+        let keys: Vec<&str> = preview.changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, ["dev_mode", "from_the_future", "metrics.listen"]);
+        assert!(!store.get().dev_mode);
+
+        let result = import_from(&store, &path, true, false).unwrap();
+        assert!(result.applied);
+        let settings = store.get();
+        assert!(settings.dev_mode && settings.assume_metered);
+        assert_eq!(settings.metrics.token.as_deref(), Some(TOKEN));
+        let backup = std::fs::read_to_string(result.backup.unwrap()).unwrap();
+        assert!(!backup.contains("9500"));
+
+        // The newer version's key is kept, and exported again
+        let reloaded = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        assert_eq!(reloaded.get().unknown["from_the_future"], json!({ "x": 1 }));
+        let exported = dir.join("again.json");
+        export_to(&reloaded, &exported).unwrap();
+        assert!(std::fs::read_to_string(&exported)
+            .unwrap()
+            .contains("from_the_future"));
+
+        // Without merging, what the profile leaves out goes back to defaults
+        let replaced = import_from(&store, &path, false, true).unwrap();
+        assert!(replaced.changes.iter().any(|c| c.key == "assume_metered"));
+    }
+
+    #[test]
+    fn test_import_rejects_bad_profiles_without_writing() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        let path = dir.join("profile.json");
+        let profile = |version: u32, settings: Value| {
+            json!({
+                "format": PROFILE_FORMAT,
+                "version": version,
+                "app_version": "1.0.0",
+                "exported_at": "2026-01-01T00:00:00Z",
+                "settings": settings,
+            })
+            .to_string()
+        };
+
+        for contents in [
+            "{}".to_string(),
+            profile(PROFILE_VERSION + 1, json!({})),
+            profile(1, json!({ "dev_mode": "yes" })),
+            profile(1, json!({ "startup_timeout_secs": 0 })),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            assert!(import_from(&store, &path, true, false).is_err());
+        }
+        assert!(!dir.join(SETTINGS_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_export_only_overwrites_profiles() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        let keys = dir.join("authorized_keys");
+        std::fs::write(&keys, "ssh-ed25519 AAAA\n").unwrap();
+
+        assert!(export_to(&store, &keys)
+            .unwrap_err()
+            .contains("isn't a settings profile"));
+        assert_eq!(
+            std::fs::read_to_string(&keys).unwrap(),
+            "ssh-ed25519 AAAA\n"
+        );
+        let profile = dir.join("profile.json");
+        assert_eq!(
+            export_to(&store, &profile).unwrap(),
+            dir.canonicalize().unwrap().join("profile.json")
+        );
+        assert!(
+            export_to(&store, &profile).is_ok(),
+            "a profile may be replaced"
+        );
+        assert!(export_to(&store, &dir.join("missing/profile.json")).is_err());
+    }
+
+    #[test]
+    fn test_paths_are_resolved_before_use() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let traversing = dir.join("sub/../profile.json");
+        assert_eq!(
+            export_to(&store, &traversing).unwrap(),
+            dir.canonicalize().unwrap().join("profile.json")
+        );
+        assert!(import_from(&store, &traversing, true, true).is_ok());
+        assert!(import_from(&store, &dir.join("sub"), true, true).is_err());
+        assert!(import_from(&store, &dir.join("missing/../profile.json"), true, true).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_are_not_followed_to_other_files() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let store = SettingsStore::load(dir.join(SETTINGS_FILE_NAME));
+        let keys = dir.join("authorized_keys");
+        std::fs::write(&keys, "ssh-ed25519 AAAA\n").unwrap();
+        let link = dir.join("profile.json");
+        std::os::unix::fs::symlink(&keys, &link).unwrap();
+
+        assert!(export_to(&store, &link)
+            .unwrap_err()
+            .contains("Path not permitted"));
+        assert_eq!(
+            std::fs::read_to_string(&keys).unwrap(),
+            "ssh-ed25519 AAAA\n"
+        );
+        // Imports resolve it, and find it isn't a profile
+        assert!(import_from(&store, &link, true, true)
+            .unwrap_err()
+            .contains("Not a settings profile"));
+    }
+}
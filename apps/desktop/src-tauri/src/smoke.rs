@@ -0,0 +1,532 @@
+// Smoke test
+// Answers "can this machine run Arbor at all?" for support: resolve the tools,
+// reach the daemon, get a tiny image, run a container serving HTTP on a random
+// port and fetch from it, round-trip a secret through the keychain and a file
+// through the app data dir. Everything it creates is labelled or named so
+// `clean_up` removes it whether the run passed, failed or was cancelled.
+
+use crate::keyring::{self, KeyringError, SecretStore};
+use crate::process::{CommandRunner, CommandSpec};
+use crate::tools::LocatedTool;
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Duration;
+
+/// Small image with an HTTP server in it
+pub const SMOKE_IMAGE: &str = "busybox:1.36";
+/// Latest report, in the app data dir; copied into diagnostics bundles
+pub const REPORT_FILE_NAME: &str = "smoke-test.json";
+pub const TOTAL_STEPS: usize = 7;
+
+pub const CONTAINER_LABEL: &str = "dev.arbor.smoke-test=1";
+const CONTAINER_PORT: &str = "8080/tcp";
+pub const SECRET_NAME: &str = "smoke_test_secret";
+const FILE_NAME: &str = "smoke-test.tmp";
+const HTTP_ATTEMPTS: usize = 10;
+const HTTP_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum StepOutcome {
+    Passed {
+        detail: String,
+    },
+    Failed {
+        error: String,
+    },
+    /// An earlier step it depends on failed
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmokeStep {
+    pub name: String,
+    #[serde(flatten)]
+    pub outcome: StepOutcome,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmokeReport {
+    pub ran_at: String,
+    pub passed: bool,
+    pub steps: Vec<SmokeStep>,
+    /// Resources that couldn't be removed afterwards
+    pub cleanup_errors: Vec<String>,
+}
+
+/// What the smoke test runs against
+pub struct SmokeEnv<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub secrets: &'a Arc<dyn SecretStore>,
+    pub data_dir: &'a Path,
+    pub tools: &'a [LocatedTool],
+}
+
+#[derive(Default)]
+struct Steps {
+    steps: Vec<SmokeStep>,
+}
+
+impl Steps {
+    async fn run(
+        &mut self,
+        name: &str,
+        work: impl std::future::Future<Output = Result<String, String>>,
+    ) -> bool {
+        let started = Instant::now();
+        let outcome = match work.await {
+            Ok(detail) => StepOutcome::Passed { detail },
+            Err(error) => StepOutcome::Failed { error },
+        };
+        let passed = matches!(outcome, StepOutcome::Passed { .. });
+        self.steps.push(SmokeStep {
+            name: name.to_string(),
+            outcome,
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+        passed
+    }
+
+    fn skip(&mut self, name: &str) {
+        self.steps.push(SmokeStep {
+            name: name.to_string(),
+            outcome: StepOutcome::Skipped,
+            duration_ms: 0,
+        });
+    }
+}
+
+/// Run every step, reporting progress as `(step, name)`, then clean up
+pub async fn run(env: &SmokeEnv<'_>, progress: &(dyn Fn(usize, &str) + Sync)) -> SmokeReport {
+    let ran_at = chrono::Utc::now().to_rfc3339();
+    let mut steps = Steps::default();
+    let mut pulled = false;
+
+    // Docker steps each need the one before
+    let docker_steps: [&str; 5] = [
+        "Resolve tools",
+        "Contact the Docker daemon",
+        "Get the test image",
+        "Run a test container",
+        "Reach the container over HTTP",
+    ];
+    let mut failed = false;
+    let mut container = None;
+    for (index, name) in docker_steps.iter().enumerate() {
+        progress(index + 1, name);
+        if failed {
+            steps.skip(name);
+            continue;
+        }
+        let passed = match index {
+            0 => steps.run(name, async { resolve_tools(env.tools) }).await,
+            1 => steps.run(name, daemon_version(env.runner)).await,
+            2 => {
+                steps
+                    .run(name, async {
+                        let (detail, did_pull) = ensure_image(env.runner).await?;
+                        pulled = did_pull;
+                        Ok(detail)
+                    })
+                    .await
+            }
+            3 => {
+                steps
+                    .run(name, async {
+                        let started = start_container(env.runner).await?;
+                        let detail = format!("Serving on {}", started.1);
+                        container = Some(started);
+                        Ok(detail)
+                    })
+                    .await
+            }
+            _ => {
+                let address = container.as_ref().map(|(_, address)| address.clone());
+                steps
+                    .run(name, async move {
+                        fetch(&address.ok_or("No container address")?).await
+                    })
+                    .await
+            }
+        };
+        failed = !passed;
+    }
+
+    progress(6, "Round-trip a keychain secret");
+    steps
+        .run("Round-trip a keychain secret", async {
+            keyring::call(
+                env.secrets.clone(),
+                "round-trip a secret",
+                round_trip_secret,
+            )
+            .await
+            .map_err(String::from)
+        })
+        .await;
+    progress(7, "Round-trip a file in the app data dir");
+    steps
+        .run("Round-trip a file in the app data dir", async {
+            round_trip_file(env.data_dir)
+        })
+        .await;
+
+    let mut cleanup_errors = clean_up(env).await;
+    if pulled {
+        let rmi = CommandSpec::new("docker").args(["rmi", SMOKE_IMAGE]);
+        if let Err(e) = succeed(env.runner, &rmi).await {
+            cleanup_errors.push(e);
+        }
+    }
+
+    SmokeReport {
+        ran_at,
+        passed: steps
+            .steps
+            .iter()
+            .all(|step| matches!(step.outcome, StepOutcome::Passed { .. })),
+        steps: steps.steps,
+        cleanup_errors,
+    }
+}
+
+/// Remove the test container, secret and file, returning what couldn't be
+/// A pulled test image is only removed by `run`, which knows it pulled it.
+pub async fn clean_up(env: &SmokeEnv<'_>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let label = format!("label={}", CONTAINER_LABEL);
+    let list = CommandSpec::new("docker").args(["ps", "-aq", "--filter", &label]);
+    match succeed(env.runner, &list).await {
+        Ok(ids) => {
+            let ids: Vec<&str> = ids.split_whitespace().collect();
+            if !ids.is_empty() {
+                let remove = CommandSpec::new("docker").args(["rm", "-f"]).args(ids);
+                if let Err(e) = succeed(env.runner, &remove).await {
+                    errors.push(e);
+                }
+            }
+        }
+        // No daemon means no container was started either
+        Err(e) => warn!("⚠️  Smoke test cleanup couldn't list containers: {}", e),
+    }
+    let delete = keyring::call(
+        env.secrets.clone(),
+        "delete the smoke test secret",
+        |secrets| secrets.delete(SECRET_NAME),
+    );
+    if let Err(e) = delete.await {
+        errors.push(e.to_string());
+    }
+    match std::fs::remove_file(env.data_dir.join(FILE_NAME)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            errors.push(format!("Failed to remove {}: {}", FILE_NAME, e))
+        }
+        _ => {}
+    }
+    errors
+}
+
+/// Run `spec`, returning its trimmed stdout, or stderr as the error
+async fn succeed(runner: &dyn CommandRunner, spec: &CommandSpec) -> Result<String, String> {
+    let output = runner
+        .output(spec)
+        .await
+        .map_err(|e| format!("Failed to run {}: {}", spec.command_line(), e))?;
+    if !output.success() {
+        return Err(format!(
+            "{} failed: {}",
+            spec.command_line(),
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(output.stdout_text().trim().to_string())
+}
+
+fn resolve_tools(tools: &[LocatedTool]) -> Result<String, String> {
+    let missing: Vec<String> = tools
+        .iter()
+        .filter(|tool| tool.path.is_none() && tool.error.is_some())
+        .map(|tool| format!("{:?}: {}", tool.tool, tool.error.as_deref().unwrap_or("")))
+        .collect();
+    match (tools.is_empty(), missing.is_empty()) {
+        (true, _) => Err("Tools haven't been located".to_string()),
+        (false, true) => Ok(format!("{} tools found", tools.len())),
+        (false, false) => Err(missing.join("; ")),
+    }
+}
+
+async fn daemon_version(runner: &dyn CommandRunner) -> Result<String, String> {
+    let version = CommandSpec::new("docker").args(["version", "--format", "{{.Server.Version}}"]);
+    succeed(runner, &version)
+        .await
+        .map(|version| format!("Docker {}", version))
+}
+
+/// Use the image if it's already there (e.g. offline), otherwise pull it
+async fn ensure_image(runner: &dyn CommandRunner) -> Result<(String, bool), String> {
+    let inspect = CommandSpec::new("docker").args(["image", "inspect", SMOKE_IMAGE]);
+    if succeed(runner, &inspect).await.is_ok() {
+        return Ok((format!("{} already present", SMOKE_IMAGE), false));
+    }
+    let pull = CommandSpec::new("docker").args(["pull", SMOKE_IMAGE]);
+    succeed(runner, &pull).await?;
+    Ok((format!("Pulled {}", SMOKE_IMAGE), true))
+}
+
+/// Start busybox httpd on a random loopback port, returning its id and address
+async fn start_container(runner: &dyn CommandRunner) -> Result<(String, String), String> {
+    let run = CommandSpec::new("docker").args([
+        "run",
+        "-d",
+        "--label",
+        CONTAINER_LABEL,
+        "-p",
+        "127.0.0.1::8080",
+        SMOKE_IMAGE,
+        "httpd",
+        "-f",
+        "-p",
+        "8080",
+        "-h",
+        "/etc",
+    ]);
+    let id = succeed(runner, &run).await?;
+    let port = CommandSpec::new("docker").args(["port", &id, CONTAINER_PORT]);
+    let address = succeed(runner, &port).await?;
+    // One line per address family; the loopback binding only gives IPv4
+    let address = address
+        .lines()
+        .next()
+        .ok_or_else(|| "Container has no published port".to_string())?
+        .to_string();
+    Ok((id, address))
+}
+
+/// GET a file busybox always has, retrying while httpd starts
+async fn fetch(address: &str) -> Result<String, String> {
+    let mut last_error = String::new();
+    for attempt in 0..HTTP_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(HTTP_RETRY_DELAY).await;
+        }
+        match get(address).await {
+            Ok(status) if status.contains(" 200") => return Ok(status),
+            Ok(status) => last_error = format!("Unexpected response: {}", status),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+async fn get(address: &str) -> Result<String, String> {
+    let mut stream = tokio::net::TcpStream::connect(address)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+    let request = format!(
+        "GET /hostname HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        address
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .await
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+    let response = String::from_utf8_lossy(&response);
+    Ok(response.lines().next().unwrap_or_default().to_string())
+}
+
+fn round_trip_secret(secrets: &dyn SecretStore) -> Result<String, KeyringError> {
+    let value = format!("smoke-{}", std::process::id());
+    secrets.set(SECRET_NAME, &value)?;
+    let read = secrets.get(SECRET_NAME)?;
+    secrets.delete(SECRET_NAME)?;
+    if read != value {
+        return Err(KeyringError::Io {
+            detail: "The keychain returned a different secret".to_string(),
+        });
+    }
+    Ok("Secret written, read back and deleted".to_string())
+}
+
+fn round_trip_file(data_dir: &Path) -> Result<String, String> {
+    std::fs::create_dir_all(data_dir)
+        .map_err(|e| format!("Failed to create {:?}: {}", data_dir, e))?;
+    let path = data_dir.join(FILE_NAME);
+    let value = format!("smoke-{}", std::process::id());
+    std::fs::write(&path, &value).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    let read =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {:?}: {}", path, e))?;
+    if read != value {
+        return Err(format!("{:?} read back differently", path));
+    }
+    Ok(format!("Wrote and read {:?}", path))
+}
+
+fn report_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(REPORT_FILE_NAME)
+}
+
+pub fn save_report(data_dir: &Path, report: &SmokeReport) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(report).map_err(|e| e.to_string())?;
+    std::fs::write(report_path(data_dir), contents)
+        .map_err(|e| format!("Failed to save smoke test report: {}", e))
+}
+
+/// The last saved report, if any
+pub fn load_report(data_dir: &Path) -> Option<SmokeReport> {
+    let contents = std::fs::read_to_string(report_path(data_dir)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyring::mock::MemorySecrets;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::test_support::temp_dir;
+    use crate::tools::Tool;
+
+    fn tools() -> Vec<LocatedTool> {
+        vec![LocatedTool {
+            tool: Tool::Docker,
+            path: Some(PathBuf::from("/usr/bin/docker")),
+            source: None,
+            error: None,
+        }]
+    }
+
+    fn reply(code: i32, stdout: &str) -> std::io::Result<CommandOutput> {
+        Ok(CommandOutput {
+            code: Some(code),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        })
+    }
+
+    /// A stand-in for the container's httpd
+    async fn http_server() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(b"HTTP/1.0 200 OK\r\n\r\nsmoke\n").await;
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_passing_run_pulls_then_removes_everything() {
+        let address = http_server().await;
+        let runner = MockRunner::new(move |spec| match spec.args[0].as_str() {
+            "version" => reply(0, "27.1.0\n"),
+            "image" => reply(1, ""),
+            "run" => reply(0, "c0ffee\n"),
+            "port" => reply(0, &format!("{}\n", address)),
+            "ps" => reply(0, "c0ffee\n"),
+            _ => reply(0, ""),
+        });
+        let secrets: Arc<dyn SecretStore> = Arc::new(MemorySecrets::default());
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let tools = tools();
+        let env = SmokeEnv {
+            runner: &runner,
+            secrets: &secrets,
+            data_dir: dir,
+            tools: &tools,
+        };
+
+        let report = run(&env, &|_, _| {}).await;
+        assert!(report.passed, "{:?}", report);
+        assert_eq!(report.steps.len(), TOTAL_STEPS);
+        assert!(report.cleanup_errors.is_empty());
+
+        let commands: Vec<String> = runner
+            .calls()
+            .iter()
+            .map(|call| call.args[..2].join(" "))
+            .collect();
+        assert!(commands.contains(&"pull busybox:1.36".to_string()));
+        assert!(commands.contains(&"rm -f".to_string()));
+        assert!(commands.contains(&"rmi busybox:1.36".to_string()));
+        assert!(!dir.join(FILE_NAME).exists());
+    }
+
+    #[tokio::test]
+    async fn test_daemon_failure_skips_docker_steps_but_still_cleans_up() {
+        let runner = MockRunner::new(|spec| match spec.args[0].as_str() {
+            "ps" => reply(0, "leftover\n"),
+            "rm" => reply(0, ""),
+            _ => Ok(CommandOutput {
+                code: Some(1),
+                stdout: Vec::new(),
+                stderr: b"Cannot connect to the Docker daemon".to_vec(),
+            }),
+        });
+        let secrets: Arc<dyn SecretStore> = Arc::new(MemorySecrets::default());
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let tools = tools();
+        let env = SmokeEnv {
+            runner: &runner,
+            secrets: &secrets,
+            data_dir: dir,
+            tools: &tools,
+        };
+
+        let report = run(&env, &|_, _| {}).await;
+        assert!(!report.passed);
+        let outcomes: Vec<&StepOutcome> = report.steps.iter().map(|step| &step.outcome).collect();
+        assert!(matches!(outcomes[0], StepOutcome::Passed { .. }));
+        assert!(matches!(outcomes[1], StepOutcome::Failed { error } if error.contains("daemon")));
+        assert!(outcomes[2..5]
+            .iter()
+            .all(|outcome| **outcome == StepOutcome::Skipped));
+        assert!(matches!(outcomes[5], StepOutcome::Passed { .. }));
+        assert!(matches!(outcomes[6], StepOutcome::Passed { .. }));
+
+        // A container from an earlier, interrupted run is swept up; the image
+        // wasn't pulled, so it stays
+        let calls = runner.calls();
+        assert!(calls
+            .iter()
+            .any(|call| call.args == ["rm", "-f", "leftover"]));
+        assert!(!calls.iter().any(|call| call.args[0] == "rmi"));
+    }
+
+    #[test]
+    fn test_report_round_trips() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir).unwrap();
+        let report = SmokeReport {
+            ran_at: "2026-01-01T00:00:00Z".to_string(),
+            passed: false,
+            steps: vec![SmokeStep {
+                name: "Contact the Docker daemon".to_string(),
+                outcome: StepOutcome::Failed {
+                    error: "no daemon".to_string(),
+                },
+                duration_ms: 3,
+            }],
+            cleanup_errors: Vec::new(),
+        };
+
+        save_report(dir, &report).unwrap();
+        assert_eq!(load_report(dir), Some(report));
+    }
+}
@@ -0,0 +1,139 @@
+// Full state snapshot
+// After a webview reload (or crash recovery) the frontend re-registers its
+// listeners, but events emitted in the gap are lost. `get_full_state` returns
+// everything the UI renders in one go, stamped with the event sequence, so the
+// frontend fetches it on mount and drops events the snapshot already covers.
+
+use crate::compat::{VersionGate, VersionOverride};
+use crate::docker::ContainerStatus;
+use crate::errors::ErrorHistory;
+use crate::events;
+use crate::jobs::{Job, JobManager, MeteredPrompt};
+use crate::keyring::{KeyringHealth, KeyringStatus};
+use crate::lifecycle::{Lifecycle, StopReason};
+use crate::services::StackState;
+use crate::settings::SettingsStore;
+use crate::splash::{Splash, StartupStage};
+use crate::status::StatusCache;
+use crate::warn;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FullState {
+    /// Events with a `state_seq` at or below this are reflected here already
+    pub state_seq: u64,
+    pub startup: StartupStage,
+    pub stack: StackState,
+    pub safe_mode: bool,
+    /// App version that started the running stack, when it's known
+    pub stack_version: Option<String>,
+    /// Set once the user let an incompatible stack run this session
+    pub version_override: Option<VersionOverride>,
+    /// `None` when docker couldn't be asked
+    pub containers: Option<Vec<ContainerStatus>>,
+    /// Active `arbor.toml` profile
+    pub profile: Option<String>,
+    pub endpoints: Vec<Endpoint>,
+    /// Jobs still running, and the metered download prompts they wait on
+    pub jobs: Vec<Job>,
+    pub prompts: Vec<MeteredPrompt>,
+    pub keyring: Option<KeyringHealth>,
+    pub unread_errors: usize,
+    pub last_stop_reason: Option<StopReason>,
+}
+
+/// A published port of a running container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Endpoint {
+    pub container: String,
+    pub address: String,
+}
+
+fn endpoints(containers: &[ContainerStatus]) -> Vec<Endpoint> {
+    containers
+        .iter()
+        .filter(|container| container.is_running())
+        .flat_map(|container| {
+            container.ports.iter().map(|port| Endpoint {
+                container: container.name.clone(),
+                address: format!("localhost:{}", port),
+            })
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub async fn get_full_state(app_handle: AppHandle) -> Result<FullState, String> {
+    // Taken before anything is read: state changes before its event is
+    // emitted, so any event newer than this is kept even if the snapshot
+    // already shows its change, and none the snapshot misses is dropped
+    let state_seq = events::state_seq();
+
+    let containers = match app_handle.state::<StatusCache>().get().await {
+        Ok(status) => Some(status.containers),
+        Err(e) => {
+            warn!(
+                "⚠️  Failed to read container status for the snapshot: {}",
+                e
+            );
+            None
+        }
+    };
+    let jobs = app_handle.state::<JobManager>();
+    let service_manager = app_handle.state::<crate::ServiceManager>();
+    let stack = *service_manager.stack.lock();
+    let stack_version = service_manager.stack_version.lock().clone();
+
+    Ok(FullState {
+        state_seq,
+        startup: app_handle.state::<Splash>().stage(),
+        stack,
+        safe_mode: service_manager.in_safe_mode(),
+        stack_version,
+        version_override: app_handle.state::<VersionGate>().overridden(),
+        endpoints: containers.as_deref().map(endpoints).unwrap_or_default(),
+        containers,
+        profile: app_handle.state::<SettingsStore>().get().profile,
+        jobs: jobs
+            .running_ids()
+            .iter()
+            .filter_map(|id| jobs.get(id))
+            .collect(),
+        prompts: jobs.pending_prompts(),
+        keyring: app_handle.state::<KeyringStatus>().health(),
+        unread_errors: app_handle.state::<ErrorHistory>().unread_count(),
+        last_stop_reason: app_handle.state::<Lifecycle>().last_stop(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(name: &str, state: &str, ports: Vec<u16>) -> ContainerStatus {
+        ContainerStatus {
+            name: name.to_string(),
+            image: String::new(),
+            state: state.to_string(),
+            status: String::new(),
+            ports,
+            health: None,
+            started_at: None,
+        }
+    }
+
+    #[test]
+    fn test_endpoints_of_running_containers() {
+        let containers = [
+            container("arbor-api", "running", vec![3000, 9229]),
+            container("arbor-redis", "exited", vec![6379]),
+        ];
+
+        let addresses: Vec<String> = endpoints(&containers)
+            .into_iter()
+            .map(|endpoint| endpoint.address)
+            .collect();
+        assert_eq!(addresses, ["localhost:3000", "localhost:9229"]);
+    }
+}
@@ -0,0 +1,294 @@
+// Native splash window and startup lifecycle events
+// A small window shown from the setup hook, before the main webview has
+// loaded anything. It's driven entirely from Rust: each startup stage is
+// emitted as an event for the frontend and pushed into the splash page. The
+// main window stays hidden until the frontend reports it has rendered.
+
+use crate::events::{self, EventSink};
+use crate::warn;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tauri::{App, AppHandle, Manager, WebviewUrl, WebviewWindowBuilder};
+use tokio::sync::watch;
+use tokio::time::Duration;
+
+pub const APP_STARTING_EVENT: &str = "app-starting";
+pub const DOCKER_CHECKING_EVENT: &str = "docker-checking";
+pub const SERVICES_STARTING_EVENT: &str = "services-starting";
+/// Followed by the service name, e.g. `service-ready:arbor-postgres`
+pub const SERVICE_READY_EVENT_PREFIX: &str = "service-ready:";
+/// Asks an already rendered frontend to switch screens, with the screen name
+pub const OPEN_SCREEN_EVENT: &str = "open-screen";
+
+pub const MAIN_WINDOW: &str = "main";
+pub const SPLASH_WINDOW: &str = "splash";
+const SPLASH_SCHEME: &str = "arbor-splash";
+const SPLASH_HTML: &str = include_str!("splash.html");
+
+/// How long the main window may take to render once services are ready,
+/// before it's shown anyway so the splash never outlives a broken frontend
+const FRONTEND_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where startup has got to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum StartupStage {
+    Starting,
+    DockerChecking,
+    ServicesStarting,
+    ServiceReady {
+        service: String,
+    },
+    ServicesReady,
+    /// A stack Arbor didn't start is already running; the main window offers adopting it
+    StackFound { services: usize },
+    Failed {
+        message: String,
+        /// Docker is missing or unusable, so the setup screen is the way forward
+        setup_required: bool,
+    },
+}
+
+impl StartupStage {
+    /// The lifecycle event for this stage; the terminal stages already go out
+    /// as `services-ready` / `services-failed` / `docker-unavailable` with
+    /// richer payloads
+    fn event_name(&self) -> Option<String> {
+        match self {
+            StartupStage::Starting => Some(APP_STARTING_EVENT.to_string()),
+            StartupStage::DockerChecking => Some(DOCKER_CHECKING_EVENT.to_string()),
+            StartupStage::ServicesStarting => Some(SERVICES_STARTING_EVENT.to_string()),
+            StartupStage::ServiceReady { service } => {
+                Some(format!("{}{}", SERVICE_READY_EVENT_PREFIX, service))
+            }
+            StartupStage::ServicesReady
+            | StartupStage::StackFound { .. }
+            | StartupStage::Failed { .. } => None,
+        }
+    }
+
+    /// Nothing left for the splash to show once the frontend is up
+    fn hands_over(&self) -> bool {
+        matches!(self, StartupStage::ServicesReady | StartupStage::StackFound { .. })
+    }
+}
+
+/// Startup progress and whether the main window has taken over
+pub struct Splash {
+    stage: watch::Sender<StartupStage>,
+    rendered: AtomicBool,
+    /// Screen the user picked on the splash before the frontend had rendered
+    screen: Mutex<Option<String>>,
+}
+
+impl Default for Splash {
+    fn default() -> Self {
+        Self {
+            stage: watch::channel(StartupStage::Starting).0,
+            rendered: AtomicBool::new(false),
+            screen: Mutex::new(None),
+        }
+    }
+}
+
+impl Splash {
+    /// Record a stage and emit its lifecycle event
+    pub fn record(&self, sink: &dyn EventSink, stage: StartupStage) {
+        if let Some(event) = stage.event_name() {
+            events::emit(sink, &event, &stage);
+        }
+        self.stage.send_replace(stage);
+    }
+
+    pub fn stage(&self) -> StartupStage {
+        self.stage.borrow().clone()
+    }
+
+    /// Frontend rendered and startup handed over to it
+    /// A failure keeps the splash up so its actions stay reachable.
+    fn ready_to_swap(&self) -> bool {
+        self.rendered.load(Ordering::SeqCst) && self.stage().hands_over()
+    }
+}
+
+/// Open the splash window; the main window starts hidden (see tauri.conf.json)
+pub fn open(app: &App) -> tauri::Result<()> {
+    #[cfg(windows)]
+    let url = format!("http://{}.localhost/", SPLASH_SCHEME);
+    #[cfg(not(windows))]
+    let url = format!("{}://localhost/", SPLASH_SCHEME);
+
+    WebviewWindowBuilder::new(
+        app,
+        SPLASH_WINDOW,
+        WebviewUrl::CustomProtocol(url.parse().expect("splash URL is valid")),
+    )
+    .title("Arbor")
+    .inner_size(420.0, 260.0)
+    .resizable(false)
+    .decorations(false)
+    .center()
+    .on_page_load(|window, payload| {
+        // Catch the page up on whatever happened while it loaded
+        if payload.event() == tauri::webview::PageLoadEvent::Finished {
+            refresh(window.app_handle());
+        }
+    })
+    .build()?;
+    Ok(())
+}
+
+/// Serves the splash page; its buttons navigate to `/open[?screen=...]`
+pub fn handle_request(app_handle: &AppHandle, path: &str, query: Option<&str>) -> Vec<u8> {
+    if path == "/open" {
+        let screen = query
+            .and_then(|q| q.split('&').find_map(|pair| pair.strip_prefix("screen=")))
+            .map(str::to_string);
+        // Not from inside the splash's own request handler, which it destroys
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move { show_main(&app_handle, screen) });
+    }
+    SPLASH_HTML.as_bytes().to_vec()
+}
+
+/// Move startup on to `stage`, updating the splash and swapping to the main
+/// window if it's ready
+pub fn advance(app_handle: &AppHandle, stage: StartupStage) {
+    let splash = app_handle.state::<Splash>();
+    splash.record(app_handle, stage);
+    refresh(app_handle);
+    if splash.ready_to_swap() {
+        show_main(app_handle, None);
+    }
+}
+
+fn refresh(app_handle: &AppHandle) {
+    let Some(window) = app_handle.get_webview_window(SPLASH_WINDOW) else {
+        return;
+    };
+    let stage = app_handle.state::<Splash>().stage();
+    match serde_json::to_string(&stage) {
+        Ok(json) => {
+            if let Err(e) = window.eval(format!(
+                "window.arborSplash && window.arborSplash({})",
+                json
+            )) {
+                warn!("⚠️  Failed to update splash: {}", e);
+            }
+        }
+        Err(e) => warn!("⚠️  Failed to serialize startup stage: {}", e),
+    }
+}
+
+/// Show the main window (optionally on `screen`) and close the splash
+pub fn show_main(app_handle: &AppHandle, screen: Option<String>) {
+    if let Some(screen) = screen {
+        let splash = app_handle.state::<Splash>();
+        if splash.rendered.load(Ordering::SeqCst) {
+            events::emit(app_handle, OPEN_SCREEN_EVENT, &screen);
+        } else {
+            *splash.screen.lock().unwrap() = Some(screen);
+        }
+    }
+    if let Some(main) = app_handle.get_webview_window(MAIN_WINDOW) {
+        if let Err(e) = main.show().and_then(|_| main.set_focus()) {
+            warn!("⚠️  Failed to show main window: {}", e);
+        }
+    }
+    close(app_handle);
+}
+
+/// Close the splash if it's still open
+pub fn close(app_handle: &AppHandle) {
+    if let Some(window) = app_handle.get_webview_window(SPLASH_WINDOW) {
+        // destroy() rather than close(): no CloseRequested round trip
+        if let Err(e) = window.destroy() {
+            warn!("⚠️  Failed to close splash: {}", e);
+        }
+    }
+}
+
+/// Show the main window anyway if the frontend never reports rendering
+/// After a failure the splash offers its own way out, so only a hand-over counts.
+pub async fn watchdog(app_handle: AppHandle) {
+    let mut stage = app_handle.state::<Splash>().stage.subscribe();
+    if stage
+        .wait_for(StartupStage::hands_over)
+        .await
+        .is_err()
+    {
+        return;
+    }
+    tokio::time::sleep(FRONTEND_READY_TIMEOUT).await;
+    if !app_handle.state::<Splash>().rendered.load(Ordering::SeqCst)
+        && app_handle.get_webview_window(SPLASH_WINDOW).is_some()
+    {
+        warn!(
+            "⚠️  Frontend didn't report rendering within {}s; showing the main window",
+            FRONTEND_READY_TIMEOUT.as_secs()
+        );
+        show_main(&app_handle, None);
+    }
+}
+
+/// Called by the frontend once it has rendered; returns the screen the user
+/// picked on the splash, if any
+#[tauri::command]
+pub async fn frontend_ready(app_handle: AppHandle) -> Result<Option<String>, String> {
+    let splash = app_handle.state::<Splash>();
+    splash.rendered.store(true, Ordering::SeqCst);
+    if splash.ready_to_swap() {
+        show_main(&app_handle, None);
+    }
+    let screen = splash.screen.lock().unwrap().take();
+    Ok(screen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+
+    #[test]
+    fn test_stages_emit_lifecycle_events() {
+        let sink = RecordingSink::default();
+        let splash = Splash::default();
+
+        splash.record(&sink, StartupStage::DockerChecking);
+        splash.record(
+            &sink,
+            StartupStage::ServiceReady {
+                service: "arbor-postgres".to_string(),
+            },
+        );
+        splash.record(&sink, StartupStage::ServicesReady);
+
+        assert_eq!(sink.events_named(DOCKER_CHECKING_EVENT).len(), 1);
+        let ready = sink.events_named("service-ready:arbor-postgres");
+        assert_eq!(ready[0]["stage"], "service_ready");
+        assert_eq!(ready[0]["service"], "arbor-postgres");
+        assert_eq!(splash.stage(), StartupStage::ServicesReady);
+    }
+
+    #[test]
+    fn test_swap_waits_for_render_and_ready_services() {
+        let sink = RecordingSink::default();
+        let splash = Splash::default();
+        splash.record(&sink, StartupStage::ServicesStarting);
+        splash.rendered.store(true, Ordering::SeqCst);
+        assert!(!splash.ready_to_swap(), "Services still starting");
+
+        splash.record(
+            &sink,
+            StartupStage::Failed {
+                message: "Docker is not installed".to_string(),
+                setup_required: true,
+            },
+        );
+        assert!(!splash.ready_to_swap(), "A failure stays on the splash");
+
+        splash.record(&sink, StartupStage::ServicesReady);
+        assert!(splash.ready_to_swap());
+    }
+}
@@ -0,0 +1,508 @@
+// Container resource stats
+// Sampled periodically from `docker stats`; the most recent samples per
+// container are kept so other features (crash reports, post-mortems) can say
+// what a container was using before something went wrong.
+// `get_services_resource_usage` takes a fresh sample with the stack's totals,
+// and while the frontend watches (`watch_resource_usage`) one is emitted as
+// `resource-usage` every few seconds, for the usage graph.
+
+use crate::docker::{self, DockerError};
+use crate::events::{self, EventSink};
+use crate::messages::{Coded, Message};
+use crate::process::{CommandRunner, CommandSpec};
+use crate::tasks::TaskManager;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, State};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Emitted with a ResourceUsage on every sample while watched
+pub const RESOURCE_USAGE_EVENT: &str = "resource-usage";
+/// Emitted with a StatsError when a watched sample couldn't be taken
+pub const RESOURCE_USAGE_FAILED_EVENT: &str = "resource-usage-failed";
+/// Time between samples while watched
+const WATCH_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Bytes received and sent (network), or read and written (block devices)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct IoBytes {
+    pub in_bytes: u64,
+    pub out_bytes: u64,
+}
+
+impl std::ops::Add for IoBytes {
+    type Output = IoBytes;
+
+    fn add(self, other: IoBytes) -> IoBytes {
+        IoBytes {
+            in_bytes: self.in_bytes + other.in_bytes,
+            out_bytes: self.out_bytes + other.out_bytes,
+        }
+    }
+}
+
+/// One container's usage in a `docker stats` sample
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContainerStats {
+    pub name: String,
+    pub cpu_percent: f64,
+    pub memory_usage_bytes: u64,
+    pub memory_limit_bytes: u64,
+    /// Since the container started
+    pub net_io: IoBytes,
+    /// Since the container started
+    pub block_io: IoBytes,
+    /// RFC 3339 time the sample was taken
+    pub sampled_at: String,
+}
+
+/// Usage of the whole stack, summed over its containers
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageTotals {
+    pub cpu_percent: f64,
+    pub memory_usage_bytes: u64,
+    pub net_io: IoBytes,
+    pub block_io: IoBytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResourceUsage {
+    pub containers: Vec<ContainerStats>,
+    pub total: UsageTotals,
+}
+
+impl ResourceUsage {
+    fn of(containers: Vec<ContainerStats>) -> Self {
+        let total = containers
+            .iter()
+            .fold(UsageTotals::default(), |total, stats| UsageTotals {
+                cpu_percent: total.cpu_percent + stats.cpu_percent,
+                memory_usage_bytes: total.memory_usage_bytes + stats.memory_usage_bytes,
+                net_io: total.net_io + stats.net_io,
+                block_io: total.block_io + stats.block_io,
+            });
+        Self { containers, total }
+    }
+}
+
+/// Why no sample could be taken
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StatsError {
+    /// Docker can't be used, as `check_docker_status` would report it
+    Docker { error: DockerError },
+    /// Docker is fine but `docker stats` failed anyway
+    Failed { detail: String },
+}
+
+impl fmt::Display for StatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatsError::Docker { error } => write!(f, "{}", error),
+            StatsError::Failed { detail } => {
+                write!(f, "Failed to sample container stats: {}", detail)
+            }
+        }
+    }
+}
+
+impl Coded for StatsError {
+    fn message(&self) -> Message {
+        match self {
+            StatsError::Docker { error } => error.message(),
+            StatsError::Failed { detail } => {
+                Message::new("stats-failed").with_param("detail", detail)
+            }
+        }
+    }
+}
+
+/// Samples kept per container
+const HISTORY_LEN: usize = 20;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StatsLine {
+    name: String,
+    #[serde(rename = "CPUPerc")]
+    cpu_perc: String,
+    mem_usage: String,
+    #[serde(rename = "NetIO", default)]
+    net_io: String,
+    #[serde(rename = "BlockIO", default)]
+    block_io: String,
+}
+
+/// Recent stats samples of every arbor container
+#[derive(Clone)]
+pub struct StatsSampler {
+    runner: Arc<dyn CommandRunner>,
+    history: Arc<Mutex<HashMap<String, VecDeque<ContainerStats>>>>,
+}
+
+impl StatsSampler {
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self {
+            runner,
+            history: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take a sample, add it to each container's history and return it
+    pub async fn sample(&self) -> Result<Vec<ContainerStats>, StatsError> {
+        let sample = match self.read_stats().await {
+            // A container went away between docker listing and reading it
+            Err(detail) if detail.contains("No such container") => self.read_stats().await,
+            sample => sample,
+        };
+        let sample = match sample {
+            Ok(sample) => sample,
+            Err(detail) => {
+                // A stopped daemon is reported as such, not as a failed parse
+                return Err(match docker::check_status(&*self.runner).await {
+                    Err(error) => StatsError::Docker { error },
+                    Ok(_) => StatsError::Failed { detail },
+                });
+            }
+        };
+
+        let mut history = self.history.lock().unwrap();
+        for stats in &sample {
+            let samples = history.entry(stats.name.clone()).or_default();
+            if samples.len() == HISTORY_LEN {
+                samples.pop_front();
+            }
+            samples.push_back(stats.clone());
+        }
+        Ok(sample)
+    }
+
+    /// A fresh sample with the stack's totals
+    pub async fn usage(&self) -> Result<ResourceUsage, StatsError> {
+        let mut sample = self.sample().await?;
+        sample.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ResourceUsage::of(sample))
+    }
+
+    async fn read_stats(&self) -> Result<Vec<ContainerStats>, String> {
+        let spec = CommandSpec::new("docker").args([
+            "stats",
+            "--no-stream",
+            "--filter",
+            "name=arbor",
+            "--format",
+            "{{json .}}",
+        ]);
+        let output = self.runner.output(&spec).await.map_err(|e| e.to_string())?;
+        if !output.success() {
+            return Err(output.stderr_text().trim().to_string());
+        }
+
+        let sampled_at = chrono::Utc::now().to_rfc3339();
+        Ok(output
+            .stdout_text()
+            .lines()
+            .filter_map(|line| parse_stats_line(line, &sampled_at))
+            .collect())
+    }
+
+    /// Last sample taken for `container`, if any
+    pub fn latest(&self, container: &str) -> Option<ContainerStats> {
+        self.history.lock().unwrap().get(container)?.back().cloned()
+    }
+
+    /// Recent samples for `container`, oldest first
+    pub fn recent(&self, container: &str) -> Vec<ContainerStats> {
+        self.history
+            .lock()
+            .unwrap()
+            .get(container)
+            .map(|samples| samples.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Last sample of every container, by name
+    pub fn snapshot(&self) -> Vec<ContainerStats> {
+        let mut stats: Vec<_> = self
+            .history
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|samples| samples.back().cloned())
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+}
+
+/// Emit a sample of `sampler` on `sink` every `interval`, until cancelled
+pub async fn poll_usage(sampler: StatsSampler, sink: &dyn EventSink, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        match sampler.usage().await {
+            Ok(usage) => events::emit(sink, RESOURCE_USAGE_EVENT, &usage),
+            Err(e) => events::emit_coded(sink, RESOURCE_USAGE_FAILED_EVENT, &e),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Watchers {
+    count: usize,
+    task: Option<CancellationToken>,
+}
+
+/// Frontend subscriptions to `resource-usage`; it's sampled while any remain
+#[derive(Default)]
+pub struct UsageWatch {
+    watchers: Mutex<Watchers>,
+}
+
+#[tauri::command]
+pub async fn get_container_stats(
+    sampler: State<'_, StatsSampler>,
+) -> Result<Vec<ContainerStats>, String> {
+    Ok(sampler.snapshot())
+}
+
+/// Each arbor container's current usage, with the stack's totals
+#[tauri::command]
+pub async fn get_services_resource_usage(
+    sampler: State<'_, StatsSampler>,
+) -> Result<ResourceUsage, StatsError> {
+    sampler.usage().await
+}
+
+/// Emit `resource-usage` every few seconds until `unwatch_resource_usage`
+#[tauri::command]
+pub async fn watch_resource_usage(
+    app_handle: AppHandle,
+    watch: State<'_, UsageWatch>,
+    sampler: State<'_, StatsSampler>,
+) -> Result<(), String> {
+    let mut watchers = watch.watchers.lock().unwrap();
+    watchers.count += 1;
+    if watchers.task.is_none() {
+        let sampler = sampler.inner().clone();
+        let sink = app_handle.clone();
+        let task = app_handle
+            .state::<TaskManager>()
+            .spawn("resource_usage", async move {
+                poll_usage(sampler, &sink, WATCH_INTERVAL).await
+            });
+        watchers.task = Some(task);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn unwatch_resource_usage(watch: State<'_, UsageWatch>) -> Result<(), String> {
+    let mut watchers = watch.watchers.lock().unwrap();
+    watchers.count = watchers.count.saturating_sub(1);
+    if watchers.count == 0 {
+        if let Some(task) = watchers.task.take() {
+            task.cancel();
+        }
+    }
+    Ok(())
+}
+
+fn parse_stats_line(line: &str, sampled_at: &str) -> Option<ContainerStats> {
+    let line: StatsLine = serde_json::from_str(line.trim()).ok()?;
+    let (usage, limit) = parse_pair(&line.mem_usage)?;
+
+    Some(ContainerStats {
+        name: line.name,
+        cpu_percent: line.cpu_perc.trim().trim_end_matches('%').parse().ok()?,
+        memory_usage_bytes: usage,
+        memory_limit_bytes: limit,
+        net_io: parse_io(&line.net_io),
+        block_io: parse_io(&line.block_io),
+        sampled_at: sampled_at.to_string(),
+    })
+}
+
+/// Both sizes of `12MiB / 1GiB`
+fn parse_pair(text: &str) -> Option<(u64, u64)> {
+    let (first, second) = text.split_once('/')?;
+    Some((parse_size(first)?, parse_size(second)?))
+}
+
+/// Counters like `1.2kB / 648B`, zero where docker has none
+fn parse_io(text: &str) -> IoBytes {
+    let (in_bytes, out_bytes) = parse_pair(text).unwrap_or_default();
+    IoBytes {
+        in_bytes,
+        out_bytes,
+    }
+}
+
+/// Parse docker's human readable sizes, e.g. `12.5MiB` or `1.2GB`
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let multiplier: f64 = match unit {
+        "" | "B" => 1.0,
+        "kB" | "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((number.parse::<f64>().ok()? * multiplier) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0B"), Some(0));
+        assert_eq!(parse_size("512KiB"), Some(512 * 1024));
+        assert_eq!(parse_size("1.5GiB"), Some(1_610_612_736));
+        assert_eq!(parse_size("2MB"), Some(2_000_000));
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[tokio::test]
+    async fn test_sample_keeps_recent_history_per_container() {
+        let runner = Arc::new(MockRunner::with_stdout(
+            r#"{"Name":"arbor-postgres","CPUPerc":"1.25%","MemUsage":"256MiB / 1GiB"}
+{"Name":"arbor-redis","CPUPerc":"--","MemUsage":"-- / --"}
+"#,
+        ));
+        let sampler = StatsSampler::new(runner);
+
+        sampler.sample().await.unwrap();
+
+        let snapshot = sampler.snapshot();
+        assert_eq!(
+            snapshot.len(),
+            1,
+            "Stopped containers report no usable stats"
+        );
+        assert_eq!(snapshot[0].name, "arbor-postgres");
+        assert_eq!(snapshot[0].cpu_percent, 1.25);
+        assert_eq!(snapshot[0].memory_usage_bytes, 256 * 1024 * 1024);
+        assert_eq!(snapshot[0].memory_limit_bytes, 1024 * 1024 * 1024);
+
+        for _ in 0..HISTORY_LEN + 5 {
+            sampler.sample().await.unwrap();
+        }
+        assert_eq!(sampler.recent("arbor-postgres").len(), HISTORY_LEN);
+    }
+
+    fn stats_runner(stats: fn(usize) -> (i32, &'static str, &'static str)) -> Arc<MockRunner> {
+        let calls = Arc::new(Mutex::new(0));
+        Arc::new(MockRunner::new(move |spec| {
+            let (code, stdout, stderr) = match spec.args[0].as_str() {
+                "stats" => {
+                    let mut calls = calls.lock().unwrap();
+                    *calls += 1;
+                    stats(*calls)
+                }
+                "--version" => (0, "Docker version 24.0.7, build afdd53b", ""),
+                "info" => (0, "24.0.7", ""),
+                _ => (1, "", ""),
+            };
+            Ok(CommandOutput {
+                code: Some(code),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+            })
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_usage_totals_the_stack() {
+        // The first read races a container being removed
+        let runner = stats_runner(|call| match call {
+            1 => (1, "", "Error response from daemon: No such container: 3f2a"),
+            _ => (
+                0,
+                r#"{"Name":"arbor-redis","CPUPerc":"0.50%","MemUsage":"8MiB / 1GiB","NetIO":"1kB / 2kB","BlockIO":"0B / 4.1kB"}
+{"Name":"arbor-postgres","CPUPerc":"1.25%","MemUsage":"256MiB / 1GiB","NetIO":"3kB / 1kB","BlockIO":"12MB / 0B"}
+"#,
+                "",
+            ),
+        });
+
+        let usage = StatsSampler::new(runner).usage().await.unwrap();
+        assert_eq!(usage.containers[0].name, "arbor-postgres");
+        assert_eq!(
+            usage.containers[1].block_io,
+            IoBytes {
+                in_bytes: 0,
+                out_bytes: 4100,
+            }
+        );
+        assert_eq!(usage.total.cpu_percent, 1.75);
+        assert_eq!(usage.total.memory_usage_bytes, 264 * 1024 * 1024);
+        assert_eq!(
+            usage.total.net_io,
+            IoBytes {
+                in_bytes: 4000,
+                out_bytes: 3000,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_told_apart() {
+        let failing = stats_runner(|_| (1, "", "unexpected EOF"));
+        assert_eq!(
+            StatsSampler::new(failing).usage().await,
+            Err(StatsError::Failed {
+                detail: "unexpected EOF".to_string()
+            })
+        );
+
+        let no_docker = Arc::new(MockRunner::new(
+            |_| Err(std::io::ErrorKind::NotFound.into()),
+        ));
+        let error = StatsSampler::new(no_docker).usage().await.unwrap_err();
+        assert_eq!(
+            error,
+            StatsError::Docker {
+                error: DockerError::NotInstalled
+            }
+        );
+        assert_eq!(error.message().code, "docker-not-installed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watched_usage_is_emitted_periodically() {
+        let runner = stats_runner(|_| {
+            (
+                0,
+                r#"{"Name":"arbor-redis","CPUPerc":"0.50%","MemUsage":"8MiB / 1GiB"}"#,
+                "",
+            )
+        });
+        let sink = RecordingSink::default();
+
+        let polled = tokio::time::timeout(
+            Duration::from_secs(7),
+            poll_usage(StatsSampler::new(runner), &sink, Duration::from_secs(3)),
+        );
+        assert!(polled.await.is_err());
+        let events = sink.events_named(RESOURCE_USAGE_EVENT);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["total"]["memory_usage_bytes"], 8 * 1024 * 1024);
+    }
+}
@@ -0,0 +1,546 @@
+// Service status cache
+// `docker ps` is slow enough that the status served to the frontend is cached.
+// Entries expire after a TTL, and container events from the docker events
+// subscription mark the cache stale and trigger one refresh per burst, whose
+// result is cached and broadcast as `service-status-changed`.
+// `get_services_status` summarizes the cached status as a ServicesReport,
+// which says whether the whole stack or only part of it is running. Stopped
+// containers of services the active compose profile leaves out, e.g. a
+// worker left over from a full start, don't count as part of the stack.
+
+use crate::crashes::{RestartPolicies, RestartPolicy};
+use crate::docker::{self, ContainerEvent, ContainerStatus};
+use crate::events::{self, EventSink};
+use crate::process::CommandRunner;
+use crate::{info, warn};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tauri::State;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+pub const STATUS_CHANGED_EVENT: &str = "service-status-changed";
+
+const CACHE_TTL: Duration = Duration::from_secs(5);
+// A compose up/down produces a burst of events; wait for it to go quiet
+// (but not forever) before refreshing
+const EVENT_QUIET_PERIOD: Duration = Duration::from_millis(250);
+const EVENT_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Snapshot of the arbor containers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServicesStatus {
+    pub containers: Vec<ContainerStatus>,
+    /// Effective restart policy of each container
+    pub restart_policies: BTreeMap<String, RestartPolicy>,
+}
+
+impl ServicesStatus {
+    pub fn running_count(&self) -> usize {
+        self.containers.iter().filter(|c| c.is_running()).count()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverallStatus {
+    AllRunning,
+    /// Some containers exited or are restarting
+    PartiallyRunning,
+    Stopped,
+    DockerUnavailable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContainerReport {
+    pub name: String,
+    pub image: String,
+    /// As docker says, e.g. `running`, `exited` or `restarting`
+    pub state: String,
+    /// `healthy`, `unhealthy` or `starting`; none without a healthcheck
+    pub health: Option<String>,
+    pub uptime_secs: Option<u64>,
+    pub ports: Vec<u16>,
+    /// Not running, and its service isn't one the active profile starts
+    pub optional: bool,
+}
+
+/// The compose profile the stack was started with, and the services it
+/// enables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileScope {
+    pub profile: Option<String>,
+    pub services: Vec<String>,
+}
+
+/// Result of `get_services_status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServicesReport {
+    pub overall: OverallStatus,
+    pub containers: Vec<ContainerReport>,
+    /// Compose profile of the running stack, if it was started with one
+    pub profile: Option<String>,
+    /// Why docker couldn't be asked
+    pub error: Option<String>,
+}
+
+impl ServicesReport {
+    /// Containers outside `scope` are left out of `overall` while stopped
+    pub fn new(status: &ServicesStatus, scope: Option<&ProfileScope>, now: DateTime<Utc>) -> Self {
+        let optional = |c: &ContainerStatus| {
+            !c.is_running()
+                && scope.is_some_and(|scope| {
+                    !scope
+                        .services
+                        .iter()
+                        .any(|service| service == docker::service_of(&c.name))
+                })
+        };
+        let running = status.running_count();
+        let expected = status.containers.iter().filter(|c| !optional(c)).count();
+        let overall = match running {
+            0 => OverallStatus::Stopped,
+            n if n == expected => OverallStatus::AllRunning,
+            _ => OverallStatus::PartiallyRunning,
+        };
+        let uptime = |started_at: &str| {
+            let started = DateTime::parse_from_rfc3339(started_at).ok()?;
+            (now - started.with_timezone(&Utc))
+                .num_seconds()
+                .try_into()
+                .ok()
+        };
+        let containers = status
+            .containers
+            .iter()
+            .map(|c| ContainerReport {
+                name: c.name.clone(),
+                image: c.image.clone(),
+                state: c.state.clone(),
+                health: c.health.clone(),
+                uptime_secs: c.started_at.as_deref().and_then(uptime),
+                ports: c.ports.clone(),
+                optional: optional(c),
+            })
+            .collect();
+        Self {
+            overall,
+            containers,
+            profile: scope.and_then(|scope| scope.profile.clone()),
+            error: None,
+        }
+    }
+
+    pub fn unavailable(error: String) -> Self {
+        Self {
+            overall: OverallStatus::DockerUnavailable,
+            containers: Vec::new(),
+            profile: None,
+            error: Some(error),
+        }
+    }
+
+    /// One line for `check_services_status`, e.g. `Running (3 of 5 containers)`
+    pub fn summary(&self) -> String {
+        let running: Vec<&ContainerReport> = self
+            .containers
+            .iter()
+            .filter(|c| c.state == "running")
+            .collect();
+        let starting = running
+            .iter()
+            .filter(|c| {
+                c.health
+                    .as_deref()
+                    .is_some_and(|health| health != "healthy")
+            })
+            .count();
+        let count = match self.overall {
+            OverallStatus::AllRunning => format!("{} containers", running.len()),
+            OverallStatus::PartiallyRunning => {
+                let expected = self.containers.iter().filter(|c| !c.optional).count();
+                format!("{} of {} containers", running.len(), expected)
+            }
+            OverallStatus::Stopped => return "Stopped".to_string(),
+            OverallStatus::DockerUnavailable => return "Docker unavailable".to_string(),
+        };
+        match starting {
+            0 => format!("Running ({})", count),
+            _ => format!("Running ({}, {} starting)", count, starting),
+        }
+    }
+}
+
+struct CacheEntry {
+    status: ServicesStatus,
+    fetched_at: Instant,
+    stale: bool,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        !self.stale && self.fetched_at.elapsed() < CACHE_TTL
+    }
+}
+
+struct Inner {
+    runner: Arc<dyn CommandRunner>,
+    sink: Arc<dyn EventSink>,
+    policies: RestartPolicies,
+    scope: Mutex<Option<ProfileScope>>,
+    entry: Mutex<Option<CacheEntry>>,
+    // Serializes refreshes so concurrent readers share one `docker ps`
+    refresh_lock: tokio::sync::Mutex<()>,
+}
+
+#[derive(Clone)]
+pub struct StatusCache {
+    inner: Arc<Inner>,
+}
+
+impl StatusCache {
+    /// `policies` are reported alongside each container's status
+    pub fn new(
+        runner: Arc<dyn CommandRunner>,
+        sink: Arc<dyn EventSink>,
+        policies: RestartPolicies,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                runner,
+                sink,
+                policies,
+                scope: Mutex::new(None),
+                entry: Mutex::new(None),
+                refresh_lock: tokio::sync::Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Cached status, refreshed first if expired or marked stale
+    pub async fn get(&self) -> Result<ServicesStatus, String> {
+        if let Some(status) = self.fresh_status() {
+            return Ok(status);
+        }
+
+        let _guard = self.inner.refresh_lock.lock().await;
+        if let Some(status) = self.fresh_status() {
+            return Ok(status);
+        }
+
+        self.fetch().await
+    }
+
+    /// The cached status summarized, or why docker couldn't be asked
+    pub async fn report(&self) -> ServicesReport {
+        match self.get().await {
+            Ok(status) => {
+                let scope = self.inner.scope.lock().unwrap().clone();
+                ServicesReport::new(&status, scope.as_ref(), Utc::now())
+            }
+            Err(e) => ServicesReport::unavailable(e),
+        }
+    }
+
+    /// What the stack was started with, so `report` knows which stopped
+    /// containers are expected; `None` when that isn't known
+    pub fn set_scope(&self, scope: Option<ProfileScope>) {
+        *self.inner.scope.lock().unwrap() = scope;
+    }
+
+    /// Mark the cached status stale so the next read goes to docker
+    pub fn invalidate(&self) {
+        if let Some(entry) = self.inner.entry.lock().unwrap().as_mut() {
+            entry.stale = true;
+        }
+    }
+
+    /// Refresh unconditionally and broadcast the result
+    pub async fn refresh_and_broadcast(&self) {
+        let result = {
+            let _guard = self.inner.refresh_lock.lock().await;
+            self.fetch().await
+        };
+
+        match result {
+            Ok(status) => events::emit(&*self.inner.sink, STATUS_CHANGED_EVENT, &status),
+            Err(e) => warn!("⚠️  Failed to refresh service status: {}", e),
+        }
+    }
+
+    /// Periodic safety net for missed events: refetch, broadcast only on change
+    pub async fn poll(&self) {
+        let previous = self
+            .inner
+            .entry
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|entry| entry.status.clone());
+        let result = {
+            let _guard = self.inner.refresh_lock.lock().await;
+            self.fetch().await
+        };
+
+        match result {
+            Ok(status) if Some(&status) != previous.as_ref() => {
+                events::emit(&*self.inner.sink, STATUS_CHANGED_EVENT, &status)
+            }
+            Ok(_) => {}
+            Err(e) => warn!("⚠️  Failed to poll service status: {}", e),
+        }
+    }
+
+    /// Consume container events, invalidating and refreshing once per burst
+    pub async fn watch_events(self, mut rx: mpsc::Receiver<ContainerEvent>) {
+        while let Some(event) = rx.recv().await {
+            self.invalidate();
+            info!(
+                "🔄 {} {} — refreshing service status",
+                event.container, event.action
+            );
+
+            let burst_deadline = Instant::now() + EVENT_MAX_DELAY;
+            loop {
+                let quiet_deadline = (Instant::now() + EVENT_QUIET_PERIOD).min(burst_deadline);
+                match tokio::time::timeout_at(quiet_deadline, rx.recv()).await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return,
+                    Err(_) => break,
+                }
+            }
+
+            self.refresh_and_broadcast().await;
+        }
+    }
+
+    fn fresh_status(&self) -> Option<ServicesStatus> {
+        self.inner
+            .entry
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|entry| entry.is_fresh())
+            .map(|entry| entry.status.clone())
+    }
+
+    async fn fetch(&self) -> Result<ServicesStatus, String> {
+        let mut containers = docker::list_containers(&*self.inner.runner).await?;
+        if let Err(e) = docker::inspect_states(&*self.inner.runner, &mut containers).await {
+            warn!("⚠️  {}", e);
+        }
+        let restart_policies = containers
+            .iter()
+            .map(|c| (c.name.clone(), self.inner.policies.for_container(&c.name)))
+            .collect();
+        let status = ServicesStatus {
+            containers,
+            restart_policies,
+        };
+
+        *self.inner.entry.lock().unwrap() = Some(CacheEntry {
+            status: status.clone(),
+            fetched_at: Instant::now(),
+            stale: false,
+        });
+
+        Ok(status)
+    }
+}
+
+#[tauri::command]
+pub async fn get_services_status(
+    status_cache: State<'_, StatusCache>,
+) -> Result<ServicesReport, String> {
+    Ok(status_cache.report().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    const PS_OUTPUT: &str =
+        "arbor-postgres\trunning\tUp 1 minute\narbor-redis\texited\tExited (1)\n";
+    const INSPECT_OUTPUT: &str =
+        "/arbor-postgres\tstarting\t2026-10-14T09:00:00.5Z\n/arbor-redis\t\t\n";
+
+    /// Round trips to docker, each a `docker ps` plus a `docker inspect`
+    fn ps_calls(runner: &MockRunner) -> usize {
+        runner.calls().iter().filter(|c| c.args[0] == "ps").count()
+    }
+
+    fn event(action: &str) -> ContainerEvent {
+        ContainerEvent {
+            container: "arbor-redis".to_string(),
+            action: action.to_string(),
+        }
+    }
+
+    fn cache() -> (StatusCache, Arc<MockRunner>, Arc<RecordingSink>) {
+        let runner = Arc::new(MockRunner::new(|spec| {
+            let stdout = match spec.args[0].as_str() {
+                "inspect" => INSPECT_OUTPUT,
+                _ => PS_OUTPUT,
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        }));
+        let sink = Arc::new(RecordingSink::default());
+        let cache = StatusCache::new(runner.clone(), sink.clone(), RestartPolicies::default());
+        (cache, runner, sink)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_get_serves_from_cache_within_ttl() {
+        let (cache, runner, _) = cache();
+
+        let first = cache.get().await.unwrap();
+        let second = cache.get().await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first.running_count(), 1);
+        assert_eq!(ps_calls(&runner), 1);
+
+        tokio::time::advance(CACHE_TTL).await;
+        cache.get().await.unwrap();
+        assert_eq!(ps_calls(&runner), 2, "Expired entry should be refetched");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalidate_forces_refetch() {
+        let (cache, runner, _) = cache();
+
+        cache.get().await.unwrap();
+        cache.invalidate();
+        cache.get().await.unwrap();
+
+        assert_eq!(ps_calls(&runner), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poll_broadcasts_only_changes() {
+        let (cache, runner, sink) = cache();
+
+        cache.poll().await;
+        cache.poll().await;
+
+        assert_eq!(ps_calls(&runner), 2);
+        assert_eq!(sink.events_named(STATUS_CHANGED_EVENT).len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_event_burst_triggers_exactly_one_refresh() {
+        let (cache, runner, sink) = cache();
+        let (tx, rx) = mpsc::channel(64);
+        let watcher = tokio::spawn(cache.clone().watch_events(rx));
+
+        for action in ["kill", "die", "stop", "destroy", "create", "start"] {
+            tx.send(event(action)).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        assert_eq!(ps_calls(&runner), 1, "One docker ps per burst");
+        let broadcasts = sink.events_named(STATUS_CHANGED_EVENT);
+        assert_eq!(broadcasts.len(), 1);
+        assert_eq!(broadcasts[0]["containers"][1]["name"], "arbor-redis");
+
+        // The refreshed result is cached, not fetched again on read
+        cache.get().await.unwrap();
+        assert_eq!(ps_calls(&runner), 1);
+
+        drop(tx);
+        watcher.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_separate_bursts_refresh_separately() {
+        let (cache, runner, sink) = cache();
+        let (tx, rx) = mpsc::channel(64);
+        let watcher = tokio::spawn(cache.watch_events(rx));
+
+        tx.send(event("die")).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        tx.send(event("start")).await.unwrap();
+        tx.send(event("health_status: healthy")).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        assert_eq!(ps_calls(&runner), 2);
+        assert_eq!(sink.events_named(STATUS_CHANGED_EVENT).len(), 2);
+
+        drop(tx);
+        watcher.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_continuous_events_still_refresh_within_max_delay() {
+        let (cache, runner, _) = cache();
+        let (tx, rx) = mpsc::channel(64);
+        let watcher = tokio::spawn(cache.watch_events(rx));
+
+        for _ in 0..20 {
+            tx.send(event("health_status: starting")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert!(
+            !runner.calls().is_empty(),
+            "A steady stream of events must not postpone the refresh forever"
+        );
+
+        drop(tx);
+        watcher.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_report_flags_a_partially_running_stack() {
+        let (cache, _, _) = cache();
+
+        let report = cache.report().await;
+        assert_eq!(report.overall, OverallStatus::PartiallyRunning);
+        assert_eq!(report.containers[1].state, "exited");
+        assert_eq!(report.summary(), "Running (1 of 2 containers, 1 starting)");
+
+        let now = DateTime::parse_from_rfc3339("2026-10-14T09:01:30Z").unwrap();
+        let status = cache.get().await.unwrap();
+        let report = ServicesReport::new(&status, None, now.with_timezone(&Utc));
+        assert_eq!(report.containers[0].health.as_deref(), Some("starting"));
+        assert_eq!(report.containers[0].uptime_secs, Some(89));
+        assert_eq!(report.containers[1].uptime_secs, None);
+
+        let payload =
+            serde_json::to_value(ServicesReport::unavailable("no daemon".into())).unwrap();
+        assert_eq!(payload["overall"], "docker_unavailable");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_services_outside_the_profile_are_not_failures() {
+        let (cache, _, _) = cache();
+        cache.set_scope(Some(ProfileScope {
+            profile: Some("minimal".to_string()),
+            services: vec!["postgres".to_string()],
+        }));
+
+        let report = cache.report().await;
+        assert_eq!(report.overall, OverallStatus::AllRunning);
+        assert_eq!(report.profile.as_deref(), Some("minimal"));
+        assert!(report.containers[1].optional);
+        assert_eq!(report.summary(), "Running (1 containers, 1 starting)");
+
+        cache.set_scope(Some(ProfileScope {
+            profile: Some("full".to_string()),
+            services: vec!["postgres".to_string(), "redis".to_string()],
+        }));
+        let report = cache.report().await;
+        assert_eq!(report.overall, OverallStatus::PartiallyRunning);
+        assert!(!report.containers[1].optional);
+    }
+}
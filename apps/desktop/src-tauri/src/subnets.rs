@@ -0,0 +1,244 @@
+// IPv4 subnets and host routes
+// Compose's bridge network can land on a range a VPN also routes, so the API
+// is unreachable only while the VPN is up. We read the host's routing table
+// and check it against the compose network's subnets.
+
+use crate::process::{CommandRunner, CommandSpec};
+use crate::warn;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// An IPv4 range like `172.18.0.0/16`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: u32,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (addr, prefix) = text
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| format!("Invalid subnet {:?}: expected address/prefix", text))?;
+        let addr: Ipv4Addr = addr
+            .parse()
+            .map_err(|_| format!("Invalid subnet {:?}: bad IPv4 address", text))?;
+        let prefix: u8 = prefix
+            .parse()
+            .ok()
+            .filter(|p| *p <= 32)
+            .ok_or_else(|| format!("Invalid subnet {:?}: prefix must be 0-32", text))?;
+        Ok(Self::new(u32::from(addr), prefix))
+    }
+
+    fn new(addr: u32, prefix: u8) -> Self {
+        Self {
+            network: addr & Self::mask(prefix),
+            prefix,
+        }
+    }
+
+    fn mask(prefix: u8) -> u32 {
+        u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+    }
+
+    pub fn overlaps(&self, other: &Cidr) -> bool {
+        let mask = Self::mask(self.prefix.min(other.prefix));
+        self.network & mask == other.network & mask
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", Ipv4Addr::from(self.network), self.prefix)
+    }
+}
+
+/// Host routes that overlap `subnet`
+/// The default route and the subnet's own bridge route don't count.
+pub fn overlapping_routes(subnet: &Cidr, routes: &[Cidr]) -> Vec<Cidr> {
+    routes
+        .iter()
+        .filter(|route| route.prefix > 0 && *route != subnet && route.overlaps(subnet))
+        .copied()
+        .collect()
+}
+
+/// The host's IPv4 routes, empty if the routing table can't be read
+pub async fn host_routes(runner: &dyn CommandRunner) -> Vec<Cidr> {
+    let (spec, parse): (CommandSpec, fn(&str) -> Vec<Cidr>) = if cfg!(windows) {
+        (
+            CommandSpec::new("route").args(["print", "-4"]),
+            parse_windows_routes,
+        )
+    } else if cfg!(target_os = "macos") {
+        (
+            CommandSpec::new("netstat").args(["-rn", "-f", "inet"]),
+            parse_bsd_routes,
+        )
+    } else {
+        (
+            CommandSpec::new("ip").args(["-4", "route", "show"]),
+            parse_linux_routes,
+        )
+    };
+    match runner.output(&spec).await {
+        Ok(output) if output.success() => parse(&output.stdout_text()),
+        Ok(output) => {
+            warn!(
+                "⚠️  Failed to read host routes: {}",
+                output.stderr_text().trim()
+            );
+            Vec::new()
+        }
+        Err(e) => {
+            warn!("⚠️  Failed to read host routes: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// `ip -4 route` lines like `10.8.0.0/16 dev tun0 scope link`
+/// Routes on docker's own bridges are skipped.
+fn parse_linux_routes(text: &str) -> Vec<Cidr> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let destination = fields.next()?;
+            let device = line
+                .split_once(" dev ")
+                .and_then(|(_, rest)| rest.split_whitespace().next());
+            if device.is_some_and(|dev| dev == "docker0" || dev.starts_with("br-")) {
+                return None;
+            }
+            match destination {
+                "default" => None,
+                dest if dest.contains('/') => Cidr::parse(dest).ok(),
+                dest => Cidr::parse(&format!("{}/32", dest)).ok(),
+            }
+        })
+        .collect()
+}
+
+/// `netstat -rn` on macOS abbreviates destinations: `10.8/16`, `192.168.1`, `127`
+fn parse_bsd_routes(text: &str) -> Vec<Cidr> {
+    text.lines()
+        .filter_map(|line| {
+            let destination = line.split_whitespace().next()?;
+            let (addr, prefix) = match destination.split_once('/') {
+                Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().ok()?)),
+                None => (destination, None),
+            };
+            let octets: Vec<u8> = addr
+                .split('.')
+                .map(|octet| octet.parse().ok())
+                .collect::<Option<_>>()?;
+            if octets.is_empty() || octets.len() > 4 {
+                return None;
+            }
+            let prefix = prefix.unwrap_or(8 * octets.len() as u8);
+            let mut full = [0u8; 4];
+            full[..octets.len()].copy_from_slice(&octets);
+            (prefix <= 32).then(|| Cidr::new(u32::from(Ipv4Addr::from(full)), prefix))
+        })
+        .collect()
+}
+
+/// `route print -4` rows: destination, netmask, gateway, interface, metric
+fn parse_windows_routes(text: &str) -> Vec<Cidr> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let destination: Ipv4Addr = fields.next()?.parse().ok()?;
+            let mask = u32::from(fields.next()?.parse::<Ipv4Addr>().ok()?);
+            // A netmask is contiguous ones
+            if mask.leading_ones() + mask.trailing_zeros() != 32 {
+                return None;
+            }
+            Some(Cidr::new(u32::from(destination), mask.leading_ones() as u8))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(text: &str) -> Cidr {
+        Cidr::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_cidr_parse_and_overlap() {
+        assert_eq!(cidr("172.18.3.4/16").to_string(), "172.18.0.0/16");
+        assert!(Cidr::parse("172.18.0.0").is_err());
+        assert!(Cidr::parse("172.18.0.0/33").is_err());
+        assert!(Cidr::parse("not-a-subnet/8").is_err());
+
+        assert!(cidr("172.16.0.0/12").overlaps(&cidr("172.18.0.0/16")));
+        assert!(cidr("172.18.0.0/16").overlaps(&cidr("172.16.0.0/12")));
+        assert!(!cidr("172.18.0.0/16").overlaps(&cidr("172.19.0.0/16")));
+    }
+
+    #[test]
+    fn test_overlapping_routes_ignores_default_and_own_route() {
+        let subnet = cidr("172.18.0.0/16");
+        let routes = [
+            cidr("0.0.0.0/0"),
+            cidr("172.18.0.0/16"),
+            cidr("172.16.0.0/12"),
+            cidr("10.0.0.0/8"),
+        ];
+        assert_eq!(
+            overlapping_routes(&subnet, &routes),
+            vec![cidr("172.16.0.0/12")]
+        );
+    }
+
+    #[test]
+    fn test_parse_linux_routes() {
+        let text = "default via 192.168.1.1 dev wlp2s0 proto dhcp metric 600\n\
+                    172.16.0.0/12 dev tun0 scope link\n\
+                    172.18.0.0/16 dev br-5e2f1a proto kernel scope link src 172.18.0.1\n\
+                    172.17.0.0/16 dev docker0 proto kernel scope link src 172.17.0.1 linkdown\n\
+                    192.168.1.0/24 dev wlp2s0 proto kernel scope link src 192.168.1.20\n\
+                    10.8.0.1 dev tun0 scope link\n";
+        assert_eq!(
+            parse_linux_routes(text),
+            vec![
+                cidr("172.16.0.0/12"),
+                cidr("192.168.1.0/24"),
+                cidr("10.8.0.1/32")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_bsd_and_windows_routes() {
+        let netstat = "Routing tables\n\nInternet:\n\
+                       Destination        Gateway            Flags        Netif Expire\n\
+                       default            192.168.1.1        UGScg          en0\n\
+                       10.8/16            utun3              USc          utun3\n\
+                       127                127.0.0.1          UCS            lo0\n\
+                       192.168.1          link#6             UCS            en0      !\n";
+        assert_eq!(
+            parse_bsd_routes(netstat),
+            vec![
+                cidr("10.8.0.0/16"),
+                cidr("127.0.0.0/8"),
+                cidr("192.168.1.0/24")
+            ]
+        );
+
+        let route_print = "IPv4 Route Table\n\
+                           Active Routes:\n\
+                           Network Destination        Netmask          Gateway       Interface  Metric\n\
+                           \x20         0.0.0.0          0.0.0.0      192.168.1.1    192.168.1.20     25\n\
+                           \x20      172.16.0.0      255.240.0.0         On-link       10.8.0.2     35\n";
+        assert_eq!(
+            parse_windows_routes(route_print),
+            vec![cidr("0.0.0.0/0"), cidr("172.16.0.0/12")]
+        );
+    }
+}
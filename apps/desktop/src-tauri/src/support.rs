@@ -0,0 +1,458 @@
+// Support bundles
+// `export_support_bundle` zips up what a support request needs so it can be
+// attached in one go: the app's log files from the last LOG_DAYS days, the
+// doctor report, the resolved compose config, the tail of each arbor
+// container's logs and version details. Nothing from the keychain goes in,
+// and values that look like secrets (`*_PASSWORD=…`, `api_token: …`) are
+// replaced with `<redacted>` in the config and every log. The archive is
+// written under a `.partial` name and only renamed once complete. The
+// destination comes from the frontend, so it goes through `paths::validate`
+// and the job only sees the resolved directory.
+
+use crate::archive::ZipWriter;
+use crate::diagnostics::SystemInfo;
+use crate::docker;
+use crate::doctor::{self, DiagnosticsReport};
+use crate::flight;
+use crate::jobs::{JobEnv, JobKind, JobManager};
+use crate::paths::{self, PathPolicy};
+use crate::postmortem::is_secret_name;
+use crate::process::{CommandRunner, CommandSpec};
+use crate::protocol::Handshake;
+use crate::services::ComposeSetup;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tauri::{AppHandle, State};
+
+/// App logs older than this are left out
+pub const LOG_DAYS: u64 = 7;
+const CONTAINER_LOG_LINES: &str = "500";
+/// How long one docker call may take before its part of the bundle is
+/// replaced with an error
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(15);
+const REDACTED: &str = "<redacted>";
+
+/// What a bundle is made from
+pub struct BundleEnv<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub log_dir: &'a Path,
+    pub system: &'a SystemInfo,
+    pub diagnostics: &'a DiagnosticsReport,
+    /// The project root and compose setup; without them the config is left out
+    pub stack: Option<(&'a Path, &'a ComposeSetup)>,
+}
+
+/// Write a support bundle into `dest`, returning the archive's path
+pub async fn write_bundle(env: &BundleEnv<'_>, dest: &Path) -> Result<PathBuf, String> {
+    if !dest.is_dir() {
+        return Err(format!("{} is not a folder", dest.display()));
+    }
+    let files = collect(env).await?;
+
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    let path = dest.join(format!("arbor-support-{}.zip", timestamp));
+    let partial = path.with_extension("zip.partial");
+    let written = File::create(&partial)
+        .map_err(|e| format!("Can't write to {}: {}", dest.display(), e))
+        .and_then(|file| {
+            let mut zip = ZipWriter::new(BufWriter::new(file));
+            for (name, contents) in &files {
+                zip.add(name, contents)
+                    .map_err(|e| format!("Failed to write {}: {}", name, e))?;
+            }
+            zip.finish()
+                .map_err(|e| format!("Failed to write the support bundle: {}", e))?;
+            std::fs::rename(&partial, &path)
+                .map_err(|e| format!("Failed to write the support bundle: {}", e))
+        });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    written.map(|_| path)
+}
+
+/// The bundle's files, by path inside the archive
+async fn collect(env: &BundleEnv<'_>) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut files = Vec::new();
+    let mut notes = Vec::new();
+    files.push(("version.json".to_string(), pretty(env.system)?));
+    files.push(("diagnostics.json".to_string(), pretty(env.diagnostics)?));
+
+    let config = match env.stack {
+        Some((root, setup)) => compose_config(env.runner, root, setup).await,
+        None => Err("No project root, so no compose config".to_string()),
+    };
+    let config = config.unwrap_or_else(|error| serde_json::json!({ "error": error }));
+    files.push(("compose-config.json".to_string(), pretty(&config)?));
+
+    match with_timeout(docker::list_containers(env.runner)).await {
+        Ok(containers) => {
+            for container in containers {
+                let logs = container_logs(env.runner, &container.name).await;
+                let name = format!("containers/{}.log", container.name);
+                files.push((name, redact_text(&logs).into_bytes()));
+            }
+        }
+        Err(e) => notes.push(format!("Container logs left out: {}", e)),
+    }
+
+    let logs = recent_logs(env.log_dir);
+    if logs.is_empty() {
+        notes.push(format!("No app logs from the last {} days", LOG_DAYS));
+    }
+    for (name, contents) in logs {
+        let contents = redact_text(&String::from_utf8_lossy(&contents));
+        files.push((format!("logs/{}", name), contents.into_bytes()));
+    }
+
+    let manifest = serde_json::json!({
+        "created_at": chrono::Utc::now().to_rfc3339(),
+        "app_version": env.system.app_version,
+        "build": env.system.build.describe(),
+        "files": files.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+        "notes": notes,
+    });
+    files.insert(0, ("manifest.json".to_string(), pretty(&manifest)?));
+    Ok(files)
+}
+
+fn pretty(value: &impl Serialize) -> Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(value).map_err(|e| e.to_string())
+}
+
+async fn with_timeout<T>(
+    work: impl std::future::Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    tokio::time::timeout(COMMAND_TIMEOUT, work)
+        .await
+        .unwrap_or_else(|_| Err(format!("No answer within {}s", COMMAND_TIMEOUT.as_secs())))
+}
+
+/// `docker compose config` as docker resolves it, secrets redacted
+async fn compose_config(
+    runner: &dyn CommandRunner,
+    root: &Path,
+    setup: &ComposeSetup,
+) -> Result<Value, String> {
+    let spec = setup
+        .compose_command(root)
+        .args(["config", "--format", "json"]);
+    let output = with_timeout(async {
+        runner
+            .output(&spec)
+            .await
+            .map_err(|e| format!("Failed to read compose config: {}", e))
+    })
+    .await?;
+    if !output.success() {
+        return Err(format!(
+            "Failed to read compose config: {}",
+            output.stderr_text().trim()
+        ));
+    }
+    let mut config: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Invalid compose config: {}", e))?;
+    redact_config(&mut config);
+    Ok(config)
+}
+
+async fn container_logs(runner: &dyn CommandRunner, container: &str) -> String {
+    let spec = CommandSpec::new("docker").args(["logs", "--tail", CONTAINER_LOG_LINES, container]);
+    let output = with_timeout(async { runner.output(&spec).await.map_err(|e| e.to_string()) });
+    match output.await {
+        Ok(output) => format!(
+            "{}\n--- stderr ---\n{}",
+            output.stdout_text(),
+            output.stderr_text()
+        ),
+        Err(e) => format!("Failed to read logs: {}", e),
+    }
+}
+
+/// The daily log files, if written to in the last LOG_DAYS days
+fn recent_logs(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let cutoff = SystemTime::now() - Duration::from_secs(LOG_DAYS * 24 * 60 * 60);
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<(String, Vec<u8>)> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            flight::is_log_file(&name.to_string_lossy())
+                && entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| modified >= cutoff)
+        })
+        .filter_map(|entry| {
+            let contents = std::fs::read(entry.path()).ok()?;
+            Some((entry.file_name().to_string_lossy().into_owned(), contents))
+        })
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// Blank out secret-looking values anywhere in a compose config: under
+/// secret-looking keys, and in `NAME=value` list items such as a list-form
+/// `environment`
+fn redact_config(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::Object(_) | Value::Array(_) => redact_config(value),
+                    _ if is_secret_name(key) => *value = Value::String(REDACTED.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    Value::String(text) => {
+                        if let Some(redacted) = redact_assignment(text) {
+                            *text = redacted;
+                        }
+                    }
+                    _ => redact_config(item),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `NAME=value` (or `--name=value`) with the value blanked, if the name
+/// looks like a secret's
+fn redact_assignment(word: &str) -> Option<String> {
+    let (name, value) = word.split_once('=')?;
+    (!value.is_empty() && is_secret_name(name)).then(|| format!("{}={}", name, REDACTED))
+}
+
+/// Log text with `NAME=value` and `name: value` secrets blanked
+fn redact_text(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    for line in text.lines() {
+        let mut words = line.split(' ').peekable();
+        let mut first = true;
+        while let Some(word) = words.next() {
+            if !first {
+                redacted.push(' ');
+            }
+            first = false;
+            if let Some(word) = redact_assignment(word) {
+                redacted.push_str(&word);
+                continue;
+            }
+            redacted.push_str(word);
+            // `password: hunter2`
+            if word.len() > 1 && word.ends_with(':') && is_secret_name(word) {
+                if let Some(next) = words.next() {
+                    redacted.push(' ');
+                    redacted.push_str(if next.is_empty() { next } else { REDACTED });
+                }
+            }
+        }
+        redacted.push('\n');
+    }
+    redacted
+}
+
+/// What the `support_bundle` job runs with: the compose setup the stack
+/// actually uses, where that differs from the settings
+pub fn job_env(app_handle: &AppHandle) -> Result<JobEnv, String> {
+    let mut env = JobEnv::without_project(app_handle)?;
+    if !env.project_root.as_os_str().is_empty() {
+        if let Some(setup) = doctor::stack_setup(app_handle, &env.project_root) {
+            env.setup = setup;
+        }
+    }
+    Ok(env)
+}
+
+/// The bundle job for `dest_dir`, resolved to an existing directory
+pub fn bundle_job(dest_dir: &str) -> Result<JobKind, String> {
+    let dest_dir = paths::validate(Path::new(dest_dir), PathPolicy::Directory)?
+        .into_os_string()
+        .into_string()
+        .map_err(|path| format!("Support bundle directory {:?} isn't valid UTF-8", path))?;
+    Ok(JobKind::SupportBundle { dest_dir })
+}
+
+/// Zip up logs, diagnostics and the compose config into `dest_dir` for a
+/// support request, returning the archive's path; `start_job` with
+/// `support_bundle` does the same without waiting
+#[tauri::command]
+pub async fn export_support_bundle(
+    app_handle: AppHandle,
+    jobs: State<'_, JobManager>,
+    handshake: State<'_, Handshake>,
+    dest_dir: String,
+) -> Result<String, String> {
+    handshake.ensure()?;
+    let kind = bundle_job(&dest_dir)?;
+    let (_, done) = jobs.start(kind, job_env(&app_handle)?);
+    let result = done
+        .await
+        .map_err(|_| "Support bundle export was interrupted".to_string())??;
+    result["output_file"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| "The support bundle job didn't say where it wrote".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doctor::CheckStatus;
+    use crate::keyring::KeyringStatus;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::test_support::temp_dir;
+
+    fn diagnostics() -> DiagnosticsReport {
+        DiagnosticsReport {
+            ran_at: "2026-01-01T00:00:00Z".to_string(),
+            app_version: "0.1.0".to_string(),
+            os: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            status: CheckStatus::Ok,
+            checks: Vec::new(),
+        }
+    }
+
+    fn runner() -> MockRunner {
+        MockRunner::new(|spec| {
+            let stdout = if spec.args.contains(&"config".to_string()) {
+                r#"{"services":{"api":{"environment":{"API_TOKEN":"tok-123","PORT":"8080"},
+                    "command":["serve","--db-password=pw-456"]}}}"#
+            } else if spec.args[0] == "ps" {
+                "arbor-api\trunning\tUp\t\tapi\n"
+            } else {
+                "booting\nPOSTGRES_PASSWORD=pw-789 ready\n"
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        })
+    }
+
+    fn file(files: &[(String, Vec<u8>)], name: &str) -> String {
+        let (_, contents) = files.iter().find(|(path, _)| path == name).unwrap();
+        String::from_utf8_lossy(contents).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_bundle_parts_are_redacted() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        std::fs::write(
+            dir.join("arbor.2026-10-14.log"),
+            "🔑 Secret github stored (22 bytes)\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("unrelated.txt"), "").unwrap();
+        let runner = runner();
+        let setup = ComposeSetup::from_settings(&Default::default(), dir, dir);
+        let system = SystemInfo::collect(&KeyringStatus::default());
+        let env = BundleEnv {
+            runner: &runner,
+            log_dir: dir,
+            system: &system,
+            diagnostics: &diagnostics(),
+            stack: Some((dir, &setup)),
+        };
+
+        let files = collect(&env).await.unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "manifest.json",
+                "version.json",
+                "diagnostics.json",
+                "compose-config.json",
+                "containers/arbor-api.log",
+                "logs/arbor.2026-10-14.log",
+            ]
+        );
+        assert!(file(&files, "version.json").contains(r#""git_sha""#));
+        let config = file(&files, "compose-config.json");
+        assert!(config.contains(r#""API_TOKEN": "<redacted>""#));
+        assert!(config.contains(r#""PORT": "8080""#));
+        assert!(config.contains("--db-password=<redacted>"));
+        let all: String = files
+            .iter()
+            .map(|(_, c)| String::from_utf8_lossy(c))
+            .collect();
+        for secret in ["tok-123", "pw-456", "pw-789"] {
+            assert!(!all.contains(secret), "{} leaked", secret);
+        }
+        assert!(file(&files, "containers/arbor-api.log").contains("POSTGRES_PASSWORD=<redacted>"));
+    }
+
+    #[tokio::test]
+    async fn test_bundle_without_logs_or_a_writable_dest() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let runner = MockRunner::with_stdout("");
+        let system = SystemInfo::collect(&KeyringStatus::default());
+        let env = BundleEnv {
+            runner: &runner,
+            log_dir: &dir.join("missing"),
+            system: &system,
+            diagnostics: &diagnostics(),
+            stack: None,
+        };
+
+        let path = write_bundle(&env, dir).await.unwrap();
+        assert!(path.is_file());
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+        let files = collect(&env).await.unwrap();
+        assert!(file(&files, "manifest.json").contains("No app logs from the last 7 days"));
+        assert!(file(&files, "compose-config.json").contains("No project root"));
+
+        let error = write_bundle(&env, &dir.join("nowhere")).await.unwrap_err();
+        assert!(error.contains("is not a folder"));
+    }
+
+    #[test]
+    fn test_bundle_dest_is_resolved() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("exports")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let dotted = dir.join("exports").join("..").join("exports");
+        assert_eq!(
+            bundle_job(&dotted.to_string_lossy()).unwrap(),
+            JobKind::SupportBundle {
+                dest_dir: dir
+                    .canonicalize()
+                    .unwrap()
+                    .join("exports")
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        );
+        assert!(bundle_job(&dir.join("notes.txt").to_string_lossy()).is_err());
+        assert!(bundle_job(&dir.join("missing").to_string_lossy()).is_err());
+        assert!(bundle_job("").is_err());
+    }
+
+    #[test]
+    fn test_log_text_is_redacted() {
+        assert_eq!(
+            redact_text("export DB_PASSWORD=hunter2 PORT=5432\napi_key: abc123\nplain line"),
+            "export DB_PASSWORD=<redacted> PORT=5432\napi_key: <redacted>\nplain line\n"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+// Locks that survive a panic
+// A panic while a std Mutex is held poisons it, and every later
+// `lock().unwrap()` panics too, which left the service controls dead until
+// a restart. What `Lock` guards is plain state that each writer replaces
+// whole, so a poisoned lock is taken back with a warning instead.
+// Guards are held only between awaits, never across one; clippy's
+// `await_holding_lock` keeps it that way.
+
+use crate::warn;
+use std::sync::{Mutex, MutexGuard};
+
+pub struct Lock<T> {
+    name: &'static str,
+    inner: Mutex<T>,
+}
+
+impl<T> Lock<T> {
+    /// `name` identifies the lock in the warning logged on recovery
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().unwrap_or_else(|poisoned| {
+            warn!(
+                "⚠️  Recovered {} after a panic while it was locked",
+                self.name
+            );
+            self.inner.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_poisoned_lock_is_recovered() {
+        let lock = Arc::new(Lock::new("test", 1));
+        let holder = lock.clone();
+        let panicked = std::thread::spawn(move || {
+            let mut value = holder.lock();
+            *value = 2;
+            panic!("while locked");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(lock.inner.is_poisoned());
+
+        assert_eq!(*lock.lock(), 2);
+        assert!(!lock.inner.is_poisoned());
+        *lock.lock() = 3;
+        assert_eq!(*lock.lock(), 3);
+    }
+}
@@ -0,0 +1,270 @@
+// Background task manager
+// Every long-lived task is spawned through the TaskManager, which names it,
+// gives it a cancellation token and keeps its handle. On shutdown all tasks
+// are cancelled and awaited up to a deadline; a task that panics is recorded
+// in the error history instead of silently disappearing.
+
+use crate::errors::ErrorHistory;
+use crate::messages::Message;
+use crate::schedule::{BackgroundTasks, TaskSchedule};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tauri::async_runtime::JoinHandle;
+use tauri::State;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Finished tasks kept for `get_background_tasks`
+const MAX_FINISHED: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    Finished,
+    Cancelled,
+    Panicked { message: String },
+}
+
+/// One registered task, as reported by `get_background_tasks`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: String,
+    pub started_at: String,
+    #[serde(flatten)]
+    pub state: TaskState,
+    /// Interval and next run, for periodic tasks
+    pub schedule: Option<TaskSchedule>,
+}
+
+struct TaskEntry {
+    name: String,
+    started_at: String,
+    state: TaskState,
+}
+
+/// Supervisor of one task, by task id
+type SupervisorHandle = (u64, JoinHandle<()>);
+
+/// What happened to the tasks on shutdown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub stopped: usize,
+    /// Tasks still running at the deadline, which were aborted
+    pub timed_out: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct TaskManager {
+    entries: Arc<Mutex<BTreeMap<u64, TaskEntry>>>,
+    handles: Arc<Mutex<Vec<SupervisorHandle>>>,
+    next_id: Arc<Mutex<u64>>,
+    shutdown: CancellationToken,
+    errors: ErrorHistory,
+}
+
+impl TaskManager {
+    pub fn new(errors: ErrorHistory) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(BTreeMap::new())),
+            handles: Arc::new(Mutex::new(Vec::new())),
+            next_id: Arc::new(Mutex::new(0)),
+            shutdown: CancellationToken::new(),
+            errors,
+        }
+    }
+
+    /// Spawn `task` under `name`, returning its cancellation token
+    /// The future is dropped as soon as the token (or shutdown) cancels it.
+    pub fn spawn<F>(&self, name: &str, task: F) -> CancellationToken
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let token = self.shutdown.child_token();
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        self.entries.lock().unwrap().insert(
+            id,
+            TaskEntry {
+                name: name.to_string(),
+                started_at: chrono::Utc::now().to_rfc3339(),
+                state: TaskState::Running,
+            },
+        );
+
+        let cancel = token.clone();
+        let inner = tauri::async_runtime::spawn(async move {
+            tokio::select! {
+                _ = cancel.cancelled() => {}
+                _ = task => {}
+            }
+        });
+
+        // Supervisor: records how the task ended, including panics
+        let manager = self.clone();
+        let supervised = token.clone();
+        let handle = tauri::async_runtime::spawn(async move {
+            let state = match inner.await {
+                Ok(()) if supervised.is_cancelled() => TaskState::Cancelled,
+                Ok(()) => TaskState::Finished,
+                Err(tauri::Error::JoinError(e)) if e.is_panic() => {
+                    let message = panic_message(e.into_panic());
+                    TaskState::Panicked { message }
+                }
+                Err(_) => TaskState::Cancelled,
+            };
+            manager.finish(id, state);
+        });
+        self.handles.lock().unwrap().push((id, handle));
+
+        token
+    }
+
+    fn finish(&self, id: u64, state: TaskState) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(&id) {
+            if let TaskState::Panicked { message } = &state {
+                let coded = Message::new("task-panicked")
+                    .with_param("task", &entry.name)
+                    .with_param("message", message);
+                self.errors.record(&entry.name, &coded);
+            }
+            entry.state = state;
+        }
+
+        let finished: Vec<u64> = entries
+            .iter()
+            .filter(|(_, entry)| entry.state != TaskState::Running)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED))
+        {
+            entries.remove(id);
+        }
+    }
+
+    pub fn list(&self, schedules: &[TaskSchedule]) -> Vec<TaskInfo> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| TaskInfo {
+                id: *id,
+                name: entry.name.clone(),
+                started_at: entry.started_at.clone(),
+                state: entry.state.clone(),
+                schedule: schedules.iter().find(|s| s.name == entry.name).cloned(),
+            })
+            .collect()
+    }
+
+    /// Cancel every task and wait for them to stop, aborting stragglers at `deadline`
+    pub async fn shutdown(&self, deadline: Duration) -> ShutdownReport {
+        self.shutdown.cancel();
+        let handles: Vec<_> = self.handles.lock().unwrap().drain(..).collect();
+        let total = handles.len();
+
+        let mut timed_out = Vec::new();
+        let deadline = tokio::time::Instant::now() + deadline;
+        for (id, handle) in handles {
+            if tokio::time::timeout_at(deadline, handle).await.is_err() {
+                let entries = self.entries.lock().unwrap();
+                timed_out.push(entries.get(&id).map(|e| e.name.clone()).unwrap_or_default());
+            }
+        }
+
+        ShutdownReport {
+            stopped: total - timed_out.len(),
+            timed_out,
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[tauri::command]
+pub async fn get_background_tasks(
+    tasks: State<'_, TaskManager>,
+    schedules: State<'_, BackgroundTasks>,
+) -> Result<Vec<TaskInfo>, String> {
+    Ok(tasks.list(&schedules.schedules()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_and_awaits_tasks() {
+        let manager = TaskManager::new(ErrorHistory::default());
+        manager.spawn("forever", std::future::pending());
+        manager.spawn("quick", async {});
+        settle().await;
+
+        let report = manager.shutdown(Duration::from_secs(1)).await;
+
+        assert_eq!(report.stopped, 2);
+        assert!(report.timed_out.is_empty());
+        let states: Vec<_> = manager
+            .list(&[])
+            .into_iter()
+            .map(|t| (t.name, t.state))
+            .collect();
+        assert!(states.contains(&("forever".to_string(), TaskState::Cancelled)));
+        assert!(states.contains(&("quick".to_string(), TaskState::Finished)));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_is_recorded() {
+        let errors = ErrorHistory::default();
+        let manager = TaskManager::new(errors.clone());
+
+        manager.spawn("sampler", async { panic!("stats parse exploded") });
+        settle().await;
+
+        let task = &manager.list(&[])[0];
+        assert_eq!(
+            task.state,
+            TaskState::Panicked {
+                message: "stats parse exploded".to_string()
+            }
+        );
+        let recorded = errors.recent();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].source, "sampler");
+        assert_eq!(recorded[0].code, "task-panicked");
+        assert_eq!(recorded[0].params["message"], "stats parse exploded");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_single_task() {
+        let manager = TaskManager::new(ErrorHistory::default());
+        let token = manager.spawn("log-follower", std::future::pending());
+        manager.spawn("status-watcher", std::future::pending());
+
+        token.cancel();
+        settle().await;
+
+        let tasks = manager.list(&[]);
+        assert_eq!(tasks[0].state, TaskState::Cancelled);
+        assert_eq!(tasks[1].state, TaskState::Running);
+    }
+}
@@ -0,0 +1,9 @@
+// Helpers shared by the tests of several modules
+
+use tempfile::TempDir;
+
+/// A fresh, empty directory, removed with its contents when dropped, so a
+/// failing test cleans up as well as a passing one
+pub fn temp_dir() -> TempDir {
+    tempfile::Builder::new().prefix("arbor-").tempdir().unwrap()
+}
@@ -0,0 +1,472 @@
+// External tool resolution
+// GUI launches get a minimal PATH (launchd on macOS), GNU make is `gmake`
+// on the BSDs and some setups use a standalone `docker-compose`. Tools are
+// looked up in the configured paths first, then PATH, then well-known
+// install locations. The resolved table is process-wide so every runner
+// picks it up without threading it through each call site.
+// The container runtime is docker, or podman where docker isn't installed
+// (or the `container_runtime` setting says so); with podman, `docker`
+// commands run as `podman` and `docker compose` as `podman compose`.
+
+use crate::process::CommandSpec;
+use crate::settings::ToolPaths;
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tauri::State;
+
+#[cfg(target_os = "freebsd")]
+const MAKE_NAMES: [&str; 2] = ["gmake", "make"];
+#[cfg(not(target_os = "freebsd"))]
+const MAKE_NAMES: [&str; 2] = ["make", "gmake"];
+
+// Where installers put the binaries when the app's PATH doesn't include them
+const WELL_KNOWN_DIRS: [&str; 6] = [
+    "/usr/local/bin",
+    "/opt/homebrew/bin",
+    "/usr/bin",
+    "/Applications/Docker.app/Contents/Resources/bin",
+    "/snap/bin",
+    r"C:\Program Files\Docker\Docker\resources\bin",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tool {
+    Make,
+    Docker,
+    Podman,
+    /// Standalone compose binary; without one `docker compose` is used
+    Compose,
+}
+
+impl Tool {
+    const ALL: [Tool; 4] = [Tool::Make, Tool::Docker, Tool::Podman, Tool::Compose];
+
+    fn names(self) -> &'static [&'static str] {
+        match self {
+            Tool::Make => &MAKE_NAMES,
+            Tool::Docker => &["docker"],
+            Tool::Podman => &["podman"],
+            Tool::Compose => &["docker-compose", "podman-compose"],
+        }
+    }
+
+    fn configured(self, paths: &ToolPaths) -> Option<&Path> {
+        match self {
+            Tool::Make => paths.make.as_deref(),
+            Tool::Docker => paths.docker.as_deref(),
+            Tool::Podman => paths.podman.as_deref(),
+            Tool::Compose => paths.compose.as_deref(),
+        }
+    }
+}
+
+/// What runs the containers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerRuntime {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    /// `choice` if set, else docker unless only podman was found
+    fn choose(located: &[LocatedTool], choice: Option<ContainerRuntime>) -> Self {
+        let found = |tool| {
+            located
+                .iter()
+                .any(|located| located.tool == tool && located.path.is_some())
+        };
+        match choice {
+            Some(runtime) => runtime,
+            None if !found(Tool::Docker) && found(Tool::Podman) => ContainerRuntime::Podman,
+            None => ContainerRuntime::Docker,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolSource {
+    Setting,
+    Path,
+    WellKnown,
+    /// Compose runs as the `docker compose` plugin
+    DockerPlugin,
+}
+
+/// Where a tool was found, as shown in settings and diagnostics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocatedTool {
+    pub tool: Tool,
+    pub path: Option<PathBuf>,
+    pub source: Option<ToolSource>,
+    pub error: Option<String>,
+}
+
+/// Finds tools given the configured paths, PATH and the well-known dirs
+pub struct ToolLocator {
+    search_path: Option<OsString>,
+    well_known: Vec<PathBuf>,
+}
+
+impl ToolLocator {
+    pub fn from_env() -> Self {
+        Self {
+            search_path: std::env::var_os("PATH"),
+            well_known: WELL_KNOWN_DIRS.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn find_in(&self, dirs: impl IntoIterator<Item = PathBuf>, tool: Tool) -> Option<PathBuf> {
+        dirs.into_iter().find_map(|dir| {
+            tool.names()
+                .iter()
+                .map(|name| dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX)))
+                .find(|candidate| candidate.is_file())
+        })
+    }
+
+    pub fn locate(&self, tool: Tool, paths: &ToolPaths) -> LocatedTool {
+        let found = |path: PathBuf, source| LocatedTool {
+            tool,
+            path: Some(path),
+            source: Some(source),
+            error: None,
+        };
+
+        let mut error = None;
+        if let Some(configured) = tool.configured(paths) {
+            if configured.is_file() {
+                return found(configured.to_path_buf(), ToolSource::Setting);
+            }
+            error = Some(format!("Configured path {:?} is not a file", configured));
+        } else if tool == Tool::Compose {
+            // The plugin is the default; only an explicit standalone binary replaces it
+            return LocatedTool {
+                tool,
+                path: None,
+                source: Some(ToolSource::DockerPlugin),
+                error: None,
+            };
+        }
+
+        let path_dirs = self
+            .search_path
+            .as_ref()
+            .map(|path| std::env::split_paths(path).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if let Some(path) = self.find_in(path_dirs, tool) {
+            return found(path, ToolSource::Path);
+        }
+        if let Some(path) = self.find_in(self.well_known.clone(), tool) {
+            return found(path, ToolSource::WellKnown);
+        }
+
+        LocatedTool {
+            tool,
+            path: None,
+            source: None,
+            error: Some(error.unwrap_or_else(|| {
+                format!(
+                    "{} not found on PATH or in {}",
+                    tool.names().join(" / "),
+                    WELL_KNOWN_DIRS.join(", ")
+                )
+            })),
+        }
+    }
+
+    pub fn locate_all(&self, paths: &ToolPaths) -> Vec<LocatedTool> {
+        Tool::ALL
+            .iter()
+            .map(|tool| self.locate(*tool, paths))
+            .collect()
+    }
+}
+
+/// Resolved tools, applied to commands just before they run
+#[derive(Debug, Clone, Default)]
+pub struct ToolTable {
+    located: Vec<LocatedTool>,
+    runtime: ContainerRuntime,
+}
+
+impl ToolTable {
+    fn path(&self, tool: Tool) -> Option<&Path> {
+        self.located
+            .iter()
+            .find(|located| located.tool == tool)
+            .and_then(|located| located.path.as_deref())
+    }
+
+    /// `spec` with tool names replaced by their resolved paths
+    /// Unresolved tools keep their bare name, failing as they always have.
+    pub fn apply(&self, spec: &CommandSpec) -> CommandSpec {
+        let mut resolved = spec.clone();
+        let runtime = match self.runtime {
+            ContainerRuntime::Docker => Tool::Docker,
+            ContainerRuntime::Podman => Tool::Podman,
+        };
+        let runtime_program = || match self.path(runtime) {
+            Some(path) => path.to_string_lossy().to_string(),
+            None => self.runtime.name().to_string(),
+        };
+        match spec.program.as_str() {
+            "docker" if spec.args.first().map(String::as_str) == Some("compose") => {
+                if let Some(compose) = self.path(Tool::Compose) {
+                    resolved.program = compose.to_string_lossy().to_string();
+                    resolved.args.remove(0);
+                } else {
+                    resolved.program = runtime_program();
+                }
+            }
+            "docker" => resolved.program = runtime_program(),
+            "make" => {
+                if let Some(make) = self.path(Tool::Make) {
+                    resolved.program = make.to_string_lossy().to_string();
+                }
+                if let Some(path) = self.child_path() {
+                    resolved.env.push(("PATH".to_string(), path));
+                }
+            }
+            _ => {}
+        }
+        resolved
+    }
+
+    /// PATH for children such as make, with the resolved tools' dirs first
+    /// The Makefile calls `docker` itself, so it must be findable there too.
+    fn child_path(&self) -> Option<OsString> {
+        let mut dirs: Vec<PathBuf> = Tool::ALL
+            .iter()
+            .filter_map(|tool| self.path(*tool)?.parent().map(Path::to_path_buf))
+            .collect();
+        if let Some(path) = std::env::var_os("PATH") {
+            dirs.extend(std::env::split_paths(&path));
+        }
+        dirs.dedup();
+        std::env::join_paths(dirs).ok()
+    }
+}
+
+static TOOLS: RwLock<Option<ToolTable>> = RwLock::new(None);
+
+/// Re-resolve the tools, e.g. after the paths in the settings changed
+/// `runtime` is the configured container runtime, if any.
+pub fn configure(paths: &ToolPaths, runtime: Option<ContainerRuntime>) {
+    let located = ToolLocator::from_env().locate_all(paths);
+    let runtime = ContainerRuntime::choose(&located, runtime);
+    // Only the runtime in use needs to be there
+    let unneeded = match runtime {
+        ContainerRuntime::Docker => Tool::Podman,
+        ContainerRuntime::Podman => Tool::Docker,
+    };
+    for tool in located
+        .iter()
+        .filter(|tool| tool.error.is_some() && tool.tool != unneeded)
+    {
+        warn!(
+            "⚠️  {:?}: {}",
+            tool.tool,
+            tool.error.as_deref().unwrap_or_default()
+        );
+    }
+    info!("🐳 Container runtime: {}", runtime.name());
+    *TOOLS.write().unwrap() = Some(ToolTable { located, runtime });
+}
+
+/// Resolved tools, empty until `configure` has run
+pub fn located() -> Vec<LocatedTool> {
+    TOOLS
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|table| table.located.clone())
+        .unwrap_or_default()
+}
+
+/// The container runtime in use, docker until `configure` has run
+pub fn runtime() -> ContainerRuntime {
+    TOOLS
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|table| table.runtime)
+        .unwrap_or_default()
+}
+
+/// `spec` with the resolved tool paths applied
+pub fn resolve(spec: &CommandSpec) -> CommandSpec {
+    match TOOLS.read().unwrap().as_ref() {
+        Some(table) => table.apply(spec),
+        None => spec.clone(),
+    }
+}
+
+#[tauri::command]
+pub async fn locate_tools(
+    settings: State<'_, crate::settings::SettingsStore>,
+) -> Result<Vec<LocatedTool>, String> {
+    let settings = settings.get();
+    configure(&settings.tool_paths, settings.container_runtime);
+    Ok(located())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    fn bin_dir(tmp: &TempDir, name: &str, binaries: &[&str]) -> PathBuf {
+        let dir = tmp.path().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        for binary in binaries {
+            let file = format!("{}{}", binary, std::env::consts::EXE_SUFFIX);
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    fn locator(path: &Path, well_known: &Path) -> ToolLocator {
+        ToolLocator {
+            search_path: Some(path.as_os_str().to_owned()),
+            well_known: vec![well_known.to_path_buf()],
+        }
+    }
+
+    #[test]
+    fn test_setting_then_path_then_well_known() {
+        let tmp = temp_dir();
+        let path = bin_dir(&tmp, "path", &["make"]);
+        let well_known = bin_dir(&tmp, "well-known", &["docker"]);
+        let custom = bin_dir(&tmp, "custom", &["make"]);
+        let locator = locator(&path, &well_known);
+
+        let make = locator.locate(Tool::Make, &ToolPaths::default());
+        assert_eq!(
+            make.path,
+            Some(path.join(format!("make{}", std::env::consts::EXE_SUFFIX)))
+        );
+        assert_eq!(make.source, Some(ToolSource::Path));
+
+        let docker = locator.locate(Tool::Docker, &ToolPaths::default());
+        assert_eq!(docker.source, Some(ToolSource::WellKnown));
+
+        let configured = custom.join(format!("make{}", std::env::consts::EXE_SUFFIX));
+        let paths = ToolPaths {
+            make: Some(configured.clone()),
+            ..Default::default()
+        };
+        let make = locator.locate(Tool::Make, &paths);
+        assert_eq!(make.path, Some(configured));
+        assert_eq!(make.source, Some(ToolSource::Setting));
+    }
+
+    #[test]
+    fn test_gmake_is_accepted() {
+        let tmp = temp_dir();
+        let path = bin_dir(&tmp, "gmake", &["gmake"]);
+        let make = locator(&path, &path).locate(Tool::Make, &ToolPaths::default());
+        assert_eq!(
+            make.path,
+            Some(path.join(format!("gmake{}", std::env::consts::EXE_SUFFIX)))
+        );
+    }
+
+    #[test]
+    fn test_missing_tool_and_bad_setting_are_reported() {
+        let tmp = temp_dir();
+        let empty = bin_dir(&tmp, "empty", &[]);
+        let locator = locator(&empty, &empty);
+
+        let docker = locator.locate(Tool::Docker, &ToolPaths::default());
+        assert_eq!(docker.path, None);
+        assert!(docker.error.unwrap().contains("docker not found"));
+
+        let paths = ToolPaths {
+            docker: Some(empty.join("nope")),
+            ..Default::default()
+        };
+        let docker = locator.locate(Tool::Docker, &paths);
+        assert!(docker.error.unwrap().contains("is not a file"));
+    }
+
+    #[test]
+    fn test_apply_rewrites_programs() {
+        let table = ToolTable {
+            located: vec![
+                LocatedTool {
+                    tool: Tool::Docker,
+                    path: Some(PathBuf::from("/opt/docker/bin/docker")),
+                    source: Some(ToolSource::WellKnown),
+                    error: None,
+                },
+                LocatedTool {
+                    tool: Tool::Compose,
+                    path: Some(PathBuf::from("/opt/compose/docker-compose")),
+                    source: Some(ToolSource::Setting),
+                    error: None,
+                },
+            ],
+            runtime: ContainerRuntime::Docker,
+        };
+
+        let ps = table.apply(&CommandSpec::new("docker").args(["ps"]));
+        assert_eq!(ps.program, "/opt/docker/bin/docker");
+        assert_eq!(ps.args, vec!["ps"]);
+
+        let up = table.apply(&CommandSpec::new("docker").args(["compose", "up", "-d"]));
+        assert_eq!(up.program, "/opt/compose/docker-compose");
+        assert_eq!(up.args, vec!["up", "-d"]);
+
+        let make = table.apply(&CommandSpec::new("make").args(["up"]));
+        assert_eq!(make.program, "make", "Unresolved tools keep their name");
+    }
+
+    #[test]
+    fn test_podman_runs_docker_commands_where_docker_is_missing() {
+        let podman = LocatedTool {
+            tool: Tool::Podman,
+            path: Some(PathBuf::from("/usr/bin/podman")),
+            source: Some(ToolSource::Path),
+            error: None,
+        };
+        let docker = LocatedTool {
+            tool: Tool::Docker,
+            path: Some(PathBuf::from("/usr/bin/docker")),
+            ..podman.clone()
+        };
+        let both = [docker, podman.clone()];
+        assert_eq!(ContainerRuntime::choose(&both, None), ContainerRuntime::Docker);
+        assert_eq!(
+            ContainerRuntime::choose(&both, Some(ContainerRuntime::Podman)),
+            ContainerRuntime::Podman
+        );
+        let only_podman = [podman];
+        assert_eq!(ContainerRuntime::choose(&only_podman, None), ContainerRuntime::Podman);
+        assert_eq!(ContainerRuntime::choose(&[], None), ContainerRuntime::Docker);
+
+        let table = ToolTable {
+            located: only_podman.to_vec(),
+            runtime: ContainerRuntime::Podman,
+        };
+        let ps = table.apply(&CommandSpec::new("docker").args(["ps"]));
+        assert_eq!(ps.program, "/usr/bin/podman");
+        let up = table.apply(&CommandSpec::new("docker").args(["compose", "up", "-d"]));
+        assert_eq!(up.program, "/usr/bin/podman");
+        assert_eq!(up.args, vec!["compose", "up", "-d"]);
+    }
+}
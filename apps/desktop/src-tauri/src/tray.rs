@@ -0,0 +1,331 @@
+// System tray
+// The tray icon says in its tooltip and first menu line what the stack is
+// doing, and its menu starts, stops and restarts services, shows the window
+// and quits. It follows the `stack-state` and `service-crashed` events the
+// window gets: the icon is greyed while services are stopped and badged red
+// after a crash, until the next start or stop. Quitting from the tray goes
+// through the same shutdown as closing the window; with `minimize_to_tray`,
+// closing the window only hides it.
+
+use crate::crashes::SERVICE_CRASHED_EVENT;
+use crate::quit;
+use crate::services::{StackState, STACK_STATE_EVENT};
+use crate::splash;
+use crate::ServiceManager;
+use crate::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Mutex;
+use tauri::image::Image;
+use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
+use tauri::tray::{TrayIcon, TrayIconBuilder};
+use tauri::{AppHandle, Listener, Manager, Wry};
+
+pub const TRAY_ID: &str = "arbor";
+
+/// How the icon is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Badge {
+    None,
+    /// Greyed out
+    Stopped,
+    /// A red dot in the corner
+    Crashed,
+}
+
+/// What the tray shows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrayStatus {
+    pub stack: StackState,
+    /// The container that crashed since the stack last started or stopped
+    pub crashed: Option<String>,
+}
+
+impl TrayStatus {
+    pub fn badge(&self) -> Badge {
+        match (self.crashed.is_some(), self.stack) {
+            (true, _) => Badge::Crashed,
+            (false, StackState::Stopped) => Badge::Stopped,
+            (false, _) => Badge::None,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let stack = match self.stack {
+            StackState::Stopped => "Services stopped",
+            StackState::Starting => "Services starting…",
+            StackState::Running { adopted: false } => "Services running",
+            StackState::Running { adopted: true } => "Services running (started outside Arbor)",
+            StackState::Stopping => "Services stopping…",
+        };
+        match &self.crashed {
+            Some(container) => format!("{}; {} crashed", stack, container),
+            None => stack.to_string(),
+        }
+    }
+}
+
+/// The part of a `service-crashed` CrashReport the tray shows
+#[derive(Deserialize)]
+struct Crashed {
+    container: String,
+}
+
+struct Items {
+    status: MenuItem<Wry>,
+    start: MenuItem<Wry>,
+    stop: MenuItem<Wry>,
+    restart: MenuItem<Wry>,
+}
+
+/// The installed tray, kept in sync with the stack
+pub struct Tray {
+    icon: TrayIcon<Wry>,
+    items: Items,
+    /// The app icon as is, grey and badged
+    images: [Image<'static>; 3],
+    status: Mutex<TrayStatus>,
+}
+
+impl Tray {
+    fn update(&self, change: impl FnOnce(&mut TrayStatus)) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            change(&mut status);
+            status.clone()
+        };
+        let summary = status.summary();
+        let image = match status.badge() {
+            Badge::None => &self.images[0],
+            Badge::Stopped => &self.images[1],
+            Badge::Crashed => &self.images[2],
+        };
+        // Lifecycle actions are only offered where they'd be accepted
+        let owned = status.stack == StackState::Running { adopted: false };
+        let applied = self
+            .icon
+            .set_tooltip(Some(format!("Arbor: {}", summary)))
+            .and_then(|_| self.icon.set_icon(Some(image.clone())))
+            .and_then(|_| self.items.status.set_text(summary))
+            .and_then(|_| {
+                self.items
+                    .start
+                    .set_enabled(status.stack == StackState::Stopped)
+            })
+            .and_then(|_| self.items.stop.set_enabled(owned))
+            .and_then(|_| self.items.restart.set_enabled(owned));
+        if let Err(e) = applied {
+            warn!("⚠️  Failed to update the tray: {}", e);
+        }
+    }
+}
+
+/// Draw `badge` over an RGBA image
+pub fn badged(rgba: &[u8], width: u32, height: u32, badge: Badge) -> Vec<u8> {
+    let mut pixels = rgba.to_vec();
+    match badge {
+        Badge::None => {}
+        Badge::Stopped => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(u32::from);
+                let grey = ((r * 30 + g * 59 + b * 11) / 100) as u8;
+                pixel[..3].fill(grey);
+                pixel[3] /= 2;
+            }
+        }
+        Badge::Crashed => {
+            // A dot a third of the icon across, in the bottom right corner
+            let radius = width.min(height) as f32 / 6.0;
+            let (cx, cy) = (width as f32 - radius, height as f32 - radius);
+            for (i, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+                let (x, y) = (
+                    (i as u32 % width) as f32 + 0.5,
+                    (i as u32 / width) as f32 + 0.5,
+                );
+                if (x - cx).powi(2) + (y - cy).powi(2) <= radius.powi(2) {
+                    pixel.copy_from_slice(&[0xE0, 0x24, 0x24, 0xFF]);
+                }
+            }
+        }
+    }
+    pixels
+}
+
+/// Add the tray icon and follow the stack's events
+pub fn install(app_handle: &AppHandle) -> Result<(), String> {
+    let icon = app_handle
+        .default_window_icon()
+        .cloned()
+        .ok_or("The app has no icon")?;
+    let image = |badge| {
+        let rgba = badged(icon.rgba(), icon.width(), icon.height(), badge);
+        Image::new_owned(rgba, icon.width(), icon.height())
+    };
+    let images = [
+        image(Badge::None),
+        image(Badge::Stopped),
+        image(Badge::Crashed),
+    ];
+
+    let item = |id: &str, text: &str, enabled| {
+        MenuItem::with_id(app_handle, id, text, enabled, None::<&str>).map_err(|e| e.to_string())
+    };
+    let items = Items {
+        status: item("status", "", false)?,
+        start: item("start", "Start Services", false)?,
+        stop: item("stop", "Stop Services", false)?,
+        restart: item("restart", "Restart Services", false)?,
+    };
+    let show = item("show", "Show Window", true)?;
+    let quit = item("quit", "Quit Arbor", true)?;
+    let separator = || PredefinedMenuItem::separator(app_handle).map_err(|e| e.to_string());
+    let menu = Menu::with_items(
+        app_handle,
+        &[
+            &items.status,
+            &separator()?,
+            &items.start,
+            &items.stop,
+            &items.restart,
+            &separator()?,
+            &show,
+            &quit,
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    let icon = TrayIconBuilder::with_id(TRAY_ID)
+        .icon(images[0].clone())
+        .menu(&menu)
+        .show_menu_on_left_click(true)
+        .on_menu_event(|app_handle, event| on_menu_event(app_handle, event.id().as_ref()))
+        .build(app_handle)
+        .map_err(|e| format!("Failed to add the tray icon: {}", e))?;
+    let tray = Tray {
+        icon,
+        items,
+        images,
+        status: Mutex::new(TrayStatus::default()),
+    };
+    let stack = *app_handle.state::<ServiceManager>().stack.lock();
+    tray.update(|status| status.stack = stack);
+    app_handle.manage(tray);
+
+    let handle = app_handle.clone();
+    app_handle.listen_any(STACK_STATE_EVENT, move |event| {
+        if let Some(stack) = payload::<StackState>(event.payload()) {
+            handle.state::<Tray>().update(|status| {
+                // A crash stays marked while the stack it happened in runs
+                if !matches!(stack, StackState::Running { .. }) {
+                    status.crashed = None;
+                }
+                status.stack = stack;
+            });
+        }
+    });
+    let handle = app_handle.clone();
+    app_handle.listen_any(SERVICE_CRASHED_EVENT, move |event| {
+        if let Some(report) = payload::<Crashed>(event.payload()) {
+            handle
+                .state::<Tray>()
+                .update(|status| status.crashed = Some(report.container));
+        }
+    });
+    Ok(())
+}
+
+/// The payload of an emitted event, out of its envelope
+fn payload<T: DeserializeOwned>(event: &str) -> Option<T> {
+    let mut envelope: serde_json::Value = serde_json::from_str(event).ok()?;
+    serde_json::from_value(envelope["payload"].take()).ok()
+}
+
+fn on_menu_event(app_handle: &AppHandle, id: &str) {
+    let app_handle = app_handle.clone();
+    match id {
+        "start" => {
+            tauri::async_runtime::spawn(async move {
+                let state = (app_handle.state(), app_handle.state());
+                let result =
+                    crate::start_stack(app_handle.clone(), state.0, state.1, None, None).await;
+                if let Err(e) = result {
+                    error!("❌ Failed to start services: {}", e);
+                }
+            });
+        }
+        "stop" => {
+            tauri::async_runtime::spawn(async move {
+                let state = (app_handle.state(), app_handle.state());
+                let result = crate::stop_for_user(app_handle.clone(), state.0, state.1).await;
+                if let Err(e) = result {
+                    error!("❌ Failed to stop services: {}", e);
+                }
+            });
+        }
+        "restart" => {
+            tauri::async_runtime::spawn(async move {
+                let state = (app_handle.state(), app_handle.state());
+                let result = crate::restart_for_user(app_handle.clone(), state.0, state.1).await;
+                if let Err(e) = result {
+                    error!("❌ Failed to restart services: {}", e);
+                }
+            });
+        }
+        "show" => show_window(&app_handle),
+        "quit" => match quit::stops_stack(&app_handle) {
+            true => quit::quit(app_handle),
+            false => {
+                info!("👋 Leaving the adopted stack running");
+                app_handle.exit(0);
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Bring back the main window, hidden by `minimize_to_tray`
+pub fn show_window(app_handle: &AppHandle) {
+    let Some(window) = app_handle.get_webview_window(splash::MAIN_WINDOW) else {
+        return;
+    };
+    let shown = window
+        .show()
+        .and_then(|_| window.unminimize())
+        .and_then(|_| window.set_focus());
+    if let Err(e) = shown {
+        warn!("⚠️  Failed to show the window: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_follows_the_stack_and_crashes() {
+        let mut status = TrayStatus::default();
+        assert_eq!(status.badge(), Badge::Stopped);
+        assert_eq!(status.summary(), "Services stopped");
+
+        status.stack = StackState::Running { adopted: false };
+        assert_eq!(status.badge(), Badge::None);
+        status.crashed = Some("arbor-postgres".to_string());
+        assert_eq!(status.badge(), Badge::Crashed);
+        assert_eq!(status.summary(), "Services running; arbor-postgres crashed");
+    }
+
+    #[test]
+    fn test_badges_are_drawn_over_the_icon() {
+        let (width, height) = (6, 6);
+        let icon = [0x20, 0x80, 0xF0, 0xFF].repeat(width * height);
+
+        let stopped = badged(&icon, width as u32, height as u32, Badge::Stopped);
+        assert_eq!(stopped[..4], [0x6F, 0x6F, 0x6F, 0x7F]);
+
+        let crashed = badged(&icon, width as u32, height as u32, Badge::Crashed);
+        let pixel = |x: usize, y: usize| &crashed[(y * width + x) * 4..][..4];
+        assert_eq!(pixel(5, 5), [0xE0, 0x24, 0x24, 0xFF]);
+        assert_eq!(pixel(0, 0), [0x20, 0x80, 0xF0, 0xFF]);
+        assert_eq!(badged(&icon, 6, 6, Badge::None), icon);
+    }
+}
